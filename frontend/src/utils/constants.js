@@ -21,16 +21,20 @@ export const REGEX_API_KEY = /^[a-zA-Z0-9_/-]{2,24}$/gm;
 export const REGEX_NAME = /^[\w\sÀ-ÿ\-]{0,32}$/gm;
 export const REGEX_ATTR_DESC = /^[a-zA-Z0-9\-_/\s]{0,128}$/gm;
 export const REGEX_ATTR_KEY = /^[a-zA-Z0-9\-_/]{2,32}$/gm;
+export const REGEX_AUTHORIZATION_DETAILS_TYPE = /^[a-zA-Z0-9\-_.:/]{1,128}$/;
 export const REGEX_CLIENT_ID = /^[a-zA-Z0-9\-_/]{2,128}$/gm;
 export const REGEX_CLIENT_NAME = /^[a-zA-Z0-9À-ÿ\-\s]{0,128}$/m;
 export const REGEX_CONTACT = /^[a-zA-Z0-9+.@/:]{0,48}$/gm;
+export const REGEX_LOWERCASE = /^[a-z0-9\-_/]{2,128}$/;
 export const REGEX_LOWERCASE_SPACE = /^[a-z0-9-_\/\s]{2,128}$/gm;
 export const REGEX_PROVIDER_SCOPE = /^[a-z0-9-_\/:\s]{0,128}$/gm;
 export const REGEX_PEM = /^(-----BEGIN CERTIFICATE-----)[a-zA-Z0-9+/=\n]+(-----END CERTIFICATE-----)$/gm;
 export const REGEX_ROLES = /^[a-z0-9\-_/:*]{2,64}$/gm;
 export const REGEX_URI = /^[a-zA-Z0-9,.:/_\-&?=~#!$'()*+%]*$/gm;
+export const REGEX_USERNAME = /^[a-zA-Z0-9][a-zA-Z0-9\-_.]{1,47}$/m;
 export const REGEX_URI_SPACE = /^[a-zA-Z0-9,.:/_\-&?=~#!$'()*+%\s]+$/m;
-export const REGEX_IP_V4 = /^(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]\d|\d)(?:\.(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]\d|\d)){3}$/gm;
+// IPv4 or IPv6 with an optional CIDR prefix - the exact validation happens in the backend
+export const REGEX_IP_CIDR = /^(?:[0-9.]{7,15}|[0-9a-fA-F:.]{2,45})(?:\/\d{1,3})?$/;
 
 // https://gist.github.com/olmokramer/82ccce673f86db7cda5e
 export const REGEX_CSS_COLOR = /(#(?:[0-9a-f]{2}){2,4}$|(#[0-9a-f]{3}$)|(rgb|hsl)a?\((-?\d+%?[,\s]+){2,3}\s*[\d\.]+%?\)$|black$|silver$|gray$|whitesmoke$|maroon$|red$|purple$|fuchsia$|green$|lime$|olivedrab$|yellow$|navy$|blue$|teal$|aquamarine$|orange$|aliceblue$|antiquewhite$|aqua$|azure$|beige$|bisque$|blanchedalmond$|blueviolet$|brown$|burlywood$|cadetblue$|chartreuse$|chocolate$|coral$|cornflowerblue$|cornsilk$|crimson$|currentcolor$|darkblue$|darkcyan$|darkgoldenrod$|darkgray$|darkgreen$|darkgrey$|darkkhaki$|darkmagenta$|darkolivegreen$|darkorange$|darkorchid$|darkred$|darksalmon$|darkseagreen$|darkslateblue$|darkslategray$|darkslategrey$|darkturquoise$|darkviolet$|deeppink$|deepskyblue$|dimgray$|dimgrey$|dodgerblue$|firebrick$|floralwhite$|forestgreen$|gainsboro$|ghostwhite$|goldenrod$|gold$|greenyellow$|grey$|honeydew$|hotpink$|indianred$|indigo$|ivory$|khaki$|lavenderblush$|lavender$|lawngreen$|lemonchiffon$|lightblue$|lightcoral$|lightcyan$|lightgoldenrodyellow$|lightgray$|lightgreen$|lightgrey$|lightpink$|lightsalmon$|lightseagreen$|lightskyblue$|lightslategray$|lightslategrey$|lightsteelblue$|lightyellow$|limegreen$|linen$|mediumaquamarine$|mediumblue$|mediumorchid$|mediumpurple$|mediumseagreen$|mediumslateblue$|mediumspringgreen$|mediumturquoise$|mediumvioletred$|midnightblue$|mintcream$|mistyrose$|moccasin$|navajowhite$|oldlace$|olive$|orangered$|orchid$|palegoldenrod$|palegreen$|paleturquoise$|palevioletred$|papayawhip$|peachpuff$|peru$|pink$|plum$|powderblue$|rosybrown$|royalblue$|saddlebrown$|salmon$|sandybrown$|seagreen$|seashell$|sienna$|skyblue$|slateblue$|slategray$|slategrey$|snow$|springgreen$|steelblue$|tan$|thistle$|tomato$|transparent$|turquoise$|violet$|wheat$|white$|yellowgreen$|rebeccapurple$)/i;
@@ -60,6 +64,24 @@ export const EVENT_TYPES = [
     'SecretsMigrated',
     'UserEmailChange',
     'UserPasswordReset',
+    'UserSessionsRevoked',
+    'LoginRestricted',
+    'LoginPolicyOverride',
+    'BreakGlass',
+    'LegalHold',
+    'AdminResetEmail',
+    'AdminResetOtp',
+    'AdminResetSms',
+    'JwkChanged',
+    'MfaRecovery',
+    'ElevatedRole',
+    'ClientIpRejected',
+    'UserMerged',
+    'LoginSloBreach',
+    'AuthCodeReuse',
+    'ClientSecretRead',
+    'UserInactiveDisabled',
+    'EventRecipientsChanged',
     'Test',
 ]
 export const LANGUAGES = ['DE', 'EN'];
@@ -70,6 +92,18 @@ export const TOKEN_ALGS = [
     'EdDSA'
 ]
 
+export const JWE_ALGS = [
+    'none',
+    'RSA-OAEP',
+    'RSA-OAEP-256',
+    'ECDH-ES'
+]
+
+export const JWE_ENCS = [
+    'A128GCM',
+    'A256GCM'
+]
+
 export const FLOWS = [
     {
         label: 'authorization_code',
@@ -91,6 +125,10 @@ export const FLOWS = [
         label: 'device_code',
         value: false,
     },
+    {
+        label: 'token_exchange',
+        value: false,
+    },
 ];
 
 export const PKCE_CHALLENGES = [{
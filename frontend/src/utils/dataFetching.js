@@ -32,6 +32,20 @@ export async function authorize(data) {
     return res;
 }
 
+export async function authorizeEmailMfa(data) {
+    const res = await fetch('/auth/v1/oidc/authorize/email_mfa', {
+        method: 'POST',
+        headers: getCsrfHeaders(),
+        body: JSON.stringify(data),
+    });
+
+    if (res.status === 202) {
+        window.location.replace(res.headers.get('location'));
+    }
+
+    return res;
+}
+
 export async function authorizeRefresh(data) {
     const res = await fetch('/auth/v1/oidc/authorize/refresh', {
         method: 'POST',
@@ -47,6 +61,14 @@ export async function authorizeRefresh(data) {
 }
 
 
+export async function getAnnouncementsActive(clientId) {
+    const query = clientId ? `?client_id=${encodeURIComponent(clientId)}` : '';
+    return await fetch(`/auth/v1/announcements/active${query}`, {
+        method: 'GET',
+        headers: HEADERS.json,
+    });
+}
+
 export async function getAppVersion() {
     return await fetch('/auth/v1/version', {
         method: 'GET',
@@ -86,11 +108,10 @@ export async function getSessionInfoXsrf(accessToken) {
     });
 }
 
-export async function logout(data) {
+export async function logout() {
     return await fetch('/auth/v1/oidc/logout', {
         method: 'POST',
         headers: getCsrfHeaders(),
-        body: data,
     });
 }
 
@@ -101,6 +122,44 @@ export async function getClientLogo(id) {
     });
 }
 
+export async function authorizePushMfa(data) {
+    const res = await fetch('/auth/v1/oidc/authorize/push_mfa', {
+        method: 'POST',
+        headers: getCsrfHeaders(),
+        body: JSON.stringify(data),
+    });
+
+    if (res.status === 202) {
+        window.location.replace(res.headers.get('location'));
+    }
+
+    return res;
+}
+
+export async function authorizePushMfaFallback(data) {
+    return await fetch('/auth/v1/oidc/authorize/push_mfa/fallback', {
+        method: 'POST',
+        headers: getCsrfHeaders(),
+        body: JSON.stringify(data),
+    });
+}
+
+export async function postPushMfaApprove(id, data) {
+    return await fetch(`/auth/v1/push_mfa/${id}/approve`, {
+        method: 'POST',
+        headers: HEADERS.json,
+        body: JSON.stringify(data),
+    });
+}
+
+export async function postPushMfaDeny(id, data) {
+    return await fetch(`/auth/v1/push_mfa/${id}/deny`, {
+        method: 'POST',
+        headers: HEADERS.json,
+        body: JSON.stringify(data),
+    });
+}
+
 export async function postDeviceVerify(data) {
     return await fetch('/auth/v1/oidc/device/verify', {
         method: 'POST',
@@ -323,6 +382,28 @@ export async function webauthnAuthFinish(id, data) {
     });
 }
 
+export async function getUserPushSubscriptions(id) {
+    return await fetch(`/auth/v1/users/${id}/self/push_subscriptions`, {
+        method: 'GET',
+        headers: getCsrfHeaders(),
+    });
+}
+
+export async function postUserPushSubscription(id, data) {
+    return await fetch(`/auth/v1/users/${id}/self/push_subscriptions`, {
+        method: 'POST',
+        headers: getCsrfHeaders(),
+        body: JSON.stringify(data),
+    });
+}
+
+export async function deleteUserPushSubscription(id, subId) {
+    return await fetch(`/auth/v1/users/${id}/self/push_subscriptions/${subId}`, {
+        method: 'DELETE',
+        headers: getCsrfHeaders(),
+    });
+}
+
 export async function webauthnDelete(id, name) {
     return await fetch(`/auth/v1/users/${id}/webauthn/delete/${name}`, {
         method: 'DELETE',
@@ -387,6 +387,15 @@ export async function putUser(uid, data) {
     return await checkRedirectForbidden(res);
 }
 
+export async function postUserPasswordReset(uid, data) {
+    const res = await fetch(`/auth/v1/users/${uid}/reset`, {
+        method: 'POST',
+        headers: getHeaders(),
+        body: JSON.stringify(data),
+    });
+    return await checkRedirectForbidden(res);
+}
+
 export async function deleteUser(uid) {
     const res = await fetch(`/auth/v1/users/${uid}`, {
         method: 'DELETE',
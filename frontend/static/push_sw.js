@@ -0,0 +1,27 @@
+// Service Worker for push approvals of logins.
+// The payload never contains any secret apart from the link to the approval page.
+
+self.addEventListener('push', event => {
+    if (!event.data) {
+        return;
+    }
+
+    const payload = event.data.json();
+    event.waitUntil(
+        self.registration.showNotification(payload.title, {
+            body: payload.body,
+            data: {url: payload.url},
+            requireInteraction: true,
+            tag: 'rauthy-push-mfa',
+        })
+    );
+});
+
+self.addEventListener('notificationclick', event => {
+    event.notification.close();
+
+    const url = event.notification.data?.url;
+    if (url) {
+        event.waitUntil(self.clients.openWindow(url));
+    }
+});
@@ -22,7 +22,8 @@ const config = {
                 'connect-src': ['self'],
                 'script-src': ['self', 'wasm-unsafe-eval'],
                 'style-src': ['self', 'unsafe-inline'],
-                'img-src': ['self'],
+                // logos may be redirected to an external S3 storage or CDN
+                'img-src': ['self', 'https:'],
             },
         },
 
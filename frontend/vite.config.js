@@ -38,6 +38,7 @@ const config = {
             '/auth/v1/login_time': backend,
             '/auth/v1/users': backend,
             '/auth/v1/pow': backend,
+            '^/auth/v1/push_mfa/.+': backend,
             '/auth/v1/roles': backend,
             '/auth/v1/groups': backend,
             '/auth/v1/password_hash_times': backend,
@@ -0,0 +1,16 @@
+create table jobs
+(
+    id         varchar not null
+        constraint jobs_pk
+            primary key,
+    typ        varchar not null,
+    payload    varchar not null,
+    status     varchar not null,
+    attempts   bigint  not null,
+    next_run   bigint  not null,
+    last_error varchar,
+    created    bigint  not null
+);
+
+create index jobs_typ_next_run_index
+    on jobs (typ, next_run);
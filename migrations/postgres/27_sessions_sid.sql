@@ -0,0 +1,13 @@
+create table sessions_sid
+(
+    sid        varchar not null
+        constraint sessions_sid_pk
+            primary key,
+    session_id varchar not null
+        references sessions
+            on delete cascade
+            on update cascade
+);
+
+create index sessions_sid_session_id_index
+    on sessions_sid (session_id);
@@ -0,0 +1,11 @@
+create table legal_holds
+(
+    user_id varchar not null
+        constraint legal_holds_pk
+            primary key
+        references users
+            on delete cascade
+            on update cascade,
+    reason  varchar not null,
+    created bigint  not null
+);
@@ -0,0 +1,5 @@
+alter table users
+    add username varchar;
+
+create unique index users_username_uindex
+    on users (username);
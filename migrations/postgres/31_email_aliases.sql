@@ -0,0 +1,14 @@
+create table email_aliases
+(
+    email   varchar not null
+        constraint email_aliases_pk
+            primary key,
+    user_id varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    created bigint  not null
+);
+
+create index email_aliases_user_id_index
+    on email_aliases (user_id);
@@ -0,0 +1,35 @@
+create table organizations
+(
+    id      varchar not null
+        constraint organizations_pk
+            primary key,
+    name    varchar not null,
+    domains varchar,
+    created bigint  not null
+);
+
+create unique index organizations_name_uindex
+    on organizations (name);
+
+create table organization_members
+(
+    org_id   varchar not null
+        references organizations
+            on delete cascade
+            on update cascade,
+    user_id  varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    is_owner bool    not null,
+    created  bigint  not null,
+    constraint organization_members_pk
+        primary key (org_id, user_id)
+);
+
+create index organization_members_user_id_index
+    on organization_members (user_id);
+
+insert into scopes (id, name, attr_include_access, attr_include_id)
+values ('Qw3vZ8nCkT5yRbH2mXpL7dJa', 'organizations', null, null)
+on conflict do nothing;
@@ -0,0 +1,12 @@
+create table revoked_tokens
+(
+    jti     varchar not null
+        constraint revoked_tokens_pk
+            primary key,
+    exp     bigint  not null,
+    reason  varchar not null,
+    created bigint  not null
+);
+
+create index revoked_tokens_exp_index
+    on revoked_tokens (exp);
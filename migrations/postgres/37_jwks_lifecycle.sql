@@ -0,0 +1,12 @@
+create table jwks_lifecycle
+(
+    kid         varchar              not null
+        constraint jwks_lifecycle_pk
+            primary key
+        references jwks
+            on delete cascade,
+    imported    bool   default false not null,
+    activate_at bigint,
+    retired_at  bigint,
+    usage_count bigint default 0     not null
+);
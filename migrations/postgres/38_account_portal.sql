@@ -0,0 +1,31 @@
+create table user_client_grants
+(
+    user_id   varchar not null
+        references users
+            on delete cascade,
+    client_id varchar not null
+        references clients
+            on delete cascade,
+    scope     varchar,
+    created   bigint  not null,
+    last_used bigint  not null,
+    constraint user_client_grants_pk
+        primary key (user_id, client_id)
+);
+
+create index user_client_grants_client_id_index
+    on user_client_grants (client_id);
+
+alter table refresh_tokens
+    add column client_id varchar;
+
+create table user_notification_prefs
+(
+    user_id          varchar            not null
+        constraint user_notification_prefs_pk
+            primary key
+        references users
+            on delete cascade,
+    password_expiry  bool default true  not null,
+    sessions_revoked bool default true  not null
+);
@@ -0,0 +1,33 @@
+alter table events
+    add column seq bigint;
+
+alter table events
+    add column prev_hash varchar;
+
+alter table events
+    add column hash varchar;
+
+create unique index events_seq_uindex
+    on events (seq);
+
+create table event_chain_head
+(
+    id   smallint not null
+        constraint event_chain_head_pk
+            primary key,
+    seq  bigint   not null,
+    hash varchar  not null
+);
+
+insert into event_chain_head (id, seq, hash)
+values (1, 0, '');
+
+create table event_anchors
+(
+    seq     bigint  not null
+        constraint event_anchors_pk
+            primary key,
+    hash    varchar not null,
+    token   varchar not null,
+    created bigint  not null
+);
@@ -0,0 +1,21 @@
+create table user_role_grants
+(
+    id          varchar not null
+        constraint user_role_grants_pk
+            primary key,
+    user_id     varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    role        varchar not null,
+    reason      varchar not null,
+    approved_by varchar not null,
+    created     bigint  not null,
+    exp         bigint  not null
+);
+
+create index user_role_grants_user_id_index
+    on user_role_grants (user_id);
+
+create index user_role_grants_exp_index
+    on user_role_grants (exp);
@@ -0,0 +1,2 @@
+alter table clients
+    add column allowed_cidrs varchar;
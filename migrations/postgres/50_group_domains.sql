@@ -0,0 +1,2 @@
+alter table groups
+    add domains varchar;
@@ -0,0 +1,13 @@
+create table client_secret_reads
+(
+    client_id  varchar not null
+        references clients
+            on delete cascade
+            on update cascade,
+    reads      bigint  not null,
+    last_read  bigint  not null,
+    last_actor varchar not null,
+    last_ip    varchar,
+    constraint client_secret_reads_pk
+        primary key (client_id)
+);
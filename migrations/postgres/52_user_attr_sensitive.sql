@@ -0,0 +1,2 @@
+alter table user_attr_config
+    add sensitive boolean default false not null;
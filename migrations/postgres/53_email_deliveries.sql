@@ -0,0 +1,17 @@
+create table email_deliveries
+(
+    id       varchar not null
+        constraint email_deliveries_pk
+            primary key,
+    address  varchar not null,
+    subject  varchar not null,
+    relay    varchar,
+    status   varchar not null,
+    attempts bigint  not null,
+    error    varchar,
+    created  bigint  not null,
+    updated  bigint  not null
+);
+
+create index email_deliveries_updated_index
+    on email_deliveries (updated);
@@ -0,0 +1,10 @@
+create table user_activity
+(
+    user_id       varchar not null
+        constraint user_activity_pk
+            primary key
+        references users
+            on delete cascade,
+    last_refresh  bigint,
+    last_userinfo bigint
+);
@@ -0,0 +1,13 @@
+create table cluster_nodes
+(
+    hostname              varchar not null
+        constraint cluster_nodes_pk
+            primary key,
+    version               varchar not null,
+    started_at            bigint  not null,
+    last_heartbeat        bigint  not null,
+    db_healthy            boolean not null,
+    cache_role            varchar not null,
+    cache_health          varchar,
+    cache_connected_hosts bigint
+);
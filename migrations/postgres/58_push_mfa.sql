@@ -0,0 +1,48 @@
+create table push_subscriptions
+(
+    id        varchar not null
+        constraint push_subscriptions_pk
+            primary key,
+    user_id   varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    name      varchar not null,
+    endpoint  varchar not null,
+    p256dh    varchar not null,
+    auth      varchar not null,
+    created   bigint  not null,
+    last_used bigint
+);
+
+create index push_subscriptions_user_id_index
+    on push_subscriptions (user_id);
+
+create unique index push_subscriptions_endpoint_uindex
+    on push_subscriptions (endpoint);
+
+create table push_mfa_requests
+(
+    id            varchar not null
+        constraint push_mfa_requests_pk
+            primary key,
+    user_id       varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    session_id    varchar not null
+        references sessions
+            on delete cascade
+            on update cascade,
+    number        bigint  not null,
+    token_hash    varchar not null,
+    status        varchar not null,
+    ip            varchar,
+    header_loc    varchar not null,
+    header_origin varchar,
+    attempts      bigint  not null,
+    exp           bigint  not null
+);
+
+create index push_mfa_requests_exp_index
+    on push_mfa_requests (exp);
@@ -0,0 +1,18 @@
+create table app_attest_keys
+(
+    key_id     varchar not null
+        constraint app_attest_keys_pk
+            primary key,
+    client_id  varchar not null
+        references clients
+            on delete cascade
+            on update cascade,
+    app_id     varchar not null,
+    public_key varchar not null,
+    counter    bigint  not null,
+    created    bigint  not null,
+    last_used  bigint  not null
+);
+
+create index app_attest_keys_client_id_index
+    on app_attest_keys (client_id);
@@ -0,0 +1,2 @@
+alter table clients
+    add column authorization_details_types varchar;
@@ -0,0 +1,2 @@
+alter table clients
+    add column token_exchange_audiences varchar;
@@ -0,0 +1,2 @@
+alter table resource_servers
+    add column client_ids varchar default '' not null;
@@ -0,0 +1,11 @@
+create table events_outbox
+(
+    id      varchar not null
+        constraint events_outbox_pk
+            primary key,
+    payload varchar not null,
+    created bigint  not null
+);
+
+create index events_outbox_created_index
+    on events_outbox (created);
@@ -0,0 +1,24 @@
+create table group_login_policies
+(
+    group_id   varchar not null
+        constraint group_login_policies_pk
+            primary key
+        references groups
+            on delete cascade
+            on update cascade,
+    weekdays   bigint  not null,
+    time_from  bigint  not null,
+    time_to    bigint  not null,
+    utc_offset bigint  not null
+);
+
+create table login_policy_overrides
+(
+    user_id varchar not null
+        constraint login_policy_overrides_pk
+            primary key
+        references users
+            on delete cascade
+            on update cascade,
+    exp     bigint  not null
+);
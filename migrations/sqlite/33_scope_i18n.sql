@@ -0,0 +1,5 @@
+alter table scopes
+    add i18n varchar;
+
+alter table scopes
+    add client_ids varchar;
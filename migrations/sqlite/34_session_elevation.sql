@@ -0,0 +1,2 @@
+alter table sessions
+    add elevated_until bigint;
@@ -0,0 +1,13 @@
+create table client_user_agents
+(
+    client_id  varchar not null
+        references clients
+            on delete cascade
+            on update cascade,
+    user_agent varchar not null,
+    first_seen bigint  not null,
+    last_seen  bigint  not null,
+    requests   bigint  not null,
+    constraint client_user_agents_pk
+        primary key (client_id, user_agent)
+);
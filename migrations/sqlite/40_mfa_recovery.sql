@@ -0,0 +1,19 @@
+create table mfa_recoveries
+(
+    id          varchar not null
+        constraint mfa_recoveries_pk
+            primary key,
+    user_id     varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    status      varchar not null,
+    created     bigint  not null,
+    confirmed   bigint,
+    not_before  bigint,
+    approved_by varchar,
+    exp         bigint  not null
+);
+
+create index mfa_recoveries_user_id_index
+    on mfa_recoveries (user_id);
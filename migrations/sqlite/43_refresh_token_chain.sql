@@ -0,0 +1,5 @@
+alter table refresh_tokens
+    add column chain_exp bigint;
+
+alter table refresh_tokens
+    add column device_fp varchar;
@@ -0,0 +1,29 @@
+alter table users
+    add column email_mfa boolean default false not null;
+
+create table email_mfa_codes
+(
+    id            varchar not null
+        constraint email_mfa_codes_pk
+            primary key,
+    user_id       varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    session_id    varchar not null
+        references sessions
+            on delete cascade
+            on update cascade,
+    purpose       varchar not null,
+    otp_hash      varchar not null,
+    header_loc    varchar,
+    header_origin varchar,
+    attempts      bigint  not null,
+    exp           bigint  not null
+);
+
+create index email_mfa_codes_user_id_index
+    on email_mfa_codes (user_id);
+
+create index email_mfa_codes_exp_index
+    on email_mfa_codes (exp);
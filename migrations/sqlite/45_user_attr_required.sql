@@ -0,0 +1,5 @@
+alter table user_attr_config
+    add required boolean default false not null;
+
+alter table user_attr_config
+    add skip_client_ids varchar;
@@ -0,0 +1,12 @@
+create table announcements
+(
+    id         varchar not null
+        constraint announcements_pk
+            primary key,
+    message    varchar not null,
+    severity   varchar not null,
+    nbf        bigint  not null,
+    exp        bigint,
+    client_ids varchar,
+    created    bigint  not null
+);
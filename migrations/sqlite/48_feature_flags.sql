@@ -0,0 +1,20 @@
+create table feature_flags
+(
+    id          varchar               not null
+        constraint feature_flags_pk
+            primary key,
+    name        varchar               not null
+        constraint feature_flags_name_uindex
+            unique,
+    description varchar,
+    enabled     boolean default false not null,
+    percentage  bigint,
+    groups      varchar,
+    attr_key    varchar,
+    attr_value  varchar,
+    client_ids  varchar,
+    created     bigint                not null
+);
+
+insert or ignore into scopes (id, name, attr_include_access, attr_include_id)
+values ('Fz6tLq2WnB8kVcR4xHjM9pYe', 'flags', null, null);
@@ -0,0 +1,15 @@
+create table user_merges
+(
+    old_id    varchar not null
+        constraint user_merges_pk
+            primary key,
+    new_id    varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    merged_by varchar not null,
+    merged_at bigint  not null
+);
+
+create index user_merges_new_id_index
+    on user_merges (new_id);
@@ -0,0 +1,5 @@
+alter table clients
+    add column jwks_uri varchar;
+
+alter table clients
+    add column require_signed_request_object boolean default false not null;
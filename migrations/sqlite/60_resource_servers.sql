@@ -0,0 +1,12 @@
+create table resource_servers
+(
+    id       varchar not null
+        constraint resource_servers_pk
+            primary key,
+    name     varchar not null,
+    resource varchar not null
+        constraint resource_servers_resource_uindex
+            unique,
+    scopes   varchar not null,
+    created  bigint  not null
+);
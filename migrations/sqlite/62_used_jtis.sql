@@ -0,0 +1,10 @@
+create table used_jtis
+(
+    id  varchar not null
+        constraint used_jtis_pk
+            primary key,
+    exp bigint  not null
+);
+
+create index used_jtis_exp_index
+    on used_jtis (exp);
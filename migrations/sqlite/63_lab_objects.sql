@@ -0,0 +1,7 @@
+create table lab_objects
+(
+    typ varchar not null,
+    id  varchar not null,
+    constraint lab_objects_pk
+        primary key (typ, id)
+);
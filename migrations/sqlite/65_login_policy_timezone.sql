@@ -0,0 +1,2 @@
+alter table group_login_policies
+    add column timezone varchar;
@@ -0,0 +1,40 @@
+-- the attempts are counted per user now, in-flight codes are short-lived and can be dropped
+drop table email_mfa_codes;
+
+create table email_mfa_codes
+(
+    id            varchar not null
+        constraint email_mfa_codes_pk
+            primary key,
+    user_id       varchar not null
+        references users
+            on delete cascade
+            on update cascade,
+    session_id    varchar not null
+        references sessions
+            on delete cascade
+            on update cascade,
+    purpose       varchar not null,
+    otp_hash      varchar not null,
+    header_loc    varchar,
+    header_origin varchar,
+    exp           bigint  not null
+);
+
+create index email_mfa_codes_user_id_index
+    on email_mfa_codes (user_id);
+
+create index email_mfa_codes_exp_index
+    on email_mfa_codes (exp);
+
+create table email_mfa_failures
+(
+    user_id      varchar not null
+        constraint email_mfa_failures_pk
+            primary key
+        references users
+            on delete cascade
+            on update cascade,
+    attempts     bigint  not null,
+    locked_until bigint
+);
@@ -0,0 +1,11 @@
+alter table events
+    add column anonymized boolean default false not null;
+
+create table event_chain_pruned
+(
+    seq       bigint  not null
+        constraint event_chain_pruned_pk
+            primary key,
+    prev_hash varchar not null,
+    hash      varchar not null
+);
@@ -0,0 +1,5 @@
+alter table events
+    add column user_id varchar;
+
+create index events_user_id_index
+    on events (user_id);
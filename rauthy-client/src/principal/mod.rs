@@ -17,7 +17,8 @@ pub struct PrincipalOidc {
     pub id: String,
     /// Matches the `expires_at` token claim -> UNIX timestamp in seconds
     pub expires_at_ts: Option<u64>,
-    /// Rauthy always set's the users email as the `preferred_username`
+    /// Rauthy sets the users email as the `preferred_username`, or the username if it has
+    /// been configured with `USERNAME_PREFERRED_CLAIM`
     pub preferred_username: Option<String>,
     /// Matches the `roles` token claim
     pub roles: Vec<String>,
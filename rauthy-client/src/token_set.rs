@@ -49,6 +49,13 @@ pub struct JktClaim {
     pub jkt: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationClaim {
+    pub id: String,
+    pub name: String,
+    pub owner: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JwtAccessClaims {
     pub sub: Option<String>,
@@ -63,6 +70,7 @@ pub struct JwtAccessClaims {
     pub preferred_username: Option<String>,
     pub roles: Option<Vec<String>>,
     pub groups: Option<Vec<String>>,
+    pub organizations: Option<Vec<OrganizationClaim>>,
     pub cnf: Option<JktClaim>,
     pub custom: Option<HashMap<String, serde_json::Value>>,
 }
@@ -116,6 +124,7 @@ pub struct JwtIdClaims {
     pub phone: Option<String>,
     pub roles: Vec<String>,
     pub groups: Option<Vec<String>>,
+    pub organizations: Option<Vec<OrganizationClaim>>,
     pub cnf: Option<JktClaim>,
     pub custom: Option<HashMap<String, serde_json::Value>>,
     pub webid: Option<String>,
@@ -0,0 +1,175 @@
+use crate::constants::{
+    CACHE_NAME_12HR, CACHE_NAME_AUTH_CODES, CACHE_NAME_AUTH_PROVIDER_CALLBACK,
+    CACHE_NAME_BREAK_GLASS, CACHE_NAME_CLIENTS_DYN, CACHE_NAME_DEVICE_CODES,
+    CACHE_NAME_DPOP_NONCES, CACHE_NAME_EPHEMERAL_CLIENTS, CACHE_NAME_INVALIDATIONS,
+    CACHE_NAME_IP_RATE_LIMIT, CACHE_NAME_LOGIN_DELAY, CACHE_NAME_LOGIN_TRACES, CACHE_NAME_POW,
+    CACHE_NAME_RATE_LIMIT, CACHE_NAME_REQUEST_URIS, CACHE_NAME_REVOKED_TOKENS, CACHE_NAME_SESSIONS,
+    CACHE_NAME_SSO_HANDOFF, CACHE_NAME_USERS, CACHE_NAME_WEBAUTHN, CACHE_NAME_WEBAUTHN_DATA,
+};
+use lazy_static::lazy_static;
+use prometheus::{IntCounterVec, Opts, Registry};
+use redhac::{cache_get_from, cache_get_value, AckLevel, CacheConfig, CacheError};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use tracing::error;
+
+/// All caches, which are spawned at startup. Only these are accepted as metric labels.
+pub static CACHE_NAMES: [&str; 21] = [
+    CACHE_NAME_12HR,
+    CACHE_NAME_AUTH_CODES,
+    CACHE_NAME_AUTH_PROVIDER_CALLBACK,
+    CACHE_NAME_BREAK_GLASS,
+    CACHE_NAME_CLIENTS_DYN,
+    CACHE_NAME_DEVICE_CODES,
+    CACHE_NAME_DPOP_NONCES,
+    CACHE_NAME_EPHEMERAL_CLIENTS,
+    CACHE_NAME_INVALIDATIONS,
+    CACHE_NAME_IP_RATE_LIMIT,
+    CACHE_NAME_LOGIN_DELAY,
+    CACHE_NAME_LOGIN_TRACES,
+    CACHE_NAME_POW,
+    CACHE_NAME_RATE_LIMIT,
+    CACHE_NAME_REQUEST_URIS,
+    CACHE_NAME_REVOKED_TOKENS,
+    CACHE_NAME_SESSIONS,
+    CACHE_NAME_SSO_HANDOFF,
+    CACHE_NAME_USERS,
+    CACHE_NAME_WEBAUTHN,
+    CACHE_NAME_WEBAUTHN_DATA,
+];
+
+lazy_static! {
+    static ref CACHE_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "rauthy_cache_requests_total",
+            "Cache lookups by cache and result"
+        ),
+        &["cache", "result"],
+    )
+    .expect("Cannot build rauthy_cache_requests_total");
+    static ref CACHE_EVICTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "rauthy_cache_evictions_total",
+            "Explicitly removed cache entries by cache"
+        ),
+        &["cache"],
+    )
+    .expect("Cannot build rauthy_cache_evictions_total");
+}
+
+/// Registers the cache counters with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(CACHE_REQUESTS.clone())) {
+        error!("Cannot register cache request metrics: {}", err);
+    }
+    if let Err(err) = registry.register(Box::new(CACHE_EVICTIONS.clone())) {
+        error!("Cannot register cache eviction metrics: {}", err);
+    }
+}
+
+/// Per cache counters since the start of this instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheCounters {
+    pub name: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+pub fn counters() -> Vec<CacheCounters> {
+    CACHE_NAMES
+        .iter()
+        .map(|&name| CacheCounters {
+            name,
+            hits: CACHE_REQUESTS.with_label_values(&[name, "hit"]).get(),
+            misses: CACHE_REQUESTS.with_label_values(&[name, "miss"]).get(),
+            evictions: CACHE_EVICTIONS.with_label_values(&[name]).get(),
+        })
+        .collect()
+}
+
+#[inline]
+pub fn is_known_cache(name: &str) -> bool {
+    CACHE_NAMES.contains(&name)
+}
+
+/// Sharded caches are counted under their logical name.
+#[inline]
+fn base_name(cache_name: &str) -> &str {
+    cache_name
+        .split_once('#')
+        .map(|(name, _)| name)
+        .unwrap_or(cache_name)
+}
+
+/// Drop-in replacement for `redhac::cache_get!`, which counts hits and misses.
+pub async fn cache_get<T: Debug + DeserializeOwned>(
+    cache_name: String,
+    entry: String,
+    cache_config: &CacheConfig,
+    from_remote: bool,
+) -> Result<Option<T>, CacheError> {
+    let res = redhac::cache_get!(T, cache_name.clone(), entry, cache_config, from_remote).await?;
+    let result = if res.is_some() { "hit" } else { "miss" };
+    CACHE_REQUESTS
+        .with_label_values(&[base_name(&cache_name), result])
+        .inc();
+    Ok(res)
+}
+
+/// Drop-in replacement for `redhac::cache_remove`, which counts the eviction.
+pub async fn cache_remove(
+    cache_name: String,
+    entry: String,
+    cache_config: &CacheConfig,
+    ack_level: AckLevel,
+) -> Result<(), CacheError> {
+    CACHE_EVICTIONS
+        .with_label_values(&[base_name(&cache_name)])
+        .inc();
+    redhac::cache_remove(cache_name, entry, cache_config, ack_level).await
+}
+
+/// Drop-in replacement for `redhac::cache_del`, which counts the eviction.
+pub async fn cache_del(
+    cache_name: String,
+    entry: String,
+    cache_config: &CacheConfig,
+) -> Result<(), CacheError> {
+    CACHE_EVICTIONS
+        .with_label_values(&[base_name(&cache_name)])
+        .inc();
+    redhac::cache_del(cache_name, entry, cache_config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_counters() {
+        assert!(is_known_cache(CACHE_NAME_USERS));
+        assert!(is_known_cache(CACHE_NAME_INVALIDATIONS));
+        assert!(is_known_cache(CACHE_NAME_SSO_HANDOFF));
+        assert!(!is_known_cache("unknown"));
+        assert_eq!(base_name("sessions#3"), CACHE_NAME_SESSIONS);
+        assert_eq!(base_name(CACHE_NAME_USERS), CACHE_NAME_USERS);
+
+        let registry = Registry::new();
+        register(&registry);
+        CACHE_REQUESTS
+            .with_label_values(&[CACHE_NAME_POW, "hit"])
+            .inc();
+        assert!(registry
+            .gather()
+            .iter()
+            .any(|m| m.get_name() == "rauthy_cache_requests_total"));
+
+        let pow = counters()
+            .into_iter()
+            .find(|c| c.name == CACHE_NAME_POW)
+            .unwrap();
+        assert_eq!(pow.hits, 1);
+        assert_eq!(pow.misses, 0);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::utils::{parse_date_ts, IpCidr};
 use crate::DbType;
 use actix_web::http::Uri;
 use lazy_static::lazy_static;
@@ -14,17 +15,23 @@ pub const HEADER_ALLOW_ALL_ORIGINS: (&str, &str) = ("access-control-allow-origin
 pub const HEADER_HTML: (&str, &str) = ("content-type", "text/html;charset=utf-8");
 pub const HEADER_JSON: (&str, &str) = ("content-type", "application/json");
 pub const HEADER_RETRY_NOT_BEFORE: &str = "x-retry-not-before";
+pub const HEADER_X_DEBUG: &str = "x-debug";
+pub const HEADER_X_RAUTHY_SERVER_ID: &str = "x-rauthy-server-id";
 pub const APPLICATION_JSON: &str = "application/json";
+pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
 pub const TEXT_TURTLE: &str = "text/turtle";
 
 pub const TOKEN_API_KEY: &str = "API-Key";
 pub const TOKEN_BEARER: &str = "Bearer";
 pub const TOKEN_DPOP: &str = "DPoP";
 pub const TOKEN_DPOP_NONCE: &str = "DPoP-nonce";
+/// The JWT header `typ` for access tokens following RFC 9068
+pub const JWT_TYP_ACCESS_TOKEN: &str = "at+jwt";
 pub const COOKIE_SESSION: &str = "rauthy-session";
 pub const COOKIE_MFA: &str = "rauthy-mfa";
 pub const COOKIE_LOCALE: &str = "locale";
 pub const COOKIE_UPSTREAM_CALLBACK: &str = "upstream_auth_callback";
+pub const COOKIE_SSO_HANDOFF: &str = "rauthy-sso-handoff";
 pub const PROVIDER_LINK_COOKIE: &str = "rauthy-provider-link";
 pub const PWD_RESET_COOKIE: &str = "rauthy-pwd-reset";
 pub const APP_ID_HEADER: &str = "mfa-app-id";
@@ -34,46 +41,77 @@ pub const PWD_CSRF_HEADER: &str = "pwd-csrf-token";
 pub const ARGON2ID_M_COST_MIN: u32 = 32768;
 pub const ARGON2ID_T_COST_MIN: u32 = 1;
 pub const API_KEY_LENGTH: usize = 64;
+/// The `type` prefix of `authorization_details` derived from parameterized scopes, which clients
+/// may never request directly
+pub const AUTHORIZATION_DETAILS_SCOPE_PREFIX: &str = "scope:";
 pub const DEVICE_KEY_LENGTH: u8 = 64;
 pub const EVENTS_LATEST_LIMIT: u16 = 100;
 pub const GRANT_TYPE_DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+pub const GRANT_TYPE_TOKEN_EXCHANGE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+pub const TOKEN_TYPE_ACCESS_TOKEN: &str = "urn:ietf:params:oauth:token-type:access_token";
+/// The max amount of actors in the `act` claim of an exchanged token
+pub const TOKEN_EXCHANGE_MAX_ACTORS: usize = 5;
+pub const CLIENT_ASSERTION_TYPE_SPIFFE: &str =
+    "urn:ietf:params:oauth:client-assertion-type:jwt-spiffe";
+pub const CLIENT_ASSERTION_TYPE_AWS_STS: &str = "urn:rauthy:client-assertion-type:aws-sts";
+pub const CLIENT_ASSERTION_TYPE_GCP_ID_TOKEN: &str =
+    "urn:rauthy:client-assertion-type:gcp-id-token";
 pub const UPSTREAM_AUTH_CALLBACK_TIMEOUT_SECS: u16 = 300;
+pub const SSO_HANDOFF_CONFIRM_TIMEOUT_SECS: u64 = 300;
 
 pub const CACHE_NAME_12HR: &str = "12hr";
 pub const CACHE_NAME_AUTH_CODES: &str = "auth-codes";
 pub const CACHE_NAME_DEVICE_CODES: &str = "device-codes";
 pub const CACHE_NAME_AUTH_PROVIDER_CALLBACK: &str = "auth-provider-callback";
+pub const CACHE_NAME_BREAK_GLASS: &str = "break-glass";
 pub const CACHE_NAME_CLIENTS_DYN: &str = "clients-dyn";
 pub const CACHE_NAME_DPOP_NONCES: &str = "dpop-nonces";
 pub const CACHE_NAME_EPHEMERAL_CLIENTS: &str = "ephemeral-clients";
+pub const CACHE_NAME_INVALIDATIONS: &str = "invalidations";
 pub const CACHE_NAME_IP_RATE_LIMIT: &str = "ip_rate_limit";
 pub const CACHE_NAME_LOGIN_DELAY: &str = "login-dly";
+pub const CACHE_NAME_LOGIN_TRACES: &str = "login-traces";
 pub const CACHE_NAME_SESSIONS: &str = "sessions";
+pub const CACHE_NAME_SSO_HANDOFF: &str = "sso-handoff";
 pub const CACHE_NAME_POW: &str = "pow";
+pub const CACHE_NAME_RATE_LIMIT: &str = "rate-limit";
+pub const CACHE_NAME_REQUEST_URIS: &str = "request-uris";
+pub const CACHE_NAME_REVOKED_TOKENS: &str = "revoked-tokens";
 pub const CACHE_NAME_USERS: &str = "users";
 pub const CACHE_NAME_WEBAUTHN: &str = "webauthn";
 pub const CACHE_NAME_WEBAUTHN_DATA: &str = "webauthn-data";
 
+pub const IDX_ANNOUNCEMENTS: &str = "announcements";
 pub const IDX_APP_VERSION: &str = "rauthy_app_version";
 pub const IDX_AUTH_PROVIDER: &str = "auth_provider_";
 pub const IDX_AUTH_PROVIDER_LOGO: &str = "auth_provider_logo_";
 pub const IDX_AUTH_PROVIDER_TEMPLATE: &str = "provider_json_tpl";
 pub const IDX_CLIENTS: &str = "clients_";
 pub const IDX_CLIENT_LOGO: &str = "client_logo_";
+pub const IDX_FEATURE_FLAGS: &str = "feature_flags";
+pub const IDX_GCP_CERTS: &str = "gcp_certs";
 pub const IDX_GROUPS: &str = "groups_";
+pub const IDX_GROUP_LOGIN_POLICIES: &str = "group_login_policies_";
 pub const IDX_JWK_KID: &str = "jwk_kid_";
 pub const IDX_JWK_LATEST: &str = "jwk_latest_";
 pub const IDX_JWKS: &str = "jkws_";
 pub const IDX_LOGIN_TIME: &str = "login_time_";
+pub const IDX_LOGIN_TRACES: &str = "login_traces_";
 pub const IDX_MFA_APP: &str = "mfa_app_";
 pub const IDX_MFA_LOGIN_REQ: &str = "mfa_login_req_";
 pub const IDX_PASSWORD_RULES: &str = "password_rules_";
+pub const IDX_RESOURCE_SERVERS: &str = "resource_servers";
+pub const IDX_REVOKED_TOKEN: &str = "revoked_token_";
 pub const IDX_ROLES: &str = "roles_";
 pub const IDX_SCOPES: &str = "scopes_";
 pub const IDX_SESSION: &str = "session_";
 pub const IDX_SESSIONS: &str = "sessions";
+pub const IDX_SPIFFE_BUNDLE: &str = "spiffe_bundle";
 pub const IDX_USERS: &str = "users_";
 pub const USER_COUNT_IDX: &str = "users_count_total";
+
+pub const LAB_CLIENT_PREFIX: &str = "lab-client-";
+pub const LAB_EMAIL_DOMAIN: &str = "lab.rauthy.invalid";
 pub const IDX_USERS_VALUES: &str = "users_values_";
 pub const IDX_USER_ATTR_CONFIG: &str = "user_attrs_";
 pub const IDX_WEBAUTHN: &str = "webauthn_";
@@ -105,33 +143,50 @@ lazy_static! {
     pub static ref RE_ALNUM_48: Regex = Regex::new(r"^[a-zA-Z0-9]{48}$").unwrap();
     pub static ref RE_ALNUM_64: Regex = Regex::new(r"^[a-zA-Z0-9]{64}$").unwrap();
     pub static ref RE_ALNUM_SPACE: Regex = Regex::new(r"^[a-zA-Z0-9\s]+$").unwrap();
+    pub static ref RE_ANNOUNCEMENT: Regex = Regex::new(r#"^[a-zA-Z0-9À-ÿ-_.,:;/#()!?'"@%&=+*\s]{2,512}$"#).unwrap();
     pub static ref RE_API_KEY: Regex = Regex::new(r"^[a-zA-Z0-9_/-]{2,24}$").unwrap();
     pub static ref RE_APP_ID: Regex = Regex::new(r"^[a-zA-Z0-9]{12}$").unwrap();
+    pub static ref RE_AUTHORIZATION_DETAILS_TYPE: Regex = Regex::new(r"^[a-zA-Z0-9-_.:/]{1,128}$").unwrap();
+    pub static ref RE_ATTESTATION_TYPE: Regex = Regex::new(r"^(app_attest|app_assertion|play_integrity)$").unwrap();
     pub static ref RE_BASE64: Regex = Regex::new(r"^[a-zA-Z0-9+/=]{4}$").unwrap();
+    pub static ref RE_AWS_STS_HOST: Regex = Regex::new(r"^sts(\.[a-z]{2}(-gov)?-[a-z]+-[0-9])?\.amazonaws\.com$").unwrap();
     pub static ref RE_CHALLENGE: Regex = Regex::new(r"^(plain|S256)$").unwrap();
     pub static ref RE_CITY: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-]{0,48}$").unwrap();
+    pub static ref RE_CLIENT_ASSERTION_TYPE: Regex = Regex::new(r"^(urn:ietf:params:oauth:client-assertion-type:jwt-spiffe|urn:rauthy:client-assertion-type:aws-sts|urn:rauthy:client-assertion-type:gcp-id-token)$").unwrap();
     pub static ref RE_CLIENT_ID_EPHEMERAL: Regex = Regex::new(r"^[a-zA-Z0-9,.:/_\-&?=~#!$'()*+%]{2,128}$").unwrap();
     pub static ref RE_CLIENT_NAME: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-\s]{2,128}$").unwrap();
     pub static ref RE_CODE_CHALLENGE: Regex = Regex::new(r"^[a-zA-Z0-9-\._~]{43,128}$").unwrap();
     pub static ref RE_CODE_VERIFIER: Regex = Regex::new(r"^[a-zA-Z0-9-\._~+/=]+$").unwrap();
     pub static ref RE_CONTACT: Regex = Regex::new(r"^[a-zA-Z0-9\+.@/:]{0,48}$").unwrap();
     pub static ref RE_DATE_STR: Regex = Regex::new(r"^[0-9]{4}-[0-9]{2}-[0-9]{2}$").unwrap();
-    pub static ref RE_GRANT_TYPES: Regex = Regex::new(r"^(authorization_code|client_credentials|urn:ietf:params:oauth:grant-type:device_code|password|refresh_token)$").unwrap();
+    pub static ref RE_DOMAIN: Regex = Regex::new(r"^[a-z0-9-]{1,63}(\.[a-z0-9-]{1,63})+$").unwrap();
+    pub static ref RE_GRANT_TYPES: Regex = Regex::new(r"^(authorization_code|client_credentials|urn:ietf:params:oauth:grant-type:device_code|urn:ietf:params:oauth:grant-type:token-exchange|password|refresh_token)$").unwrap();
     pub static ref RE_GRANT_TYPES_EPHEMERAL: Regex = Regex::new(r"^(authorization_code|client_credentials|password|refresh_token)$").unwrap();
     pub static ref RE_GROUPS: Regex = Regex::new(r"^[a-z0-9-_/,:*]{2,64}$").unwrap();
+    pub static ref RE_LEGAL_HOLD_REASON: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-_.,:;/#()\s]{2,256}$").unwrap();
     pub static ref RE_LOWERCASE: Regex = Regex::new(r"^[a-z0-9-_/]{2,128}$").unwrap();
     pub static ref RE_LOWERCASE_SPACE: Regex = Regex::new(r"^[a-z0-9-_/\s]{2,128}$").unwrap();
+    pub static ref RE_REVOKE_REASON: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-_.,:;/#()\s]{2,256}$").unwrap();
     pub static ref RE_MFA_CODE: Regex = Regex::new(r"^[a-zA-Z0-9]{48}$").unwrap();
+    pub static ref RE_MFA_OTP: Regex = Regex::new(r"^[0-9]{6,10}$").unwrap();
+    pub static ref RE_ORG_NAME: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-_.&\s]{2,64}$").unwrap();
     pub static ref RE_PEM: Regex = Regex::new(r"^(-----BEGIN CERTIFICATE-----)[a-zA-Z0-9+/=\n]+(-----END CERTIFICATE-----)$").unwrap();
+    pub static ref RE_PEM_KEY: Regex = Regex::new(r"^(-----BEGIN (RSA )?PRIVATE KEY-----)[a-zA-Z0-9+/=\r\n]+(-----END (RSA )?PRIVATE KEY-----)\s*$").unwrap();
     pub static ref RE_PHONE: Regex = Regex::new(r"^\+[0-9]{0,32}$").unwrap();
+    pub static ref RE_SCOPE_DESC: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-_.,:;/#()!?'\s]{0,256}$").unwrap();
     // we have a pretty high upper limit for characters here just to be sure that even if
     // multiple values like 'urn:ietf:params:oauth:grant-type:device_code' would not fail
     pub static ref RE_SCOPE_SPACE: Regex = Regex::new(r"^[a-z0-9-_/:\s*]{0,512}$").unwrap();
     pub static ref RE_SEARCH: Regex = Regex::new(r"^[a-zA-Z0-9,.:/_\-&?=~#!$'()*+%@]+$").unwrap();
+    pub static ref RE_SID: Regex = Regex::new(r"^[a-zA-Z0-9_-]{43}$").unwrap();
     pub static ref RE_STREET: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-.\s]{0,48}$").unwrap();
     pub static ref RE_URI: Regex = Regex::new(r"^[a-zA-Z0-9,.:/_\-&?=~#!$'()*+%]+$").unwrap();
+    pub static ref RE_USERNAME: Regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9-_.]{1,47}$").unwrap();
     pub static ref RE_USER_NAME: Regex = Regex::new(r"^[a-zA-Z0-9À-ÿ-\s]{2,32}$").unwrap();
     pub static ref RE_TOKEN_68: Regex = Regex::new(r"^[a-zA-Z0-9-._~+/]+=*$").unwrap();
+    pub static ref RE_TIME: Regex = Regex::new(r"^([01][0-9]|2[0-3]):[0-5][0-9]$").unwrap();
+    pub static ref RE_UTC_OFFSET: Regex = Regex::new(r"^[+-]([01][0-9]|2[0-3]):[0-5][0-9]$").unwrap();
+    pub static ref RE_WEEKDAY: Regex = Regex::new(r"^(mon|tue|wed|thu|fri|sat|sun)$").unwrap();
     pub static ref RE_TOKEN_ENDPOINT_AUTH_METHOD: Regex = Regex::new(r"^(client_secret_post|client_secret_basic|none)$").unwrap();
 
     pub static ref USERINFO_STRICT: bool = env::var("USERINFO_STRICT")
@@ -139,6 +194,15 @@ lazy_static! {
         .parse::<bool>()
         .expect("USERINFO_STRICT cannot be parsed to bool - bad format");
 
+    pub static ref PII_ENCRYPTION: bool = env::var("PII_ENCRYPTION")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("PII_ENCRYPTION cannot be parsed to bool - bad format");
+    pub static ref PII_ENCRYPTION_EMAIL: bool = env::var("PII_ENCRYPTION_EMAIL")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("PII_ENCRYPTION_EMAIL cannot be parsed to bool - bad format");
+
     pub static ref AUTH_HEADERS_ENABLE: bool = env::var("AUTH_HEADERS_ENABLE")
         .unwrap_or_else(|_| String::from("false"))
         .parse::<bool>()
@@ -160,6 +224,19 @@ lazy_static! {
     pub static ref AUTH_HEADER_MFA: String = env::var("AUTH_HEADER_MFA")
         .unwrap_or_else(|_| String::from("x-forwarded-user-mfa"));
 
+    pub static ref ASSET_CACHE_MAX_AGE: u32 = env::var("ASSET_CACHE_MAX_AGE")
+        .unwrap_or_else(|_| String::from("43200"))
+        .parse::<u32>()
+        .expect("ASSET_CACHE_MAX_AGE cannot be parsed to u32 - bad format");
+    pub static ref ASSET_S3_SIGNED_URL_SECS: u64 = env::var("ASSET_S3_SIGNED_URL_SECS")
+        .unwrap_or_else(|_| String::from("3600"))
+        .parse::<u64>()
+        .expect("ASSET_S3_SIGNED_URL_SECS cannot be parsed to u64 - bad format");
+    pub static ref ASSET_S3_PUBLIC_URL: Option<String> = env::var("ASSET_S3_PUBLIC_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| url.trim_end_matches('/').to_string());
+
     pub static ref PUB_URL: String = env::var("PUB_URL").expect("PUB_URL env var is not set");
     pub static ref PUB_URL_WITH_SCHEME: String = {
         let scheme = if env::var("LISTEN_SCHEME").as_deref() == Ok("http") && !*PROXY_MODE {
@@ -217,6 +294,11 @@ lazy_static! {
         let uri = format!("{}://{}/auth/v1/oidc/token", scheme, *PUB_URL);
         Uri::from_str(&uri).unwrap()
     };
+    pub static ref DPOP_USERINFO_ENDPOINT: Uri = {
+        let scheme = if *DEV_MODE && *DEV_DPOP_HTTP { "http" } else { "https" };
+        let uri = format!("{}://{}/auth/v1/oidc/userinfo", scheme, *PUB_URL);
+        Uri::from_str(&uri).unwrap()
+    };
     pub static ref DPOP_FORCE_NONCE: bool = env::var("DPOP_NONCE_FORCE")
         .unwrap_or_else(|_| String::from("true"))
         .parse::<bool>()
@@ -292,6 +374,17 @@ lazy_static! {
        .unwrap_or_else(|_| String::from("48"))
        .parse::<u16>()
        .expect("REFRESH_TOKEN_LIFETIME cannot be parsed to u16 - bad format");
+    pub static ref REFRESH_TOKEN_IDLE_TIMEOUT: Option<u32> = env::var("REFRESH_TOKEN_IDLE_TIMEOUT")
+        .ok()
+        .map(|v| {
+            v.parse::<u32>()
+                .expect("REFRESH_TOKEN_IDLE_TIMEOUT cannot be parsed to u32 - bad format")
+        })
+        .filter(|mins| *mins > 0);
+    pub static ref REFRESH_TOKEN_BIND_DEVICE: bool = env::var("REFRESH_TOKEN_BIND_DEVICE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("REFRESH_TOKEN_BIND_DEVICE cannot be parsed to bool - bad format");
 
     pub static ref PROXY_MODE: bool = env::var("PROXY_MODE")
         .unwrap_or_else(|_| String::from("false"))
@@ -309,7 +402,142 @@ lazy_static! {
         }
     };
 
+    pub static ref USERNAME_LOGIN_ENABLE: bool = env::var("USERNAME_LOGIN_ENABLE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("USERNAME_LOGIN_ENABLE cannot be parsed to bool - bad format");
+    pub static ref HEADLESS_LOGIN_POW: bool = env::var("HEADLESS_LOGIN_POW")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("HEADLESS_LOGIN_POW cannot be parsed to bool - bad format");
+    pub static ref USERNAME_SELF_EDIT: bool = env::var("USERNAME_SELF_EDIT")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("USERNAME_SELF_EDIT cannot be parsed to bool - bad format");
+    pub static ref USERNAME_PREFERRED_CLAIM: bool = env::var("USERNAME_PREFERRED_CLAIM")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("USERNAME_PREFERRED_CLAIM cannot be parsed to bool - bad format");
+    pub static ref EMAIL_NORMALIZE: bool = env::var("EMAIL_NORMALIZE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("EMAIL_NORMALIZE cannot be parsed to bool - bad format");
+    pub static ref EMAIL_NORMALIZE_TAG_DOMAINS: Vec<String> = env::var("EMAIL_NORMALIZE_TAG_DOMAINS")
+        .unwrap_or_else(|_| String::from("gmail.com googlemail.com"))
+        .split(' ')
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| d.trim().to_lowercase())
+        .collect();
+
+    pub static ref LOGIN_DELAY_MAX_SOURCES: usize = env::var("LOGIN_DELAY_MAX_SOURCES")
+        .unwrap_or_else(|_| String::from("10000"))
+        .parse::<usize>()
+        .expect("LOGIN_DELAY_MAX_SOURCES cannot be parsed to usize - bad format");
+    pub static ref LOGIN_DELAY_WINDOW_SAMPLES: usize = env::var("LOGIN_DELAY_WINDOW_SAMPLES")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse::<usize>()
+        .expect("LOGIN_DELAY_WINDOW_SAMPLES cannot be parsed to usize - bad format")
+        .max(1);
+    pub static ref LOGIN_DELAY_WINDOW_SECS: u64 = env::var("LOGIN_DELAY_WINDOW_SECS")
+        .unwrap_or_else(|_| String::from("3600"))
+        .parse::<u64>()
+        .expect("LOGIN_DELAY_WINDOW_SECS cannot be parsed to u64 - bad format");
+
+    pub static ref LOGIN_LATENCY_WINDOW_SECS: u64 = env::var("LOGIN_LATENCY_WINDOW_SECS")
+        .unwrap_or_else(|_| String::from("300"))
+        .parse::<u64>()
+        .expect("LOGIN_LATENCY_WINDOW_SECS cannot be parsed to u64 - bad format");
+    pub static ref LOGIN_SLO_P95_PASSWORD_HASH_MS: u64 = env::var("LOGIN_SLO_P95_PASSWORD_HASH_MS")
+        .unwrap_or_else(|_| String::from("0"))
+        .parse::<u64>()
+        .expect("LOGIN_SLO_P95_PASSWORD_HASH_MS cannot be parsed to u64 - bad format");
+    pub static ref LOGIN_SLO_P95_DB_MS: u64 = env::var("LOGIN_SLO_P95_DB_MS")
+        .unwrap_or_else(|_| String::from("0"))
+        .parse::<u64>()
+        .expect("LOGIN_SLO_P95_DB_MS cannot be parsed to u64 - bad format");
+    pub static ref LOGIN_SLO_P95_AUTHORIZE_MS: u64 = env::var("LOGIN_SLO_P95_AUTHORIZE_MS")
+        .unwrap_or_else(|_| String::from("0"))
+        .parse::<u64>()
+        .expect("LOGIN_SLO_P95_AUTHORIZE_MS cannot be parsed to u64 - bad format");
+
+    pub static ref LOGIN_TRACE_MAX_MINUTES: u16 = env::var("LOGIN_TRACE_MAX_MINUTES")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u16>()
+        .expect("LOGIN_TRACE_MAX_MINUTES cannot be parsed to u16 - bad format");
+    pub static ref LOGIN_TRACE_MAX_RECORDS: usize = env::var("LOGIN_TRACE_MAX_RECORDS")
+        .unwrap_or_else(|_| String::from("1000"))
+        .parse::<usize>()
+        .expect("LOGIN_TRACE_MAX_RECORDS cannot be parsed to usize - bad format");
+
+    pub static ref ERROR_PROBLEM_JSON: bool = env::var("ERROR_PROBLEM_JSON")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("ERROR_PROBLEM_JSON cannot be parsed to bool - bad format");
+    pub static ref ERROR_PROBLEM_TYPE_PREFIX: String = env::var("ERROR_PROBLEM_TYPE_PREFIX")
+        .unwrap_or_else(|_| String::from("urn:rauthy:error:"));
+
+    pub static ref API_V1_DEPRECATED_SINCE: Option<i64> = env::var("API_V1_DEPRECATED_SINCE")
+        .ok()
+        .map(|d| parse_date_ts(&d).expect("API_V1_DEPRECATED_SINCE must be in the format YYYY-MM-DD"));
+    pub static ref API_V1_SUNSET: Option<i64> = env::var("API_V1_SUNSET")
+        .ok()
+        .map(|d| parse_date_ts(&d).expect("API_V1_SUNSET must be in the format YYYY-MM-DD"));
+
+    pub static ref LOG_STREAM_ENABLE: bool = env::var("LOG_STREAM_ENABLE")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("LOG_STREAM_ENABLE cannot be parsed to bool - bad format");
+    pub static ref LOG_STREAM_BUFFER: usize = env::var("LOG_STREAM_BUFFER")
+        .unwrap_or_else(|_| String::from("1000"))
+        .parse::<usize>()
+        .expect("LOG_STREAM_BUFFER cannot be parsed to usize - bad format");
+
     pub static ref PEER_IP_HEADER_NAME: Option<String> = env::var("PEER_IP_HEADER_NAME").ok();
+    pub static ref IP_BLACKLIST_V6_PREFIX: u8 = {
+        let prefix = env::var("IP_BLACKLIST_V6_PREFIX")
+            .unwrap_or_else(|_| String::from("64"))
+            .parse::<u8>()
+            .expect("IP_BLACKLIST_V6_PREFIX cannot be parsed to u8 - bad format");
+        if prefix == 0 || prefix > 128 {
+            panic!("IP_BLACKLIST_V6_PREFIX must be in the range 1..=128");
+        }
+        prefix
+    };
+    pub static ref IP_FEED_INTERVAL_MINS: u64 = env::var("IP_FEED_INTERVAL_MINS")
+        .unwrap_or_else(|_| String::from("720"))
+        .parse::<u64>()
+        .expect("IP_FEED_INTERVAL_MINS cannot be parsed to u64 - bad format")
+        .max(10);
+    pub static ref IP_FEED_ABUSEIPDB_API_KEY: Option<String> =
+        env::var("IP_FEED_ABUSEIPDB_API_KEY").ok().filter(|k| !k.is_empty());
+    pub static ref IP_FEED_ABUSEIPDB_CONFIDENCE_MIN: u8 = env::var("IP_FEED_ABUSEIPDB_CONFIDENCE_MIN")
+        .unwrap_or_else(|_| String::from("90"))
+        .parse::<u8>()
+        .expect("IP_FEED_ABUSEIPDB_CONFIDENCE_MIN cannot be parsed to u8 - bad format")
+        .clamp(25, 100);
+    pub static ref IP_FEED_ABUSEIPDB_LIMIT: u32 = env::var("IP_FEED_ABUSEIPDB_LIMIT")
+        .unwrap_or_else(|_| String::from("10000"))
+        .parse::<u32>()
+        .expect("IP_FEED_ABUSEIPDB_LIMIT cannot be parsed to u32 - bad format");
+    pub static ref IP_FEED_ABUSEIPDB_TTL_HOURS: i64 = env::var("IP_FEED_ABUSEIPDB_TTL_HOURS")
+        .unwrap_or_else(|_| String::from("24"))
+        .parse::<i64>()
+        .expect("IP_FEED_ABUSEIPDB_TTL_HOURS cannot be parsed to i64 - bad format");
+    pub static ref IP_FEED_SPAMHAUS_DROP: bool = env::var("IP_FEED_SPAMHAUS_DROP")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("IP_FEED_SPAMHAUS_DROP cannot be parsed to bool - bad format");
+    pub static ref IP_FEED_SPAMHAUS_TTL_HOURS: i64 = env::var("IP_FEED_SPAMHAUS_TTL_HOURS")
+        .unwrap_or_else(|_| String::from("72"))
+        .parse::<i64>()
+        .expect("IP_FEED_SPAMHAUS_TTL_HOURS cannot be parsed to i64 - bad format");
+    pub static ref LOGIN_TRUSTED_CIDRS: Vec<IpCidr> = env::var("LOGIN_TRUSTED_CIDRS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| IpCidr::from_str(c).expect("Cannot parse LOGIN_TRUSTED_CIDRS - bad format"))
+        .collect();
 
     pub static ref POW_IT: u64 = env::var("POW_IT")
         .unwrap_or_else(|_| String::from("1000000"))
@@ -323,11 +551,33 @@ lazy_static! {
             .unwrap_or_else(|_| String::from("20"))
             .parse::<u8>()
             .expect("POW_DIFFICULTY cannot be parsed to u8 - bad format");
+    pub static ref POW_DIFFICULTY_MAX: u8 = env::var("POW_DIFFICULTY_MAX")
+        .unwrap_or_else(|_| String::from("24"))
+        .parse::<u8>()
+        .expect("POW_DIFFICULTY_MAX cannot be parsed to u8 - bad format");
+    pub static ref POW_SCALE_THRESHOLD: u64 = env::var("POW_SCALE_THRESHOLD")
+        .unwrap_or_else(|_| String::from("100"))
+        .parse::<u64>()
+        .expect("POW_SCALE_THRESHOLD cannot be parsed to u64 - bad format");
 
     pub static ref ADMIN_FORCE_MFA: bool = env::var("ADMIN_FORCE_MFA")
         .unwrap_or_else(|_| String::from("true"))
         .parse::<bool>()
         .expect("ADMIN_FORCE_MFA cannot be parsed to bool - bad format");
+    pub static ref BREAK_GLASS_HASH: Option<String> = env::var("BREAK_GLASS_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty());
+    pub static ref BREAK_GLASS_SESSION_LIFETIME: u32 = env::var("BREAK_GLASS_SESSION_LIFETIME")
+        .unwrap_or_else(|_| String::from("900"))
+        .parse::<u32>()
+        .expect("BREAK_GLASS_SESSION_LIFETIME cannot be parsed to u32 - bad format");
+
+    pub static ref LAB_MODE_SECRET: Option<String> = env::var("LAB_MODE_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty());
+    pub static ref LAB_MODE_SEED: Option<String> = env::var("LAB_MODE_SEED")
+        .ok()
+        .filter(|seed| !seed.is_empty());
 
     pub static ref DPOP_NONCE_EXP: u32 = env::var("DPOP_NONCE_EXP")
         .unwrap_or_else(|_| String::from("900"))
@@ -335,10 +585,79 @@ lazy_static! {
         .parse::<u32>()
         .expect("DPOP_NONCE_EXP cannot be parsed to u32 - bad format");
 
+    pub static ref SESSION_ELEVATION_ENABLE: bool = env::var("SESSION_ELEVATION_ENABLE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("SESSION_ELEVATION_ENABLE cannot be parsed to bool - bad format");
+    pub static ref SESSION_ELEVATION_MINUTES: u32 = env::var("SESSION_ELEVATION_MINUTES")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse::<u32>()
+        .expect("SESSION_ELEVATION_MINUTES cannot be parsed to u32 - bad format");
     pub static ref SESSION_LIFETIME: u32 = env::var("SESSION_LIFETIME")
         .unwrap_or_else(|_| String::from("14400"))
         .parse::<u32>()
         .expect("SESSION_LIFETIME cannot be parsed to u32 - bad format");
+    pub static ref SESSION_REVOKE_NOTIFY_USER: bool = env::var("SESSION_REVOKE_NOTIFY_USER")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("SESSION_REVOKE_NOTIFY_USER cannot be parsed to bool - bad format");
+    pub static ref SESSION_REVOKE_ON_CREDENTIAL_CHANGE: bool = env::var("SESSION_REVOKE_ON_CREDENTIAL_CHANGE")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("SESSION_REVOKE_ON_CREDENTIAL_CHANGE cannot be parsed to bool - bad format");
+    pub static ref MFA_RECOVERY_ENABLE: bool = env::var("MFA_RECOVERY_ENABLE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("MFA_RECOVERY_ENABLE cannot be parsed to bool - bad format");
+    pub static ref MFA_RECOVERY_CODE_LIFETIME: u32 = env::var("MFA_RECOVERY_CODE_LIFETIME")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse::<u32>()
+        .expect("MFA_RECOVERY_CODE_LIFETIME cannot be parsed to u32 - bad format");
+    pub static ref MFA_RECOVERY_WAIT_HOURS: u32 = env::var("MFA_RECOVERY_WAIT_HOURS")
+        .unwrap_or_else(|_| String::from("72"))
+        .parse::<u32>()
+        .expect("MFA_RECOVERY_WAIT_HOURS cannot be parsed to u32 - bad format");
+    pub static ref MFA_RECOVERY_ADMIN_APPROVAL: bool = env::var("MFA_RECOVERY_ADMIN_APPROVAL")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("MFA_RECOVERY_ADMIN_APPROVAL cannot be parsed to bool - bad format");
+    pub static ref EMAIL_MFA_ENABLE: bool = env::var("EMAIL_MFA_ENABLE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("EMAIL_MFA_ENABLE cannot be parsed to bool - bad format");
+    pub static ref EMAIL_MFA_CODE_LENGTH: usize = env::var("EMAIL_MFA_CODE_LENGTH")
+        .unwrap_or_else(|_| String::from("6"))
+        .parse::<usize>()
+        .expect("EMAIL_MFA_CODE_LENGTH cannot be parsed to usize - bad format")
+        .clamp(6, 10);
+    pub static ref EMAIL_MFA_CODE_LIFETIME: u32 = env::var("EMAIL_MFA_CODE_LIFETIME")
+        .unwrap_or_else(|_| String::from("5"))
+        .parse::<u32>()
+        .expect("EMAIL_MFA_CODE_LIFETIME cannot be parsed to u32 - bad format");
+    pub static ref EMAIL_MFA_MAX_ATTEMPTS: i64 = env::var("EMAIL_MFA_MAX_ATTEMPTS")
+        .unwrap_or_else(|_| String::from("3"))
+        .parse::<i64>()
+        .expect("EMAIL_MFA_MAX_ATTEMPTS cannot be parsed to i64 - bad format")
+        .max(1);
+    pub static ref EMAIL_MFA_LOCKOUT: u32 = env::var("EMAIL_MFA_LOCKOUT")
+        .unwrap_or_else(|_| String::from("15"))
+        .parse::<u32>()
+        .expect("EMAIL_MFA_LOCKOUT cannot be parsed to u32 - bad format");
+    pub static ref PUSH_MFA_ENABLE: bool = env::var("PUSH_MFA_ENABLE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("PUSH_MFA_ENABLE cannot be parsed to bool - bad format");
+    pub static ref PUSH_MFA_REQ_LIFETIME: u32 = env::var("PUSH_MFA_REQ_LIFETIME")
+        .unwrap_or_else(|_| String::from("90"))
+        .parse::<u32>()
+        .expect("PUSH_MFA_REQ_LIFETIME cannot be parsed to u32 - bad format")
+        .clamp(30, 300);
+    pub static ref PUSH_MFA_VAPID_SUBJECT: String = env::var("PUSH_MFA_VAPID_SUBJECT")
+        .unwrap_or_else(|_| format!("https://{}", *PUB_URL));
+    pub static ref ROLE_GRANT_MAX_HOURS: u32 = env::var("ROLE_GRANT_MAX_HOURS")
+        .unwrap_or_else(|_| String::from("24"))
+        .parse::<u32>()
+        .expect("ROLE_GRANT_MAX_HOURS cannot be parsed to u32 - bad format");
     pub static ref SESSION_RENEW_MFA: bool = env::var("SESSION_RENEW_MFA")
         .unwrap_or_else(|_| String::from("false"))
         .parse::<bool>()
@@ -356,6 +675,19 @@ lazy_static! {
         .unwrap_or_else(|_| String::from("30"))
         .parse::<u16>()
         .expect("SSE_KEEP_ALIVE cannot be parsed to u16 - bad format");
+    pub static ref SSE_CLIENT_BUFFER: usize = env::var("SSE_CLIENT_BUFFER")
+        .unwrap_or_else(|_| String::from("32"))
+        .parse::<usize>()
+        .expect("SSE_CLIENT_BUFFER cannot be parsed to usize - bad format")
+        .max(1);
+    pub static ref SSE_CLIENT_MAX_DROPS: u32 = env::var("SSE_CLIENT_MAX_DROPS")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse::<u32>()
+        .expect("SSE_CLIENT_MAX_DROPS cannot be parsed to u32 - bad format");
+    pub static ref SSE_MAX_CLIENTS: usize = env::var("SSE_MAX_CLIENTS")
+        .unwrap_or_else(|_| String::from("10000"))
+        .parse::<usize>()
+        .expect("SSE_MAX_CLIENTS cannot be parsed to usize - bad format");
 
     pub static ref EMAIL_SUB_PREFIX: String = env::var("EMAIL_SUB_PREFIX")
         .unwrap_or_else(|_| String::from("Rauthy IAM"));
@@ -375,6 +707,327 @@ lazy_static! {
         .trim()
         .to_string();
 
+    pub static ref SMTP_START_DEGRADED: bool = env::var("SMTP_START_DEGRADED")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("SMTP_START_DEGRADED cannot be parsed to bool - bad format");
+
+    pub static ref SMS_GATEWAY_URL: Option<String> = env::var("SMS_GATEWAY_URL")
+        .ok()
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty());
+    pub static ref SMS_GATEWAY_TOKEN: Option<String> = env::var("SMS_GATEWAY_TOKEN")
+        .ok()
+        .map(|token| token.trim().to_string());
+
+    pub static ref STARTUP_BACKOFF_MAX_SECS: u64 = env::var("STARTUP_BACKOFF_MAX_SECS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse::<u64>()
+        .expect("STARTUP_BACKOFF_MAX_SECS cannot be parsed to u64 - bad format");
+    pub static ref STARTUP_DB_RETRIES: u32 = env::var("STARTUP_DB_RETRIES")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse::<u32>()
+        .expect("STARTUP_DB_RETRIES cannot be parsed to u32 - bad format");
+
+    pub static ref JOB_WORKERS: usize = env::var("JOB_WORKERS")
+        .unwrap_or_else(|_| String::from("4"))
+        .parse::<usize>()
+        .expect("JOB_WORKERS cannot be parsed to usize - bad format");
+    pub static ref JOB_POLL_INTERVAL_SECS: u64 = env::var("JOB_POLL_INTERVAL_SECS")
+        .unwrap_or_else(|_| String::from("5"))
+        .parse::<u64>()
+        .expect("JOB_POLL_INTERVAL_SECS cannot be parsed to u64 - bad format");
+    pub static ref JOB_MAX_ATTEMPTS: i64 = env::var("JOB_MAX_ATTEMPTS")
+        .unwrap_or_else(|_| String::from("8"))
+        .parse::<i64>()
+        .expect("JOB_MAX_ATTEMPTS cannot be parsed to i64 - bad format");
+    pub static ref JOB_BACKOFF_BASE_SECS: i64 = env::var("JOB_BACKOFF_BASE_SECS")
+        .unwrap_or_else(|_| String::from("10"))
+        .parse::<i64>()
+        .expect("JOB_BACKOFF_BASE_SECS cannot be parsed to i64 - bad format");
+    pub static ref JOB_BACKOFF_MAX_SECS: i64 = env::var("JOB_BACKOFF_MAX_SECS")
+        .unwrap_or_else(|_| String::from("3600"))
+        .parse::<i64>()
+        .expect("JOB_BACKOFF_MAX_SECS cannot be parsed to i64 - bad format");
+    pub static ref JOB_DEAD_RETENTION_DAYS: i64 = env::var("JOB_DEAD_RETENTION_DAYS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse::<i64>()
+        .expect("JOB_DEAD_RETENTION_DAYS cannot be parsed to i64 - bad format");
+    pub static ref EMAIL_BATCH_SIZE: i64 = env::var("EMAIL_BATCH_SIZE")
+        .unwrap_or_else(|_| String::from("50"))
+        .parse::<i64>()
+        .expect("EMAIL_BATCH_SIZE cannot be parsed to i64 - bad format");
+    pub static ref EMAIL_DOMAIN_RATE_LIMIT: u32 = env::var("EMAIL_DOMAIN_RATE_LIMIT")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u32>()
+        .expect("EMAIL_DOMAIN_RATE_LIMIT cannot be parsed to u32 - bad format");
+    pub static ref EMAIL_BACKLOG_THRESHOLD: i64 = env::var("EMAIL_BACKLOG_THRESHOLD")
+        .unwrap_or_else(|_| String::from("500"))
+        .parse::<i64>()
+        .expect("EMAIL_BACKLOG_THRESHOLD cannot be parsed to i64 - bad format");
+    pub static ref EMAIL_CHANNEL_BUFFER: usize = env::var("EMAIL_CHANNEL_BUFFER")
+        .unwrap_or_else(|_| String::from("128"))
+        .parse::<usize>()
+        .expect("EMAIL_CHANNEL_BUFFER cannot be parsed to usize - bad format");
+    pub static ref EMAIL_DELIVERY_RETENTION_DAYS: i64 = env::var("EMAIL_DELIVERY_RETENTION_DAYS")
+        .unwrap_or_else(|_| String::from("7"))
+        .parse::<i64>()
+        .expect("EMAIL_DELIVERY_RETENTION_DAYS cannot be parsed to i64 - bad format");
+    pub static ref EMAIL_WEBHOOK_TOKEN: Option<String> = env::var("EMAIL_WEBHOOK_TOKEN")
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty());
+    pub static ref SMTP_RELAY_COOLDOWN_SECS: u64 = env::var("SMTP_RELAY_COOLDOWN_SECS")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u64>()
+        .expect("SMTP_RELAY_COOLDOWN_SECS cannot be parsed to u64 - bad format");
+
+    pub static ref SCHED_CLEANUP_MAGIC_LINKS_MINS: u64 = env::var("SCHED_CLEANUP_MAGIC_LINKS_MINS")
+        .unwrap_or_else(|_| String::from("360"))
+        .parse::<u64>()
+        .expect("SCHED_CLEANUP_MAGIC_LINKS_MINS cannot be parsed to u64 - bad format");
+    pub static ref SCHED_CLEANUP_PASSWORD_RESETS_MINS: u64 = env::var("SCHED_CLEANUP_PASSWORD_RESETS_MINS")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u64>()
+        .expect("SCHED_CLEANUP_PASSWORD_RESETS_MINS cannot be parsed to u64 - bad format");
+    pub static ref SCHED_CLEANUP_REFRESH_TOKENS_MINS: u64 = env::var("SCHED_CLEANUP_REFRESH_TOKENS_MINS")
+        .unwrap_or_else(|_| String::from("180"))
+        .parse::<u64>()
+        .expect("SCHED_CLEANUP_REFRESH_TOKENS_MINS cannot be parsed to u64 - bad format");
+    pub static ref SCHED_CLEANUP_WEBAUTHN_MINS: u64 = env::var("SCHED_CLEANUP_WEBAUTHN_MINS")
+        .unwrap_or_else(|_| String::from("1440"))
+        .parse::<u64>()
+        .expect("SCHED_CLEANUP_WEBAUTHN_MINS cannot be parsed to u64 - bad format");
+
+    pub static ref CACHE_PRELOAD: bool = env::var("CACHE_PRELOAD")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("CACHE_PRELOAD cannot be parsed to bool - bad format");
+    pub static ref CACHE_PRELOAD_SESSIONS_LIMIT: i64 = env::var("CACHE_PRELOAD_SESSIONS_LIMIT")
+        .unwrap_or_else(|_| String::from("1000"))
+        .parse::<i64>()
+        .expect("CACHE_PRELOAD_SESSIONS_LIMIT cannot be parsed to i64 - bad format");
+    pub static ref CACHE_PRELOAD_SESSIONS_MINUTES: i64 = env::var("CACHE_PRELOAD_SESSIONS_MINUTES")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<i64>()
+        .expect("CACHE_PRELOAD_SESSIONS_MINUTES cannot be parsed to i64 - bad format");
+    pub static ref CACHE_SESSIONS_SHARDS: u16 = env::var("CACHE_SESSIONS_SHARDS")
+        .unwrap_or_else(|_| String::from("1"))
+        .parse::<u16>()
+        .expect("CACHE_SESSIONS_SHARDS cannot be parsed to u16 - bad format")
+        .max(1);
+
+    pub static ref CLUSTER_HEARTBEAT_SECS: u64 = env::var("CLUSTER_HEARTBEAT_SECS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse::<u64>()
+        .expect("CLUSTER_HEARTBEAT_SECS cannot be parsed to u64 - bad format")
+        .max(5);
+    pub static ref CLUSTER_NODE_RETENTION_HOURS: i64 = env::var("CLUSTER_NODE_RETENTION_HOURS")
+        .unwrap_or_else(|_| String::from("24"))
+        .parse::<i64>()
+        .expect("CLUSTER_NODE_RETENTION_HOURS cannot be parsed to i64 - bad format");
+
+    pub static ref COMPRESSION_ENABLE: bool = env::var("COMPRESSION_ENABLE")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("COMPRESSION_ENABLE cannot be parsed to bool - bad format");
+    pub static ref COMPRESSION_MIN_SIZE: u64 = env::var("COMPRESSION_MIN_SIZE")
+        .unwrap_or_else(|_| String::from("1024"))
+        .parse::<u64>()
+        .expect("COMPRESSION_MIN_SIZE cannot be parsed to u64 - bad format");
+
+    pub static ref PAYLOAD_LIMIT_AUTH: usize = env::var("PAYLOAD_LIMIT_AUTH")
+        .unwrap_or_else(|_| String::from("16384"))
+        .parse::<usize>()
+        .expect("PAYLOAD_LIMIT_AUTH cannot be parsed to usize - bad format");
+    pub static ref PAYLOAD_LIMIT_DEFAULT: usize = env::var("PAYLOAD_LIMIT_DEFAULT")
+        .unwrap_or_else(|_| String::from("1048576"))
+        .parse::<usize>()
+        .expect("PAYLOAD_LIMIT_DEFAULT cannot be parsed to usize - bad format");
+    pub static ref PAYLOAD_LIMIT_UPLOAD: usize = env::var("PAYLOAD_LIMIT_UPLOAD")
+        .unwrap_or_else(|_| String::from("2097152"))
+        .parse::<usize>()
+        .expect("PAYLOAD_LIMIT_UPLOAD cannot be parsed to usize - bad format");
+    pub static ref REQUEST_TIMEOUT_SECS: u64 = env::var("REQUEST_TIMEOUT_SECS")
+        .unwrap_or_else(|_| String::from("30"))
+        .parse::<u64>()
+        .expect("REQUEST_TIMEOUT_SECS cannot be parsed to u64 - bad format");
+    pub static ref TOKEN_RATE_LIMIT: Option<u32> = env::var("TOKEN_RATE_LIMIT")
+        .map(|rl| rl.parse::<u32>()
+        .expect("TOKEN_RATE_LIMIT cannot be parsed to u32 - bad format"))
+        .ok();
+    pub static ref TOKEN_USER_AGENT_STATS: bool = env::var("TOKEN_USER_AGENT_STATS")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("TOKEN_USER_AGENT_STATS cannot be parsed to bool - bad format");
+    pub static ref CLOCK_SKEW_LEEWAY: u64 = env::var("CLOCK_SKEW_LEEWAY")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u64>()
+        .expect("CLOCK_SKEW_LEEWAY cannot be parsed to u64 - bad format");
+    pub static ref ACCESS_TOKEN_JWT_PROFILE: bool = env::var("ACCESS_TOKEN_JWT_PROFILE")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("ACCESS_TOKEN_JWT_PROFILE cannot be parsed to bool - bad format");
+    pub static ref SESSION_TOKEN_CLIENTS: Vec<String> = env::var("SESSION_TOKEN_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| id.trim().to_string())
+        .collect();
+    pub static ref SESSION_TOKEN_LIFETIME: i32 = env::var("SESSION_TOKEN_LIFETIME")
+        .unwrap_or_else(|_| String::from("300"))
+        .parse::<i32>()
+        .expect("SESSION_TOKEN_LIFETIME cannot be parsed to i32 - bad format");
+    pub static ref SESSION_TOKEN_ROTATE: bool = env::var("SESSION_TOKEN_ROTATE")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("SESSION_TOKEN_ROTATE cannot be parsed to bool - bad format");
+
+    pub static ref SSO_HANDOFF_PEERS: Vec<String> = env::var("SSO_HANDOFF_PEERS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|iss| !iss.trim().is_empty())
+        .map(|iss| iss.trim().trim_end_matches('/').to_string())
+        .collect();
+    pub static ref SSO_HANDOFF_TOKEN_LIFETIME: u64 = env::var("SSO_HANDOFF_TOKEN_LIFETIME")
+        .unwrap_or_else(|_| String::from("60"))
+        .parse::<u64>()
+        .expect("SSO_HANDOFF_TOKEN_LIFETIME cannot be parsed to u64 - bad format");
+
+    pub static ref SPIFFE_TRUST_DOMAIN: Option<String> = env::var("SPIFFE_TRUST_DOMAIN").ok();
+    pub static ref SPIFFE_JWKS_URL: Option<String> = env::var("SPIFFE_JWKS_URL").ok();
+    // (SPIFFE ID, client_id)
+    pub static ref SPIFFE_CLIENTS: Vec<(String, String)> = env::var("SPIFFE_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            let (spiffe_id, client_id) = m
+                .trim()
+                .split_once('=')
+                .expect("SPIFFE_CLIENTS must be in the format 'spiffe_id=client_id'");
+            if !spiffe_id.starts_with("spiffe://") {
+                panic!("SPIFFE_CLIENTS: '{}' is not a valid SPIFFE ID", spiffe_id);
+            }
+            (spiffe_id.to_string(), client_id.to_string())
+        })
+        .collect();
+
+    // (IAM ARN, client_id)
+    pub static ref WORKLOAD_AWS_CLIENTS: Vec<(String, String)> = env::var("WORKLOAD_AWS_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            let (arn, client_id) = m
+                .trim()
+                .split_once('=')
+                .expect("WORKLOAD_AWS_CLIENTS must be in the format 'arn=client_id'");
+            if !arn.starts_with("arn:aws") {
+                panic!("WORKLOAD_AWS_CLIENTS: '{}' is not a valid ARN", arn);
+            }
+            (arn.to_string(), client_id.to_string())
+        })
+        .collect();
+    // (service account email or unique id, client_id)
+    pub static ref WORKLOAD_GCP_CLIENTS: Vec<(String, String)> = env::var("WORKLOAD_GCP_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            let (account, client_id) = m
+                .trim()
+                .split_once('=')
+                .expect("WORKLOAD_GCP_CLIENTS must be in the format 'service_account=client_id'");
+            (account.to_string(), client_id.to_string())
+        })
+        .collect();
+
+    // (team_id.bundle_id, client_id)
+    pub static ref APP_ATTEST_CLIENTS: Vec<(String, String)> = env::var("APP_ATTEST_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            let (app_id, client_id) = m
+                .trim()
+                .split_once('=')
+                .expect("APP_ATTEST_CLIENTS must be in the format 'team_id.bundle_id=client_id'");
+            if !app_id.contains('.') {
+                panic!("APP_ATTEST_CLIENTS: '{}' is not a valid App ID", app_id);
+            }
+            (app_id.to_string(), client_id.to_string())
+        })
+        .collect();
+    pub static ref APP_ATTEST_PRODUCTION: bool = env::var("APP_ATTEST_PRODUCTION")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("APP_ATTEST_PRODUCTION cannot be parsed to bool - bad format");
+    // (package_name, client_id)
+    pub static ref PLAY_INTEGRITY_CLIENTS: Vec<(String, String)> = env::var("PLAY_INTEGRITY_CLIENTS")
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            let (package, client_id) = m
+                .trim()
+                .split_once('=')
+                .expect("PLAY_INTEGRITY_CLIENTS must be in the format 'package_name=client_id'");
+            (package.to_string(), client_id.to_string())
+        })
+        .collect();
+    pub static ref PLAY_INTEGRITY_DECRYPTION_KEY: Option<String> =
+        env::var("PLAY_INTEGRITY_DECRYPTION_KEY").ok().filter(|k| !k.is_empty());
+    pub static ref PLAY_INTEGRITY_VERIFICATION_KEY: Option<String> =
+        env::var("PLAY_INTEGRITY_VERIFICATION_KEY").ok().filter(|k| !k.is_empty());
+    pub static ref PLAY_INTEGRITY_REQUIRE_DEVICE: bool = env::var("PLAY_INTEGRITY_REQUIRE_DEVICE")
+        .unwrap_or_else(|_| String::from("true"))
+        .parse::<bool>()
+        .expect("PLAY_INTEGRITY_REQUIRE_DEVICE cannot be parsed to bool - bad format");
+
+    pub static ref LISTEN_HTTP2_CLEARTEXT: bool = env::var("LISTEN_HTTP2_CLEARTEXT")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("LISTEN_HTTP2_CLEARTEXT cannot be parsed to bool - bad format");
+    pub static ref READ_ONLY_MODE: bool = match env::var("MODE").as_deref() {
+        Err(_) | Ok("primary") => false,
+        Ok("readonly") => true,
+        Ok(mode) => panic!("Unknown MODE '{}' - must be one of: primary, readonly", mode),
+    };
+    pub static ref READ_ONLY_CACHE_LIFESPAN: u64 = env::var("READ_ONLY_CACHE_LIFESPAN")
+        .unwrap_or_else(|_| String::from("30"))
+        .trim()
+        .parse::<u64>()
+        .expect("READ_ONLY_CACHE_LIFESPAN cannot be parsed to u64 - bad format");
+
+    pub static ref LISTEN_PORT_ADMIN: Option<u16> = env::var("LISTEN_PORT_ADMIN")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<u16>().expect("LISTEN_PORT_ADMIN cannot be parsed to u16 - bad format"));
+
+    pub static ref METRICS_AUTH_BEARER: Option<String> = env::var("METRICS_AUTH_BEARER")
+        .ok()
+        .filter(|t| !t.is_empty());
+    pub static ref METRICS_AUTH_BASIC: Option<String> = env::var("METRICS_AUTH_BASIC")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .inspect(|creds| {
+            if !creds.contains(':') {
+                panic!("METRICS_AUTH_BASIC must be in the format 'user:password'");
+            }
+        });
+    pub static ref METRICS_ALLOWED_CIDRS: Vec<IpCidr> = env::var("METRICS_ALLOWED_CIDRS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| IpCidr::from_str(c).expect("Cannot parse METRICS_ALLOWED_CIDRS - bad format"))
+        .collect();
+    pub static ref METRICS_TLS: bool = env::var("METRICS_TLS")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("METRICS_TLS cannot be parsed to bool - bad format");
+
     pub static ref SWAGGER_UI_INTERNAL: bool = env::var("SESSION_VALIDATE_IP")
         .unwrap_or_else(|_| String::from("true"))
         .parse::<bool>()
@@ -414,4 +1067,8 @@ lazy_static! {
         .unwrap_or_else(|_| String::from("true"))
         .parse::<bool>()
         .expect("WEBAUTHN_NO_PASSWORD_EXPIRY cannot be parsed to bool - bad format");
+    pub static ref WEBAUTHN_PASSKEY_RESET_VIA_EMAIL: bool = env::var("WEBAUTHN_PASSKEY_RESET_VIA_EMAIL")
+        .unwrap_or_else(|_| String::from("false"))
+        .parse::<bool>()
+        .expect("WEBAUTHN_PASSKEY_RESET_VIA_EMAIL cannot be parsed to bool - bad format");
 }
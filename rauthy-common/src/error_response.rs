@@ -1,9 +1,13 @@
-use crate::constants::{APPLICATION_JSON, HEADER_DPOP_NONCE, HEADER_HTML, HEADER_RETRY_NOT_BEFORE};
+use crate::constants::{
+    APPLICATION_JSON, APPLICATION_PROBLEM_JSON, ERROR_PROBLEM_TYPE_PREFIX, HEADER_DPOP_NONCE,
+    HEADER_HTML, HEADER_RETRY_NOT_BEFORE,
+};
 use actix_multipart::MultipartError;
+use actix_web::body::BoxBody;
 use actix_web::error::BlockingError;
 use actix_web::http::header::{
-    InvalidHeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
-    WWW_AUTHENTICATE,
+    HeaderValue, InvalidHeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    CONTENT_TYPE, WWW_AUTHENTICATE,
 };
 use actix_web::http::{header, StatusCode};
 use actix_web::{HttpResponse, HttpResponseBuilder, ResponseError};
@@ -44,6 +48,8 @@ pub enum ErrorResponseType {
     NotFound,
     PasswordExpired,
     PasswordRefresh,
+    PayloadTooLarge,
+    RequestTimeout,
     SessionExpired,
     SessionTimeout,
     TooManyRequests(i64),
@@ -57,27 +63,255 @@ impl Display for ErrorResponseType {
     }
 }
 
+/// Stable, machine-readable error codes.
+///
+/// Client applications should branch on these codes instead of parsing the English `message`.
+/// Existing codes will never be renamed or removed, new ones may be added at any time.
+/// Each code has an i18n message key in the format `error.<code>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    AlreadyExists,
+    ApiKeyExpired,
+    ApiKeyInvalid,
+    AttestationInvalid,
+    BadRequest,
+    ClientDisabled,
+    ClientSecretInvalid,
+    ConnectionError,
+    CsrfTokenInvalid,
+    DatabaseError,
+    Disabled,
+    DpopInvalid,
+    DpopNonceRequired,
+    ElevationRequired,
+    EncryptionError,
+    FlowNotAllowed,
+    Forbidden,
+    GrantExpired,
+    InternalError,
+    InvalidAuthorizationDetails,
+    InvalidCredentials,
+    InvalidTarget,
+    JoseError,
+    LoginRestricted,
+    MfaRequired,
+    NoSession,
+    NotFound,
+    PasswordExpired,
+    PasswordRefresh,
+    PayloadInvalid,
+    PayloadTooLarge,
+    PkceMismatch,
+    ReadOnlyMode,
+    RedirectUriMismatch,
+    RequestTimeout,
+    SessionExpired,
+    SessionTimeout,
+    TooManyRequests,
+    Unauthorized,
+    UserDisabled,
+    UserExpired,
+    ValidationFailed,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AlreadyExists => "already_exists",
+            Self::ApiKeyExpired => "api_key_expired",
+            Self::ApiKeyInvalid => "api_key_invalid",
+            Self::AttestationInvalid => "attestation_invalid",
+            Self::BadRequest => "bad_request",
+            Self::ClientDisabled => "client_disabled",
+            Self::ClientSecretInvalid => "client_secret_invalid",
+            Self::ConnectionError => "connection_error",
+            Self::CsrfTokenInvalid => "csrf_token_invalid",
+            Self::DatabaseError => "database_error",
+            Self::Disabled => "disabled",
+            Self::DpopInvalid => "dpop_invalid",
+            Self::DpopNonceRequired => "dpop_nonce_required",
+            Self::ElevationRequired => "elevation_required",
+            Self::EncryptionError => "encryption_error",
+            Self::FlowNotAllowed => "flow_not_allowed",
+            Self::Forbidden => "forbidden",
+            Self::GrantExpired => "grant_expired",
+            Self::InternalError => "internal_error",
+            Self::InvalidAuthorizationDetails => "invalid_authorization_details",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::InvalidTarget => "invalid_target",
+            Self::JoseError => "jose_error",
+            Self::LoginRestricted => "login_restricted",
+            Self::MfaRequired => "mfa_required",
+            Self::NoSession => "no_session",
+            Self::NotFound => "not_found",
+            Self::PasswordExpired => "password_expired",
+            Self::PasswordRefresh => "password_refresh",
+            Self::PayloadInvalid => "payload_invalid",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::PkceMismatch => "pkce_mismatch",
+            Self::ReadOnlyMode => "read_only_mode",
+            Self::RedirectUriMismatch => "redirect_uri_mismatch",
+            Self::RequestTimeout => "request_timeout",
+            Self::SessionExpired => "session_expired",
+            Self::SessionTimeout => "session_timeout",
+            Self::TooManyRequests => "too_many_requests",
+            Self::Unauthorized => "unauthorized",
+            Self::UserDisabled => "user_disabled",
+            Self::UserExpired => "user_expired",
+            Self::ValidationFailed => "validation_failed",
+        }
+    }
+
+    /// The i18n key for a translated, user facing message.
+    pub fn i18n_key(&self) -> &'static str {
+        match self {
+            Self::AlreadyExists => "error.already_exists",
+            Self::ApiKeyExpired => "error.api_key_expired",
+            Self::ApiKeyInvalid => "error.api_key_invalid",
+            Self::AttestationInvalid => "error.attestation_invalid",
+            Self::BadRequest => "error.bad_request",
+            Self::ClientDisabled => "error.client_disabled",
+            Self::ClientSecretInvalid => "error.client_secret_invalid",
+            Self::ConnectionError => "error.connection_error",
+            Self::CsrfTokenInvalid => "error.csrf_token_invalid",
+            Self::DatabaseError => "error.database_error",
+            Self::Disabled => "error.disabled",
+            Self::DpopInvalid => "error.dpop_invalid",
+            Self::DpopNonceRequired => "error.dpop_nonce_required",
+            Self::ElevationRequired => "error.elevation_required",
+            Self::EncryptionError => "error.encryption_error",
+            Self::FlowNotAllowed => "error.flow_not_allowed",
+            Self::Forbidden => "error.forbidden",
+            Self::GrantExpired => "error.grant_expired",
+            Self::InternalError => "error.internal_error",
+            Self::InvalidAuthorizationDetails => "error.invalid_authorization_details",
+            Self::InvalidCredentials => "error.invalid_credentials",
+            Self::InvalidTarget => "error.invalid_target",
+            Self::JoseError => "error.jose_error",
+            Self::LoginRestricted => "error.login_restricted",
+            Self::MfaRequired => "error.mfa_required",
+            Self::NoSession => "error.no_session",
+            Self::NotFound => "error.not_found",
+            Self::PasswordExpired => "error.password_expired",
+            Self::PasswordRefresh => "error.password_refresh",
+            Self::PayloadInvalid => "error.payload_invalid",
+            Self::PayloadTooLarge => "error.payload_too_large",
+            Self::PkceMismatch => "error.pkce_mismatch",
+            Self::ReadOnlyMode => "error.read_only_mode",
+            Self::RedirectUriMismatch => "error.redirect_uri_mismatch",
+            Self::RequestTimeout => "error.request_timeout",
+            Self::SessionExpired => "error.session_expired",
+            Self::SessionTimeout => "error.session_timeout",
+            Self::TooManyRequests => "error.too_many_requests",
+            Self::Unauthorized => "error.unauthorized",
+            Self::UserDisabled => "error.user_disabled",
+            Self::UserExpired => "error.user_expired",
+            Self::ValidationFailed => "error.validation_failed",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&ErrorResponseType> for ErrorCode {
+    /// The default code for each error type, if no more specific one has been set.
+    fn from(value: &ErrorResponseType) -> Self {
+        match value {
+            ErrorResponseType::BadRequest => Self::BadRequest,
+            ErrorResponseType::Connection => Self::ConnectionError,
+            ErrorResponseType::CSRFTokenError => Self::CsrfTokenInvalid,
+            ErrorResponseType::Database | ErrorResponseType::DatabaseIo => Self::DatabaseError,
+            ErrorResponseType::Disabled => Self::Disabled,
+            ErrorResponseType::DPoP(_) => Self::DpopInvalid,
+            ErrorResponseType::Encryption => Self::EncryptionError,
+            ErrorResponseType::UseDpopNonce(_) => Self::DpopNonceRequired,
+            ErrorResponseType::Forbidden => Self::Forbidden,
+            ErrorResponseType::Internal => Self::InternalError,
+            ErrorResponseType::JoseError => Self::JoseError,
+            ErrorResponseType::MfaRequired => Self::MfaRequired,
+            ErrorResponseType::NoSession => Self::NoSession,
+            ErrorResponseType::NotFound => Self::NotFound,
+            ErrorResponseType::PasswordExpired => Self::PasswordExpired,
+            ErrorResponseType::PasswordRefresh => Self::PasswordRefresh,
+            ErrorResponseType::PayloadTooLarge => Self::PayloadTooLarge,
+            ErrorResponseType::RequestTimeout => Self::RequestTimeout,
+            ErrorResponseType::SessionExpired => Self::SessionExpired,
+            ErrorResponseType::SessionTimeout => Self::SessionTimeout,
+            ErrorResponseType::TooManyRequests(_) => Self::TooManyRequests,
+            ErrorResponseType::Unauthorized | ErrorResponseType::WWWAuthenticate(_) => {
+                Self::Unauthorized
+            }
+        }
+    }
+}
+
 // This is the default `ErrorResponse` that could be the answer on almost every API endpoint in
 // case something is wrong.<br>
 // Except for input validations, every error will have this format and every possible error in the
 // backend will be converted to this.
 #[derive(Debug, Clone, Display, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
-#[display(fmt = "error: {} message: {}", error, message)]
+#[display(fmt = "error: {} code: {} message: {}", error, code, message)]
+#[schema(example = json!({
+    "timestamp": 1718000000,
+    "error": "Unauthorized",
+    "code": "invalid_credentials",
+    "i18n_key": "error.invalid_credentials",
+    "message": "Invalid user credentials"
+}))]
 pub struct ErrorResponse {
     pub timestamp: i64,
     pub error: ErrorResponseType,
+    /// Stable, machine-readable error code
+    pub code: ErrorCode,
+    /// i18n key for a translated message in the format `error.<code>`
+    pub i18n_key: String,
     pub message: String,
 }
 
 impl ErrorResponse {
     pub fn new(error: ErrorResponseType, message: String) -> Self {
+        let code = ErrorCode::from(&error);
         Self {
             timestamp: OffsetDateTime::now_utc().unix_timestamp(),
             error,
+            code,
+            i18n_key: code.i18n_key().to_string(),
             message,
         }
     }
 
+    /// Overwrites the default [ErrorCode] derived from the [ErrorResponseType] with a more
+    /// specific one.
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self.i18n_key = code.i18n_key().to_string();
+        self
+    }
+
+    /// Converts this error into an RFC 9457 `application/problem+json` response.
+    ///
+    /// Returns `None` for errors which are not JSON in the first place, like the HTML response
+    /// for blacklisted IPs.
+    pub fn problem_json_response(&self, instance: Option<&str>) -> Option<HttpResponse> {
+        if matches!(self.error, ErrorResponseType::TooManyRequests(_)) {
+            return None;
+        }
+
+        let details = ProblemDetails::from_err(self, instance);
+        let body = serde_json::to_string(&details).unwrap();
+        let mut res = self.error_response().set_body(BoxBody::new(body));
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+        );
+        Some(res)
+    }
+
     pub fn error_response_html(&self, body: String) -> HttpResponse {
         HttpResponseBuilder::new(self.status_code())
             .append_header(HEADER_HTML)
@@ -85,6 +319,52 @@ impl ErrorResponse {
     }
 }
 
+/// RFC 9457 Problem Details for HTTP APIs
+///
+/// Returned with `Content-Type: application/problem+json` if the client sent a matching `Accept`
+/// header or if `ERROR_PROBLEM_JSON` is set. `code`, `i18n_key` and `timestamp` are extension
+/// members with the same meaning as inside the default [ErrorResponse].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[schema(example = json!({
+    "type": "urn:rauthy:error:invalid_credentials",
+    "title": "Unauthorized",
+    "status": 401,
+    "detail": "Invalid user credentials",
+    "instance": "/auth/v1/oidc/authorize",
+    "code": "invalid_credentials",
+    "i18n_key": "error.invalid_credentials",
+    "timestamp": 1718000000
+}))]
+pub struct ProblemDetails {
+    /// URI identifying the error class: `ERROR_PROBLEM_TYPE_PREFIX` + `code`
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub code: ErrorCode,
+    pub i18n_key: String,
+    pub timestamp: i64,
+}
+
+impl ProblemDetails {
+    pub fn from_err(err: &ErrorResponse, instance: Option<&str>) -> Self {
+        let status = err.status_code();
+        Self {
+            typ: format!("{}{}", *ERROR_PROBLEM_TYPE_PREFIX, err.code.as_str()),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: err.message.clone(),
+            instance: instance.map(String::from),
+            code: err.code,
+            i18n_key: err.i18n_key.clone(),
+            timestamp: err.timestamp,
+        }
+    }
+}
+
 impl ResponseError for ErrorResponse {
     fn status_code(&self) -> StatusCode {
         match self.error {
@@ -94,6 +374,8 @@ impl ResponseError for ErrorResponse {
             ErrorResponseType::Forbidden => StatusCode::FORBIDDEN,
             ErrorResponseType::MfaRequired => StatusCode::NOT_ACCEPTABLE,
             ErrorResponseType::NotFound => StatusCode::NOT_FOUND,
+            ErrorResponseType::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorResponseType::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
             ErrorResponseType::Disabled
             | ErrorResponseType::CSRFTokenError
             | ErrorResponseType::DPoP(_)
@@ -255,7 +537,8 @@ impl From<sqlx::Error> for ErrorResponse {
                 let s = e.to_string();
                 if s.contains("duplicate key") || s.contains("UNIQUE") {
                     // basically returns http 400 on duplicate id column errors -> no distinct err type
-                    (ErrorResponseType::BadRequest, s)
+                    return ErrorResponse::new(ErrorResponseType::BadRequest, s)
+                        .with_code(ErrorCode::AlreadyExists);
                 } else {
                     (ErrorResponseType::Database, s)
                 }
@@ -340,6 +623,7 @@ impl From<validator::ValidationErrors> for ErrorResponse {
             ErrorResponseType::BadRequest,
             format!("Payload validation error: {:?}", value),
         )
+        .with_code(ErrorCode::ValidationFailed)
     }
 }
 
@@ -350,6 +634,7 @@ impl From<serde_json::Error> for ErrorResponse {
             ErrorResponseType::BadRequest,
             format!("Payload deserialization error: {:?}", value),
         )
+        .with_code(ErrorCode::PayloadInvalid)
     }
 }
 impl From<reqwest::header::ToStrError> for ErrorResponse {
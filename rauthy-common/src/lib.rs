@@ -1,13 +1,20 @@
 // Copyright 2024 Sebastian Dobe <sebastiandobe@mailbox.org>
 #![forbid(unsafe_code)]
 // needed because the lazy_static! initialization of constants grew quite a bit
-#![recursion_limit = "256"]
+#![recursion_limit = "512"]
 
 use std::str::FromStr;
 
+pub mod cache_metrics;
 pub mod constants;
 pub mod error_response;
+pub mod log_stream;
+pub mod login_delay;
+pub mod login_latency;
 pub mod password_hasher;
+pub mod pow_difficulty;
+pub mod rate_limit;
+pub mod token_errors;
 pub mod utils;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
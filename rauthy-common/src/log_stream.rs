@@ -0,0 +1,93 @@
+use crate::constants::{LOG_STREAM_BUFFER, LOG_STREAM_ENABLE};
+use crate::utils::redact;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+lazy_static! {
+    static ref BUFFER: Mutex<VecDeque<LogLine>> =
+        Mutex::new(VecDeque::with_capacity(*LOG_STREAM_BUFFER));
+    static ref TX: broadcast::Sender<LogLine> = broadcast::channel(128).0;
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<&tracing::Level> for LogLevel {
+    fn from(value: &tracing::Level) -> Self {
+        match *value {
+            tracing::Level::ERROR => Self::Error,
+            tracing::Level::WARN => Self::Warn,
+            tracing::Level::INFO => Self::Info,
+            tracing::Level::DEBUG => Self::Debug,
+            tracing::Level::TRACE => Self::Trace,
+        }
+    }
+}
+
+/// A single, already redacted application log line.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LogLine {
+    pub ts: i64,
+    pub level: LogLevel,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogLine {
+    pub fn new(ts: i64, level: LogLevel, target: &str, message: &str) -> Self {
+        Self {
+            ts,
+            level,
+            target: target.to_string(),
+            message: redact(message),
+        }
+    }
+}
+
+/// Saves a new log line into the ring buffer and forwards it to all live listeners.
+///
+/// This is called from inside the tracing subscriber. It must never log anything itself.
+pub fn push(line: LogLine) {
+    if !*LOG_STREAM_ENABLE {
+        return;
+    }
+
+    let mut buf = match BUFFER.lock() {
+        Ok(buf) => buf,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if buf.len() >= *LOG_STREAM_BUFFER {
+        buf.pop_front();
+    }
+    buf.push_back(line.clone());
+
+    // sending while still holding the lock makes sure `subscribe()` never misses or
+    // duplicates a line - no receivers at all is not an error for us
+    let _ = TX.send(line);
+}
+
+/// Returns up to `latest` buffered log lines together with a receiver for all new ones.
+pub fn subscribe(latest: usize) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+    let buf = match BUFFER.lock() {
+        Ok(buf) => buf,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let rx = TX.subscribe();
+    let skip = buf.len().saturating_sub(latest);
+    let lines = buf.iter().skip(skip).cloned().collect();
+    (lines, rx)
+}
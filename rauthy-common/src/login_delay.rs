@@ -0,0 +1,237 @@
+use crate::cache_metrics::cache_get;
+use crate::constants::{
+    CACHE_NAME_LOGIN_DELAY, IDX_LOGIN_TIME, LOGIN_DELAY_MAX_SOURCES, LOGIN_DELAY_WINDOW_SAMPLES,
+    LOGIN_DELAY_WINDOW_SECS,
+};
+use once_cell::sync::Lazy;
+use prometheus::{IntGauge, Registry};
+use redhac::{cache_insert, AckLevel, CacheConfig, CacheError};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+/// Used as long as no successful login has been recorded anywhere in the cluster.
+const DEFAULT_SUCCESS_MILLIS: u64 = 2000;
+const STRIPES: usize = 16;
+/// The aggregated success time is recalculated at most once in this interval.
+const RECALC_INTERVAL: Duration = Duration::from_secs(10);
+/// With fewer local sources, the value published by another cluster member is preferred.
+const MIN_LOCAL_SOURCES: usize = 3;
+
+type Window = VecDeque<(Instant, u64)>;
+
+/// Sliding windows of successful login times per source, striped to reduce lock contention.
+static WINDOWS: Lazy<[Mutex<HashMap<String, Window>>; STRIPES]> =
+    Lazy::new(|| std::array::from_fn(|_| Mutex::new(HashMap::new())));
+static SUCCESS_MILLIS: AtomicU64 = AtomicU64::new(DEFAULT_SUCCESS_MILLIS);
+static LAST_CALC: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+static METRIC_SUCCESS_MILLIS: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "rauthy_login_delay_success_millis",
+        "The time in ms a successful login takes, which is applied to failed ones",
+    )
+    .expect("Cannot build rauthy_login_delay_success_millis")
+});
+static METRIC_SOURCES: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "rauthy_login_delay_sources",
+        "The amount of local sources with successful logins inside the window",
+    )
+    .expect("Cannot build rauthy_login_delay_sources")
+});
+
+/// Registers the login delay gauges with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_SUCCESS_MILLIS.clone())) {
+        error!("Cannot register login delay metrics: {}", err);
+    }
+    if let Err(err) = registry.register(Box::new(METRIC_SOURCES.clone())) {
+        error!("Cannot register login delay metrics: {}", err);
+    }
+}
+
+/// The source of a login for the delay calculation. The account is preferred, since an
+/// attacker can usually switch IPs more easily than accounts.
+pub fn source_key(account: Option<&str>, ip: Option<&str>) -> String {
+    match (account, ip) {
+        (Some(account), _) => format!("acc:{}", account.to_lowercase()),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Records the duration of a successful login for the given source.
+pub fn record_success(source: &str, millis: u64) {
+    let now = Instant::now();
+    let window_dur = Duration::from_secs(*LOGIN_DELAY_WINDOW_SECS);
+    let max_per_stripe = (*LOGIN_DELAY_MAX_SOURCES / STRIPES).max(1);
+
+    let mut windows = WINDOWS[stripe(source)]
+        .lock()
+        .expect("login delay lock to never be poisoned");
+
+    if !windows.contains_key(source) && windows.len() >= max_per_stripe {
+        windows.retain(|_, w| {
+            prune(w, now, window_dur);
+            !w.is_empty()
+        });
+        if windows.len() >= max_per_stripe {
+            debug!("Max login delay sources reached - ignoring new source");
+            return;
+        }
+    }
+
+    let window = windows.entry(source.to_string()).or_default();
+    window.push_back((now, millis));
+    while window.len() > *LOGIN_DELAY_WINDOW_SAMPLES {
+        window.pop_front();
+    }
+}
+
+/// Returns the current time in ms a successful login takes.
+///
+/// The value is recalculated at most every 10 seconds. It is published to the other cluster
+/// members, which will use it as long as they do not have enough local data.
+pub async fn success_millis(cache_config: &CacheConfig) -> Result<u64, CacheError> {
+    if let Some((local, sources)) = recalculate_if_due() {
+        METRIC_SOURCES.set(sources as i64);
+
+        let millis = if sources >= MIN_LOCAL_SOURCES {
+            let millis = local.unwrap_or(DEFAULT_SUCCESS_MILLIS);
+            // `cache_put` would only update the local cache, while the other members read
+            // this value as long as they do not have enough data on their own.
+            // A failed publish must not fail the login itself.
+            if let Err(err) = cache_insert(
+                CACHE_NAME_LOGIN_DELAY.to_string(),
+                IDX_LOGIN_TIME.to_string(),
+                cache_config,
+                &millis,
+                AckLevel::Once,
+            )
+            .await
+            {
+                error!("Cannot publish the login success time: {:?}", err);
+            }
+            millis
+        } else {
+            cache_get::<u64>(
+                CACHE_NAME_LOGIN_DELAY.to_string(),
+                IDX_LOGIN_TIME.to_string(),
+                cache_config,
+                false,
+            )
+            .await?
+            .or(local)
+            .unwrap_or(DEFAULT_SUCCESS_MILLIS)
+        };
+
+        debug!("New login success time: {} ms", millis);
+        SUCCESS_MILLIS.store(millis, Ordering::Relaxed);
+        METRIC_SUCCESS_MILLIS.set(millis as i64);
+    }
+
+    Ok(SUCCESS_MILLIS.load(Ordering::Relaxed))
+}
+
+fn recalculate_if_due() -> Option<(Option<u64>, usize)> {
+    let now = Instant::now();
+    {
+        let mut last = LAST_CALC
+            .lock()
+            .expect("login delay lock to never be poisoned");
+        if let Some(last) = *last {
+            if now.duration_since(last) < RECALC_INTERVAL {
+                return None;
+            }
+        }
+        *last = Some(now);
+    }
+
+    let window_dur = Duration::from_secs(*LOGIN_DELAY_WINDOW_SECS);
+    let mut medians = Vec::new();
+    for stripe in WINDOWS.iter() {
+        let mut windows = stripe
+            .lock()
+            .expect("login delay lock to never be poisoned");
+        windows.retain(|_, w| {
+            prune(w, now, window_dur);
+            !w.is_empty()
+        });
+        medians.extend(windows.values().filter_map(window_median));
+    }
+
+    let sources = medians.len();
+    Some((median(&mut medians), sources))
+}
+
+#[inline]
+fn stripe(source: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish() as usize % STRIPES
+}
+
+#[inline]
+fn prune(window: &mut Window, now: Instant, window_dur: Duration) {
+    while let Some((ts, _)) = window.front() {
+        if now.duration_since(*ts) > window_dur {
+            window.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+#[inline]
+fn window_median(window: &Window) -> Option<u64> {
+    let mut values = window.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+    median(&mut values)
+}
+
+fn median(values: &mut [u64]) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    Some(values[values.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_delay_median() {
+        assert_eq!(median(&mut []), None);
+        assert_eq!(median(&mut [3, 1, 2]), Some(2));
+
+        // a single source with many very fast logins only gets a single vote
+        let now = Instant::now();
+        let attacker: Window = (0..10).map(|_| (now, 10)).collect();
+        let users: Vec<Window> = [1800, 2000, 2200]
+            .into_iter()
+            .map(|v| VecDeque::from([(now, v)]))
+            .collect();
+        let mut medians = users
+            .iter()
+            .chain(std::iter::once(&attacker))
+            .filter_map(window_median)
+            .collect::<Vec<_>>();
+        assert_eq!(median(&mut medians), Some(2000));
+    }
+
+    #[test]
+    fn test_login_delay_source_key() {
+        assert_eq!(
+            source_key(Some("Admin@localhost.de"), Some("127.0.0.1")),
+            "acc:admin@localhost.de"
+        );
+        assert_eq!(source_key(None, Some("127.0.0.1")), "ip:127.0.0.1");
+        assert_eq!(source_key(None, None), "unknown");
+    }
+}
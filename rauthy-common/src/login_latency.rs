@@ -0,0 +1,245 @@
+use crate::constants::{
+    LOGIN_LATENCY_WINDOW_SECS, LOGIN_SLO_P95_AUTHORIZE_MS, LOGIN_SLO_P95_DB_MS,
+    LOGIN_SLO_P95_PASSWORD_HASH_MS,
+};
+use once_cell::sync::Lazy;
+use prometheus::{HistogramOpts, HistogramVec, IntGaugeVec, Opts, Registry};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::error;
+
+/// The max amount of samples kept per stage inside the window.
+const MAX_SAMPLES: usize = 1000;
+/// SLOs are only evaluated with at least this amount of samples to not alert on a single outlier.
+const MIN_SLO_SAMPLES: usize = 20;
+
+/// The measured parts of a login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyStage {
+    /// Hashing or comparing a password, including the wait time for a free hashing slot
+    PasswordHash,
+    /// Looking up a user by its E-Mail or username in the database. Lookups served from the
+    /// cache are not included.
+    Db,
+    /// The whole successful authorize request
+    Authorize,
+}
+
+impl LatencyStage {
+    pub const ALL: [LatencyStage; 3] = [Self::PasswordHash, Self::Db, Self::Authorize];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PasswordHash => "password_hash",
+            Self::Db => "db",
+            Self::Authorize => "authorize",
+        }
+    }
+
+    /// The configured p95 SLO in ms, `None` if disabled.
+    pub fn slo_p95(&self) -> Option<u64> {
+        let slo = match self {
+            Self::PasswordHash => *LOGIN_SLO_P95_PASSWORD_HASH_MS,
+            Self::Db => *LOGIN_SLO_P95_DB_MS,
+            Self::Authorize => *LOGIN_SLO_P95_AUTHORIZE_MS,
+        };
+        if slo > 0 {
+            Some(slo)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn idx(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SloBreach {
+    pub stage: LatencyStage,
+    pub p95: u64,
+    pub slo: u64,
+}
+
+type Window = VecDeque<(Instant, u64)>;
+
+static WINDOWS: Lazy<[Mutex<Window>; 3]> =
+    Lazy::new(|| std::array::from_fn(|_| Mutex::new(VecDeque::new())));
+/// Remembers a running breach per stage to only alert once until it has recovered.
+static BREACHED: [AtomicBool; 3] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+static METRIC_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "rauthy_login_latency_seconds",
+            "The latency of successful logins by stage",
+        )
+        .buckets(vec![
+            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 5.0, 10.0,
+        ]),
+        &["stage"],
+    )
+    .expect("Cannot build rauthy_login_latency_seconds")
+});
+static METRIC_P95: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "rauthy_login_latency_p95_millis",
+            "The p95 login latency in ms by stage inside the current window",
+        ),
+        &["stage"],
+    )
+    .expect("Cannot build rauthy_login_latency_p95_millis")
+});
+
+/// Registers the login latency metrics with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_LATENCY.clone())) {
+        error!("Cannot register login latency metrics: {}", err);
+    }
+    if let Err(err) = registry.register(Box::new(METRIC_P95.clone())) {
+        error!("Cannot register login latency metrics: {}", err);
+    }
+}
+
+/// Records the latency for a stage of a login.
+pub fn record(stage: LatencyStage, latency: Duration) {
+    METRIC_LATENCY
+        .with_label_values(&[stage.as_str()])
+        .observe(latency.as_secs_f64());
+
+    let now = Instant::now();
+    let mut window = WINDOWS[stage.idx()]
+        .lock()
+        .expect("login latency lock to never be poisoned");
+    prune(&mut window, now);
+    window.push_back((now, latency.as_millis() as u64));
+    while window.len() > MAX_SAMPLES {
+        window.pop_front();
+    }
+}
+
+/// Returns the percentiles for the given stage inside the current window.
+pub fn percentiles(stage: LatencyStage) -> Option<LatencyPercentiles> {
+    let mut values = {
+        let mut window = WINDOWS[stage.idx()]
+            .lock()
+            .expect("login latency lock to never be poisoned");
+        prune(&mut window, Instant::now());
+        window.iter().map(|(_, v)| *v).collect::<Vec<_>>()
+    };
+    let res = calc_percentiles(&mut values);
+    METRIC_P95
+        .with_label_values(&[stage.as_str()])
+        .set(res.as_ref().map(|p| p.p95 as i64).unwrap_or_default());
+    res
+}
+
+/// Evaluates the configured p95 SLOs and returns newly started breaches. A breach is only
+/// returned once and will be reported again after the p95 has been below the SLO in between.
+pub fn slo_breaches() -> Vec<SloBreach> {
+    let mut res = Vec::new();
+
+    for stage in LatencyStage::ALL {
+        let Some(slo) = stage.slo_p95() else {
+            continue;
+        };
+        let Some(p) = percentiles(stage) else {
+            BREACHED[stage.idx()].store(false, Ordering::Relaxed);
+            continue;
+        };
+        if p.samples < MIN_SLO_SAMPLES {
+            continue;
+        }
+
+        let is_breach = p.p95 > slo;
+        let was_breach = BREACHED[stage.idx()].swap(is_breach, Ordering::Relaxed);
+        if is_breach && !was_breach {
+            res.push(SloBreach {
+                stage,
+                p95: p.p95,
+                slo,
+            });
+        }
+    }
+
+    res
+}
+
+#[inline]
+fn prune(window: &mut Window, now: Instant) {
+    let window_dur = Duration::from_secs(*LOGIN_LATENCY_WINDOW_SECS);
+    while let Some((ts, _)) = window.front() {
+        if now.duration_since(*ts) > window_dur {
+            window.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+fn calc_percentiles(values: &mut [u64]) -> Option<LatencyPercentiles> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    Some(LatencyPercentiles {
+        p50: percentile(values, 50),
+        p95: percentile(values, 95),
+        p99: percentile(values, 99),
+        samples: values.len(),
+    })
+}
+
+/// Nearest-rank percentile of already sorted, non-empty values.
+#[inline]
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_latency_percentiles() {
+        assert_eq!(calc_percentiles(&mut []), None);
+
+        let mut values = (1..=100).rev().collect::<Vec<u64>>();
+        assert_eq!(
+            calc_percentiles(&mut values),
+            Some(LatencyPercentiles {
+                p50: 50,
+                p95: 95,
+                p99: 99,
+                samples: 100,
+            })
+        );
+
+        let p = calc_percentiles(&mut [7]).unwrap();
+        assert_eq!((p.p50, p.p95, p.p99), (7, 7, 7));
+
+        // a few slow outliers must show up in the p95 without moving the median
+        let mut values = vec![100; 18];
+        values.extend([2000, 3000]);
+        let p = calc_percentiles(&mut values).unwrap();
+        assert_eq!((p.p50, p.p95, p.p99), (100, 2000, 3000));
+    }
+}
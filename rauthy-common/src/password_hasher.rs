@@ -1,4 +1,6 @@
 use crate::error_response::{ErrorResponse, ErrorResponseType};
+use crate::login_latency;
+use crate::login_latency::LatencyStage;
 use actix_web::web;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, PasswordHash, PasswordHasher, PasswordVerifier, Version};
@@ -161,6 +163,7 @@ fn hash_password(msg: HashPassword) {
         .expect("Error hashing the Password")
         .to_string();
 
+    login_latency::record(LatencyStage::PasswordHash, msg.created.elapsed());
     if let Err(err) = msg.tx.send(hash) {
         error!("{}", err);
     }
@@ -187,6 +190,7 @@ fn compare_passwords(msg: ComparePasswords) {
         }
     }
 
+    login_latency::record(LatencyStage::PasswordHash, msg.created.elapsed());
     if let Err(err) = msg.tx.send(is_match) {
         error!("{}", err);
     }
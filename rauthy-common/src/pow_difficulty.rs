@@ -0,0 +1,117 @@
+use crate::constants::{POW_DIFFICULTY, POW_DIFFICULTY_MAX, POW_SCALE_THRESHOLD};
+use once_cell::sync::Lazy;
+use prometheus::{IntCounter, IntGauge, Registry};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+const SCALE_INTERVAL: Duration = Duration::from_secs(60);
+
+static DIFFICULTY: Lazy<AtomicU8> = Lazy::new(|| AtomicU8::new(*POW_DIFFICULTY));
+/// The start of the current interval and the amount of challenges requested inside of it.
+static WINDOW: Lazy<Mutex<(Instant, u64)>> = Lazy::new(|| Mutex::new((Instant::now(), 0)));
+
+static METRIC_DIFFICULTY: Lazy<IntGauge> = Lazy::new(|| {
+    let gauge = IntGauge::new("rauthy_pow_difficulty", "The current PoW difficulty")
+        .expect("Cannot build rauthy_pow_difficulty");
+    gauge.set(*POW_DIFFICULTY as i64);
+    gauge
+});
+static METRIC_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("rauthy_pow_requests_total", "Requested PoW challenges")
+        .expect("Cannot build rauthy_pow_requests_total")
+});
+
+/// Registers the PoW metrics with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_DIFFICULTY.clone())) {
+        error!("Cannot register PoW metrics: {}", err);
+    }
+    if let Err(err) = registry.register(Box::new(METRIC_REQUESTS.clone())) {
+        error!("Cannot register PoW metrics: {}", err);
+    }
+}
+
+/// Counts a new challenge request and returns the difficulty it should be created with.
+pub fn for_new_challenge() -> u8 {
+    METRIC_REQUESTS.inc();
+
+    let mut window = WINDOW
+        .lock()
+        .expect("PoW difficulty lock to never be poisoned");
+    window.1 += 1;
+
+    let elapsed = window.0.elapsed();
+    if elapsed >= SCALE_INTERVAL {
+        let intervals = elapsed.as_secs() / SCALE_INTERVAL.as_secs();
+        let current = DIFFICULTY.load(Ordering::Relaxed);
+        let next = next_difficulty(
+            current,
+            window.1,
+            intervals,
+            *POW_DIFFICULTY,
+            *POW_DIFFICULTY_MAX,
+            *POW_SCALE_THRESHOLD,
+        );
+        if next != current {
+            info!("Changing the PoW difficulty from {} to {}", current, next);
+            DIFFICULTY.store(next, Ordering::Relaxed);
+            METRIC_DIFFICULTY.set(next as i64);
+        }
+
+        *window = (Instant::now(), 0);
+    }
+
+    DIFFICULTY.load(Ordering::Relaxed)
+}
+
+/// Rises the difficulty by 1 if the average rate over the elapsed intervals exceeds the
+/// threshold and decays it by 1 for each elapsed interval below half of the threshold.
+fn next_difficulty(
+    current: u8,
+    count: u64,
+    intervals: u64,
+    base: u8,
+    max: u8,
+    threshold: u64,
+) -> u8 {
+    if threshold == 0 {
+        return base;
+    }
+
+    let intervals = intervals.max(1);
+    if count > threshold.saturating_mul(intervals) {
+        current.saturating_add(1).min(max.max(base))
+    } else if count <= threshold.saturating_mul(intervals) / 2 {
+        let steps = intervals.min(u8::MAX as u64) as u8;
+        current.saturating_sub(steps).max(base)
+    } else {
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow_next_difficulty() {
+        // disabled
+        assert_eq!(next_difficulty(22, 1000, 1, 20, 24, 0), 20);
+
+        // rising up to the max
+        assert_eq!(next_difficulty(20, 101, 1, 20, 24, 100), 21);
+        assert_eq!(next_difficulty(24, 1000, 1, 20, 24, 100), 24);
+
+        // keeping the current difficulty between half of and the threshold
+        assert_eq!(next_difficulty(22, 80, 1, 20, 24, 100), 22);
+
+        // decaying down to the base
+        assert_eq!(next_difficulty(22, 10, 1, 20, 24, 100), 21);
+        assert_eq!(next_difficulty(23, 1, 10, 20, 24, 100), 20);
+
+        // a high count spread over multiple intervals is not an attack
+        assert_eq!(next_difficulty(20, 150, 2, 20, 24, 100), 20);
+    }
+}
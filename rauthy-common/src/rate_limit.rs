@@ -0,0 +1,189 @@
+use crate::cache_metrics::cache_get;
+use crate::constants::{CACHE_NAME_RATE_LIMIT, HA_MODE};
+use crate::error_response::{ErrorResponse, ErrorResponseType};
+use crate::utils::get_local_hostname;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use redhac::{cache_insert, AckLevel, CacheConfig};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Old windows are only cleaned up once the local counters grow above this size.
+const MAX_LOCAL_KEYS: usize = 1024;
+
+/// The counters of this node for the current window. `key -> (window, count)`
+static LOCAL: Lazy<Mutex<HashMap<String, (i64, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The id of this node and the ids of all other cluster members.
+static NODES: Lazy<(String, Vec<String>)> = Lazy::new(|| {
+    let hostname = env::var("HOSTNAME_OVERWRITE").unwrap_or_else(|_| get_local_hostname());
+    node_ids(&env::var("HA_HOSTS").unwrap_or_default(), &hostname)
+});
+
+/// Counts a request for the given key and returns an error if more than `limit` requests
+/// happened during the current second across the whole cluster.
+///
+/// Each node counts its own requests locally and publishes its count for the current window
+/// via the HA cache. The total is the local count plus the last published counts of all other
+/// members, which makes it eventually consistent within the replication delay.
+pub async fn check(key: &str, limit: u32, cache_config: &CacheConfig) -> Result<(), ErrorResponse> {
+    let window = Utc::now().timestamp();
+    let local = count_local(key, window);
+
+    let mut total = local;
+    if *HA_MODE {
+        let (own, others) = &*NODES;
+        for node in others {
+            total += cache_get::<u32>(
+                CACHE_NAME_RATE_LIMIT.to_string(),
+                cache_entry(key, window, node),
+                cache_config,
+                false,
+            )
+            .await?
+            .unwrap_or(0);
+        }
+
+        // As soon as the local count alone exceeds the limit, the other members will reject
+        // as well. Publishing any further requests would only amplify a flood.
+        if local <= limit + 1 {
+            cache_insert(
+                CACHE_NAME_RATE_LIMIT.to_string(),
+                cache_entry(key, window, own),
+                cache_config,
+                &local,
+                AckLevel::Once,
+            )
+            .await?;
+        }
+    }
+
+    if total > limit {
+        let not_before = window + 1;
+        Err(ErrorResponse::new(
+            ErrorResponseType::TooManyRequests(not_before),
+            format!("You hit a rate limit. You may try again at: {}", not_before),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn count_local(key: &str, window: i64) -> u32 {
+    let mut local = LOCAL.lock().expect("rate limit lock to never be poisoned");
+    if local.len() >= MAX_LOCAL_KEYS && !local.contains_key(key) {
+        local.retain(|_, (w, _)| *w == window);
+    }
+
+    let entry = local.entry(key.to_string()).or_insert((window, 0));
+    if entry.0 != window {
+        *entry = (window, 0);
+    }
+    entry.1 += 1;
+    entry.1
+}
+
+#[inline]
+fn cache_entry(key: &str, window: i64, node: &str) -> String {
+    format!("{}#{}#{}", key, window, node)
+}
+
+/// Builds the rate limit key for a `client_id` from a request, which is not authenticated yet.
+/// Including the source IP makes sure that nobody can use up the quota of another client by
+/// only sending its `client_id`.
+pub fn client_key(client_id: &str, ip: Option<&str>) -> String {
+    format!("{}@{}", client_id, ip.unwrap_or("unknown"))
+}
+
+/// Uses the entries from `HA_HOSTS` as node ids, since all members see the same list.
+/// The own id is the entry whose host, or the first label of it, equals the hostname.
+fn node_ids(ha_hosts: &str, hostname: &str) -> (String, Vec<String>) {
+    let hosts = ha_hosts
+        .split(',')
+        .map(|h| h.trim())
+        .filter(|h| !h.is_empty())
+        .map(|h| h.split_once("://").map(|(_, host)| host).unwrap_or(h))
+        .collect::<Vec<_>>();
+
+    match hosts.iter().position(|h| is_own_host(h, hostname)) {
+        Some(idx) => {
+            let others = hosts
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != idx)
+                .map(|(_, h)| h.to_string())
+                .collect();
+            (hosts[idx].to_string(), others)
+        }
+        None => {
+            if *HA_MODE {
+                warn!(
+                    "Cannot find hostname '{}' in HA_HOSTS - rate limits will not be shared with \
+                    the other members",
+                    hostname
+                );
+            }
+            let others = hosts.iter().map(|h| h.to_string()).collect();
+            (hostname.to_string(), others)
+        }
+    }
+}
+
+fn is_own_host(host: &str, hostname: &str) -> bool {
+    if host == hostname {
+        return true;
+    }
+    let host = host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host);
+    host == hostname || host.split('.').next() == Some(hostname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_node_ids() {
+        let hosts = "https://127.0.0.1:8001, https://127.0.0.1:8002 ,https://127.0.0.1:8003";
+        let (own, others) = node_ids(hosts, "127.0.0.1:8002");
+        assert_eq!(own, "127.0.0.1:8002");
+        assert_eq!(others, vec!["127.0.0.1:8001", "127.0.0.1:8003"]);
+
+        let (own, others) = node_ids("", "rauthy-0");
+        assert_eq!(own, "rauthy-0");
+        assert!(others.is_empty());
+
+        // a hostname must never match another member with the same prefix
+        let hosts = "http://rauthy-1.rauthy:8000,http://rauthy-10.rauthy:8000";
+        let (own, others) = node_ids(hosts, "rauthy-1");
+        assert_eq!(own, "rauthy-1.rauthy:8000");
+        assert_eq!(others, vec!["rauthy-10.rauthy:8000"]);
+
+        let (own, others) = node_ids(hosts, "rauthy-10.rauthy");
+        assert_eq!(own, "rauthy-10.rauthy:8000");
+        assert_eq!(others, vec!["rauthy-1.rauthy:8000"]);
+
+        let (own, _) = node_ids(hosts, "rauthy");
+        assert_eq!(own, "rauthy");
+    }
+
+    #[test]
+    fn test_rate_limit_client_key() {
+        assert_ne!(
+            client_key("client", Some("192.168.1.1")),
+            client_key("client", Some("192.168.1.2"))
+        );
+        assert_eq!(client_key("client", None), "client@unknown");
+    }
+
+    #[test]
+    fn test_rate_limit_count_local() {
+        assert_eq!(count_local("test_client", 100), 1);
+        assert_eq!(count_local("test_client", 100), 2);
+        assert_eq!(count_local("test_other", 100), 1);
+
+        // a new window resets the counter
+        assert_eq!(count_local("test_client", 101), 1);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::constants::GRANT_TYPE_TOKEN_EXCHANGE;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use once_cell::sync::Lazy;
+use prometheus::{IntCounterVec, Opts, Registry};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// The max amount of recent errors kept per client.
+const MAX_ERRORS_PER_CLIENT: usize = 20;
+/// The max amount of clients with recent errors. Only existing clients are tracked, but
+/// ephemeral clients could grow the map otherwise.
+const MAX_CLIENTS: usize = 1000;
+/// Messages may contain values from the request and are cut off after this many chars.
+const MAX_MESSAGE_LEN: usize = 256;
+/// The grant types, which are accepted as a metric label. The `grant_type` comes from
+/// unauthenticated requests and anything else would create a new time series each time.
+const GRANT_TYPES: [&str; 5] = [
+    "authorization_code",
+    "client_credentials",
+    "password",
+    "refresh_token",
+    GRANT_TYPE_TOKEN_EXCHANGE,
+];
+
+/// The classified cause of a failed token request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenErrorCause {
+    /// A missing or invalid app attestation of a native client
+    Attestation,
+    ClientDisabled,
+    ClientSecretInvalid,
+    ClientUnknown,
+    Dpop,
+    FlowNotAllowed,
+    /// The `code`, `device_code` or `refresh_token` is unknown or has expired
+    GrantExpired,
+    /// Malformed `authorization_details` or ones which have not been granted
+    InvalidAuthorizationDetails,
+    InvalidCredentials,
+    /// An unknown `resource` or one which does not accept any of the scopes
+    InvalidTarget,
+    PkceMismatch,
+    RedirectUriMismatch,
+    Other,
+}
+
+impl TokenErrorCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Attestation => "attestation",
+            Self::ClientDisabled => "client_disabled",
+            Self::ClientSecretInvalid => "client_secret_invalid",
+            Self::ClientUnknown => "client_unknown",
+            Self::Dpop => "dpop",
+            Self::FlowNotAllowed => "flow_not_allowed",
+            Self::GrantExpired => "grant_expired",
+            Self::InvalidAuthorizationDetails => "invalid_authorization_details",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::InvalidTarget => "invalid_target",
+            Self::PkceMismatch => "pkce_mismatch",
+            Self::RedirectUriMismatch => "redirect_uri_mismatch",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl From<&ErrorResponse> for TokenErrorCause {
+    fn from(value: &ErrorResponse) -> Self {
+        match value.code {
+            ErrorCode::AttestationInvalid => Self::Attestation,
+            ErrorCode::ClientDisabled => Self::ClientDisabled,
+            ErrorCode::ClientSecretInvalid => Self::ClientSecretInvalid,
+            ErrorCode::DpopInvalid | ErrorCode::DpopNonceRequired => Self::Dpop,
+            ErrorCode::FlowNotAllowed => Self::FlowNotAllowed,
+            ErrorCode::GrantExpired => Self::GrantExpired,
+            ErrorCode::InvalidAuthorizationDetails => Self::InvalidAuthorizationDetails,
+            ErrorCode::InvalidCredentials => Self::InvalidCredentials,
+            ErrorCode::InvalidTarget => Self::InvalidTarget,
+            ErrorCode::PkceMismatch => Self::PkceMismatch,
+            ErrorCode::RedirectUriMismatch => Self::RedirectUriMismatch,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenError {
+    pub timestamp: i64,
+    pub grant_type: String,
+    pub cause: TokenErrorCause,
+    pub message: String,
+}
+
+static RECENT: Lazy<Mutex<HashMap<String, VecDeque<TokenError>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static METRIC_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "rauthy_token_errors_total",
+            "Failed token requests by grant type and cause",
+        ),
+        &["grant_type", "cause"],
+    )
+    .expect("Cannot build rauthy_token_errors_total")
+});
+
+/// Registers the token error metrics with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_ERRORS.clone())) {
+        error!("Cannot register token error metrics: {}", err);
+    }
+}
+
+/// Classifies and records a failed token request.
+///
+/// `client_id` must only be given for existing clients. Errors for unknown clients are counted,
+/// but not kept, because they would only fill up the memory. Unknown grant types are recorded
+/// as `invalid`.
+pub fn record(client_id: Option<&str>, grant_type: &str, err: &ErrorResponse) {
+    let grant_type = if GRANT_TYPES.contains(&grant_type) {
+        grant_type
+    } else {
+        "invalid"
+    };
+    let cause = if client_id.is_some() {
+        TokenErrorCause::from(err)
+    } else {
+        TokenErrorCause::ClientUnknown
+    };
+    METRIC_ERRORS
+        .with_label_values(&[grant_type, cause.as_str()])
+        .inc();
+
+    let Some(client_id) = client_id else {
+        return;
+    };
+    let entry = TokenError {
+        timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        grant_type: grant_type.to_string(),
+        cause,
+        message: err.message.chars().take(MAX_MESSAGE_LEN).collect(),
+    };
+    let mut recent = RECENT
+        .lock()
+        .expect("token errors lock to never be poisoned");
+    insert(&mut recent, client_id, entry);
+}
+
+/// Returns the recent token errors for a client on this instance, newest first.
+pub fn recent(client_id: &str) -> Vec<TokenError> {
+    RECENT
+        .lock()
+        .expect("token errors lock to never be poisoned")
+        .get(client_id)
+        .map(|errors| errors.iter().rev().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn insert(recent: &mut HashMap<String, VecDeque<TokenError>>, client_id: &str, err: TokenError) {
+    if !recent.contains_key(client_id) && recent.len() >= MAX_CLIENTS {
+        // make room by dropping the client with the oldest latest error
+        let oldest = recent
+            .iter()
+            .min_by_key(|(_, errors)| errors.back().map(|e| e.timestamp).unwrap_or_default())
+            .map(|(id, _)| id.clone());
+        if let Some(id) = oldest {
+            recent.remove(&id);
+        }
+    }
+
+    let errors = recent.entry(client_id.to_string()).or_default();
+    errors.push_back(err);
+    while errors.len() > MAX_ERRORS_PER_CLIENT {
+        errors.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_response::ErrorResponseType;
+
+    fn token_error(timestamp: i64) -> TokenError {
+        TokenError {
+            timestamp,
+            grant_type: "authorization_code".to_string(),
+            cause: TokenErrorCause::PkceMismatch,
+            message: "'code_verifier' does not match the challenge".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_error_cause() {
+        let err = ErrorResponse::new(ErrorResponseType::Unauthorized, "Invalid".to_string());
+        assert_eq!(TokenErrorCause::from(&err), TokenErrorCause::Other);
+
+        let err = err.with_code(ErrorCode::ClientSecretInvalid);
+        assert_eq!(
+            TokenErrorCause::from(&err),
+            TokenErrorCause::ClientSecretInvalid
+        );
+
+        let err = ErrorResponse::new(ErrorResponseType::DPoP(None), "invalid".to_string());
+        assert_eq!(TokenErrorCause::from(&err), TokenErrorCause::Dpop);
+    }
+
+    #[test]
+    fn test_token_errors_record_sanitized() {
+        let err = ErrorResponse::new(ErrorResponseType::BadRequest, "x".repeat(1000));
+        record(Some("test_record_sanitized"), "made_up_grant", &err);
+
+        let errors = recent("test_record_sanitized");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].grant_type, "invalid");
+        assert_eq!(errors[0].message.len(), MAX_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn test_token_errors_bounded() {
+        let mut recent = HashMap::new();
+
+        for i in 0..MAX_ERRORS_PER_CLIENT as i64 + 5 {
+            insert(&mut recent, "client", token_error(i));
+        }
+        let errors = recent.get("client").unwrap();
+        assert_eq!(errors.len(), MAX_ERRORS_PER_CLIENT);
+        assert_eq!(errors.front().unwrap().timestamp, 5);
+
+        for i in 0..MAX_CLIENTS {
+            insert(
+                &mut recent,
+                &format!("other_{}", i),
+                token_error(100 + i as i64),
+            );
+        }
+        assert_eq!(recent.len(), MAX_CLIENTS);
+        // the client with the oldest errors has been dropped first
+        assert!(!recent.contains_key("client"));
+        assert!(recent.contains_key("other_0"));
+    }
+}
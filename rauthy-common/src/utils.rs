@@ -1,13 +1,23 @@
-use crate::constants::{PEER_IP_HEADER_NAME, PROXY_MODE};
+use crate::constants::{
+    DEV_MODE, EMAIL_NORMALIZE_TAG_DOMAINS, LOGIN_TRUSTED_CIDRS, PEER_IP_HEADER_NAME, PROXY_MODE,
+    STARTUP_BACKOFF_MAX_SECS,
+};
 use crate::error_response::{ErrorResponse, ErrorResponseType};
 use actix_web::dev::ServiceRequest;
 use actix_web::http::header::HeaderMap;
 use actix_web::HttpRequest;
 use base64::{engine, engine::general_purpose, Engine as _};
+use chrono::NaiveDate;
 use gethostname::gethostname;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use tracing::{debug, error};
+use reqwest::Url;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 const B64_URL_SAFE: engine::GeneralPurpose = general_purpose::URL_SAFE;
 const B64_URL_SAFE_NO_PAD: engine::GeneralPurpose = general_purpose::URL_SAFE_NO_PAD;
@@ -18,6 +28,48 @@ pub fn cache_entry_client(id: &str) -> String {
     format!("client_{}", id)
 }
 
+/// Returns the name of the shard for the given cache key. With only a single shard, this is
+/// the plain `cache_name` to stay compatible with unsharded nodes.
+///
+/// Uses a jump consistent hash, which only moves `1/n` of the keys when a shard is added.
+/// The key hash must be stable across all HA nodes, which is why `DefaultHasher` is not used.
+pub fn cache_shard_name(cache_name: &str, key: &str, shards: u16) -> String {
+    if shards <= 1 {
+        return cache_name.to_string();
+    }
+    format!("{}#{}", cache_name, jump_hash(fnv1a(key), shards))
+}
+
+/// Returns the names of all shards for the given cache.
+pub fn cache_shard_names(cache_name: &str, shards: u16) -> Vec<String> {
+    if shards <= 1 {
+        return vec![cache_name.to_string()];
+    }
+    (0..shards)
+        .map(|i| format!("{}#{}", cache_name, i))
+        .collect()
+}
+
+#[inline]
+fn fnv1a(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Lamping & Veach - "A Fast, Minimal Memory, Consistent Hash Algorithm"
+#[inline]
+fn jump_hash(mut key: u64, buckets: u16) -> u16 {
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1i64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u16
+}
+
 // Converts a given Json array / list into a Vec<String>
 pub fn json_arr_to_vec(arr: &str) -> Vec<String> {
     arr.chars()
@@ -113,6 +165,51 @@ pub fn new_store_id() -> String {
     get_rand(24)
 }
 
+/// Parses a date in the format `YYYY-MM-DD` into the unix timestamp of its start in UTC.
+pub fn parse_date_ts(date: &str) -> Option<i64> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|dt| dt.and_utc().timestamp())
+}
+
+/// Normalizes an E-Mail for matching. The address is lowercased, a `+tag` in the local part
+/// is removed for the `EMAIL_NORMALIZE_TAG_DOMAINS` and for Gmail addresses, all dots in the
+/// local part are removed as well.
+pub fn normalize_email(email: &str) -> String {
+    normalize_email_with(email, &EMAIL_NORMALIZE_TAG_DOMAINS)
+}
+
+/// Other providers treat `+` as a regular character, where `foo+a@` and `foo+b@` are different
+/// inboxes, which must never be matched to the same user.
+fn normalize_email_with(email: &str, tag_domains: &[String]) -> String {
+    let email = email.trim().to_lowercase();
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return email;
+    };
+
+    let local = match local.split_once('+') {
+        Some((l, _)) if !l.is_empty() && tag_domains.iter().any(|d| d == domain) => l,
+        _ => local,
+    };
+    if domain == "gmail.com" || domain == "googlemail.com" {
+        format!("{}@gmail.com", local.replace('.', ""))
+    } else {
+        format!("{}@{}", local, domain)
+    }
+}
+
+/// Checks if the domain of the given E-Mail is one of `domains`.
+pub fn is_email_in_domains(email: &str, domains: &[String]) -> bool {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| {
+            let domain = domain.to_lowercase();
+            domains.iter().any(|d| d == &domain)
+        })
+        .unwrap_or(false)
+}
+
 // Extracts the claims from a given token into a HashMap.
 // Returns an empty HashMap if no values could be extracted at all.
 // CAUTION: Does not validate the token!
@@ -174,6 +271,94 @@ pub fn real_ip_from_req(req: &HttpRequest) -> Option<String> {
     }
 }
 
+/// Returns `true` if the given client IP is inside one of the `LOGIN_TRUSTED_CIDRS`.
+/// Logins from these networks skip the PoW, the login delay and the IP blacklisting.
+pub fn is_trusted_login_ip(ip: Option<&str>) -> bool {
+    if LOGIN_TRUSTED_CIDRS.is_empty() {
+        return false;
+    }
+    ip.and_then(|ip| IpAddr::from_str(ip).ok())
+        .map(|ip| is_ip_in_cidrs(&ip, &LOGIN_TRUSTED_CIDRS))
+        .unwrap_or(false)
+}
+
+#[inline]
+pub fn is_ip_in_cidrs(ip: &IpAddr, cidrs: &[IpCidr]) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    };
+    cidrs.iter().any(|cidr| cidr.contains(&ip))
+}
+
+/// Returns `false` for all loopback, private, link-local and other special purpose addresses,
+/// which must never be the target of requests to URLs from untrusted input (SSRF).
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_unspecified()
+                || v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                // 0.0.0.0/8
+                || a == 0
+                // shared address space 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64)
+                // benchmarking 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+                // reserved 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(&IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_multicast()
+                // unique local fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // link-local fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolves the host of a URL from untrusted input, like client metadata, and makes sure that
+/// it only points to public addresses.
+///
+/// The returned address should be pinned with `reqwest::ClientBuilder::resolve()`, so the host
+/// cannot be re-bound to an internal address between this check and the request.
+/// In `DEV_MODE`, local addresses are allowed.
+pub async fn resolve_public_addr(url: &Url) -> Result<SocketAddr, ErrorResponse> {
+    let err = |msg: &str| ErrorResponse::new(ErrorResponseType::BadRequest, msg.to_string());
+
+    let host = url.host_str().ok_or_else(|| err("The URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| err("The URL has no port"))?;
+    // IPv6 hosts are returned in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| err("Cannot resolve the host of the URL"))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(err("Cannot resolve the host of the URL"));
+    }
+    if !*DEV_MODE && addrs.iter().any(|addr| !is_public_ip(&addr.ip())) {
+        return Err(err("The URL must not point to a non-public address"));
+    }
+
+    Ok(addrs[0])
+}
+
 #[inline(always)]
 pub fn real_ip_from_svc_req(req: &ServiceRequest) -> Option<String> {
     if let Some(ip) = ip_from_cust_header(req.headers()) {
@@ -200,6 +385,244 @@ fn ip_from_cust_header(headers: &HeaderMap) -> Option<String> {
     None
 }
 
+/// An IPv4 or IPv6 network in CIDR notation like `10.0.0.0/8`.
+/// A single IP without a prefix length is treated as a network with only this host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpCidr {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Invalid CIDR: {}", s),
+            )
+        };
+
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| err())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().map_err(|_| err())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(err());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the network with all host bits cleared. IPv4 mapped IPv6 addresses are converted
+    /// to IPv4 and IPv6 networks smaller than `v6_prefix` are widened to it.
+    pub fn aggregate(&self, v6_prefix: u8) -> Self {
+        match self.addr {
+            IpAddr::V4(ip) => Self::masked(IpAddr::V4(ip), self.prefix),
+            IpAddr::V6(ip) => {
+                if let Some(v4) = ip.to_ipv4_mapped() {
+                    let prefix = self.prefix.saturating_sub(96);
+                    Self::masked(IpAddr::V4(v4), prefix)
+                } else {
+                    Self::masked(IpAddr::V6(ip), self.prefix.min(v6_prefix.min(128)))
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn addr(&self) -> &IpAddr {
+        &self.addr
+    }
+
+    #[inline]
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// `true` if this network contains only a single host.
+    #[inline]
+    pub fn is_host(&self) -> bool {
+        match self.addr {
+            IpAddr::V4(_) => self.prefix == 32,
+            IpAddr::V6(_) => self.prefix == 128,
+        }
+    }
+
+    fn masked(addr: IpAddr, prefix: u8) -> Self {
+        let addr = match addr {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4((u32::from(ip) & mask).into())
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6((u128::from(ip) & mask).into())
+            }
+        };
+        Self { addr, prefix }
+    }
+}
+
+impl Display for IpCidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_host() {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+/// Parameter names whose values must never end up inside login traces or streamed logs.
+static REDACT_KEYS: [&str; 8] = [
+    "password",
+    "client_secret",
+    "code",
+    "code_verifier",
+    "refresh_token",
+    "access_token",
+    "id_token",
+    "token",
+];
+
+/// Compares two secrets in constant time to not leak the position of the first mismatch.
+#[inline]
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// Masks the values of all known secret parameters like `password=...` or `"code":"..."`.
+pub fn redact(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    'outer: while !rest.is_empty() {
+        for key in REDACT_KEYS {
+            if let Some(stripped) = rest.strip_prefix(key) {
+                // only treat this as a key if it is not part of a longer word
+                let is_word_start = out
+                    .chars()
+                    .last()
+                    .map(|c| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(true);
+                let after_key = stripped.trim_start_matches(['"', ' ']);
+                let sep = after_key.chars().next();
+                if is_word_start && matches!(sep, Some('=') | Some(':')) {
+                    out.push_str(key);
+                    // skip the separator and any whitespace in front of the value
+                    let sep_end = stripped.len() - after_key.len() + 1;
+                    let value_start = stripped.len() - stripped[sep_end..].trim_start().len();
+                    out.push_str(&stripped[..value_start]);
+                    out.push_str("***");
+
+                    let value = &stripped[value_start..];
+                    let value_end = if let Some(quoted) = value.strip_prefix('"') {
+                        // a quoted value may contain spaces and escaped quotes
+                        let mut escaped = false;
+                        quoted
+                            .find(|c: char| {
+                                let is_end = c == '"' && !escaped;
+                                escaped = c == '\\' && !escaped;
+                                is_end
+                            })
+                            .map(|i| i + 2)
+                            .unwrap_or(value.len())
+                    } else {
+                        value
+                            .find(['&', ',', ' ', '}', '\n'])
+                            .unwrap_or(value.len())
+                    };
+                    rest = &value[value_end..];
+                    continue 'outer;
+                }
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    out
+}
+
+/// Executes `f` until it succeeds, with an exponential backoff of up to
+/// `STARTUP_BACKOFF_MAX_SECS` in between.
+///
+/// Meant for external dependencies during startup, which may be unavailable for a short time.
+/// With `retries == None`, it will retry forever.
+pub async fn retry_with_backoff<T, E, F, Fut>(
+    name: &str,
+    retries: Option<u32>,
+    mut f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Debug,
+{
+    let mut attempt = 0;
+    let mut backoff = 1;
+
+    loop {
+        match f().await {
+            Ok(res) => {
+                if attempt > 0 {
+                    info!("{} is available after {} retries", name, attempt);
+                }
+                return Ok(res);
+            }
+            Err(err) => {
+                if retries.map(|r| attempt >= r).unwrap_or(false) {
+                    error!("{} is not available - giving up: {:?}", name, err);
+                    return Err(err);
+                }
+                attempt += 1;
+
+                let max = retries
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                warn!(
+                    "{} is not available yet (retry {}/{}), next try in {}s: {:?}",
+                    name, attempt, max, backoff, err
+                );
+                tokio::time::sleep(Duration::from_secs(backoff)).await;
+                backoff = (backoff * 2).min(*STARTUP_BACKOFF_MAX_SECS);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -232,4 +655,213 @@ mod tests {
         let rnd = get_rand(1024);
         assert_eq!(rnd.len(), 1024);
     }
+
+    #[test]
+    fn test_ip_cidr() {
+        let cidr = IpCidr::from_str("10.10.0.0/16").unwrap();
+        assert!(cidr.contains(&IpAddr::from_str("10.10.1.1").unwrap()));
+        assert!(!cidr.contains(&IpAddr::from_str("10.11.1.1").unwrap()));
+        assert!(!cidr.contains(&IpAddr::from_str("::1").unwrap()));
+
+        let cidr = IpCidr::from_str("192.168.1.5").unwrap();
+        assert!(cidr.contains(&IpAddr::from_str("192.168.1.5").unwrap()));
+        assert!(!cidr.contains(&IpAddr::from_str("192.168.1.6").unwrap()));
+
+        let cidr = IpCidr::from_str("0.0.0.0/0").unwrap();
+        assert!(cidr.contains(&IpAddr::from_str("1.2.3.4").unwrap()));
+
+        let cidr = IpCidr::from_str("fd00::/8").unwrap();
+        assert!(cidr.contains(&IpAddr::from_str("fd12:3456::1").unwrap()));
+        assert!(!cidr.contains(&IpAddr::from_str("fe80::1").unwrap()));
+
+        assert!(IpCidr::from_str("10.0.0.0/33").is_err());
+        assert!(IpCidr::from_str("not an ip").is_err());
+    }
+
+    #[test]
+    fn test_ip_cidr_aggregate() {
+        let agg = |s: &str| IpCidr::from_str(s).unwrap().aggregate(64).to_string();
+
+        assert_eq!(agg("192.168.1.5"), "192.168.1.5");
+        assert_eq!(agg("192.168.1.5/24"), "192.168.1.0/24");
+        assert_eq!(agg("::ffff:192.168.1.5"), "192.168.1.5");
+
+        // different representations of the same IPv6 network end up with the same key
+        assert_eq!(agg("2001:db8:0:1:aaaa::1"), "2001:db8:0:1::/64");
+        assert_eq!(agg("2001:0db8:0000:0001:ffff::2"), "2001:db8:0:1::/64");
+        assert_eq!(agg("2001:db8::/48"), "2001:db8::/48");
+
+        let host = IpCidr::from_str("2001:db8::1").unwrap().aggregate(128);
+        assert_eq!(host.to_string(), "2001:db8::1");
+    }
+
+    #[test]
+    fn test_is_ip_in_cidrs() {
+        let cidrs = vec![
+            IpCidr::from_str("10.8.0.0/16").unwrap(),
+            IpCidr::from_str("fd00:abcd::/32").unwrap(),
+        ];
+        let check = |ip: &str| is_ip_in_cidrs(&IpAddr::from_str(ip).unwrap(), &cidrs);
+
+        assert!(check("10.8.13.37"));
+        assert!(check("::ffff:10.8.13.37"));
+        assert!(check("fd00:abcd::1"));
+        assert!(!check("10.9.0.1"));
+        assert!(!check("fd00:abce::1"));
+        assert!(!is_ip_in_cidrs(&IpAddr::from_str("10.8.0.1").unwrap(), &[]));
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(is_public_ip(&IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.178.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public_ip(&IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("client_id=abc&client_secret=123&code=xyz"),
+            "client_id=abc&client_secret=***&code=***"
+        );
+        assert_eq!(
+            redact(r#"{"password":"secret","email":"a@b.c"}"#),
+            r#"{"password":***,"email":"a@b.c"}"#
+        );
+        // whitespace around the separator and quoted values with spaces
+        assert_eq!(
+            redact(r#"{"password": "my secret", "email": "a@b.c"}"#),
+            r#"{"password": ***, "email": "a@b.c"}"#
+        );
+        assert_eq!(
+            redact(r#"{"client_secret" : "a\"b c"}"#),
+            r#"{"client_secret" : ***}"#
+        );
+        assert_eq!(redact("password = secret"), "password = ***");
+        assert_eq!(redact("code:  xyz&a=b"), "code:  ***&a=b");
+        assert_eq!(redact("no secrets here"), "no secrets here");
+        assert_eq!(redact("passcode=1"), "passcode=1");
+        assert_eq!(redact("password"), "password");
+    }
+
+    #[test]
+    fn test_cache_shard_name() {
+        assert_eq!(cache_shard_name("sessions", "session_abc", 1), "sessions");
+        assert_eq!(
+            cache_shard_names("sessions", 1),
+            vec!["sessions".to_string()]
+        );
+
+        let names = cache_shard_names("sessions", 8);
+        assert_eq!(names.len(), 8);
+        for i in 0..100 {
+            let key = format!("session_{}", i);
+            let name = cache_shard_name("sessions", &key, 8);
+            assert!(names.contains(&name));
+            // must be stable
+            assert_eq!(name, cache_shard_name("sessions", &key, 8));
+        }
+
+        // growing the shard count must only move keys into the new shard
+        for i in 0..100 {
+            let key = format!("session_{}", i);
+            let before = cache_shard_name("sessions", &key, 8);
+            let after = cache_shard_name("sessions", &key, 9);
+            assert!(before == after || after == "sessions#8");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff() {
+        let mut calls = 0;
+        let res: Result<u32, &str> = retry_with_backoff("test", Some(2), || {
+            calls += 1;
+            let c = calls;
+            async move {
+                if c < 2 {
+                    Err("not yet")
+                } else {
+                    Ok(c)
+                }
+            }
+        })
+        .await;
+        assert_eq!(res, Ok(2));
+
+        let res: Result<(), &str> =
+            retry_with_backoff("test", Some(0), || async { Err("unavailable") }).await;
+        assert_eq!(res, Err("unavailable"));
+    }
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email("Foo@Example.com"), "foo@example.com");
+        // tags are only stripped for the configured domains
+        assert_eq!(
+            normalize_email("foo+news@example.com"),
+            "foo+news@example.com"
+        );
+        assert_eq!(normalize_email("foo+news@gmail.com"), "foo@gmail.com");
+        assert_eq!(normalize_email("f.o.o@example.com"), "f.o.o@example.com");
+        assert_eq!(normalize_email("F.o.o+x@GoogleMail.com"), "foo@gmail.com");
+        assert_eq!(normalize_email("+foo@gmail.com"), "+foo@gmail.com");
+        assert_eq!(normalize_email("invalid"), "invalid");
+
+        let domains = vec!["example.com".to_string()];
+        assert_eq!(
+            normalize_email_with("Foo+News@Example.com", &domains),
+            "foo@example.com"
+        );
+        assert_eq!(
+            normalize_email_with("foo+news@other.example.com", &domains),
+            "foo+news@other.example.com"
+        );
+        assert_eq!(
+            normalize_email_with("foo+x@gmail.com", &[]),
+            "foo+x@gmail.com"
+        );
+    }
+
+    #[test]
+    fn test_is_email_in_domains() {
+        let domains = vec!["corp.example".to_string(), "example.com".to_string()];
+        assert!(is_email_in_domains("foo@corp.example", &domains));
+        assert!(is_email_in_domains("Foo@Corp.Example", &domains));
+        assert!(!is_email_in_domains("foo@sub.corp.example", &domains));
+        assert!(!is_email_in_domains("foo@corp.example.com", &domains));
+        assert!(!is_email_in_domains("invalid", &domains));
+        assert!(!is_email_in_domains("foo@corp.example", &[]));
+    }
+
+    #[test]
+    fn test_parse_date_ts() {
+        assert_eq!(parse_date_ts("2024-01-01"), Some(1704067200));
+        assert_eq!(parse_date_ts(" 2030-12-31 "), Some(1924905600));
+        assert_eq!(parse_date_ts("2024-13-01"), None);
+        assert_eq!(parse_date_ts("01.01.2024"), None);
+    }
 }
@@ -0,0 +1,161 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::announcements::Announcement;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::request::{AnnouncementParams, AnnouncementRequest};
+use rauthy_models::response::AnnouncementResponse;
+
+/// Returns all existing announcements, including expired and upcoming ones
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/announcements",
+    tag = "generic",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [AnnouncementResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/announcements")]
+pub async fn get_announcements(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let res = Announcement::find_all(&data)
+        .await?
+        .into_iter()
+        .map(AnnouncementResponse::from)
+        .collect::<Vec<AnnouncementResponse>>();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Returns the currently active announcements
+///
+/// These are shown on the login and account pages. Announcements, which are restricted to
+/// some clients, are only included if the matching `client_id` is given.
+#[utoipa::path(
+    get,
+    path = "/announcements/active",
+    tag = "generic",
+    params(AnnouncementParams),
+    responses(
+        (status = 200, description = "Ok", body = [AnnouncementResponse]),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+    ),
+)]
+#[get("/announcements/active")]
+pub async fn get_announcements_active(
+    data: web::Data<AppState>,
+    params: actix_web_validator::Query<AnnouncementParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let params = params.into_inner();
+    let res = Announcement::find_active(&data, params.client_id.as_deref())
+        .await?
+        .into_iter()
+        .map(|a| AnnouncementResponse {
+            // the client restrictions are nothing the public needs to know about
+            client_ids: None,
+            ..AnnouncementResponse::from(a)
+        })
+        .collect::<Vec<AnnouncementResponse>>();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Creates a new announcement
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/announcements",
+    tag = "generic",
+    request_body = AnnouncementRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = AnnouncementResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/announcements")]
+pub async fn post_announcement(
+    data: web::Data<AppState>,
+    payload: actix_web_validator::Json<AnnouncementRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Create)?;
+
+    Announcement::create(&data, payload.into_inner())
+        .await
+        .map(|a| HttpResponse::Ok().json(AnnouncementResponse::from(a)))
+}
+
+/// Modifies an announcement
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/announcements/{id}",
+    tag = "generic",
+    request_body = AnnouncementRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = AnnouncementResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/announcements/{id}")]
+pub async fn put_announcement(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: actix_web_validator::Json<AnnouncementRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Update)?;
+
+    Announcement::update(&data, id.into_inner(), payload.into_inner())
+        .await
+        .map(|a| HttpResponse::Ok().json(AnnouncementResponse::from(a)))
+}
+
+/// Deletes an announcement
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/announcements/{id}",
+    tag = "generic",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/announcements/{id}")]
+pub async fn delete_announcement(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Delete)?;
+
+    Announcement::delete(&data, id.as_str())
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
@@ -16,6 +16,7 @@ use rauthy_models::response::{ApiKeyResponse, ApiKeysResponse};
     get,
     path = "/api_keys",
     tag = "api_keys",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = ApiKeysResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -48,6 +49,7 @@ pub async fn get_api_keys(
     path = "/api_keys",
     tag = "api_keys",
     request_body = ApiKeyRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = String),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -79,6 +81,7 @@ pub async fn post_api_key(
     path = "/api_keys/{name}",
     tag = "api_keys",
     request_body = ApiKeyRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -116,6 +119,7 @@ pub async fn put_api_key(
     delete,
     path = "/api_keys/{name}",
     tag = "api_keys",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -144,6 +148,7 @@ pub async fn delete_api_key(
     get,
     path = "/api_keys/{name}/test",
     tag = "api_keys",
+    security(("api_key" = [])),
     responses(
         (status = 200, description = "Ok", body = ApiKeyResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -182,6 +187,7 @@ pub async fn get_api_key_test(
     put,
     path = "/api_keys/{name}/secret",
     tag = "api_keys",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = String),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
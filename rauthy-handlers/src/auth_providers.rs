@@ -3,7 +3,7 @@ use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION};
 use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 use actix_web_lab::__reexports::futures_util::StreamExt;
 use actix_web_validator::Json;
-use rauthy_common::constants::{HEADER_HTML, HEADER_JSON};
+use rauthy_common::constants::{ASSET_CACHE_MAX_AGE, HEADER_HTML, HEADER_JSON};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::auth_providers::{
@@ -28,6 +28,7 @@ use tracing::debug;
     post,
     path = "/providers",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 200, description = "OK", body = ProviderResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -58,6 +59,7 @@ pub async fn post_providers(
     post,
     path = "/providers/create",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 200, description = "OK", body = ProviderResponse),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -93,6 +95,7 @@ pub async fn post_provider(
     post,
     path = "/providers/lookup",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 200, description = "OK", body = ProviderLookupResponse),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -125,6 +128,7 @@ pub async fn post_provider_lookup(
     post,
     path = "/providers/login",
     tag = "providers",
+    security(("session_init" = [])),
     responses(
         (status = 202, description = "Accepted"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -169,6 +173,7 @@ pub async fn get_provider_callback_html(
     post,
     path = "/providers/callback",
     tag = "providers",
+    security(("session_init" = [])),
     responses(
         (status = 200, description = "OK", body = ProviderLookupResponse),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -214,6 +219,7 @@ pub async fn post_provider_callback(
     delete,
     path = "/providers/link",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 200, description = "OK"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -263,6 +269,7 @@ pub async fn get_providers_minimal(
     put,
     path = "/providers/{id}",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 404, description = "NotFound", body = ErrorResponse),
@@ -296,6 +303,7 @@ pub async fn put_provider(
     delete,
     path = "/providers/{id}",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 404, description = "NotFound", body = ErrorResponse),
     ),
@@ -322,6 +330,7 @@ pub async fn delete_provider(
     get,
     path = "/providers/{id}/delete_safe",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 404, description = "NotFound", body = ErrorResponse),
     ),
@@ -353,6 +362,8 @@ pub async fn get_provider_delete_safe(
     path = "/providers/{id}/img",
     tag = "providers",
     responses(
+        (status = 200, description = "Ok"),
+        (status = 302, description = "Found - the image is stored in S3"),
         (status = 404, description = "NotFound", body = ErrorResponse),
     ),
 )]
@@ -364,12 +375,19 @@ pub async fn get_provider_img(
     let id = id.into_inner();
     let logo = Logo::find_cached(&data, &id, &LogoType::AuthProvider).await?;
 
+    if let Some((url, max_age)) = logo.s3_url(&LogoType::AuthProvider)? {
+        return Ok(HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .insert_header((CACHE_CONTROL, format!("max-age={}", max_age)))
+            .finish());
+    }
+
     Ok(HttpResponse::Ok()
         .insert_header((CONTENT_TYPE, logo.content_type))
-        // clients should cache the logos for 12 hours
+        // clients should cache the logos for `ASSET_CACHE_MAX_AGE` (default 12 hours)
         // this means if a logo has been updated, they receive the new one 12 hours
         // later in the worst case
-        .insert_header((CACHE_CONTROL, "max-age=43200"))
+        .insert_header((CACHE_CONTROL, format!("max-age={}", *ASSET_CACHE_MAX_AGE)))
         .body(logo.data))
 }
 
@@ -383,6 +401,7 @@ pub async fn get_provider_img(
     put,
     path = "/providers/{id}/img",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 404, description = "NotFound", body = ErrorResponse),
@@ -443,6 +462,7 @@ pub async fn put_provider_img(
     post,
     path = "/providers/{id}/link",
     tag = "providers",
+    security(("session" = [])),
     responses(
         (status = 200, description = "OK"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
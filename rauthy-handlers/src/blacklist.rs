@@ -1,24 +1,32 @@
 use crate::ReqPrincipal;
 use actix_web::{delete, get, post, web, HttpResponse};
-use actix_web_validator::Json;
+use actix_web_validator::{Json, Query};
 use chrono::DateTime;
-use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::ip_feeds::IpFeed;
 use rauthy_models::events::event::Event;
-use rauthy_models::events::ip_blacklist_handler::IpBlacklistReq;
-use rauthy_models::request::IpBlacklistRequest;
-use rauthy_models::response::{BlacklistResponse, BlacklistedIp};
+use rauthy_models::events::ip_blacklist_handler::{
+    blacklist_key, IpBlacklistCheck, IpBlacklistReq, IpFeedCheck,
+};
+use rauthy_models::request::{DryRunParams, IpBlacklistRequest};
+use rauthy_models::response::{
+    BlacklistDryRunResponse, BlacklistResponse, BlacklistedIp, IpFeedResponse,
+};
 use tokio::sync::oneshot;
 
 /// Returns all blacklisted IP's
 ///
+/// Entries from IP feeds are not included. Their status is available at `/blacklist/feeds`.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
     get,
     path = "/blacklist",
     tag = "blacklist",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = BlacklistResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -51,8 +59,43 @@ pub async fn get_blacklist(
     Ok(HttpResponse::Ok().json(BlacklistResponse { ips }))
 }
 
+/// Returns all IP reputation feeds with their latest update
+///
+/// The feeds are fetched by each instance on its own. The status is the one of the instance
+/// answering the request.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/blacklist/feeds",
+    tag = "blacklist",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [IpFeedResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/blacklist/feeds")]
+pub async fn get_blacklist_feeds(principal: ReqPrincipal) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Blacklist, AccessRights::Read)?;
+
+    let feeds = IpFeed::all()
+        .into_iter()
+        .map(IpFeedResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(feeds))
+}
+
 /// Manually blacklist an IP
 ///
+/// Accepts IPv4 and IPv6 addresses and networks in CIDR notation. Single IPv6 addresses will
+/// be blacklisted with their whole `IP_BLACKLIST_V6_PREFIX` network.
+///
+/// With `?dry_run=true`, the IP will not be blacklisted. Instead, the validated request is returned
+/// together with a possibly already existing blacklist expiry for this IP.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
@@ -60,8 +103,10 @@ pub async fn get_blacklist(
     path = "/blacklist",
     tag = "blacklist",
     request_body = IpBlacklistRequest,
+    params(DryRunParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
-        (status = 200, description = "Ok"),
+        (status = 200, description = "Ok", body = BlacklistDryRunResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 403, description = "Forbidden", body = ErrorResponse),
     ),
@@ -71,13 +116,33 @@ pub async fn post_blacklist(
     data: web::Data<AppState>,
     principal: ReqPrincipal,
     payload: Json<IpBlacklistRequest>,
+    params: Query<DryRunParams>,
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Blacklist, AccessRights::Create)?;
 
+    let ip = blacklist_key(&payload.ip);
+    if params.is_dry_run() {
+        let (tx, rx) = oneshot::channel();
+        data.tx_ip_blacklist
+            .send_async(IpBlacklistReq::BlacklistCheck(IpBlacklistCheck {
+                ip: ip.clone(),
+                tx,
+            }))
+            .await
+            .unwrap();
+        let blacklisted_until = rx.await.unwrap().map(|exp| exp.timestamp());
+
+        return Ok(HttpResponse::Ok().json(BlacklistDryRunResponse {
+            ip,
+            exp: payload.exp,
+            blacklisted_until,
+        }));
+    }
+
     data.tx_events
         .send_async(Event::ip_blacklisted(
             DateTime::from_timestamp(payload.exp, 0).unwrap_or_default(),
-            payload.ip.to_string(),
+            ip,
         ))
         .await
         .unwrap();
@@ -87,19 +152,24 @@ pub async fn post_blacklist(
 
 /// Manually delete a blacklisted IP
 ///
+/// Networks can be given in CIDR notation like `2001:db8::/64`.
+/// IPs listed by one of the IP feeds cannot be removed manually.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
     delete,
     path = "/blacklist/{ip}",
     tag = "blacklist",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 403, description = "Forbidden", body = ErrorResponse),
     ),
 )]
-#[delete("/blacklist/{ip}")]
+#[delete("/blacklist/{ip:.*}")]
 pub async fn delete_blacklist(
     data: web::Data<AppState>,
     principal: ReqPrincipal,
@@ -107,8 +177,26 @@ pub async fn delete_blacklist(
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Blacklist, AccessRights::Delete)?;
 
+    let (tx, rx) = oneshot::channel();
+    data.tx_ip_blacklist
+        .send_async(IpBlacklistReq::FeedCheck(IpFeedCheck {
+            ip: ip.to_string(),
+            tx,
+        }))
+        .await
+        .unwrap();
+    if let Some(feed) = rx.await.unwrap() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "{} is listed by the IP feed '{}' and cannot be removed manually",
+                ip, feed
+            ),
+        ));
+    }
+
     data.tx_events
-        .send_async(Event::ip_blacklist_removed(ip.into_inner()))
+        .send_async(Event::ip_blacklist_removed(blacklist_key(&ip)))
         .await
         .unwrap();
 
@@ -0,0 +1,84 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, web, HttpResponse};
+use rauthy_common::cache_metrics;
+use rauthy_common::constants::{CACHE_NAME_SESSIONS, CACHE_SESSIONS_SHARDS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::cache_shard_name;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::response::CacheStatsResponse;
+use redhac::AckLevel;
+
+/// Returns hit, miss and eviction counters for all caches
+///
+/// The counters are local to the instance answering the request and reset with a restart.
+/// The same values are exported via the metrics endpoint as `rauthy_cache_requests_total` and
+/// `rauthy_cache_evictions_total`.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/caches",
+    tag = "caches",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [CacheStatsResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/caches")]
+pub async fn get_caches(principal: ReqPrincipal) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let stats = cache_metrics::counters()
+        .into_iter()
+        .map(CacheStatsResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Evicts a single entry from a cache on all HA nodes
+///
+/// The next lookup will fetch the value from the database again. This is useful after
+/// modifying data directly in the database.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/caches/{name}/{key}",
+    tag = "caches",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/caches/{name}/{key}")]
+pub async fn delete_cache_entry(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Delete)?;
+
+    let (name, key) = path.into_inner();
+    if !cache_metrics::is_known_cache(&name) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            format!("Cache '{}' does not exist", name),
+        ));
+    }
+
+    let name = if name == CACHE_NAME_SESSIONS {
+        cache_shard_name(&name, &key, *CACHE_SESSIONS_SHARDS)
+    } else {
+        name
+    };
+    cache_metrics::cache_remove(name, key, &data.caches.ha_cache_config, AckLevel::Quorum).await?;
+    Ok(HttpResponse::Ok().finish())
+}
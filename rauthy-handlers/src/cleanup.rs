@@ -0,0 +1,77 @@
+use crate::ReqPrincipal;
+use actix_web::{get, post, web, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::cleanup::CleanupJob;
+use rauthy_models::response::{CleanupJobResponse, CleanupRunResponse};
+use std::str::FromStr;
+
+/// Returns all cleanup jobs with their intervals and latest runs
+///
+/// The latest runs are local to the instance answering the request. In HA deployments, only the
+/// leader executes the scheduled runs.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/cleanup",
+    tag = "cleanup",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [CleanupJobResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/cleanup")]
+pub async fn get_cleanup_jobs(principal: ReqPrincipal) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let jobs = CleanupJob::all()
+        .into_iter()
+        .map(CleanupJobResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+/// Executes a cleanup job immediately
+///
+/// The job runs on the instance answering the request, independent of its schedule.
+/// Possible jobs: `magic_links`, `password_resets`, `refresh_tokens`, `webauthn_registrations`
+///
+/// `magic_links` deletes users, which never set a password, and needs the `Users` `Delete`
+/// access rights in addition for API keys.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/cleanup/{job}",
+    tag = "cleanup",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = CleanupRunResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/cleanup/{job}")]
+pub async fn post_cleanup_job(
+    data: web::Data<AppState>,
+    job: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Delete)?;
+
+    let job = CleanupJob::from_str(&job.into_inner())?;
+    if job == CleanupJob::MagicLinks {
+        principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Delete)?;
+    }
+    job.run(&data, true).await?;
+
+    let run = job.last_run().map(CleanupRunResponse::from);
+    Ok(HttpResponse::Ok().json(run))
+}
@@ -1,25 +1,32 @@
 use crate::ReqPrincipal;
 use actix_web::http::header::{
-    ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_TYPE, WWW_AUTHENTICATE,
+    ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE,
 };
 use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 use actix_web_lab::__reexports::futures_util::StreamExt;
-use rauthy_common::constants::{DYN_CLIENT_REG_TOKEN, ENABLE_DYN_CLIENT_REG};
+use rauthy_common::constants::{ASSET_CACHE_MAX_AGE, DYN_CLIENT_REG_TOKEN, ENABLE_DYN_CLIENT_REG};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::token_errors;
 use rauthy_common::utils::real_ip_from_req;
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::client_secret_reads::ClientSecretRead;
+use rauthy_models::entity::client_user_agents::ClientUserAgent;
 use rauthy_models::entity::clients::Client;
 use rauthy_models::entity::clients_dyn::ClientDyn;
 use rauthy_models::entity::colors::ColorEntity;
 use rauthy_models::entity::logos::{Logo, LogoType};
+use rauthy_models::events::event::Event;
 use rauthy_models::request::{
     ColorsRequest, DynamicClientRequest, NewClientRequest, UpdateClientRequest,
 };
-use rauthy_models::response::{ClientResponse, DynamicClientResponse};
+use rauthy_models::response::{
+    ClientResponse, DynamicClientResponse, OAuth2ErrorResponse, TokenErrorResponse,
+};
 use rauthy_service::auth::get_bearer_token_from_header;
 use rauthy_service::client;
 use tracing::debug;
+use validator::Validate;
 
 /// Returns all existing OIDC clients with all their information, except for the client secrets.
 ///
@@ -29,6 +36,7 @@ use tracing::debug;
     get,
     path = "/clients",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [Client]),
         (status = 400, description = "BadRequest"),
@@ -63,6 +71,7 @@ pub async fn get_clients(
     get,
     path = "/clients/{id}",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Client),
         (status = 400, description = "BadRequest"),
@@ -79,19 +88,119 @@ pub async fn get_client_by_id(
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Clients, AccessRights::Read)?;
 
-    Client::find(&data, path.into_inner())
-        .await
-        .map(|c| HttpResponse::Ok().json(ClientResponse::from(c)))
+    let client = Client::find(&data, path.into_inner()).await?;
+    let secret_reads = ClientSecretRead::find(&data, &client.id).await?;
+    let mut resp = ClientResponse::from(client);
+    resp.secret_reads = secret_reads;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Returns the user agents seen at the token endpoint for all clients.
+///
+/// Each entry aggregates the successful token requests of a client with the same user agent,
+/// which helps to find relying parties running outdated SDKs.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/clients_user_agents",
+    tag = "clients",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [ClientUserAgent]),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+)]
+#[get("/clients_user_agents")]
+pub async fn get_clients_user_agents(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Clients, AccessRights::Read)?;
+
+    let agents = ClientUserAgent::find_all(&data).await?;
+    Ok(HttpResponse::Ok().json(agents))
+}
+
+/// Returns the user agents seen at the token endpoint for a single client by its *id*.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/clients/{id}/user_agents",
+    tag = "clients",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [ClientUserAgent]),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "NotFound"),
+    ),
+)]
+#[get("/clients/{id}/user_agents")]
+pub async fn get_client_user_agents(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Clients, AccessRights::Read)?;
+
+    let client = Client::find(&data, path.into_inner()).await?;
+    let agents = ClientUserAgent::find_for_client(&data, &client.id).await?;
+    Ok(HttpResponse::Ok().json(agents))
+}
+
+/// Returns the latest failed token requests for a single client by its *id*, newest first.
+///
+/// Each failure is classified by its cause, like an invalid secret, an expired code or a PKCE
+/// mismatch, to make debugging an integration easier. Invalid redirect URIs are rejected during
+/// the authorization already and show up here as well. Errors are kept in memory per instance.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/clients/{id}/token_errors",
+    tag = "clients",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [TokenErrorResponse]),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "NotFound"),
+    ),
+)]
+#[get("/clients/{id}/token_errors")]
+pub async fn get_client_token_errors(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Clients, AccessRights::Read)?;
+
+    let client = Client::find(&data, path.into_inner()).await?;
+    let errors = token_errors::recent(&client.id)
+        .into_iter()
+        .map(TokenErrorResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(errors))
 }
 
 /// Returns the secret in cleartext for a given client by its *id*.
 ///
+/// Each read is recorded with the reader and its IP, counted for the client details and
+/// generates a `ClientSecretRead` event.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
     get,
     path = "/clients/{id}/secret",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = ClientSecretResponse),
         (status = 400, description = "BadRequest"),
@@ -104,13 +213,25 @@ pub async fn get_client_by_id(
 pub async fn get_client_secret(
     data: web::Data<AppState>,
     path: web::Path<String>,
+    req: HttpRequest,
     principal: ReqPrincipal,
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Secrets, AccessRights::Read)?;
 
-    client::get_client_secret(path.into_inner(), &data)
+    let secret = client::get_client_secret(path.into_inner(), &data).await?;
+
+    let actor = match &principal.api_key {
+        Some(api_key) => format!("api_key:{}", api_key.name),
+        None => principal.user_id()?.to_string(),
+    };
+    let ip = real_ip_from_req(&req);
+    ClientSecretRead::record(&data, &secret.id, &actor, ip.as_deref()).await?;
+    data.tx_events
+        .send_async(Event::client_secret_read(&secret.id, &actor, ip))
         .await
-        .map(|c| HttpResponse::Ok().json(c))
+        .unwrap();
+
+    Ok(HttpResponse::Ok().json(secret))
 }
 
 /// Adds a new OIDC client to the database.
@@ -122,6 +243,7 @@ pub async fn get_client_secret(
     path = "/clients",
     tag = "clients",
     request_body = NewClientRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Client),
         (status = 400, description = "BadRequest"),
@@ -149,9 +271,10 @@ pub async fn post_clients(
     path = "/clients_dyn",
     tag = "clients",
     request_body = DynamicClientRequest,
+    security(("bearer" = [])),
     responses(
         (status = 201, description = "Created", body = DynamicClientResponse),
-        (status = 400, description = "BadRequest"),
+        (status = 400, description = "BadRequest", body = OAuth2ErrorResponse),
         (status = 401, description = "Unauthorized"),
         (status = 404, description = "NotFound"),
     ),
@@ -159,7 +282,7 @@ pub async fn post_clients(
 #[post("/clients_dyn")]
 pub async fn post_clients_dyn(
     data: web::Data<AppState>,
-    payload: actix_web_validator::Json<DynamicClientRequest>,
+    payload: web::Json<DynamicClientRequest>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ErrorResponse> {
     if !*ENABLE_DYN_CLIENT_REG {
@@ -177,7 +300,16 @@ pub async fn post_clients_dyn(
                 ))
                 .finish());
         }
-    } else {
+    }
+
+    // validate before the rate limiting to not block a client fixing its metadata
+    if let Err(err) = payload.validate() {
+        return Ok(HttpResponse::BadRequest()
+            .insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, "*"))
+            .json(OAuth2ErrorResponse::client_metadata(err)));
+    }
+
+    if DYN_CLIENT_REG_TOKEN.is_none() {
         let ip = real_ip_from_req(&req).unwrap_or_default();
         ClientDyn::rate_limit_ip(&data, ip).await?;
     }
@@ -197,6 +329,7 @@ pub async fn post_clients_dyn(
     get,
     path = "/clients_dyn/{id}",
     tag = "clients",
+    security(("bearer" = [])),
     responses(
         (status = 200, description = "Ok", body = DynamicClientResponse),
         (status = 400, description = "BadRequest"),
@@ -230,9 +363,10 @@ pub async fn get_clients_dyn(
     path = "/clients_dyn/{id}",
     tag = "clients",
     request_body = DynamicClientRequest,
+    security(("bearer" = [])),
     responses(
         (status = 200, description = "Ok", body = DynamicClientResponse),
-        (status = 400, description = "BadRequest"),
+        (status = 400, description = "BadRequest", body = OAuth2ErrorResponse),
         (status = 401, description = "Unauthorized"),
         (status = 404, description = "NotFound"),
     ),
@@ -240,7 +374,7 @@ pub async fn get_clients_dyn(
 #[put("/clients_dyn/{id}")]
 pub async fn put_clients_dyn(
     data: web::Data<AppState>,
-    payload: actix_web_validator::Json<DynamicClientRequest>,
+    payload: web::Json<DynamicClientRequest>,
     id: web::Path<String>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ErrorResponse> {
@@ -253,10 +387,54 @@ pub async fn put_clients_dyn(
     let client_dyn = ClientDyn::find(&data, id.clone()).await?;
     client_dyn.validate_token(&bearer)?;
 
+    if let Err(err) = payload.validate() {
+        return Ok(HttpResponse::BadRequest().json(OAuth2ErrorResponse::client_metadata(err)));
+    }
+
     let resp = Client::update_dynamic(&data, payload.into_inner(), client_dyn).await?;
     Ok(HttpResponse::Ok().json(resp))
 }
 
+/// Delete a dynamic OIDC client
+#[utoipa::path(
+    delete,
+    path = "/clients_dyn/{id}",
+    tag = "clients",
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "NoContent"),
+        (status = 400, description = "BadRequest"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "NotFound"),
+    ),
+)]
+#[delete("/clients_dyn/{id}")]
+pub async fn delete_clients_dyn(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    if !*ENABLE_DYN_CLIENT_REG {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let bearer = get_bearer_token_from_header(req.headers())?;
+    let id = id.into_inner();
+    let client_dyn = ClientDyn::find(&data, id.clone()).await?;
+    client_dyn.validate_token(&bearer)?;
+
+    let client = Client::find(&data, id).await?;
+    if !client.is_dynamic() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Invalid request for non-dynamic client".to_string(),
+        ));
+    }
+    client.delete(&data).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 /// Modifies an OIDC client
 ///
 /// **Permissions**
@@ -266,6 +444,7 @@ pub async fn put_clients_dyn(
     path = "/clients/{id}",
     tag = "clients",
     request_body = UpdateClientRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Client),
         (status = 400, description = "BadRequest"),
@@ -296,6 +475,7 @@ pub async fn put_clients(
     get,
     path = "/clients/{id}/colors",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Colors),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -324,6 +504,7 @@ pub async fn get_client_colors(
     path = "/clients/{id}/colors",
     tag = "clients",
     request_body = ColorsRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -355,6 +536,7 @@ pub async fn put_client_colors(
     delete,
     path = "/clients/{id}/colors",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -381,6 +563,7 @@ pub async fn delete_client_colors(
     tag = "clients",
     responses(
         (status = 200, description = "Ok"),
+        (status = 302, description = "Found - the logo is stored in S3"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 403, description = "Forbidden", body = ErrorResponse),
@@ -403,12 +586,19 @@ pub async fn get_client_logo(
         }
     };
 
+    if let Some((url, max_age)) = logo.s3_url(&LogoType::Client)? {
+        return Ok(HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .insert_header((CACHE_CONTROL, format!("max-age={}", max_age)))
+            .finish());
+    }
+
     Ok(HttpResponse::Ok()
         .insert_header((CONTENT_TYPE, logo.content_type))
-        // clients should cache the logos for 12 hours
+        // clients should cache the logos for `ASSET_CACHE_MAX_AGE` (default 12 hours)
         // this means if a logo has been updated, they receive the new one 12 hours
         // later in the worst case
-        .insert_header((CACHE_CONTROL, "max-age=43200"))
+        .insert_header((CACHE_CONTROL, format!("max-age={}", *ASSET_CACHE_MAX_AGE)))
         .body(logo.data))
 }
 
@@ -420,6 +610,7 @@ pub async fn get_client_logo(
     put,
     path = "/clients/{id}/logo",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -481,6 +672,7 @@ pub async fn put_client_logo(
 delete,
     path = "/clients/{id}/logo",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -515,6 +707,7 @@ pub async fn delete_client_logo(
     put,
     path = "/clients/{id}/secret",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = ClientSecretResponse),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -544,6 +737,7 @@ pub async fn put_generate_client_secret(
     delete,
     path = "/clients/{id}",
     tag = "clients",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -0,0 +1,37 @@
+use crate::ReqPrincipal;
+use actix_web::{get, web, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::cluster_nodes::ClusterNode;
+use rauthy_models::response::ClusterResponse;
+
+/// Returns all Rauthy instances, which registered themselves in the database
+///
+/// Each instance sends a heartbeat in the `CLUSTER_HEARTBEAT_SECS` interval. Nodes, which missed
+/// at least 3 heartbeats, are marked as `stale`. `version_skew` shows if the non-stale nodes are
+/// running different versions, like during a rolling upgrade.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/cluster",
+    tag = "cluster",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = ClusterResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/cluster")]
+pub async fn get_cluster(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let nodes = ClusterNode::find_all(&data).await?;
+    Ok(HttpResponse::Ok().json(ClusterResponse::from(nodes)))
+}
@@ -0,0 +1,113 @@
+use crate::ReqPrincipal;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_web_validator::{Json, Query};
+use chrono::Utc;
+use rauthy_common::constants::EMAIL_WEBHOOK_TOKEN;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::constant_time_eq;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::email_deliveries::{EmailDelivery, EmailDeliveryStatus};
+use rauthy_models::request::{EmailDeliveriesParams, EmailWebhookRequest};
+use rauthy_models::response::EmailDeliveriesResponse;
+
+/// Returns E-Mail delivery stats per SMTP relay and the most recent failures
+///
+/// Deliveries are kept for `EMAIL_DELIVERY_RETENTION_DAYS`. Failures include E-Mails, which are
+/// still retried (`deferred`), which have been reported as `bounced` by a relay, and the ones,
+/// which exceeded `JOB_MAX_ATTEMPTS` (`failed`).
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/email/deliveries",
+    tag = "email",
+    params(EmailDeliveriesParams),
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = EmailDeliveriesResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/email/deliveries")]
+pub async fn get_email_deliveries(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    params: Query<EmailDeliveriesParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let since = Utc::now().timestamp() - params.hours.unwrap_or(24) as i64 * 3600;
+    let counts = EmailDelivery::count_since(&data, since).await?;
+    let failures =
+        EmailDelivery::find_recent_failures(&data, params.limit.unwrap_or(50) as i64).await?;
+
+    let resp = EmailDeliveriesResponse::build(since, counts, failures)?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Delivery status updates from SMTP relays
+///
+/// Only available if `EMAIL_WEBHOOK_TOKEN` is set, which must be given as
+/// `Authorization: Bearer <token>`. The E-Mail is matched by its `Message-ID`. Only the status
+/// `deferred` and `bounced` can be reported.
+#[utoipa::path(
+    post,
+    path = "/email/webhook",
+    tag = "email",
+    request_body = EmailWebhookRequest,
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/email/webhook")]
+pub async fn post_email_webhook(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<EmailWebhookRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let Some(token) = EMAIL_WEBHOOK_TOKEN.as_deref() else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The E-Mail webhook is not enabled".to_string(),
+        ));
+    };
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(given, token) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::WWWAuthenticate("Bearer".to_string()),
+            "Missing or invalid webhook token".to_string(),
+        ));
+    }
+
+    let payload = payload.into_inner();
+    if !matches!(
+        payload.status,
+        EmailDeliveryStatus::Deferred | EmailDeliveryStatus::Bounced
+    ) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Only 'deferred' and 'bounced' can be reported".to_string(),
+        ));
+    }
+    let id = EmailDelivery::id_from_message_id(&payload.message_id).ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid 'message_id'".to_string(),
+        )
+    })?;
+
+    EmailDelivery::update_status(&data, id, payload.status, payload.reason).await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,16 +1,24 @@
 use crate::ReqPrincipal;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
 use actix_web_lab::sse;
-use actix_web_validator::Json;
+use actix_web_validator::{Json, Query};
 use chrono::Utc;
-use rauthy_common::constants::SSE_KEEP_ALIVE;
+use rauthy_common::constants::{SSE_CLIENT_BUFFER, SSE_KEEP_ALIVE};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::real_ip_from_req;
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::event_recipients::EventRecipient;
+use rauthy_models::events::chain::EventChain;
 use rauthy_models::events::event::Event;
 use rauthy_models::events::listener::EventRouterMsg;
-use rauthy_models::request::{EventsListenParams, EventsRequest};
+use rauthy_models::events::sse_hub;
+use rauthy_models::request::{
+    EventRecipientsRequest, EventsExportFormat, EventsExportParams, EventsListenParams,
+    EventsRequest,
+};
+use rauthy_models::response::EventRecipientResponse;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use validator::Validate;
@@ -20,6 +28,7 @@ use validator::Validate;
     post,
     path = "/events",
     tag = "events",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -50,18 +59,228 @@ pub async fn post_events(
     Ok(HttpResponse::Ok().json(events))
 }
 
+/// Export events for archival
+///
+/// Returns all matching events as a file download, either as NDJSON (one JSON event per line)
+/// or as CSV.
+#[utoipa::path(
+    get,
+    path = "/events/export",
+    tag = "events",
+    params(EventsExportParams),
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/events/export")]
+pub async fn get_events_export(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    params: Query<EventsExportParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Events, AccessRights::Read)?;
+
+    let params = params.into_inner();
+    let until = params.until.unwrap_or_else(|| Utc::now().timestamp());
+    let events = Event::find_all(
+        &data.db,
+        params.from,
+        until,
+        params.level.unwrap_or_default(),
+        params.typ,
+    )
+    .await?;
+
+    let (content_type, ext, body) = match params.format.unwrap_or_default() {
+        EventsExportFormat::Ndjson => {
+            let mut body = String::with_capacity(events.len() * 128);
+            for event in events {
+                body.push_str(&event.as_json());
+                body.push('\n');
+            }
+            ("application/x-ndjson", "ndjson", body)
+        }
+        EventsExportFormat::Csv => {
+            let mut body = String::with_capacity(events.len() * 96);
+            body.push_str(Event::CSV_HEADER);
+            body.push('\n');
+            for event in events {
+                body.push_str(&event.as_csv_row());
+                body.push('\n');
+            }
+            ("text/csv", "csv", body)
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"rauthy-events-{}-{}.{}\"",
+                params.from, until, ext
+            ),
+        ))
+        .body(body))
+}
+
+/// Verify the event hash chain
+///
+/// Checks every persisted event against its hash and the link to its predecessor, and validates
+/// the signatures of all anchors, which are created periodically with the latest `EdDSA` key.
+/// The public keys for external validation of the anchors are available via the JWKS endpoint.
+///
+/// The links of events deleted by the cleanup are kept, so that a chain stays valid after the
+/// cleanup. Events of deleted users are anonymized on purpose, and only their links are checked
+/// afterward.
+///
+/// **Permissions**
+/// - `rauthy_admin`
+/// - `events: read`
+#[utoipa::path(
+    get,
+    path = "/events/chain/verify",
+    tag = "events",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = EventChainVerifyResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/events/chain/verify")]
+pub async fn get_events_chain_verify(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Events, AccessRights::Read)?;
+
+    let report = EventChain::verify(&data).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Returns the runtime E-Mail recipients for event notifications
+///
+/// The static `EVENT_EMAIL` from the config is not included.
+///
+/// **Permissions**
+/// - `rauthy_admin`
+/// - `events: read`
+#[utoipa::path(
+    get,
+    path = "/events/recipients",
+    tag = "events",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [EventRecipientResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/events/recipients")]
+pub async fn get_event_recipients(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Events, AccessRights::Read)?;
+
+    let recipients = EventRecipient::find_all(&data.db)
+        .await?
+        .into_iter()
+        .map(EventRecipientResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(recipients))
+}
+
+/// Replaces the runtime E-Mail recipients for event notifications
+///
+/// Each recipient gets all events with at least its level. During its optional quiet hours, only
+/// critical events are sent. Changes are applied without a restart.
+///
+/// API Keys cannot modify the recipients, because they could redirect all events to an outside
+/// address. Each change creates an `EventRecipientsChanged` event.
+///
+/// **Permissions**
+/// - `rauthy_admin`
+#[utoipa::path(
+    put,
+    path = "/events/recipients",
+    tag = "events",
+    request_body = EventRecipientsRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [EventRecipientResponse]),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[put("/events/recipients")]
+pub async fn put_event_recipients(
+    data: web::Data<AppState>,
+    payload: Json<EventRecipientsRequest>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_session()?;
+
+    let recipients = payload
+        .into_inner()
+        .recipients
+        .into_iter()
+        .map(EventRecipient::from_req)
+        .collect::<Result<Vec<_>, _>>()?;
+    let before = EventRecipient::find_all(&data.db).await?;
+    let recipients = EventRecipient::save_all(&data, recipients).await?;
+
+    let emails = |r: &[EventRecipient]| {
+        r.iter()
+            .map(|r| r.email.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let text = format!(
+        "by {}: [{}] -> [{}]",
+        principal.user_id()?,
+        emails(&before),
+        emails(&recipients)
+    );
+    data.tx_events
+        .send_async(Event::event_recipients_changed(
+            text,
+            real_ip_from_req(&req),
+        ))
+        .await
+        .unwrap();
+
+    let recipients = recipients
+        .into_iter()
+        .map(EventRecipientResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(recipients))
+}
+
 /// Listen to the Events SSE stream
+///
+/// Each client has a bounded buffer. If it cannot keep up with the stream, events are dropped
+/// for it and after too many drops in a row, the stream is closed and must be reconnected.
 #[utoipa::path(
     get,
     path = "/events/stream",
     tag = "events",
     params(EventsListenParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 403, description = "Forbidden", body = ErrorResponse),
         (status = 404, description = "NotFound", body = ErrorResponse),
+        (status = 429, description = "TooManyRequests", body = ErrorResponse),
     ),
 )]
 #[get("/events/stream")]
@@ -75,6 +294,27 @@ pub async fn sse_events(
 
     params.validate()?;
 
+    if sse_hub::is_full() {
+        // Disconnected clients are only noticed with the next event. Remove them first, so
+        // they do not take the place of new ones.
+        let (tx, mut rx) = mpsc::channel(1);
+        if data
+            .tx_events_router
+            .send_async(EventRouterMsg::Prune(tx))
+            .await
+            .is_ok()
+        {
+            rx.recv().await;
+        }
+
+        if sse_hub::is_full() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::TooManyRequests(Utc::now().timestamp() + 10),
+                "The max amount of events stream clients has been reached".to_string(),
+            ));
+        }
+    }
+
     match real_ip_from_req(&req) {
         None => Err(ErrorResponse::new(
             ErrorResponseType::NotFound,
@@ -83,7 +323,9 @@ pub async fn sse_events(
         )),
         Some(ip) => {
             let params = params.into_inner();
-            let (tx, rx) = mpsc::channel(10);
+            // the buffer must hold the requested latest events on top of the live ones
+            let buffer = *SSE_CLIENT_BUFFER + params.latest.unwrap_or_default() as usize;
+            let (tx, rx) = mpsc::channel(buffer);
 
             if let Err(err) = data
                 .tx_events_router
@@ -113,6 +355,7 @@ pub async fn sse_events(
     post,
     path = "/events/test",
     tag = "events",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -0,0 +1,156 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::feature_flags::FeatureFlag;
+use rauthy_models::request::FeatureFlagRequest;
+use rauthy_models::response::FeatureFlagResponse;
+use rauthy_service::auth;
+
+/// Returns all existing feature flags
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/feature_flags",
+    tag = "generic",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [FeatureFlagResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/feature_flags")]
+pub async fn get_feature_flags(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let res = FeatureFlag::find_all(&data)
+        .await?
+        .into_iter()
+        .map(FeatureFlagResponse::from)
+        .collect::<Vec<FeatureFlagResponse>>();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Evaluates the feature flags for the bearer token
+///
+/// Returns all flags, which are visible for the tokens client, with their state for the
+/// tokens user. For tokens without a user, only flags without targeting rules can be on.
+/// The same flags can be added to the tokens and the userinfo with the `flags` scope.
+#[utoipa::path(
+    get,
+    path = "/feature_flags/evaluate",
+    tag = "generic",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Ok", body = FeatureFlagsEvaluatedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[get("/feature_flags/evaluate")]
+pub async fn get_feature_flags_evaluate(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    auth::get_feature_flags(&data, req)
+        .await
+        .map(|flags| HttpResponse::Ok().json(flags))
+}
+
+/// Creates a new feature flag
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/feature_flags",
+    tag = "generic",
+    request_body = FeatureFlagRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = FeatureFlagResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/feature_flags")]
+pub async fn post_feature_flag(
+    data: web::Data<AppState>,
+    payload: actix_web_validator::Json<FeatureFlagRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Create)?;
+
+    FeatureFlag::create(&data, payload.into_inner())
+        .await
+        .map(|f| HttpResponse::Ok().json(FeatureFlagResponse::from(f)))
+}
+
+/// Modifies a feature flag
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/feature_flags/{id}",
+    tag = "generic",
+    request_body = FeatureFlagRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = FeatureFlagResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/feature_flags/{id}")]
+pub async fn put_feature_flag(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: actix_web_validator::Json<FeatureFlagRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Update)?;
+
+    FeatureFlag::update(&data, id.into_inner(), payload.into_inner())
+        .await
+        .map(|f| HttpResponse::Ok().json(FeatureFlagResponse::from(f)))
+}
+
+/// Deletes a feature flag
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/feature_flags/{id}",
+    tag = "generic",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/feature_flags/{id}")]
+pub async fn delete_feature_flag(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Delete)?;
+
+    FeatureFlag::delete(&data, id.as_str())
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
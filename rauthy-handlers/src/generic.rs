@@ -4,10 +4,11 @@ use actix_web::http::{header, StatusCode};
 use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
 use cryptr::EncKeys;
 use rauthy_common::constants::{
-    APPLICATION_JSON, CACHE_NAME_LOGIN_DELAY, HEADER_ALLOW_ALL_ORIGINS, HEADER_HTML,
-    IDX_LOGIN_TIME, RAUTHY_VERSION,
+    APPLICATION_JSON, HEADER_ALLOW_ALL_ORIGINS, HEADER_HTML, RAUTHY_VERSION,
 };
 use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::login_delay;
+use rauthy_common::login_latency::LatencyStage;
 use rauthy_common::utils::real_ip_from_req;
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
@@ -28,24 +29,27 @@ use rauthy_models::i18n::error::I18nError;
 use rauthy_models::i18n::index::I18nIndex;
 use rauthy_models::i18n::logout::I18nLogout;
 use rauthy_models::i18n::password_reset::I18nPasswordReset;
+use rauthy_models::i18n::push_mfa::I18nPushMfa;
 use rauthy_models::i18n::register::I18nRegister;
 use rauthy_models::i18n::SsrJson;
 use rauthy_models::language::Language;
 use rauthy_models::request::{
-    EncKeyMigrateRequest, I18nContent, I18nRequest, PasswordHashTimesRequest,
-    PasswordPolicyRequest, SearchParams, SearchParamsType, WhoamiRequestParam, WhoamiRequestParams,
+    BreakGlassRequest, ComplianceReportFormat, ComplianceReportParams, EncKeyMigrateRequest,
+    I18nContent, I18nRequest, PasswordHashTimesRequest, PasswordPolicyRequest, SearchParams,
+    SearchParamsType, WhoamiRequestParam, WhoamiRequestParams,
 };
 use rauthy_models::response::{
-    AppVersionResponse, Argon2ParamsResponse, EncKeysResponse, HealthResponse, LoginTimeResponse,
-    PasswordPolicyResponse,
+    AppVersionResponse, Argon2ParamsResponse, EncKeysResponse, HealthResponse,
+    LoginLatencyResponse, LoginTimeResponse, PasswordPolicyResponse,
 };
 use rauthy_models::templates::{
     AccountHtml, AdminApiKeysHtml, AdminAttributesHtml, AdminBlacklistHtml, AdminClientsHtml,
     AdminConfigHtml, AdminDocsHtml, AdminGroupsHtml, AdminHtml, AdminRolesHtml, AdminScopesHtml,
-    AdminSessionsHtml, AdminUsersHtml, DeviceHtml, IndexHtml, ProvidersHtml,
+    AdminSessionsHtml, AdminUsersHtml, ComplianceReportHtml, DeviceHtml, IndexHtml, ProvidersHtml,
+    PushMfaHtml,
 };
-use rauthy_service::encryption;
-use redhac::{cache_get, cache_get_from, cache_get_value, QuorumHealth, QuorumState};
+use rauthy_service::{break_glass, compliance, encryption};
+use redhac::{QuorumHealth, QuorumState};
 use semver::Version;
 use std::borrow::Cow;
 use std::str::FromStr;
@@ -114,6 +118,7 @@ pub async fn post_i18n(
         I18nContent::Index => I18nIndex::build(&lang).as_json(),
         I18nContent::Logout => I18nLogout::build(&lang).as_json(),
         I18nContent::PasswordReset => I18nPasswordReset::build(&lang).as_json(),
+        I18nContent::PushMfa => I18nPushMfa::build(&lang).as_json(),
         I18nContent::Register => I18nRegister::build(&lang).as_json(),
     };
 
@@ -281,11 +286,24 @@ pub async fn get_device_html(
     Ok(HttpResponse::Ok().insert_header(HEADER_HTML).body(body))
 }
 
+#[get("/push_mfa")]
+pub async fn get_push_mfa_html(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    let colors = ColorEntity::find_rauthy(&data).await?;
+    let lang = Language::try_from(&req).unwrap_or_default();
+    let body = PushMfaHtml::build(&colors, &lang);
+
+    Ok(HttpResponse::Ok().insert_header(HEADER_HTML).body(body))
+}
+
 /// Check if the current session is valid
 #[utoipa::path(
     get,
     path = "/auth_check",
     tag = "generic",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest"),
@@ -303,6 +321,7 @@ pub async fn get_auth_check(principal: ReqPrincipal) -> Result<HttpResponse, Err
     get,
     path = "/auth_check_admin",
     tag = "generic",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest"),
@@ -315,6 +334,103 @@ pub async fn get_auth_check_admin(principal: ReqPrincipal) -> Result<HttpRespons
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Emergency admin access with the offline break glass credential
+///
+/// Only available if `BREAK_GLASS_HASH` is configured. It returns a one-time URL, which is valid
+/// for 60 seconds and must be opened in the browser to get a short-lived admin session.
+/// Each credential can only be used once. Every attempt creates a `BreakGlass` event.
+#[utoipa::path(
+    post,
+    path = "/break_glass",
+    tag = "generic",
+    request_body = BreakGlassRequest,
+    responses(
+        (status = 200, description = "Ok", body = BreakGlassResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+        (status = 429, description = "TooManyRequests", body = ErrorResponse),
+    ),
+)]
+#[post("/break_glass")]
+pub async fn post_break_glass(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Json<BreakGlassRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_listener()?;
+
+    break_glass::start(&data, &req, payload.into_inner())
+        .await
+        .map(|resp| HttpResponse::Ok().json(resp))
+}
+
+/// Exchanges a break glass code for a short-lived admin session
+///
+/// Redirects to the admin UI on success.
+#[utoipa::path(
+    get,
+    path = "/break_glass/{code}",
+    tag = "generic",
+    responses(
+        (status = 302, description = "Found"),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/break_glass/{code}")]
+pub async fn get_break_glass(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    code: web::Path<String>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_listener()?;
+
+    break_glass::finish(&data, &req, code.into_inner()).await
+}
+
+/// Compliance report
+///
+/// Generates a point-in-time compliance report for audits like SOC2 or ISO 27001. It contains
+/// all admins with their MFA status, clients with weak settings, the password policy, the age of
+/// the active signing keys and the configured event sinks.
+///
+/// With `format=html`, a self-contained HTML document will be returned, which is ready to be
+/// printed or converted into a PDF.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/compliance_report",
+    tag = "generic",
+    params(ComplianceReportParams),
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = ComplianceReport),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+)]
+#[get("/compliance_report")]
+pub async fn get_compliance_report(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    params: actix_web_validator::Query<ComplianceReportParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let report = compliance::build_report(&data).await?;
+    match params.into_inner().format.unwrap_or_default() {
+        ComplianceReportFormat::Json => Ok(HttpResponse::Ok().json(report)),
+        ComplianceReportFormat::Html => Ok(HttpResponse::Ok()
+            .insert_header(HEADER_HTML)
+            .body(ComplianceReportHtml::build(&report))),
+    }
+}
+
 /// Returns the existing encryption key ID's
 ///
 /// Only the Key ID's are returned and never the key itself.
@@ -325,6 +441,7 @@ pub async fn get_auth_check_admin(principal: ReqPrincipal) -> Result<HttpRespons
     get,
     path = "/encryption/keys",
     tag = "generic",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized"),
@@ -355,6 +472,7 @@ pub async fn get_enc_keys(principal: ReqPrincipal) -> Result<HttpResponse, Error
     post,
     path = "/encryption/migrate",
     tag = "generic",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized"),
@@ -383,6 +501,8 @@ pub async fn post_migrate_enc_key(
 /// Returns the current Argon2ID parameters and the login time
 ///
 /// The `login time` is the time it takes to complete a full login workflow incl password hashing.
+/// The `latency` contains the p50 / p95 / p99 in ms for password hashing, the user lookup and the
+/// whole authorize request for recent successful logins on this instance.
 ///
 /// **Permissions**
 /// - rauthy_admin
@@ -390,8 +510,9 @@ pub async fn post_migrate_enc_key(
     get,
     path = "/login_time",
     tag = "generic",
+    security(("api_key" = []), ("session" = [])),
     responses(
-        (status = 200, description = "LoginTimeResponse"),
+        (status = 200, description = "Ok", body = LoginTimeResponse),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
     ),
@@ -403,15 +524,7 @@ pub async fn get_login_time(
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
 
-    let login_time = cache_get!(
-        u32,
-        CACHE_NAME_LOGIN_DELAY.to_string(),
-        IDX_LOGIN_TIME.to_string(),
-        &data.caches.ha_cache_config,
-        false
-    )
-    .await?
-    .unwrap_or(2000);
+    let login_time = login_delay::success_millis(&data.caches.ha_cache_config).await? as u32;
     let argon2_params = Argon2ParamsResponse {
         m_cost: data.argon2_params.params.m_cost(),
         t_cost: data.argon2_params.params.t_cost(),
@@ -421,6 +534,10 @@ pub async fn get_login_time(
         argon2_params,
         login_time,
         num_cpus: num_cpus::get(),
+        latency: LatencyStage::ALL
+            .into_iter()
+            .map(LoginLatencyResponse::from)
+            .collect(),
     };
     Ok(HttpResponse::Ok().json(resp))
 }
@@ -436,6 +553,7 @@ pub async fn get_login_time(
     path = "/password_hash_times",
     tag = "generic",
     request_body = PasswordHashTimesRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = PasswordHashTimes),
         (status = 400, description = "BadRequest"),
@@ -463,6 +581,7 @@ pub async fn post_password_hash_times(
     get,
     path = "/password_policy",
     tag = "generic",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = PasswordPolicyResponse),
         (status = 401, description = "Unauthorized"),
@@ -487,6 +606,7 @@ pub async fn get_password_policy(
     path = "/password_policy",
     tag = "generic",
     request_body = PasswordPolicyRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = PasswordPolicyResponse),
         (status = 400, description = "BadRequest"),
@@ -523,6 +643,9 @@ pub async fn ping() -> impl Responder {
 }
 
 /// Request a Proof-of-Work Challenge
+///
+/// The difficulty is part of the returned challenge. It scales automatically with the amount
+/// of requested challenges, see `POW_SCALE_THRESHOLD`.
 #[utoipa::path(
     post,
     path = "/pow",
@@ -544,6 +667,7 @@ pub async fn post_pow(data: web::Data<AppState>) -> Result<HttpResponse, ErrorRe
     get,
     path = "/search",
     tag = "generic",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "BadRequest"),
@@ -576,6 +700,7 @@ pub async fn get_search(
     post,
     path = "/update_language",
     tag = "generic",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Valid Token / Session"),
         (status = 401, description = "Unauthorized"),
@@ -672,21 +797,31 @@ pub async fn get_ready(data: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok().finish()
 }
 
+/// The only target of the generic redirects.
+///
+/// These redirects must never be built from any request input. A static, relative location
+/// makes it impossible to abuse them as an open redirect, which is why there is no allow-list.
+const REDIRECT_LOCATION: &str = "/auth/v1/";
+
 /// Redirects from root to the "real root" /auth/v1/
 #[get("/")]
 pub async fn redirect() -> impl Responder {
     HttpResponse::MovedPermanently()
         .insert_header((
             header::LOCATION,
-            HeaderValue::from_str("/auth/v1/").unwrap(),
+            HeaderValue::from_static(REDIRECT_LOCATION),
         ))
         .finish()
 }
 
+/// Redirects from /auth/v1 to the "real root" /auth/v1/
 #[get("/v1")]
 pub async fn redirect_v1() -> HttpResponse {
     HttpResponse::MovedPermanently()
-        .insert_header(("location", "/auth/v1/"))
+        .insert_header((
+            header::LOCATION,
+            HeaderValue::from_static(REDIRECT_LOCATION),
+        ))
         .finish()
 }
 
@@ -4,7 +4,9 @@ use rauthy_common::error_response::ErrorResponse;
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
 use rauthy_models::entity::groups::Group;
-use rauthy_models::request::NewGroupRequest;
+use rauthy_models::entity::login_policies::GroupLoginPolicy;
+use rauthy_models::request::{GroupLoginPolicyRequest, NewGroupRequest};
+use rauthy_models::response::GroupLoginPolicyResponse;
 
 /// Returns all existing *groups*
 ///
@@ -14,6 +16,7 @@ use rauthy_models::request::NewGroupRequest;
     get,
     path = "/groups",
     tag = "groups",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [Group]),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -41,6 +44,7 @@ pub async fn get_groups(
     path = "/groups",
     tag = "groups",
     request_body = NewGroupRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Group),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -60,7 +64,7 @@ pub async fn post_group(
         .map(|r| HttpResponse::Ok().json(r))
 }
 
-/// Modifies a groups name
+/// Modifies a groups name and its E-Mail domains
 ///
 /// **Permissions**
 /// - rauthy_admin
@@ -69,6 +73,7 @@ pub async fn post_group(
     path = "/groups/{id}",
     tag = "groups",
     request_body = NewGroupRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Group),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -84,7 +89,7 @@ pub async fn put_group(
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Groups, AccessRights::Update)?;
 
-    Group::update(&data, id.into_inner(), group_req.group.to_owned())
+    Group::update(&data, id.into_inner(), group_req.into_inner())
         .await
         .map(|g| HttpResponse::Ok().json(g))
 }
@@ -99,6 +104,7 @@ pub async fn put_group(
     delete,
     path = "/groups/{id}",
     tag = "groups",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -117,3 +123,97 @@ pub async fn delete_group(
         .await
         .map(|_| HttpResponse::Ok().finish())
 }
+
+/// Returns the login policy for a group
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/groups/{id}/login_policy",
+    tag = "groups",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = GroupLoginPolicyResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/groups/{id}/login_policy")]
+pub async fn get_group_login_policy(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Groups, AccessRights::Read)?;
+
+    GroupLoginPolicy::find(&data, &id.into_inner())
+        .await
+        .map(|p| HttpResponse::Ok().json(GroupLoginPolicyResponse::from(p)))
+}
+
+/// Creates or replaces the login policy for a group
+///
+/// Members of this group will only be able to log in on the given weekdays inside the given time
+/// window. If a user is a member of multiple groups with a policy, each of them must allow the
+/// login. The UTC offset is fixed and does not follow daylight saving time.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/groups/{id}/login_policy",
+    tag = "groups",
+    request_body = GroupLoginPolicyRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = GroupLoginPolicyResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/groups/{id}/login_policy")]
+pub async fn put_group_login_policy(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: actix_web_validator::Json<GroupLoginPolicyRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Groups, AccessRights::Update)?;
+
+    GroupLoginPolicy::upsert(&data, id.into_inner(), payload.into_inner())
+        .await
+        .map(|p| HttpResponse::Ok().json(GroupLoginPolicyResponse::from(p)))
+}
+
+/// Deletes the login policy for a group
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/groups/{id}/login_policy",
+    tag = "groups",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/groups/{id}/login_policy")]
+pub async fn delete_group_login_policy(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Groups, AccessRights::Delete)?;
+
+    GroupLoginPolicy::delete(&data, id.into_inner())
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
@@ -0,0 +1,101 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, web, HttpResponse};
+use actix_web_validator::Query;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::jobs::Job;
+use rauthy_models::request::JobsParams;
+use rauthy_models::response::JobResponse;
+
+/// Returns all persistent background jobs
+///
+/// Successfully finished jobs are deleted immediately. Use `status=dead` to only get the dead
+/// letters, which exceeded `JOB_MAX_ATTEMPTS`. Job payloads are never returned.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    params(JobsParams),
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [JobResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/jobs")]
+pub async fn get_jobs(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    params: Query<JobsParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Read)?;
+
+    let jobs = Job::find_all(&data, params.status)
+        .await?
+        .into_iter()
+        .map(JobResponse::try_from)
+        .collect::<Result<Vec<_>, ErrorResponse>>()?;
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+/// Resets a job, so it will be executed again as soon as possible
+///
+/// This works for dead jobs as well as for pending ones with a long backoff.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/jobs/{id}/retry",
+    tag = "jobs",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/jobs/{id}/retry")]
+pub async fn post_job_retry(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Update)?;
+
+    Job::retry(&data, &id.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Deletes a job without executing it
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[delete("/jobs/{id}")]
+pub async fn delete_job(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Generic, AccessRights::Delete)?;
+
+    Job::delete(&data, &id.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,68 @@
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use actix_web_validator::Json;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::lab::LabMode;
+use rauthy_models::request::LabSeedRequest;
+use rauthy_models::response::LabPurgeResponse;
+
+/// Seed synthetic lab data
+///
+/// Creates or resets synthetic users and clients with deterministic credentials for security
+/// testing. Login delays and token rate limits are relaxed for requests with a valid `X-Debug`
+/// header.
+///
+/// Only available if `LAB_MODE_SECRET` is set. Any request without the correct `X-Debug` header
+/// will receive a `404`.
+///
+/// **Permissions**
+/// - `X-Debug: <LAB_MODE_SECRET>`
+#[utoipa::path(
+    post,
+    path = "/lab/seed",
+    tag = "lab",
+    request_body = LabSeedRequest,
+    responses(
+        (status = 200, description = "Ok", body = LabSeedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/lab/seed")]
+pub async fn post_lab_seed(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<LabSeedRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    LabMode::validate_request(&req)?;
+
+    let resp = LabMode::seed(&data, payload.users, payload.clients).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Purge synthetic lab data
+///
+/// Deletes all synthetic users and clients inside a single transaction. This happens
+/// automatically at startup as well, when `LAB_MODE_SECRET` is not set.
+///
+/// **Permissions**
+/// - `X-Debug: <LAB_MODE_SECRET>`
+#[utoipa::path(
+    delete,
+    path = "/lab/seed",
+    tag = "lab",
+    responses(
+        (status = 200, description = "Ok", body = LabPurgeResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/lab/seed")]
+pub async fn delete_lab_seed(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    LabMode::validate_request(&req)?;
+
+    let (users, clients) = LabMode::purge(&data).await?;
+    Ok(HttpResponse::Ok().json(LabPurgeResponse { users, clients }))
+}
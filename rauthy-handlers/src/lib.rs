@@ -9,21 +9,36 @@ use rauthy_models::entity::api_keys::ApiKey;
 use rauthy_models::entity::principal::Principal;
 use rauthy_models::entity::sessions::Session;
 use rauthy_models::entity::webauthn::WebauthnCookie;
-use rauthy_models::response::WebauthnLoginResponse;
+use rauthy_models::response::{
+    EmailMfaResponse, HeadlessLoginResponse, HeadlessLoginStep, PushMfaResponse,
+    UserAttrMissingResponse, UserAttrsMissingResponse, WebauthnLoginResponse,
+};
 use rauthy_models::AuthStep;
 use rust_embed::RustEmbed;
 use tracing::error;
 
+pub mod announcements;
 pub mod api_keys;
 pub mod auth_providers;
 pub mod blacklist;
+pub mod caches;
+pub mod cleanup;
 pub mod clients;
+pub mod cluster;
+pub mod email;
 pub mod events;
+pub mod feature_flags;
 pub mod generic;
 pub mod groups;
+pub mod jobs;
+pub mod lab;
+pub mod login_traces;
+pub mod logs;
 pub mod middleware;
 pub mod oidc;
 pub mod openapi;
+pub mod organizations;
+pub mod resource_servers;
 pub mod roles;
 pub mod scopes;
 pub mod sessions;
@@ -69,15 +84,54 @@ pub async fn map_auth_step(
                 resp.headers_mut().insert(name, value);
             }
 
-            // if there is no mfa_cookie present, set a new one
-            if let Ok(mfa_cookie) = WebauthnCookie::parse_validate(&req.cookie(COOKIE_MFA)) {
-                if mfa_cookie.email != res.email {
-                    add_req_mfa_cookie(&mut resp, res.email.clone()).map_err(|err| (err, true))?;
-                }
-            } else {
-                add_req_mfa_cookie(&mut resp, res.email.clone()).map_err(|err| (err, true))?;
+            add_missing_mfa_cookie(&mut resp, req, res.email).map_err(|err| (err, true))?;
+
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitEmailMfa(res) => {
+            let body = EmailMfaResponse {
+                code: res.code,
+                exp: res.exp,
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitPushMfa(res) => {
+            let body = PushMfaResponse {
+                code: res.code,
+                number: res.number,
+                exp: res.exp,
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
             }
+            Ok((resp, res.has_password_been_hashed))
+        }
 
+        AuthStep::AwaitProfile(res) => {
+            let body = UserAttrsMissingResponse {
+                missing_attrs: res
+                    .missing_attrs
+                    .into_iter()
+                    .map(UserAttrMissingResponse::from)
+                    .collect(),
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
             Ok((resp, res.has_password_been_hashed))
         }
 
@@ -88,6 +142,152 @@ pub async fn map_auth_step(
     }
 }
 
+/// The same as `map_auth_step`, but every step is returned as JSON for headless logins instead
+/// of being encoded in headers and status codes.
+pub async fn map_auth_step_headless(
+    auth_step: AuthStep,
+    req: &HttpRequest,
+) -> Result<(HttpResponse, bool), (ErrorResponse, bool)> {
+    match auth_step {
+        AuthStep::LoggedIn(res) => {
+            let location = res.header_loc.1.to_str().unwrap_or_default().to_string();
+            let mut resp =
+                HttpResponse::Ok()
+                    .insert_header(res.header_csrf)
+                    .json(HeadlessLoginResponse {
+                        next: HeadlessLoginStep::LoggedIn,
+                        location: Some(location),
+                        webauthn: None,
+                        email_mfa: None,
+                        push_mfa: None,
+                        missing_attrs: None,
+                    });
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitWebauthn(res) => {
+            let body = HeadlessLoginResponse {
+                next: HeadlessLoginStep::Webauthn,
+                location: None,
+                webauthn: Some(WebauthnLoginResponse {
+                    code: res.code,
+                    user_id: res.user_id,
+                    exp: res.exp,
+                }),
+                email_mfa: None,
+                push_mfa: None,
+                missing_attrs: None,
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+
+            add_missing_mfa_cookie(&mut resp, req, res.email).map_err(|err| (err, true))?;
+
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitEmailMfa(res) => {
+            let body = HeadlessLoginResponse {
+                next: HeadlessLoginStep::EmailMfa,
+                location: None,
+                webauthn: None,
+                email_mfa: Some(EmailMfaResponse {
+                    code: res.code,
+                    exp: res.exp,
+                }),
+                push_mfa: None,
+                missing_attrs: None,
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitPushMfa(res) => {
+            let body = HeadlessLoginResponse {
+                next: HeadlessLoginStep::PushMfa,
+                location: None,
+                webauthn: None,
+                email_mfa: None,
+                push_mfa: Some(PushMfaResponse {
+                    code: res.code,
+                    number: res.number,
+                    exp: res.exp,
+                }),
+                missing_attrs: None,
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::AwaitProfile(res) => {
+            let body = HeadlessLoginResponse {
+                next: HeadlessLoginStep::Profile,
+                location: None,
+                webauthn: None,
+                email_mfa: None,
+                push_mfa: None,
+                missing_attrs: Some(
+                    res.missing_attrs
+                        .into_iter()
+                        .map(UserAttrMissingResponse::from)
+                        .collect(),
+                ),
+            };
+            let mut resp = HttpResponse::Ok()
+                .insert_header(res.header_csrf)
+                .json(&body);
+            if let Some((name, value)) = res.header_origin {
+                resp.headers_mut().insert(name, value);
+            }
+            Ok((resp, res.has_password_been_hashed))
+        }
+
+        AuthStep::ProviderLink => Ok((
+            HttpResponse::Ok().json(HeadlessLoginResponse {
+                next: HeadlessLoginStep::ProviderLink,
+                location: None,
+                webauthn: None,
+                email_mfa: None,
+                push_mfa: None,
+                missing_attrs: None,
+            }),
+            false,
+        )),
+    }
+}
+
+/// Sets a new mfa_cookie, if none or one for another user is present.
+#[inline]
+fn add_missing_mfa_cookie(
+    resp: &mut HttpResponse,
+    req: &HttpRequest,
+    email: String,
+) -> Result<(), ErrorResponse> {
+    if let Ok(mfa_cookie) = WebauthnCookie::parse_validate(&req.cookie(COOKIE_MFA)) {
+        if mfa_cookie.email == email {
+            return Ok(());
+        }
+    }
+    add_req_mfa_cookie(resp, email)
+}
+
 #[inline]
 fn add_req_mfa_cookie(resp: &mut HttpResponse, email: String) -> Result<(), ErrorResponse> {
     let binding = WebauthnCookie::new(email);
@@ -0,0 +1,123 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, web, HttpResponse};
+use actix_web_validator::Json;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::login_traces::{LoginTrace, LoginTraceRecord};
+use rauthy_models::request::LoginTraceRequest;
+
+/// Returns all login traces
+///
+/// Expired traces will be returned as well, as long as their records are still available.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/login_traces",
+    tag = "login_traces",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [LoginTrace]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/login_traces")]
+pub async fn get_login_traces(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_session()?;
+
+    let traces = LoginTrace::find_all(&data).await?;
+    Ok(HttpResponse::Ok().json(traces))
+}
+
+/// Start a new login trace
+///
+/// While the trace is active, every step of the login flow (`POST /oidc/authorize`) for the
+/// given `email` and / or `ip` will be recorded. This includes parameter validations and
+/// policy decisions. Secrets like passwords or codes are never recorded.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/login_traces",
+    tag = "login_traces",
+    request_body = LoginTraceRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = LoginTrace),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/login_traces")]
+pub async fn post_login_trace(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    payload: Json<LoginTraceRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_session()?;
+
+    let trace = LoginTrace::create(&data, payload.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(trace))
+}
+
+/// Returns all recorded steps for a login trace
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/login_traces/{id}",
+    tag = "login_traces",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [LoginTraceRecord]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/login_traces/{id}")]
+pub async fn get_login_trace_records(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_session()?;
+
+    let records: Vec<LoginTraceRecord> = LoginTrace::find_records(&data, &id).await?;
+    Ok(HttpResponse::Ok().json(records))
+}
+
+/// Stops a login trace and deletes all its records
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/login_traces/{id}",
+    tag = "login_traces",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[delete("/login_traces/{id}")]
+pub async fn delete_login_trace(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_admin_session()?;
+
+    LoginTrace::delete(&data, &id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,88 @@
+use crate::ReqPrincipal;
+use actix_web::{get, web, Responder};
+use actix_web_lab::sse;
+use rauthy_common::constants::{LOG_STREAM_ENABLE, SSE_KEEP_ALIVE};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::log_stream;
+use rauthy_models::request::LogsListenParams;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use validator::Validate;
+
+/// Tail the application logs of this instance via SSE
+///
+/// Only logs at the configured `LOG_LEVEL` and above are available. Values of known secret
+/// parameters like passwords or tokens are redacted. In an HA deployment, each node only
+/// streams its own logs.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/logs/stream",
+    tag = "logs",
+    params(LogsListenParams),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/logs/stream")]
+pub async fn sse_logs(
+    principal: ReqPrincipal,
+    params: web::Query<LogsListenParams>,
+) -> Result<impl Responder, ErrorResponse> {
+    principal.validate_admin_session()?;
+    params.validate()?;
+
+    if !*LOG_STREAM_ENABLE {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The log stream is disabled".to_string(),
+        ));
+    }
+
+    let params = params.into_inner();
+    let level = params.level.unwrap_or_default();
+    let (lines, mut rx_logs) = log_stream::subscribe(params.latest.unwrap_or(0) as usize);
+    let (tx, rx) = mpsc::channel(10);
+
+    tokio::spawn(async move {
+        for line in lines.into_iter().filter(|l| l.level <= level) {
+            let data = sse::Data::new_json(&line).expect("LogLine to always serialize");
+            if tx.send(sse::Event::Data(data)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            match rx_logs.recv().await {
+                Ok(line) => {
+                    if line.level > level {
+                        continue;
+                    }
+                    let data = sse::Data::new_json(&line).expect("LogLine to always serialize");
+                    if tx.send(sse::Event::Data(data)).await.is_err() {
+                        // client has disconnected
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let comment = sse::Event::Comment(format!("skipped {} lines", skipped).into());
+                    if tx.send(comment).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+
+    Ok(sse::Sse::from_infallible_receiver(rx)
+        .with_keep_alive(Duration::from_secs(*SSE_KEEP_ALIVE as u64))
+        .with_retry_duration(Duration::from_secs(10)))
+}
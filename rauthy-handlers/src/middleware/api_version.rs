@@ -0,0 +1,237 @@
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
+use actix_web::http::uri::PathAndQuery;
+use actix_web::http::Uri;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use chrono::DateTime;
+use futures::future::LocalBoxFuture;
+use rauthy_common::constants::{API_V1_DEPRECATED_SINCE, API_V1_SUNSET};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+const HEADER_API_VERSION: HeaderName = HeaderName::from_static("rauthy-api-version");
+const HEADER_DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const HEADER_SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// All routes below `/auth/v2`, which have a dedicated v2 handler. Every other `/auth/v2` request
+/// will be served by the v1 handler, which makes it possible to move single endpoints to v2 with
+/// breaking changes without duplicating the whole API.
+///
+/// The routes are matched as prefixes of the path after `/auth/v2`.
+const V2_ROUTES: &[&str] = &[];
+
+/// Single endpoints, which are deprecated independently of the whole API version.
+const DEPRECATED_ENDPOINTS: &[DeprecatedEndpoint] = &[DeprecatedEndpoint {
+    method: "POST",
+    path: "/auth/v1/oidc/token/validate",
+    // 2024-01-01
+    since: 1704067200,
+    sunset: None,
+    successor: Some("/auth/v1/oidc/userinfo"),
+}];
+
+struct DeprecatedEndpoint {
+    method: &'static str,
+    path: &'static str,
+    since: i64,
+    sunset: Option<i64>,
+    successor: Option<&'static str>,
+}
+
+/// The API version of a request, which is negotiated via the `/auth/{version}` path prefix.
+///
+/// Can be used as an extractor inside handlers, which need to behave differently depending on the
+/// requested version. Requests without a version prefix resolve to `V1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const LATEST: Self = Self::V2;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1 => "1",
+            Self::V2 => "2",
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        let rest = path.strip_prefix("/auth/")?;
+        let (version, _) = rest.split_once('/').unwrap_or((rest, ""));
+        match version {
+            "v1" => Some(Self::V1),
+            "v2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+impl FromRequest for ApiVersion {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let version = req
+            .extensions()
+            .get::<ApiVersion>()
+            .copied()
+            .unwrap_or(ApiVersion::V1);
+        ready(Ok(version))
+    }
+}
+
+/// Resolves the requested [ApiVersion] and routes `/auth/v2` requests without a dedicated v2
+/// handler to the v1 handler. Responses to v1 requests get `Deprecation` (RFC 9745) and
+/// `Sunset` (RFC 8594) headers, if the endpoint or the whole v1 API has been deprecated.
+pub struct RauthyApiVersionMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RauthyApiVersionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ApiVersionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiVersionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ApiVersionMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiVersionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let Some(version) = ApiVersion::from_path(req.path()) else {
+                return service.call(req).await;
+            };
+            req.extensions_mut().insert(version);
+
+            let method = req.method().clone();
+            let path = req.path().to_string();
+            if version == ApiVersion::V2 && !has_v2_route(&path) {
+                rewrite_to_v1(&mut req);
+            }
+
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+            headers.insert(
+                HEADER_API_VERSION,
+                HeaderValue::from_static(version.as_str()),
+            );
+
+            if version == ApiVersion::V1 {
+                if let Some(ep) = DEPRECATED_ENDPOINTS
+                    .iter()
+                    .find(|ep| ep.method == method.as_str() && ep.path == path)
+                {
+                    insert_deprecation(
+                        headers,
+                        ep.since,
+                        ep.sunset,
+                        ep.successor.map(String::from),
+                    );
+                } else if let Some(since) = *API_V1_DEPRECATED_SINCE {
+                    let successor = path.replacen("/auth/v1", "/auth/v2", 1);
+                    insert_deprecation(headers, since, *API_V1_SUNSET, Some(successor));
+                }
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[inline]
+fn has_v2_route(path: &str) -> bool {
+    let route = path.strip_prefix("/auth/v2").unwrap_or_default();
+    V2_ROUTES.iter().any(|r| route.starts_with(r))
+}
+
+fn rewrite_to_v1(req: &mut ServiceRequest) {
+    let path_and_query = req
+        .head()
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or_default()
+        .replacen("/auth/v2", "/auth/v1", 1);
+    let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) else {
+        return;
+    };
+
+    let mut parts = req.head().uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+}
+
+fn insert_deprecation(
+    headers: &mut actix_web::http::header::HeaderMap,
+    since: i64,
+    sunset: Option<i64>,
+    successor: Option<String>,
+) {
+    if let Ok(value) = HeaderValue::from_str(&format!("@{}", since)) {
+        headers.insert(HEADER_DEPRECATION, value);
+    }
+
+    if let Some(dt) = sunset.and_then(|ts| DateTime::from_timestamp(ts, 0)) {
+        let http_date = dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(value) = HeaderValue::from_str(&http_date) {
+            headers.insert(HEADER_SUNSET, value);
+        }
+    }
+
+    if let Some(successor) = successor {
+        let link = format!("<{}>; rel=\"successor-version\"", successor);
+        if let Ok(value) = HeaderValue::from_str(&link) {
+            headers.append(LINK, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_version_from_path() {
+        assert_eq!(
+            ApiVersion::from_path("/auth/v1/users"),
+            Some(ApiVersion::V1)
+        );
+        assert_eq!(ApiVersion::from_path("/auth/v2"), Some(ApiVersion::V2));
+        assert_eq!(ApiVersion::from_path("/auth/v3/users"), None);
+        assert_eq!(ApiVersion::from_path("/auth/webid/123"), None);
+        assert_eq!(ApiVersion::from_path("/.well-known/openid"), None);
+        assert!(ApiVersion::V1 < ApiVersion::LATEST);
+    }
+}
@@ -0,0 +1,100 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use rauthy_common::constants::COMPRESSION_MIN_SIZE;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Responses for these paths are never compressed.
+///
+/// The token endpoint returns secrets while reflecting parts of the request, which would make
+/// it a target for compression side channel attacks like BREACH.
+static EXCLUDED_PATHS: [&str; 1] = ["/auth/v1/oidc/token"];
+
+/// Decides which responses the outer `Compress` middleware may encode.
+///
+/// Only JSON and HTML bodies above `COMPRESSION_MIN_SIZE` will be compressed. Everything else
+/// gets a `content-encoding: identity`, which makes `Compress` skip the response.
+/// Event streams are always excluded, since the encoder would buffer single events.
+pub struct RauthyCompressionMiddleware;
+
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for RauthyCompressionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CompressionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CompressionMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let excluded = EXCLUDED_PATHS.contains(&req.path());
+            let mut res = service.call(req).await?;
+
+            // pre-compressed static assets already have their encoding set
+            if res.headers().contains_key(CONTENT_ENCODING) {
+                return Ok(res);
+            }
+
+            if excluded || !is_compressible(&res) {
+                res.headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[inline]
+fn is_compressible<B: MessageBody>(res: &ServiceResponse<B>) -> bool {
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.contains("json") && !content_type.starts_with("text/html") {
+        return false;
+    }
+
+    match res.response().body().size() {
+        BodySize::Sized(size) => size >= *COMPRESSION_MIN_SIZE,
+        BodySize::Stream => true,
+        BodySize::None => false,
+    }
+}
@@ -8,6 +8,7 @@ use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::real_ip_from_svc_req;
 use rauthy_models::app_state::AppState;
 use rauthy_models::events::ip_blacklist_handler::{IpBlacklistCheck, IpBlacklistReq};
+use rauthy_models::language::Language;
 use rauthy_models::templates::TooManyRequestsHtml;
 use std::future::{ready, Ready};
 use std::rc::Rc;
@@ -76,9 +77,10 @@ where
                         if let Some(exp) = exp {
                             if exp > Utc::now() {
                                 let ts = exp.timestamp();
+                                let lang = Language::try_from(req.request()).unwrap_or_default();
                                 return Err(Error::from(ErrorResponse::new(
                                     ErrorResponseType::TooManyRequests(ts),
-                                    TooManyRequestsHtml::build(&ip, ts),
+                                    TooManyRequestsHtml::build_for_lang(&lang, &ip, ts),
                                 )));
                             }
                         }
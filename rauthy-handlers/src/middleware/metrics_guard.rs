@@ -0,0 +1,137 @@
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use rauthy_common::constants::{METRICS_ALLOWED_CIDRS, METRICS_AUTH_BASIC, METRICS_AUTH_BEARER};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_encode, constant_time_eq};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use tracing::warn;
+
+/// Protects the internal metrics server with optional source CIDRs and bearer / basic auth.
+pub struct RauthyMetricsGuardMiddleware {
+    expected_headers: Rc<Vec<String>>,
+}
+
+impl Default for RauthyMetricsGuardMiddleware {
+    fn default() -> Self {
+        let mut expected_headers = Vec::with_capacity(2);
+        if let Some(token) = METRICS_AUTH_BEARER.as_deref() {
+            expected_headers.push(format!("Bearer {}", token));
+        }
+        if let Some(creds) = METRICS_AUTH_BASIC.as_deref() {
+            expected_headers.push(format!("Basic {}", base64_encode(creds.as_bytes())));
+        }
+
+        Self {
+            expected_headers: Rc::new(expected_headers),
+        }
+    }
+}
+
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for RauthyMetricsGuardMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = MetricsGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MetricsGuardMiddleware {
+            service: Rc::new(service),
+            expected_headers: self.expected_headers.clone(),
+        }))
+    }
+}
+
+pub struct MetricsGuardMiddleware<S> {
+    service: Rc<S>,
+    expected_headers: Rc<Vec<String>>,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let expected_headers = Rc::clone(&self.expected_headers);
+
+        Box::pin(async move {
+            check_source(&req)?;
+            check_auth(&req, &expected_headers)?;
+            service.call(req).await
+        })
+    }
+}
+
+fn check_source(req: &ServiceRequest) -> Result<(), ErrorResponse> {
+    if METRICS_ALLOWED_CIDRS.is_empty() {
+        return Ok(());
+    }
+
+    // we explicitly do not care about any proxy headers here, scrapers connect directly
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    if let Some(ip) = peer_ip {
+        if METRICS_ALLOWED_CIDRS.iter().any(|cidr| cidr.contains(&ip)) {
+            return Ok(());
+        }
+    }
+
+    warn!(
+        "Blocked metrics request from not allowed source {:?}",
+        peer_ip
+    );
+    Err(ErrorResponse::new(
+        ErrorResponseType::Forbidden,
+        "Source IP not allowed".to_string(),
+    ))
+}
+
+fn check_auth(req: &ServiceRequest, expected_headers: &[String]) -> Result<(), ErrorResponse> {
+    if expected_headers.is_empty() {
+        return Ok(());
+    }
+
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    // check against all values to not leak which one has been configured via timings
+    let valid = expected_headers.iter().fold(false, |valid, expected| {
+        valid | constant_time_eq(given, expected)
+    });
+
+    if valid {
+        Ok(())
+    } else {
+        let challenge = if METRICS_AUTH_BASIC.is_some() {
+            "Basic realm=\"metrics\""
+        } else {
+            "Bearer"
+        };
+        Err(ErrorResponse::new(
+            ErrorResponseType::WWWAuthenticate(challenge.to_string()),
+            "Missing or invalid metrics credentials".to_string(),
+        ))
+    }
+}
@@ -1,3 +1,9 @@
+pub mod api_version;
+pub mod compression;
 pub mod ip_blacklist;
 pub mod logging;
+pub mod metrics_guard;
 pub mod principal;
+pub mod problem_json;
+pub mod read_only;
+pub mod request_limits;
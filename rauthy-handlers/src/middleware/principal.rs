@@ -3,7 +3,9 @@ use actix_web::{
     http, web, Error, HttpMessage,
 };
 use futures::future::LocalBoxFuture;
-use rauthy_common::constants::{COOKIE_SESSION, SESSION_VALIDATE_IP, TOKEN_API_KEY};
+use rauthy_common::constants::{
+    COOKIE_SESSION, LISTEN_PORT_ADMIN, SESSION_VALIDATE_IP, TOKEN_API_KEY,
+};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::real_ip_from_svc_req;
 use rauthy_models::app_state::AppState;
@@ -71,6 +73,10 @@ where
                 principal.session = Some(s);
             }
 
+            principal.admin_api_blocked = LISTEN_PORT_ADMIN
+                .map(|port| req.app_config().local_addr().port() != port)
+                .unwrap_or(false);
+
             req.extensions_mut().insert(principal);
 
             service.call(req).await
@@ -167,5 +173,7 @@ async fn get_session_from_cookie(
 // CSRF MUST BE CHECKED FOR THESE EXCEPTIONS MANUALLY !
 #[inline(always)]
 fn is_path_csrf_exception(path: &str) -> bool {
-    path.ends_with("/webauthn/register/start") || path.ends_with("/webauthn/register/finish")
+    path.ends_with("/webauthn/register/start")
+        || path.ends_with("/webauthn/register/finish")
+        || path.ends_with("/oidc/token/session")
 }
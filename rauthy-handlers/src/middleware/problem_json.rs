@@ -0,0 +1,98 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::http::header::ACCEPT;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use rauthy_common::constants::{APPLICATION_PROBLEM_JSON, ERROR_PROBLEM_JSON};
+use rauthy_common::error_response::ErrorResponse;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Converts all [ErrorResponse]s into RFC 9457 `application/problem+json` responses, if
+/// `ERROR_PROBLEM_JSON` is set or the client asks for it via the `Accept` header.
+pub struct RauthyProblemJsonMiddleware;
+
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for RauthyProblemJsonMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ProblemJsonMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ProblemJsonMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ProblemJsonMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ProblemJsonMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if !*ERROR_PROBLEM_JSON && !accepts_problem_json(&req) {
+                return service.call(req).await.map(|res| res.map_into_left_body());
+            }
+
+            let http_req = req.request().clone();
+            match service.call(req).await {
+                Ok(res) => {
+                    let problem = res
+                        .response()
+                        .error()
+                        .and_then(|err| err.as_error::<ErrorResponse>())
+                        .and_then(|err| err.problem_json_response(Some(http_req.path())));
+                    match problem {
+                        Some(problem) => Ok(res.into_response(problem).map_into_right_body()),
+                        None => Ok(res.map_into_left_body()),
+                    }
+                }
+
+                // errors returned by inner middlewares did not go through a handler yet
+                Err(err) => match err
+                    .as_error::<ErrorResponse>()
+                    .and_then(|err| err.problem_json_response(Some(http_req.path())))
+                {
+                    Some(problem) => {
+                        Ok(ServiceResponse::new(http_req, problem).map_into_right_body())
+                    }
+                    None => Err(err),
+                },
+            }
+        })
+    }
+}
+
+#[inline]
+fn accepts_problem_json(req: &ServiceRequest) -> bool {
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains(APPLICATION_PROBLEM_JSON))
+        .unwrap_or(false)
+}
@@ -0,0 +1,103 @@
+use actix_web::http::Method;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use rauthy_common::constants::READ_ONLY_MODE;
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Endpoints which are served with `GET` in read-only mode.
+static READ_ONLY_GET: [&str; 9] = [
+    "/.well-known/openid-configuration",
+    "/auth/v1/.well-known/openid-configuration",
+    "/auth/v1/oidc/certs",
+    "/auth/v1/oidc/forward_auth",
+    "/auth/v1/oidc/userinfo",
+    "/auth/v1/health",
+    "/auth/v1/ping",
+    "/auth/v1/ready",
+    "/auth/v1/version",
+];
+
+/// Endpoints which are served with `POST` in read-only mode. These only validate tokens.
+static READ_ONLY_POST: [&str; 4] = [
+    "/auth/v1/oidc/introspect",
+    "/auth/v1/oidc/sidInfo",
+    "/auth/v1/oidc/tokenInfo",
+    "/auth/v1/oidc/token/validate",
+];
+
+/// Rejects everything apart from discovery, JWKS, userinfo and token validation when running
+/// with `MODE=readonly`.
+pub struct RauthyReadOnlyMiddleware;
+
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for RauthyReadOnlyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ReadOnlyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReadOnlyMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ReadOnlyMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ReadOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if *READ_ONLY_MODE && !is_read_only_allowed(req.method(), req.path()) {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Forbidden,
+                    "This instance runs in read-only mode".to_string(),
+                )
+                .with_code(ErrorCode::ReadOnlyMode)
+                .into());
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+#[inline]
+fn is_read_only_allowed(method: &Method, path: &str) -> bool {
+    match *method {
+        // CORS preflight requests
+        Method::OPTIONS => true,
+        Method::GET | Method::HEAD => {
+            READ_ONLY_GET.contains(&path) || path.starts_with("/auth/v1/oidc/certs/")
+        }
+        Method::POST => READ_ONLY_POST.contains(&path),
+        _ => false,
+    }
+}
@@ -0,0 +1,144 @@
+use actix_web::dev::Payload;
+use actix_web::error::PayloadError;
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use rauthy_common::constants::{
+    PAYLOAD_LIMIT_AUTH, PAYLOAD_LIMIT_DEFAULT, PAYLOAD_LIMIT_UPLOAD, REQUEST_TIMEOUT_SECS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Duration;
+use tracing::warn;
+
+/// These endpoints may legitimately run longer than `REQUEST_TIMEOUT_SECS`.
+static TIMEOUT_EXCLUDED_PATHS: [&str; 1] = ["/auth/v1/encryption/migrate"];
+
+/// Enforces a maximum body size depending on the endpoint class and a timeout for the whole
+/// handler execution, including reading the request body.
+///
+/// Event streams are not affected by the timeout, since it only applies until the response
+/// head has been created.
+pub struct RauthyRequestLimitsMiddleware;
+
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for RauthyRequestLimitsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestLimitsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLimitsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestLimitsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLimitsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            limit_payload(&mut req)?;
+
+            if *REQUEST_TIMEOUT_SECS == 0 || TIMEOUT_EXCLUDED_PATHS.contains(&req.path()) {
+                return service.call(req).await;
+            }
+
+            let path = req.path().to_string();
+            match tokio::time::timeout(
+                Duration::from_secs(*REQUEST_TIMEOUT_SECS),
+                service.call(req),
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(_) => {
+                    warn!("Request timeout for {}", path);
+                    Err(ErrorResponse::new(
+                        ErrorResponseType::RequestTimeout,
+                        "Request timeout".to_string(),
+                    )
+                    .into())
+                }
+            }
+        })
+    }
+}
+
+/// Returns the max allowed body size in bytes for the given request path.
+#[inline]
+fn payload_limit(path: &str) -> usize {
+    if path.starts_with("/auth/v1/oidc/") {
+        *PAYLOAD_LIMIT_AUTH
+    } else if path.ends_with("/logo") || path.ends_with("/img") {
+        *PAYLOAD_LIMIT_UPLOAD
+    } else {
+        *PAYLOAD_LIMIT_DEFAULT
+    }
+}
+
+fn limit_payload(req: &mut ServiceRequest) -> Result<(), ErrorResponse> {
+    let limit = payload_limit(req.path());
+
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(len) = content_length {
+        return if len > limit {
+            Err(ErrorResponse::new(
+                ErrorResponseType::PayloadTooLarge,
+                format!("Payload too large, max allowed size: {} bytes", limit),
+            ))
+        } else {
+            Ok(())
+        };
+    }
+
+    // without a content-length, we need to count the bytes while the body is being read
+    let mut read = 0;
+    let limited = req.take_payload().map(move |chunk| {
+        let chunk = chunk?;
+        read += chunk.len();
+        if read > limit {
+            Err(PayloadError::Overflow)
+        } else {
+            Ok(chunk)
+        }
+    });
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(limited),
+    });
+
+    Ok(())
+}
@@ -1,62 +1,90 @@
-use crate::{map_auth_step, ReqPrincipal};
+use crate::{map_auth_step, map_auth_step_headless, ReqPrincipal};
 use actix_web::cookie::time::OffsetDateTime;
 use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
 use actix_web::http::{header, StatusCode};
-use actix_web::{get, post, web, HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError};
+use actix_web::{
+    get, post, put, route, routes, web, HttpRequest, HttpResponse, HttpResponseBuilder,
+    ResponseError,
+};
 use chrono::Utc;
 use rauthy_common::constants::{
     APPLICATION_JSON, AUTH_HEADERS_ENABLE, AUTH_HEADER_EMAIL, AUTH_HEADER_EMAIL_VERIFIED,
     AUTH_HEADER_FAMILY_NAME, AUTH_HEADER_GIVEN_NAME, AUTH_HEADER_GROUPS, AUTH_HEADER_MFA,
     AUTH_HEADER_ROLES, AUTH_HEADER_USER, COOKIE_MFA, DEVICE_GRANT_CODE_LIFETIME,
     DEVICE_GRANT_POLL_INTERVAL, DEVICE_GRANT_RATE_LIMIT, GRANT_TYPE_DEVICE_CODE, HEADER_HTML,
-    HEADER_RETRY_NOT_BEFORE, OPEN_USER_REG, SESSION_LIFETIME,
+    HEADER_RETRY_NOT_BEFORE, HEADLESS_LOGIN_POW, OPEN_USER_REG, SESSION_LIFETIME, TOKEN_RATE_LIMIT,
+    TOKEN_USER_AGENT_STATS, USERNAME_LOGIN_ENABLE,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::real_ip_from_req;
+use rauthy_common::rate_limit;
+use rauthy_common::utils::{is_trusted_login_ip, real_ip_from_req};
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::auth_codes::{JarmClaims, JarmFormPost};
 use rauthy_models::entity::auth_providers::AuthProviderTemplate;
+use rauthy_models::entity::client_user_agents::ClientUserAgent;
 use rauthy_models::entity::clients::Client;
 use rauthy_models::entity::colors::ColorEntity;
 use rauthy_models::entity::devices::DeviceAuthCode;
+use rauthy_models::entity::email_mfa::{EmailMfaCode, EmailMfaPurpose};
 use rauthy_models::entity::ip_rate_limit::DeviceIpRateLimit;
-use rauthy_models::entity::jwk::{JWKSPublicKey, JwkKeyPair, JWKS};
+use rauthy_models::entity::jwk::{JWKSPublicKey, Jwk, JwkKeyPair, JWKS};
+use rauthy_models::entity::lab::LabMode;
 use rauthy_models::entity::pow::PowEntity;
+use rauthy_models::entity::push_mfa::{PushMfaRequest, PushMfaStatus};
+use rauthy_models::entity::request_objects::RequestObject;
+use rauthy_models::entity::resource_servers::ResourceServer;
+use rauthy_models::entity::scopes::Scope;
 use rauthy_models::entity::sessions::Session;
 use rauthy_models::entity::users::User;
-use rauthy_models::entity::webauthn::WebauthnCookie;
+use rauthy_models::entity::webauthn::{WebauthnAdditionalData, WebauthnCookie, WebauthnLoginReq};
 use rauthy_models::entity::well_known::WellKnown;
+use rauthy_models::events::event::Event;
 use rauthy_models::language::Language;
 use rauthy_models::request::{
     AuthRequest, DeviceAcceptedRequest, DeviceGrantRequest, DeviceVerifyRequest,
-    LoginRefreshRequest, LoginRequest, LogoutRequest, TokenRequest, TokenValidationRequest,
+    EmailMfaVerifyRequest, FormPostJwtRequest, HeadlessLoginRequest, IntrospectionRequest,
+    JwkActivationRequest, JwkImportRequest, LoginRefreshRequest, LoginRequest, LogoutRequest,
+    PushMfaLoginRequest, ResponseMode, SessionTokenRequest, SidInfoRequest,
+    SsoHandoffConfirmRequest, SsoHandoffParams, SsoHandoffRequest, TokenRequest,
+    TokenValidationRequest,
 };
 use rauthy_models::response::{
-    DeviceCodeResponse, DeviceVerifyResponse, JWKSCerts, JWKSPublicKeyCerts, OAuth2ErrorResponse,
-    OAuth2ErrorTypeResponse, SessionInfoResponse,
+    DeviceCodeResponse, DeviceVerifyResponse, HeadlessAuthorizeResponse, HeadlessLoginStep,
+    JWKSCerts, JWKSPublicKeyCerts, OAuth2ErrorResponse, OAuth2ErrorTypeResponse,
+    SessionInfoResponse,
 };
 use rauthy_models::templates::{
-    AuthorizeHtml, CallbackHtml, Error1Html, ErrorHtml, FrontendAction,
+    AuthorizeHtml, CallbackHtml, Error1Html, ErrorHtml, FormPostHtml, FrontendAction,
 };
-use rauthy_models::JwtCommonClaims;
-use rauthy_service::auth;
+use rauthy_models::{AuthorizationDetail, JwtCommonClaims};
+use rauthy_service::{auth, sso_handoff};
 use spow::pow::Pow;
 use std::borrow::Cow;
 use std::ops::Add;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, error};
 
+const ERR_PROMPT_CREATE: &str =
+    "invalid_request: 'prompt=create' is not supported when the open user registration is disabled";
+
 /// OIDC Authorization HTML
 ///
 /// Starts the authorization_code flow. Log in with username / password.<br>
 /// If one does not exist, a new session will be opened with the 'Init' state and set's a cookie.
+/// With `prompt=create`, the user will be redirected to the registration and gets back to this
+/// authorization request after the password has been set.
+/// A request object (JAR) given with `request` or `request_uri` replaces all other params. The
+/// request will be redirected to the same endpoint with all params from the request object.
 #[utoipa::path(
     get,
     path = "/oidc/authorize",
     tag = "oidc",
     params(AuthRequest),
+    security((), ("session" = [])),
     responses(
         (status = 200, description = "If the params match the allowed settings, returns the pre-rendered HTML",),
+        (status = 302, description = "Redirect to the user registration with `prompt=create`, to the expanded request object or the signed error response for JWT response modes",),
         (status = 400, description = "If any params do not match the backend config", body = ErrorResponse),
     ),
 )]
@@ -72,78 +100,116 @@ pub async fn get_authorize(
         .unwrap_or_default();
     let lang = Language::try_from(&req).unwrap_or_default();
 
-    let (client, origin_header) = match auth::validate_auth_req_param(
-        &data,
-        &req,
-        &req_data.client_id,
-        &req_data.redirect_uri,
-        &req_data.code_challenge,
-        &req_data.code_challenge_method,
-    )
-    .await
-    {
+    // a request object replaces all query params, which are expanded with a redirect first
+    let redirect_uri = match RequestObject::expand(&data, &req_data, "/oidc/authorize").await {
+        Ok(Some(location)) => {
+            return Ok(HttpResponse::Found()
+                .insert_header((header::LOCATION, location))
+                .finish());
+        }
+        Ok(None) => req_data.get_redirect_uri(),
+        Err(err) => Err(err),
+    };
+    let res = match redirect_uri {
+        Ok(redirect_uri) => {
+            auth::validate_auth_req_param(
+                &data,
+                &req,
+                &req_data.client_id,
+                redirect_uri,
+                &req_data.code_challenge,
+                &req_data.code_challenge_method,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    let (client, origin_header) = match res {
         Ok(res) => res,
         Err(err) => {
             let status = err.status_code();
-            let body = Error1Html::build(&colors, &lang, status, Some(err.message));
+            let body = Error1Html::build_for_err(&colors, &lang, &err);
             return Ok(ErrorHtml::response(body, status));
         }
     };
-
-    // check prompt and max_age to possibly force a new session
-    let mut force_new_session = if req_data
-        .prompt
-        .as_ref()
-        .map(|p| p.as_str() == "login")
-        .unwrap_or(false)
+    // an unknown `resource` should fail before the user logs in
+    if let Err(err) =
+        ResourceServer::find_for_request(&data, req_data.resource.as_deref(), &client.id).await
     {
-        true
-    } else if let Some(max_age) = req_data.max_age {
-        if let Some(session) = &principal.session {
-            let session_created = session.exp - *SESSION_LIFETIME as i64;
-            Utc::now().timestamp() > session_created + max_age
-        } else {
-            true
+        if let Some(resp) = jarm_error(&data, &client, &req_data, "invalid_target", &err).await {
+            return Ok(resp);
         }
-    } else {
-        false
-    };
+        let status = err.status_code();
+        let body = Error1Html::build_for_err(&colors, &lang, &err);
+        return Ok(ErrorHtml::response(body, status));
+    }
+    if let Err(err) =
+        AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref())
+    {
+        let error = "invalid_authorization_details";
+        if let Some(resp) = jarm_error(&data, &client, &req_data, error, &err).await {
+            return Ok(resp);
+        }
+        let status = err.status_code();
+        let body = Error1Html::build_for_err(&colors, &lang, &err);
+        return Ok(ErrorHtml::response(body, status));
+    }
 
-    // check if the user needs to do the Webauthn login each time
-    let mut action = FrontendAction::None;
-    if let Ok(mfa_cookie) = WebauthnCookie::parse_validate(&req.cookie(COOKIE_MFA)) {
-        if let Ok(user) = User::find_by_email(&data, mfa_cookie.email.clone()).await {
-            // we need to check this, because a user could deactivate MFA in another browser or
-            // be deleted while still having existing mfa cookies somewhere else
-            if user.has_webauthn_enabled() {
-                action = FrontendAction::MfaLogin(mfa_cookie.email);
-
-                // if the user must do another MFA login anyway, we do never force a new session creation,
-                // because the authentication happens each time anyway
-                force_new_session = false;
+    // the client wants the user to register first and come back here afterward
+    if req_data.is_prompt_create() {
+        if !*OPEN_USER_REG {
+            let err =
+                ErrorResponse::new(ErrorResponseType::BadRequest, ERR_PROMPT_CREATE.to_string());
+            if let Some(resp) = jarm_error(&data, &client, &req_data, "invalid_request", &err).await
+            {
+                return Ok(resp);
             }
+            let status = StatusCode::BAD_REQUEST;
+            let body =
+                Error1Html::build(&colors, &lang, status, Some(ERR_PROMPT_CREATE.to_string()));
+            return Ok(ErrorHtml::response(body, status));
         }
+        let location = AuthRequest::registration_uri(&data.issuer, req.query_string())?;
+        return Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, location))
+            .finish());
+    }
+
+    let mut force_new_session = is_new_session_forced(&req_data, &principal);
+
+    // check if the user needs to do the Webauthn login each time
+    let mut action = FrontendAction::None;
+    if let Some(email) = mfa_login_email(&data, &req).await {
+        action = FrontendAction::MfaLogin(email);
+
+        // if the user must do another MFA login anyway, we do never force a new session creation,
+        // because the authentication happens each time anyway
+        force_new_session = false;
     }
 
     // check for no-prompt
-    if !force_new_session
-        && req_data
-            .prompt
-            .as_ref()
-            .map(|p| p.as_str() == "none")
-            .unwrap_or(false)
-    {
+    if !force_new_session && is_prompt_none(&req_data) {
+        let err = ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "login_required".to_string(),
+        );
+        if let Some(resp) = jarm_error(&data, &client, &req_data, "login_required", &err).await {
+            return Ok(resp);
+        }
         let status = StatusCode::UNAUTHORIZED;
         let body = Error1Html::build(&colors, &lang, status, Some("login_required".to_string()));
         return Ok(ErrorHtml::response(body, status));
     }
 
     let auth_providers_json = AuthProviderTemplate::get_all_json_template(&data).await?;
+    let scopes_json = Scope::login_i18n_json(&data, &client, &req_data.scope, &lang).await?;
     let tpl_data = Some(format!(
-        "{}\n{}\n{}",
+        "{}\n{}\n{}\n{}\n{}",
         client.name.unwrap_or_default(),
         client.client_uri.unwrap_or_default(),
         *OPEN_USER_REG,
+        *USERNAME_LOGIN_ENABLE,
+        scopes_json,
     ));
 
     // if the user is still authenticated and everything is valid -> immediate refresh
@@ -170,7 +236,7 @@ pub async fn get_authorize(
     let session = Session::new(*SESSION_LIFETIME, real_ip_from_req(&req));
     if let Err(err) = session.save(&data).await {
         let status = err.status_code();
-        let body = Error1Html::build(&colors, &lang, status, Some(err.message));
+        let body = Error1Html::build_for_err(&colors, &lang, &err);
         return Ok(ErrorHtml::response(body, status));
     }
 
@@ -198,6 +264,264 @@ pub async fn get_authorize(
         .body(body))
 }
 
+/// Clients, which requested a JWT secured `response_mode`, get errors after the `redirect_uri`
+/// has been validated as a signed response as well. Returns `None` for all other modes, which
+/// keep showing the error page.
+async fn jarm_error(
+    data: &web::Data<AppState>,
+    client: &Client,
+    req_data: &AuthRequest,
+    error: &str,
+    err: &ErrorResponse,
+) -> Option<HttpResponse> {
+    let response_mode = req_data.response_mode.unwrap_or_default();
+    if response_mode == ResponseMode::Query {
+        return None;
+    }
+
+    let location = JarmClaims::error_location(
+        data,
+        client,
+        req_data.get_redirect_uri().ok()?,
+        req_data.state.clone(),
+        response_mode,
+        error,
+        Some(err.message.clone()),
+    )
+    .await;
+    match location {
+        Ok(location) => Some(
+            HttpResponse::Found()
+                .insert_header((header::LOCATION, location))
+                .finish(),
+        ),
+        Err(err) => {
+            error!("Error building the JARM error response: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Checks prompt and max_age to possibly force a new session
+fn is_new_session_forced(req_data: &AuthRequest, principal: &ReqPrincipal) -> bool {
+    if req_data
+        .prompt
+        .as_ref()
+        .map(|p| p.as_str() == "login")
+        .unwrap_or(false)
+    {
+        true
+    } else if let Some(max_age) = req_data.max_age {
+        if let Some(session) = &principal.session {
+            let session_created = session.exp - *SESSION_LIFETIME as i64;
+            Utc::now().timestamp() > session_created + max_age
+        } else {
+            true
+        }
+    } else {
+        false
+    }
+}
+
+#[inline]
+fn is_prompt_none(req_data: &AuthRequest) -> bool {
+    req_data
+        .prompt
+        .as_ref()
+        .map(|p| p.as_str() == "none")
+        .unwrap_or(false)
+}
+
+/// Returns the E-Mail from a valid mfa_cookie, if the user needs to do the Webauthn login.
+async fn mfa_login_email(data: &web::Data<AppState>, req: &HttpRequest) -> Option<String> {
+    let mfa_cookie = WebauthnCookie::parse_validate(&req.cookie(COOKIE_MFA)).ok()?;
+    let user = User::find_by_email(data, mfa_cookie.email.clone())
+        .await
+        .ok()?;
+    // we need to check this, because a user could deactivate MFA in another browser or
+    // be deleted while still having existing mfa cookies somewhere else
+    if user.has_webauthn_enabled() {
+        Some(mfa_cookie.email)
+    } else {
+        None
+    }
+}
+
+/// Headless OIDC Authorization
+///
+/// The JSON counterpart to `GET /oidc/authorize` for native apps and custom login frontends.
+/// It validates the same params, opens a new session in the 'Init' state if necessary and sets
+/// the session cookie. Instead of an HTML page, it returns everything needed to build a custom
+/// login UX, including the CSRF token, whether a PoW is required and the requested scopes the
+/// user consents to.
+/// With `prompt=create`, no session will be opened and `next` is `register` with the
+/// `register_uri`, which leads back to this authorization request after the password has been set.
+#[utoipa::path(
+    get,
+    path = "/oidc/authorize/headless",
+    tag = "oidc",
+    params(AuthRequest),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = HeadlessAuthorizeResponse),
+        (status = 302, description = "Redirect to the expanded request object"),
+        (status = 400, description = "If any params do not match the backend config or `prompt=create` is not supported", body = ErrorResponse),
+        (status = 401, description = "`login_required` if `prompt=none` and no valid session exists", body = ErrorResponse),
+    ),
+)]
+#[get("/oidc/authorize/headless")]
+pub async fn get_authorize_headless(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    req_data: actix_web_validator::Query<AuthRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    // a request object replaces all query params, which are expanded with a redirect first
+    if let Some(location) =
+        RequestObject::expand(&data, &req_data, "/oidc/authorize/headless").await?
+    {
+        return Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, location))
+            .finish());
+    }
+
+    let (client, origin_header) = auth::validate_auth_req_param(
+        &data,
+        &req,
+        &req_data.client_id,
+        req_data.get_redirect_uri()?,
+        &req_data.code_challenge,
+        &req_data.code_challenge_method,
+    )
+    .await?;
+    ResourceServer::find_for_request(&data, req_data.resource.as_deref(), &client.id).await?;
+    let authorization_details =
+        AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref())?;
+
+    if req_data.is_prompt_create() && !*OPEN_USER_REG {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            ERR_PROMPT_CREATE.to_string(),
+        ));
+    }
+
+    let mut force_new_session = is_new_session_forced(&req_data, &principal);
+    let mfa_email = mfa_login_email(&data, &req).await;
+    if mfa_email.is_some() {
+        force_new_session = false;
+    }
+
+    let session_valid = !force_new_session && principal.validate_session_auth().is_ok();
+    if !session_valid && is_prompt_none(&req_data) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "login_required".to_string(),
+        ));
+    }
+
+    let lang = Language::try_from(&req).unwrap_or_default();
+    let scopes = Scope::login_i18n(&data, &client, &req_data.scope, &lang).await?;
+    let mut resp = HeadlessAuthorizeResponse {
+        next: HeadlessLoginStep::Credentials,
+        csrf_token: String::default(),
+        client_id: client.id,
+        client_name: client.name,
+        client_uri: client.client_uri,
+        scopes,
+        pow_required: *HEADLESS_LOGIN_POW
+            && !is_trusted_login_ip(real_ip_from_req(&req).as_deref()),
+        username_login: *USERNAME_LOGIN_ENABLE,
+        mfa_email,
+        register_uri: None,
+        authorization_details,
+    };
+
+    let mut builder = HttpResponse::Ok();
+    if let Some(o) = origin_header {
+        builder.insert_header(o);
+    }
+
+    if req_data.is_prompt_create() {
+        resp.next = HeadlessLoginStep::Register;
+        resp.register_uri = Some(AuthRequest::registration_uri(
+            &data.issuer,
+            req.query_string(),
+        )?);
+        return Ok(builder.json(resp));
+    }
+
+    // if the user is still authenticated and everything is valid -> immediate refresh
+    if session_valid {
+        resp.next = HeadlessLoginStep::Refresh;
+        resp.csrf_token = principal.get_session_csrf_token()?.to_string();
+        return Ok(builder.json(resp));
+    }
+
+    let session = Session::new(*SESSION_LIFETIME, real_ip_from_req(&req));
+    session.save(&data).await?;
+    resp.csrf_token = session.csrf_token.clone();
+
+    Ok(builder.cookie(session.client_cookie()).json(resp))
+}
+
+/// Headless login with user credentials
+///
+/// The JSON counterpart to `POST /oidc/authorize`. It needs the session cookie and CSRF token
+/// from `GET /oidc/authorize/headless` and, if `HEADLESS_LOGIN_POW` is set, a solved PoW from
+/// `POST /pow`. The response always contains the `next` step. After a successful login,
+/// `location` is the `redirect_uri` with the `code` and `state` appended.
+/// If the user has MFA configured, the login must be finished with the Webauthn endpoints.
+///
+/// **Permissions**
+/// - `session-init`
+/// - `session-auth`
+#[utoipa::path(
+    post,
+    path = "/oidc/authorize/headless",
+    tag = "oidc",
+    request_body = HeadlessLoginRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Ok", body = HeadlessLoginResponse),
+        (status = 400, description = "Missing / bad input data or PoW", body = ErrorResponse),
+        (status = 401, description = "Bad input or CSRF Token error", body = ErrorResponse),
+        (status = 403, description = "Login restricted by a group login policy", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/authorize/headless")]
+pub async fn post_authorize_headless(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: actix_web_validator::Json<HeadlessLoginRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+
+    let payload = payload.into_inner();
+    if *HEADLESS_LOGIN_POW && !is_trusted_login_ip(real_ip_from_req(&req).as_deref()) {
+        let pow = payload.pow.as_deref().ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A solved 'pow' is required".to_string(),
+            )
+        })?;
+        let challenge = Pow::validate(pow)?;
+        PowEntity::check_prevent_reuse(&data, challenge.to_string()).await?;
+    }
+
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    let session = principal.get_session()?;
+    let account = Some(payload.login.email.clone());
+    let res = match auth::authorize(&data, &req, payload.login, session.clone()).await {
+        Ok(auth_step) => map_auth_step_headless(auth_step, &req).await,
+        Err(err) => Err(err),
+    };
+
+    let ip = real_ip_from_req(&req);
+    auth::handle_login_delay(&data, ip, account, start, &data.caches.ha_cache_config, res).await
+}
+
 /// POST login credentials to proceed with the authorization_code flow
 ///
 /// This is the 2nd step of the authorization_code flow.<br>
@@ -215,11 +539,14 @@ pub async fn get_authorize(
     path = "/oidc/authorize",
     tag = "oidc",
     request_body = LoginRequest,
+    security(("session_init" = [])),
     responses(
         (status = 200, description = "Correct credentials, but needs to continue with Webauthn MFA Login", body = WebauthnLoginResponse),
+        (status = 200, description = "Correct credentials, but required user attributes are missing", body = UserAttrsMissingResponse),
         (status = 202, description = "Correct credentials and not MFA Login required, adds Location header"),
         (status = 400, description = "Missing / bad input data", body = ErrorResponse),
         (status = 401, description = "Bad input or CSRF Token error", body = ErrorResponse),
+        (status = 403, description = "Login restricted by a group login policy", body = ErrorResponse),
     ),
 )]
 #[post("/oidc/authorize")]
@@ -235,13 +562,21 @@ pub async fn post_authorize(
     let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
 
     let session = principal.get_session()?;
-    let res = match auth::authorize(&data, &req, req_data.into_inner(), session.clone()).await {
+    let account = Some(req_data.email.clone());
+    let is_lab = LabMode::is_lab_user(&req_data.email) && LabMode::is_debug_request(&req);
+    let mut res = match auth::authorize(&data, &req, req_data.into_inner(), session.clone()).await {
         Ok(auth_step) => map_auth_step(auth_step, &req).await,
         Err(err) => Err(err),
     };
+    if is_lab {
+        // synthetic lab users never get a login delay to not slow down security testing
+        res = res
+            .map(|(resp, _)| (resp, false))
+            .map_err(|(err, _)| (err, false));
+    }
 
     let ip = real_ip_from_req(&req);
-    auth::handle_login_delay(&data, ip, start, &data.caches.ha_cache_config, res).await
+    auth::handle_login_delay(&data, ip, account, start, &data.caches.ha_cache_config, res).await
 }
 
 /// Immediate login refresh with valid session
@@ -254,10 +589,12 @@ pub async fn post_authorize(
     path = "/oidc/authorize/refresh",
     tag = "oidc",
     request_body = LoginRefreshRequest,
+    security(("session" = [])),
     responses(
         (status = 202, description = "Accepted"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Login restricted by a group login policy", body = ErrorResponse),
     ),
 )]
 #[post("/oidc/authorize/refresh")]
@@ -279,9 +616,183 @@ pub async fn post_authorize_refresh(
     )
     .await?;
 
-    let auth_step =
-        auth::authorize_refresh(&data, session, client, header_origin, req_data.into_inner())
-            .await?;
+    let auth_step = auth::authorize_refresh(
+        &data,
+        session,
+        client,
+        header_origin,
+        req_data.into_inner(),
+        real_ip_from_req(&req),
+    )
+    .await?;
+    map_auth_step(auth_step, &req)
+        .await
+        .map(|res| res.0)
+        .map_err(|err| err.0)
+}
+
+/// Submits a JWT secured authorization response with `response_mode=form_post.jwt`
+///
+/// Renders a form, which is posted to the `redirect_uri` of the client right away. The `id` is
+/// returned in the `Location` of the authorization response and can only be used once. The
+/// signed response itself never shows up in a URL.
+#[utoipa::path(
+    get,
+    path = "/oidc/authorize/form_post",
+    tag = "oidc",
+    params(FormPostJwtRequest),
+    responses(
+        (status = 200, description = "The self-submitting form"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/oidc/authorize/form_post")]
+pub async fn get_authorize_form_post(
+    data: web::Data<AppState>,
+    params: actix_web_validator::Query<FormPostJwtRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let form_post = JarmFormPost::take(&data, &params.id).await?;
+
+    let body = FormPostHtml::build(&form_post.redirect_uri, &form_post.response);
+    Ok(HttpResponse::Ok().insert_header(HEADER_HTML).body(body))
+}
+
+/// Finishes a login with an E-Mail one-time code
+///
+/// If the user has E-Mail MFA enabled, `POST /oidc/authorize` will send a one-time code and
+/// return the `code` for this request. After a successful validation, the response is the same
+/// as for a finished Webauthn login.
+#[utoipa::path(
+    post,
+    path = "/oidc/authorize/email_mfa",
+    tag = "oidc",
+    request_body = EmailMfaVerifyRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 202, description = "Correct code, adds Location header", body = WebauthnLoginFinishResponse),
+        (status = 400, description = "Invalid or expired code", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/authorize/email_mfa")]
+pub async fn post_authorize_email_mfa(
+    data: web::Data<AppState>,
+    req_data: actix_web_validator::Json<EmailMfaVerifyRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+    let session = principal.get_session()?;
+
+    let payload = req_data.into_inner();
+    let code = EmailMfaCode::find(&data, &payload.code)
+        .await?
+        .verify(&data, &session.id, EmailMfaPurpose::Login, &payload.otp)
+        .await?;
+    let header_loc = code.header_loc.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            "Missing location for the E-Mail MFA login".to_string(),
+        )
+    })?;
+    // The session is not marked as MFA on purpose. The code has been sent via E-Mail, which
+    // would allow a password reset as well.
+
+    Ok(WebauthnAdditionalData::Login(WebauthnLoginReq {
+        code: code.id,
+        user_id: code.user_id,
+        header_loc,
+        header_origin: code.header_origin,
+    })
+    .into_response())
+}
+
+/// Polls a login, which awaits an approval via push notification
+///
+/// If the user has registered push subscriptions, `POST /oidc/authorize` sends a login request
+/// to them and returns the `code` and the `number`, which must be shown to the user. This
+/// endpoint should be polled every few seconds. After the approval, the response is the same as
+/// for a finished Webauthn login.
+#[utoipa::path(
+    post,
+    path = "/oidc/authorize/push_mfa",
+    tag = "oidc",
+    request_body = PushMfaLoginRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 202, description = "Approved, adds Location header", body = WebauthnLoginFinishResponse),
+        (status = 204, description = "The request is still pending"),
+        (status = 400, description = "Invalid or expired request", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "The request has been denied", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/authorize/push_mfa")]
+pub async fn post_authorize_push_mfa(
+    data: web::Data<AppState>,
+    req_data: actix_web_validator::Json<PushMfaLoginRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+    let mut session = principal.get_session()?.clone();
+
+    let push_req = PushMfaRequest::find(&data, &req_data.code).await?;
+    push_req.validate_session(&session.id)?;
+
+    match push_req.status() {
+        PushMfaStatus::Pending => Ok(HttpResponse::NoContent().finish()),
+        PushMfaStatus::Denied => {
+            push_req.delete(&data).await?;
+            Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "The login has been denied".to_string(),
+            ))
+        }
+        PushMfaStatus::Approved => {
+            push_req.delete(&data).await?;
+            session.set_mfa(&data, true).await?;
+
+            Ok(WebauthnAdditionalData::Login(WebauthnLoginReq {
+                code: push_req.id,
+                user_id: push_req.user_id,
+                header_loc: push_req.header_loc,
+                header_origin: push_req.header_origin,
+            })
+            .into_response())
+        }
+    }
+}
+
+/// Cancels a pending push approval and continues the login with another factor
+///
+/// The response is the same as for `POST /oidc/authorize` with a user, who has no push
+/// subscriptions.
+#[utoipa::path(
+    post,
+    path = "/oidc/authorize/push_mfa/fallback",
+    tag = "oidc",
+    request_body = PushMfaLoginRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Awaiting the other factor", body = WebauthnLoginResponse),
+        (status = 400, description = "Invalid or expired request", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/authorize/push_mfa/fallback")]
+pub async fn post_authorize_push_mfa_fallback(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    req_data: actix_web_validator::Json<PushMfaLoginRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+    let session = principal.get_session()?.clone();
+
+    let auth_step = PushMfaRequest::find(&data, &req_data.code)
+        .await?
+        .fallback(&data, session)
+        .await?;
     map_auth_step(auth_step, &req)
         .await
         .map(|res| res.0)
@@ -341,6 +852,9 @@ pub async fn get_cert_by_kid(
 }
 
 /// POST for starting an OAuth 2.0 Device Authorization Grant flow
+///
+/// The same endpoint is reachable via `/oidc/device_authorization` for clients which expect
+/// the naming from RFC 8628.
 #[utoipa::path(
     post,
     path = "/oidc/device",
@@ -351,7 +865,9 @@ pub async fn get_cert_by_kid(
         (status = 400, description = "BadRequest", body = OAuth2ErrorResponse),
     ),
 )]
+#[routes]
 #[post("/oidc/device")]
+#[post("/oidc/device_authorization")]
 pub async fn post_device_auth(
     data: web::Data<AppState>,
     req: HttpRequest,
@@ -478,6 +994,7 @@ pub async fn post_device_auth(
     path = "/oidc/device/verify",
     tag = "oidc",
     request_body = DeviceVerifyRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = DeviceVerifyResponse),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -526,8 +1043,10 @@ pub async fn post_device_verify(
 // Logout HTML page
 //
 // Returns an HTML page which can be used for logging the user out. Invalidates the session and deletes
-// all possibly existing refresh tokens from the database. Does an automatic logout if the
-// `id_token_hint` is given.
+// all possibly existing refresh tokens from the database. Does an automatic logout if a valid
+// `id_token_hint` for the current user is given and shows a confirmation otherwise.
+// The `post_logout_redirect_uri` must be registered for the client from the `id_token_hint` or
+// `client_id` and the `state` is passed through.
 #[utoipa::path(
     get,
     path = "/oidc/logout",
@@ -535,6 +1054,7 @@ pub async fn post_device_verify(
     params(LogoutRequest),
     responses(
         (status = 200, description = "Ok"),
+        (status = 302, description = "Without a session to the `post_logout_redirect_uri`"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
     ),
 )]
@@ -545,25 +1065,29 @@ pub async fn get_logout(
     req_data: actix_web_validator::Query<LogoutRequest>,
     principal: ReqPrincipal,
 ) -> HttpResponse {
-    // If we get any logout errors, maybe because there is no session anymore or whatever happens,
-    // just redirect to rauthy's root page, since the user is not logged in anyway anymore.
+    let lang = Language::try_from(&req).unwrap_or_default();
+    let req_data = req_data.into_inner();
+
+    // If there is no session anymore, the user is logged out already, and we can redirect
+    // immediately, as long as the request is valid.
     let session = match principal.get_session() {
         Ok(s) => s,
         Err(_) => {
-            return HttpResponse::build(StatusCode::from_u16(302).unwrap())
-                .insert_header(("location", "/auth/v1/"))
-                .finish()
+            return match auth::validate_logout_request(&data, &req_data, None).await {
+                Ok((_, location)) => HttpResponse::Found()
+                    .insert_header((
+                        header::LOCATION,
+                        location.unwrap_or_else(|| "/auth/v1/".to_string()),
+                    ))
+                    .finish(),
+                Err(err) => logout_err_html(&data, &lang, err).await,
+            };
         }
     };
 
-    let lang = Language::try_from(&req).unwrap_or_default();
-    let body = match auth::logout(req_data.into_inner(), session, &data, &lang).await {
+    let body = match auth::logout(req_data, session, &data, &lang).await {
         Ok(t) => t,
-        Err(_) => {
-            return HttpResponse::build(StatusCode::from_u16(302).unwrap())
-                .insert_header(("location", "/auth/v1/"))
-                .finish()
-        }
+        Err(err) => return logout_err_html(&data, &lang, err).await,
     };
 
     return HttpResponse::build(StatusCode::OK)
@@ -571,9 +1095,22 @@ pub async fn get_logout(
         .body(body);
 }
 
+/// An invalid logout request must never lead to a redirect.
+async fn logout_err_html(
+    data: &web::Data<AppState>,
+    lang: &Language,
+    err: ErrorResponse,
+) -> HttpResponse {
+    let colors = ColorEntity::find_rauthy(data).await.unwrap_or_default();
+    let status = err.status_code();
+    let body = Error1Html::build_for_err(&colors, lang, &err);
+    ErrorHtml::response(body, status)
+}
+
 /// Send the logout confirmation
 ///
-/// This is the corresponding endpoint for the `GET /auth/v1/oidc/logout`
+/// This is the corresponding endpoint for the `GET /auth/v1/oidc/logout`. A given
+/// `post_logout_redirect_uri` is validated the same way.
 #[utoipa::path(
     post,
     path = "/oidc/logout",
@@ -581,7 +1118,7 @@ pub async fn get_logout(
     params(LogoutRequest),
     responses(
         (status = 200, description = "Ok without `post_logout_redirect_uri`"),
-        (status = 301, description = "if a `post_logout_redirect_uri` was given"),
+        (status = 302, description = "if a valid `post_logout_redirect_uri` was given"),
         (status = 400, description = "BadRequest", body = ErrorResponse),
     ),
 )]
@@ -592,20 +1129,12 @@ pub async fn post_logout(
     principal: ReqPrincipal,
 ) -> Result<HttpResponse, ErrorResponse> {
     let mut session = principal.get_session()?.clone();
+    let (_, location) =
+        auth::validate_logout_request(&data, &req_data, session.user_id.as_deref()).await?;
     let cookie = session.invalidate(&data).await?;
 
-    if req_data.post_logout_redirect_uri.is_some() {
-        let state = if req_data.state.is_some() {
-            req_data.state.as_ref().unwrap().as_str()
-        } else {
-            ""
-        };
-        let loc = format!(
-            "{}?state={}",
-            req_data.post_logout_redirect_uri.as_ref().unwrap(),
-            state
-        );
-        return Ok(HttpResponse::build(StatusCode::MOVED_PERMANENTLY)
+    if let Some(loc) = location {
+        return Ok(HttpResponse::Found()
             .append_header((header::LOCATION, loc))
             .cookie(cookie)
             .finish());
@@ -627,6 +1156,7 @@ pub async fn post_logout(
     post,
     path = "/oidc/rotateJwk",
     tag = "oidc",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -644,6 +1174,182 @@ pub async fn rotate_jwk(
         .map(|_| HttpResponse::Ok().finish())
 }
 
+/// JWK history
+///
+/// Returns all signing keys with their creation, activation and retirement timestamps and how
+/// many tokens have been signed with each of them.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/oidc/jwks",
+    tag = "oidc",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [JwkHistory]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/oidc/jwks")]
+pub async fn get_jwks_history(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Secrets, AccessRights::Read)?;
+
+    let history = Jwk::find_history(&data.db).await?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// Import a JWK
+///
+/// Imports an externally generated private key in PEM format, for instance from a key ceremony.
+/// The key will be used for signing new tokens from `activate_at` on, or immediately if not given.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/oidc/jwks/import",
+    tag = "oidc",
+    request_body = JwkImportRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/jwks/import")]
+pub async fn post_jwk_import(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Json<JwkImportRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Secrets, AccessRights::Create)?;
+
+    let payload = payload.into_inner();
+    let jwk = Jwk::import(
+        &data,
+        payload.alg,
+        &payload.pem,
+        payload.kid,
+        payload.activate_at,
+    )
+    .await?;
+
+    data.tx_events
+        .send_async(Event::jwk_changed(
+            format!(
+                "{} key {} imported, active from {}",
+                jwk.signature,
+                jwk.kid,
+                payload.activate_at.unwrap_or(jwk.created_at)
+            ),
+            real_ip_from_req(&req),
+        ))
+        .await
+        .unwrap();
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Schedule a JWK activation
+///
+/// Sets the timestamp from which on the given key will be used for signing new tokens.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/oidc/jwks/{kid}/activation",
+    tag = "oidc",
+    request_body = JwkActivationRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/oidc/jwks/{kid}/activation")]
+pub async fn put_jwk_activation(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Json<JwkActivationRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Secrets, AccessRights::Update)?;
+
+    let activate_at = payload.activate_at;
+    let jwk = Jwk::schedule_activation(&data, &path.into_inner(), activate_at).await?;
+
+    data.tx_events
+        .send_async(Event::jwk_changed(
+            format!(
+                "{} key {} activation scheduled for {}",
+                jwk.signature,
+                jwk.kid,
+                activate_at.unwrap_or(jwk.created_at)
+            ),
+            real_ip_from_req(&req),
+        ))
+        .await
+        .unwrap();
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Retire a JWK
+///
+/// Retires the given key early. It will not be used for signing anymore and will be removed from
+/// the published JWKS, which invalidates all tokens signed with it. The only active key for an
+/// algorithm cannot be retired.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/oidc/jwks/{kid}/retire",
+    tag = "oidc",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/jwks/{kid}/retire")]
+pub async fn post_jwk_retire(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Secrets, AccessRights::Delete)?;
+
+    let jwk = Jwk::retire(&data, &path.into_inner()).await?;
+
+    data.tx_events
+        .send_async(Event::jwk_changed(
+            format!("{} key {} retired", jwk.signature, jwk.kid),
+            real_ip_from_req(&req),
+        ))
+        .await
+        .unwrap();
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 /// OIDC sessioninfo
 ///
 /// Returns information about the current session. This is currently only used in the Rauthy Admin UI
@@ -655,6 +1361,7 @@ pub async fn rotate_jwk(
     post,
     path = "/oidc/sessioninfo",
     tag = "oidc",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
     ),
@@ -701,6 +1408,7 @@ pub async fn get_session_info(
     get,
     path = "/oidc/sessioninfo/xsrf",
     tag = "oidc",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
     ),
@@ -739,11 +1447,13 @@ pub async fn get_session_xsrf(
     path = "/oidc/token",
     tag = "oidc",
     request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    security((), ("basic" = [])),
     responses(
         (status = 200, description = "Ok", body = TokenSet),
         (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 404, description = "NotFound", body = ErrorResponse),
+        (status = 429, description = "TooManyRequests", body = ErrorResponse),
     ),
 )]
 #[post("/oidc/token")]
@@ -755,18 +1465,70 @@ pub async fn post_token(
 ) -> Result<HttpResponse, ErrorResponse> {
     let ip = real_ip_from_req(&req);
 
+    // The client_id can only be trusted after a successful grant, which is why the user agent
+    // will only be recorded further down.
+    let ua_stats = if *TOKEN_USER_AGENT_STATS {
+        payload
+            .try_get_client_id_secret(&req)
+            .ok()
+            .map(|(client_id, _)| {
+                let user_agent = req
+                    .headers()
+                    .get(header::USER_AGENT)
+                    .and_then(|h| h.to_str().ok())
+                    .map(String::from);
+                (client_id, user_agent)
+            })
+    } else {
+        None
+    };
+
+    // synthetic lab clients and users must never be usable without the X-Debug header
+    LabMode::validate_principal(
+        &req,
+        payload.username.as_deref(),
+        payload
+            .try_get_client_id_secret(&req)
+            .ok()
+            .map(|(client_id, _)| client_id)
+            .as_deref(),
+    )?;
+
     if payload.grant_type == GRANT_TYPE_DEVICE_CODE {
         // TODO the `urn:ietf:params:oauth:grant-type:device_code` needs
         // a fully customized handling here with customized error response
         // to meet the oauth rfc
-        return Ok(auth::grant_type_device_code(&data, ip, payload.into_inner()).await);
+        let resp = auth::grant_type_device_code(&data, &req, payload.into_inner()).await;
+        if resp.status().is_success() {
+            record_user_agent(&data, ua_stats);
+        }
+        return Ok(resp);
+    }
+
+    // synthetic lab clients are excluded from rate limits and login delays
+    let is_lab = LabMode::is_debug_request(&req)
+        && payload
+            .try_get_client_id_secret(&req)
+            .map(|(client_id, _)| LabMode::is_lab_client(&client_id))
+            .unwrap_or(false);
+
+    if let Some(limit) = *TOKEN_RATE_LIMIT {
+        // a missing or invalid client_id will be rejected by the grant itself
+        if let Ok((client_id, _)) = payload.try_get_client_id_secret(&req) {
+            if !is_lab {
+                let key = rate_limit::client_key(&client_id, ip.as_deref());
+                rate_limit::check(&key, limit, &data.caches.ha_cache_config).await?;
+            }
+        }
     }
 
     let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    let add_login_delay = payload.grant_type == "password";
+    let add_login_delay = payload.grant_type == "password" && !is_lab;
+    let account = payload.username.clone();
 
     let res = match auth::get_token_set(payload.into_inner(), &data, req).await {
         Ok((token_set, headers)) => {
+            record_user_agent(&data, ua_stats);
             let mut builder = HttpResponseBuilder::new(StatusCode::OK);
             for h in headers {
                 builder.insert_header(h);
@@ -777,27 +1539,315 @@ pub async fn post_token(
         Err(err) => Err((err, add_login_delay)),
     };
 
-    auth::handle_login_delay(&data, ip, start, &data.caches.ha_cache_config, res).await
+    auth::handle_login_delay(&data, ip, account, start, &data.caches.ha_cache_config, res).await
+}
+
+/// Records the user agent for the client SDK stats in the background to not slow down the
+/// token endpoint.
+fn record_user_agent(data: &web::Data<AppState>, ua_stats: Option<(String, Option<String>)>) {
+    if let Some((client_id, user_agent)) = ua_stats {
+        let data = data.clone();
+        tokio::spawn(async move {
+            if let Err(err) =
+                ClientUserAgent::record(&data, &client_id, user_agent.as_deref()).await
+            {
+                error!(
+                    "Error recording the user agent for client {}: {:?}",
+                    client_id, err
+                );
+            }
+        });
+    }
 }
 
-/// The tokenInfo endpoint for the OIDC standard.
+/// Session based token refresh for first-party SPAs
+///
+/// Issues a short-lived access token and an id token in exchange for a valid Rauthy session
+/// cookie. Refresh tokens are never issued this way, so none will ever live inside the browser.
+/// Only clients listed in `SESSION_TOKEN_CLIENTS` are allowed to use it, and the `Origin` must
+/// match the client's `allowed_origins`. With `SESSION_TOKEN_ROTATE`, each successful request
+/// rotates the session cookie as well.
+///
+/// **Permissions**
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/oidc/token/session",
+    tag = "oidc",
+    request_body = SessionTokenRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = TokenSet),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/token/session")]
+pub async fn post_token_session(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Json<SessionTokenRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let session = principal.validate_session_auth()?.clone();
+
+    let (token_set, session, headers) =
+        auth::session_token(&data, &req, session, payload.into_inner()).await?;
+
+    let mut builder = HttpResponseBuilder::new(StatusCode::OK);
+    for h in headers {
+        builder.insert_header(h);
+    }
+    Ok(builder
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .cookie(session.client_cookie())
+        .json(token_set))
+}
+
+/// CORS preflight for the session based token refresh
+#[route("/oidc/token/session", method = "OPTIONS")]
+pub async fn options_token_session(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let mut builder = HttpResponse::NoContent();
+    for h in auth::session_token_preflight(&data, &req).await {
+        builder.insert_header(h);
+    }
+    Ok(builder.finish())
+}
+
+/// Issues a login hint token to hand over the current session to a peer instance
+///
+/// The `audience` must be one of the `SSO_HANDOFF_PEERS`. The returned `url` must be opened in
+/// the browser to silently get a session at the peer. The token is only valid for
+/// `SSO_HANDOFF_TOKEN_LIFETIME` seconds and can be used once.
+///
+/// **Permissions**
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/oidc/sso_handoff",
+    tag = "oidc",
+    request_body = SsoHandoffRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = SsoHandoffResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/sso_handoff")]
+pub async fn post_sso_handoff(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Json<SsoHandoffRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let session = principal.validate_session_auth()?.clone();
+
+    sso_handoff::start(&data, session, payload.into_inner())
+        .await
+        .map(|resp| {
+            HttpResponse::Ok()
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .json(resp)
+        })
+}
+
+/// Validates a login hint token from a peer instance
+///
+/// Returns a page, on which the user must confirm the account before the session will be
+/// created. The `redirect_uri` must be a path at this instance.
+#[utoipa::path(
+    get,
+    path = "/oidc/sso_handoff",
+    tag = "oidc",
+    params(SsoHandoffParams),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/oidc/sso_handoff")]
+pub async fn get_sso_handoff(
+    data: web::Data<AppState>,
+    params: actix_web_validator::Query<SsoHandoffParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    sso_handoff::finish(&data, params.into_inner()).await
+}
+
+/// Confirms an SSO handoff and creates a new session
+///
+/// Only works in the same browser, which opened the `/oidc/sso_handoff` link. Redirects to the
+/// `redirect_uri` or to the account page on success. With an `/auth/v1/oidc/authorize` request
+/// as `redirect_uri`, the login at the client will happen without another prompt.
+#[utoipa::path(
+    post,
+    path = "/oidc/sso_handoff/confirm",
+    tag = "oidc",
+    request_body(content = SsoHandoffConfirmRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 302, description = "Found"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/sso_handoff/confirm")]
+pub async fn post_sso_handoff_confirm(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: actix_web_validator::Form<SsoHandoffConfirmRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    sso_handoff::confirm(&data, &req, payload.into_inner()).await
+}
+
+/// Token introspection as defined in RFC 7662
+///
+/// The caller must authenticate either as a confidential client with `client_secret_basic` or
+/// `client_secret_post`, or with an API key with `sessions: read` access. Invalid, expired and
+/// revoked tokens only return `active: false`.
+///
+/// Refresh tokens are opaque to everyone apart from the client they have been issued to. They
+/// are only reported as active to this client, or to an API key.
+///
+/// Rauthy only issues JWTs and has no opaque access tokens. Any token, which is not a valid JWT
+/// issued by this instance, is reported with `active: false`.
+#[utoipa::path(
+    post,
+    path = "/oidc/introspect",
+    tag = "oidc",
+    request_body(content = IntrospectionRequest, content_type = "application/x-www-form-urlencoded"),
+    security(("basic" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Ok", body = TokenInfo),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/introspect")]
+pub async fn post_introspect(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    payload: actix_web_validator::Form<IntrospectionRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    introspect(&req, &data, &principal, payload.into_inner()).await
+}
+
+/// DEPRECATED: use `/oidc/introspect` instead
+///
+/// Accepts the token as JSON and returns the same response as the introspection endpoint. The
+/// caller must authenticate as a confidential client via the `Authorization: Basic` header, or
+/// with an API key with `sessions: read` access.
 #[utoipa::path(
     post,
     path = "/oidc/tokenInfo",
     tag = "oidc",
     request_body = TokenValidationRequest,
+    security(("basic" = []), ("api_key" = [])),
     responses(
         (status = 200, description = "Ok", body = TokenInfo),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
-        (status = 404, description = "NotFound", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
     ),
 )]
 #[post("/oidc/tokenInfo")]
 pub async fn post_token_info(
+    req: HttpRequest,
     data: web::Data<AppState>,
+    principal: ReqPrincipal,
     req_data: actix_web_validator::Json<TokenValidationRequest>,
 ) -> Result<HttpResponse, ErrorResponse> {
-    auth::get_token_info(&data, &req_data.token)
+    let payload = IntrospectionRequest {
+        token: req_data.into_inner().token,
+        token_type_hint: None,
+        client_id: None,
+        client_secret: None,
+    };
+    introspect(&req, &data, &principal, payload).await
+}
+
+async fn introspect(
+    req: &HttpRequest,
+    data: &web::Data<AppState>,
+    principal: &ReqPrincipal,
+    payload: IntrospectionRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    // `None` for API keys, which may introspect all tokens
+    let caller_client = if principal.api_key.is_some() {
+        principal.validate_api_key(AccessGroup::Sessions, AccessRights::Read)?;
+        None
+    } else {
+        Some(introspection_client(req, data, &payload).await?)
+    };
+
+    let info = auth::introspect(data, &payload.token, caller_client.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+/// Authenticates the confidential client calling the introspection endpoint.
+async fn introspection_client(
+    req: &HttpRequest,
+    data: &web::Data<AppState>,
+    payload: &IntrospectionRequest,
+) -> Result<String, ErrorResponse> {
+    let err = || {
+        ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "Token introspection requires client or API key authentication".to_string(),
+        )
+    };
+
+    let (client_id, client_secret) = payload.try_get_client_id_secret(req)?;
+    let client_secret = client_secret.ok_or_else(err)?;
+    if client_id.is_empty() {
+        return Err(err());
+    }
+
+    let client = Client::find(data, client_id).await.map_err(|_| err())?;
+    if !client.confidential {
+        return Err(err());
+    }
+    client.validate_enabled()?;
+    client
+        .validate_secret(&client_secret, req)
+        .map_err(|_| err())?;
+
+    Ok(client.id)
+}
+
+/// Checks if the session behind a `sid` claim is still alive
+///
+/// Tokens issued via the `authorization_code` flow contain the `sid` claim, which binds them to
+/// the users session. Tokens from a `refresh_token` keep the `sid` of the original ones.
+/// Resource servers can check with this endpoint, if the user has logged out or if the session
+/// has been revoked in the meantime, without the need for a backchannel logout.
+///
+/// The session is `active` until it expires, is logged out or revoked. The idle timeout for the
+/// session inside Rauthy is not taken into account.
+#[utoipa::path(
+    post,
+    path = "/oidc/sidInfo",
+    tag = "oidc",
+    request_body = SidInfoRequest,
+    responses(
+        (status = 200, description = "Ok", body = SidInfo),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+    ),
+)]
+#[post("/oidc/sidInfo")]
+pub async fn post_sid_info(
+    data: web::Data<AppState>,
+    req_data: actix_web_validator::Json<SidInfoRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    auth::get_sid_info(&data, &req_data.sid)
         .await
         .map(|i| HttpResponse::Ok().json(i))
 }
@@ -848,19 +1898,23 @@ pub async fn post_validate_token(
     data: web::Data<AppState>,
     req_data: actix_web_validator::Json<TokenValidationRequest>,
 ) -> Result<HttpResponse, ErrorResponse> {
-    auth::validate_token::<JwtCommonClaims>(&data, &req_data.token)
-        .await
-        .map(|_| HttpResponse::Accepted().finish())
+    let claims = auth::validate_token::<JwtCommonClaims>(&data, &req_data.token).await?;
+    auth::validate_access_token_profile(&req_data.token, &claims)?;
+    Ok(HttpResponse::Accepted().finish())
 }
 
 /// The userinfo endpoint for the OIDC standard.
 ///
 /// Depending on the JWT token from the *Authorization* header, it will return information about
 /// the requesting user / token.
+///
+/// If the client has registered a `userinfo_encrypted_response_alg`, the response will be an
+/// encrypted JWE with the content type `application/jwt`.
 #[utoipa::path(
     post,
     path = "/oidc/userinfo",
     tag = "oidc",
+    security(("bearer" = [])),
     responses(
         (status = 200, description = "Ok", body = Userinfo),
         (status = 400, description = "BadRequest", body = ErrorResponse),
@@ -873,9 +1927,7 @@ pub async fn get_userinfo(
     data: web::Data<AppState>,
     req: HttpRequest,
 ) -> Result<HttpResponse, ErrorResponse> {
-    auth::get_userinfo(&data, req)
-        .await
-        .map(|u| HttpResponse::Ok().json(u))
+    auth::get_userinfo_response(&data, req).await
 }
 
 /// GET forward authentication
@@ -896,6 +1948,7 @@ pub async fn get_userinfo(
     get,
     path = "/oidc/forward_auth",
     tag = "oidc",
+    security(("bearer" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
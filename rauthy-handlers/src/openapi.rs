@@ -1,22 +1,33 @@
 use crate::{
-    api_keys, auth_providers, blacklist, clients, events, generic, groups, oidc, roles, scopes,
-    sessions, users,
+    announcements, api_keys, auth_providers, blacklist, caches, cleanup, clients, cluster, email,
+    events, feature_flags, generic, groups, jobs, lab, login_traces, logs, oidc, organizations,
+    resource_servers, roles, scopes, sessions, users,
 };
 use actix_web::web;
-use rauthy_common::constants::{PROXY_MODE, RAUTHY_VERSION};
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::constants::{
+    APPLICATION_JSON, APPLICATION_PROBLEM_JSON, COOKIE_SESSION, PROXY_MODE, RAUTHY_VERSION,
+};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType, ProblemDetails};
+use rauthy_common::log_stream;
 use rauthy_models::app_state::AppState;
 use rauthy_models::events::event;
 use rauthy_models::language;
 use rauthy_models::ListenScheme;
 use rauthy_models::{entity, request, response};
 use rauthy_service::token_set;
-use utoipa::openapi::Server;
-use utoipa::{openapi, OpenApi};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, ResponseBuilder, Server};
+use utoipa::{openapi, Modify, OpenApi};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        announcements::get_announcements,
+        announcements::get_announcements_active,
+        announcements::post_announcement,
+        announcements::put_announcement,
+        announcements::delete_announcement,
+
         api_keys::get_api_keys,
         api_keys::post_api_key,
         api_keys::put_api_key,
@@ -38,9 +49,18 @@ use utoipa::{openapi, OpenApi};
         auth_providers::put_provider_img,
 
         blacklist::get_blacklist,
+        blacklist::get_blacklist_feeds,
         blacklist::post_blacklist,
         blacklist::delete_blacklist,
 
+        caches::get_caches,
+        caches::delete_cache_entry,
+
+        cleanup::get_cleanup_jobs,
+        cleanup::post_cleanup_job,
+
+        cluster::get_cluster,
+
         clients::get_clients,
         clients::get_client_by_id,
         clients::get_client_colors,
@@ -50,16 +70,32 @@ use utoipa::{openapi, OpenApi};
         clients::put_client_logo,
         clients::delete_client_logo,
         clients::get_client_secret,
+        clients::get_client_user_agents,
+        clients::get_client_token_errors,
+        clients::get_clients_user_agents,
         clients::post_clients,
         clients::put_clients,
         clients::put_generate_client_secret,
         clients::delete_client,
 
+        events::get_events_export,
+        events::get_events_chain_verify,
+        events::get_event_recipients,
+        events::put_event_recipients,
         events::sse_events,
         events::post_event_test,
 
+        feature_flags::get_feature_flags,
+        feature_flags::get_feature_flags_evaluate,
+        feature_flags::post_feature_flag,
+        feature_flags::put_feature_flag,
+        feature_flags::delete_feature_flag,
+
         generic::get_auth_check,
         generic::get_auth_check_admin,
+        generic::post_break_glass,
+        generic::get_break_glass,
+        generic::get_compliance_report,
         generic::get_enc_keys,
         generic::post_migrate_enc_key,
         generic::get_login_time,
@@ -77,24 +113,67 @@ use utoipa::{openapi, OpenApi};
         groups::post_group,
         groups::put_group,
         groups::delete_group,
+        groups::get_group_login_policy,
+        groups::put_group_login_policy,
+        groups::delete_group_login_policy,
+
+        email::get_email_deliveries,
+        email::post_email_webhook,
+        jobs::get_jobs,
+        jobs::post_job_retry,
+        jobs::delete_job,
+
+        lab::post_lab_seed,
+        lab::delete_lab_seed,
+
+        login_traces::get_login_traces,
+        login_traces::post_login_trace,
+        login_traces::get_login_trace_records,
+        login_traces::delete_login_trace,
+
+        logs::sse_logs,
 
         oidc::get_authorize,
         oidc::post_authorize,
+        oidc::post_authorize_email_mfa,
+        oidc::post_authorize_push_mfa,
+        oidc::post_authorize_push_mfa_fallback,
+        oidc::get_authorize_form_post,
+        oidc::get_authorize_headless,
+        oidc::post_authorize_headless,
         oidc::get_certs,
         oidc::get_cert_by_kid,
         oidc::post_device_auth,
         oidc::get_logout,
         oidc::post_logout,
         oidc::rotate_jwk,
+        oidc::get_jwks_history,
+        oidc::post_jwk_import,
+        oidc::put_jwk_activation,
+        oidc::post_jwk_retire,
         oidc::get_session_info,
         oidc::get_session_xsrf,
         oidc::post_token,
+        oidc::post_token_session,
+        oidc::post_sso_handoff,
+        oidc::get_sso_handoff,
+        oidc::post_sso_handoff_confirm,
+        oidc::post_introspect,
         oidc::post_token_info,
+        oidc::post_sid_info,
         oidc::post_validate_token,
         oidc::get_userinfo,
         oidc::get_forward_auth,
         oidc::get_well_known,
 
+        organizations::get_organizations,
+        organizations::post_organization,
+        organizations::put_organization,
+        organizations::delete_organization,
+        organizations::get_organization_members,
+        organizations::put_organization_member,
+        organizations::delete_organization_member,
+
         roles::get_roles,
         roles::post_role,
         roles::put_role,
@@ -104,13 +183,21 @@ use utoipa::{openapi, OpenApi};
         scopes::post_scope,
         scopes::put_scope,
         scopes::delete_scope,
+        resource_servers::get_resource_servers,
+        resource_servers::post_resource_server,
+        resource_servers::put_resource_server,
+        resource_servers::delete_resource_server,
 
         sessions::get_sessions,
         sessions::delete_sessions,
         sessions::delete_sessions_for_user,
+        sessions::get_revoked_tokens,
+        sessions::post_revoked_token,
+        sessions::delete_revoked_token,
 
         users::get_users,
         users::post_users,
+        users::post_users_provisioning,
         users::get_cust_attr,
         users::post_cust_attr,
         users::put_cust_attr,
@@ -125,6 +212,7 @@ use utoipa::{openapi, OpenApi};
         users::put_user_webid_data,
         users::get_user_password_reset,
         users::put_user_password_reset,
+        users::post_user_password_reset_admin,
         users::post_webauthn_auth_start,
         users::post_webauthn_auth_finish,
         users::delete_webauthn,
@@ -134,23 +222,78 @@ use utoipa::{openapi, OpenApi};
         users::put_user_by_id,
         users::put_user_self,
         users::post_user_self_convert_passkey,
+        users::post_user_self_elevate,
+        users::post_user_self_elevate_email_mfa,
+        users::put_user_self_email_mfa,
+        users::get_user_self_push_subscriptions,
+        users::post_user_self_push_subscription,
+        users::delete_user_self_push_subscription,
+        users::post_push_mfa_approve,
+        users::post_push_mfa_deny,
+        users::get_user_self_apps,
+        users::delete_user_self_app,
+        users::get_user_self_security,
+        users::get_user_self_export,
+        users::get_user_self_preferences,
+        users::put_user_self_preferences,
         users::delete_user_by_id,
+        users::post_user_login_policy_override,
+        users::delete_user_login_policy_override,
+        users::get_users_legal_holds,
+        users::get_users_inactive,
+        users::put_user_legal_hold,
+        users::delete_user_legal_hold,
+        users::post_mfa_recovery,
+        users::post_mfa_recovery_confirm,
+        users::post_mfa_recovery_cancel,
+        users::post_mfa_recovery_finish,
+        users::get_user_mfa_recovery,
+        users::post_user_mfa_recovery_approve,
+        users::delete_user_mfa_recovery,
+        users::get_user_role_grants,
+        users::post_user_role_grant,
+        users::delete_user_role_grant,
+        users::post_user_merge,
+        users::get_user_email_aliases,
+        users::post_user_email_alias,
+        users::delete_user_email_alias,
     ),
     components(
         schemas(
+            entity::announcements::AnnouncementSeverity,
             entity::api_keys::AccessGroup,
             entity::api_keys::AccessRights,
             entity::api_keys::ApiKeyAccess,
+            entity::auth_provider_claims::ProviderAttrMapping,
+            entity::auth_provider_claims::ProviderClaim,
+            entity::auth_provider_claims::ProviderClaimMapping,
             entity::auth_providers::AuthProviderType,
+            entity::cleanup::CleanupJob,
+            entity::client_secret_reads::ClientSecretRead,
+            entity::client_user_agents::ClientUserAgent,
+            entity::cluster_nodes::CacheRole,
             entity::clients::Client,
             entity::colors::Colors,
             entity::groups::Group,
+            entity::email_deliveries::EmailDeliveryStatus,
+            entity::jobs::JobStatus,
+            entity::ip_feeds::IpFeed,
+            entity::jobs::JobType,
+            entity::jwe::JweAlg,
+            entity::jwe::JweEnc,
+            entity::jwk::JwkHistory,
             entity::jwk::JwkKeyPairAlg,
             entity::jwk::JwkKeyPairType,
+            entity::login_traces::LoginTrace,
+            entity::login_traces::LoginTraceOutcome,
+            entity::login_traces::LoginTraceRecord,
+            entity::mfa_recovery::MfaRecoveryStatus,
             entity::password::PasswordHashTime,
             entity::password::PasswordHashTimes,
+            entity::revoked_tokens::RevokedToken,
             entity::roles::Role,
             entity::scopes::Scope,
+            entity::scopes::ScopeI18n,
             entity::sessions::SessionState,
             entity::user_attr::UserAttrConfigEntity,
             entity::user_attr::UserAttrValueEntity,
@@ -161,46 +304,104 @@ use utoipa::{openapi, OpenApi};
             entity::webids::WebId,
 
             event::EventLevel,
+            event::EventType,
+            ErrorCode,
             ErrorResponse,
             ErrorResponseType,
+            ProblemDetails,
+            log_stream::LogLevel,
+            log_stream::LogLine,
             language::Language,
 
+            request::AdminPasswordResetRequest,
+            request::AnnouncementParams,
+            request::AnnouncementRequest,
             request::ApiKeyRequest,
             request::AuthCodeRequest,
             request::AuthRequest,
+            request::FormPostJwtRequest,
             request::IpBlacklistRequest,
             request::ColorsRequest,
             request::DeviceGrantRequest,
+            request::ComplianceReportFormat,
+            request::ComplianceReportParams,
+            request::DryRunParams,
+            request::EmailAliasRequest,
+            request::EmailMfaUpdateRequest,
+            request::EmailMfaVerifyRequest,
+            request::EmailDeliveriesParams,
+            request::EmailWebhookRequest,
             request::EncKeyMigrateRequest,
+            request::FeatureFlagRequest,
+            request::HeadlessLoginRequest,
+            request::LabSeedRequest,
+            request::EventsExportFormat,
+            request::EventRecipientRequest,
+            request::EventRecipientsRequest,
+            request::JobsParams,
             request::LoginRequest,
+            request::ResponseMode,
+            request::LoginTraceRequest,
             request::LogoutRequest,
             request::MfaAwaitRequest,
             request::MfaPurpose,
+            request::MfaRecoveryRequest,
+            request::MfaRecoveryCodeRequest,
             request::NewClientRequest,
             request::DynamicClientRequest,
+            request::BreakGlassRequest,
+            request::GroupLoginPolicyRequest,
+            request::LegalHoldRequest,
+            request::InactiveUsersParams,
+            request::UserMergeRequest,
+            request::UserMergeWinner,
+            request::UserRoleGrantRequest,
+            request::LoginPolicyOverrideRequest,
             request::NewGroupRequest,
             request::NewUserRequest,
             request::NewUserRegistrationRequest,
             request::NewRoleRequest,
             request::PaginationParams,
+            request::OrganizationRequest,
+            request::OrganizationMemberRequest,
             request::PasswordHashTimesRequest,
             request::PasswordPolicyRequest,
+            request::PasswordResetChannel,
             request::PasswordResetRequest,
+            request::ProvisioningAction,
+            request::ProvisioningRequest,
             request::ProviderRequest,
             request::ProviderLoginRequest,
             request::ProviderLookupRequest,
             request::ProviderCallbackRequest,
+            request::PushMfaApproveRequest,
+            request::PushMfaDenyRequest,
+            request::PushMfaLoginRequest,
+            request::PushSubscriptionRequest,
             request::RequestResetRequest,
+            request::ResourceServerRequest,
             request::ScopeRequest,
+            request::ScopeI18nRequest,
+            request::IntrospectionRequest,
+            request::SidInfoRequest,
             request::TokenRequest,
+            request::TokenRevocationRequest,
+            request::JwkActivationRequest,
+            request::JwkImportRequest,
             request::TokenValidationRequest,
             request::UpdateClientRequest,
             request::UpdateUserRequest,
+            request::SessionElevateRequest,
+            request::SessionTokenRequest,
+            request::SsoHandoffConfirmRequest,
+            request::SsoHandoffParams,
+            request::SsoHandoffRequest,
             request::UpdateUserSelfRequest,
             request::UserValuesRequest,
             request::UserAttrConfigRequest,
             request::UserAttrValueRequest,
             request::UserAttrValuesUpdateRequest,
+            request::UserPreferencesRequest,
             request::WebauthnRegStartRequest,
             request::WebauthnRegFinishRequest,
             request::WebauthnAuthStartRequest,
@@ -209,38 +410,97 @@ use utoipa::{openapi, OpenApi};
             request::WhoamiRequestParam,
             request::WhoamiRequestParams,
 
+            response::AdminPasswordResetResponse,
+            response::AnnouncementResponse,
             response::ApiKeyResponse,
             response::ApiKeysResponse,
             response::AppVersionResponse,
+            response::BlacklistDryRunResponse,
             response::BlacklistResponse,
             response::BlacklistedIp,
+            response::IpFeedResponse,
+            response::BreakGlassResponse,
+            response::ComplianceReport,
+            response::ConnectedAppResponse,
+            response::EventChainGap,
+            response::EventChainVerifyResponse,
+            response::EventRecipientResponse,
+            response::FeatureFlagResponse,
+            response::FeatureFlagsEvaluatedResponse,
+            response::ComplianceReportAdmin,
+            response::ComplianceReportClient,
+            response::ComplianceReportEventSink,
+            response::ComplianceReportKey,
+            response::CacheStatsResponse,
+            response::CleanupJobResponse,
+            response::CleanupRunResponse,
+            response::ClusterNodeResponse,
+            response::ClusterResponse,
+            response::LabClientCredentials,
+            response::LabPurgeResponse,
+            response::LabSeedResponse,
+            response::LabUserCredentials,
+            response::GroupLoginPolicyResponse,
+            response::LegalHoldResponse,
+            response::InactiveUserResponse,
+            response::LoginPolicyOverrideResponse,
+            response::MfaRecoveryResponse,
+            response::TokenErrorResponse,
+            response::UserMergeResponse,
+            response::UserRoleGrantResponse,
+            response::LoginLatencyResponse,
             response::LoginTimeResponse,
             response::ClientResponse,
             response::DeviceCodeResponse,
             response::DynamicClientResponse,
             response::ClientSecretResponse,
+            response::EmailAliasResponse,
+            response::EmailDeliveriesResponse,
+            response::EmailDeliveryResponse,
+            response::EmailDeliveryStats,
+            response::EmailMfaResponse,
             response::EncKeysResponse,
+            response::HeadlessAuthorizeResponse,
+            response::HeadlessLoginResponse,
+            response::HeadlessLoginStep,
             response::HealthResponse,
+            response::JobResponse,
             response::JWKSCerts,
             response::JWKSPublicKeyCerts,
             response::Argon2ParamsResponse,
             response::OAuth2ErrorResponse,
             response::OAuth2ErrorTypeResponse,
+            response::OrganizationResponse,
+            response::OrganizationMemberResponse,
             response::PasswordPolicyResponse,
             response::ProviderResponse,
             response::ProviderLinkedUserResponse,
             response::ProviderLookupResponse,
+            response::PushMfaResponse,
+            response::PushSubscriptionResponse,
+            response::PushSubscriptionsResponse,
+            response::ResourceServerResponse,
             response::ScopeResponse,
+            response::SecurityEventResponse,
             response::SessionResponse,
             response::SessionInfoResponse,
+            response::SsoHandoffResponse,
+            response::SessionElevationResponse,
+            response::SessionsDryRunResponse,
+            response::SidInfo,
             response::TokenInfo,
             response::UserAttrConfigResponse,
+            response::UserAttrMissingResponse,
+            response::UserAttrsMissingResponse,
             response::UserAttrValueResponse,
             response::UserAttrValuesResponse,
             response::Userinfo,
             response::UserValuesResponse,
             response::UserAccountTypeResponse,
+            response::UserDataExportResponse,
+            response::UserPreferencesResponse,
             response::UserResponse,
+            response::UserSecurityResponse,
             response::WebauthnAuthStartResponse,
             response::WebauthnLoginFinishResponse,
             response::WebauthnLoginResponse,
@@ -248,6 +508,8 @@ use utoipa::{openapi, OpenApi};
 
             rauthy_models::AddressClaim,
             rauthy_models::JktClaim,
+            rauthy_models::AuthorizationDetail,
+            rauthy_models::OrganizationClaim,
             rauthy_models::JwtTokenType,
             token_set::TokenSet,
         ),
@@ -259,21 +521,122 @@ use utoipa::{openapi, OpenApi};
         (name = "mfa", description = "MFA endpoints"),
         (name = "sessions", description = "Sessions endpoints"),
         (name = "groups", description = "Groups endpoints"),
+        (name = "organizations", description = "Organizations endpoints"),
         (name = "roles", description = "Roles endpoints"),
         (name = "scopes", description = "Scopes endpoints"),
         (name = "events", description = "Events Stream"),
         (name = "providers", description = "Upstream Auth Providers"),
         (name = "health", description = "Ping, Health, Ready Check"),
         (name = "blacklist", description = "IP Blacklist endpoints"),
+        (name = "caches", description = "Cache inspection"),
+        (name = "cleanup", description = "Cleanup jobs for expired auth artifacts"),
+        (name = "cluster", description = "Registered Rauthy instances and their versions"),
+        (name = "email", description = "E-Mail delivery status"),
         (name = "api_keys", description = "API Keys endpoints"),
         (name = "generic", description = "Generic endpoints"),
+        (name = "jobs", description = "Persistent background jobs"),
+        (name = "lab", description = "Lab mode for security testing"),
+        (name = "login_traces", description = "Login debug traces"),
+        (name = "logs", description = "Application logs stream"),
         (name = "webid", description = "WebID endpoints"),
         (name = "deprecated", description = "Deprecated endpoints - will be removed in a future version"),
     ),
+    modifiers(&SecurityAddon, &ErrorResponsesAddon),
 )]
 
 pub struct ApiDoc;
 
+/// Registers all security schemes used by the `security(...)` attributes of the endpoints.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let components = openapi
+            .components
+            .get_or_insert_with(openapi::Components::default);
+
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                COOKIE_SESSION,
+                "Session cookie. All modifying requests additionally need the `csrf-token` \
+                header with the value from the session.",
+            ))),
+        );
+        components.add_security_scheme(
+            "session_init",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                COOKIE_SESSION,
+                "Session cookie, which does not need to be authenticated. It is created by \
+                `GET /oidc/authorize` before the login and is used by the public login, \
+                password reset and recovery flows.",
+            ))),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "API Key in the format `API-Key <name>$<secret>`",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
+
+/// Makes sure every endpoint documents its possible error responses, which are not always
+/// listed explicitly on the endpoints themselves.
+struct ErrorResponsesAddon;
+
+impl Modify for ErrorResponsesAddon {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        for item in openapi.paths.paths.values_mut() {
+            for op in item.operations.values_mut() {
+                let is_secured = op.security.as_ref().map(|s| !s.is_empty()).unwrap_or(false);
+                let responses = &mut op.responses.responses;
+
+                if is_secured {
+                    responses
+                        .entry("401".to_string())
+                        .or_insert_with(|| error_response("Unauthorized"));
+                    responses
+                        .entry("403".to_string())
+                        .or_insert_with(|| error_response("Forbidden"));
+                }
+                responses
+                    .entry("500".to_string())
+                    .or_insert_with(|| error_response("Internal Server Error"));
+            }
+        }
+    }
+}
+
+fn error_response(description: &str) -> RefOr<openapi::Response> {
+    RefOr::T(
+        ResponseBuilder::new()
+            .description(description)
+            .content(
+                APPLICATION_JSON,
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ErrorResponse"))
+                    .build(),
+            )
+            .content(
+                APPLICATION_PROBLEM_JSON,
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ProblemDetails"))
+                    .build(),
+            )
+            .build(),
+    )
+}
+
 impl ApiDoc {
     pub fn build(app_state: &web::Data<AppState>) -> openapi::OpenApi {
         let mut doc = Self::openapi();
@@ -0,0 +1,235 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::organizations::{Organization, OrganizationMember};
+use rauthy_models::entity::users::User;
+use rauthy_models::request::{OrganizationMemberRequest, OrganizationRequest};
+use rauthy_models::response::{OrganizationMemberResponse, OrganizationResponse};
+
+/// Returns all existing *organizations*
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/organizations",
+    tag = "organizations",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [OrganizationResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/organizations")]
+pub async fn get_organizations(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Read)?;
+
+    let orgs = Organization::find_all(&data)
+        .await?
+        .into_iter()
+        .map(OrganizationResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(orgs))
+}
+
+/// Adds a new organization
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/organizations",
+    tag = "organizations",
+    request_body = OrganizationRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = OrganizationResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/organizations")]
+pub async fn post_organization(
+    data: web::Data<AppState>,
+    payload: actix_web_validator::Json<OrganizationRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal
+        .validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Create)?;
+
+    Organization::create(&data, payload.into_inner())
+        .await
+        .map(|o| HttpResponse::Ok().json(OrganizationResponse::from(o)))
+}
+
+/// Modifies an organizations name and domains
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/organizations/{id}",
+    tag = "organizations",
+    request_body = OrganizationRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = OrganizationResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/organizations/{id}")]
+pub async fn put_organization(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: actix_web_validator::Json<OrganizationRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal
+        .validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Update)?;
+
+    Organization::update(&data, &id.into_inner(), payload.into_inner())
+        .await
+        .map(|o| HttpResponse::Ok().json(OrganizationResponse::from(o)))
+}
+
+/// Deletes an organization
+///
+/// All memberships will be removed as well and this operation cannot be reverted.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/organizations/{id}",
+    tag = "organizations",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/organizations/{id}")]
+pub async fn delete_organization(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal
+        .validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Delete)?;
+
+    Organization::delete(&data, &id.into_inner())
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
+
+/// Returns all members of an organization
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/organizations/{id}/members",
+    tag = "organizations",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [OrganizationMemberResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/organizations/{id}/members")]
+pub async fn get_organization_members(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Read)?;
+
+    let org = Organization::find(&data, &id.into_inner()).await?;
+    let members = OrganizationMember::find_all(&data, &org.id)
+        .await?
+        .into_iter()
+        .map(OrganizationMemberResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// Adds a user to an organization or updates the owner flag of an existing member
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/organizations/{id}/members/{user_id}",
+    tag = "organizations",
+    request_body = OrganizationMemberRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = OrganizationMemberResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/organizations/{id}/members/{user_id}")]
+pub async fn put_organization_member(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    payload: actix_web_validator::Json<OrganizationMemberRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal
+        .validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Update)?;
+
+    let (id, user_id) = path.into_inner();
+    let org = Organization::find(&data, &id).await?;
+    let user = User::find(&data, user_id).await?;
+
+    OrganizationMember::upsert(&data, org.id, user.id, payload.is_owner)
+        .await
+        .map(|m| HttpResponse::Ok().json(OrganizationMemberResponse::from(m)))
+}
+
+/// Removes a user from an organization
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/organizations/{id}/members/{user_id}",
+    tag = "organizations",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/organizations/{id}/members/{user_id}")]
+pub async fn delete_organization_member(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal
+        .validate_api_key_or_admin_session(AccessGroup::Organizations, AccessRights::Update)?;
+
+    let (id, user_id) = path.into_inner();
+    OrganizationMember::delete(&data, &id, &user_id)
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
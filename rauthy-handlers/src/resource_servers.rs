@@ -0,0 +1,132 @@
+use crate::ReqPrincipal;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
+use rauthy_models::entity::resource_servers::ResourceServer;
+use rauthy_models::request::ResourceServerRequest;
+use rauthy_models::response::ResourceServerResponse;
+
+/// Returns all registered resource servers
+///
+/// These can be targeted with the `resource` parameter from RFC 8707 during authorization and
+/// token requests.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/resource_servers",
+    tag = "scopes",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [ResourceServerResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/resource_servers")]
+pub async fn get_resource_servers(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Scopes, AccessRights::Read)?;
+
+    let res = ResourceServer::find_all(&data)
+        .await?
+        .into_iter()
+        .map(ResourceServerResponse::from)
+        .collect::<Vec<ResourceServerResponse>>();
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Registers a new resource server
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/resource_servers",
+    tag = "scopes",
+    request_body = ResourceServerRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = ResourceServerResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/resource_servers")]
+pub async fn post_resource_server(
+    data: web::Data<AppState>,
+    payload: actix_web_validator::Json<ResourceServerRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Scopes, AccessRights::Create)?;
+
+    ResourceServer::create(&data, payload.into_inner())
+        .await
+        .map(|rs| HttpResponse::Ok().json(ResourceServerResponse::from(rs)))
+}
+
+/// Modifies a resource server
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/resource_servers/{id}",
+    tag = "scopes",
+    request_body = ResourceServerRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = ResourceServerResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/resource_servers/{id}")]
+pub async fn put_resource_server(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: actix_web_validator::Json<ResourceServerRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Scopes, AccessRights::Update)?;
+
+    ResourceServer::update(&data, id.into_inner(), payload.into_inner())
+        .await
+        .map(|rs| HttpResponse::Ok().json(ResourceServerResponse::from(rs)))
+}
+
+/// Deletes a resource server
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/resource_servers/{id}",
+    tag = "scopes",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/resource_servers/{id}")]
+pub async fn delete_resource_server(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Scopes, AccessRights::Delete)?;
+
+    ResourceServer::delete(&data, id.as_str())
+        .await
+        .map(|_| HttpResponse::Ok().finish())
+}
@@ -14,6 +14,7 @@ use rauthy_models::request::NewRoleRequest;
     get,
     path = "/roles",
     tag = "roles",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [Role]),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -41,6 +42,7 @@ pub async fn get_roles(
     path = "/roles",
     tag = "roles",
     request_body = NewRoleRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Role),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -69,6 +71,7 @@ pub async fn post_role(
     path = "/roles/{id}",
     tag = "roles",
     request_body = NewRoleRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Role),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -99,6 +102,7 @@ pub async fn put_role(
     delete,
     path = "/roles/{id}",
     tag = "roles",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
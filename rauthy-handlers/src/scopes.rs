@@ -15,6 +15,7 @@ use rauthy_models::response::ScopeResponse;
     get,
     path = "/scopes",
     tag = "scopes",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [Scope]),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -46,6 +47,7 @@ pub async fn get_scopes(
     path = "/scopes",
     tag = "scopes",
     request_body = ScopeRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Scope),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -65,7 +67,10 @@ pub async fn post_scope(
         .map(|s| HttpResponse::Ok().json(s))
 }
 
-/// Modifies a scopes name
+/// Modifies a scope
+///
+/// If `client_ids` are given, the scope will be removed from all other clients and it cannot be
+/// assigned to them anymore until the restriction is lifted.
 ///
 /// **Permissions**
 /// - rauthy_admin
@@ -74,6 +79,7 @@ pub async fn post_scope(
     path = "/scopes/{id}",
     tag = "scopes",
     request_body = ScopeRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = Scope),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -104,6 +110,7 @@ pub async fn put_scope(
     delete,
     path = "/scopes/{id}",
     tag = "scopes",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1,16 +1,19 @@
 use crate::ReqPrincipal;
-use actix_web::{delete, get, web, HttpResponse};
-use actix_web_validator::Query;
+use actix_web::{delete, get, post, web, HttpResponse};
+use actix_web_validator::{Json, Query};
 use rauthy_common::constants::SSP_THRESHOLD;
-use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
 use rauthy_models::entity::continuation_token::ContinuationToken;
 use rauthy_models::entity::refresh_tokens::RefreshToken;
+use rauthy_models::entity::revoked_tokens::RevokedToken;
 use rauthy_models::entity::sessions::Session;
 use rauthy_models::entity::users::User;
-use rauthy_models::request::PaginationParams;
-use rauthy_models::response::SessionResponse;
+use rauthy_models::request::{DryRunParams, PaginationParams, TokenRevocationRequest};
+use rauthy_models::response::{SessionResponse, SessionsDryRunResponse};
+use rauthy_models::JwtCommonClaims;
+use rauthy_service::auth;
 
 /// Returns all existing sessions
 ///
@@ -23,6 +26,7 @@ use rauthy_models::response::SessionResponse;
     path = "/sessions",
     tag = "sessions",
     params(PaginationParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [SessionResponse]),
         (status = 401, description = "Unauthorized"),
@@ -108,14 +112,19 @@ pub async fn get_sessions(
 ///
 /// **Important:** Since JWT Tokens are stateless, it cannot invalidate already existing tokens.
 ///
+/// With `?dry_run=true`, nothing will be invalidated. Instead, all affected sessions and the
+/// amount of affected refresh tokens will be returned.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
     delete,
     path = "/sessions",
     tag = "sessions",
+    params(DryRunParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
-        (status = 200, description = "Ok"),
+        (status = 200, description = "Ok", body = SessionsDryRunResponse),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
     ),
@@ -124,9 +133,14 @@ pub async fn get_sessions(
 pub async fn delete_sessions(
     data: web::Data<AppState>,
     principal: ReqPrincipal,
+    params: Query<DryRunParams>,
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Sessions, AccessRights::Delete)?;
 
+    if params.is_dry_run() {
+        return sessions_dry_run(&data, None).await;
+    }
+
     Session::invalidate_all(&data).await?;
     RefreshToken::invalidate_all(&data).await?;
 
@@ -137,14 +151,19 @@ pub async fn delete_sessions(
 ///
 ///**Important:** Since JWT Tokens are stateless, it cannot invalidate already existing tokens.
 ///
+/// With `?dry_run=true`, nothing will be invalidated. Instead, all affected sessions and the
+/// amount of affected refresh tokens will be returned.
+///
 /// **Permissions**
 /// - rauthy_admin
 #[utoipa::path(
     delete,
     path = "/sessions/{user_id}",
     tag = "sessions",
+    params(DryRunParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
-        (status = 200, description = "Ok"),
+        (status = 200, description = "Ok", body = SessionsDryRunResponse),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
     ),
@@ -154,12 +173,152 @@ pub async fn delete_sessions_for_user(
     data: web::Data<AppState>,
     path: web::Path<String>,
     principal: ReqPrincipal,
+    params: Query<DryRunParams>,
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Sessions, AccessRights::Delete)?;
 
     let uid = path.into_inner();
+    if params.is_dry_run() {
+        return sessions_dry_run(&data, Some(&uid)).await;
+    }
+
     Session::invalidate_for_user(&data, &uid).await?;
     RefreshToken::invalidate_for_user(&data, &uid).await?;
 
     Ok(HttpResponse::Ok().finish())
 }
+
+/// Returns all revoked, not yet expired tokens
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/revoked_tokens",
+    tag = "sessions",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [RevokedToken]),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+)]
+#[get("/revoked_tokens")]
+pub async fn get_revoked_tokens(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Sessions, AccessRights::Read)?;
+
+    let tokens = RevokedToken::find_all(&data).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Revokes a single JWT by its `jti` before it expires
+///
+/// The token will be rejected by all validation and introspection endpoints on all cluster
+/// members afterward. Either the full `token` must be given, or its `jti` together with its
+/// `exp`. Tokens without a `jti` cannot be revoked.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/revoked_tokens",
+    tag = "sessions",
+    request_body = TokenRevocationRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = RevokedToken),
+        (status = 400, description = "BadRequest"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+)]
+#[post("/revoked_tokens")]
+pub async fn post_revoked_token(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    payload: Json<TokenRevocationRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Sessions, AccessRights::Create)?;
+
+    let payload = payload.into_inner();
+    let (jti, exp) = if let Some(token) = payload.token {
+        let claims = auth::validate_token::<JwtCommonClaims>(&data, &token).await?;
+        let jti = claims.jwt_id.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The token has no 'jti' and cannot be revoked".to_string(),
+            )
+        })?;
+        let exp = claims
+            .expires_at
+            .map(|exp| exp.as_secs() as i64)
+            .unwrap_or_default();
+        (jti, exp)
+    } else if let (Some(jti), Some(exp)) = (payload.jti, payload.exp) {
+        (jti, exp)
+    } else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Either the 'token' or the 'jti' together with 'exp' must be given".to_string(),
+        ));
+    };
+
+    let revoked = RevokedToken::create(&data, jti, exp, payload.reason).await?;
+    Ok(HttpResponse::Ok().json(revoked))
+}
+
+/// Removes a token from the revocation list
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/revoked_tokens/{jti}",
+    tag = "sessions",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "NotFound"),
+    ),
+)]
+#[delete("/revoked_tokens/{jti}")]
+pub async fn delete_revoked_token(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Sessions, AccessRights::Delete)?;
+
+    RevokedToken::delete(&data, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn sessions_dry_run(
+    data: &web::Data<AppState>,
+    user_id: Option<&str>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let sessions = Session::find_valid(data, user_id).await?;
+    let refresh_tokens = RefreshToken::count_valid(data, user_id).await?;
+
+    let resp = SessionsDryRunResponse {
+        sessions: sessions
+            .iter()
+            .map(|s| SessionResponse {
+                id: &s.id,
+                user_id: s.user_id.as_deref(),
+                is_mfa: s.is_mfa,
+                state: &s.state,
+                exp: s.exp,
+                last_seen: s.last_seen,
+                remote_ip: s.remote_ip.as_deref(),
+            })
+            .collect(),
+        refresh_tokens,
+    };
+    Ok(HttpResponse::Ok().json(resp))
+}
@@ -1,43 +1,64 @@
 use crate::ReqPrincipal;
-use actix_web::http::header::LOCATION;
+use actix_web::http::header::{CONTENT_DISPOSITION, LOCATION};
 use actix_web::http::StatusCode;
 use actix_web::{cookie, delete, get, post, put, web, HttpRequest, HttpResponse, ResponseError};
 use actix_web_validator::{Json, Query};
 use rauthy_common::constants::{
-    COOKIE_MFA, ENABLE_WEB_ID, HEADER_ALLOW_ALL_ORIGINS, HEADER_HTML, OPEN_USER_REG,
-    PWD_RESET_COOKIE, SSP_THRESHOLD, TEXT_TURTLE, USER_REG_DOMAIN_RESTRICTION,
+    COOKIE_MFA, EMAIL_MFA_ENABLE, ENABLE_WEB_ID, HEADER_ALLOW_ALL_ORIGINS, HEADER_HTML,
+    OPEN_USER_REG, PUSH_MFA_ENABLE, PWD_RESET_COOKIE, SSP_THRESHOLD, TEXT_TURTLE,
+    USER_REG_DOMAIN_RESTRICTION,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::real_ip_from_req;
+use rauthy_common::utils::{is_trusted_login_ip, real_ip_from_req};
 use rauthy_models::app_state::AppState;
+use rauthy_models::email::send_email_mfa;
 use rauthy_models::entity::api_keys::{AccessGroup, AccessRights};
 use rauthy_models::entity::colors::ColorEntity;
 use rauthy_models::entity::continuation_token::ContinuationToken;
 use rauthy_models::entity::devices::DeviceEntity;
+use rauthy_models::entity::email_aliases::EmailAlias;
+use rauthy_models::entity::email_mfa::{EmailMfaCode, EmailMfaPurpose};
+use rauthy_models::entity::legal_holds::LegalHold;
+use rauthy_models::entity::login_policies::LoginPolicyOverride;
 use rauthy_models::entity::password::PasswordPolicy;
 use rauthy_models::entity::pow::PowEntity;
+use rauthy_models::entity::push_mfa::{PushMfaRequest, PushSubscription};
+use rauthy_models::entity::user_activity::UserActivity;
 use rauthy_models::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
-use rauthy_models::entity::users::User;
+use rauthy_models::entity::user_client_grants::UserClientGrant;
+use rauthy_models::entity::user_merges::UserMerge;
+use rauthy_models::entity::user_role_grants::UserRoleGrant;
+use rauthy_models::entity::users::{AccountType, CredentialChange, User};
 use rauthy_models::entity::users_values::UserValues;
+use rauthy_models::entity::web_push::VapidKey;
 use rauthy_models::entity::webauthn;
-use rauthy_models::entity::webauthn::PasskeyEntity;
+use rauthy_models::entity::webauthn::{PasskeyEntity, WebauthnServiceReq};
 use rauthy_models::entity::webids::WebId;
 use rauthy_models::events::event::Event;
 use rauthy_models::language::Language;
 use rauthy_models::request::{
-    DeviceRequest, MfaPurpose, NewUserRegistrationRequest, NewUserRequest, PaginationParams,
-    PasswordResetRequest, RequestResetRequest, UpdateUserRequest, UpdateUserSelfRequest,
-    UserAttrConfigRequest, UserAttrValuesUpdateRequest, WebIdRequest, WebauthnAuthFinishRequest,
-    WebauthnAuthStartRequest, WebauthnRegFinishRequest, WebauthnRegStartRequest,
+    AdminPasswordResetRequest, DeviceRequest, DryRunParams, EmailAliasRequest,
+    EmailMfaUpdateRequest, InactiveUsersParams, LegalHoldRequest, LoginPolicyOverrideRequest,
+    MfaPurpose, MfaRecoveryCodeRequest, MfaRecoveryRequest, NewUserRegistrationRequest,
+    NewUserRequest, PaginationParams, PasswordResetRequest, ProvisioningAction,
+    ProvisioningRequest, PushMfaApproveRequest, PushMfaDenyRequest, PushSubscriptionRequest,
+    RequestResetRequest, SessionElevateRequest, UpdateUserRequest, UpdateUserSelfRequest,
+    UserAttrConfigRequest, UserAttrValuesUpdateRequest, UserMergeRequest, UserPreferencesRequest,
+    UserRoleGrantRequest, WebIdRequest, WebauthnAuthFinishRequest, WebauthnAuthStartRequest,
+    WebauthnRegFinishRequest, WebauthnRegStartRequest,
 };
 use rauthy_models::response::{
-    DeviceResponse, PasskeyResponse, UserAttrConfigResponse, UserAttrValueResponse,
-    UserAttrValuesResponse, UserResponse, WebIdResponse,
+    DeviceResponse, EmailAliasResponse, EmailMfaResponse, InactiveUserResponse, LegalHoldResponse,
+    LoginPolicyOverrideResponse, PasskeyResponse, PushSubscriptionResponse,
+    PushSubscriptionsResponse, SessionElevationResponse, UserAttrConfigResponse,
+    UserAttrValueResponse, UserAttrValuesResponse, UserResponse, UserRoleGrantResponse,
+    WebIdResponse,
 };
 use rauthy_models::templates::{Error1Html, Error3Html, ErrorHtml, UserRegisterHtml};
-use rauthy_service::password_reset;
+use rauthy_service::{account, auth, mfa_recovery, password_reset};
 use spow::pow::Pow;
 use std::ops::Add;
+use std::time::{SystemTime, UNIX_EPOCH};
 use time::OffsetDateTime;
 use tracing::{error, warn};
 
@@ -52,6 +73,7 @@ use tracing::{error, warn};
     path = "/users",
     tag = "users",
     params(PaginationParams),
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = [UserResponse]),
         (status = 401, description = "Unauthorized"),
@@ -113,6 +135,7 @@ pub async fn get_users(
     path = "/users",
     tag = "users",
     request_body = NewUserRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized"),
@@ -131,15 +154,17 @@ pub async fn post_users(
     let user = User::create_from_new(&data, user.into_inner()).await?;
 
     data.tx_events
-        .send_async(Event::new_user(user.email.clone(), real_ip_from_req(&req)))
+        .send_async(
+            Event::new_user(user.email.clone(), real_ip_from_req(&req)).for_user(user.id.clone()),
+        )
         .await
         .unwrap();
     if user.is_admin() {
         data.tx_events
-            .send_async(Event::new_rauthy_admin(
-                user.email.clone(),
-                real_ip_from_req(&req),
-            ))
+            .send_async(
+                Event::new_rauthy_admin(user.email.clone(), real_ip_from_req(&req))
+                    .for_user(user.id.clone()),
+            )
             .await
             .unwrap();
     }
@@ -147,11 +172,75 @@ pub async fn post_users(
     Ok(HttpResponse::Ok().json(UserResponse::build(user, None)))
 }
 
+/// Inbound provisioning webhook for user lifecycle notices from external systems
+///
+/// A lightweight alternative to SCIM, which can be called by HR tools or similar systems to
+/// `create`, `update` or `deactivate` users. Users are identified by their E-Mail.
+///
+/// **Permissions**
+/// - API Key with `users` `create` access for `create`
+/// - API Key with `users` `update` access for `update` and `deactivate`
+#[utoipa::path(
+    post,
+    path = "/users/provisioning",
+    tag = "users",
+    request_body = ProvisioningRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/provisioning")]
+pub async fn post_users_provisioning(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: Json<ProvisioningRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let payload = payload.into_inner();
+    let is_create = payload.action == ProvisioningAction::Create;
+    let access_rights = if is_create {
+        AccessRights::Create
+    } else {
+        AccessRights::Update
+    };
+    principal.validate_api_key(AccessGroup::Users, access_rights)?;
+
+    let (user, is_new_admin) = User::provision(&data, payload).await?;
+
+    if is_create {
+        data.tx_events
+            .send_async(
+                Event::new_user(user.email.clone(), real_ip_from_req(&req))
+                    .for_user(user.id.clone()),
+            )
+            .await
+            .unwrap();
+    }
+    if is_new_admin {
+        data.tx_events
+            .send_async(
+                Event::new_rauthy_admin(user.email.clone(), real_ip_from_req(&req))
+                    .for_user(user.id.clone()),
+            )
+            .await
+            .unwrap();
+    }
+
+    let values = UserValues::find(&data, &user.id).await?;
+    Ok(HttpResponse::Ok().json(UserResponse::build(user, values)))
+}
+
 /// Get the configured / allowed additional custom user attribute
 #[utoipa::path(
     get,
     path = "/users/attr",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserAttrConfigResponse),
         (status = 403, description = "Forbidden"),
@@ -175,6 +264,7 @@ pub async fn get_cust_attr(
     path = "/users/attr",
     tag = "users",
     request_body = UserAttrConfigRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 403, description = "Forbidden"),
@@ -202,6 +292,7 @@ pub async fn post_cust_attr(
     path = "/users/attr/{name}",
     tag = "users",
     request_body = UserAttrConfigRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 403, description = "Forbidden"),
@@ -227,6 +318,7 @@ pub async fn put_cust_attr(
     delete,
     path = "/users/attr/{name}",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 204, description = "NoContent"),
         (status = 403, description = "Forbidden"),
@@ -319,15 +411,17 @@ pub async fn post_users_register(
         }
     }
 
-    // validate the PoW
-    let challenge = Pow::validate(&req_data.pow)?;
-    PowEntity::check_prevent_reuse(&data, challenge.to_string()).await?;
+    // validate the PoW - skipped for trusted networks
+    if !is_trusted_login_ip(real_ip_from_req(&req).as_deref()) {
+        let challenge = Pow::validate(&req_data.pow)?;
+        PowEntity::check_prevent_reuse(&data, challenge.to_string()).await?;
+    }
 
     let lang = Language::try_from(&req).unwrap_or_default();
     let user = User::create_from_reg(&data, req_data.into_inner(), lang).await?;
 
     data.tx_events
-        .send_async(Event::new_user(user.email, real_ip_from_req(&req)))
+        .send_async(Event::new_user(user.email, real_ip_from_req(&req)).for_user(user.id))
         .await
         .unwrap();
 
@@ -341,6 +435,7 @@ pub async fn post_users_register(
     get,
     path = "/users/{id}",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -365,8 +460,9 @@ pub async fn get_user_by_id(
 
     let user = User::find(&data, id).await?;
     let values = UserValues::find(&data, &user.id).await?;
+    let activity = UserActivity::find(&data, &user.id).await?;
 
-    Ok(HttpResponse::Ok().json(UserResponse::build(user, values)))
+    Ok(HttpResponse::Ok().json(UserResponse::build(user, values).with_activity(activity)))
 }
 
 /// Returns the additional custom attributes for the given user id
@@ -374,6 +470,7 @@ pub async fn get_user_by_id(
     get,
     path = "/users/{id}/attr",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserAttrValuesResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -402,6 +499,7 @@ pub async fn get_user_attr(
     path = "/users/{id}/attr",
     tag = "users",
     request_body = UserAttrValuesUpdateRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 204, description = "NoContent"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -431,6 +529,7 @@ pub async fn put_user_attr(
     get,
     path = "/users/{id}/devices",
     tag = "users",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = DeviceResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -460,6 +559,7 @@ pub async fn get_user_devices(
     put,
     path = "/users/{id}/devices",
     tag = "users",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -491,6 +591,7 @@ pub async fn put_user_device_name(
     delete,
     path = "/users/{id}/devices",
     tag = "users",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -547,7 +648,7 @@ pub async fn get_user_email_confirm(
         Err(err) => {
             let colors = ColorEntity::find_rauthy(&data).await.unwrap_or_default();
             let status = err.status_code();
-            let body = Error3Html::build(&colors, &lang, status, Some(err.message));
+            let body = Error3Html::build_for_err(&colors, &lang, &err);
             ErrorHtml::response(body, status)
         }
     }
@@ -583,7 +684,7 @@ pub async fn get_user_password_reset(
         Err(err) => {
             let colors = ColorEntity::find_rauthy(&data).await.unwrap_or_default();
             let status = err.status_code();
-            let body = Error3Html::build(&colors, &lang, status, Some(err.message));
+            let body = Error3Html::build_for_err(&colors, &lang, &err);
             ErrorHtml::response(body, status)
         }
     }
@@ -639,6 +740,52 @@ pub async fn put_user_password_reset(
     })
 }
 
+/// Starts a password reset for a user with a chosen delivery channel
+///
+/// - `email`: sends a reset link via E-Mail
+/// - `otp`: replaces the current password with a one-time password, which is valid for 10
+///   minutes and only returned in this response. It can be used for a single login in the UI,
+///   which redirects to a password reset instead of creating a session.
+/// - `sms`: sends a reset link to the users phone number, if `SMS_GATEWAY_URL` is configured
+///
+/// Each channel generates its own event.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/reset",
+    tag = "users",
+    request_body = AdminPasswordResetRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = AdminPasswordResetResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/reset")]
+pub async fn post_user_password_reset_admin(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: Json<AdminPasswordResetRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    password_reset::handle_admin_password_reset(
+        &data,
+        req,
+        id.into_inner(),
+        payload.into_inner().channel,
+    )
+    .await
+    .map(|res| HttpResponse::Ok().json(res))
+}
+
 /// Get all registered Webauthn Passkeys for a user
 ///
 /// **Permissions**
@@ -648,6 +795,7 @@ pub async fn put_user_password_reset(
     get,
     path = "/users/{id}/webauthn",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -689,6 +837,7 @@ pub async fn get_user_webauthn_passkeys(
     path = "/users/{id}/webauthn/auth/start",
     tag = "mfa",
     request_body = WebauthnAuthStartRequest,
+    security((), ("session_init" = [])),
     responses(
         (status = 200, description = "Ok", body = WebauthnAuthStartResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -795,6 +944,7 @@ pub async fn post_webauthn_auth_finish(
     delete,
     path = "/users/{id}/webauthn/delete/{name}",
     tag = "mfa",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -805,6 +955,7 @@ pub async fn post_webauthn_auth_finish(
 pub async fn delete_webauthn(
     data: web::Data<AppState>,
     path: web::Path<(String, String)>,
+    req: HttpRequest,
     principal: ReqPrincipal,
 ) -> Result<HttpResponse, ErrorResponse> {
     // Note: Currently, this is not allowed with an ApiKey on purpose
@@ -821,6 +972,7 @@ pub async fn delete_webauthn(
     // validate that Principal matches the user or is an admin
     if !is_admin {
         principal.is_user(&id)?;
+        principal.get_session()?.validate_elevated()?;
         warn!("Passkey delete for user {} for key {}", id, name);
     } else {
         warn!("Passkey delete from admin for user {} for key {}", id, name);
@@ -828,12 +980,20 @@ pub async fn delete_webauthn(
 
     // if we delete a passkey, we must check if this is the last existing one for the user
     let pks = PasskeyEntity::find_for_user(&data, &id).await?;
+    let mut user = User::find(&data, id.clone()).await?;
+
+    // a passkey only account would be left without any credentials
+    if !is_admin && pks.len() < 2 && user.account_type() == AccountType::Passkey {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The last passkey of a passkey only account cannot be deleted".to_string(),
+        ));
+    }
 
     let mut txn = data.db.begin().await?;
 
     PasskeyEntity::delete_by_id_name(&data, &id, &name, Some(&mut txn)).await?;
     if pks.len() < 2 {
-        let mut user = User::find(&data, id).await?;
         user.webauthn_user_id = None;
 
         // in this case, we need to check against the current password policy, if the password
@@ -857,6 +1017,14 @@ pub async fn delete_webauthn(
         txn.commit().await?;
     }
 
+    user.revoke_sessions_on_credential_change(
+        &data,
+        CredentialChange::PasskeyRemoved,
+        Some(principal.get_session()?.id.as_str()),
+        real_ip_from_req(&req),
+    )
+    .await?;
+
     // make sure to delete any existing MFA cookie when a key is deleted
     let cookie = cookie::Cookie::build(COOKIE_MFA, "")
         .secure(true)
@@ -881,6 +1049,7 @@ pub async fn delete_webauthn(
     path = "/users/{id}/webauthn/register/start",
     tag = "mfa",
     request_body = WebauthnRegStartRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok - Returns a default Webauthn CreationChallengeResponse, which cannot be serialized into OpenAPI schema currently"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -913,6 +1082,7 @@ pub async fn post_webauthn_reg_start(
         // validate that Principal matches the user
         let id = id.into_inner();
         principal.is_user(&id)?;
+        principal.get_session()?.validate_elevated()?;
 
         webauthn::reg_start(&data, id, req_data.into_inner())
             .await
@@ -929,6 +1099,7 @@ pub async fn post_webauthn_reg_start(
     path = "/users/{id}/webauthn/register/finish",
     tag = "mfa",
     request_body = WebauthnRegFinishRequest,
+    security(("session" = [])),
     responses(
         (status = 201, description = "Created"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -961,8 +1132,9 @@ pub async fn post_webauthn_reg_finish(
         // validate that Principal matches the user
         let id = id.into_inner();
         principal.is_user(&id)?;
+        principal.get_session()?.validate_elevated()?;
 
-        webauthn::reg_finish(&data, id, req_data.into_inner()).await?;
+        webauthn::reg_finish(&data, id, req_data.into_inner(), false).await?;
         Ok(HttpResponse::Created().finish())
     }
 }
@@ -1013,6 +1185,7 @@ pub async fn get_user_webid(
     get,
     path = "/users/{id}/webid/data",
     tag = "users",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = WebId),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1053,6 +1226,7 @@ pub async fn get_user_webid_data(
     path = "/users/{id}/webid/data",
     tag = "users",
     request_body = WebIdRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1105,6 +1279,7 @@ pub async fn put_user_webid_data(
     path = "/users/request_reset",
     tag = "users",
     request_body = RequestResetRequest,
+    security(("session_init" = [])),
     responses(
         (status = 200, description = "Ok"),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1141,6 +1316,7 @@ pub async fn post_user_password_request_reset(
     get,
     path = "/users/email/{email}",
     tag = "users",
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1170,6 +1346,7 @@ pub async fn get_user_by_email(
     path = "/users/{id}",
     tag = "users",
     request_body = UpdateUserRequest,
+    security(("api_key" = []), ("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1186,15 +1363,28 @@ pub async fn put_user_by_id(
 ) -> Result<HttpResponse, ErrorResponse> {
     principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
 
+    let password_changed = user.password.is_some();
     let (user, user_values, is_new_admin) =
         User::update(&data, id.into_inner(), user.into_inner(), None).await?;
 
+    if password_changed {
+        // the admin session will only be kept, if an admin updates its own password
+        let keep_sid = principal.session.as_ref().map(|s| s.id.as_str());
+        user.revoke_sessions_on_credential_change(
+            &data,
+            CredentialChange::Password,
+            keep_sid,
+            real_ip_from_req(&req),
+        )
+        .await?;
+    }
+
     if is_new_admin {
         data.tx_events
-            .send_async(Event::new_rauthy_admin(
-                user.email.clone(),
-                real_ip_from_req(&req),
-            ))
+            .send_async(
+                Event::new_rauthy_admin(user.email.clone(), real_ip_from_req(&req))
+                    .for_user(user.id.clone()),
+            )
             .await
             .unwrap();
     }
@@ -1211,6 +1401,7 @@ pub async fn put_user_by_id(
     path = "/users/{id}/self",
     tag = "users",
     request_body = UpdateUserSelfRequest,
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1221,6 +1412,7 @@ pub async fn put_user_by_id(
 pub async fn put_user_self(
     data: web::Data<AppState>,
     id: web::Path<String>,
+    req: HttpRequest,
     principal: ReqPrincipal,
     user: Json<UpdateUserSelfRequest>,
 ) -> Result<HttpResponse, ErrorResponse> {
@@ -1230,8 +1422,27 @@ pub async fn put_user_self(
     let id = id.into_inner();
     principal.is_user(&id)?;
 
+    let password_changed = user.password_new.is_some();
+    let email_changed = if let Some(email) = &user.email {
+        User::find(&data, id.clone()).await?.email != email.to_lowercase()
+    } else {
+        false
+    };
+    if password_changed || email_changed {
+        principal.get_session()?.validate_elevated()?;
+    }
     let (user, user_values, email_updated) =
         User::update_self_req(&data, id, user.into_inner()).await?;
+
+    if password_changed {
+        user.revoke_sessions_on_credential_change(
+            &data,
+            CredentialChange::Password,
+            Some(principal.get_session()?.id.as_str()),
+            real_ip_from_req(&req),
+        )
+        .await?;
+    }
     if email_updated {
         Ok(HttpResponse::Accepted().json(UserResponse::build(user, user_values)))
     } else {
@@ -1247,6 +1458,7 @@ pub async fn put_user_self(
     post,
     path = "/users/{id}/self/convert_passkey",
     tag = "users",
+    security(("session" = [])),
     responses(
         (status = 200, description = "Ok", body = UserResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
@@ -1264,23 +1476,1404 @@ pub async fn post_user_self_convert_passkey(
     // make sure the logged in user can only update itself
     let id = id.into_inner();
     principal.is_user(&id)?;
+    principal.get_session()?.validate_elevated()?;
 
     User::convert_to_passkey(&data, id).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
-/// Deletes a user
+/// Elevates the current session after a fresh re-authentication ("sudo mode")
+///
+/// Changing the E-Mail, the password or passkeys needs an elevated session, if
+/// `SESSION_ELEVATION_ENABLE` is set. Users without MFA provide their current password. Users
+/// with passkeys provide the code from a finished passkey authentication with
+/// `MfaPurpose::PasswordNew`. Users with E-Mail MFA use a one-time code from
+/// `POST /users/{id}/self/elevate/email_mfa`. Failed password attempts get the same delays
+/// and IP blacklisting as a failed login.
 ///
 /// **Permissions**
-/// - rauthy_admin
+/// - authenticated user
 #[utoipa::path(
-    delete,
-    path = "/users/{id}",
+    post,
+    path = "/users/{id}/self/elevate",
     tag = "users",
+    request_body = SessionElevateRequest,
+    security(("session" = [])),
     responses(
-        (status = 204, description = "NoContent"),
+        (status = 200, description = "Ok", body = SessionElevationResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/self/elevate")]
+pub async fn post_user_self_elevate(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    req: HttpRequest,
+    principal: ReqPrincipal,
+    payload: Json<SessionElevateRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let id = id.into_inner();
+    principal.is_user(&id)?;
+
+    let payload = payload.into_inner();
+    let user = User::find(&data, id).await?;
+    if let Some(password) = payload.password {
+        // the password alone would be a downgrade for users with a 2nd factor
+        if user.has_mfa_enabled() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "Users with MFA must elevate their session with their 2nd factor".to_string(),
+            ));
+        }
+
+        // the same delays and IP blacklisting as for a login protect against brute-force
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let res = user
+            .validate_password(&data, password)
+            .await
+            .map(|_| (HttpResponse::Ok().finish(), true))
+            .map_err(|err| (err, true));
+        auth::handle_login_delay(
+            &data,
+            real_ip_from_req(&req),
+            Some(user.email.clone()),
+            start,
+            &data.caches.ha_cache_config,
+            res,
+        )
+        .await?;
+    } else if let Some(code) = payload.mfa_code {
+        let svc_req = WebauthnServiceReq::find(&data, code).await?;
+        if svc_req.user_id != user.id {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "User ID does not match".to_string(),
+            ));
+        }
+        svc_req.delete(&data).await?;
+    } else if let Some(email_mfa) = payload.email_mfa {
+        let code = EmailMfaCode::find(&data, &email_mfa.code).await?;
+        if code.user_id != user.id {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "User ID does not match".to_string(),
+            ));
+        }
+        code.verify(
+            &data,
+            &principal.get_session()?.id,
+            EmailMfaPurpose::Elevate,
+            &email_mfa.otp,
+        )
+        .await?;
+    } else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Either the password or a passkey authentication is needed".to_string(),
+        ));
+    }
+
+    let mut session = principal.get_session()?.clone();
+    session.elevate(&data).await?;
+
+    Ok(HttpResponse::Ok().json(SessionElevationResponse {
+        elevated_until: session.elevated_until.unwrap_or_default(),
+    }))
+}
+
+/// Sends an E-Mail one-time code for a session elevation
+///
+/// The returned `code` must be sent together with the one-time code as `email_mfa` to
+/// `POST /users/{id}/self/elevate`.
+///
+/// **Permissions**
+/// - authenticated user with E-Mail MFA enabled
+#[utoipa::path(
+    post,
+    path = "/users/{id}/self/elevate/email_mfa",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = EmailMfaResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/self/elevate/email_mfa")]
+pub async fn post_user_self_elevate_email_mfa(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let id = id.into_inner();
+    principal.is_user(&id)?;
+
+    let user = User::find(&data, id).await?;
+    if !user.has_email_mfa_enabled() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "E-Mail MFA is not enabled for this user".to_string(),
+        ));
+    }
+
+    let (code, otp) = EmailMfaCode::create(
+        &data,
+        user.id.clone(),
+        principal.get_session()?.id.clone(),
+        EmailMfaPurpose::Elevate,
+        None,
+        None,
+    )
+    .await?;
+    send_email_mfa(&data, &user, &otp).await;
+
+    Ok(HttpResponse::Ok().json(EmailMfaResponse {
+        code: code.id,
+        exp: code.exp,
+    }))
+}
+
+/// Enables or disables E-Mail one-time codes as the second factor
+///
+/// This is only a fallback for users without any passkey. As soon as a passkey exists, it will
+/// always be preferred.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id} with an elevated session
+#[utoipa::path(
+    put,
+    path = "/users/{id}/self/email_mfa",
+    tag = "mfa",
+    request_body = EmailMfaUpdateRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[put("/users/{id}/self/email_mfa")]
+pub async fn put_user_self_email_mfa(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+    payload: Json<EmailMfaUpdateRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let id = id.into_inner();
+    principal.is_user(&id)?;
+    principal.get_session()?.validate_elevated()?;
+
+    if !*EMAIL_MFA_ENABLE {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "E-Mail MFA is disabled".to_string(),
+        ));
+    }
+
+    let mut user = User::find(&data, id).await?;
+    user.email_mfa = payload.enabled;
+    user.save(&data, None, None).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Returns the registered push subscriptions for login approvals
+///
+/// The `vapid_key` must be used as the `applicationServerKey` for new subscriptions.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+#[utoipa::path(
+    get,
+    path = "/users/{id}/self/push_subscriptions",
+    tag = "mfa",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = PushSubscriptionsResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Push MFA is disabled", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/self/push_subscriptions")]
+pub async fn get_user_self_push_subscriptions(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let id = id.into_inner();
+    principal.is_user(&id)?;
+    push_mfa_enabled()?;
+
+    let vapid_key = VapidKey::get(&data).await?.public_key_b64();
+    let subscriptions = PushSubscription::find_by_user(&data, &id)
+        .await?
+        .into_iter()
+        .map(PushSubscriptionResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PushSubscriptionsResponse {
+        vapid_key,
+        subscriptions,
+    }))
+}
+
+/// Registers a new push subscription for login approvals
+///
+/// Push approvals are only an addition to the existing factors, which is why the user must have
+/// MFA enabled already.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id} with an elevated session
+#[utoipa::path(
+    post,
+    path = "/users/{id}/self/push_subscriptions",
+    tag = "mfa",
+    request_body = PushSubscriptionRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = PushSubscriptionResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
         (status = 401, description = "Unauthorized", body = ErrorResponse),
         (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "Push MFA is disabled", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/self/push_subscriptions")]
+pub async fn post_user_self_push_subscription(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+    payload: Json<PushSubscriptionRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let id = id.into_inner();
+    principal.is_user(&id)?;
+    principal.get_session()?.validate_elevated()?;
+    push_mfa_enabled()?;
+
+    let user = User::find(&data, id).await?;
+    if !user.has_mfa_enabled() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Push approvals require another MFA factor".to_string(),
+        ));
+    }
+
+    let payload = payload.into_inner();
+    let sub = PushSubscription::create(
+        &data,
+        user.id,
+        payload.name,
+        payload.endpoint,
+        payload.p256dh,
+        payload.auth,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(PushSubscriptionResponse::from(sub)))
+}
+
+/// Deletes a push subscription
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/self/push_subscriptions/{sub_id}",
+    tag = "mfa",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/self/push_subscriptions/{sub_id}")]
+pub async fn delete_user_self_push_subscription(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth()?;
+
+    let (id, sub_id) = path.into_inner();
+    principal.is_user(&id)?;
+
+    PushSubscription::delete(&data, &id, &sub_id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Approves a login from the page opened by a push notification
+///
+/// The `number` is shown on the login page and must match. After 3 wrong inputs, the login
+/// is denied.
+#[utoipa::path(
+    post,
+    path = "/push_mfa/{id}/approve",
+    tag = "mfa",
+    request_body = PushMfaApproveRequest,
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "Invalid or expired request", body = ErrorResponse),
+        (status = 401, description = "Wrong number", body = ErrorResponse),
+    ),
+)]
+#[post("/push_mfa/{id}/approve")]
+pub async fn post_push_mfa_approve(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: Json<PushMfaApproveRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    PushMfaRequest::find(&data, &id.into_inner())
+        .await?
+        .approve(&data, &payload.token, payload.number)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Denies a login from the page opened by a push notification
+#[utoipa::path(
+    post,
+    path = "/push_mfa/{id}/deny",
+    tag = "mfa",
+    request_body = PushMfaDenyRequest,
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "Invalid or expired request", body = ErrorResponse),
+    ),
+)]
+#[post("/push_mfa/{id}/deny")]
+pub async fn post_push_mfa_deny(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: Json<PushMfaDenyRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    PushMfaRequest::find(&data, &id.into_inner())
+        .await?
+        .deny(&data, &payload.token)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[inline]
+fn push_mfa_enabled() -> Result<(), ErrorResponse> {
+    if *PUSH_MFA_ENABLE {
+        Ok(())
+    } else {
+        Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "Push MFA is disabled".to_string(),
+        ))
+    }
+}
+
+/// Get all applications the user has logged in to
+///
+/// Each client, which has been issued tokens on behalf of the user, is listed until the user
+/// revokes it.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/self/apps",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [ConnectedAppResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/self/apps")]
+pub async fn get_user_self_apps(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    let apps = account::connected_apps(&data, &id).await?;
+    Ok(HttpResponse::Ok().json(apps))
+}
+
+/// Revoke the consent for a connected application
+///
+/// Deletes all refresh tokens this client holds for the user. The client will show up again
+/// after the next login.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/self/apps/{client_id}",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 204, description = "NoContent"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/self/apps/{client_id}")]
+pub async fn delete_user_self_app(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let (id, client_id) = path.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    UserClientGrant::revoke(&data, &id, &client_id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Get a security overview for the user
+///
+/// Contains the MFA status with all registered passkeys, login information and the latest
+/// security relevant events for this user. Passkeys are managed with the
+/// `/users/{id}/webauthn` endpoints.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/self/security",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserSecurityResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/self/security")]
+pub async fn get_user_self_security(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    let user = User::find(&data, id).await?;
+    let resp = account::security_overview(&data, user).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Export all personal data for the user
+///
+/// Returns everything Rauthy stores about the user as a single JSON file.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/self/export",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserDataExportResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/self/export")]
+pub async fn get_user_self_export(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    let user = User::find(&data, id).await?;
+    let filename = format!("attachment; filename=\"rauthy-export-{}.json\"", user.id);
+    let resp = account::data_export(&data, user).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_DISPOSITION, filename))
+        .json(resp))
+}
+
+/// Get the language and notification preferences for the user
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/self/preferences",
+    tag = "users",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserPreferencesResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/self/preferences")]
+pub async fn get_user_self_preferences(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    let user = User::find(&data, id).await?;
+    let resp = account::preferences(&data, &user).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Update the language and notification preferences for the user
+///
+/// Values, which are not given, stay untouched. Opting out of a security notification needs an
+/// elevated session for the user itself.
+///
+/// **Permissions**
+/// - authenticated and logged in user for this very {id}
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/users/{id}/self/preferences",
+    tag = "users",
+    request_body = UserPreferencesRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserPreferencesResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[put("/users/{id}/self/preferences")]
+pub async fn put_user_self_preferences(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+    payload: Json<UserPreferencesRequest>,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    principal.validate_user_or_admin(&id)?;
+
+    // silencing these would hide a takeover of a stolen session from the real user
+    let is_opt_out = payload.notify_password_expiry == Some(false)
+        || payload.notify_sessions_revoked == Some(false);
+    if is_opt_out && principal.validate_admin_session().is_err() {
+        principal.get_session()?.validate_elevated()?;
+    }
+
+    let user = User::find(&data, id).await?;
+    let resp = account::update_preferences(&data, user, payload.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Allows a user to log in regardless of any group login policy for the given time
+///
+/// Meant for exceptions like an urgent task outside the usual hours. An existing override
+/// will be replaced.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/login_policy_override",
+    tag = "users",
+    request_body = LoginPolicyOverrideRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = LoginPolicyOverrideResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/login_policy_override")]
+pub async fn post_user_login_policy_override(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    payload: Json<LoginPolicyOverrideRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let user = User::find(&data, id.into_inner()).await?;
+    let minutes = payload.minutes;
+    let text = format!("{} for {} minutes", user.email, minutes);
+    let event =
+        Event::login_policy_override(text, real_ip_from_req(&req)).for_user(user.id.clone());
+    let ovr = LoginPolicyOverride::create(&data, user.id, minutes, event).await?;
+
+    Ok(HttpResponse::Ok().json(LoginPolicyOverrideResponse {
+        user_id: ovr.user_id,
+        exp: ovr.exp,
+    }))
+}
+
+/// Removes a still active login policy override for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/login_policy_override",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/login_policy_override")]
+pub async fn delete_user_login_policy_override(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    LoginPolicyOverride::delete(&data, &id.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Returns all users with an active legal hold
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/legal_holds",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [LegalHoldResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/legal_holds")]
+pub async fn get_users_legal_holds(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Read)?;
+
+    let holds = LegalHold::find_all(&data)
+        .await?
+        .into_iter()
+        .map(LegalHoldResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(holds))
+}
+
+/// Returns all users without any login, token refresh or userinfo access for the given amount
+/// of days
+///
+/// Users, which have never been active, are measured from their creation. The oldest activity
+/// comes first.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/inactive",
+    tag = "users",
+    params(InactiveUsersParams),
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [InactiveUserResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/inactive")]
+pub async fn get_users_inactive(
+    data: web::Data<AppState>,
+    principal: ReqPrincipal,
+    params: Query<InactiveUsersParams>,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Read)?;
+
+    let since =
+        OffsetDateTime::now_utc().unix_timestamp() - params.days.unwrap_or(90) as i64 * 86400;
+    let mut users = UserActivity::find_inactive(&data, since)
+        .await?
+        .into_iter()
+        .filter(|u| params.enabled.map(|e| e == u.enabled).unwrap_or(true))
+        .map(InactiveUserResponse::from)
+        .collect::<Vec<_>>();
+    users.sort_by_key(|u| u.last_activity);
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Puts a user under a legal hold
+///
+/// While the hold is active, the user cannot be deleted and all events mentioning the user are
+/// exempt from the events retention cleanup. Updating an existing hold only changes its reason.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    put,
+    path = "/users/{id}/legal_hold",
+    tag = "users",
+    request_body = LegalHoldRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = LegalHoldResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[put("/users/{id}/legal_hold")]
+pub async fn put_user_legal_hold(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    payload: Json<LegalHoldRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let user = User::find(&data, id.into_inner()).await?;
+    let reason = payload.into_inner().reason;
+    let text = format!("set for {}: {}", user.email, reason);
+    let event = Event::legal_hold(text, real_ip_from_req(&req)).for_user(user.id.clone());
+    let hold = LegalHold::create(&data, user.id, reason, event).await?;
+
+    Ok(HttpResponse::Ok().json(LegalHoldResponse::from(hold)))
+}
+
+/// Releases the legal hold for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/legal_hold",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/legal_hold")]
+pub async fn delete_user_legal_hold(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let user = User::find(&data, id.into_inner()).await?;
+    if LegalHold::find(&data, &user.id).await?.is_none() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The user is not under a legal hold".to_string(),
+        ));
+    }
+    let text = format!("released for {}", user.email);
+    let event = Event::legal_hold(text, real_ip_from_req(&req)).for_user(user.id.clone());
+    LegalHold::delete(&data, &user.id, event).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Request an account recovery after all MFA factors have been lost
+///
+/// This Endpoint will always return an `OK` to not provide any additional attack surface.
+/// Only if the E-Mail belongs to an enabled user with at least one passkey, a confirmation code
+/// will be sent out. A recovery with an already running waiting period will not be replaced,
+/// and a new code is sent at most every 5 minutes. Requires a solved PoW, unless the client is
+/// inside `LOGIN_TRUSTED_CIDRS`.
+///
+/// Only available if `MFA_RECOVERY_ENABLE` is set.
+///
+/// **Permissions**
+/// - session-init
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/users/mfa_recovery",
+    tag = "users",
+    request_body = MfaRecoveryRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[post("/users/mfa_recovery")]
+pub async fn post_mfa_recovery(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<MfaRecoveryRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+
+    // validate the PoW - skipped for trusted networks
+    let payload = payload.into_inner();
+    if !is_trusted_login_ip(real_ip_from_req(&req).as_deref()) {
+        let challenge = Pow::validate(&payload.pow)?;
+        PowEntity::check_prevent_reuse(&data, challenge.to_string()).await?;
+    }
+
+    mfa_recovery::request(&data, &req, payload.email).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Confirm an account recovery with the code from the E-Mail
+///
+/// Starts the waiting period of `MFA_RECOVERY_WAIT_HOURS`, during which the user will be
+/// notified and is able to cancel the recovery.
+///
+/// **Permissions**
+/// - session-init
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/users/mfa_recovery/confirm",
+    tag = "users",
+    request_body = MfaRecoveryCodeRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Ok", body = MfaRecoveryResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[post("/users/mfa_recovery/confirm")]
+pub async fn post_mfa_recovery_confirm(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<MfaRecoveryCodeRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+
+    mfa_recovery::confirm(&data, &req, payload.into_inner().code)
+        .await
+        .map(|resp| HttpResponse::Ok().json(resp))
+}
+
+/// Cancel an account recovery with the code from the E-Mail
+///
+/// **Permissions**
+/// - session-init
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/users/mfa_recovery/cancel",
+    tag = "users",
+    request_body = MfaRecoveryCodeRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+#[post("/users/mfa_recovery/cancel")]
+pub async fn post_mfa_recovery_cancel(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<MfaRecoveryCodeRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+
+    mfa_recovery::cancel(&data, &req, payload.into_inner().code).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Finish an account recovery after the waiting period
+///
+/// Removes all passkeys and the password from the account and revokes all sessions and refresh
+/// tokens. A new magic link will be sent via E-Mail, which can only be used to register new
+/// credentials. If `MFA_RECOVERY_ADMIN_APPROVAL` is set, an admin must have approved the
+/// recovery beforehand.
+///
+/// **Permissions**
+/// - session-init
+/// - session-auth
+#[utoipa::path(
+    post,
+    path = "/users/mfa_recovery/finish",
+    tag = "users",
+    request_body = MfaRecoveryCodeRequest,
+    security(("session_init" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[post("/users/mfa_recovery/finish")]
+pub async fn post_mfa_recovery_finish(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    payload: Json<MfaRecoveryCodeRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_session_auth_or_init()?;
+
+    mfa_recovery::finish(&data, &req, payload.into_inner().code).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Returns the open MFA recovery for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+/// - authenticated and logged in user for this very {id}
+#[utoipa::path(
+    get,
+    path = "/users/{id}/mfa_recovery",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = MfaRecoveryResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/mfa_recovery")]
+pub async fn get_user_mfa_recovery(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    if principal
+        .validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Read)
+        .is_err()
+    {
+        principal.validate_session_auth()?;
+        principal.is_user(&id)?;
+    }
+
+    mfa_recovery::status(&data, &id)
+        .await
+        .map(|resp| HttpResponse::Ok().json(resp))
+}
+
+/// Approves the confirmed MFA recovery for a user
+///
+/// Only needed if `MFA_RECOVERY_ADMIN_APPROVAL` is set. The recovery still can only be finished
+/// after the waiting period.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/mfa_recovery/approve",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = MfaRecoveryResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/mfa_recovery/approve")]
+pub async fn post_user_mfa_recovery_approve(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let id = id.into_inner();
+    if principal.user_id() == Ok(id.as_str()) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "You cannot approve your own recovery".to_string(),
+        ));
+    }
+    let approved_by = match &principal.api_key {
+        Some(api_key) => format!("api_key:{}", api_key.name),
+        None => principal.user_id()?.to_string(),
+    };
+
+    mfa_recovery::approve(&data, &req, &id, approved_by)
+        .await
+        .map(|resp| HttpResponse::Ok().json(resp))
+}
+
+/// Cancels any open MFA recovery for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+/// - authenticated and logged in user for this very {id}
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/mfa_recovery",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/mfa_recovery")]
+pub async fn delete_user_mfa_recovery(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    let id = id.into_inner();
+    let by = if principal
+        .validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)
+        .is_ok()
+    {
+        "an admin"
+    } else {
+        principal.validate_session_auth()?;
+        principal.is_user(&id)?;
+        "the user"
+    };
+
+    mfa_recovery::cancel_for_user(&data, &req, &id, by).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Returns all time-limited role grants for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/role_grants",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [UserRoleGrantResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/role_grants")]
+pub async fn get_user_role_grants(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Read)?;
+
+    let resp = UserRoleGrant::find_for_user(&data, &id.into_inner())
+        .await?
+        .into_iter()
+        .map(UserRoleGrantResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// Grants a role to a user for a limited time
+///
+/// The role will be added to the user immediately and shows up in all new tokens. After
+/// `duration_mins`, it will be removed again automatically. The reason and the approving admin
+/// are recorded. An already running grant for the same role will be replaced.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/role_grants",
+    tag = "users",
+    request_body = UserRoleGrantRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserRoleGrantResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/role_grants")]
+pub async fn post_user_role_grant(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    payload: Json<UserRoleGrantRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let id = id.into_inner();
+    if principal.user_id() == Ok(id.as_str()) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "You cannot grant a role to yourself".to_string(),
+        ));
+    }
+    let approved_by = match &principal.api_key {
+        Some(api_key) => format!("api_key:{}", api_key.name),
+        None => principal.user_id()?.to_string(),
+    };
+
+    let payload = payload.into_inner();
+    let grant = UserRoleGrant::create(
+        &data,
+        id,
+        payload.role,
+        payload.reason,
+        approved_by,
+        payload.duration_mins,
+        real_ip_from_req(&req),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(UserRoleGrantResponse::from(grant)))
+}
+
+/// Revokes a time-limited role grant before it expires
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/role_grants/{grant_id}",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/role_grants/{grant_id}")]
+pub async fn delete_user_role_grant(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let (id, grant_id) = path.into_inner();
+    let grant = UserRoleGrant::find(&data, &grant_id).await?;
+    if grant.user_id != id {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The role grant does not exist for this user".to_string(),
+        ));
+    }
+    let user = User::find(&data, grant.user_id.clone()).await?;
+    let text = format!("'{}' revoked for {}", grant.role, user.email);
+    let event = Event::elevated_role(text, real_ip_from_req(&req)).for_user(user.id);
+    grant.revoke(&data, event).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Merges a duplicate user into the user with the given `{id}`
+///
+/// Roles, groups, passkeys, consents, organization memberships and E-Mail aliases of both users
+/// are united. For attributes, which are set on both sides, the `conflict_winner` decides. The
+/// password and MFA settings of `{id}` are always kept. The duplicate is deleted afterward and its
+/// id will be redirected to `{id}` for tokens issued before the merge.
+///
+/// With `?dry_run=true`, nothing will be changed and only the result will be returned.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/merge",
+    tag = "users",
+    params(DryRunParams),
+    request_body = UserMergeRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = UserMergeResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/merge")]
+pub async fn post_user_merge(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<String>,
+    params: Query<DryRunParams>,
+    payload: Json<UserMergeRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let merged_by = match &principal.api_key {
+        Some(api_key) => format!("api_key:{}", api_key.name),
+        None => principal.user_id()?.to_string(),
+    };
+
+    let payload = payload.into_inner();
+    let res = UserMerge::merge(
+        &data,
+        id.into_inner(),
+        payload.source_user_id,
+        payload.conflict_winner.unwrap_or_default(),
+        merged_by,
+        params.is_dry_run(),
+        real_ip_from_req(&req),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Returns all E-Mail aliases for a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    get,
+    path = "/users/{id}/email_aliases",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = [EmailAliasResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+#[get("/users/{id}/email_aliases")]
+pub async fn get_user_email_aliases(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Read)?;
+
+    let aliases = EmailAlias::find_for_user(&data, &id.into_inner())
+        .await?
+        .into_iter()
+        .map(EmailAliasResponse::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(aliases))
+}
+
+/// Adds an E-Mail alias for a user
+///
+/// The user can log in with the alias just like with the E-Mail. An alias must not be in use
+/// as E-Mail or alias by any other user.
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    post,
+    path = "/users/{id}/email_aliases",
+    tag = "users",
+    request_body = EmailAliasRequest,
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok", body = EmailAliasResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[post("/users/{id}/email_aliases")]
+pub async fn post_user_email_alias(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    payload: Json<EmailAliasRequest>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let user = User::find(&data, id.into_inner()).await?;
+    let alias = EmailAlias::create(&data, user.id, payload.into_inner().email).await?;
+    Ok(HttpResponse::Ok().json(EmailAliasResponse::from(alias)))
+}
+
+/// Removes an E-Mail alias from a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/email_aliases/{email}",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+#[delete("/users/{id}/email_aliases/{email}")]
+pub async fn delete_user_email_alias(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+    principal: ReqPrincipal,
+) -> Result<HttpResponse, ErrorResponse> {
+    principal.validate_api_key_or_admin_session(AccessGroup::Users, AccessRights::Update)?;
+
+    let (id, email) = path.into_inner();
+    EmailAlias::delete(&data, &id, &email).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Deletes a user
+///
+/// **Permissions**
+/// - rauthy_admin
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "users",
+    security(("api_key" = []), ("session" = [])),
+    responses(
+        (status = 204, description = "NoContent"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden - also returned for users under a legal hold", body = ErrorResponse),
     ),
 )]
 #[delete("/users/{id}")]
@@ -1,12 +1,31 @@
 use actix_web::web;
+use rauthy_common::constants::CACHE_NAME_INVALIDATIONS;
 use rauthy_models::app_state::AppState;
+use rauthy_models::cache_invalidation::Invalidation;
 use redhac::{CacheMethod, CacheNotify};
+use std::str::FromStr;
 use tokio::sync::mpsc;
-use tracing::debug;
+use tracing::{debug, error};
 
-pub async fn handle_notify(_data: web::Data<AppState>, mut rx: mpsc::Receiver<CacheNotify>) {
+pub async fn handle_notify(data: web::Data<AppState>, mut rx: mpsc::Receiver<CacheNotify>) {
     while let Some(msg) = rx.recv().await {
         match msg.method {
+            CacheMethod::Put if msg.cache_name == CACHE_NAME_INVALIDATIONS => {
+                debug!("Remote cache invalidation '{}'", msg.entry);
+
+                match Invalidation::from_str(&msg.entry) {
+                    Ok(invalidation) => {
+                        if let Err(err) = invalidation.evict(&data).await {
+                            error!(
+                                "Error evicting cache entries for invalidation '{}': {}",
+                                msg.entry, err.message
+                            );
+                        }
+                    }
+                    Err(err) => error!("{}", err.message),
+                }
+            }
+
             CacheMethod::Put => {
                 debug!(
                     "Remote push to the cache for '{}/{}'",
@@ -0,0 +1,78 @@
+use actix_web::web;
+use rauthy_common::constants::{
+    CACHE_NAME_12HR, CACHE_PRELOAD_SESSIONS_LIMIT, CACHE_PRELOAD_SESSIONS_MINUTES,
+};
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::jwk::{JwkKeyPair, JwkKeyPairAlg, JWKS};
+use rauthy_models::entity::password::PasswordPolicy;
+use rauthy_models::entity::sessions::Session;
+use redhac::cache_put;
+use std::time::Instant;
+use tracing::{error, info};
+
+/// Populates the local caches with hot entities, so the first requests after a (rolling)
+/// restart do not all end up on the database.
+///
+/// Errors are only logged. A failed preload is not a reason to not start up.
+pub async fn preload(data: &web::Data<AppState>) {
+    info!("Preloading caches");
+    let start = Instant::now();
+
+    if let Err(err) = preload_clients(data).await {
+        error!("Error preloading clients: {}", err.message);
+    }
+    if let Err(err) = preload_jwks(data).await {
+        error!("Error preloading JWKS: {}", err.message);
+    }
+    if let Err(err) = PasswordPolicy::find(data).await {
+        error!("Error preloading the password policy: {}", err.message);
+    }
+
+    match Session::preload_recently_active(
+        data,
+        *CACHE_PRELOAD_SESSIONS_MINUTES,
+        *CACHE_PRELOAD_SESSIONS_LIMIT,
+    )
+    .await
+    {
+        Ok(count) => info!("Preloaded {} recently active sessions", count),
+        Err(err) => error!("Error preloading sessions: {}", err.message),
+    }
+
+    info!(
+        "Cache preloading finished after {} ms",
+        start.elapsed().as_millis()
+    );
+}
+
+async fn preload_clients(data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+    let clients = Client::find_all(data).await?;
+    let mut count = 0;
+    for client in clients.iter().filter(|c| c.enabled) {
+        cache_put(
+            CACHE_NAME_12HR.to_string(),
+            Client::get_cache_entry(&client.id),
+            &data.caches.ha_cache_config,
+            client,
+        )
+        .await?;
+        count += 1;
+    }
+    info!("Preloaded {} active clients", count);
+    Ok(())
+}
+
+async fn preload_jwks(data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+    JWKS::find_pk(data).await?;
+    for alg in [
+        JwkKeyPairAlg::RS256,
+        JwkKeyPairAlg::RS384,
+        JwkKeyPairAlg::RS512,
+        JwkKeyPairAlg::EdDSA,
+    ] {
+        JwkKeyPair::find_latest(data, alg.as_str(), alg.clone()).await?;
+    }
+    Ok(())
+}
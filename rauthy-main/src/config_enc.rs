@@ -0,0 +1,67 @@
+use cryptr::EncValue;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::utils::{base64_decode, base64_encode};
+use std::env;
+use std::io::{self, BufRead};
+use tracing::info;
+
+/// Prefix for config values which are encrypted with the active `ENC_KEYS`
+const ENC_VALUE_PREFIX: &str = "enc:";
+
+/// Decrypts all `enc:` prefixed values from the environment in place.
+///
+/// This must be executed right after the `EncKeys` have been initialized and before any other
+/// config value is being read.
+pub fn decrypt_env_values() {
+    let mut count = 0;
+    for (key, value) in env::vars() {
+        if let Some(enc) = value.strip_prefix(ENC_VALUE_PREFIX) {
+            let plain = decrypt_value(enc.trim()).unwrap_or_else(|err| {
+                panic!(
+                    "Cannot decrypt the encrypted config value for '{}': {}",
+                    key, err.message
+                )
+            });
+            env::set_var(&key, plain);
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        info!("Decrypted {} encrypted config values", count);
+    }
+}
+
+/// CLI helper to encrypt a config value with the currently active `ENC_KEY_ACTIVE`.
+/// The value is either given as an argument or read from stdin, if it is missing.
+///
+/// `rauthy encrypt <value>`
+pub fn encrypt_cli(value: Option<&String>) {
+    let value = match value {
+        Some(value) => value.to_string(),
+        None => {
+            let mut line = String::new();
+            io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .expect("Cannot read the value to encrypt from stdin");
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    match encrypt_value(&value) {
+        Ok(enc) => println!("{}{}", ENC_VALUE_PREFIX, enc),
+        Err(err) => panic!("Cannot encrypt the value: {}", err.message),
+    }
+}
+
+fn encrypt_value(value: &str) -> Result<String, ErrorResponse> {
+    let enc = EncValue::encrypt(value.as_bytes())?.into_bytes();
+    Ok(base64_encode(enc.as_ref()))
+}
+
+fn decrypt_value(b64: &str) -> Result<String, ErrorResponse> {
+    let bytes = base64_decode(b64)?;
+    let plain = EncValue::try_from(bytes)?.decrypt()?;
+    Ok(String::from_utf8(plain.to_vec())?)
+}
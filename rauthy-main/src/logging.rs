@@ -1,4 +1,12 @@
+use chrono::Utc;
+use rauthy_common::constants::LOG_STREAM_ENABLE;
+use rauthy_common::log_stream::{self, LogLevel, LogLine};
 use std::env;
+use std::fmt::Write;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::Layer;
 
 // Sets up the logging / tracing depending on the env var `LOG_LEVEL`
 pub fn setup_logging() -> tracing::Level {
@@ -32,9 +40,45 @@ pub fn setup_logging() -> tracing::Level {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(log_level)
         .with_env_filter(filter)
-        .finish();
+        .finish()
+        .with(LogStreamLayer);
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
     log_level
 }
+
+/// Forwards all log events into the in-memory buffer for the admin log stream.
+struct LogStreamLayer;
+
+impl<S: Subscriber> Layer<S> for LogStreamLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !*LOG_STREAM_ENABLE {
+            return;
+        }
+
+        let meta = event.metadata();
+        let mut visitor = LogLineVisitor::default();
+        event.record(&mut visitor);
+
+        log_stream::push(LogLine::new(
+            Utc::now().timestamp_millis(),
+            LogLevel::from(meta.level()),
+            meta.target(),
+            &visitor.0,
+        ));
+    }
+}
+
+#[derive(Default)]
+struct LogLineVisitor(String);
+
+impl Visit for LogLineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
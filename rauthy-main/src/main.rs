@@ -7,34 +7,56 @@ use actix_web::{middleware, web, App, HttpServer};
 use actix_web_prom::PrometheusMetricsBuilder;
 use cryptr::EncKeys;
 use prometheus::Registry;
+use rauthy_common::cache_metrics;
 use rauthy_common::constants::{
-    CACHE_NAME_12HR, CACHE_NAME_AUTH_CODES, CACHE_NAME_AUTH_PROVIDER_CALLBACK,
-    CACHE_NAME_CLIENTS_DYN, CACHE_NAME_DEVICE_CODES, CACHE_NAME_DPOP_NONCES,
-    CACHE_NAME_EPHEMERAL_CLIENTS, CACHE_NAME_IP_RATE_LIMIT, CACHE_NAME_LOGIN_DELAY, CACHE_NAME_POW,
-    CACHE_NAME_SESSIONS, CACHE_NAME_USERS, CACHE_NAME_WEBAUTHN, CACHE_NAME_WEBAUTHN_DATA,
-    DEVICE_GRANT_CODE_CACHE_SIZE, DEVICE_GRANT_CODE_LIFETIME, DEVICE_GRANT_RATE_LIMIT,
-    DPOP_NONCE_EXP, DYN_CLIENT_RATE_LIMIT_SEC, DYN_CLIENT_REG_TOKEN, ENABLE_DYN_CLIENT_REG,
-    ENABLE_WEB_ID, EPHEMERAL_CLIENTS_CACHE_LIFETIME, POW_EXP, RAUTHY_VERSION, SWAGGER_UI_EXTERNAL,
-    SWAGGER_UI_INTERNAL, UPSTREAM_AUTH_CALLBACK_TIMEOUT_SECS, WEBAUTHN_DATA_EXP, WEBAUTHN_REQ_EXP,
+    BREAK_GLASS_HASH, CACHE_NAME_12HR, CACHE_NAME_AUTH_CODES, CACHE_NAME_AUTH_PROVIDER_CALLBACK,
+    CACHE_NAME_BREAK_GLASS, CACHE_NAME_CLIENTS_DYN, CACHE_NAME_DEVICE_CODES,
+    CACHE_NAME_DPOP_NONCES, CACHE_NAME_EPHEMERAL_CLIENTS, CACHE_NAME_INVALIDATIONS,
+    CACHE_NAME_IP_RATE_LIMIT, CACHE_NAME_LOGIN_DELAY, CACHE_NAME_LOGIN_TRACES, CACHE_NAME_POW,
+    CACHE_NAME_RATE_LIMIT, CACHE_NAME_REQUEST_URIS, CACHE_NAME_REVOKED_TOKENS, CACHE_NAME_SESSIONS,
+    CACHE_NAME_SSO_HANDOFF, CACHE_NAME_USERS, CACHE_NAME_WEBAUTHN, CACHE_NAME_WEBAUTHN_DATA,
+    CACHE_PRELOAD, CACHE_SESSIONS_SHARDS, COMPRESSION_ENABLE, DEVICE_GRANT_CODE_CACHE_SIZE,
+    DEVICE_GRANT_CODE_LIFETIME, DEVICE_GRANT_RATE_LIMIT, DPOP_NONCE_EXP, DYN_CLIENT_RATE_LIMIT_SEC,
+    DYN_CLIENT_REG_TOKEN, EMAIL_CHANNEL_BUFFER, ENABLE_DYN_CLIENT_REG, ENABLE_WEB_ID,
+    EPHEMERAL_CLIENTS_CACHE_LIFETIME, HA_MODE, LAB_MODE_SECRET, LAB_MODE_SEED,
+    LISTEN_HTTP2_CLEARTEXT, LISTEN_PORT_ADMIN, LOGIN_DELAY_WINDOW_SECS, LOGIN_TRACE_MAX_MINUTES,
+    METRICS_TLS, POW_EXP, RAUTHY_VERSION, READ_ONLY_CACHE_LIFESPAN, READ_ONLY_MODE,
+    SSO_HANDOFF_CONFIRM_TIMEOUT_SECS, SSO_HANDOFF_PEERS, SWAGGER_UI_EXTERNAL, SWAGGER_UI_INTERNAL,
+    TOKEN_RATE_LIMIT, UPSTREAM_AUTH_CALLBACK_TIMEOUT_SECS, WEBAUTHN_DATA_EXP, WEBAUTHN_REQ_EXP,
 };
+use rauthy_common::login_delay;
+use rauthy_common::login_latency;
 use rauthy_common::password_hasher;
+use rauthy_common::pow_difficulty;
+use rauthy_common::token_errors;
+use rauthy_common::utils::cache_shard_names;
+use rauthy_handlers::middleware::api_version::RauthyApiVersionMiddleware;
+use rauthy_handlers::middleware::compression::RauthyCompressionMiddleware;
 use rauthy_handlers::middleware::ip_blacklist::RauthyIpBlacklistMiddleware;
 use rauthy_handlers::middleware::logging::RauthyLoggingMiddleware;
+use rauthy_handlers::middleware::metrics_guard::RauthyMetricsGuardMiddleware;
 use rauthy_handlers::middleware::principal::RauthyPrincipalMiddleware;
+use rauthy_handlers::middleware::problem_json::RauthyProblemJsonMiddleware;
+use rauthy_handlers::middleware::read_only::RauthyReadOnlyMiddleware;
+use rauthy_handlers::middleware::request_limits::RauthyRequestLimitsMiddleware;
 use rauthy_handlers::openapi::ApiDoc;
 use rauthy_handlers::{
-    api_keys, auth_providers, blacklist, clients, events, generic, groups, oidc, roles, scopes,
-    sessions, users,
+    announcements, api_keys, auth_providers, blacklist, caches, cleanup, clients, cluster, events,
+    feature_flags, generic, groups, jobs, lab, login_traces, logs, oidc, organizations,
+    resource_servers, roles, scopes, sessions, users,
 };
 use rauthy_models::app_state::{AppState, Caches};
 use rauthy_models::email::EMail;
+use rauthy_models::entity::lab::LabMode;
+use rauthy_models::entity::{cluster_nodes, ip_feeds};
 use rauthy_models::events::event::Event;
 use rauthy_models::events::health_watch::watch_health;
 use rauthy_models::events::listener::EventListener;
 use rauthy_models::events::notifier::EventNotifier;
-use rauthy_models::events::{init_event_vars, ip_blacklist_handler};
+use rauthy_models::events::{init_event_vars, ip_blacklist_handler, sse_hub};
 use rauthy_models::migration::check_restore_backup;
-use rauthy_models::{email, ListenScheme};
+use rauthy_models::{asset_storage, email, user_store, ListenScheme};
+use rauthy_service::encryption;
 use spow::pow::Pow;
 use std::error::Error;
 use std::net::Ipv4Addr;
@@ -43,19 +65,34 @@ use std::time::Duration;
 use std::{env, thread};
 use tokio::sync::mpsc;
 use tokio::time;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::cache_notify::handle_notify;
 use crate::logging::setup_logging;
 
 mod cache_notify;
+mod cache_preload;
+mod config_enc;
 mod logging;
 mod schedulers;
 mod tls;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // CLI helper to encrypt config values with the active ENC_KEYS -> no server startup
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "encrypt" {
+        dotenvy::from_filename("rauthy.cfg").ok();
+        dotenvy::dotenv().ok();
+        EncKeys::from_env()
+            .expect("The `ENC_KEYS` are not correctly set up")
+            .init()
+            .unwrap();
+        config_enc::encrypt_cli(args.get(2));
+        return Ok(());
+    }
+
     println!(
         r#"
                                           88
@@ -76,7 +113,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // setup logging
     let mut test_mode = false;
-    let args: Vec<String> = env::args().collect();
     if args.len() > 1 && args[1] == "test" {
         test_mode = true;
         dotenvy::from_filename("rauthy.test.cfg").ok();
@@ -92,6 +128,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     if test_mode {
         info!("Application started in Integration Test Mode");
     }
+    if *READ_ONLY_MODE {
+        if *HA_MODE {
+            panic!("MODE=readonly cannot be used together with HA_MODE");
+        }
+        info!("Application started in read-only mode");
+    }
 
     // init encryption keys and pow secrets
     match EncKeys::from_env() {
@@ -109,19 +151,35 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         }
     }
 
+    // decrypt all `enc:` values from the config before anything else reads them
+    config_enc::decrypt_env_values();
+
     // check if a backup should be restored
     if let Err(err) = check_restore_backup().await {
         error!("\nError restoring backup:\n\n{}\n", err.message);
         panic!("{:?}", err);
     }
 
+    // optional S3 storage for logos
+    asset_storage::init().await;
+
     // caches
     let (tx_health_state, mut cache_config) = redhac::CacheConfig::new();
 
+    // A read-only replica never receives invalidations from the primary, so cached entities
+    // must expire quickly.
+    let data_lifespan = |secs: u64| {
+        if *READ_ONLY_MODE {
+            secs.min(*READ_ONLY_CACHE_LIFESPAN)
+        } else {
+            secs
+        }
+    };
+
     // "infinity" cache
     cache_config.spawn_cache(
         CACHE_NAME_12HR.to_string(),
-        redhac::TimedCache::with_lifespan(43200),
+        redhac::TimedCache::with_lifespan(data_lifespan(43200)),
         Some(32),
     );
 
@@ -168,7 +226,7 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
     // ephemeral clients
     cache_config.spawn_cache(
         CACHE_NAME_EPHEMERAL_CLIENTS.to_string(),
-        redhac::TimedCache::with_lifespan(*EPHEMERAL_CLIENTS_CACHE_LIFETIME),
+        redhac::TimedCache::with_lifespan(data_lifespan(*EPHEMERAL_CLIENTS_CACHE_LIFETIME)),
         None,
     );
 
@@ -187,11 +245,13 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         .trim()
         .parse::<u64>()
         .expect("SESSION_LIFETIME cannot be parsed to u64 - bad format");
-    cache_config.spawn_cache(
-        CACHE_NAME_SESSIONS.to_string(),
-        redhac::TimedCache::with_lifespan(sessions_lifetime),
-        Some(64),
-    );
+    for name in cache_shard_names(CACHE_NAME_SESSIONS, *CACHE_SESSIONS_SHARDS) {
+        cache_config.spawn_cache(
+            name,
+            redhac::TimedCache::with_lifespan(data_lifespan(sessions_lifetime)),
+            Some(64),
+        );
+    }
 
     // PoWs
     cache_config.spawn_cache(
@@ -200,6 +260,48 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         Some(16),
     );
 
+    // cluster wide rate limits
+    if TOKEN_RATE_LIMIT.is_some() || BREAK_GLASS_HASH.is_some() {
+        cache_config.spawn_cache(
+            CACHE_NAME_RATE_LIMIT.to_string(),
+            redhac::TimedCache::with_lifespan(5),
+            None,
+        );
+    }
+
+    // request objects fetched from a `request_uri`
+    cache_config.spawn_cache(
+        CACHE_NAME_REQUEST_URIS.to_string(),
+        redhac::TimedSizedCache::with_size_and_lifespan(256, 60),
+        None,
+    );
+
+    // the revocation state of validated token jtis - checked with each request, so it must
+    // stay bounded
+    cache_config.spawn_cache(
+        CACHE_NAME_REVOKED_TOKENS.to_string(),
+        redhac::TimedSizedCache::with_size_and_lifespan(16384, 3600),
+        Some(32),
+    );
+
+    // break glass access codes
+    if BREAK_GLASS_HASH.is_some() {
+        cache_config.spawn_cache(
+            CACHE_NAME_BREAK_GLASS.to_string(),
+            redhac::TimedCache::with_lifespan(60),
+            None,
+        );
+    }
+
+    // SSO handoffs from peers, which wait for the confirmation of the user
+    if !SSO_HANDOFF_PEERS.is_empty() {
+        cache_config.spawn_cache(
+            CACHE_NAME_SSO_HANDOFF.to_string(),
+            redhac::TimedCache::with_lifespan(SSO_HANDOFF_CONFIRM_TIMEOUT_SECS),
+            None,
+        );
+    }
+
     // Users
     let users_lifespan = env::var("CACHE_USERS_LIFESPAN")
         .unwrap_or_else(|_| String::from("28800"))
@@ -220,7 +322,10 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
     };
     cache_config.spawn_cache(
         CACHE_NAME_USERS.to_string(),
-        redhac::TimedCache::with_lifespan_and_capacity(users_lifespan, users_size_adjust),
+        redhac::TimedCache::with_lifespan_and_capacity(
+            data_lifespan(users_lifespan),
+            users_size_adjust,
+        ),
         Some(16),
     );
 
@@ -237,19 +342,35 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
     );
 
     // login delay cache
+    // only holds the success time published by the member with the most recent data
     cache_config.spawn_cache(
         CACHE_NAME_LOGIN_DELAY.to_string(),
-        redhac::SizedCache::with_size(1),
+        redhac::TimedCache::with_lifespan(*LOGIN_DELAY_WINDOW_SECS),
         Some(16),
     );
 
+    // login traces
+    // records stay available for the same amount of time after a trace has expired
+    cache_config.spawn_cache(
+        CACHE_NAME_LOGIN_TRACES.to_string(),
+        redhac::TimedCache::with_lifespan(*LOGIN_TRACE_MAX_MINUTES as u64 * 60 * 2),
+        Some(16),
+    );
+
+    // cache invalidations
+    // the entries themselves are never read, only the remote notifications for them matter
+    cache_config.spawn_cache(
+        CACHE_NAME_INVALIDATIONS.to_string(),
+        redhac::TimedCache::with_lifespan(60),
+        Some(32),
+    );
+
     // The ha cache must be started after all entries have been added to the cache map
     let (tx_notify, rx_notify) = mpsc::channel(64);
     redhac::start_cluster(tx_health_state, &mut cache_config, Some(tx_notify), None).await?;
 
     // email sending
-    let (tx_email, rx_email) = mpsc::channel::<EMail>(16);
-    tokio::spawn(email::sender(rx_email, test_mode));
+    let (tx_email, rx_email) = mpsc::channel::<EMail>(*EMAIL_CHANNEL_BUFFER);
 
     // build the application state
     let caches = Caches {
@@ -271,9 +392,13 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         .await?,
     );
 
+    // the email sender persists all E-Mails as jobs before sending them out
+    tokio::spawn(email::sender(app_state.clone(), rx_email, test_mode));
+
     // events listener
     init_event_vars().unwrap();
     EventNotifier::init_notifiers(tx_email).await.unwrap();
+    user_store::init_user_store().unwrap();
     tokio::spawn(EventListener::listen(
         tx_ip_blacklist.clone(),
         tx_events_router,
@@ -298,6 +423,14 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         app_state.caches.ha_cache_config.rx_health_state.clone(),
     ));
 
+    // register this instance in the cluster registry
+    if !*READ_ONLY_MODE {
+        tokio::spawn(cluster_nodes::heartbeat(app_state.clone()));
+    }
+
+    // IP reputation feeds - the blacklist lives in memory, so each instance fetches them
+    tokio::spawn(ip_feeds::run(app_state.clone()));
+
     // schedulers
     match env::var("SCHED_DISABLE")
         .unwrap_or_else(|_| String::from("false"))
@@ -306,6 +439,10 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         "true" => {
             info!("Schedulers are disabled");
         }
+        // all schedulers modify data in some way
+        _ if *READ_ONLY_MODE => {
+            info!("Running in read-only mode - schedulers are disabled");
+        }
         _ => {
             tokio::spawn(schedulers::scheduler_main(app_state.clone()));
         }
@@ -316,6 +453,44 @@ https://sebadob.github.io/rauthy/getting_started/main.html"#
         error!("Error clearing cache after migrations: {}", err.error);
     }
 
+    // lab mode for security testing
+    if LabMode::is_enabled() {
+        if LAB_MODE_SECRET.as_ref().unwrap().len() < 32 {
+            panic!("LAB_MODE_SECRET must be at least 32 characters long");
+        }
+        // the seed derives all lab credentials and must never be a publicly known value
+        match LAB_MODE_SEED.as_ref() {
+            None => panic!("LAB_MODE_SEED must be set when LAB_MODE_SECRET is set"),
+            Some(seed) if seed.len() < 32 => {
+                panic!("LAB_MODE_SEED must be at least 32 characters long")
+            }
+            Some(_) => {}
+        }
+        warn!(
+            r#"
+    LAB MODE IS ENABLED - synthetic users and clients can be created via /auth/v1/lab/seed.
+    Rate limits are relaxed for requests with a valid X-Debug header.
+    NEVER enable this in production!
+"#
+        );
+    } else if !*READ_ONLY_MODE {
+        // make sure no synthetic data is left over after the lab mode has been disabled
+        if let Err(err) = LabMode::purge(&app_state).await {
+            error!("Error purging lab mode data: {}", err.message);
+        }
+    }
+
+    // personal data from before `PII_ENCRYPTION` has been enabled
+    if !*READ_ONLY_MODE {
+        if let Err(err) = encryption::encrypt_existing_pii(&app_state).await {
+            error!("Error encrypting existing personal data: {}", err.message);
+        }
+    }
+
+    if *CACHE_PRELOAD {
+        cache_preload::preload(&app_state).await;
+    }
+
     // actix web
     let state = app_state.clone();
     let actix = thread::spawn(move || {
@@ -364,6 +539,15 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
         .expect("Cannot parse METRICS_ENABLE to bool");
     let pub_metrics = if metrics_enable {
         let shared_registry = Registry::new();
+        cache_metrics::register(&shared_registry);
+        login_delay::register(&shared_registry);
+        login_latency::register(&shared_registry);
+        token_errors::register(&shared_registry);
+        sse_hub::register(&shared_registry);
+        pow_difficulty::register(&shared_registry);
+        email::register_metrics(&shared_registry);
+        rauthy_models::entity::sessions::register_metrics(&shared_registry);
+        rauthy_models::entity::cleanup::register_metrics(&shared_registry);
         let metrics = PrometheusMetricsBuilder::new("api")
             .registry(shared_registry.clone())
             .endpoint("/metrics")
@@ -383,29 +567,35 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
             }
             let addr_full = format!("{}:{}", addr, port);
 
-            info!("Metrics available on: http://{}/metrics", addr_full);
-            let srv = if *SWAGGER_UI_INTERNAL {
+            let scheme = if *METRICS_TLS { "https" } else { "http" };
+            info!("Metrics available on: {}://{}/metrics", scheme, addr_full);
+            if *SWAGGER_UI_INTERNAL {
                 info!(
-                    "Serving Swagger UI internally on: http://{}/docs/v1/swagger-ui/",
-                    addr_full
+                    "Serving Swagger UI internally on: {}://{}/docs/v1/swagger-ui/",
+                    scheme, addr_full
                 );
-                HttpServer::new(move || {
-                    App::new()
-                        .wrap(metrics.clone())
-                        .service(swagger_clone.clone())
-                })
-                .workers(1)
-                .bind(addr_full)
-                .unwrap()
-                .run()
-            } else {
-                HttpServer::new(move || App::new().wrap(metrics.clone()))
-                    .workers(1)
-                    .bind(addr_full)
-                    .unwrap()
-                    .run()
-            };
-            System::new().block_on(srv).unwrap();
+            }
+
+            let srv = HttpServer::new(move || {
+                let app = App::new()
+                    .wrap(metrics.clone())
+                    .wrap(RauthyMetricsGuardMiddleware::default());
+                if *SWAGGER_UI_INTERNAL {
+                    app.service(swagger_clone.clone())
+                } else {
+                    app
+                }
+            })
+            .workers(1);
+
+            System::new().block_on(async move {
+                let srv = if *METRICS_TLS {
+                    srv.bind_rustls_0_22(addr_full, tls::load_tls_metrics().await)
+                } else {
+                    srv.bind(addr_full)
+                };
+                srv.unwrap().run().await.unwrap();
+            });
         });
 
         PrometheusMetricsBuilder::new("rauthy")
@@ -436,8 +626,16 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
         let mut app = App::new()
             // .data shares application state for all workers
             .app_data(app_state.clone())
+            .wrap(RauthyRequestLimitsMiddleware)
             .wrap(RauthyPrincipalMiddleware)
             .wrap(RauthyLoggingMiddleware)
+            .wrap(RauthyReadOnlyMiddleware)
+            .wrap(RauthyProblemJsonMiddleware)
+            .wrap(RauthyCompressionMiddleware)
+            .wrap(middleware::Condition::new(
+                *COMPRESSION_ENABLE,
+                middleware::Compress::default(),
+            ))
             .wrap(
                 middleware::DefaultHeaders::new()
                     .add(("x-frame-options", "SAMEORIGIN"))
@@ -456,6 +654,7 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                     ))
                     .add(("cache-control", "no-store")),
             )
+            .wrap(RauthyApiVersionMiddleware)
             .wrap(pub_metrics.clone())
             .service(oidc::get_well_known)
             .service(generic::redirect)
@@ -468,6 +667,11 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                     .service(users::get_user_webid)
                     .service(
                         web::scope("/v1")
+                            .service(announcements::get_announcements)
+                            .service(announcements::get_announcements_active)
+                            .service(announcements::post_announcement)
+                            .service(announcements::put_announcement)
+                            .service(announcements::delete_announcement)
                             .service(api_keys::get_api_keys)
                             .service(api_keys::post_api_key)
                             .service(api_keys::put_api_key)
@@ -489,11 +693,21 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(auth_providers::put_provider_img)
                             .service(auth_providers::post_provider_link)
                             .service(blacklist::get_blacklist)
+                            .service(blacklist::get_blacklist_feeds)
                             .service(blacklist::post_blacklist)
                             .service(blacklist::delete_blacklist)
                             .service(events::post_events)
+                            .service(events::get_events_export)
+                            .service(events::get_events_chain_verify)
+                            .service(events::get_event_recipients)
+                            .service(events::put_event_recipients)
                             .service(events::sse_events)
                             .service(events::post_event_test)
+                            .service(feature_flags::get_feature_flags)
+                            .service(feature_flags::get_feature_flags_evaluate)
+                            .service(feature_flags::post_feature_flag)
+                            .service(feature_flags::put_feature_flag)
+                            .service(feature_flags::delete_feature_flag)
                             .service(generic::get_index)
                             .service(generic::get_account_html)
                             .service(generic::get_admin_html)
@@ -511,14 +725,23 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(generic::get_admin_sessions_html)
                             .service(generic::get_admin_users_html)
                             .service(generic::get_device_html)
+                            .service(generic::get_push_mfa_html)
                             .service(generic::get_auth_check)
                             .service(generic::get_auth_check_admin)
+                            .service(generic::post_break_glass)
+                            .service(generic::get_break_glass)
                             .service(generic::post_i18n)
                             .service(generic::post_update_language)
                             .service(generic::get_version)
                             .service(oidc::get_authorize)
                             .service(oidc::post_authorize)
                             .service(oidc::post_authorize_refresh)
+                            .service(oidc::get_authorize_form_post)
+                            .service(oidc::post_authorize_email_mfa)
+                            .service(oidc::post_authorize_push_mfa)
+                            .service(oidc::post_authorize_push_mfa_fallback)
+                            .service(oidc::get_authorize_headless)
+                            .service(oidc::post_authorize_headless)
                             .service(oidc::post_device_auth)
                             .service(oidc::post_device_verify)
                             .service(oidc::get_callback_html)
@@ -527,6 +750,10 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(oidc::get_logout)
                             .service(oidc::post_logout)
                             .service(oidc::rotate_jwk)
+                            .service(oidc::get_jwks_history)
+                            .service(oidc::post_jwk_import)
+                            .service(oidc::put_jwk_activation)
+                            .service(oidc::post_jwk_retire)
                             .service(oidc::get_session_info)
                             .service(oidc::get_session_xsrf)
                             .service(clients::get_clients)
@@ -538,6 +765,9 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(clients::put_client_logo)
                             .service(clients::delete_client_logo)
                             .service(clients::get_client_secret)
+                            .service(clients::get_client_user_agents)
+                            .service(clients::get_client_token_errors)
+                            .service(clients::get_clients_user_agents)
                             .service(clients::post_clients)
                             .service(clients::put_clients)
                             .service(clients::put_generate_client_secret)
@@ -545,10 +775,13 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(clients::post_clients_dyn)
                             .service(clients::get_clients_dyn)
                             .service(clients::put_clients_dyn)
+                            .service(clients::delete_clients_dyn)
                             .service(generic::get_login_time)
                             .service(users::get_users)
                             .service(users::get_users_register)
                             .service(users::post_users_register)
+                            .service(users::get_users_legal_holds)
+                            .service(users::get_users_inactive)
                             .service(users::get_cust_attr)
                             .service(users::post_cust_attr)
                             .service(users::put_cust_attr)
@@ -563,17 +796,54 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(users::put_user_webid_data)
                             .service(users::get_user_email_confirm)
                             .service(users::post_user_self_convert_passkey)
+                            .service(users::post_user_self_elevate)
+                            .service(users::post_user_self_elevate_email_mfa)
+                            .service(users::put_user_self_email_mfa)
+                            .service(users::get_user_self_push_subscriptions)
+                            .service(users::post_user_self_push_subscription)
+                            .service(users::delete_user_self_push_subscription)
+                            .service(users::post_push_mfa_approve)
+                            .service(users::post_push_mfa_deny)
+                            .service(users::get_user_self_apps)
+                            .service(users::delete_user_self_app)
+                            .service(users::get_user_self_security)
+                            .service(users::get_user_self_export)
+                            .service(users::get_user_self_preferences)
+                            .service(users::put_user_self_preferences)
                             .service(generic::post_password_hash_times)
                             .service(sessions::get_sessions)
                             .service(sessions::delete_sessions)
+                            .service(sessions::get_revoked_tokens)
+                            .service(sessions::post_revoked_token)
+                            .service(sessions::delete_revoked_token)
                             .service(sessions::delete_sessions_for_user)
                             .service(users::get_user_password_reset)
                             .service(users::put_user_password_reset)
+                            .service(users::post_user_password_reset_admin)
                             .service(users::get_user_by_email)
                             .service(users::post_users)
+                            .service(users::post_users_provisioning)
                             .service(users::put_user_by_id)
                             .service(users::put_user_self)
                             .service(users::delete_user_by_id)
+                            .service(users::post_user_login_policy_override)
+                            .service(users::delete_user_login_policy_override)
+                            .service(users::put_user_legal_hold)
+                            .service(users::delete_user_legal_hold)
+                            .service(users::post_mfa_recovery)
+                            .service(users::post_mfa_recovery_confirm)
+                            .service(users::post_mfa_recovery_cancel)
+                            .service(users::post_mfa_recovery_finish)
+                            .service(users::get_user_mfa_recovery)
+                            .service(users::post_user_mfa_recovery_approve)
+                            .service(users::delete_user_mfa_recovery)
+                            .service(users::get_user_role_grants)
+                            .service(users::post_user_role_grant)
+                            .service(users::delete_user_role_grant)
+                            .service(users::post_user_merge)
+                            .service(users::get_user_email_aliases)
+                            .service(users::post_user_email_alias)
+                            .service(users::delete_user_email_alias)
                             .service(users::post_user_password_request_reset)
                             .service(users::get_user_webauthn_passkeys)
                             .service(users::post_webauthn_reg_start)
@@ -589,6 +859,31 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(groups::post_group)
                             .service(groups::put_group)
                             .service(groups::delete_group)
+                            .service(groups::get_group_login_policy)
+                            .service(groups::put_group_login_policy)
+                            .service(groups::delete_group_login_policy)
+                            .service(caches::get_caches)
+                            .service(caches::delete_cache_entry)
+                            .service(cleanup::get_cleanup_jobs)
+                            .service(cleanup::post_cleanup_job)
+                            .service(cluster::get_cluster)
+                            .service(rauthy_handlers::email::get_email_deliveries)
+                            .service(rauthy_handlers::email::post_email_webhook)
+                            .service(jobs::get_jobs)
+                            .service(jobs::post_job_retry)
+                            .service(jobs::delete_job)
+                            .service(login_traces::get_login_traces)
+                            .service(login_traces::post_login_trace)
+                            .service(login_traces::get_login_trace_records)
+                            .service(login_traces::delete_login_trace)
+                            .service(logs::sse_logs)
+                            .service(organizations::get_organizations)
+                            .service(organizations::post_organization)
+                            .service(organizations::put_organization)
+                            .service(organizations::delete_organization)
+                            .service(organizations::get_organization_members)
+                            .service(organizations::put_organization_member)
+                            .service(organizations::delete_organization_member)
                             .service(roles::get_roles)
                             .service(roles::post_role)
                             .service(roles::put_role)
@@ -597,10 +892,24 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
                             .service(scopes::post_scope)
                             .service(scopes::put_scope)
                             .service(scopes::delete_scope)
+                            .service(resource_servers::get_resource_servers)
+                            .service(resource_servers::post_resource_server)
+                            .service(resource_servers::put_resource_server)
+                            .service(resource_servers::delete_resource_server)
                             .service(oidc::post_token)
+                            .service(oidc::post_token_session)
+                            .service(oidc::options_token_session)
+                            .service(oidc::post_sso_handoff)
+                            .service(oidc::get_sso_handoff)
+                            .service(oidc::post_sso_handoff_confirm)
+                            .service(oidc::post_introspect)
                             .service(oidc::post_token_info)
+                            .service(oidc::post_sid_info)
                             .service(oidc::get_userinfo)
                             .service(oidc::get_forward_auth)
+                            .service(generic::get_compliance_report)
+                            .service(lab::post_lab_seed)
+                            .service(lab::delete_lab_seed)
                             .service(generic::get_enc_keys)
                             .service(generic::post_migrate_enc_key)
                             .service(generic::ping)
@@ -623,35 +932,55 @@ async fn actix_main(app_state: web::Data<AppState>) -> std::io::Result<()> {
     .workers(workers)
     .shutdown_timeout(10);
 
-    match listen_scheme {
+    // with h2c enabled, the plain HTTP listener auto-detects HTTP/1.1 and HTTP/2 prior knowledge
+    // connections, while HTTP/2 via TLS is always negotiated through ALPN
+    let server = match listen_scheme {
         ListenScheme::Http => {
-            server
-                .bind(format!("{}:{}", &listen_addr, get_http_port()))?
-                .run()
-                .await
+            let addr = format!("{}:{}", &listen_addr, get_http_port());
+            if *LISTEN_HTTP2_CLEARTEXT {
+                server.bind_auto_h2c(addr)?
+            } else {
+                server.bind(addr)?
+            }
         }
 
-        ListenScheme::Https => {
-            server
-                .bind_rustls_0_22(
-                    format!("{}:{}", &listen_addr, get_https_port()),
-                    tls::load_tls().await,
-                )?
-                .run()
-                .await
-        }
+        ListenScheme::Https => server.bind_rustls_0_22(
+            format!("{}:{}", &listen_addr, get_https_port()),
+            tls::load_tls().await,
+        )?,
 
         ListenScheme::HttpHttps => {
-            server
-                .bind(format!("{}:{}", &listen_addr, get_http_port()))?
-                .bind_rustls_0_22(
-                    format!("{}:{}", &listen_addr, get_https_port()),
-                    tls::load_tls().await,
-                )?
-                .run()
-                .await
+            let addr = format!("{}:{}", &listen_addr, get_http_port());
+            let server = if *LISTEN_HTTP2_CLEARTEXT {
+                server.bind_auto_h2c(addr)?
+            } else {
+                server.bind(addr)?
+            };
+            server.bind_rustls_0_22(
+                format!("{}:{}", &listen_addr, get_https_port()),
+                tls::load_tls().await,
+            )?
         }
-    }
+    };
+
+    // the admin API is served by the same app, the Principal middleware decides about
+    // access depending on the local port the request came in through
+    let server = match *LISTEN_PORT_ADMIN {
+        Some(port) => {
+            info!("Admin API listen port: {}", port);
+            let addr = format!("{}:{}", &listen_addr, port);
+            if listen_scheme != ListenScheme::Http {
+                server.bind_rustls_0_22(addr, tls::load_tls().await)?
+            } else if *LISTEN_HTTP2_CLEARTEXT {
+                server.bind_auto_h2c(addr)?
+            } else {
+                server.bind(addr)?
+            }
+        }
+        None => server,
+    };
+
+    server.run().await
 }
 
 fn get_http_port() -> String {
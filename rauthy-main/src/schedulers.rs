@@ -1,27 +1,40 @@
 use ::time::OffsetDateTime;
 use actix_web::web;
 use chrono::Utc;
+use rauthy_common::cache_metrics::cache_remove;
 use rauthy_common::constants::{
-    CACHE_NAME_12HR, DB_TYPE, DYN_CLIENT_CLEANUP_INTERVAL, DYN_CLIENT_CLEANUP_MINUTES,
-    DYN_CLIENT_REG_TOKEN, ENABLE_DYN_CLIENT_REG, IDX_JWK_KID, RAUTHY_VERSION,
+    CACHE_NAME_12HR, CLOCK_SKEW_LEEWAY, DB_TYPE, DYN_CLIENT_CLEANUP_INTERVAL,
+    DYN_CLIENT_CLEANUP_MINUTES, DYN_CLIENT_REG_TOKEN, EMAIL_DELIVERY_RETENTION_DAYS,
+    ENABLE_DYN_CLIENT_REG, IDX_JWK_KID, JOB_DEAD_RETENTION_DAYS, RAUTHY_VERSION,
 };
-use rauthy_common::DbType;
+use rauthy_common::login_latency::LatencyStage;
+use rauthy_common::{login_latency, DbType};
 use rauthy_models::app_state::{AppState, DbPool};
 use rauthy_models::email::send_pwd_reset_info;
 use rauthy_models::entity::app_version::LatestAppVersion;
+use rauthy_models::entity::cleanup::CleanupJob;
 use rauthy_models::entity::clients::Client;
 use rauthy_models::entity::clients_dyn::ClientDyn;
-use rauthy_models::entity::jwk::Jwk;
+use rauthy_models::entity::email_deliveries::EmailDelivery;
+use rauthy_models::entity::email_mfa::EmailMfaCode;
+use rauthy_models::entity::jobs::Job;
+use rauthy_models::entity::jwk::{Jwk, JwkLifecycle};
+use rauthy_models::entity::legal_holds::LegalHold;
+use rauthy_models::entity::push_mfa::PushMfaRequest;
 use rauthy_models::entity::refresh_tokens::RefreshToken;
 use rauthy_models::entity::sessions::Session;
+use rauthy_models::entity::user_activity::UserActivity;
+use rauthy_models::entity::user_notification_prefs::UserNotificationPrefs;
+use rauthy_models::entity::user_role_grants::UserRoleGrant;
 use rauthy_models::entity::users::User;
-use rauthy_models::events::event::Event;
+use rauthy_models::events::chain::EventChain;
+use rauthy_models::events::event::{Event, EventLevel};
 use rauthy_models::migration::{backup_db, s3_backup_init_test};
 use rauthy_service::auth;
-use redhac::{cache_del, QuorumHealthState, QuorumState};
+use redhac::{AckLevel, QuorumHealthState, QuorumState};
 use semver::Version;
 use sqlx::query_as;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ops::{Add, Sub};
 use std::str::FromStr;
@@ -41,14 +54,27 @@ pub async fn scheduler_main(data: web::Data<AppState>) {
     tokio::spawn(db_backup(data.db.clone()));
     tokio::spawn(dynamic_client_cleanup(data.clone(), rx_health.clone()));
     tokio::spawn(events_cleanup(data.db.clone(), rx_health.clone()));
+    tokio::spawn(events_chain_anchor(data.clone(), rx_health.clone()));
+    tokio::spawn(jobs_cleanup(data.clone(), rx_health.clone()));
+    tokio::spawn(email_deliveries_cleanup(data.clone(), rx_health.clone()));
     tokio::spawn(devices_cleanup(data.db.clone(), rx_health.clone()));
-    tokio::spawn(magic_link_cleanup(data.db.clone(), rx_health.clone()));
-    tokio::spawn(refresh_tokens_cleanup(data.db.clone(), rx_health.clone()));
+    for job in CleanupJob::all() {
+        tokio::spawn(cleanup_job(data.clone(), rx_health.clone(), job));
+    }
+    tokio::spawn(role_grants_cleanup(data.clone(), rx_health.clone()));
+    tokio::spawn(email_mfa_cleanup(data.clone(), rx_health.clone()));
+    tokio::spawn(push_mfa_cleanup(data.clone(), rx_health.clone()));
+    tokio::spawn(revoked_tokens_cleanup(data.db.clone(), rx_health.clone()));
+    tokio::spawn(used_jtis_cleanup(data.db.clone(), rx_health.clone()));
     tokio::spawn(sessions_cleanup(data.db.clone(), rx_health.clone()));
+    tokio::spawn(sessions_consistency(data.clone()));
+    tokio::spawn(login_slo_checker(data.clone()));
     tokio::spawn(jwks_auto_rotate(data.clone(), rx_health.clone()));
     tokio::spawn(jwks_cleanup(data.clone(), rx_health.clone()));
+    tokio::spawn(jwks_lifecycle(data.clone(), rx_health.clone()));
     tokio::spawn(password_expiry_checker(data.clone(), rx_health.clone()));
     tokio::spawn(user_expiry_checker(data.clone(), rx_health.clone()));
+    tokio::spawn(user_inactivity_checker(data.clone(), rx_health.clone()));
     tokio::spawn(app_version_check(data, rx_health));
 }
 
@@ -199,7 +225,72 @@ pub async fn devices_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealth
     }
 }
 
-// Cleans up all Events that exceed the configured EVENT_CLEANUP_DAYS
+// Cleans up all dead jobs that exceed the configured JOB_DEAD_RETENTION_DAYS.
+pub async fn jobs_cleanup(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!(
+                    "Running HA mode without being the leader - skipping jobs_cleanup scheduler"
+                );
+                continue;
+            }
+        }
+
+        debug!("Running jobs_cleanup scheduler");
+
+        let threshold = Utc::now()
+            .sub(chrono::Duration::try_days(*JOB_DEAD_RETENTION_DAYS).unwrap())
+            .timestamp();
+        match Job::delete_dead_before(&data, threshold).await {
+            Ok(count) => debug!("Cleaned up {} dead jobs", count),
+            Err(err) => error!("jobs_cleanup error: {}", err.message),
+        }
+    }
+}
+
+// Cleans up all E-Mail deliveries that exceed the configured EMAIL_DELIVERY_RETENTION_DAYS.
+pub async fn email_deliveries_cleanup(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!(
+                    "Running HA mode without being the leader - skipping email_deliveries_cleanup scheduler"
+                );
+                continue;
+            }
+        }
+
+        debug!("Running email_deliveries_cleanup scheduler");
+
+        let threshold = Utc::now()
+            .sub(chrono::Duration::try_days(*EMAIL_DELIVERY_RETENTION_DAYS).unwrap())
+            .timestamp();
+        match EmailDelivery::delete_before(&data, threshold).await {
+            Ok(count) => debug!("Cleaned up {} E-Mail deliveries", count),
+            Err(err) => error!("email_deliveries_cleanup error: {}", err.message),
+        }
+    }
+}
+
+// Cleans up all Events that exceed the configured EVENT_CLEANUP_DAYS.
+// Higher levels can be kept longer with EVENT_CLEANUP_DAYS_<LEVEL>.
 pub async fn events_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthState>>) {
     let mut interval = time::interval(Duration::from_secs(3600));
 
@@ -207,6 +298,25 @@ pub async fn events_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthS
         .unwrap_or_else(|_| "31".to_string())
         .parse::<u32>()
         .expect("Cannot parse EVENT_CLEANUP_DAYS to u32") as i64;
+    let retention = [
+        EventLevel::Info,
+        EventLevel::Notice,
+        EventLevel::Warning,
+        EventLevel::Critical,
+    ]
+    .into_iter()
+    .map(|level| {
+        let var = format!("EVENT_CLEANUP_DAYS_{}", level.as_str());
+        let days = match env::var(&var) {
+            Ok(v) => v
+                .parse::<u32>()
+                .unwrap_or_else(|_| panic!("Cannot parse {} to u32", var))
+                as i64,
+            Err(_) => cleanup_days,
+        };
+        (level.value(), days)
+    })
+    .collect::<Vec<(i16, i64)>>();
 
     loop {
         interval.tick().await;
@@ -223,26 +333,36 @@ pub async fn events_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthS
 
         debug!("Running events_cleanup scheduler");
 
-        let threshold = Utc::now()
-            .sub(chrono::Duration::days(cleanup_days))
-            .timestamp_millis();
-        let res = sqlx::query!("DELETE FROM events WHERE timestamp < $1", threshold)
-            .execute(&db)
-            .await;
-        match res {
-            Ok(r) => {
-                debug!("Cleaned up {} expired events", r.rows_affected());
+        for (level, days) in &retention {
+            let threshold = Utc::now()
+                .sub(chrono::Duration::days(*days))
+                .timestamp_millis();
+            // events mentioning a user under a legal hold are kept until the hold is released
+            match EventChain::prune(&db, *level, threshold).await {
+                Ok(count) => {
+                    debug!("Cleaned up {} expired events with level {}", count, level);
+                }
+                Err(err) => error!("Events cleanup error: {:?}", err),
             }
-            Err(err) => error!("Events cleanup error: {:?}", err),
         }
     }
 }
 
-// Cleans up old / expired magic links and deletes users, that have never used their
-// 'set first ever password' magic link to keep the database clean in case of an open user registration.
-// Runs every 6 hours.
-pub async fn magic_link_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthState>>) {
-    let mut interval = time::interval(Duration::from_secs(3600 * 6));
+// Signs the current head of the event hash chain every EVENT_CHAIN_ANCHOR_HOURS.
+pub async fn events_chain_anchor(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let hours = env::var("EVENT_CHAIN_ANCHOR_HOURS")
+        .unwrap_or_else(|_| "24".to_string())
+        .parse::<u64>()
+        .expect("Cannot parse EVENT_CHAIN_ANCHOR_HOURS to u64");
+    if hours == 0 {
+        info!("EVENT_CHAIN_ANCHOR_HOURS is 0 - exiting events_chain_anchor scheduler");
+        return;
+    }
+
+    let mut interval = time::interval(Duration::from_secs(hours * 3600));
 
     loop {
         interval.tick().await;
@@ -250,51 +370,53 @@ pub async fn magic_link_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHea
         // will return None in a non-HA deployment
         if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
             if !is_ha_leader {
-                debug!("Running HA mode without being the leader - skipping magic_link_cleanup scheduler");
+                debug!(
+                    "Running HA mode without being the leader - skipping events_chain_anchor scheduler"
+                );
                 continue;
             }
         }
 
-        debug!("Running magic_link_cleanup scheduler");
+        debug!("Running events_chain_anchor scheduler");
 
-        // allow 300 seconds of clock skew before cleaning up magic links
-        let exp = OffsetDateTime::now_utc().unix_timestamp() - 300;
-
-        // Check for expired and unused magic links that are bound to a user which has no password
-        // at all. These users should be deleted since they never cared about the (very important)
-        // password E-Mail.
-        let res = sqlx::query(
-            r#"delete from users where
-            id in (select distinct user_id from magic_links where exp < 1683003398 and used = false)
-            and password is null"#,
-        )
-        .bind(exp)
-        .execute(&db)
-        .await;
-        match res {
-            Ok(r) => {
-                debug!(
-                    "Cleaned up {} users which did not use their initial password reset magic link",
-                    r.rows_affected()
-                );
-            }
-            Err(err) => error!("Magic link / orphan users cleanup error: {:?}", err),
+        match EventChain::anchor(&data).await {
+            Ok(Some(seq)) => info!("Signed a new event chain anchor at seq {}", seq),
+            Ok(None) => debug!("No new events since the last event chain anchor"),
+            Err(err) => error!("Error signing the event chain anchor: {}", err.message),
         }
+    }
+}
 
-        // now we can just delete all expired magic links
-        let res = sqlx::query("delete from magic_links where exp < $1")
-            .bind(exp)
-            .execute(&db)
-            .await;
-        match res {
-            Ok(r) => {
+// Runs a single cleanup job with its configured interval. The jobs can be triggered manually via
+// the API as well.
+pub async fn cleanup_job(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+    job: CleanupJob,
+) {
+    let mins = job.interval_mins();
+    if mins == 0 {
+        info!("Cleanup job {} is disabled", job);
+        return;
+    }
+    let mut interval = time::interval(Duration::from_secs(mins * 60));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
                 debug!(
-                    "Cleaned up {} expired and used magic links",
-                    r.rows_affected()
+                    "Running HA mode without being the leader - skipping cleanup job {}",
+                    job
                 );
+                continue;
             }
-            Err(err) => error!("Magic link cleanup error: {:?}", err),
         }
+
+        // errors are logged and recorded by the job itself
+        let _ = job.run(&data, false).await;
     }
 }
 
@@ -340,6 +462,17 @@ pub async fn password_expiry_checker(
                     .collect::<Vec<User>>();
 
                 for user in users_to_notify {
+                    match UserNotificationPrefs::find(&data, &user.id).await {
+                        Ok(prefs) if !prefs.password_expiry => {
+                            debug!("User {} opted out of password expiry info", user.email);
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            error!("Error looking up notification preferences: {:?}", err);
+                        }
+                    }
+
                     send_pwd_reset_info(&data, &user).await;
                     debug!("User {} notified about password expiry", user.email);
                 }
@@ -425,6 +558,13 @@ pub async fn user_expiry_checker(
                     if let Some(secs) = cleanup_after_secs {
                         let expired_since_secs = (exp_ts - now).unsigned_abs();
                         if expired_since_secs > secs {
+                            if let Ok(Some(_)) = LegalHold::find(&data, &user.id).await {
+                                debug!(
+                                    "Skipping auto cleanup for user {} under a legal hold",
+                                    user.id
+                                );
+                                continue;
+                            }
                             info!(
                                 "Auto cleanup for user {} after being expired for {} minutes",
                                 user.id,
@@ -448,8 +588,192 @@ pub async fn user_expiry_checker(
     }
 }
 
-// Cleans up old / expired / already used Refresh Tokens
-pub async fn refresh_tokens_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthState>>) {
+// Disables users without any login, token refresh or userinfo access for a configured amount
+// of days
+pub async fn user_inactivity_checker(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let Some(days) = env::var("SCHED_USER_INACTIVE_DISABLE_DAYS").ok().map(|d| {
+        d.parse::<u32>()
+            .expect("Cannot parse 'SCHED_USER_INACTIVE_DISABLE_DAYS' to u32")
+    }) else {
+        info!("Auto disable for inactive users disabled");
+        return;
+    };
+    let mut interval = time::interval(Duration::from_secs(3600 * 24));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!("Running HA mode without being the leader - skipping user_inactivity_checker scheduler");
+                continue;
+            }
+        }
+
+        debug!("Running user_inactivity_checker scheduler");
+
+        let since = Utc::now().timestamp() - days as i64 * 86400;
+        let inactive = match UserActivity::find_inactive(&data, since).await {
+            Ok(users) => users,
+            Err(err) => {
+                error!("user_inactivity_checker error: {}", err.message);
+                continue;
+            }
+        };
+
+        for inactive in inactive {
+            if !inactive.should_disable() {
+                debug!("Skipping auto disable for inactive user {}", inactive.id);
+                continue;
+            }
+            let mut user = match User::find(&data, inactive.id).await {
+                Ok(user) => user,
+                Err(err) => {
+                    error!("user_inactivity_checker error: {}", err.message);
+                    continue;
+                }
+            };
+
+            user.enabled = false;
+            if let Err(err) = user.save(&data, None, None).await {
+                error!("Error disabling inactive user {}: {:?}", user.id, err);
+                continue;
+            }
+            if let Err(err) = Session::invalidate_for_user(&data, &user.id).await {
+                error!(
+                    "Error invalidating sessions for user {}: {:?}",
+                    user.id, err
+                );
+            }
+            if let Err(err) = RefreshToken::invalidate_for_user(&data, &user.id).await {
+                error!(
+                    "Error invalidating refresh tokens for user {}: {:?}",
+                    user.id, err
+                );
+            }
+
+            info!(
+                "Disabled user {} after being inactive for more than {} days",
+                user.id, days
+            );
+            data.tx_events
+                .send_async(
+                    Event::user_inactive_disabled(format!("{} after {} days", user.email, days))
+                        .for_user(user.id.clone()),
+                )
+                .await
+                .unwrap();
+        }
+    }
+}
+
+// Cleans up expired E-Mail MFA one-time codes
+pub async fn email_mfa_cleanup(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(300));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!("Running HA mode without being the leader - skipping email_mfa_cleanup scheduler");
+                continue;
+            }
+        }
+
+        debug!("Running email_mfa_cleanup scheduler");
+
+        match EmailMfaCode::delete_expired(&data).await {
+            Ok(count) => debug!("Cleaned up {} expired E-Mail MFA codes", count),
+            Err(err) => error!("email_mfa_cleanup error: {}", err.message),
+        }
+    }
+}
+
+// Cleans up expired push MFA login requests
+pub async fn push_mfa_cleanup(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(300));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!("Running HA mode without being the leader - skipping push_mfa_cleanup scheduler");
+                continue;
+            }
+        }
+
+        debug!("Running push_mfa_cleanup scheduler");
+
+        match PushMfaRequest::delete_expired(&data).await {
+            Ok(count) => debug!("Cleaned up {} expired push MFA requests", count),
+            Err(err) => error!("push_mfa_cleanup error: {}", err.message),
+        }
+    }
+}
+
+// Removes expired time-limited roles from users
+pub async fn role_grants_cleanup(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!("Running HA mode without being the leader - skipping role_grants_cleanup scheduler");
+                continue;
+            }
+        }
+
+        debug!("Running role_grants_cleanup scheduler");
+
+        let grants = match UserRoleGrant::find_expired(&data).await {
+            Ok(grants) => grants,
+            Err(err) => {
+                error!("role_grants_cleanup error: {}", err.message);
+                continue;
+            }
+        };
+
+        for grant in grants {
+            let text = format!("'{}' expired for user {}", grant.role, grant.user_id);
+            let event = Event::elevated_role(text, None).for_user(grant.user_id.clone());
+            if let Err(err) = grant.revoke(&data, event).await {
+                error!(
+                    "Error removing expired role '{}' from user {}: {:?}",
+                    grant.role, grant.user_id, err
+                );
+                continue;
+            }
+
+            info!(
+                "Removed expired role '{}' from user {}",
+                grant.role, grant.user_id
+            );
+        }
+    }
+}
+
+// Cleans up entries from the token denylist which would have expired anyway
+pub async fn revoked_tokens_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthState>>) {
     let mut interval = time::interval(Duration::from_secs(3600 * 3));
 
     loop {
@@ -458,22 +782,52 @@ pub async fn refresh_tokens_cleanup(db: DbPool, rx_health: Receiver<Option<Quoru
         // will return None in a non-HA deployment
         if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
             if !is_ha_leader {
-                debug!("Running HA mode without being the leader - skipping refresh_tokens_cleanup scheduler");
+                debug!("Running HA mode without being the leader - skipping revoked_tokens_cleanup scheduler");
                 continue;
             }
         }
 
-        debug!("Running refresh_tokens_cleanup scheduler");
+        debug!("Running revoked_tokens_cleanup scheduler");
 
         let now = OffsetDateTime::now_utc().unix_timestamp();
-        let res = sqlx::query("delete from refresh_tokens where exp < $1")
+        let res = sqlx::query("delete from revoked_tokens where exp < $1")
             .bind(now)
             .execute(&db)
             .await;
 
         match res {
             Ok(_) => {}
-            Err(err) => error!("Refresh Token Cleanup Error: {:?}", err),
+            Err(err) => error!("Revoked Tokens Cleanup Error: {:?}", err),
+        }
+    }
+}
+
+// Cleans up one-time identifiers, which cannot be replayed anymore, because they have expired
+pub async fn used_jtis_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealthState>>) {
+    let mut interval = time::interval(Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!("Running HA mode without being the leader - skipping used_jtis_cleanup scheduler");
+                continue;
+            }
+        }
+
+        debug!("Running used_jtis_cleanup scheduler");
+
+        let thres = OffsetDateTime::now_utc().unix_timestamp() - *CLOCK_SKEW_LEEWAY as i64;
+        let res = sqlx::query("delete from used_jtis where exp < $1")
+            .bind(thres)
+            .execute(&db)
+            .await;
+
+        match res {
+            Ok(_) => {}
+            Err(err) => error!("Used JTIs Cleanup Error: {:?}", err),
         }
     }
 }
@@ -511,6 +865,79 @@ pub async fn sessions_cleanup(db: DbPool, rx_health: Receiver<Option<QuorumHealt
     }
 }
 
+// Cross-checks the locally cached sessions with the database every
+// SESSION_CONSISTENCY_CHECK_INTERVAL minutes. In contrast to most other schedulers, this one runs
+// on each HA node, since each one needs to check its own cache.
+pub async fn sessions_consistency(data: web::Data<AppState>) {
+    let minutes = env::var("SESSION_CONSISTENCY_CHECK_INTERVAL")
+        .unwrap_or_else(|_| "10".to_string())
+        .parse::<u64>()
+        .expect("Cannot parse SESSION_CONSISTENCY_CHECK_INTERVAL to u64");
+    if minutes == 0 {
+        info!("SESSION_CONSISTENCY_CHECK_INTERVAL is 0 - exiting sessions_consistency scheduler");
+        return;
+    }
+
+    let mut interval = time::interval(Duration::from_secs(minutes * 60));
+
+    loop {
+        interval.tick().await;
+
+        debug!("Running sessions_consistency scheduler");
+
+        match Session::repair_cache(&data).await {
+            Ok(drift) => {
+                if drift.stale > 0 || drift.diverged > 0 {
+                    warn!(
+                        "Session cache drift repaired - checked: {}, stale: {}, diverged: {}",
+                        drift.checked, drift.stale, drift.diverged
+                    );
+                } else {
+                    debug!(
+                        "Checked {} cached sessions without any drift",
+                        drift.checked
+                    );
+                }
+            }
+            Err(err) => error!("Session consistency check error: {}", err.message),
+        }
+    }
+}
+
+// Checks the login latencies against the configured SLOs. The latencies are tracked locally,
+// so this runs on each instance.
+pub async fn login_slo_checker(data: web::Data<AppState>) {
+    if LatencyStage::ALL.iter().all(|s| s.slo_p95().is_none()) {
+        info!("No login latency SLOs configured - exiting login_slo_checker scheduler");
+        return;
+    }
+
+    let mut interval = time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        debug!("Running login_slo_checker scheduler");
+
+        for breach in login_latency::slo_breaches() {
+            warn!(
+                "Login latency SLO breached for {}: p95 {} ms > {} ms",
+                breach.stage.as_str(),
+                breach.p95,
+                breach.slo
+            );
+            data.tx_events
+                .send_async(Event::login_slo_breach(
+                    breach.stage.as_str(),
+                    breach.p95,
+                    breach.slo,
+                ))
+                .await
+                .unwrap();
+        }
+    }
+}
+
 // Auto-Rotates JWKS
 pub async fn jwks_auto_rotate(
     data: web::Data<AppState>,
@@ -581,23 +1008,52 @@ pub async fn jwks_cleanup(
             }
         };
 
-        // TODO after rdbms migration has been done, a nice query can do this more easily
+        let lifecycles = match JwkLifecycle::find_all(&data.db).await {
+            Ok(lcs) => lcs,
+            Err(err) => {
+                error!("Error while running the jwks_cleanup: {:?}", err);
+                continue;
+            }
+        };
+
+        // The active key for each algorithm must never be deleted, even if it is older than the
+        // threshold, because signing would not be possible anymore. The newest one is kept as
+        // well, since it may be scheduled for a future activation.
+        let mut keep: HashSet<String> = Jwk::active_kids(
+            &jwks_all,
+            &lifecycles,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        )
+        .into_iter()
+        .collect();
+        let mut newest = HashMap::with_capacity(4);
+        for jwk in &jwks_all {
+            // ordered by created_at asc -> the last one per algorithm wins
+            newest.insert(jwk.signature.as_str(), jwk.kid.as_str());
+        }
+        keep.extend(newest.into_values().map(String::from));
 
-        // At this point, the latest / current one will always be the first for each key type.
-        // Skip it and check the created timestamps for all following to be older than the max time
-        // they could be needed (offline tokens) and delete them.
-        let mut found = HashSet::with_capacity(4);
+        // Delete all other keys, which are older than the max time they could be needed for
+        // (offline tokens).
         let mut to_delete: HashSet<String> = HashSet::default();
-        for jwk in jwks_all {
-            let signature = jwk.signature.to_string();
-            if found.contains(&signature) {
-                // We already found the first JWK for the current key type -> check created timestamp
-                if jwk.created_at < cleanup_threshold {
-                    to_delete.insert(jwk.kid);
-                }
-            } else {
-                found.insert(signature);
+        for jwk in &jwks_all {
+            if jwk.created_at >= cleanup_threshold {
+                continue;
+            }
+            if keep.contains(&jwk.kid) {
+                debug!(
+                    "Keeping expired JWK {} for {} in jwks_cleanup - it is the active or newest one",
+                    jwk.kid,
+                    jwk.signature.as_str()
+                );
+                continue;
             }
+            debug!(
+                "JWK {} for {} is older than the cleanup threshold - deleting",
+                jwk.kid,
+                jwk.signature.as_str()
+            );
+            to_delete.insert(jwk.kid.clone());
         }
 
         // finally, delete all expired JWKs
@@ -613,17 +1069,77 @@ pub async fn jwks_cleanup(
             }
 
             let idx = format!("{}{}", IDX_JWK_KID, kid);
-            let _ = cache_del(
+            if let Err(err) = cache_remove(
                 CACHE_NAME_12HR.to_string(),
                 idx,
                 &data.caches.ha_cache_config,
+                AckLevel::Quorum,
             )
-            .await;
+            .await
+            {
+                error!(
+                    "Cannot evict JWK {} from the cache in jwks_cleanup: {:?}",
+                    kid, err
+                );
+            }
         }
         info!("Cleaned up old JWKs: {}", count);
     }
 }
 
+// Persists the JWK usage counters and makes sure that scheduled JWK activations take effect
+pub async fn jwks_lifecycle(
+    data: web::Data<AppState>,
+    rx_health: Receiver<Option<QuorumHealthState>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(60));
+    let mut last_check = Utc::now().timestamp();
+
+    loop {
+        interval.tick().await;
+
+        // the usage counters are local to each instance -> flush on each node
+        if let Err(err) = JwkLifecycle::flush_usage(&data.db).await {
+            error!("Error flushing JWK usage counters: {:?}", err);
+        }
+
+        let now = Utc::now().timestamp();
+        let since = last_check;
+        last_check = now;
+
+        // will return None in a non-HA deployment
+        if let Some(is_ha_leader) = is_ha_leader(&rx_health) {
+            if !is_ha_leader {
+                debug!(
+                    "Running HA mode without being the leader - skipping jwks_lifecycle activations"
+                );
+                continue;
+            }
+        }
+
+        debug!("Running jwks_lifecycle scheduler");
+
+        let res = sqlx::query(
+            "select kid from jwks_lifecycle where activate_at > $1 and activate_at <= $2",
+        )
+        .bind(since)
+        .bind(now)
+        .fetch_all(&data.db)
+        .await;
+
+        match res {
+            Ok(rows) if rows.is_empty() => {}
+            Ok(_) => {
+                info!("Scheduled JWK activation is due - clearing latest JWK caches");
+                if let Err(err) = Jwk::clear_latest_caches(&data).await {
+                    error!("Error clearing latest JWK caches: {:?}", err);
+                }
+            }
+            Err(err) => error!("JWK Lifecycle Error: {:?}", err),
+        }
+    }
+}
+
 pub async fn app_version_check(
     data: web::Data<AppState>,
     rx_health: Receiver<Option<QuorumHealthState>>,
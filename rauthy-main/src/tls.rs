@@ -9,7 +9,21 @@ use tracing::error;
 pub async fn load_tls() -> rustls::ServerConfig {
     let path_key = env::var("TLS_KEY").unwrap_or_else(|_| "tls/tls.key".to_string());
     let path_cert = env::var("TLS_CERT").unwrap_or_else(|_| "tls/tls.crt".to_string());
+    load_tls_from(path_key, path_cert).await
+}
+
+/// Loads the TLS config for the metrics server, which falls back to the main TLS key and cert.
+pub async fn load_tls_metrics() -> rustls::ServerConfig {
+    let path_key = env::var("METRICS_TLS_KEY")
+        .or_else(|_| env::var("TLS_KEY"))
+        .unwrap_or_else(|_| "tls/tls.key".to_string());
+    let path_cert = env::var("METRICS_TLS_CERT")
+        .or_else(|_| env::var("TLS_CERT"))
+        .unwrap_or_else(|_| "tls/tls.crt".to_string());
+    load_tls_from(path_key, path_cert).await
+}
 
+async fn load_tls_from(path_key: String, path_cert: String) -> rustls::ServerConfig {
     let key_file = fs::read(&path_key).await.expect("Reading TLS private key");
     let key = if path_key.ends_with(".der") {
         PrivateKeyDer::try_from(key_file).expect("TLS private key to be valid")
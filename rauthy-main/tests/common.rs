@@ -71,6 +71,11 @@ pub async fn get_token_set() -> TokenSet {
 }
 
 pub async fn session_headers() -> (HeaderMap, TokenSet) {
+    session_headers_for(USERNAME, PASSWORD).await
+}
+
+/// Logs in the given user without MFA via the UI flow and returns the session headers
+pub async fn session_headers_for(email: &str, password: &str) -> (HeaderMap, TokenSet) {
     let backend_url = get_backend_url();
 
     // Step 1: GET /authorize for the CSRF token and simulate UI login
@@ -91,8 +96,8 @@ pub async fn session_headers() -> (HeaderMap, TokenSet) {
     let headers = cookie_csrf_headers_from_res(res).await.unwrap();
 
     let req_login = LoginRequest {
-        email: USERNAME.to_string(),
-        password: Some(PASSWORD.to_string()),
+        email: email.to_string(),
+        password: Some(password.to_string()),
         client_id: "rauthy".to_string(),
         redirect_uri: redirect_uri.to_owned(),
         scopes: None,
@@ -100,6 +105,11 @@ pub async fn session_headers() -> (HeaderMap, TokenSet) {
         nonce: Some("MySuperNonce".to_string()),
         code_challenge: Some(challenge_s256),
         code_challenge_method: Some("S256".to_string()),
+        user_attrs: None,
+        response_mode: None,
+        request: None,
+        resource: None,
+        authorization_details: None,
     };
 
     let mut res = reqwest::Client::new()
@@ -123,6 +133,20 @@ pub async fn session_headers() -> (HeaderMap, TokenSet) {
         username: None,
         password: None,
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
 
     let url_token = format!("{}/oidc/token", backend_url);
@@ -1,6 +1,6 @@
 use crate::common::{
     check_status, code_state_from_headers, cookie_csrf_headers_from_res, get_auth_headers,
-    get_backend_url, CLIENT_ID, CLIENT_SECRET, PASSWORD, USERNAME,
+    get_backend_url, get_issuer, session_headers, CLIENT_ID, CLIENT_SECRET, PASSWORD, USERNAME,
 };
 use actix_web::{http, web, App, HttpResponse, HttpServer};
 use chrono::Utc;
@@ -8,19 +8,31 @@ use ed25519_compact::Noise;
 use josekit::jwk;
 use pretty_assertions::assert_eq;
 use rauthy_common::constants::{
-    APPLICATION_JSON, DPOP_TOKEN_ENDPOINT, HEADER_DPOP_NONCE, TOKEN_DPOP,
+    APPLICATION_JSON, APPLICATION_PROBLEM_JSON, CLIENT_ASSERTION_TYPE_SPIFFE, CSRF_HEADER,
+    DPOP_TOKEN_ENDPOINT, DPOP_USERINFO_ENDPOINT, GRANT_TYPE_DEVICE_CODE, GRANT_TYPE_TOKEN_EXCHANGE,
+    HEADER_DPOP_NONCE, JWT_TYP_ACCESS_TOKEN, TOKEN_DPOP, TOKEN_TYPE_ACCESS_TOKEN,
+};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType, ProblemDetails};
+use rauthy_common::utils::{
+    base64_url_encode, base64_url_no_pad_decode, base64_url_no_pad_encode, get_rand,
 };
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::{base64_url_encode, base64_url_no_pad_encode, get_rand};
 use rauthy_models::entity::dpop_proof::{DPoPClaims, DPoPHeader};
-use rauthy_models::entity::jwk::{JWKSPublicKey, JwkKeyPairAlg, JwkKeyPairType, JWKS};
+use rauthy_models::entity::jwk::{JWKSPublicKey, JwkHistory, JwkKeyPairAlg, JwkKeyPairType, JWKS};
+use rauthy_models::entity::revoked_tokens::RevokedToken;
 use rauthy_models::request::{
-    LoginRequest, TokenRequest, TokenValidationRequest, UpdateClientRequest,
+    HeadlessLoginRequest, LoginRequest, NewClientRequest, ResponseMode, SessionTokenRequest,
+    SidInfoRequest, TokenRequest, TokenRevocationRequest, TokenValidationRequest,
+    UpdateClientRequest,
+};
+use rauthy_models::response::{
+    ClientSecretResponse, HeadlessAuthorizeResponse, HeadlessLoginStep, SidInfo, TokenInfo,
 };
-use rauthy_models::response::TokenInfo;
 use rauthy_models::JwtTokenType;
 use rauthy_service::token_set::TokenSet;
-use reqwest::header::AUTHORIZATION;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE, LOCATION, ORIGIN,
+    SET_COOKIE,
+};
 use ring::digest;
 use std::error::Error;
 use std::fmt::Write;
@@ -103,6 +115,11 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
         nonce: Some(nonce.to_owned()),
         code_challenge: Some(challenge_plain.to_owned()),
         code_challenge_method: Some("plain".to_string()),
+        user_attrs: None,
+        response_mode: None,
+        request: None,
+        resource: None,
+        authorization_details: None,
     };
     let res = reqwest::Client::new()
         .post(&url_auth)
@@ -111,7 +128,28 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
         .send()
         .await?;
     // should be 401 - wrong password
-    check_status(res, 401).await?;
+    let res = check_status(res, 401).await?;
+    let err = res.json::<ErrorResponse>().await?;
+    assert_eq!(err.code, ErrorCode::InvalidCredentials);
+    assert_eq!(err.i18n_key, "error.invalid_credentials");
+
+    // the same error as RFC 9457 problem+json
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers.clone())
+        .header(ACCEPT, APPLICATION_PROBLEM_JSON)
+        .json(&req_login)
+        .send()
+        .await?;
+    let res = check_status(res, 401).await?;
+    assert_eq!(
+        res.headers().get(CONTENT_TYPE).unwrap(),
+        APPLICATION_PROBLEM_JSON
+    );
+    let problem = res.json::<ProblemDetails>().await?;
+    assert_eq!(problem.typ, "urn:rauthy:error:invalid_credentials");
+    assert_eq!(problem.status, 401);
+    assert_eq!(problem.code, ErrorCode::InvalidCredentials);
 
     req_login.password = Some(PASSWORD.to_string());
     let mut res = reqwest::Client::new()
@@ -138,6 +176,20 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
         username: None,
         password: None,
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let url_token = format!("{}/oidc/token", backend_url);
     let res = reqwest::Client::new()
@@ -169,6 +221,8 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
     assert!(ts.id_token.is_some());
     assert!(ts.refresh_token.is_some());
     assert_eq!(ts.expires_in, 60);
+    let access_token = ts.access_token.clone();
+    let refresh_token = ts.refresh_token.clone().unwrap();
 
     // verify 'nonce' existing in id token
     let id_token = ts.id_token.unwrap();
@@ -196,6 +250,21 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
         .expect("'nonce' is not set in id token");
     assert_eq!(nonce_claim, nonce);
 
+    // the 'sid' must be bound to the still active session
+    let sid = payload
+        .claim("sid")
+        .map(|v| v.to_string().replace('\"', ""))
+        .expect("'sid' is not set in id token");
+    let info = sid_info(SidInfoRequest { sid }).await?;
+    assert!(info.active);
+    assert!(info.exp.is_some());
+
+    let info = sid_info(SidInfoRequest {
+        sid: base64_url_no_pad_encode(digest::digest(&digest::SHA256, b"unknown").as_ref()),
+    })
+    .await?;
+    assert!(!info.active);
+
     // ############################################################################
     // ########################## flow with S256 pkce #############################
     // ############################################################################
@@ -227,6 +296,26 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
     // should be 401 - trying to use already used authorization code
     check_status(res, 401).await?;
 
+    // the tokens from the first redemption must have been revoked with the reuse
+    let res = reqwest::Client::new()
+        .post(format!("{}/oidc/introspect", backend_url))
+        .basic_auth(CLIENT_ID, Some(CLIENT_SECRET))
+        .form(&[("token", &refresh_token)])
+        .send()
+        .await?;
+    let res = check_status(res, 200).await?;
+    assert!(!res.json::<TokenInfo>().await?.active);
+
+    // the tests run without ACCESS_TOKEN_JWT_PROFILE - the access token must be revoked anyway
+    let res = reqwest::Client::new()
+        .post(format!("{}/oidc/introspect", backend_url))
+        .basic_auth(CLIENT_ID, Some(CLIENT_SECRET))
+        .form(&[("token", &access_token)])
+        .send()
+        .await?;
+    let res = check_status(res, 200).await?;
+    assert!(!res.json::<TokenInfo>().await?.active);
+
     req_token.code = Some(code);
     let mut res = reqwest::Client::new()
         .post(&url_token)
@@ -282,6 +371,15 @@ async fn test_authorization_code_flow() -> Result<(), Box<dyn Error>> {
         force_mfa: false,
         client_uri: None,
         contacts: None,
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
     };
     let url_client = format!("{}/clients/{}", backend_url, CLIENT_ID);
     let auth_headers = get_auth_headers().await?;
@@ -356,6 +454,20 @@ async fn test_client_credentials_flow() -> Result<(), Box<dyn Error>> {
         username: None,
         password: None,
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let url = format!("{}/oidc/token", backend_url);
     let client = reqwest::Client::new();
@@ -375,6 +487,19 @@ async fn test_client_credentials_flow() -> Result<(), Box<dyn Error>> {
     assert!(ts.id_token.is_none());
     assert!(ts.refresh_token.is_none());
 
+    // the tests run with the default config without the RFC 9068 JWT access token profile,
+    // which is covered by unit tests
+    let (header, rest) = ts.access_token.split_once('.').unwrap();
+    let header =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(header).unwrap())?;
+    assert_ne!(header["typ"], JWT_TYP_ACCESS_TOKEN);
+    let (claims, _) = rest.split_once('.').unwrap();
+    let claims =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(claims).unwrap())?;
+    assert!(claims["client_id"].is_null());
+    // the `jti` is needed for revocations and must always exist
+    assert!(claims["jti"].is_string());
+
     let req = TokenValidationRequest {
         token: ts.access_token,
     };
@@ -383,6 +508,152 @@ async fn test_client_credentials_flow() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_revoked_tokens() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+
+    let body = TokenRequest {
+        grant_type: "client_credentials".to_string(),
+        code: None,
+        redirect_uri: None,
+        client_id: Some(CLIENT_ID.to_string()),
+        client_secret: Some(CLIENT_SECRET.to_string()),
+        code_verifier: None,
+        device_code: None,
+        username: None,
+        password: None,
+        refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
+    };
+    let res = client
+        .post(format!("{}/oidc/token", backend_url))
+        .form(&body)
+        .send()
+        .await?;
+    let res = check_status(res, 200).await?;
+    let ts = res.json::<TokenSet>().await?;
+
+    let info = validate_token(TokenValidationRequest {
+        token: ts.access_token.clone(),
+    })
+    .await?;
+    assert!(info.active);
+
+    // The tests run without ACCESS_TOKEN_JWT_PROFILE, so this makes sure revocations work
+    // with the default access tokens as well.
+    // either the token or the jti with its exp is needed
+    let url = format!("{}/revoked_tokens", backend_url);
+    let mut payload = TokenRevocationRequest {
+        token: None,
+        jti: Some("SomeJti".to_string()),
+        exp: None,
+        reason: "Leaked in a build log".to_string(),
+    };
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    payload.jti = None;
+    payload.token = Some(ts.access_token.clone());
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let revoked = res.json::<RevokedToken>().await?;
+
+    let info = validate_token(TokenValidationRequest {
+        token: ts.access_token.clone(),
+    })
+    .await?;
+    assert!(!info.active);
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let all = res.json::<Vec<RevokedToken>>().await?;
+    assert!(all.iter().any(|t| t.jti == revoked.jti));
+
+    let res = client
+        .delete(format!("{}/{}", url, revoked.jti))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let info = validate_token(TokenValidationRequest {
+        token: ts.access_token,
+    })
+    .await?;
+    assert!(info.active);
+
+    // concurrent revocations must not overwrite each other
+    let mut tokens = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let res = client
+            .post(format!("{}/oidc/token", backend_url))
+            .form(&body)
+            .send()
+            .await?;
+        let res = check_status(res, 200).await?;
+        tokens.push(res.json::<TokenSet>().await?.access_token);
+    }
+    let mut handles = Vec::with_capacity(tokens.len());
+    for token in &tokens {
+        let client = client.clone();
+        let url = url.clone();
+        let headers = auth_headers.clone();
+        let payload = TokenRevocationRequest {
+            token: Some(token.clone()),
+            jti: None,
+            exp: None,
+            reason: "Leaked in a build log".to_string(),
+        };
+        handles.push(tokio::spawn(async move {
+            client
+                .post(&url)
+                .headers(headers)
+                .json(&payload)
+                .send()
+                .await
+                .map(|res| res.status().as_u16())
+        }));
+    }
+    for handle in handles {
+        assert_eq!(handle.await??, 200);
+    }
+    for token in tokens {
+        let info = validate_token(TokenValidationRequest { token }).await?;
+        assert!(!info.active);
+    }
+
+    Ok(())
+}
+
 // This test is a bit messy currently with some code reception and so one - WIP
 #[tokio::test]
 #[ignore]
@@ -421,6 +692,11 @@ async fn test_concurrent_logins() -> Result<(), Box<dyn Error>> {
         nonce: None,
         code_challenge: Some(challenge_plain.to_owned()),
         code_challenge_method: None,
+        user_attrs: None,
+        response_mode: None,
+        request: None,
+        resource: None,
+        authorization_details: None,
     };
 
     let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
@@ -486,6 +762,20 @@ async fn test_password_flow() -> Result<(), Box<dyn Error>> {
         username: Some(USERNAME.to_string()),
         password: None,
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let client = reqwest::Client::new();
     let res = client.post(&url).form(&body).send().await?;
@@ -540,6 +830,20 @@ async fn test_password_flow() -> Result<(), Box<dyn Error>> {
         username: None,
         password: None,
         refresh_token: Some(ts.refresh_token.clone().unwrap()),
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let url = format!("{}/oidc/token", get_backend_url());
     let res = reqwest::Client::new().post(&url).form(&req).send().await?;
@@ -577,6 +881,20 @@ async fn test_dpop() -> Result<(), Box<dyn Error>> {
         username: Some(USERNAME.to_string()),
         password: Some(PASSWORD.to_string()),
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
 
     // dpop header
@@ -602,7 +920,7 @@ async fn test_dpop() -> Result<(), Box<dyn Error>> {
         htm: http::Method::POST.to_string(),
         htu: DPOP_TOKEN_ENDPOINT.clone().to_string(),
         iat: Utc::now().timestamp(),
-        // ath: None,
+        ath: None,
         nonce: None,
     };
 
@@ -686,6 +1004,20 @@ async fn test_dpop() -> Result<(), Box<dyn Error>> {
         username: None,
         password: None,
         refresh_token: Some(ts.refresh_token.clone().unwrap()),
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
 
     // without DPoP header, it should fail
@@ -714,6 +1046,57 @@ async fn test_dpop() -> Result<(), Box<dyn Error>> {
     assert!(token_info.cnf.is_some());
     assert_eq!(token_info.cnf.unwrap().jkt, fingerprint);
 
+    // the bound access token on /userinfo needs a proof for this request
+    let url_userinfo = format!("{}/oidc/userinfo", get_backend_url());
+    let sign_proof = |claims: &DPoPClaims| {
+        let claims_json = serde_json::to_string(claims).unwrap();
+        let claims_b64 = base64_url_no_pad_encode(claims_json.as_bytes());
+        let mut proof = format!("{}.{}", header_b64, claims_b64);
+        let sig = kp.sk.sign(&proof, Some(Noise::generate()));
+        write!(proof, ".{}", base64_url_no_pad_encode(sig.as_ref())).unwrap();
+        proof
+    };
+    let hash = digest::digest(&digest::SHA256, ts.access_token.as_bytes());
+    let mut claims = DPoPClaims {
+        jti: get_rand(16),
+        htm: http::Method::GET.to_string(),
+        htu: DPOP_USERINFO_ENDPOINT.clone().to_string(),
+        iat: Utc::now().timestamp(),
+        ath: Some(base64_url_no_pad_encode(hash.as_ref())),
+        nonce: Some(nonce.to_string()),
+    };
+    let proof = sign_proof(&claims);
+    let auth = format!("{} {}", TOKEN_DPOP, ts.access_token);
+
+    let res = client
+        .get(&url_userinfo)
+        .header(AUTHORIZATION, &auth)
+        .header(TOKEN_DPOP, &proof)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    // the same proof must not be accepted twice
+    let res = client
+        .get(&url_userinfo)
+        .header(AUTHORIZATION, &auth)
+        .header(TOKEN_DPOP, &proof)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+    let err = res.json::<ErrorResponse>().await?;
+    assert!(err.message.contains("used already"));
+
+    // a new proof is fine again
+    claims.jti = get_rand(16);
+    let res = client
+        .get(&url_userinfo)
+        .header(AUTHORIZATION, &auth)
+        .header(TOKEN_DPOP, sign_proof(&claims))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
     Ok(())
 }
 
@@ -763,6 +1146,11 @@ async fn test_authorization_code_flow_ephemeral_client() -> Result<(), Box<dyn E
         nonce: Some(nonce.to_owned()),
         code_challenge: Some(challenge_s256),
         code_challenge_method: Some("S256".to_string()),
+        user_attrs: None,
+        response_mode: None,
+        request: None,
+        resource: None,
+        authorization_details: None,
     };
     let res = client
         .post(&url_auth)
@@ -787,6 +1175,20 @@ async fn test_authorization_code_flow_ephemeral_client() -> Result<(), Box<dyn E
         username: None,
         password: None,
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
 
     let url_token = format!("{}/oidc/token", backend_url);
@@ -812,6 +1214,20 @@ async fn test_authorization_code_flow_ephemeral_client() -> Result<(), Box<dyn E
         username: None,
         password: None,
         refresh_token: Some(ts.refresh_token.clone().unwrap()),
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let res = client.post(&url_token).form(&req).send().await?;
     assert!(res.status().is_success());
@@ -886,6 +1302,20 @@ async fn test_auth_headers() -> Result<(), Box<dyn Error>> {
         username: Some(USERNAME.to_string()),
         password: Some(PASSWORD.to_string()),
         refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
     };
     let res = client.post(&url_token).form(&body).send().await?;
     assert!(res.status().is_success());
@@ -958,14 +1388,1180 @@ async fn test_auth_headers() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_token_payload_limit() -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/oidc/token", get_backend_url());
+    let client = reqwest::Client::new();
+
+    // the default limit for the auth endpoints is 16 kB
+    let body = format!("grant_type=password&username={}", "a".repeat(32 * 1024));
+    let res = client
+        .post(&url)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await?;
+    let err = check_status(res, 413)
+        .await?
+        .json::<ErrorResponse>()
+        .await?;
+    assert_eq!(err.code, ErrorCode::PayloadTooLarge);
+
+    Ok(())
+}
+
+async fn sid_info(req: SidInfoRequest) -> Result<SidInfo, Box<dyn Error>> {
+    let url = format!("{}/oidc/sidInfo", get_backend_url());
+    let res = reqwest::Client::new().post(&url).json(&req).send().await?;
+    assert_eq!(res.status(), 200);
+    let info = res.json::<SidInfo>().await?;
+    Ok(info)
+}
+
 async fn validate_token(req: TokenValidationRequest) -> Result<TokenInfo, Box<dyn Error>> {
-    let url_valid = format!("{}/oidc/tokenInfo", get_backend_url());
-    let res = reqwest::Client::new()
+    let url_valid = format!("{}/oidc/introspect", get_backend_url());
+    let client = reqwest::Client::new();
+
+    // the caller must authenticate
+    let res = client
         .post(&url_valid)
-        .json(&req)
+        .form(&[("token", &req.token)])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .post(&url_valid)
+        .basic_auth(CLIENT_ID, Some(CLIENT_SECRET))
+        .form(&[("token", &req.token)])
         .send()
         .await?;
     assert_eq!(res.status(), 200);
     let info = res.json::<TokenInfo>().await.unwrap();
+    assert!(info.active);
+    assert_eq!(info.sub, info.username);
+
+    // Rauthy never issues opaque tokens, anything that is not one of our JWTs is inactive
+    let res = client
+        .post(&url_valid)
+        .basic_auth(CLIENT_ID, Some(CLIENT_SECRET))
+        .form(&[("token", "SomeOpaqueToken1337")])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    assert!(!res.json::<TokenInfo>().await?.active);
+
+    // the deprecated alias takes JSON and requires authentication as well
+    let url_token_info = format!("{}/oidc/tokenInfo", get_backend_url());
+    let res = client.post(&url_token_info).json(&req).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .post(&url_token_info)
+        .basic_auth(CLIENT_ID, Some(CLIENT_SECRET))
+        .json(&req)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let alias_info = res.json::<TokenInfo>().await?;
+    assert!(alias_info.active);
+    assert_eq!(alias_info.jti, info.jti);
+    assert_eq!(alias_info.client_id, info.client_id);
+
     Ok(info)
 }
+
+#[tokio::test]
+async fn test_jwks_history() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("{}/oidc/jwks", backend_url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let res = check_status(res, 200).await?;
+    let history = res.json::<Vec<JwkHistory>>().await?;
+    assert!(history.len() >= 4);
+
+    // exactly one active key for each algorithm
+    for alg in [
+        JwkKeyPairAlg::RS256,
+        JwkKeyPairAlg::RS384,
+        JwkKeyPairAlg::RS512,
+        JwkKeyPairAlg::EdDSA,
+    ] {
+        let active = history
+            .iter()
+            .filter(|h| h.alg == alg && h.active)
+            .collect::<Vec<_>>();
+        assert_eq!(active.len(), 1);
+    }
+
+    // the only active key must never be retired
+    let active_eddsa = history
+        .iter()
+        .find(|h| h.alg == JwkKeyPairAlg::EdDSA && h.active)
+        .unwrap();
+    if history
+        .iter()
+        .filter(|h| h.alg == JwkKeyPairAlg::EdDSA && h.retired_at.is_none())
+        .count()
+        == 1
+    {
+        let res = client
+            .post(format!(
+                "{}/oidc/jwks/{}/retire",
+                backend_url, active_eddsa.kid
+            ))
+            .headers(auth_headers.clone())
+            .send()
+            .await?;
+        assert_eq!(res.status(), 400);
+    }
+
+    // no access without a valid session
+    let res = client
+        .get(format!("{}/oidc/jwks", backend_url))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_headless_login() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let redirect_uri = "http://localhost:3000/oidc/callback";
+    let challenge_plain = "oDXug9zfYqfz8ejcqMpALRPXfW8QhbKV2AVuScAt8xrLKDAmaRYQ4yRi2uqcH9ys";
+    let url_auth = format!(
+        "{}/oidc/authorize/headless?client_id={}&redirect_uri={}&response_type=code&code_challenge={}",
+        backend_url, CLIENT_ID, redirect_uri, challenge_plain
+    );
+
+    // a not allowed redirect_uri must be rejected the same way as for the HTML login
+    let res = reqwest::get(format!(
+        "{}/oidc/authorize/headless?client_id={}&redirect_uri=http://localhost:1337/evil&response_type=code",
+        backend_url, CLIENT_ID
+    ))
+    .await?;
+    assert_eq!(res.status(), 400);
+
+    let res = reqwest::get(&url_auth).await?;
+    let res = check_status(res, 200).await?;
+    let cookie = res.headers().get(SET_COOKIE).unwrap().to_str()?.to_string();
+    let (session_cookie, _) = cookie.split_once(';').unwrap();
+    let mut headers = HeaderMap::new();
+    headers.append(COOKIE, HeaderValue::from_str(session_cookie)?);
+
+    let auth = res.json::<HeadlessAuthorizeResponse>().await?;
+    assert_eq!(auth.next, HeadlessLoginStep::Credentials);
+    assert_eq!(auth.client_id, CLIENT_ID);
+    assert!(auth.pow_required);
+    assert!(!auth.csrf_token.is_empty());
+    headers.append(CSRF_HEADER, HeaderValue::from_str(&auth.csrf_token)?);
+
+    let req_login = HeadlessLoginRequest {
+        login: LoginRequest {
+            email: USERNAME.to_string(),
+            password: Some(PASSWORD.to_string()),
+            client_id: CLIENT_ID.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            scopes: None,
+            state: None,
+            nonce: None,
+            code_challenge: Some(challenge_plain.to_string()),
+            code_challenge_method: Some("plain".to_string()),
+            user_attrs: None,
+            response_mode: None,
+            request: None,
+            resource: None,
+            authorization_details: None,
+        },
+        pow: None,
+    };
+    let url_login = format!("{}/oidc/authorize/headless", backend_url);
+
+    // the CSRF token is mandatory
+    let res = reqwest::Client::new()
+        .post(&url_login)
+        .header(COOKIE, HeaderValue::from_str(session_cookie)?)
+        .json(&req_login)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    // the PoW is mandatory by default
+    let res = reqwest::Client::new()
+        .post(&url_login)
+        .headers(headers)
+        .json(&req_login)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_objects() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let redirect_uri = "http://localhost:3000/oidc/callback";
+    let challenge_plain = "oDXug9zfYqfz8ejcqMpALRPXfW8QhbKV2AVuScAt8xrLKDAmaRYQ4yRi2uqcH9ys";
+    let url_auth = format!("{}/oidc/authorize", backend_url);
+    let client_no_redirect = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    let request_object = |exp: Option<i64>| {
+        let mut claims = serde_json::json!({
+            "client_id": CLIENT_ID,
+            "redirect_uri": redirect_uri,
+            "response_type": "code",
+            "scope": "openid",
+            "code_challenge": challenge_plain,
+            "code_challenge_method": "plain",
+            "jti": get_rand(24),
+        });
+        if let Some(exp) = exp {
+            claims["exp"] = serde_json::json!(exp);
+        }
+        format!(
+            "{}.{}.",
+            base64_url_no_pad_encode(br#"{"alg":"none"}"#),
+            base64_url_no_pad_encode(claims.to_string().as_bytes())
+        )
+    };
+
+    // 'exp' is mandatory
+    let res = client_no_redirect
+        .get(&url_auth)
+        .query(&[("client_id", CLIENT_ID), ("request", &request_object(None))])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    // encrypted request objects are not supported
+    let res = client_no_redirect
+        .get(&url_auth)
+        .query(&[("client_id", CLIENT_ID), ("request", "a.b.c.d.e")])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    // a valid object is expanded into the query params
+    let jwt = request_object(Some(Utc::now().timestamp() + 60));
+    let res = client_no_redirect
+        .get(&url_auth)
+        .query(&[("client_id", CLIENT_ID), ("request", &jwt)])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 302);
+    let location = res.headers().get(LOCATION).unwrap().to_str()?.to_string();
+    assert!(location.contains("code_challenge_method=plain"));
+
+    let res = reqwest::get(&location).await?;
+    let res = check_status(res, 200).await?;
+    let headers = cookie_csrf_headers_from_res(res).await?;
+
+    let mut req_login = LoginRequest {
+        email: USERNAME.to_string(),
+        password: Some(PASSWORD.to_string()),
+        client_id: CLIENT_ID.to_string(),
+        redirect_uri: redirect_uri.to_string(),
+        scopes: Some(vec!["openid".to_string()]),
+        state: None,
+        nonce: None,
+        code_challenge: Some(challenge_plain.to_string()),
+        code_challenge_method: Some("plain".to_string()),
+        user_attrs: None,
+        response_mode: None,
+        request: Some(jwt),
+        resource: None,
+        authorization_details: None,
+    };
+
+    // the login must match the request object
+    req_login.nonce = Some(get_rand(16));
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers.clone())
+        .json(&req_login)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    req_login.nonce = None;
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers.clone())
+        .json(&req_login)
+        .send()
+        .await?;
+    check_status(res, 202).await?;
+
+    // each request object can only be used for a single code
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers)
+        .json(&req_login)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
+
+/// Returns the unvalidated claims of a JARM `response`
+fn jarm_claims(location: &str) -> serde_json::Value {
+    let (_, response) = location.split_once("response=").unwrap();
+    let claims = response.split('.').nth(1).unwrap();
+    serde_json::from_slice(&base64_url_no_pad_decode(claims).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_jarm() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let redirect_uri = "http://localhost:3000/oidc/callback";
+    let challenge_plain = "oDXug9zfYqfz8ejcqMpALRPXfW8QhbKV2AVuScAt8xrLKDAmaRYQ4yRi2uqcH9ys";
+    let url_auth = format!("{}/oidc/authorize", backend_url);
+    let client_no_redirect = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    // errors after the redirect_uri validation are signed responses as well
+    let res = client_no_redirect
+        .get(&url_auth)
+        .query(&[
+            ("client_id", CLIENT_ID),
+            ("redirect_uri", redirect_uri),
+            ("response_type", "code"),
+            ("prompt", "none"),
+            ("response_mode", "jwt"),
+            ("state", "jarm_err"),
+        ])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 302);
+    let location = res.headers().get(LOCATION).unwrap().to_str()?.to_string();
+    assert!(location.starts_with(&format!("{}?response=", redirect_uri)));
+    let claims = jarm_claims(&location);
+    assert_eq!(claims["error"], "login_required");
+    assert_eq!(claims["state"], "jarm_err");
+    assert_eq!(claims["aud"], CLIENT_ID);
+    assert!(claims.get("code").is_none());
+
+    // without a JWT response mode, the error page is shown
+    let res = client_no_redirect
+        .get(&url_auth)
+        .query(&[
+            ("client_id", CLIENT_ID),
+            ("redirect_uri", redirect_uri),
+            ("response_type", "code"),
+            ("prompt", "none"),
+        ])
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    let res = reqwest::get(format!(
+        "{}?client_id={}&redirect_uri={}&response_type=code",
+        url_auth, CLIENT_ID, redirect_uri
+    ))
+    .await?;
+    let res = check_status(res, 200).await?;
+    let headers = cookie_csrf_headers_from_res(res).await?;
+
+    let mut req_login = LoginRequest {
+        email: USERNAME.to_string(),
+        password: Some(PASSWORD.to_string()),
+        client_id: CLIENT_ID.to_string(),
+        redirect_uri: redirect_uri.to_string(),
+        scopes: None,
+        state: Some("jarm_state".to_string()),
+        nonce: None,
+        code_challenge: Some(challenge_plain.to_string()),
+        code_challenge_method: Some("plain".to_string()),
+        user_attrs: None,
+        response_mode: Some(ResponseMode::QueryJwt),
+        request: None,
+        resource: None,
+        authorization_details: None,
+    };
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers.clone())
+        .json(&req_login)
+        .send()
+        .await?;
+    let res = check_status(res, 202).await?;
+    let location = res.headers().get(LOCATION).unwrap().to_str()?.to_string();
+    assert!(location.starts_with(&format!("{}?response=", redirect_uri)));
+    let claims = jarm_claims(&location);
+    assert_eq!(claims["state"], "jarm_state");
+    assert_eq!(claims["iss"], backend_url);
+    assert_eq!(claims["aud"], CLIENT_ID);
+    let code = claims["code"].as_str().unwrap().to_string();
+
+    let req_token = TokenRequest {
+        grant_type: "authorization_code".to_string(),
+        code: Some(code),
+        redirect_uri: None,
+        client_id: Some(CLIENT_ID.to_string()),
+        client_secret: Some(CLIENT_SECRET.to_string()),
+        code_verifier: Some(challenge_plain.to_string()),
+        device_code: None,
+        username: None,
+        password: None,
+        refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
+    };
+    let res = reqwest::Client::new()
+        .post(format!("{}/oidc/token", backend_url))
+        .form(&req_token)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    // form_post.jwt never puts the signed response into a URL
+    req_login.response_mode = Some(ResponseMode::FormPostJwt);
+    let res = reqwest::Client::new()
+        .post(&url_auth)
+        .headers(headers)
+        .json(&req_login)
+        .send()
+        .await?;
+    let res = check_status(res, 202).await?;
+    let location = res.headers().get(LOCATION).unwrap().to_str()?.to_string();
+    assert!(location.starts_with(&format!("{}/oidc/authorize/form_post?id=", backend_url)));
+    assert!(!location.contains("response="));
+
+    let res = reqwest::get(&location).await?;
+    let body = check_status(res, 200).await?.text().await?;
+    assert!(body.contains(r#"<form method="post" action="http:"#));
+    assert!(body.contains("localhost:3000"));
+    assert!(body.contains(r#"name="response""#));
+
+    // and the form can only be rendered once
+    let res = reqwest::get(&location).await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_token() -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/oidc/token/session", get_backend_url());
+    let payload = SessionTokenRequest {
+        client_id: CLIENT_ID.to_string(),
+        scope: None,
+    };
+
+    // a valid session is mandatory
+    let res = reqwest::Client::new()
+        .post(&url)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    // the preflight must not allow any unknown origin
+    let res = reqwest::Client::new()
+        .request(reqwest::Method::OPTIONS, &url)
+        .header("origin", "https://evil.example.com")
+        .send()
+        .await?;
+    assert_eq!(res.status(), 204);
+    assert!(res.headers().get("access-control-allow-origin").is_none());
+
+    // a fresh session, since the rotation would invalidate the shared one
+    let (headers, _ts) = session_headers().await;
+    let origin = get_backend_url().trim_end_matches("/auth/v1").to_string();
+    let payload = SessionTokenRequest {
+        client_id: "rauthy".to_string(),
+        scope: None,
+    };
+    let res = reqwest::Client::new()
+        .post(&url)
+        .headers(headers.clone())
+        .header(ORIGIN, &origin)
+        .json(&payload)
+        .send()
+        .await?;
+    let res = check_status(res, 200).await?;
+    let cookie = res.headers().get(SET_COOKIE).unwrap().to_str()?;
+    let (rotated, _) = cookie.split_once(';').unwrap();
+    assert_ne!(rotated, headers.get(COOKIE).unwrap().to_str()?);
+    let ts = res.json::<TokenSet>().await?;
+    assert!(ts.refresh_token.is_none());
+
+    // requests which have been in-flight during the rotation still use the old cookie
+    let res = reqwest::Client::new()
+        .post(&url)
+        .headers(headers)
+        .header(ORIGIN, &origin)
+        .json(&payload)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_app_attestation_grants() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+    let client_id = "attested";
+
+    // mapped to an App ID in `APP_ATTEST_CLIENTS`
+    let new_client = NewClientRequest {
+        id: client_id.to_string(),
+        secret: None,
+        name: Some("Attested App".to_string()),
+        confidential: true,
+        redirect_uris: vec!["de.localhost.app:/oidc/callback".to_string()],
+        post_logout_redirect_uris: None,
+    };
+    let res = client
+        .post(format!("{}/clients", backend_url))
+        .headers(auth_headers.clone())
+        .json(&new_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let update_client = UpdateClientRequest {
+        id: client_id.to_string(),
+        name: Some("Attested App".to_string()),
+        confidential: true,
+        redirect_uris: vec!["de.localhost.app:/oidc/callback".to_string()],
+        post_logout_redirect_uris: None,
+        allowed_origins: None,
+        enabled: true,
+        flows_enabled: vec![
+            "authorization_code".to_string(),
+            "password".to_string(),
+            "client_credentials".to_string(),
+            "refresh_token".to_string(),
+            GRANT_TYPE_DEVICE_CODE.to_string(),
+            GRANT_TYPE_TOKEN_EXCHANGE.to_string(),
+        ],
+        access_token_alg: JwkKeyPairAlg::EdDSA,
+        id_token_alg: JwkKeyPairAlg::EdDSA,
+        refresh_token: true,
+        auth_code_lifetime: 60,
+        access_token_lifetime: 60,
+        scopes: vec!["openid".to_string()],
+        default_scopes: vec!["openid".to_string()],
+        challenges: Some(vec!["S256".to_string()]),
+        force_mfa: false,
+        client_uri: None,
+        contacts: None,
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
+    };
+    let url_client = format!("{}/clients/{}", backend_url, client_id);
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let res = client
+        .get(format!("{}/secret", url_client))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let secret = check_status(res, 200)
+        .await?
+        .json::<ClientSecretResponse>()
+        .await?
+        .secret;
+
+    // even though the flows are enabled, only `authorization_code` with a valid attestation
+    // and `refresh_token` must be accepted
+    let mut body = TokenRequest {
+        grant_type: "client_credentials".to_string(),
+        code: None,
+        redirect_uri: None,
+        client_id: Some(client_id.to_string()),
+        client_secret: secret,
+        code_verifier: None,
+        device_code: None,
+        username: None,
+        password: None,
+        refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
+    };
+    let url_token = format!("{}/oidc/token", backend_url);
+    let res = client.post(&url_token).form(&body).send().await?;
+    let err = check_status(res, 400)
+        .await?
+        .json::<ErrorResponse>()
+        .await?;
+    assert_eq!(err.code, ErrorCode::FlowNotAllowed);
+
+    body.grant_type = "password".to_string();
+    body.username = Some(USERNAME.to_string());
+    body.password = Some(PASSWORD.to_string());
+    let res = client.post(&url_token).form(&body).send().await?;
+    let err = check_status(res, 400)
+        .await?
+        .json::<ErrorResponse>()
+        .await?;
+    assert_eq!(err.code, ErrorCode::FlowNotAllowed);
+
+    let res = client
+        .post(format!("{}/oidc/device", backend_url))
+        .form(&[("client_id", client_id)])
+        .send()
+        .await?;
+    check_status(res, 403).await?;
+
+    let res = client
+        .delete(&url_client)
+        .headers(auth_headers)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_device_code_source_ip() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+    let client_id = "device_cidrs";
+
+    let new_client = NewClientRequest {
+        id: client_id.to_string(),
+        secret: None,
+        name: Some("Device CIDRs".to_string()),
+        confidential: true,
+        redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+        post_logout_redirect_uris: None,
+    };
+    let res = client
+        .post(format!("{}/clients", backend_url))
+        .headers(auth_headers.clone())
+        .json(&new_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    // the test requests never come from this network
+    let update_client = UpdateClientRequest {
+        id: client_id.to_string(),
+        name: Some("Device CIDRs".to_string()),
+        confidential: true,
+        redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+        post_logout_redirect_uris: None,
+        allowed_origins: None,
+        enabled: true,
+        flows_enabled: vec![GRANT_TYPE_DEVICE_CODE.to_string()],
+        access_token_alg: JwkKeyPairAlg::EdDSA,
+        id_token_alg: JwkKeyPairAlg::EdDSA,
+        refresh_token: false,
+        auth_code_lifetime: 60,
+        access_token_lifetime: 60,
+        scopes: vec!["openid".to_string()],
+        default_scopes: vec!["openid".to_string()],
+        challenges: None,
+        force_mfa: false,
+        client_uri: None,
+        contacts: None,
+        allowed_cidrs: Some(vec!["10.0.0.0/8".to_string()]),
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
+    };
+    let url_client = format!("{}/clients/{}", backend_url, client_id);
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let res = client
+        .get(format!("{}/secret", url_client))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let secret = check_status(res, 200)
+        .await?
+        .json::<ClientSecretResponse>()
+        .await?
+        .secret
+        .unwrap();
+
+    let res = client
+        .post(format!("{}/oidc/device", backend_url))
+        .form(&[("client_id", client_id), ("client_secret", secret.as_str())])
+        .send()
+        .await?;
+    let device = check_status(res, 200)
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    let device_code = device["device_code"].as_str().unwrap();
+
+    // the device code must not be usable from outside the allowed networks,
+    // even with a valid secret
+    let res = client
+        .post(format!("{}/oidc/token", backend_url))
+        .form(&[
+            ("grant_type", GRANT_TYPE_DEVICE_CODE),
+            ("client_id", client_id),
+            ("client_secret", secret.as_str()),
+            ("device_code", device_code),
+        ])
+        .send()
+        .await?;
+    let err = check_status(res, 400)
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    assert_eq!(err["error"], "unauthorized_client");
+
+    let res = client
+        .delete(&url_client)
+        .headers(auth_headers)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_exchange() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+
+    let mut update_client = UpdateClientRequest {
+        id: CLIENT_ID.to_string(),
+        name: Some("Init Client".to_string()),
+        confidential: true,
+        redirect_uris: vec!["http://localhost:3000/oidc/callback".to_string()],
+        post_logout_redirect_uris: Some(vec!["http://localhost:8080".to_string()]),
+        allowed_origins: Some(vec!["http://localhost:8080/*".to_string()]),
+        enabled: true,
+        flows_enabled: vec![
+            "authorization_code".to_string(),
+            "password".to_string(),
+            "client_credentials".to_string(),
+            "refresh_token".to_string(),
+            GRANT_TYPE_TOKEN_EXCHANGE.to_string(),
+        ],
+        access_token_alg: JwkKeyPairAlg::RS384,
+        id_token_alg: JwkKeyPairAlg::EdDSA,
+        refresh_token: true,
+        auth_code_lifetime: 120,
+        access_token_lifetime: 60,
+        scopes: vec![
+            "openid".to_string(),
+            "email".to_string(),
+            "profile".to_string(),
+            "groups".to_string(),
+        ],
+        default_scopes: vec!["openid".to_string(), "email".to_string()],
+        challenges: Some(vec!["S256".to_string(), "plain".to_string()]),
+        force_mfa: false,
+        client_uri: None,
+        contacts: None,
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
+    };
+    let url_client = format!("{}/clients/{}", backend_url, CLIENT_ID);
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    // the subject token for a user
+    let url = format!("{}/oidc/token", backend_url);
+    let mut body = TokenRequest {
+        grant_type: "password".to_string(),
+        code: None,
+        redirect_uri: None,
+        client_id: Some(CLIENT_ID.to_string()),
+        client_secret: Some(CLIENT_SECRET.to_string()),
+        code_verifier: None,
+        device_code: None,
+        username: Some(USERNAME.to_string()),
+        password: Some(PASSWORD.to_string()),
+        refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
+    };
+    let res = client.post(&url).form(&body).send().await?;
+    let subject = check_status(res, 200).await?.json::<TokenSet>().await?;
+    let claims = subject.access_token.split('.').nth(1).unwrap();
+    let subject_sub =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(claims).unwrap())?
+            ["sub"]
+            .clone();
+
+    body.grant_type = GRANT_TYPE_TOKEN_EXCHANGE.to_string();
+    body.username = None;
+    body.password = None;
+    body.subject_token = Some(subject.access_token);
+    body.subject_token_type = Some(TOKEN_TYPE_ACCESS_TOKEN.to_string());
+
+    // another audience must be allowed for the client
+    body.audience = Some("rauthy".to_string());
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 400);
+
+    // scopes outside the subject token are dropped
+    body.audience = None;
+    body.scope = Some("openid groups".to_string());
+    let res = client.post(&url).form(&body).send().await?;
+    let ts = check_status(res, 200).await?.json::<TokenSet>().await?;
+    let claims = ts.access_token.split('.').nth(1).unwrap();
+    let claims =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(claims).unwrap())?;
+    assert_eq!(claims["scope"], "openid");
+
+    // nothing left to grant
+    body.scope = Some("groups".to_string());
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 400);
+
+    update_client.token_exchange_audiences = Some(vec!["rauthy".to_string()]);
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    body.audience = Some("rauthy".to_string());
+    body.scope = None;
+    let res = client.post(&url).form(&body).send().await?;
+    let ts = check_status(res, 200).await?.json::<TokenSet>().await?;
+    let claims = ts.access_token.split('.').nth(1).unwrap();
+    let claims =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(claims).unwrap())?;
+    assert!(claims["aud"].to_string().contains("rauthy"));
+
+    // a mesh workload exchanges the user token with its SPIFFE identity instead of a secret
+    let handle = serve_spiffe_bundle();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!handle.is_finished());
+
+    let spiffe_id = "spiffe://example.org/ns/test/sa/api";
+    body.client_secret = None;
+    body.client_assertion_type = Some(CLIENT_ASSERTION_TYPE_SPIFFE.to_string());
+    body.client_assertion = Some(spiffe_svid(spiffe_id, &get_issuer()));
+    let res = client.post(&url).form(&body).send().await?;
+    let ts = check_status(res, 200).await?.json::<TokenSet>().await?;
+    let claims = ts.access_token.split('.').nth(1).unwrap();
+    let claims =
+        serde_json::from_slice::<serde_json::Value>(&base64_url_no_pad_decode(claims).unwrap())?;
+    assert!(claims["aud"].to_string().contains("rauthy"));
+    assert_eq!(claims["sub"], subject_sub);
+
+    // the SVID must have been issued for Rauthy
+    body.client_assertion = Some(spiffe_svid(spiffe_id, "https://api.example.org"));
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // only mapped SPIFFE IDs are accepted
+    body.client_assertion = Some(spiffe_svid(
+        "spiffe://example.org/ns/test/sa/other",
+        &get_issuer(),
+    ));
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // the same SVID authenticates the workload for the `client_credentials` grant
+    body.grant_type = "client_credentials".to_string();
+    body.subject_token = None;
+    body.subject_token_type = None;
+    body.audience = None;
+    body.client_assertion = Some(spiffe_svid(spiffe_id, &get_issuer()));
+    let res = client.post(&url).form(&body).send().await?;
+    check_status(res, 200).await?;
+
+    // clean up
+    update_client.flows_enabled.pop();
+    update_client.token_exchange_audiences = None;
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers)
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_user_store_import() -> Result<(), Box<dyn Error>> {
+    // host the external user store from `USER_STORE_URL`
+    let handle = serve_user_store();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!handle.is_finished());
+
+    let url = format!("{}/oidc/token", get_backend_url());
+    let mut body = TokenRequest {
+        grant_type: "password".to_string(),
+        code: None,
+        redirect_uri: None,
+        client_id: Some(CLIENT_ID.to_string()),
+        client_secret: Some(CLIENT_SECRET.to_string()),
+        code_verifier: None,
+        device_code: None,
+        username: Some("hijack@localhost.de".to_string()),
+        password: Some("LegacyPassword123!".to_string()),
+        refresh_token: None,
+        client_assertion_type: None,
+        client_assertion: None,
+        subject_token: None,
+        subject_token_type: None,
+        actor_token: None,
+        actor_token_type: None,
+        requested_token_type: None,
+        audience: None,
+        scope: None,
+        resource: None,
+        authorization_details: None,
+        attestation_type: None,
+        attestation: None,
+        attestation_key_id: None,
+    };
+    let client = reqwest::Client::new();
+
+    // the store must not be able to hand out another account
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 401);
+
+    body.username = Some("legacy@localhost.de".to_string());
+    body.password = Some("WrongPassword123!".to_string());
+    let res = client.post(&url).form(&body).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // the user is imported with the password grant and keeps working afterward
+    body.password = Some("LegacyPassword123!".to_string());
+    for _ in 0..2 {
+        let res = client.post(&url).form(&body).send().await?;
+        let ts = check_status(res, 200).await?.json::<TokenSet>().await?;
+        let claims = ts.access_token.split('.').nth(1).unwrap();
+        let claims = serde_json::from_slice::<serde_json::Value>(
+            &base64_url_no_pad_decode(claims).unwrap(),
+        )?;
+        let roles = claims["roles"].as_array().unwrap();
+        assert!(roles.contains(&serde_json::Value::from("user")));
+        assert!(!roles.contains(&serde_json::Value::from("rauthy_admin")));
+    }
+
+    Ok(())
+}
+
+/// The Ed25519 key of the SPIFFE trust bundle, which is stable across test runs.
+fn spiffe_key_pair() -> ed25519_compact::KeyPair {
+    ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([7; 32]))
+}
+
+/// Builds a JWT-SVID like SPIRE would issue it.
+fn spiffe_svid(spiffe_id: &str, aud: &str) -> String {
+    let header = serde_json::json!({ "alg": "EdDSA", "kid": "spiffe-test", "typ": "JWT" });
+    let claims = serde_json::json!({
+        "sub": spiffe_id,
+        "aud": [aud],
+        "exp": Utc::now().timestamp() + 300,
+    });
+    let mut token = format!(
+        "{}.{}",
+        base64_url_no_pad_encode(header.to_string().as_bytes()),
+        base64_url_no_pad_encode(claims.to_string().as_bytes()),
+    );
+    let sig = spiffe_key_pair().sk.sign(&token, Some(Noise::generate()));
+    write!(token, ".{}", base64_url_no_pad_encode(sig.as_ref())).unwrap();
+    token
+}
+
+fn serve_spiffe_bundle() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let actix_system = actix_web::rt::System::new();
+        actix_system.block_on(async {
+            HttpServer::new(|| {
+                App::new().route(
+                    "/keys",
+                    web::get().to(|| async {
+                        // SPIRE does not include the `alg` in its bundle
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "keys": [{
+                                "kty": "OKP",
+                                "crv": "Ed25519",
+                                "kid": "spiffe-test",
+                                "x": base64_url_encode(spiffe_key_pair().pk.as_slice()),
+                            }]
+                        }))
+                    }),
+                )
+            })
+            .bind(("127.0.0.1", 10082))
+            .expect("port 10082 to be free for testing")
+            .run()
+            .await
+            .expect("SPIFFE bundle test http server to start")
+        })
+    })
+}
+
+fn serve_user_store() -> JoinHandle<()> {
+    thread::spawn(move || {
+        let actix_system = actix_web::rt::System::new();
+        actix_system.block_on(async {
+            HttpServer::new(|| {
+                App::new().route(
+                    "/verify",
+                    web::post().to(|payload: web::Json<serde_json::Value>| async move {
+                        let login = payload["login"].as_str().unwrap_or_default();
+                        let password = payload["password"].as_str().unwrap_or_default();
+                        if password != "LegacyPassword123!" {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+                        match login {
+                            "legacy@localhost.de" => HttpResponse::Ok().json(serde_json::json!({
+                                "email": "legacy@localhost.de",
+                                "given_name": "Legacy",
+                                "roles": ["user", "rauthy_admin"],
+                            })),
+                            "hijack@localhost.de" => HttpResponse::Ok().json(serde_json::json!({
+                                "email": "admin@localhost.de",
+                                "roles": ["rauthy_admin"],
+                            })),
+                            _ => HttpResponse::NotFound().finish(),
+                        }
+                    }),
+                )
+            })
+            .bind(("127.0.0.1", 10081))
+            .expect("port 10081 to be free for testing")
+            .run()
+            .await
+            .expect("user store test http server to start")
+        })
+    })
+}
+
+#[tokio::test]
+async fn test_rp_initiated_logout() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    // without a session, a valid request redirects immediately with the state passed through
+    let res = client
+        .get(format!(
+            "{}/oidc/logout?client_id=rauthy&post_logout_redirect_uri=http://localhost:8080/auth/v1/&state=abc123",
+            backend_url
+        ))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 302);
+    assert_eq!(
+        res.headers().get(LOCATION).unwrap().to_str()?,
+        "http://localhost:8080/auth/v1/?state=abc123"
+    );
+
+    // not registered for the client
+    let res = client
+        .get(format!(
+            "{}/oidc/logout?client_id=rauthy&post_logout_redirect_uri=https://evil.example.com/",
+            backend_url
+        ))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    // the client cannot be identified without an id_token_hint or client_id
+    let res = client
+        .get(format!(
+            "{}/oidc/logout?post_logout_redirect_uri=http://localhost:8080/auth/v1/",
+            backend_url
+        ))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
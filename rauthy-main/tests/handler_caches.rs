@@ -0,0 +1,62 @@
+use crate::common::{check_status, get_auth_headers, get_backend_url, CLIENT_ID};
+use pretty_assertions::assert_eq;
+use rauthy_common::constants::CACHE_NAME_12HR;
+use rauthy_common::utils::cache_entry_client;
+use rauthy_models::response::CacheStatsResponse;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_caches() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    let url = format!("{}/caches", backend_url);
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let stats = check_status(res, 200)
+        .await?
+        .json::<Vec<CacheStatsResponse>>()
+        .await?;
+    assert!(stats.iter().any(|s| s.name == CACHE_NAME_12HR));
+
+    // evict the init client and make sure it is loaded from the DB afterward
+    let url_evict = format!(
+        "{}/caches/{}/{}",
+        backend_url,
+        CACHE_NAME_12HR,
+        cache_entry_client(CLIENT_ID)
+    );
+    let res = client
+        .delete(&url_evict)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let url_client = format!("{}/clients/{}", backend_url, CLIENT_ID);
+    let res = client
+        .get(&url_client)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let url_unknown = format!("{}/caches/notExisting/someKey", backend_url);
+    let res = client
+        .delete(&url_unknown)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use crate::common::{check_status, get_auth_headers, get_backend_url};
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights, ApiKeyAccess};
+use rauthy_models::request::ApiKeyRequest;
+use reqwest::header::AUTHORIZATION;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_cleanup_jobs() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    // must be rejected without a valid session
+    let url = format!("{}/cleanup", backend_url);
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+    let res = client
+        .post(format!("{}/refresh_tokens", url))
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let jobs = check_status(res, 200)
+        .await?
+        .json::<Vec<serde_json::Value>>()
+        .await?;
+    assert_eq!(jobs.len(), 4);
+
+    let res = client
+        .post(format!("{}/pow", url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    check_status(res, 404).await?;
+
+    for job in ["magic_links", "refresh_tokens"] {
+        let res = client
+            .post(format!("{}/{}", url, job))
+            .headers(auth_headers.clone())
+            .send()
+            .await?;
+        let run = check_status(res, 200)
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(run["manual"], true);
+        assert!(run.get("error").is_none());
+    }
+
+    // magic_links deletes users and needs more than the generic delete rights
+    let payload = ApiKeyRequest {
+        name: "cleanup_test".to_string(),
+        exp: None,
+        access: vec![ApiKeyAccess {
+            group: AccessGroup::Generic,
+            access_rights: vec![AccessRights::Delete],
+        }],
+    };
+    let res = client
+        .post(format!("{}/api_keys", backend_url))
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    let secret = check_status(res, 200).await?.text().await?;
+    let key_header = format!("API-Key {}", secret);
+
+    let res = client
+        .post(format!("{}/refresh_tokens", url))
+        .header(AUTHORIZATION, &key_header)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+    let res = client
+        .post(format!("{}/magic_links", url))
+        .header(AUTHORIZATION, &key_header)
+        .send()
+        .await?;
+    check_status(res, 403).await?;
+
+    let res = client
+        .delete(format!("{}/api_keys/{}", backend_url, payload.name))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use crate::common::{check_status, get_backend_url};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::error::Error;
+
+mod common;
+
+const WEBHOOK_TOKEN: &str = "SuperSecureWebhookToken1337";
+
+#[tokio::test]
+async fn test_email_webhook() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+    let url = format!("{}/email/webhook", backend_url);
+
+    let payload = json!({
+        "message_id": "<doesNotExist123@localhost.de>",
+        "status": "bounced",
+        "reason": "550 5.1.1 unknown user",
+    });
+
+    // missing and invalid tokens must be rejected
+    let res = client.post(&url).json(&payload).send().await?;
+    assert_eq!(res.status(), 401);
+    let res = client
+        .post(&url)
+        .bearer_auth("invalidToken")
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+
+    // only 'deferred' and 'bounced' can be reported
+    let res = client
+        .post(&url)
+        .bearer_auth(WEBHOOK_TOKEN)
+        .json(&json!({
+            "message_id": "<doesNotExist123@localhost.de>",
+            "status": "sent",
+        }))
+        .send()
+        .await?;
+    check_status(res, 400).await?;
+
+    // the delivery id cannot be extracted
+    let res = client
+        .post(&url)
+        .bearer_auth(WEBHOOK_TOKEN)
+        .json(&json!({
+            "message_id": "<@localhost.de>",
+            "status": "bounced",
+        }))
+        .send()
+        .await?;
+    check_status(res, 400).await?;
+
+    // a valid request for an unknown delivery
+    let res = client
+        .post(&url)
+        .bearer_auth(WEBHOOK_TOKEN)
+        .json(&payload)
+        .send()
+        .await?;
+    check_status(res, 404).await?;
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::common::{check_status, get_auth_headers, get_backend_url};
+use chrono::Utc;
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights, ApiKeyAccess};
+use rauthy_models::events::event::{Event, EventLevel, EventType};
+use rauthy_models::request::{ApiKeyRequest, EventRecipientRequest, EventRecipientsRequest};
+use rauthy_models::response::{EventChainVerifyResponse, EventRecipientResponse};
+use reqwest::header::AUTHORIZATION;
+use std::error::Error;
+use std::time::Duration;
+
+mod common;
+
+#[tokio::test]
+async fn test_events_chain_verify() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    let url = format!("{}/events/chain/verify", backend_url);
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client.get(&url).headers(auth_headers).send().await?;
+    let report = check_status(res, 200)
+        .await?
+        .json::<EventChainVerifyResponse>()
+        .await?;
+    assert!(report.last_seq.unwrap_or_default() <= report.head_seq);
+    assert!(report.anchors_invalid.is_empty());
+    // events of users deleted by other tests are anonymized and must not break the chain
+    assert!(report.modified.is_empty());
+    assert!(report.broken_links.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_event_recipients() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+    let start = Utc::now().timestamp() - 1;
+
+    let url = format!("{}/events/recipients", backend_url);
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let payload = EventRecipientsRequest {
+        recipients: vec![EventRecipientRequest {
+            email: "Oncall@Localhost.de".to_string(),
+            level: EventLevel::Warning,
+            quiet_from: Some("22:00".to_string()),
+            quiet_to: Some("07:00".to_string()),
+            utc_offset: None,
+            timezone: Some("Europe/Berlin".to_string()),
+        }],
+    };
+
+    // an API Key must never be able to redirect events, even with `events: update`
+    let url_keys = format!("{}/api_keys", backend_url);
+    let key_req = ApiKeyRequest {
+        name: "event_recipients".to_string(),
+        exp: None,
+        access: vec![ApiKeyAccess {
+            group: AccessGroup::Events,
+            access_rights: vec![AccessRights::Read, AccessRights::Update],
+        }],
+    };
+    let res = client
+        .post(&url_keys)
+        .headers(auth_headers.clone())
+        .json(&key_req)
+        .send()
+        .await?;
+    let key_header = format!("API-Key {}", check_status(res, 200).await?.text().await?);
+
+    let res = client
+        .put(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 401);
+    let res = client
+        .get(&url)
+        .header(AUTHORIZATION, &key_header)
+        .send()
+        .await?;
+    let recipients = check_status(res, 200)
+        .await?
+        .json::<Vec<EventRecipientResponse>>()
+        .await?;
+    assert!(recipients.is_empty());
+
+    let res = client
+        .delete(format!("{}/{}", url_keys, key_req.name))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    // unknown time zones are rejected
+    let mut invalid = EventRecipientsRequest {
+        recipients: vec![EventRecipientRequest {
+            email: "oncall@localhost.de".to_string(),
+            level: EventLevel::Warning,
+            quiet_from: None,
+            quiet_to: None,
+            utc_offset: None,
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+        }],
+    };
+    let res = client
+        .put(&url)
+        .headers(auth_headers.clone())
+        .json(&invalid)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+    invalid.recipients[0].timezone = None;
+    invalid.recipients[0].quiet_from = Some("22:00".to_string());
+    let res = client
+        .put(&url)
+        .headers(auth_headers.clone())
+        .json(&invalid)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    let res = client
+        .put(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    let recipients = check_status(res, 200)
+        .await?
+        .json::<Vec<EventRecipientResponse>>()
+        .await?;
+    assert_eq!(recipients.len(), 1);
+    assert_eq!(recipients[0].email, "oncall@localhost.de");
+    assert_eq!(recipients[0].level, EventLevel::Warning);
+    assert_eq!(recipients[0].quiet_from.as_deref(), Some("22:00"));
+    assert_eq!(recipients[0].quiet_to.as_deref(), Some("07:00"));
+    assert_eq!(recipients[0].timezone.as_deref(), Some("Europe/Berlin"));
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let fetched = check_status(res, 200)
+        .await?
+        .json::<Vec<EventRecipientResponse>>()
+        .await?;
+    assert_eq!(fetched.len(), 1);
+    assert_eq!(fetched[0].timezone.as_deref(), Some("Europe/Berlin"));
+
+    // reset for other tests
+    let res = client
+        .put(&url)
+        .headers(auth_headers.clone())
+        .json(&EventRecipientsRequest { recipients: vec![] })
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    // both changes must have been recorded, the events are persisted asynchronously
+    let url_events = format!("{}/events", backend_url);
+    let mut texts = Vec::new();
+    for _ in 0..20 {
+        let res = client
+            .post(&url_events)
+            .headers(auth_headers.clone())
+            .json(&serde_json::json!({
+                "from": start,
+                "level": "info",
+                "typ": EventType::EventRecipientsChanged,
+            }))
+            .send()
+            .await?;
+        texts = check_status(res, 200)
+            .await?
+            .json::<Vec<Event>>()
+            .await?
+            .into_iter()
+            .filter_map(|e| e.text)
+            .collect::<Vec<_>>();
+        if texts.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(texts
+        .iter()
+        .any(|t| t.ends_with("[] -> [oncall@localhost.de]")));
+    assert!(texts
+        .iter()
+        .any(|t| t.ends_with("[oncall@localhost.de] -> []")));
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::common::{check_status, get_auth_headers, get_backend_url};
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::jwk::JwkKeyPairAlg;
+use rauthy_models::request::{FeatureFlagRequest, NewClientRequest, UpdateClientRequest};
+use rauthy_models::response::{
+    ClientSecretResponse, FeatureFlagResponse, FeatureFlagsEvaluatedResponse,
+};
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_feature_flags_evaluate() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+    let client_id = "feature_flags";
+
+    let new_client = NewClientRequest {
+        id: client_id.to_string(),
+        secret: None,
+        name: Some("Feature Flags".to_string()),
+        confidential: true,
+        redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+        post_logout_redirect_uris: None,
+    };
+    let res = client
+        .post(format!("{}/clients", backend_url))
+        .headers(auth_headers.clone())
+        .json(&new_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let mut update_client = UpdateClientRequest {
+        id: client_id.to_string(),
+        name: Some("Feature Flags".to_string()),
+        confidential: true,
+        redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+        post_logout_redirect_uris: None,
+        allowed_origins: None,
+        enabled: true,
+        flows_enabled: vec!["client_credentials".to_string()],
+        access_token_alg: JwkKeyPairAlg::EdDSA,
+        id_token_alg: JwkKeyPairAlg::EdDSA,
+        refresh_token: false,
+        auth_code_lifetime: 60,
+        access_token_lifetime: 60,
+        scopes: vec!["openid".to_string()],
+        default_scopes: vec!["openid".to_string()],
+        challenges: None,
+        force_mfa: false,
+        client_uri: None,
+        contacts: None,
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
+    };
+    let url_client = format!("{}/clients/{}", backend_url, client_id);
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let res = client
+        .get(format!("{}/secret", url_client))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let secret = check_status(res, 200)
+        .await?
+        .json::<ClientSecretResponse>()
+        .await?
+        .secret
+        .unwrap();
+
+    // only visible for our client
+    let flag = FeatureFlagRequest {
+        name: "ff_client_only".to_string(),
+        description: None,
+        enabled: true,
+        percentage: None,
+        groups: None,
+        attr_key: None,
+        attr_value: None,
+        client_ids: Some(vec![client_id.to_string()]),
+    };
+    let res = client
+        .post(format!("{}/feature_flags", backend_url))
+        .headers(auth_headers.clone())
+        .json(&flag)
+        .send()
+        .await?;
+    let flag = check_status(res, 200)
+        .await?
+        .json::<FeatureFlagResponse>()
+        .await?;
+
+    let res = client
+        .post(format!("{}/oidc/token", backend_url))
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", secret.as_str()),
+        ])
+        .send()
+        .await?;
+    let ts = check_status(res, 200)
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    let access_token = ts["access_token"].as_str().unwrap();
+
+    let url_evaluate = format!("{}/feature_flags/evaluate", backend_url);
+    let res = client.get(&url_evaluate).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // the token has no user and the client itself as the `sub`
+    let res = client
+        .get(&url_evaluate)
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+    let evaluated = check_status(res, 200)
+        .await?
+        .json::<FeatureFlagsEvaluatedResponse>()
+        .await?;
+    assert_eq!(evaluated.flags.get("ff_client_only"), Some(&true));
+
+    // the still valid token must not be usable after the client has been disabled
+    update_client.enabled = false;
+    let res = client
+        .put(&url_client)
+        .headers(auth_headers.clone())
+        .json(&update_client)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let res = client
+        .get(&url_evaluate)
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+    check_status(res, 401).await?;
+
+    // the same for a deleted client
+    let res = client
+        .delete(&url_client)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    let res = client
+        .get(&url_evaluate)
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+    check_status(res, 401).await?;
+
+    let res = client
+        .delete(format!("{}/feature_flags/{}", backend_url, flag.id))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    check_status(res, 200).await?;
+
+    Ok(())
+}
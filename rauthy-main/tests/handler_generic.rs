@@ -1,7 +1,11 @@
-use crate::common::{get_backend_url, get_issuer};
-use pretty_assertions::assert_eq;
+use crate::common::{get_auth_headers, get_backend_url, get_issuer, USERNAME};
+use pretty_assertions::{assert_eq, assert_ne};
 use rauthy_models::entity::well_known::WellKnown;
+use rauthy_models::request::{BreakGlassRequest, LabSeedRequest};
+use rauthy_models::response::{BreakGlassResponse, ComplianceReport};
+use reqwest::header;
 use std::error::Error;
+use std::time::Duration;
 
 mod common;
 
@@ -25,3 +29,165 @@ async fn test_get_well_known() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_response_compression() -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+
+    let url = format!("{}/.well-known/openid-configuration", get_backend_url());
+    let res = client
+        .get(&url)
+        .header("accept-encoding", "gzip")
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers()
+            .get("content-encoding")
+            .and_then(|v| v.to_str().ok()),
+        Some("gzip")
+    );
+
+    // small and non-JSON responses must never be compressed
+    let url = format!("{}/ping", get_backend_url());
+    let res = client
+        .get(&url)
+        .header("accept-encoding", "gzip")
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    assert_ne!(
+        res.headers()
+            .get("content-encoding")
+            .and_then(|v| v.to_str().ok()),
+        Some("gzip")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compliance_report() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+
+    let url = format!("{}/compliance_report", get_backend_url());
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let report = res.json::<ComplianceReport>().await?;
+    assert_eq!(report.issuer, get_issuer());
+    assert!(report.admins.iter().any(|a| a.email == USERNAME));
+    assert_eq!(report.signing_keys.len(), 4);
+
+    let res = client
+        .get(format!("{}?format=html", url))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    assert!(res
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .starts_with("text/html"));
+    let body = res.text().await?;
+    assert!(body.contains("Compliance Report"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lab_mode_disabled() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let client = reqwest::Client::new();
+
+    // the lab endpoints must be invisible without lab mode, even for an admin
+    let url = format!("{}/lab/seed", get_backend_url());
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .header("x-debug", "some-guessed-secret-value-for-the-lab-mode")
+        .json(&LabSeedRequest {
+            users: 1,
+            clients: 1,
+        })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
+    let res = client.delete(&url).headers(auth_headers).send().await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_break_glass() -> Result<(), Box<dyn Error>> {
+    let backend_url = get_backend_url();
+    let url = format!("{}/break_glass", backend_url);
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    // matches the `BREAK_GLASS_HASH` from the test config
+    let secret = "IntegrationTestsBreakGlassSecret1337";
+    // only a single attempt per second is allowed
+    let wait = || tokio::time::sleep(Duration::from_millis(1100));
+
+    // wrong secret
+    let req = BreakGlassRequest {
+        email: USERNAME.to_string(),
+        secret: "ThisIsNotTheBreakGlassSecret1337".to_string(),
+    };
+    let res = client.post(&url).json(&req).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // correct secret for a user, which does not exist
+    wait().await;
+    let req = BreakGlassRequest {
+        email: "break_glass_404@localhost.de".to_string(),
+        secret: secret.to_string(),
+    };
+    let res = client.post(&url).json(&req).send().await?;
+    assert_eq!(res.status(), 401);
+
+    // success
+    wait().await;
+    let req = BreakGlassRequest {
+        email: USERNAME.to_string(),
+        secret: secret.to_string(),
+    };
+    let res = client.post(&url).json(&req).send().await?;
+    assert_eq!(res.status(), 200);
+    let resp = res.json::<BreakGlassResponse>().await?;
+    assert!(resp.exp > chrono::Utc::now().timestamp());
+    let code = resp.url.rsplit('/').next().unwrap();
+    let url_code = format!("{}/break_glass/{}", backend_url, code);
+
+    // the code creates an admin session and redirects to the admin UI
+    let res = client.get(&url_code).send().await?;
+    assert_eq!(res.status(), 302);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        "/auth/v1/admin"
+    );
+    assert!(res.headers().get(header::SET_COOKIE).is_some());
+
+    // the code can only be used once
+    let res = client.get(&url_code).send().await?;
+    assert_eq!(res.status(), 404);
+
+    // the credential has been burned
+    wait().await;
+    let res = client.post(&url).json(&req).send().await?;
+    assert_eq!(res.status(), 403);
+
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use crate::common::{get_auth_headers, get_backend_url};
 use pretty_assertions::assert_eq;
 use rauthy_models::entity::groups::Group;
-use rauthy_models::request::NewGroupRequest;
+use rauthy_models::request::{GroupLoginPolicyRequest, NewGroupRequest};
+use rauthy_models::response::GroupLoginPolicyResponse;
 use std::error::Error;
 
 mod common;
@@ -24,6 +25,7 @@ async fn test_groups() -> Result<(), Box<dyn Error>> {
     // add a group
     let new_group = NewGroupRequest {
         group: "group123".to_string(),
+        domains: None,
     };
     let res = reqwest::Client::new()
         .post(&url)
@@ -38,6 +40,7 @@ async fn test_groups() -> Result<(), Box<dyn Error>> {
     // modify the group
     let upd_group = NewGroupRequest {
         group: "group456".to_string(),
+        domains: None,
     };
     let url_name = format!("{}/{}", url, group.id);
     let res = reqwest::Client::new()
@@ -50,6 +53,63 @@ async fn test_groups() -> Result<(), Box<dyn Error>> {
     let upd_grp = res.json::<Group>().await?;
     assert_eq!(upd_group.group, upd_grp.name);
 
+    // add a login policy
+    let url_policy = format!("{}/{}/login_policy", url, upd_grp.id);
+    let policy = GroupLoginPolicyRequest {
+        weekdays: vec!["mon".to_string(), "fri".to_string()],
+        time_from: "07:00".to_string(),
+        time_to: "19:00".to_string(),
+        utc_offset: None,
+        timezone: Some("Europe/Berlin".to_string()),
+    };
+    let res = reqwest::Client::new()
+        .put(&url_policy)
+        .headers(auth_headers.clone())
+        .json(&policy)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let res = reqwest::Client::new()
+        .get(&url_policy)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let resp = res.json::<GroupLoginPolicyResponse>().await?;
+    assert_eq!(resp.weekdays, policy.weekdays);
+    assert_eq!(resp.time_from, policy.time_from);
+    assert_eq!(resp.time_to, policy.time_to);
+    assert_eq!(resp.timezone, policy.timezone);
+
+    // invalid times must be rejected
+    let policy = GroupLoginPolicyRequest {
+        time_to: "24:00".to_string(),
+        ..policy
+    };
+    let res = reqwest::Client::new()
+        .put(&url_policy)
+        .headers(auth_headers.clone())
+        .json(&policy)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    // delete the login policy
+    let res = reqwest::Client::new()
+        .delete(&url_policy)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let res = reqwest::Client::new()
+        .get(&url_policy)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
     // delete the group
     let url_del = format!("{}/{}", url, upd_grp.id);
     let res = reqwest::Client::new()
@@ -0,0 +1,52 @@
+use crate::common::{check_status, get_auth_headers, get_backend_url};
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::jobs::JobStatus;
+use rauthy_models::response::JobResponse;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_jobs() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    // must be rejected without a valid session
+    let url = format!("{}/jobs", backend_url);
+    let res = client.get(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let _jobs = check_status(res, 200)
+        .await?
+        .json::<Vec<JobResponse>>()
+        .await?;
+
+    let url_dead = format!("{}/jobs?status=dead", backend_url);
+    let res = client
+        .get(&url_dead)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    let jobs = check_status(res, 200)
+        .await?
+        .json::<Vec<JobResponse>>()
+        .await?;
+    assert!(jobs.iter().all(|j| j.status == JobStatus::Dead));
+
+    // retrying a non-existing job
+    let url_retry = format!("{}/jobs/notExisting123/retry", backend_url);
+    let res = client
+        .post(&url_retry)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::common::{get_auth_headers, get_backend_url, session_headers, PASSWORD, USERNAME};
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::login_traces::{LoginTrace, LoginTraceOutcome, LoginTraceRecord};
+use rauthy_models::request::LoginTraceRequest;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_login_traces() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    let url = format!("{}/login_traces", backend_url);
+
+    // a trace without any filter must be rejected
+    let payload = LoginTraceRequest {
+        email: None,
+        ip: None,
+        minutes: 5,
+    };
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    let payload = LoginTraceRequest {
+        email: Some(USERNAME.to_string()),
+        ip: None,
+        minutes: 5,
+    };
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let trace = res.json::<LoginTrace>().await?;
+    assert_eq!(trace.email.as_deref(), Some(USERNAME));
+
+    // do a new login which should be traced
+    let _ = session_headers().await;
+
+    let url_id = format!("{}/{}", url, trace.id);
+    let res = client
+        .get(&url_id)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let body = res.text().await?;
+    assert!(!body.contains(PASSWORD));
+    let records = serde_json::from_str::<Vec<LoginTraceRecord>>(&body)?;
+    assert!(records
+        .iter()
+        .any(|r| r.step == "password_validation" && r.outcome == LoginTraceOutcome::Ok));
+
+    // delete the trace
+    let res = client
+        .delete(&url_id)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .get(&url_id)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
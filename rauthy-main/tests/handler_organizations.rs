@@ -0,0 +1,124 @@
+use crate::common::{get_auth_headers, get_backend_url};
+use pretty_assertions::assert_eq;
+use rauthy_models::request::{OrganizationMemberRequest, OrganizationRequest};
+use rauthy_models::response::{OrganizationMemberResponse, OrganizationResponse};
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_organizations() -> Result<(), Box<dyn Error>> {
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+
+    let url = format!("{}/organizations", backend_url);
+    let res = reqwest::Client::new()
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let orgs = res.json::<Vec<OrganizationResponse>>().await?;
+    assert!(orgs.is_empty());
+
+    // add an organization
+    let res = reqwest::Client::new()
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&OrganizationRequest {
+            name: "Wayne Enterprises".to_string(),
+            domains: Some(vec!["wayne.io".to_string()]),
+        })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let org = res.json::<OrganizationResponse>().await?;
+    assert_eq!(org.name, "Wayne Enterprises");
+    assert_eq!(org.domains, vec!["wayne.io".to_string()]);
+
+    // the name must be unique
+    let res = reqwest::Client::new()
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&OrganizationRequest {
+            name: "Wayne Enterprises".to_string(),
+            domains: None,
+        })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 400);
+
+    // modify the organization
+    let url_org = format!("{}/{}", url, org.id);
+    let res = reqwest::Client::new()
+        .put(&url_org)
+        .headers(auth_headers.clone())
+        .json(&OrganizationRequest {
+            name: "Wayne Industries".to_string(),
+            domains: None,
+        })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let org = res.json::<OrganizationResponse>().await?;
+    assert_eq!(org.name, "Wayne Industries");
+    assert!(org.domains.is_empty());
+
+    // add the admin as an owner
+    let url_member = format!("{}/members/za9UxpH7XVxqrtpEbThoqvn2", url_org);
+    let res = reqwest::Client::new()
+        .put(&url_member)
+        .headers(auth_headers.clone())
+        .json(&OrganizationMemberRequest { is_owner: true })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let member = res.json::<OrganizationMemberResponse>().await?;
+    assert!(member.is_owner);
+
+    // unknown users cannot be added
+    let res = reqwest::Client::new()
+        .put(format!("{}/members/doesNotExist", url_org))
+        .headers(auth_headers.clone())
+        .json(&OrganizationMemberRequest { is_owner: false })
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/members", url_org))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let members = res.json::<Vec<OrganizationMemberResponse>>().await?;
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].user_id, "za9UxpH7XVxqrtpEbThoqvn2");
+
+    // remove the member
+    let res = reqwest::Client::new()
+        .delete(&url_member)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    // delete the organization
+    let res = reqwest::Client::new()
+        .delete(&url_org)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let res = reqwest::Client::new()
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let orgs = res.json::<Vec<OrganizationResponse>>().await?;
+    assert!(orgs.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use crate::common::{get_auth_headers, get_backend_url};
+use pretty_assertions::assert_eq;
+use rauthy_models::entity::api_keys::{AccessGroup, AccessRights, ApiKeyAccess};
+use rauthy_models::request::{ApiKeyRequest, ProvisioningAction, ProvisioningRequest};
+use rauthy_models::response::UserResponse;
+use reqwest::header::AUTHORIZATION;
+use reqwest::StatusCode;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_provisioning_webhook() -> Result<(), Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    let auth_headers = get_auth_headers().await?;
+    let url = format!("{}/users/provisioning", get_backend_url());
+
+    let mut payload = ProvisioningRequest {
+        action: ProvisioningAction::Create,
+        email: "hr.provisioned@localhost.de".to_string(),
+        new_email: None,
+        given_name: Some("Hr".to_string()),
+        family_name: Some("Provisioned".to_string()),
+        language: None,
+        roles: None,
+        groups: None,
+        user_expires: None,
+    };
+
+    // an admin session is not enough, the webhook is API Key only
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // create an API Key which may only update users
+    let url_keys = format!("{}/api_keys", get_backend_url());
+    let mut key_payload = ApiKeyRequest {
+        name: "provisioning".to_string(),
+        exp: None,
+        access: vec![ApiKeyAccess {
+            group: AccessGroup::Users,
+            access_rights: vec![AccessRights::Update],
+        }],
+    };
+    let res = client
+        .post(&url_keys)
+        .headers(auth_headers.clone())
+        .json(&key_payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let key_header = format!("API-Key {}", res.text().await?);
+
+    // creating users needs the create access right
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    key_payload.access = vec![ApiKeyAccess {
+        group: AccessGroup::Users,
+        access_rights: vec![AccessRights::Create, AccessRights::Update],
+    }];
+    let res = client
+        .put(format!("{}/{}", url_keys, key_payload.name))
+        .headers(auth_headers.clone())
+        .json(&key_payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // names are mandatory for create
+    payload.given_name = None;
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    payload.given_name = Some("Hr".to_string());
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let user = res.json::<UserResponse>().await?;
+    assert_eq!(user.email, payload.email);
+    assert!(user.enabled);
+
+    // update only the family name
+    payload.action = ProvisioningAction::Update;
+    payload.given_name = None;
+    payload.family_name = Some("Updated".to_string());
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let user = res.json::<UserResponse>().await?;
+    assert_eq!(user.given_name, "Hr");
+    assert_eq!(user.family_name, "Updated");
+
+    // deactivate
+    payload.action = ProvisioningAction::Deactivate;
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let user = res.json::<UserResponse>().await?;
+    assert!(!user.enabled);
+
+    // unknown users cannot be updated
+    payload.action = ProvisioningAction::Update;
+    payload.email = "hr.unknown@localhost.de".to_string();
+    let res = client
+        .post(&url)
+        .header(AUTHORIZATION, &key_header)
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // cleanup
+    let res = client
+        .delete(format!("{}/users/{}", get_backend_url(), user.id))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = client
+        .delete(format!("{}/{}", url_keys, key_payload.name))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use crate::common::{get_auth_headers, get_backend_url};
 use pretty_assertions::assert_eq;
 use rauthy_models::entity::scopes::Scope;
-use rauthy_models::request::ScopeRequest;
+use rauthy_models::language::Language;
+use rauthy_models::request::{ScopeI18nRequest, ScopeRequest};
+use rauthy_models::response::ScopeResponse;
 use std::error::Error;
 
 mod common;
@@ -20,13 +22,15 @@ async fn test_scopes() -> Result<(), Box<dyn Error>> {
     assert_eq!(res.status(), 200);
 
     let scopes = res.json::<Vec<Scope>>().await?;
-    assert_eq!(scopes.len(), 6);
+    assert_eq!(scopes.len(), 7);
 
     // add a scope
     let new_scope = ScopeRequest {
         scope: "scope123".to_string(),
         attr_include_access: None,
         attr_include_id: None,
+        i18n: None,
+        client_ids: None,
     };
     let res = reqwest::Client::new()
         .post(&url)
@@ -44,6 +48,8 @@ async fn test_scopes() -> Result<(), Box<dyn Error>> {
         scope: "scope456".to_string(),
         attr_include_access: None,
         attr_include_id: None,
+        i18n: None,
+        client_ids: None,
     };
     let url_name = format!("{}/{}", url, scope.id);
     let res = reqwest::Client::new()
@@ -57,6 +63,34 @@ async fn test_scopes() -> Result<(), Box<dyn Error>> {
     let upd_scp = res.json::<Scope>().await?;
     assert_eq!(upd_scope.scope, upd_scp.name);
 
+    // add translations and restrict it to a single client
+    let upd_scope = ScopeRequest {
+        scope: "scope456".to_string(),
+        attr_include_access: None,
+        attr_include_id: None,
+        i18n: Some(vec![ScopeI18nRequest {
+            lang: Language::En,
+            display_name: "Scope Four".to_string(),
+            description: Some("Read access to your data".to_string()),
+        }]),
+        client_ids: Some(vec!["init_client".to_string()]),
+    };
+    let res = reqwest::Client::new()
+        .put(&url_name)
+        .headers(auth_headers.clone())
+        .json(&upd_scope)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let upd_scp_resp = res.json::<ScopeResponse>().await?;
+    assert_eq!(upd_scp_resp.i18n.len(), 1);
+    assert_eq!(upd_scp_resp.i18n[0].display_name, "Scope Four");
+    assert_eq!(
+        upd_scp_resp.client_ids,
+        Some(vec!["init_client".to_string()])
+    );
+
     // delete the scope
     let url_del = format!("{}/{}", url, upd_scp.id);
     let res = reqwest::Client::new()
@@ -75,7 +109,7 @@ async fn test_scopes() -> Result<(), Box<dyn Error>> {
     assert_eq!(res.status(), 200);
 
     let scopes = res.json::<Vec<Scope>>().await?;
-    assert_eq!(scopes.len(), 6);
+    assert_eq!(scopes.len(), 7);
 
     Ok(())
 }
@@ -0,0 +1,71 @@
+use crate::common::{get_backend_url, session_headers};
+use pretty_assertions::assert_eq;
+use std::error::Error;
+
+mod common;
+
+#[tokio::test]
+async fn test_sessions_dry_run() -> Result<(), Box<dyn Error>> {
+    // a dedicated session, which is not shared with the other tests
+    let (auth_headers, _) = session_headers().await;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("{}/oidc/sessioninfo", backend_url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let info = res.json::<serde_json::Value>().await?;
+    let sid = info["id"].as_str().unwrap().to_string();
+    let uid = info["user_id"].as_str().unwrap().to_string();
+
+    // a dry run for all sessions must contain our own one
+    let res = client
+        .delete(format!("{}/sessions?dry_run=true", backend_url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let body = res.json::<serde_json::Value>().await?;
+    let sessions = body["sessions"].as_array().unwrap();
+    assert!(sessions.iter().any(|s| s["id"].as_str() == Some(&sid)));
+    assert!(body["refresh_tokens"].as_i64().is_some());
+
+    // a dry run for the user must only contain sessions of this user
+    let res = client
+        .delete(format!("{}/sessions/{}?dry_run=true", backend_url, uid))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let body = res.json::<serde_json::Value>().await?;
+    let sessions = body["sessions"].as_array().unwrap();
+    assert!(sessions.iter().any(|s| s["id"].as_str() == Some(&sid)));
+    assert!(sessions
+        .iter()
+        .all(|s| s["user_id"].as_str() == Some(uid.as_str())));
+
+    // nothing has been invalidated - our session must still be valid
+    let res = client
+        .get(format!("{}/oidc/sessioninfo", backend_url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let info = res.json::<serde_json::Value>().await?;
+    assert_eq!(info["id"].as_str(), Some(sid.as_str()));
+
+    let res = client
+        .delete(format!("{}/sessions/{}?dry_run=true", backend_url, uid))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let body = res.json::<serde_json::Value>().await?;
+    let sessions = body["sessions"].as_array().unwrap();
+    assert!(sessions.iter().any(|s| s["id"].as_str() == Some(&sid)));
+
+    Ok(())
+}
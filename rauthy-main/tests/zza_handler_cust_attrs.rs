@@ -36,6 +36,10 @@ async fn test_cust_attrs() -> Result<(), Box<dyn Error>> {
     let cust_attr = UserAttrConfigRequest {
         name: "cust1".to_string(),
         desc: Some("some description".to_string()),
+        required: None,
+        skip_client_ids: None,
+        // the values are encrypted with `PII_ENCRYPTION` and must come back in cleartext
+        sensitive: Some(true),
     };
     let res = client
         .post(&url_attrs)
@@ -53,6 +57,8 @@ async fn test_cust_attrs() -> Result<(), Box<dyn Error>> {
         scope: "cust_scope".to_string(),
         attr_include_access: None,
         attr_include_id: None,
+        i18n: None,
+        client_ids: None,
     };
     let url_scopes = format!("{}/scopes", backend_url);
     let res = client
@@ -72,6 +78,8 @@ async fn test_cust_attrs() -> Result<(), Box<dyn Error>> {
         scope: "cust_scope".to_string(),
         attr_include_access: Some(vec!["cust1".to_string()]),
         attr_include_id: Some(vec!["cust1".to_string()]),
+        i18n: None,
+        client_ids: None,
     };
     let url_scope = format!("{}/{}", url_scopes, scope.id);
     let res = client
@@ -128,6 +136,15 @@ async fn test_cust_attrs() -> Result<(), Box<dyn Error>> {
         force_mfa: c.force_mfa,
         client_uri: None,
         contacts: None,
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
     };
     let res = client
         .put(&url_client)
@@ -178,6 +195,9 @@ async fn test_cust_attrs() -> Result<(), Box<dyn Error>> {
     let cust_attr_mod = UserAttrConfigRequest {
         name: "cust2".to_string(),
         desc: Some("some description 2".to_string()),
+        required: None,
+        skip_client_ids: None,
+        sensitive: Some(true),
     };
     let url_attr_mod = format!("{}/users/attr/{}", backend_url, cust_attr.name);
     let res = client
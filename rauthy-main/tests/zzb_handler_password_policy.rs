@@ -89,6 +89,7 @@ async fn test_password_policy() -> Result<(), Box<dyn Error>> {
         roles: vec!["user".to_string()],
         groups: None,
         user_expires: None,
+        username: None,
     };
     let mut res = reqwest::Client::new()
         .post(&url)
@@ -112,6 +113,7 @@ async fn test_password_policy() -> Result<(), Box<dyn Error>> {
         email_verified: false,
         user_expires: None,
         user_values: None,
+        username: None,
     };
     let user_url = format!("{}/{}", url, user.id);
     let mut res = reqwest::Client::new()
@@ -1,9 +1,11 @@
 use crate::common::{get_auth_headers, get_backend_url, CLIENT_SECRET};
 use pretty_assertions::{assert_eq, assert_ne};
+use rauthy_models::entity::client_user_agents::ClientUserAgent;
 use rauthy_models::entity::jwk::JwkKeyPairAlg;
 use rauthy_models::request::{NewClientRequest, UpdateClientRequest};
 use rauthy_models::response::{ClientResponse, ClientSecretResponse};
 use std::error::Error;
+use std::time::Duration;
 
 mod common;
 
@@ -119,6 +121,15 @@ async fn test_clients() -> Result<(), Box<dyn Error>> {
             "batman@localhost.de".to_string(),
             "@alfred:matrix.org".to_string(),
         ]),
+        allowed_cidrs: Some(vec!["10.0.0.0/8".to_string()]),
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
     };
 
     let url_id = format!("{}/clients/{}", backend_url, client.id);
@@ -162,6 +173,8 @@ async fn test_clients() -> Result<(), Box<dyn Error>> {
     let contacts = client.contacts.expect("contacts to exist");
     assert!(contacts.contains(&"batman@localhost.de".to_string()));
     assert!(contacts.contains(&"@alfred:matrix.org".to_string()));
+    // source networks are only restricted for confidential clients
+    assert_eq!(client.allowed_cidrs, None);
 
     // delete the client again
     let res = reqwest::Client::new()
@@ -191,6 +204,21 @@ async fn test_client_secret() -> Result<(), Box<dyn Error>> {
     let backend_url = get_backend_url();
     let client = reqwest::Client::new();
 
+    // every read of the secret is counted in the client details
+    let url_client = format!("{}/clients/init_client", backend_url);
+    let res = client
+        .get(&url_client)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let reads_before = res
+        .json::<ClientResponse>()
+        .await?
+        .secret_reads
+        .map(|r| r.reads)
+        .unwrap_or_default();
+
     // get the current client secret
     let url = format!("{}/clients/init_client/secret", backend_url);
     let res = client
@@ -238,5 +266,68 @@ async fn test_client_secret() -> Result<(), Box<dyn Error>> {
     assert!(resp.secret.is_some());
     assert_eq!(resp.secret.unwrap(), new_secret);
 
+    // 3 reads, while generating a new secret does not count
+    let res = client
+        .get(&url_client)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+    let reads = res.json::<ClientResponse>().await?.secret_reads.unwrap();
+    assert_eq!(reads.reads, reads_before + 3);
+    // the id of the init_admin, which owns the session
+    assert_eq!(reads.last_actor, "m4PJ3TnyP32LA8hzY23deme3");
+    assert!(reads.last_ip.is_some());
+    assert!(reads.last_read > 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_user_agents() -> Result<(), Box<dyn Error>> {
+    // the session for the auth headers has been created with a token request for `rauthy`
+    let auth_headers = get_auth_headers().await?;
+    let backend_url = get_backend_url();
+    let client = reqwest::Client::new();
+
+    // the user agents are recorded in the background
+    let mut agents = Vec::new();
+    for _ in 0..10 {
+        let res = client
+            .get(format!("{}/clients/rauthy/user_agents", backend_url))
+            .headers(auth_headers.clone())
+            .send()
+            .await?;
+        assert_eq!(res.status(), 200);
+        agents = res.json::<Vec<ClientUserAgent>>().await?;
+        if !agents.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(!agents.is_empty());
+    assert!(agents
+        .iter()
+        .all(|a| a.client_id == "rauthy" && a.requests > 0));
+    // the test requests do not send a user agent
+    assert!(agents.iter().any(|a| a.user_agent == "UNKNOWN"));
+
+    let res = client
+        .get(format!("{}/clients_user_agents", backend_url))
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .get(format!(
+            "{}/clients/i_do_not_exist/user_agents",
+            backend_url
+        ))
+        .headers(auth_headers)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 404);
+
     Ok(())
 }
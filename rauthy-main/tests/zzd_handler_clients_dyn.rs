@@ -22,8 +22,31 @@ async fn test_dynamic_client() -> Result<(), Box<dyn Error>> {
         id_token_signed_response_alg: None,
         token_endpoint_auth_method: Some("none".to_string()),
         token_endpoint_auth_signing_alg: None,
+        jwks_uri: None,
+        require_signed_request_object: None,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
         post_logout_redirect_uri: None,
     };
+
+    // invalid metadata must be rejected with an RFC7591 error response
+    let mut invalid = payload.clone();
+    invalid.redirect_uris = vec!["http://localhost:8080/<script>".to_string()];
+    let res = client.post(&url).json(&invalid).send().await?;
+    assert_eq!(res.status(), 400);
+    let err = res.json::<serde_json::Value>().await?;
+    assert_eq!(err["error"], "invalid_redirect_uri");
+
+    invalid.redirect_uris = payload.redirect_uris.clone();
+    invalid.grant_types = vec!["implicit".to_string()];
+    let res = client.post(&url).json(&invalid).send().await?;
+    assert_eq!(res.status(), 400);
+    let err = res.json::<serde_json::Value>().await?;
+    assert_eq!(err["error"], "invalid_client_metadata");
+
     let res = client.post(&url).json(&payload).send().await?;
     assert_eq!(res.status(), 201);
     let resp = res.json::<DynamicClientResponse>().await?;
@@ -72,6 +95,7 @@ async fn test_dynamic_client() -> Result<(), Box<dyn Error>> {
     // We should get back the exact same response as from the registration, except for the
     // registration token and url, which should only be included when it has been changed.
     assert_eq!(resp.client_id, resp_get.client_id);
+    assert_eq!(resp.client_id_issued_at, resp_get.client_id_issued_at);
     assert_eq!(resp.client_name, resp_get.client_name);
     assert_eq!(resp.client_secret, resp_get.client_secret);
     assert_eq!(resp.client_secret, resp_get.client_secret);
@@ -166,5 +190,27 @@ async fn test_dynamic_client() -> Result<(), Box<dyn Error>> {
     assert_ne!(resp.registration_access_token, token_old);
     assert_ne!(resp.client_secret, secret_old);
 
+    // the client can delete itself with its current registration token
+    let token = format!(
+        "Bearer {}",
+        resp.registration_access_token.as_ref().unwrap()
+    );
+    let res = client.delete(&url).send().await?;
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .delete(&url)
+        .header(AUTHORIZATION, &token)
+        .send()
+        .await?;
+    assert_eq!(res.status(), 204);
+
+    let res = client
+        .get(&url)
+        .header(AUTHORIZATION, &token)
+        .send()
+        .await?;
+    assert_ne!(res.status(), 200);
+
     Ok(())
 }
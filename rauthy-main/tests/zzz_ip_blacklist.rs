@@ -42,7 +42,36 @@ async fn test_ip_blacklist() -> Result<(), Box<dyn Error>> {
 
     // blacklist for 2 seconds
     let exp = Utc::now().add(chrono::Duration::seconds(2)).timestamp();
-    let payload = IpBlacklistRequest { ip, exp };
+    let payload = IpBlacklistRequest {
+        ip: ip.to_string(),
+        exp,
+    };
+
+    // a dry run must not blacklist anything
+    let res = client
+        .post(format!("{}?dry_run=true", url))
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = res.json::<serde_json::Value>().await?;
+    assert_eq!(body["ip"].as_str(), Some(ip.to_string().as_str()));
+    assert!(body.get("blacklisted_until").is_none());
+
+    // we still have access and the IP has not been added to the blacklist
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let blacklist = res.json::<serde_json::Value>().await?;
+    let ips = blacklist["ips"].as_array().unwrap();
+    assert!(!ips
+        .iter()
+        .any(|b| b["ip"].as_str() == Some(&ip.to_string())));
+
     let res = client
         .post(&url)
         .headers(auth_headers.clone())
@@ -76,5 +105,44 @@ async fn test_ip_blacklist() -> Result<(), Box<dyn Error>> {
         .await?;
     assert_eq!(res.status(), StatusCode::OK);
 
+    // IPv6 addresses are normalized and blacklisted with their whole /64
+    let payload = IpBlacklistRequest {
+        ip: "2001:0db8:0000:0001:aaaa::1".to_string(),
+        exp: Utc::now().add(chrono::Duration::seconds(60)).timestamp(),
+    };
+    let res = client
+        .post(format!("{}?dry_run=true", url))
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = res.json::<serde_json::Value>().await?;
+    assert_eq!(body["ip"].as_str(), Some("2001:db8:0:1::/64"));
+
+    let res = client
+        .get(&url)
+        .headers(auth_headers.clone())
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let blacklist = res.json::<serde_json::Value>().await?;
+    let ips = blacklist["ips"].as_array().unwrap();
+    assert!(!ips
+        .iter()
+        .any(|b| b["ip"].as_str() == Some("2001:db8:0:1::/64")));
+
+    let payload = IpBlacklistRequest {
+        ip: "not an ip".to_string(),
+        exp: payload.exp,
+    };
+    let res = client
+        .post(&url)
+        .headers(auth_headers.clone())
+        .json(&payload)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
     Ok(())
 }
@@ -9,7 +9,10 @@ use crate::migration::db_migrate_dev::migrate_dev_data;
 use crate::ListenScheme;
 use anyhow::Context;
 use argon2::Params;
-use rauthy_common::constants::{DATABASE_URL, DB_TYPE, DEV_MODE, HA_MODE, PROXY_MODE};
+use rauthy_common::constants::{
+    DATABASE_URL, DB_TYPE, DEV_MODE, HA_MODE, PROXY_MODE, READ_ONLY_MODE, STARTUP_DB_RETRIES,
+};
+use rauthy_common::utils::retry_with_backoff;
 use rauthy_common::DbType;
 use sqlx::pool::PoolOptions;
 use sqlx::ConnectOptions;
@@ -208,11 +211,16 @@ impl AppState {
             }
 
             info!("Trying to connect to Postgres instance");
-            let pool = Self::connect_postgres(&DATABASE_URL, db_max_conn).await?;
+            let pool = retry_with_backoff("Postgres", Some(*STARTUP_DB_RETRIES), || {
+                Self::connect_postgres(&DATABASE_URL, db_max_conn)
+            })
+            .await?;
             info!("Database Connection established");
 
-            debug!("Migrating data from ../migrations/postgres");
-            sqlx::migrate!("../migrations/postgres").run(&pool).await?;
+            if !*READ_ONLY_MODE {
+                debug!("Migrating data from ../migrations/postgres");
+                sqlx::migrate!("../migrations/postgres").run(&pool).await?;
+            }
 
             pool
         };
@@ -228,15 +236,20 @@ impl AppState {
                 panic!("{msg}");
             }
 
-            let pool = Self::connect_sqlite(&DATABASE_URL, db_max_conn, false).await?;
+            let pool = retry_with_backoff("SQLite", Some(*STARTUP_DB_RETRIES), || {
+                Self::connect_sqlite(&DATABASE_URL, db_max_conn, false)
+            })
+            .await?;
             if DATABASE_URL.ends_with(":memory:") {
                 info!("Using in-memory SQLite");
             } else {
                 info!("Using on-disk SQLite");
             }
 
-            debug!("Migrating data from ../migrations/sqlite");
-            sqlx::migrate!("../migrations/sqlite").run(&pool).await?;
+            if !*READ_ONLY_MODE {
+                debug!("Migrating data from ../migrations/sqlite");
+                sqlx::migrate!("../migrations/sqlite").run(&pool).await?;
+            }
 
             pool
         };
@@ -247,6 +260,12 @@ impl AppState {
             .await
             .map_err(|err| anyhow::Error::msg(err.message))?;
 
+        // a read-only instance expects an already migrated DB from a primary
+        if *READ_ONLY_MODE {
+            info!("Running in read-only mode - skipping all database migrations");
+            return Ok(pool);
+        }
+
         // migrate DB data
         if !*DEV_MODE {
             migrate_init_prod(&pool, argon2_params.clone(), issuer)
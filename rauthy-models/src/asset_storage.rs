@@ -0,0 +1,211 @@
+use chrono::Utc;
+use rauthy_common::constants::{
+    ASSET_CACHE_MAX_AGE, ASSET_S3_PUBLIC_URL, ASSET_S3_SIGNED_URL_SECS, RAUTHY_VERSION,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use reqwest::header::{CACHE_CONTROL, CONTENT_TYPE};
+use reqwest::tls;
+use rusty_s3::actions::{DeleteObject, GetObject, ListObjectsV2, PutObject};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, info};
+
+static S3: OnceLock<S3Storage> = OnceLock::new();
+
+/// Optional S3 compatible object storage for assets like client logos.
+///
+/// If it is not configured, all assets are stored inside the database.
+struct S3Storage {
+    bucket: Bucket,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+/// Initializes and tests the connection to the S3 asset storage, if configured.
+/// This will panic if anything is not configured correctly to avoid unexpected behavior at runtime.
+pub async fn init() {
+    let s3_url = match env::var("ASSET_S3_URL") {
+        Ok(url) if !url.is_empty() => url,
+        _ => {
+            info!("S3 asset storage is not configured - storing assets in the database");
+            return;
+        }
+    };
+
+    let region = env::var("ASSET_S3_REGION").expect("Found ASSET_S3_URL but no ASSET_S3_REGION\n");
+    let use_path_style = env::var("ASSET_S3_PATH_STYLE")
+        .unwrap_or_else(|_| "false".to_string())
+        .parse::<bool>()
+        .expect("Cannot parse ASSET_S3_PATH_STYLE to bool\n");
+    let bucket = env::var("ASSET_S3_BUCKET").expect("Found ASSET_S3_URL but no ASSET_S3_BUCKET\n");
+    let access_key =
+        env::var("ASSET_S3_ACCESS_KEY").expect("Found ASSET_S3_URL but no ASSET_S3_ACCESS_KEY\n");
+    let secret = env::var("ASSET_S3_ACCESS_SECRET")
+        .expect("Found ASSET_S3_URL but no ASSET_S3_ACCESS_SECRET\n");
+    let danger_accept_invalid_certs = env::var("ASSET_S3_DANGER_ACCEPT_INVALID_CERTS")
+        .unwrap_or_else(|_| "false".to_string())
+        .parse::<bool>()
+        .expect("Cannot parse ASSET_S3_DANGER_ACCEPT_INVALID_CERTS to bool\n");
+
+    let credentials = Credentials::new(access_key, secret);
+    let path_style = if use_path_style {
+        UrlStyle::Path
+    } else {
+        UrlStyle::VirtualHost
+    };
+    let bucket = Bucket::new(
+        s3_url.parse().expect("Invalid format for ASSET_S3_URL"),
+        path_style,
+        bucket,
+        region,
+    )
+    .expect("Cannot build S3 Bucket object from given asset storage configuration");
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(30))
+        .user_agent(format!("Rauthy v{} Asset Storage", RAUTHY_VERSION))
+        .min_tls_version(tls::Version::TLS_1_2)
+        .danger_accept_invalid_certs(danger_accept_invalid_certs)
+        .build()
+        .unwrap();
+
+    // test the connection to be able to panic early
+    let action = ListObjectsV2::new(&bucket, Some(&credentials)).sign(Duration::from_secs(10));
+    match client.get(action).send().await {
+        Ok(resp) => {
+            if !resp.status().is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                panic!(
+                    "\nCannot connect to the S3 asset storage - check your configuration and access rights\n\n{}\n",
+                    body
+                );
+            }
+        }
+        Err(err) => {
+            panic!("Cannot connect to the S3 asset storage: {}", err);
+        }
+    }
+    info!("S3 asset storage is configured for '{}'", bucket.name());
+
+    let _ = S3.set(S3Storage {
+        bucket,
+        credentials,
+        client,
+    });
+}
+
+/// Returns `true` if new assets should be pushed into the S3 storage.
+#[inline]
+pub fn is_s3() -> bool {
+    S3.get().is_some()
+}
+
+/// Uploads an object to the S3 storage.
+pub async fn put(key: &str, content_type: &str, data: Vec<u8>) -> Result<(), ErrorResponse> {
+    let s3 = s3()?;
+    let url = PutObject::new(&s3.bucket, Some(&s3.credentials), key).sign(Duration::from_secs(30));
+
+    debug!("Pushing asset '{}' to S3", key);
+    let res = s3
+        .client
+        .put(url)
+        .header(CONTENT_TYPE, content_type)
+        .header(CACHE_CONTROL, format!("max-age={}", *ASSET_CACHE_MAX_AGE))
+        .body(data)
+        .send()
+        .await?;
+    check_response(res, key).await
+}
+
+/// Deletes an object from the S3 storage. Does nothing if no storage is configured.
+pub async fn delete(key: &str) -> Result<(), ErrorResponse> {
+    let Some(s3) = S3.get() else {
+        return Ok(());
+    };
+    let url =
+        DeleteObject::new(&s3.bucket, Some(&s3.credentials), key).sign(Duration::from_secs(30));
+
+    debug!("Deleting asset '{}' from S3", key);
+    let res = s3.client.delete(url).send().await?;
+    check_response(res, key).await
+}
+
+/// Returns the URL an asset can be downloaded from and the `max-age` for caching the redirect.
+///
+/// With an `ASSET_S3_PUBLIC_URL`, the object is expected to be readable from there directly.
+/// Otherwise, a pre-signed URL is created. It is signed with the start of the current
+/// window, so it stays the same for all requests inside the window and can be cached.
+pub fn url(key: &str) -> Result<(String, u64), ErrorResponse> {
+    if let Some(public_url) = &*ASSET_S3_PUBLIC_URL {
+        return Ok((
+            format!("{}/{}", public_url, key),
+            *ASSET_CACHE_MAX_AGE as u64,
+        ));
+    }
+
+    let s3 = s3()?;
+    let (start, remaining) = sign_window(Utc::now().timestamp(), *ASSET_S3_SIGNED_URL_SECS);
+    let time = OffsetDateTime::from_unix_timestamp(start).map_err(|err| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            format!("Cannot build S3 signing time: {}", err),
+        )
+    })?;
+
+    // valid for 2 windows, which means at least a full window after the last possible redirect
+    let url = GetObject::new(&s3.bucket, Some(&s3.credentials), key)
+        .sign_with_time(Duration::from_secs(*ASSET_S3_SIGNED_URL_SECS * 2), &time);
+    Ok((url.to_string(), remaining.min(*ASSET_CACHE_MAX_AGE as u64)))
+}
+
+#[inline]
+fn s3() -> Result<&'static S3Storage, ErrorResponse> {
+    S3.get().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            "The S3 asset storage is not configured".to_string(),
+        )
+    })
+}
+
+async fn check_response(res: reqwest::Response, key: &str) -> Result<(), ErrorResponse> {
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        Err(ErrorResponse::new(
+            ErrorResponseType::Connection,
+            format!(
+                "S3 asset storage error for '{}': {} - {}",
+                key, status, body
+            ),
+        ))
+    }
+}
+
+/// Returns the start of the signing window `now` is in and the seconds left until its end.
+fn sign_window(now: i64, window_secs: u64) -> (i64, u64) {
+    let window = window_secs.max(1) as i64;
+    let start = now - now.rem_euclid(window);
+    (start, (start + window - now) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_sign_window() {
+        assert_eq!(sign_window(7200, 3600), (7200, 3600));
+        assert_eq!(sign_window(7201, 3600), (7200, 3599));
+        assert_eq!(sign_window(10799, 3600), (7200, 1));
+
+        // a window of 0 would never change the signing time
+        assert_eq!(sign_window(10, 0), (10, 1));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::app_state::AppState;
+use crate::entity::api_keys::ApiKeyEntity;
+use crate::entity::clients::Client;
+use crate::entity::colors::ColorEntity;
+use crate::entity::jwk::JwkKeyPairAlg;
+use crate::entity::logos::{Logo, LogoType};
+use actix_web::web;
+use rauthy_common::cache_metrics::cache_del;
+use rauthy_common::constants::{
+    CACHE_NAME_12HR, CACHE_NAME_INVALIDATIONS, CACHE_NAME_USERS, HA_MODE, IDX_GROUP_LOGIN_POLICIES,
+    IDX_JWKS, IDX_JWK_KID, IDX_JWK_LATEST, IDX_PASSWORD_RULES, IDX_USERS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use redhac::{cache_insert, AckLevel};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use time::OffsetDateTime;
+use tracing::{debug, error};
+
+/// Invalidations for cache entries, which are only updated or deleted inside the local cache of
+/// the node modifying an entity.
+///
+/// Published invalidations are replicated via the `invalidations` cache. The other nodes receive
+/// them as remote cache notifications and evict the affected entries from their local caches
+/// immediately, instead of serving them until they expire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    /// A client including its colors and logo
+    Client(String),
+    /// An API key by its name
+    ApiKey(String),
+    /// The public JWKS and the latest signing keys for all algorithms
+    Jwks,
+    /// A single JWK by its `kid`, which also invalidates the JWKS
+    Jwk(String),
+    /// The user cache entry for an E-Mail, which does not belong to the user anymore
+    UserEmail(String),
+    /// The password policy and the group login policies
+    Policy,
+}
+
+impl Invalidation {
+    /// Publishes this invalidation to all other nodes. The local cache must be updated by the
+    /// caller, which usually has the new value at hand already. A no-op without `HA_MODE`.
+    ///
+    /// This is best effort. It runs after the change has been committed, which must not be
+    /// reported as failed to the caller. Nodes missing the invalidation serve the old entries
+    /// until they expire.
+    pub async fn publish(&self, data: &web::Data<AppState>) {
+        if !*HA_MODE {
+            return;
+        }
+
+        debug!("Publishing cache invalidation {}", self);
+        if let Err(err) = cache_insert(
+            CACHE_NAME_INVALIDATIONS.to_string(),
+            self.to_string(),
+            &data.caches.ha_cache_config,
+            &OffsetDateTime::now_utc().unix_timestamp(),
+            AckLevel::Once,
+        )
+        .await
+        {
+            error!("Error publishing cache invalidation {}: {:?}", self, err);
+        }
+    }
+
+    /// Evicts all entries affected by this invalidation from the local cache.
+    pub async fn evict(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        debug!("Evicting local cache entries for invalidation {}", self);
+        for (cache_name, entry) in self.cache_entries() {
+            cache_del(cache_name.to_string(), entry, &data.caches.ha_cache_config).await?;
+        }
+        Ok(())
+    }
+
+    fn cache_entries(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Client(id) => vec![
+                (CACHE_NAME_12HR, Client::get_cache_entry(id)),
+                (CACHE_NAME_12HR, ColorEntity::cache_idx(id)),
+                (CACHE_NAME_12HR, Logo::cache_idx(&LogoType::Client, id)),
+            ],
+            Self::ApiKey(name) => vec![(CACHE_NAME_12HR, ApiKeyEntity::cache_idx(name))],
+            Self::Jwks => Self::jwks_entries(),
+            Self::Jwk(kid) => {
+                let mut entries = Self::jwks_entries();
+                entries.push((CACHE_NAME_12HR, format!("{}{}", IDX_JWK_KID, kid)));
+                entries
+            }
+            Self::UserEmail(email) => vec![(CACHE_NAME_USERS, format!("{}_{}", IDX_USERS, email))],
+            Self::Policy => vec![
+                (CACHE_NAME_12HR, IDX_PASSWORD_RULES.to_string()),
+                (CACHE_NAME_12HR, IDX_GROUP_LOGIN_POLICIES.to_string()),
+            ],
+        }
+    }
+
+    fn jwks_entries() -> Vec<(&'static str, String)> {
+        let mut entries = vec![(CACHE_NAME_12HR, IDX_JWKS.to_string())];
+        for alg in [
+            JwkKeyPairAlg::RS256,
+            JwkKeyPairAlg::RS384,
+            JwkKeyPairAlg::RS512,
+            JwkKeyPairAlg::EdDSA,
+        ] {
+            entries.push((
+                CACHE_NAME_12HR,
+                format!("{}{}", IDX_JWK_LATEST, alg.as_str()),
+            ));
+        }
+        entries
+    }
+}
+
+/// The format used as the key inside the `invalidations` cache
+impl Display for Invalidation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Client(id) => write!(f, "client:{}", id),
+            Self::ApiKey(name) => write!(f, "api_key:{}", name),
+            Self::Jwks => write!(f, "jwks"),
+            Self::Jwk(kid) => write!(f, "jwk:{}", kid),
+            Self::UserEmail(email) => write!(f, "user_email:{}", email),
+            Self::Policy => write!(f, "policy"),
+        }
+    }
+}
+
+impl FromStr for Invalidation {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let res = match s.split_once(':') {
+            None if s == "jwks" => Self::Jwks,
+            None if s == "policy" => Self::Policy,
+            Some(("client", id)) => Self::Client(id.to_string()),
+            Some(("api_key", name)) => Self::ApiKey(name.to_string()),
+            Some(("jwk", kid)) => Self::Jwk(kid.to_string()),
+            Some(("user_email", email)) => Self::UserEmail(email.to_string()),
+            _ => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Internal,
+                    format!("Unknown cache invalidation: {}", s),
+                ))
+            }
+        };
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidation_from_str() {
+        for inv in [
+            Invalidation::Client("rauthy".to_string()),
+            Invalidation::ApiKey("my_key".to_string()),
+            Invalidation::Jwks,
+            Invalidation::Jwk("kid123".to_string()),
+            // the separator may appear in the value again
+            Invalidation::UserEmail("\"a:b\"@example.com".to_string()),
+            Invalidation::Policy,
+        ] {
+            assert_eq!(Invalidation::from_str(&inv.to_string()).unwrap(), inv);
+        }
+        assert!(Invalidation::from_str("jwks:").is_err());
+        assert!(Invalidation::from_str("session:123").is_err());
+    }
+
+    #[test]
+    fn test_invalidation_cache_entries() {
+        let entries = Invalidation::Jwk("kid123".to_string()).cache_entries();
+        assert!(entries.contains(&(CACHE_NAME_12HR, IDX_JWKS.to_string())));
+        assert!(entries.contains(&(CACHE_NAME_12HR, format!("{}kid123", IDX_JWK_KID))));
+        assert_eq!(entries.len(), Invalidation::Jwks.cache_entries().len() + 1);
+    }
+}
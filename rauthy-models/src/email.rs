@@ -1,30 +1,47 @@
 use crate::app_state::AppState;
+use crate::entity::email_deliveries::EmailDelivery;
+use crate::entity::jobs::{Job, JobType};
 use crate::entity::magic_links::MagicLink;
-use crate::entity::users::User;
+use crate::entity::users::{CredentialChange, User};
+use crate::events::event::Event;
 use crate::i18n::email_change_info_new::I18nEmailChangeInfoNew;
 use crate::i18n::email_confirm_change::I18nEmailConfirmChange;
+use crate::i18n::email_mfa::I18nEmailMfa;
+use crate::i18n::email_mfa_recovery::I18nEmailMfaRecovery;
 use crate::i18n::email_password_new::I18nEmailPasswordNew;
 use crate::i18n::email_reset::I18nEmailReset;
 use crate::i18n::email_reset_info::I18nEmailResetInfo;
+use crate::i18n::email_sessions_revoked::I18nEmailSessionsRevoked;
 use crate::i18n::SsrJson;
 use actix_web::web;
 use askama_actix::Template;
 use chrono::DateTime;
-use lettre::message::{MultiPart, SinglePart};
+use futures_util::{stream, StreamExt};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
 use lettre::transport::smtp::authentication;
 use lettre::{AsyncSmtpTransport, AsyncTransport};
+use once_cell::sync::Lazy;
+use prometheus::{IntGauge, Registry};
 use rauthy_common::constants::{
-    EMAIL_SUB_PREFIX, SMTP_FROM, SMTP_PASSWORD, SMTP_URL, SMTP_USERNAME,
+    EMAIL_BACKLOG_THRESHOLD, EMAIL_BATCH_SIZE, EMAIL_DOMAIN_RATE_LIMIT, EMAIL_MFA_CODE_LIFETIME,
+    EMAIL_SUB_PREFIX, JOB_POLL_INTERVAL_SECS, JOB_WORKERS, READ_ONLY_MODE, SMTP_FROM,
+    SMTP_PASSWORD, SMTP_RELAY_COOLDOWN_SECS, SMTP_START_DEGRADED, SMTP_URL, SMTP_USERNAME,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::retry_with_backoff;
 use rauthy_notify::Notification;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
-use std::time::Duration;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::Notify;
 use tracing::{debug, error, info, warn};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EMail {
     pub address: String,
     pub subject: String,
@@ -430,13 +447,145 @@ pub async fn send_pwd_reset_info(data: &web::Data<AppState>, user: &User) {
     }
 }
 
-pub async fn sender(mut rx: Receiver<EMail>, test_mode: bool) {
+pub async fn send_sessions_revoked_info(
+    data: &web::Data<AppState>,
+    user: &User,
+    change: CredentialChange,
+) {
+    let i18n = I18nEmailSessionsRevoked::build(&user.language);
+    let text = EMailEventTxt {
+        head: i18n.subject,
+        row_1: match change {
+            CredentialChange::Password => i18n.password,
+            CredentialChange::PasskeyRemoved => i18n.passkey,
+            CredentialChange::PasskeyReset => i18n.passkey_reset,
+        },
+        row_2: i18n.hint,
+    };
+
+    let html = EMailEventHtml {
+        head: text.head,
+        row_1: text.row_1,
+        row_2: text.row_2,
+    };
+
+    let req = EMail {
+        address: user.email.to_string(),
+        subject: format!("{} - {}", *EMAIL_SUB_PREFIX, i18n.subject),
+        text: text.render().expect("Template rendering: EMailEventTxt"),
+        html: Some(html.render().expect("Template rendering: EMailEventHtml")),
+    };
+
+    let tx = &data.tx_email;
+    let res = tx.send_timeout(req, Duration::from_secs(10)).await;
+    match res {
+        Ok(_) => {}
+        Err(ref e) => {
+            error!(
+                "Error sending sessions revoked email request for user '{}': {:?}",
+                user.email, e
+            );
+        }
+    }
+}
+
+/// Sends the code for a new MFA recovery, or the notice about the waiting period with `not_before`
+/// after the recovery has been confirmed.
+pub async fn send_mfa_recovery(
+    data: &web::Data<AppState>,
+    user: &User,
+    code: &str,
+    not_before: Option<i64>,
+) {
+    let i18n = I18nEmailMfaRecovery::build(&user.language);
+    let row_1 = match not_before {
+        None => format!("{} {}", i18n.requested, code),
+        Some(ts) => format!("{} {}", i18n.waiting, email_ts_prettify(ts)),
+    };
+    let text = EMailEventTxt {
+        head: i18n.subject,
+        row_1: &row_1,
+        row_2: if not_before.is_none() {
+            i18n.requested_hint
+        } else {
+            i18n.waiting_hint
+        },
+    };
+
+    let html = EMailEventHtml {
+        head: text.head,
+        row_1: text.row_1,
+        row_2: text.row_2,
+    };
+
+    let req = EMail {
+        address: user.email.to_string(),
+        subject: format!("{} - {}", *EMAIL_SUB_PREFIX, i18n.subject),
+        text: text.render().expect("Template rendering: EMailEventTxt"),
+        html: Some(html.render().expect("Template rendering: EMailEventHtml")),
+    };
+
+    let tx = &data.tx_email;
+    let res = tx.send_timeout(req, Duration::from_secs(10)).await;
+    match res {
+        Ok(_) => {}
+        Err(ref e) => {
+            error!(
+                "Error sending MFA recovery email request for user '{}': {:?}",
+                user.email, e
+            );
+        }
+    }
+}
+
+/// Sends a one-time code for the E-Mail MFA.
+pub async fn send_email_mfa(data: &web::Data<AppState>, user: &User, otp: &str) {
+    let i18n = I18nEmailMfa::build(&user.language);
+    let row_1 = format!(
+        "{} {} - {} {}",
+        i18n.code, otp, i18n.expires, *EMAIL_MFA_CODE_LIFETIME
+    );
+    let text = EMailEventTxt {
+        head: i18n.subject,
+        row_1: &row_1,
+        row_2: i18n.hint,
+    };
+
+    let html = EMailEventHtml {
+        head: text.head,
+        row_1: text.row_1,
+        row_2: text.row_2,
+    };
+
+    let req = EMail {
+        address: user.email.to_string(),
+        subject: format!("{} - {}", *EMAIL_SUB_PREFIX, i18n.subject),
+        text: text.render().expect("Template rendering: EMailEventTxt"),
+        html: Some(html.render().expect("Template rendering: EMailEventHtml")),
+    };
+
+    let tx = &data.tx_email;
+    let res = tx.send_timeout(req, Duration::from_secs(10)).await;
+    match res {
+        Ok(_) => {}
+        Err(ref e) => {
+            error!(
+                "Error sending E-Mail MFA code for user '{}': {:?}",
+                user.email, e
+            );
+        }
+    }
+}
+
+pub async fn sender(data: web::Data<AppState>, mut rx: Receiver<EMail>, test_mode: bool) {
     debug!("E-Mail sender started");
 
     // to make the integration tests not panic, results are taken and just thrown away
     // not the nicest approach for now, but it works
-    if test_mode || SMTP_URL.is_none() {
-        if SMTP_URL.is_none() {
+    if test_mode || SMTP_URL.is_none() || *READ_ONLY_MODE {
+        if *READ_ONLY_MODE {
+            info!("Running in read-only mode - E-Mail sending and jobs are disabled");
+        } else if SMTP_URL.is_none() {
             error!("SMTP_URL is not configured, cannot send out any E-Mails!");
         }
 
@@ -454,42 +603,43 @@ pub async fn sender(mut rx: Receiver<EMail>, test_mode: bool) {
         }
     }
 
-    let mailer = {
-        let smtp_url = SMTP_URL.as_deref().unwrap();
-        let smtp_insecure = env::var("SMTP_DANGER_INSECURE")
-            .unwrap_or_else(|_| "false".to_string())
-            .parse::<bool>()
-            .expect("Cannot parse SMTP_DANGER_INSECURE to bool");
-
-        let mut retries = 0;
-        let retries_max = env::var("SMTP_CONNECT_RETRIES")
-            .unwrap_or_else(|_| "3".to_string())
-            .trim()
-            .parse::<u16>()
-            .expect("Cannot parse SMTP_CONNECT_RETRIES to u16");
-
-        let mut conn = if smtp_insecure {
-            conn_test_smtp_insecure(smtp_url).await
-        } else {
-            connect_test_smtp(smtp_url).await
-        };
-
-        while let Err(err) = conn {
-            error!("{:?}", err);
-
-            if retries >= retries_max {
-                panic!("SMTP connection retries exceeded");
-            }
-            retries += 1;
-            tokio::time::sleep(Duration::from_secs(5)).await;
+    let smtp_insecure = env::var("SMTP_DANGER_INSECURE")
+        .unwrap_or_else(|_| "false".to_string())
+        .parse::<bool>()
+        .expect("Cannot parse SMTP_DANGER_INSECURE to bool");
+    let retries_max = env::var("SMTP_CONNECT_RETRIES")
+        .unwrap_or_else(|_| "3".to_string())
+        .trim()
+        .parse::<u32>()
+        .expect("Cannot parse SMTP_CONNECT_RETRIES to u32");
+    let connect = move || SmtpRelays::connect(&SMTP_RELAY_URLS, smtp_insecure);
 
-            conn = if smtp_insecure {
-                conn_test_smtp_insecure(smtp_url).await
-            } else {
-                connect_test_smtp(smtp_url).await
-            }
+    let notify = Arc::new(Notify::new());
+    let mailer = match retry_with_backoff("SMTP", Some(retries_max), connect).await {
+        Ok(mailer) => {
+            tokio::spawn(email_jobs_worker(
+                data.clone(),
+                mailer.clone(),
+                notify.clone(),
+            ));
+            Some(mailer)
         }
-        conn.unwrap()
+        Err(_) if *SMTP_START_DEGRADED => {
+            error!(
+                "Starting in degraded mode without an SMTP connection - E-Mails will be queued \
+                until the connection can be established"
+            );
+            let data = data.clone();
+            let notify = notify.clone();
+            tokio::spawn(async move {
+                // cannot fail without a retry limit
+                let mailer = retry_with_backoff("SMTP", None, connect).await.unwrap();
+                info!("SMTP connection established - leaving degraded mode");
+                email_jobs_worker(data, mailer, notify).await;
+            });
+            None
+        }
+        Err(_) => panic!("SMTP connection retries exceeded"),
     };
 
     loop {
@@ -497,36 +647,26 @@ pub async fn sender(mut rx: Receiver<EMail>, test_mode: bool) {
         if let Some(req) = rx.recv().await {
             debug!("New E-Mail for address: {:?}", req.address);
 
-            let to = format!("{} <{}>", req.subject, req.address);
-
-            let email = if let Some(html) = req.html {
-                lettre::Message::builder()
-                    .from(
-                        SMTP_FROM
-                            .parse()
-                            .expect("SMTP_FROM could not be parsed correctly"),
-                    )
-                    .to(to.parse().unwrap())
-                    .subject(req.subject)
-                    .multipart(MultiPart::alternative_plain_html(req.text, html))
-            } else {
-                lettre::Message::builder()
-                    .from(
-                        SMTP_FROM
-                            .parse()
-                            .expect("SMTP_FROM could not be parsed correctly"),
-                    )
-                    .to(to.parse().unwrap())
-                    .subject(req.subject)
-                    .singlepart(SinglePart::plain(req.text))
-            };
-
-            match email {
-                Ok(addr) => match mailer.send(addr).await {
-                    Ok(_) => info!("E-Mail to '{}' sent successfully!", req.address),
-                    Err(e) => error!("Could not send E-Mail: {:?}", e),
-                },
-                Err(_) => error!("Error building the E-Mail to '{}'", req.address),
+            match Job::enqueue(&data, JobType::Email, &req).await {
+                Ok(_) => notify.notify_one(),
+                Err(err) => {
+                    // never drop an E-Mail only because the DB is currently not available
+                    match &mailer {
+                        Some(mailer) => {
+                            error!(
+                                "Cannot persist E-Mail job - trying to send it directly: {:?}",
+                                err
+                            );
+                            if let Err(err) = send_email(mailer, req, None).await {
+                                error!("{}", err.message);
+                            }
+                        }
+                        None => error!(
+                            "Cannot persist E-Mail job for '{}' while being degraded: {:?}",
+                            req.address, err
+                        ),
+                    }
+                }
             }
         } else {
             warn!("Received 'None' in email 'sender' - exiting");
@@ -535,9 +675,425 @@ pub async fn sender(mut rx: Receiver<EMail>, test_mode: bool) {
     }
 }
 
-async fn connect_test_smtp(
-    smtp_url: &str,
-) -> Result<AsyncSmtpTransport<lettre::Tokio1Executor>, ErrorResponse> {
+/// The SMTP relays from the `SMTP_URL` in order of their priority
+static SMTP_RELAY_URLS: Lazy<Vec<&'static str>> = Lazy::new(|| {
+    SMTP_URL
+        .as_deref()
+        .unwrap_or_default()
+        .split(' ')
+        .filter(|url| !url.is_empty())
+        .collect()
+});
+
+static METRIC_BACKLOG: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "rauthy_email_backlog",
+        "E-Mails waiting inside the job queue, excluding dead letters",
+    )
+    .expect("Cannot build rauthy_email_backlog")
+});
+
+/// Registers the E-Mail metrics with the given Prometheus registry.
+pub fn register_metrics(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_BACKLOG.clone())) {
+        error!("Cannot register E-Mail metrics: {}", err);
+    }
+}
+
+/// Counts sent E-Mails per recipient domain inside fixed one minute windows.
+#[derive(Debug, Default)]
+struct DomainRateLimiter {
+    windows: HashMap<String, (Instant, u32)>,
+}
+
+impl DomainRateLimiter {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Returns `None` if an E-Mail may be sent to the domain right now, or the seconds until
+    /// the current window ends otherwise.
+    fn check(&mut self, domain: &str, limit: u32) -> Option<i64> {
+        if limit == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        self.windows
+            .retain(|_, (start, _)| now.duration_since(*start) < Self::WINDOW);
+
+        let (start, count) = self.windows.entry(domain.to_string()).or_insert((now, 0));
+        if *count >= limit {
+            let remaining = Self::WINDOW.saturating_sub(now.duration_since(*start));
+            Some(remaining.as_secs().max(1) as i64)
+        } else {
+            *count += 1;
+            None
+        }
+    }
+}
+
+/// E-Mail jobs with their decrypted payload, grouped by the recipients domain.
+type DomainBatches = HashMap<String, Vec<(Job, Result<EMail, ErrorResponse>)>>;
+
+#[inline]
+fn email_domain(address: &str) -> String {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Executes all due E-Mail jobs in batches of up to `EMAIL_BATCH_SIZE`.
+///
+/// The jobs of a batch are grouped by the recipients domain. The groups are processed with up
+/// to `JOB_WORKERS` in parallel, while all E-Mails inside a group are sent sequentially over
+/// the same pooled SMTP connection.
+async fn email_jobs_worker(data: web::Data<AppState>, mailer: SmtpRelays, notify: Arc<Notify>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(*JOB_POLL_INTERVAL_SECS));
+    let limiter = Mutex::new(DomainRateLimiter::default());
+    let mut backlog_exceeded = false;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = notify.notified() => {},
+        }
+
+        match Job::count_backlog(&data, JobType::Email).await {
+            Ok(backlog) => {
+                METRIC_BACKLOG.set(backlog);
+
+                if backlog > *EMAIL_BACKLOG_THRESHOLD && !backlog_exceeded {
+                    warn!("E-Mail backlog exceeds the threshold: {}", backlog);
+                    backlog_exceeded = true;
+                    if let Err(err) = data
+                        .tx_events
+                        .send_async(Event::rauthy_unhealthy_email_backlog(backlog))
+                        .await
+                    {
+                        error!("Error sending E-Mail backlog event: {:?}", err);
+                    }
+                } else if backlog <= *EMAIL_BACKLOG_THRESHOLD {
+                    backlog_exceeded = false;
+                }
+            }
+            Err(err) => error!("Error counting the E-Mail backlog: {:?}", err),
+        }
+
+        let jobs = match Job::find_due(&data, JobType::Email, *EMAIL_BATCH_SIZE).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                error!("Error looking up due E-Mail jobs: {:?}", err);
+                continue;
+            }
+        };
+
+        let mut batches = DomainBatches::new();
+        for job in jobs {
+            let payload = job.payload::<EMail>();
+            let domain = payload
+                .as_ref()
+                .map(|req| email_domain(&req.address))
+                .unwrap_or_default();
+            batches.entry(domain).or_default().push((job, payload));
+        }
+
+        stream::iter(batches)
+            .for_each_concurrent(*JOB_WORKERS, |(domain, batch)| {
+                let data = &data;
+                let mailer = &mailer;
+                let limiter = &limiter;
+
+                async move {
+                    for (mut job, payload) in batch {
+                        match job.claim(data).await {
+                            Ok(true) => {}
+                            // another worker was faster
+                            Ok(false) => continue,
+                            Err(err) => {
+                                error!("Error claiming job {}: {:?}", job.id, err);
+                                continue;
+                            }
+                        }
+
+                        let postpone = limiter
+                            .lock()
+                            .expect("E-Mail rate limiter lock to never be poisoned")
+                            .check(&domain, *EMAIL_DOMAIN_RATE_LIMIT);
+                        if let Some(secs) = postpone {
+                            debug!("E-Mail rate limit reached for domain '{}'", domain);
+                            if let Err(err) = job.postpone(data, secs).await {
+                                error!("Error postponing E-Mail job: {:?}", err);
+                            }
+                            continue;
+                        }
+
+                        let res = match payload {
+                            Ok(req) => {
+                                let res = send_email(mailer, req.clone(), Some(&job.id)).await;
+                                if let Err(err) =
+                                    EmailDelivery::record(data, &job, &req, &res).await
+                                {
+                                    error!("Error recording the E-Mail delivery: {:?}", err);
+                                }
+                                res.map(|_| ())
+                            }
+                            Err(err) => Err(SendError {
+                                message: format!("Invalid E-Mail job payload: {}", err.message),
+                                permanent: true,
+                            }),
+                        };
+                        let res = match res {
+                            Ok(_) => job.complete(data).await,
+                            Err(err) => {
+                                error!("{}", err.message);
+                                job.fail(data, err.message, err.permanent).await
+                            }
+                        };
+                        if let Err(err) = res {
+                            error!("Error updating E-Mail job: {:?}", err);
+                        }
+                    }
+                }
+            })
+            .await;
+    }
+}
+
+type SmtpTransport = AsyncSmtpTransport<lettre::Tokio1Executor>;
+
+/// The error of a failed send over the SMTP relays.
+#[derive(Debug)]
+pub struct SendError {
+    pub message: String,
+    /// The E-Mail has been rejected with a permanent error (5xx), which would be the same on
+    /// every relay and with every further attempt.
+    pub permanent: bool,
+}
+
+impl From<lettre::transport::smtp::Error> for SendError {
+    fn from(err: lettre::transport::smtp::Error) -> Self {
+        Self {
+            permanent: err.is_permanent(),
+            message: format!("{:?}", err),
+        }
+    }
+}
+
+/// A single SMTP relay from the `SMTP_URL`.
+struct SmtpRelay {
+    url: &'static str,
+    /// `None` as long as the relay could not be connected to
+    transport: RwLock<Option<SmtpTransport>>,
+    /// After a failed send, the relay is skipped until this instant, as long as another one
+    /// is available.
+    cooldown_until: Mutex<Option<Instant>>,
+}
+
+impl SmtpRelay {
+    fn transport(&self) -> Option<SmtpTransport> {
+        self.transport
+            .read()
+            .expect("SMTP relay lock to never be poisoned")
+            .clone()
+    }
+
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until
+            .lock()
+            .expect("SMTP relay lock to never be poisoned")
+            .map(|until| until > now)
+            .unwrap_or(false)
+    }
+
+    fn set_cooldown(&self, failed: bool) {
+        let until = failed.then(|| Instant::now() + Duration::from_secs(*SMTP_RELAY_COOLDOWN_SECS));
+        *self
+            .cooldown_until
+            .lock()
+            .expect("SMTP relay lock to never be poisoned") = until;
+    }
+}
+
+/// All SMTP relays from the `SMTP_URL` in order of their priority.
+#[derive(Clone)]
+struct SmtpRelays {
+    relays: Arc<Vec<SmtpRelay>>,
+}
+
+impl SmtpRelays {
+    /// Connects to all relays and succeeds, if at least one of them is available. Relays, which
+    /// cannot be reached, are retried in the background.
+    async fn connect(urls: &[&'static str], insecure: bool) -> Result<Self, ErrorResponse> {
+        let mut relays = Vec::with_capacity(urls.len());
+        for url in urls {
+            relays.push(SmtpRelay {
+                url,
+                transport: RwLock::new(connect_relay(url, insecure).await.ok()),
+                cooldown_until: Mutex::new(None),
+            });
+        }
+
+        if relays.iter().all(|relay| relay.transport().is_none()) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "None of the SMTP relays is available".to_string(),
+            ));
+        }
+
+        let slf = Self {
+            relays: Arc::new(relays),
+        };
+        for (idx, relay) in slf.relays.iter().enumerate() {
+            if relay.transport().is_some() {
+                continue;
+            }
+
+            let relays = slf.relays.clone();
+            tokio::spawn(async move {
+                let relay = &relays[idx];
+                let name = format!("SMTP relay {}", relay.url);
+                // cannot fail without a retry limit
+                let transport =
+                    retry_with_backoff(&name, None, || connect_relay(relay.url, insecure))
+                        .await
+                        .unwrap();
+                *relay
+                    .transport
+                    .write()
+                    .expect("SMTP relay lock to never be poisoned") = Some(transport);
+            });
+        }
+
+        Ok(slf)
+    }
+
+    /// Sends the E-Mail over the first available relay and fails over to the next one in case
+    /// of a connection problem or a transient error. Returns the relay, which accepted the E-Mail.
+    async fn send(&self, email: lettre::Message) -> Result<&'static str, SendError> {
+        let now = Instant::now();
+        let connected = self
+            .relays
+            .iter()
+            .filter_map(|relay| relay.transport().map(|transport| (relay, transport)))
+            .collect::<Vec<_>>();
+        let mut candidates = connected
+            .iter()
+            .filter(|(relay, _)| !relay.is_cooling_down(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        // rather try a relay, which failed recently, than not sending at all
+        if candidates.is_empty() {
+            candidates = connected;
+        }
+
+        try_relays(candidates, |transport| {
+            let email = email.clone();
+            async move {
+                transport.send(email).await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+}
+
+/// Tries the relays in order until one of them accepts the E-Mail.
+///
+/// A permanent rejection is returned right away. Another relay would reject the E-Mail the same
+/// way, or could deliver it a second time, if the first one has accepted it partially.
+async fn try_relays<T, F, Fut>(
+    candidates: Vec<(&SmtpRelay, T)>,
+    send: F,
+) -> Result<&'static str, SendError>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<(), SendError>>,
+{
+    let mut last_err = SendError {
+        message: "No SMTP relay is available".to_string(),
+        permanent: false,
+    };
+    for (relay, transport) in candidates {
+        match send(transport).await {
+            Ok(_) => {
+                relay.set_cooldown(false);
+                return Ok(relay.url);
+            }
+            Err(err) if err.permanent => {
+                warn!(
+                    "E-Mail rejected permanently by SMTP relay {}: {}",
+                    relay.url, err.message
+                );
+                return Err(SendError {
+                    message: format!("E-Mail rejected by {}: {}", relay.url, err.message),
+                    permanent: true,
+                });
+            }
+            Err(err) => {
+                warn!(
+                    "Could not send E-Mail via SMTP relay {}: {}",
+                    relay.url, err.message
+                );
+                relay.set_cooldown(true);
+                last_err = SendError {
+                    message: format!("Could not send E-Mail via {}: {}", relay.url, err.message),
+                    permanent: false,
+                };
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// Sends the E-Mail and returns the relay, which accepted it. The `id` of the delivery becomes
+/// the local part of the `Message-ID`, so status updates from the relay can be matched.
+async fn send_email(
+    mailer: &SmtpRelays,
+    req: EMail,
+    id: Option<&str>,
+) -> Result<&'static str, SendError> {
+    let address = req.address.clone();
+    let email = build_email(req, id).map_err(|message| SendError {
+        message,
+        permanent: true,
+    })?;
+    let relay = mailer.send(email).await?;
+    info!("E-Mail to '{}' sent successfully via {}", address, relay);
+    Ok(relay)
+}
+
+fn build_email(req: EMail, id: Option<&str>) -> Result<lettre::Message, String> {
+    let from = SMTP_FROM
+        .parse::<Mailbox>()
+        .expect("SMTP_FROM could not be parsed correctly");
+    let to = format!("{} <{}>", req.subject, req.address);
+
+    let mut builder = lettre::Message::builder();
+    if let Some(id) = id {
+        builder = builder.message_id(Some(format!("<{}@{}>", id, from.email.domain())));
+    }
+    let builder = builder
+        .from(from)
+        .to(to.parse().unwrap())
+        .subject(req.subject);
+
+    let email = if let Some(html) = req.html {
+        builder.multipart(MultiPart::alternative_plain_html(req.text, html))
+    } else {
+        builder.singlepart(SinglePart::plain(req.text))
+    };
+    email.map_err(|_| format!("Error building the E-Mail to '{}'", req.address))
+}
+
+async fn connect_relay(url: &str, insecure: bool) -> Result<SmtpTransport, ErrorResponse> {
+    if insecure {
+        conn_test_smtp_insecure(url).await
+    } else {
+        connect_test_smtp(url).await
+    }
+}
+
+async fn connect_test_smtp(smtp_url: &str) -> Result<SmtpTransport, ErrorResponse> {
     let creds = authentication::Credentials::new(SMTP_USERNAME.clone(), SMTP_PASSWORD.clone());
 
     // always try fully wrapped TLS first
@@ -585,9 +1141,7 @@ async fn connect_test_smtp(
     Ok(conn)
 }
 
-async fn conn_test_smtp_insecure(
-    smtp_url: &str,
-) -> Result<AsyncSmtpTransport<lettre::Tokio1Executor>, ErrorResponse> {
+async fn conn_test_smtp_insecure(smtp_url: &str) -> Result<SmtpTransport, ErrorResponse> {
     let port = env::var("SMTP_DANGER_INSECURE_PORT")
         .unwrap_or_else(|_| "1025".to_string())
         .trim()
@@ -625,3 +1179,91 @@ fn email_ts_prettify(ts: i64) -> String {
     let fmt = dt.format("%d/%m/%Y %H:%M:%S");
     format!("{} UTC", fmt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_domain_rate_limit() {
+        assert_eq!(email_domain("Admin@Localhost.de"), "localhost.de");
+        assert_eq!(email_domain("invalid"), "");
+
+        let mut limiter = DomainRateLimiter::default();
+        assert_eq!(limiter.check("localhost.de", 2), None);
+        assert_eq!(limiter.check("localhost.de", 2), None);
+        let secs = limiter.check("localhost.de", 2).unwrap();
+        assert!(secs > 0 && secs <= 60);
+        // other domains are not affected
+        assert_eq!(limiter.check("example.com", 2), None);
+        // 0 disables the limit
+        assert_eq!(limiter.check("localhost.de", 0), None);
+    }
+
+    fn test_relay(url: &'static str) -> SmtpRelay {
+        SmtpRelay {
+            url,
+            transport: RwLock::new(None),
+            cooldown_until: Mutex::new(None),
+        }
+    }
+
+    fn send_err(permanent: bool) -> SendError {
+        SendError {
+            message: "rejected".to_string(),
+            permanent,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay_failover() {
+        let first = test_relay("smtp1.localhost");
+        let second = test_relay("smtp2.localhost");
+        let now = Instant::now();
+
+        // a transient error fails over to the next relay and cools the first one down
+        let tried = Mutex::new(Vec::new());
+        let res = try_relays(vec![(&first, 1), (&second, 2)], |i| {
+            tried.lock().unwrap().push(i);
+            async move {
+                if i == 1 {
+                    Err(send_err(false))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+        assert_eq!(res.unwrap(), "smtp2.localhost");
+        assert_eq!(*tried.lock().unwrap(), vec![1, 2]);
+        assert!(first.is_cooling_down(now));
+        assert!(!second.is_cooling_down(now));
+
+        // a permanent error is returned right away without trying another relay
+        first.set_cooldown(false);
+        let tried = Mutex::new(Vec::new());
+        let res = try_relays(vec![(&first, 1), (&second, 2)], |i| {
+            tried.lock().unwrap().push(i);
+            async move {
+                if i == 1 {
+                    Err(send_err(true))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+        assert!(res.unwrap_err().permanent);
+        assert_eq!(*tried.lock().unwrap(), vec![1]);
+        assert!(!first.is_cooling_down(now));
+
+        // only transient errors on all relays
+        let res = try_relays(vec![(&first, 1), (&second, 2)], |_| async {
+            Err(send_err(false))
+        })
+        .await;
+        let err = res.unwrap_err();
+        assert!(!err.permanent);
+        assert!(err.message.contains("smtp2.localhost"));
+    }
+}
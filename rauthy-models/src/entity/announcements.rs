@@ -0,0 +1,287 @@
+use crate::app_state::AppState;
+use crate::request::AnnouncementRequest;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_12HR, IDX_ANNOUNCEMENTS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::new_store_id;
+use redhac::{cache_insert, AckLevel};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl AnnouncementSeverity {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+impl From<String> for AnnouncementSeverity {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "warning" => Self::Warning,
+            "critical" => Self::Critical,
+            _ => Self::Info,
+        }
+    }
+}
+
+/// An instance-wide message, like a planned maintenance or a phishing warning, which is shown on
+/// the login and account pages between `nbf` and `exp`. If `client_ids` is set, it will only be
+/// shown during logins to these clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub id: String,
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    pub nbf: i64,
+    pub exp: Option<i64>,
+    pub client_ids: Option<String>,
+    pub created: i64,
+}
+
+// CRUD
+impl Announcement {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        req: AnnouncementRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let slf = Self {
+            id: new_store_id(),
+            message: req.message,
+            severity: req.severity,
+            nbf: req.nbf.unwrap_or(now),
+            exp: req.exp,
+            client_ids: Self::join_client_ids(req.client_ids),
+            created: now,
+        };
+        slf.validate_times()?;
+
+        let severity = slf.severity.as_str();
+        sqlx::query!(
+            r#"INSERT INTO announcements (id, message, severity, nbf, exp, client_ids, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            slf.id,
+            slf.message,
+            severity,
+            slf.nbf,
+            slf.exp,
+            slf.client_ids,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        let mut all = Self::find_all(data).await?;
+        all.push(slf.clone());
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!("DELETE FROM announcements WHERE id = $1", id)
+            .execute(&data.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Self::err_not_found());
+        }
+
+        let all = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|a| a.id != id)
+            .collect::<Vec<Self>>();
+        Self::update_cache(data, all).await?;
+
+        Ok(())
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = cache_get::<Vec<Self>>(
+            CACHE_NAME_12HR.to_string(),
+            IDX_ANNOUNCEMENTS.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?;
+        if let Some(res) = res {
+            return Ok(res);
+        }
+
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, message, severity, nbf, exp, client_ids, created
+            FROM announcements ORDER BY nbf"#
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_ANNOUNCEMENTS.to_string(),
+            &data.caches.ha_cache_config,
+            &res,
+            AckLevel::Leader,
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Returns all currently active announcements. Announcements restricted to some clients are
+    /// only included, if the `client_id` matches.
+    pub async fn find_active(
+        data: &web::Data<AppState>,
+        client_id: Option<&str>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|a| a.is_active(now) && a.is_visible_for(client_id))
+            .collect();
+        Ok(res)
+    }
+
+    pub async fn update(
+        data: &web::Data<AppState>,
+        id: String,
+        req: AnnouncementRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut all = Self::find_all(data).await?;
+        let slf = all
+            .iter_mut()
+            .find(|a| a.id == id)
+            .ok_or_else(Self::err_not_found)?;
+
+        slf.message = req.message;
+        slf.severity = req.severity;
+        slf.nbf = req.nbf.unwrap_or(slf.nbf);
+        slf.exp = req.exp;
+        slf.client_ids = Self::join_client_ids(req.client_ids);
+        slf.validate_times()?;
+
+        let severity = slf.severity.as_str();
+        sqlx::query!(
+            r#"UPDATE announcements
+            SET message = $1, severity = $2, nbf = $3, exp = $4, client_ids = $5
+            WHERE id = $6"#,
+            slf.message,
+            severity,
+            slf.nbf,
+            slf.exp,
+            slf.client_ids,
+            slf.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        let slf = slf.clone();
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+}
+
+impl Announcement {
+    pub fn is_active(&self, now: i64) -> bool {
+        self.nbf <= now && self.exp.map(|exp| exp > now).unwrap_or(true)
+    }
+
+    pub fn is_visible_for(&self, client_id: Option<&str>) -> bool {
+        match (&self.client_ids, client_id) {
+            (None, _) => true,
+            (Some(ids), Some(client_id)) => ids.split(',').any(|id| id == client_id),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn join_client_ids(client_ids: Option<Vec<String>>) -> Option<String> {
+        client_ids
+            .map(|ids| ids.join(","))
+            .filter(|ids| !ids.is_empty())
+    }
+
+    async fn update_cache(data: &web::Data<AppState>, all: Vec<Self>) -> Result<(), ErrorResponse> {
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_ANNOUNCEMENTS.to_string(),
+            &data.caches.ha_cache_config,
+            &all,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn validate_times(&self) -> Result<(), ErrorResponse> {
+        if let Some(exp) = self.exp {
+            if exp <= self.nbf {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "'exp' must be after 'nbf'".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn err_not_found() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "Announcement not found".to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement_visibility() {
+        let mut a = Announcement {
+            id: new_store_id(),
+            message: "Planned maintenance".to_string(),
+            severity: AnnouncementSeverity::Warning,
+            nbf: 100,
+            exp: Some(200),
+            client_ids: None,
+            created: 50,
+        };
+        assert!(!a.is_active(99));
+        assert!(a.is_active(100));
+        assert!(a.is_active(199));
+        assert!(!a.is_active(200));
+        assert!(a.is_visible_for(None));
+        assert!(a.is_visible_for(Some("app")));
+
+        a.exp = None;
+        assert!(a.is_active(i64::MAX));
+
+        a.client_ids = Some("app,other".to_string());
+        assert!(a.is_visible_for(Some("app")));
+        assert!(a.is_visible_for(Some("other")));
+        assert!(!a.is_visible_for(Some("ap")));
+        assert!(!a.is_visible_for(None));
+
+        assert_eq!(
+            AnnouncementSeverity::from("unknown".to_string()),
+            AnnouncementSeverity::Info
+        );
+    }
+}
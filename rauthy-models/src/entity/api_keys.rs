@@ -1,11 +1,13 @@
 use crate::app_state::{AppState, DbPool};
+use crate::cache_invalidation::Invalidation;
 use actix_web::web;
 use chrono::Utc;
 use cryptr::{EncKeys, EncValue};
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::{API_KEY_LENGTH, CACHE_NAME_12HR};
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::get_rand;
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_put};
+use redhac::cache_put;
 use ring::digest;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, FromRow};
@@ -171,7 +173,7 @@ impl ApiKeyEntity {
 }
 
 impl ApiKeyEntity {
-    fn cache_idx(name: &str) -> String {
+    pub(crate) fn cache_idx(name: &str) -> String {
         format!("api_key_{}", name)
     }
 
@@ -183,6 +185,7 @@ impl ApiKeyEntity {
             &data.caches.ha_cache_config,
         )
         .await?;
+        Invalidation::ApiKey(name.to_string()).publish(data).await;
 
         Ok(())
     }
@@ -200,12 +203,11 @@ impl ApiKeyEntity {
         })?;
 
         let idx = Self::cache_idx(name);
-        let api_key = if let Some(key) = cache_get!(
-            ApiKey,
+        let api_key = if let Some(key) = cache_get::<ApiKey>(
             CACHE_NAME_12HR.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -242,6 +244,8 @@ impl ApiKeyEntity {
     }
 }
 
+/// The access is stored bincode serialized inside the encrypted API keys, which uses the variant
+/// index. New variants must always be appended at the end.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub enum AccessGroup {
     Blacklist,
@@ -255,6 +259,7 @@ pub enum AccessGroup {
     Scopes,
     UserAttributes,
     Users,
+    Organizations,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
@@ -314,7 +319,8 @@ impl ApiKey {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Unauthorized,
                     "API Key has expired".to_string(),
-                ));
+                )
+                .with_code(ErrorCode::ApiKeyExpired));
             }
         }
 
@@ -325,7 +331,36 @@ impl ApiKey {
             Err(ErrorResponse::new(
                 ErrorResponseType::Unauthorized,
                 "Invalid API-Key".to_string(),
-            ))
+            )
+            .with_code(ErrorCode::ApiKeyInvalid))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_group_bincode_order() {
+        // `Vec<ApiKeyAccess>` with `Users` (index 10) and `[Read]`, as created before the
+        // `Organizations` group existed
+        let mut old_bytes = Vec::new();
+        old_bytes.extend_from_slice(&1u64.to_le_bytes());
+        old_bytes.extend_from_slice(&10u32.to_le_bytes());
+        old_bytes.extend_from_slice(&1u64.to_le_bytes());
+        old_bytes.extend_from_slice(&0u32.to_le_bytes());
+
+        let access = bincode::deserialize::<Vec<ApiKeyAccess>>(&old_bytes).unwrap();
+        assert_eq!(
+            access,
+            vec![ApiKeyAccess {
+                group: AccessGroup::Users,
+                access_rights: vec![AccessRights::Read],
+            }]
+        );
+
+        let bytes = bincode::serialize(&access).unwrap();
+        assert_eq!(bytes, old_bytes);
+    }
+}
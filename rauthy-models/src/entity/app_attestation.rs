@@ -0,0 +1,985 @@
+use crate::app_state::AppState;
+use crate::request::TokenRequest;
+use actix_web::web;
+use chrono::Utc;
+use openssl::aes::{unwrap_key, AesKey};
+use openssl::bn::BigNumContext;
+use openssl::ec::PointConversionForm;
+use openssl::pkey::{PKey, PKeyRef, Public};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509};
+use rauthy_common::constants::{
+    APP_ATTEST_CLIENTS, APP_ATTEST_PRODUCTION, PLAY_INTEGRITY_CLIENTS,
+    PLAY_INTEGRITY_DECRYPTION_KEY, PLAY_INTEGRITY_REQUIRE_DEVICE, PLAY_INTEGRITY_VERIFICATION_KEY,
+};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{
+    base64_decode, base64_encode, base64_url_no_pad_decode, base64_url_no_pad_encode,
+};
+use ring::{aead, digest, signature};
+use serde::Deserialize;
+use serde_cbor_2::Value;
+use sqlx::{query, query_as, FromRow};
+use std::fmt::Display;
+use tracing::{debug, error, warn};
+use x509_parser::der_parser::der::{
+    parse_der_octetstring, parse_der_sequence_defined_g, parse_der_tagged_explicit_g,
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Apple App Attestation Root CA, valid until 2045-03-15
+const APPLE_APP_ATTEST_ROOT_CA: &str = r#"-----BEGIN CERTIFICATE-----
+MIICITCCAaegAwIBAgIQC/O+DvHN0uD7jG5yH2IXmDAKBggqhkjOPQQDAzBSMSYw
+JAYDVQQDDB1BcHBsZSBBcHAgQXR0ZXN0YXRpb24gUm9vdCBDQTETMBEGA1UECgwK
+QXBwbGUgSW5jLjETMBEGA1UECAwKQ2FsaWZvcm5pYTAeFw0yMDAzMTgxODMyNTNa
+Fw00NTAzMTUwMDAwMDBaMFIxJjAkBgNVBAMMHUFwcGxlIEFwcCBBdHRlc3RhdGlv
+biBSb290IENBMRMwEQYDVQQKDApBcHBsZSBJbmMuMRMwEQYDVQQIDApDYWxpZm9y
+bmlhMHYwEAYHKoZIzj0CAQYFK4EEACIDYgAERTHhmLW07ATaFQIEVwTtT4dyctdh
+NbJhFs/Ii2FdCgAHGbpphY3+d8qjuDngIN3WVhQUBHAoMeQ/cLiP1sOUtgjqK9au
+Yen1mMEvRq9Sk3Jm5X8U62H+xTD3FE9TgS41o0IwQDAPBgNVHRMBAf8EBTADAQH/
+MB0GA1UdDgQWBBSskRBTM72+aEH/pwyp5frq5eWKoTAOBgNVHQ8BAf8EBAMCAQYw
+CgYIKoZIzj0EAwMDaAAwZQIwQgFGnByvsiVbpTKwSga0kP0e8EeDS4+sQmTvb7vn
+53O5+FRXgeLhpJ06ysC5PrOyAjEAp5U4xDgEgllF7En3VcE3iexZZtKeYnpqtijV
+oyFraWVIyd/dganmrduC1bmTBGwD
+-----END CERTIFICATE-----"#;
+
+/// OID of the credential certificate extension, which contains the nonce of the attestation
+const APPLE_NONCE_OID: &str = "1.2.840.113635.100.8.2";
+/// The only grants, which are allowed for clients with app attestation
+const ATTESTED_GRANTS: [&str; 2] = ["authorization_code", "refresh_token"];
+const AAGUID_PRODUCTION: &[u8; 16] = b"appattest\0\0\0\0\0\0\0";
+const AAGUID_DEVELOPMENT: &[u8; 16] = b"appattestdevelop";
+
+/// A registered App Attest key of a single app installation.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct AppAttestKey {
+    pub key_id: String,
+    pub client_id: String,
+    pub app_id: String,
+    /// base64 encoded, uncompressed P-256 public key
+    pub public_key: String,
+    pub counter: i64,
+    pub created: i64,
+    pub last_used: i64,
+}
+
+// CRUD
+impl AppAttestKey {
+    async fn insert(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        query(
+            r#"INSERT INTO app_attest_keys
+            (key_id, client_id, app_id, public_key, counter, created, last_used)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(&self.key_id)
+        .bind(&self.client_id)
+        .bind(&self.app_id)
+        .bind(&self.public_key)
+        .bind(self.counter)
+        .bind(self.created)
+        .bind(self.last_used)
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn find(data: &web::Data<AppState>, key_id: &str) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as::<_, Self>("SELECT * FROM app_attest_keys WHERE key_id = $1")
+            .bind(key_id)
+            .fetch_optional(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    /// Only updates the counter, if the new one is greater than the stored one. Returns `false`
+    /// otherwise, which means that an assertion has been replayed.
+    async fn update_counter(
+        &self,
+        data: &web::Data<AppState>,
+        counter: i64,
+    ) -> Result<bool, ErrorResponse> {
+        let res = query(
+            r#"UPDATE app_attest_keys SET counter = $1, last_used = $2
+            WHERE key_id = $3 AND counter < $1"#,
+        )
+        .bind(counter)
+        .bind(Utc::now().timestamp())
+        .bind(&self.key_id)
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JoseHeader {
+    alg: String,
+    enc: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlayIntegrityPayload {
+    request_details: PlayRequestDetails,
+    app_integrity: PlayAppIntegrity,
+    #[serde(default)]
+    device_integrity: PlayDeviceIntegrity,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlayRequestDetails {
+    request_package_name: String,
+    /// Set for classic requests
+    nonce: Option<String>,
+    /// Set for standard requests
+    request_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlayAppIntegrity {
+    app_recognition_verdict: String,
+    package_name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlayDeviceIntegrity {
+    #[serde(default)]
+    device_recognition_verdict: Vec<String>,
+}
+
+/// The parsed authenticator data of an App Attest attestation or assertion.
+#[derive(Debug, PartialEq)]
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    counter: u32,
+    /// Only present for attestations
+    aaguid: Option<&'a [u8]>,
+    /// Only present for attestations
+    credential_id: Option<&'a [u8]>,
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(input: &'a [u8]) -> Option<Self> {
+        if input.len() < 37 {
+            return None;
+        }
+        let rp_id_hash = &input[..32];
+        let flags = input[32];
+        let counter = u32::from_be_bytes(input[33..37].try_into().ok()?);
+
+        // the `AT` flag is set if attested credential data is included
+        if flags & 0x40 == 0 {
+            return Some(Self {
+                rp_id_hash,
+                counter,
+                aaguid: None,
+                credential_id: None,
+            });
+        }
+
+        if input.len() < 55 {
+            return None;
+        }
+        let aaguid = &input[37..53];
+        let cred_len = u16::from_be_bytes(input[53..55].try_into().ok()?) as usize;
+        let credential_id = input.get(55..55 + cred_len)?;
+        Some(Self {
+            rp_id_hash,
+            counter,
+            aaguid: Some(aaguid),
+            credential_id: Some(credential_id),
+        })
+    }
+}
+
+/// Returns the value for a text key of a CBOR map.
+fn cbor_get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Map(map) => map.get(&Value::Text(key.to_string())),
+        _ => None,
+    }
+}
+
+fn cbor_bytes(value: &Value) -> Option<&[u8]> {
+    match value {
+        Value::Bytes(b) => Some(b),
+        _ => None,
+    }
+}
+
+/// Verifies that a token request for the `authorization_code` grant comes from a genuine build
+/// of a native first-party app, either with Apple App Attest or Google Play Integrity.
+///
+/// The SHA256 hash of the `code` is used as the client data the proof has been generated for.
+/// This makes each proof single use and binds it to the login, without the need for another
+/// challenge round trip.
+pub struct AppAttestation;
+
+impl AppAttestation {
+    /// Returns `true` if the client is mapped via `APP_ATTEST_CLIENTS` or
+    /// `PLAY_INTEGRITY_CLIENTS`.
+    pub fn is_required(client_id: &str) -> bool {
+        APP_ATTEST_CLIENTS.iter().any(|(_, id)| id == client_id)
+            || PLAY_INTEGRITY_CLIENTS.iter().any(|(_, id)| id == client_id)
+    }
+
+    /// Clients with app attestation only get tokens from an attested `authorization_code`
+    /// grant, and may refresh them afterward. Every other grant would skip the attestation.
+    pub fn validate_grant(client_id: &str, grant_type: &str) -> Result<(), ErrorResponse> {
+        if Self::is_required(client_id) && !ATTESTED_GRANTS.contains(&grant_type) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The '{}' flow is not allowed for clients with app attestation",
+                    grant_type
+                ),
+            )
+            .with_code(ErrorCode::FlowNotAllowed));
+        }
+        Ok(())
+    }
+
+    pub async fn validate(
+        data: &web::Data<AppState>,
+        client_id: &str,
+        code: &str,
+        req_data: &TokenRequest,
+    ) -> Result<(), ErrorResponse> {
+        let attestation = req_data
+            .attestation
+            .as_deref()
+            .ok_or_else(|| Self::err_invalid("'attestation' is missing"))?;
+        let client_data_hash = digest::digest(&digest::SHA256, code.as_bytes());
+
+        match req_data.attestation_type.as_deref() {
+            Some("app_attest") => {
+                let key_id = Self::key_id(req_data)?;
+                Self::validate_apple_attestation(
+                    data,
+                    client_id,
+                    key_id,
+                    attestation,
+                    client_data_hash.as_ref(),
+                )
+                .await
+            }
+            Some("app_assertion") => {
+                let key_id = Self::key_id(req_data)?;
+                Self::validate_apple_assertion(
+                    data,
+                    client_id,
+                    key_id,
+                    attestation,
+                    client_data_hash.as_ref(),
+                )
+                .await
+            }
+            Some("play_integrity") => {
+                Self::validate_play_integrity(client_id, attestation, client_data_hash.as_ref())
+            }
+            _ => Err(Self::err_invalid("'attestation_type' is missing")),
+        }
+    }
+
+    /// Validates the one-time attestation of a new App Attest key and registers it for
+    /// following assertions.
+    async fn validate_apple_attestation(
+        data: &web::Data<AppState>,
+        client_id: &str,
+        key_id: &str,
+        attestation: &str,
+        client_data_hash: &[u8],
+    ) -> Result<(), ErrorResponse> {
+        let app_ids = Self::apple_app_ids(client_id)?;
+        let (app_id, public_key) = Self::verify_apple_attestation(
+            APPLE_APP_ATTEST_ROOT_CA,
+            &app_ids,
+            key_id,
+            attestation,
+            client_data_hash,
+        )?;
+
+        // a key can only be attested once, each login after that must send an assertion
+        if AppAttestKey::find(data, key_id).await?.is_some() {
+            return Err(Self::err_invalid("The key has been attested already"));
+        }
+        let now = Utc::now().timestamp();
+        AppAttestKey {
+            key_id: key_id.to_string(),
+            client_id: client_id.to_string(),
+            app_id: app_id.to_string(),
+            public_key: base64_encode(&public_key),
+            counter: 0,
+            created: now,
+            last_used: now,
+        }
+        .insert(data)
+        .await
+    }
+
+    /// Verifies an App Attest attestation object against the given root CA and returns the
+    /// matching App ID and the uncompressed public key of the attested key.
+    fn verify_apple_attestation<'a>(
+        root_ca_pem: &str,
+        app_ids: &[&'a str],
+        key_id: &str,
+        attestation: &str,
+        client_data_hash: &[u8],
+    ) -> Result<(&'a str, Vec<u8>), ErrorResponse> {
+        let key_id_bytes = base64_decode(key_id)?;
+
+        let obj = serde_cbor_2::from_slice::<Value>(&base64_decode(attestation)?)
+            .map_err(|_| Self::err_invalid("Malformed App Attest attestation object"))?;
+        if cbor_get(&obj, "fmt") != Some(&Value::Text("apple-appattest".to_string())) {
+            return Err(Self::err_invalid("Unsupported attestation format"));
+        }
+        let auth_data = cbor_get(&obj, "authData")
+            .and_then(cbor_bytes)
+            .ok_or_else(|| Self::err_invalid("'authData' is missing"))?;
+        let x5c = match cbor_get(&obj, "attStmt").and_then(|s| cbor_get(s, "x5c")) {
+            Some(Value::Array(certs)) => certs.iter().filter_map(cbor_bytes).collect::<Vec<_>>(),
+            _ => Vec::default(),
+        };
+        if x5c.len() < 2 {
+            return Err(Self::err_invalid("'x5c' is missing"));
+        }
+
+        let cred_cert = Self::verify_apple_chain(root_ca_pem, &x5c)?;
+
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(auth_data);
+        ctx.update(client_data_hash);
+        let nonce = ctx.finish();
+        if Self::apple_cert_nonce(x5c[0]).as_deref() != Some(nonce.as_ref()) {
+            return Err(Self::err_invalid(
+                "The attestation has not been created for this 'code'",
+            ));
+        }
+
+        let public_key = cred_cert
+            .public_key()
+            .map_err(Self::err_cert)
+            .and_then(|pk| Self::ec_point(&pk))?;
+        if digest::digest(&digest::SHA256, &public_key).as_ref() != key_id_bytes.as_slice() {
+            return Err(Self::err_invalid("The 'attestation_key_id' does not match"));
+        }
+
+        let auth = AuthenticatorData::parse(auth_data)
+            .ok_or_else(|| Self::err_invalid("Malformed authenticator data"))?;
+        let app_id = app_ids
+            .iter()
+            .find(|id| digest::digest(&digest::SHA256, id.as_bytes()).as_ref() == auth.rp_id_hash)
+            .copied()
+            .ok_or_else(|| {
+                warn!("App Attest attestation for an unknown App ID");
+                Self::err_invalid("The App ID is not allowed for this client")
+            })?;
+        if auth.counter != 0 {
+            return Err(Self::err_invalid("Invalid App Attest counter"));
+        }
+        let aaguid = if *APP_ATTEST_PRODUCTION {
+            AAGUID_PRODUCTION
+        } else {
+            AAGUID_DEVELOPMENT
+        };
+        if auth.aaguid != Some(aaguid.as_slice()) {
+            return Err(Self::err_invalid("Wrong App Attest environment"));
+        }
+        if auth.credential_id != Some(key_id_bytes.as_slice()) {
+            return Err(Self::err_invalid("The credential ID does not match"));
+        }
+
+        Ok((app_id, public_key))
+    }
+
+    /// Validates an assertion generated with a previously attested App Attest key.
+    async fn validate_apple_assertion(
+        data: &web::Data<AppState>,
+        client_id: &str,
+        key_id: &str,
+        assertion: &str,
+        client_data_hash: &[u8],
+    ) -> Result<(), ErrorResponse> {
+        let app_ids = Self::apple_app_ids(client_id)?;
+        let key = AppAttestKey::find(data, key_id)
+            .await?
+            .filter(|k| k.client_id == client_id)
+            .ok_or_else(|| Self::err_invalid("Unknown 'attestation_key_id'"))?;
+        // the mapping may have been removed in the meantime
+        if !app_ids.contains(&key.app_id.as_str()) {
+            return Err(Self::err_invalid(
+                "The App ID is not allowed for this client",
+            ));
+        }
+
+        let public_key = base64_decode(&key.public_key)?;
+        let counter =
+            Self::verify_apple_assertion(&public_key, &key.app_id, assertion, client_data_hash)?;
+        if !key.update_counter(data, counter as i64).await? {
+            warn!("Replayed App Attest assertion for key {}", key.key_id);
+            return Err(Self::err_invalid("Invalid App Attest counter"));
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the signature of an App Attest assertion and returns its counter.
+    fn verify_apple_assertion(
+        public_key: &[u8],
+        app_id: &str,
+        assertion: &str,
+        client_data_hash: &[u8],
+    ) -> Result<u32, ErrorResponse> {
+        let obj = serde_cbor_2::from_slice::<Value>(&base64_decode(assertion)?)
+            .map_err(|_| Self::err_invalid("Malformed App Attest assertion"))?;
+        let (Some(sig), Some(auth_data)) = (
+            cbor_get(&obj, "signature").and_then(cbor_bytes),
+            cbor_get(&obj, "authenticatorData").and_then(cbor_bytes),
+        ) else {
+            return Err(Self::err_invalid("Malformed App Attest assertion"));
+        };
+
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(auth_data);
+        ctx.update(client_data_hash);
+        let nonce = ctx.finish();
+        signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public_key)
+            .verify(nonce.as_ref(), sig)
+            .map_err(|_| Self::err_invalid("Invalid App Attest assertion signature"))?;
+
+        let auth = AuthenticatorData::parse(auth_data)
+            .ok_or_else(|| Self::err_invalid("Malformed authenticator data"))?;
+        if digest::digest(&digest::SHA256, app_id.as_bytes()).as_ref() != auth.rp_id_hash {
+            return Err(Self::err_invalid("The App ID does not match"));
+        }
+
+        Ok(auth.counter)
+    }
+
+    /// Decrypts and verifies a Play Integrity token locally with the keys from the Play Console.
+    fn validate_play_integrity(
+        client_id: &str,
+        token: &str,
+        client_data_hash: &[u8],
+    ) -> Result<(), ErrorResponse> {
+        let packages = PLAY_INTEGRITY_CLIENTS
+            .iter()
+            .filter(|(_, id)| id == client_id)
+            .map(|(package, _)| package.as_str())
+            .collect::<Vec<_>>();
+        if packages.is_empty() {
+            return Err(Self::err_invalid(
+                "Play Integrity is not enabled for this client",
+            ));
+        }
+        let (Some(decryption_key), Some(verification_key)) = (
+            PLAY_INTEGRITY_DECRYPTION_KEY.as_deref(),
+            PLAY_INTEGRITY_VERIFICATION_KEY.as_deref(),
+        ) else {
+            error!("PLAY_INTEGRITY_DECRYPTION_KEY and PLAY_INTEGRITY_VERIFICATION_KEY must be set");
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "Play Integrity keys are not configured".to_string(),
+            ));
+        };
+
+        let jws = Self::decrypt_play_token(decryption_key, token)?;
+        let payload = Self::verify_play_token(verification_key, &jws)?;
+
+        // Android encodes the nonce with padding by default
+        let expected = base64_url_no_pad_encode(client_data_hash);
+        let details = &payload.request_details;
+        if ![&details.nonce, &details.request_hash]
+            .into_iter()
+            .flatten()
+            .any(|v| v.trim_end_matches('=') == expected)
+        {
+            return Err(Self::err_invalid(
+                "The integrity token has not been requested for this 'code'",
+            ));
+        }
+
+        if !packages.contains(&details.request_package_name.as_str()) {
+            warn!(
+                "Play Integrity token for unknown package {}",
+                details.request_package_name
+            );
+            return Err(Self::err_invalid(
+                "The package is not allowed for this client",
+            ));
+        }
+        let app = &payload.app_integrity;
+        if app.app_recognition_verdict != "PLAY_RECOGNIZED"
+            || app.package_name.as_ref() != Some(&details.request_package_name)
+        {
+            return Err(Self::err_invalid(
+                "The app is not recognized by Google Play",
+            ));
+        }
+        if *PLAY_INTEGRITY_REQUIRE_DEVICE
+            && !payload
+                .device_integrity
+                .device_recognition_verdict
+                .iter()
+                .any(|v| v == "MEETS_DEVICE_INTEGRITY" || v == "MEETS_STRONG_INTEGRITY")
+        {
+            return Err(Self::err_invalid(
+                "The device does not meet the integrity requirements",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the `A256KW` / `A256GCM` JWE and returns the inner JWS.
+    fn decrypt_play_token(key_b64: &str, token: &str) -> Result<Vec<u8>, ErrorResponse> {
+        let parts = token.split('.').collect::<Vec<_>>();
+        if parts.len() != 5 {
+            return Err(Self::err_invalid("Malformed integrity token"));
+        }
+        let header = serde_json::from_slice::<JoseHeader>(&base64_url_no_pad_decode(parts[0])?)
+            .map_err(|_| Self::err_invalid("Malformed integrity token header"))?;
+        if header.alg != "A256KW" || header.enc.as_deref() != Some("A256GCM") {
+            return Err(Self::err_invalid("Unsupported integrity token encryption"));
+        }
+
+        let kek = base64_decode(key_b64)?;
+        let wrapped = base64_url_no_pad_decode(parts[1])?;
+        if kek.len() != 32 || wrapped.len() != 40 {
+            return Err(Self::err_invalid("Invalid integrity token key"));
+        }
+        let kek = AesKey::new_decrypt(&kek)
+            .map_err(|_| Self::err_invalid("Invalid PLAY_INTEGRITY_DECRYPTION_KEY"))?;
+        let mut cek = [0u8; 32];
+        unwrap_key(&kek, None, &mut cek, &wrapped)
+            .map_err(|_| Self::err_invalid("Cannot decrypt the integrity token"))?;
+
+        let nonce = aead::Nonce::try_assume_unique_for_key(&base64_url_no_pad_decode(parts[2])?)
+            .map_err(|_| Self::err_invalid("Invalid integrity token IV"))?;
+        let mut in_out = base64_url_no_pad_decode(parts[3])?;
+        in_out.extend(base64_url_no_pad_decode(parts[4])?);
+        let key = aead::UnboundKey::new(&aead::AES_256_GCM, &cek)
+            .map(aead::LessSafeKey::new)
+            .map_err(|_| Self::err_invalid("Invalid integrity token key"))?;
+        let plain = key
+            .open_in_place(nonce, aead::Aad::from(parts[0].as_bytes()), &mut in_out)
+            .map_err(|_| Self::err_invalid("Cannot decrypt the integrity token"))?;
+
+        Ok(plain.to_vec())
+    }
+
+    /// Verifies the `ES256` signature of the inner JWS and returns its payload.
+    fn verify_play_token(key_b64: &str, jws: &[u8]) -> Result<PlayIntegrityPayload, ErrorResponse> {
+        let jws =
+            std::str::from_utf8(jws).map_err(|_| Self::err_invalid("Malformed integrity token"))?;
+        let (message, sig) = jws
+            .rsplit_once('.')
+            .ok_or_else(|| Self::err_invalid("Malformed integrity token"))?;
+        let (header, payload) = message
+            .split_once('.')
+            .ok_or_else(|| Self::err_invalid("Malformed integrity token"))?;
+
+        let header = serde_json::from_slice::<JoseHeader>(&base64_url_no_pad_decode(header)?)
+            .map_err(|_| Self::err_invalid("Malformed integrity token header"))?;
+        if header.alg != "ES256" {
+            return Err(Self::err_invalid("Unsupported integrity token signature"));
+        }
+
+        let public_key = PKey::public_key_from_der(&base64_decode(key_b64)?)
+            .map_err(|err| {
+                error!("Invalid PLAY_INTEGRITY_VERIFICATION_KEY: {}", err);
+                Self::err_invalid("Invalid PLAY_INTEGRITY_VERIFICATION_KEY")
+            })
+            .and_then(|pk| Self::ec_point(&pk))?;
+        signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key)
+            .verify(message.as_bytes(), &base64_url_no_pad_decode(sig)?)
+            .map_err(|_| Self::err_invalid("Invalid integrity token signature"))?;
+
+        serde_json::from_slice(&base64_url_no_pad_decode(payload)?).map_err(|err| {
+            debug!("Cannot deserialize the integrity token payload: {}", err);
+            Self::err_invalid("Malformed integrity token payload")
+        })
+    }
+
+    /// Verifies the `x5c` chain up to the root CA, which is always the Apple App Attestation
+    /// Root CA outside of tests, and returns the credential certificate.
+    fn verify_apple_chain(root_ca_pem: &str, x5c: &[&[u8]]) -> Result<X509, ErrorResponse> {
+        let root = X509::from_pem(root_ca_pem.as_bytes()).map_err(Self::err_cert)?;
+        let mut store = X509StoreBuilder::new().map_err(Self::err_cert)?;
+        store.add_cert(root).map_err(Self::err_cert)?;
+        let store = store.build();
+
+        let cred_cert = X509::from_der(x5c[0]).map_err(Self::err_cert)?;
+        let mut chain = Stack::new().map_err(Self::err_cert)?;
+        for der in &x5c[1..] {
+            let cert = X509::from_der(der).map_err(Self::err_cert)?;
+            chain.push(cert).map_err(Self::err_cert)?;
+        }
+
+        let mut ctx = X509StoreContext::new().map_err(Self::err_cert)?;
+        let is_valid = ctx
+            .init(&store, &cred_cert, &chain, |c| c.verify_cert())
+            .map_err(Self::err_cert)?;
+        if !is_valid {
+            return Err(Self::err_invalid(
+                "The App Attest certificate chain is not trusted",
+            ));
+        }
+
+        Ok(cred_cert)
+    }
+
+    /// Extracts the nonce from the credential certificate extension, which is a
+    /// `SEQUENCE { [1] EXPLICIT OCTET STRING }`.
+    fn apple_cert_nonce(cert_der: &[u8]) -> Option<Vec<u8>> {
+        let (_, cert) = X509Certificate::from_der(cert_der).ok()?;
+        let ext = cert
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == APPLE_NONCE_OID)?;
+
+        let (_, nonce) = parse_der_sequence_defined_g(|content, _| {
+            parse_der_tagged_explicit_g(1, |inner, _| parse_der_octetstring(inner))(content)
+        })(ext.value)
+        .ok()?;
+        nonce.as_slice().ok().map(<[u8]>::to_vec)
+    }
+
+    fn apple_app_ids(client_id: &str) -> Result<Vec<&'static str>, ErrorResponse> {
+        let app_ids = APP_ATTEST_CLIENTS
+            .iter()
+            .filter(|(_, id)| id == client_id)
+            .map(|(app_id, _)| app_id.as_str())
+            .collect::<Vec<_>>();
+        if app_ids.is_empty() {
+            return Err(Self::err_invalid(
+                "App Attest is not enabled for this client",
+            ));
+        }
+        Ok(app_ids)
+    }
+
+    /// Returns the uncompressed point of an EC public key.
+    fn ec_point(key: &PKeyRef<Public>) -> Result<Vec<u8>, ErrorResponse> {
+        let ec = key.ec_key().map_err(Self::err_cert)?;
+        let mut ctx = BigNumContext::new().map_err(Self::err_cert)?;
+        ec.public_key()
+            .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .map_err(Self::err_cert)
+    }
+
+    fn key_id(req_data: &TokenRequest) -> Result<&str, ErrorResponse> {
+        req_data
+            .attestation_key_id
+            .as_deref()
+            .ok_or_else(|| Self::err_invalid("'attestation_key_id' is missing"))
+    }
+
+    fn err_cert<E: Display>(err: E) -> ErrorResponse {
+        debug!("App attestation crypto error: {}", err);
+        Self::err_invalid("Invalid App Attest certificate or key")
+    }
+
+    fn err_invalid(msg: &str) -> ErrorResponse {
+        ErrorResponse::new(ErrorResponseType::Unauthorized, msg.to_string())
+            .with_code(ErrorCode::AttestationInvalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::sign::Signer;
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::{X509Builder, X509Extension, X509NameBuilder};
+    use std::collections::BTreeMap;
+
+    const APP_ID: &str = "ABCDE12345.de.localhost.app";
+
+    fn build_cert(
+        cn: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        nonce: Option<&[u8]>,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(cn.len() as u32).unwrap();
+        builder
+            .set_serial_number(&serial.to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder
+            .set_issuer_name(issuer.map(|(c, _)| c.subject_name()).unwrap_or(&name))
+            .unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+
+        if let Some(nonce) = nonce {
+            // SEQUENCE { [1] EXPLICIT OCTET STRING }
+            let mut value = vec![0x30, 0x24, 0xa1, 0x22, 0x04, 0x20];
+            value.extend(nonce);
+            let oid = Asn1Object::from_str(APPLE_NONCE_OID).unwrap();
+            let value = Asn1OctetString::new_from_bytes(&value).unwrap();
+            builder
+                .append_extension(X509Extension::new_from_der(&oid, false, &value).unwrap())
+                .unwrap();
+        } else {
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+        }
+
+        let signing_key = issuer.map(|(_, k)| k).unwrap_or(key);
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn ec_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn public_point(key: &PKey<Private>) -> Vec<u8> {
+        let public = PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap();
+        AppAttestation::ec_point(&public).unwrap()
+    }
+
+    fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        for part in parts {
+            ctx.update(part);
+        }
+        ctx.finish().as_ref().to_vec()
+    }
+
+    fn cbor_map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Value::Text(k.to_string()), v))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn test_app_attest_attestation_and_assertion() {
+        let root_key = ec_key();
+        let root = build_cert("Test Root CA", &root_key, None, None);
+        let root_pem = String::from_utf8(root.to_pem().unwrap()).unwrap();
+        let int_key = ec_key();
+        let intermediate = build_cert(
+            "Test Intermediate CA",
+            &int_key,
+            Some((&root, &root_key)),
+            None,
+        );
+
+        let cred_key = ec_key();
+        let public_key = public_point(&cred_key);
+        let key_id_bytes = sha256(&[&public_key]);
+        let key_id = base64_encode(&key_id_bytes);
+        let client_data_hash = sha256(&[b"some_code"]);
+
+        let mut auth_data = sha256(&[APP_ID.as_bytes()]);
+        auth_data.push(0x40);
+        auth_data.extend(0u32.to_be_bytes());
+        auth_data.extend(if *APP_ATTEST_PRODUCTION {
+            AAGUID_PRODUCTION
+        } else {
+            AAGUID_DEVELOPMENT
+        });
+        auth_data.extend((key_id_bytes.len() as u16).to_be_bytes());
+        auth_data.extend(&key_id_bytes);
+
+        let nonce = sha256(&[&auth_data, &client_data_hash]);
+        let cred_cert = build_cert(
+            "Test Credential",
+            &cred_key,
+            Some((&intermediate, &int_key)),
+            Some(&nonce),
+        );
+        assert_eq!(
+            AppAttestation::apple_cert_nonce(&cred_cert.to_der().unwrap()),
+            Some(nonce.clone())
+        );
+        assert!(AppAttestation::apple_cert_nonce(&intermediate.to_der().unwrap()).is_none());
+        assert!(AppAttestation::apple_cert_nonce(&[0x30, 0x00]).is_none());
+
+        let attestation = |fmt: &str, x5c: Vec<Vec<u8>>| {
+            let obj = cbor_map(vec![
+                ("fmt", Value::Text(fmt.to_string())),
+                (
+                    "attStmt",
+                    cbor_map(vec![
+                        (
+                            "x5c",
+                            Value::Array(x5c.into_iter().map(Value::Bytes).collect()),
+                        ),
+                        ("receipt", Value::Bytes(vec![1, 2, 3])),
+                    ]),
+                ),
+                ("authData", Value::Bytes(auth_data.clone())),
+            ]);
+            base64_encode(&serde_cbor_2::to_vec(&obj).unwrap())
+        };
+        let x5c = vec![cred_cert.to_der().unwrap(), intermediate.to_der().unwrap()];
+        let valid = attestation("apple-appattest", x5c.clone());
+
+        let (app_id, pk) = AppAttestation::verify_apple_attestation(
+            &root_pem,
+            &["other.app", APP_ID],
+            &key_id,
+            &valid,
+            &client_data_hash,
+        )
+        .unwrap();
+        assert_eq!(app_id, APP_ID);
+        assert_eq!(pk, public_key);
+
+        // the nonce is bound to the client data
+        let other_hash = sha256(&[b"other_code"]);
+        assert!(AppAttestation::verify_apple_attestation(
+            &root_pem,
+            &[APP_ID],
+            &key_id,
+            &valid,
+            &other_hash
+        )
+        .is_err());
+        // unknown App ID
+        assert!(AppAttestation::verify_apple_attestation(
+            &root_pem,
+            &["other.app"],
+            &key_id,
+            &valid,
+            &client_data_hash
+        )
+        .is_err());
+        // not issued by the trusted root
+        assert!(AppAttestation::verify_apple_attestation(
+            APPLE_APP_ATTEST_ROOT_CA,
+            &[APP_ID],
+            &key_id,
+            &valid,
+            &client_data_hash
+        )
+        .is_err());
+        // key id of another key
+        let other_key_id = base64_encode(&sha256(&[b"other"]));
+        assert!(AppAttestation::verify_apple_attestation(
+            &root_pem,
+            &[APP_ID],
+            &other_key_id,
+            &valid,
+            &client_data_hash
+        )
+        .is_err());
+        // wrong format, missing intermediate and garbage
+        for invalid in [
+            attestation("packed", x5c.clone()),
+            attestation("apple-appattest", x5c[..1].to_vec()),
+            base64_encode(b"garbage"),
+        ] {
+            assert!(AppAttestation::verify_apple_attestation(
+                &root_pem,
+                &[APP_ID],
+                &key_id,
+                &invalid,
+                &client_data_hash
+            )
+            .is_err());
+        }
+
+        // a following assertion
+        let mut assert_auth_data = sha256(&[APP_ID.as_bytes()]);
+        assert_auth_data.push(0x00);
+        assert_auth_data.extend(1u32.to_be_bytes());
+        let assert_nonce = sha256(&[&assert_auth_data, &client_data_hash]);
+        let mut signer = Signer::new(MessageDigest::sha256(), &cred_key).unwrap();
+        signer.update(&assert_nonce).unwrap();
+        let sig = signer.sign_to_vec().unwrap();
+        let assertion = base64_encode(
+            &serde_cbor_2::to_vec(&cbor_map(vec![
+                ("signature", Value::Bytes(sig)),
+                ("authenticatorData", Value::Bytes(assert_auth_data)),
+            ]))
+            .unwrap(),
+        );
+
+        let counter =
+            AppAttestation::verify_apple_assertion(&pk, APP_ID, &assertion, &client_data_hash)
+                .unwrap();
+        assert_eq!(counter, 1);
+        assert!(
+            AppAttestation::verify_apple_assertion(&pk, APP_ID, &assertion, &other_hash).is_err()
+        );
+        assert!(AppAttestation::verify_apple_assertion(
+            &pk,
+            "other.app",
+            &assertion,
+            &client_data_hash
+        )
+        .is_err());
+        let other_pk = public_point(&ec_key());
+        assert!(AppAttestation::verify_apple_assertion(
+            &other_pk,
+            APP_ID,
+            &assertion,
+            &client_data_hash
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_app_attest_authenticator_data() {
+        let mut input = vec![1u8; 32];
+        input.push(0x40);
+        input.extend(0u32.to_be_bytes());
+        input.extend(AAGUID_PRODUCTION);
+        input.extend(3u16.to_be_bytes());
+        input.extend([9, 9, 9]);
+        let auth = AuthenticatorData::parse(&input).unwrap();
+        assert_eq!(auth.rp_id_hash, [1u8; 32].as_slice());
+        assert_eq!(auth.counter, 0);
+        assert_eq!(auth.aaguid, Some(AAGUID_PRODUCTION.as_slice()));
+        assert_eq!(auth.credential_id, Some([9u8, 9, 9].as_slice()));
+        // credential ID longer than the input
+        assert!(AuthenticatorData::parse(&input[..input.len() - 1]).is_none());
+
+        // assertion without attested credential data
+        let mut input = vec![2u8; 32];
+        input.push(0x00);
+        input.extend(5u32.to_be_bytes());
+        let auth = AuthenticatorData::parse(&input).unwrap();
+        assert_eq!(auth.counter, 5);
+        assert!(auth.aaguid.is_none());
+        assert!(AuthenticatorData::parse(&input[..36]).is_none());
+    }
+
+    #[test]
+    fn test_app_attest_root_ca() {
+        let root = X509::from_pem(APPLE_APP_ATTEST_ROOT_CA.as_bytes()).unwrap();
+        let pk = root.public_key().unwrap();
+        assert!(root.verify(&pk).unwrap());
+    }
+}
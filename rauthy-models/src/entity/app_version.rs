@@ -1,9 +1,10 @@
 use crate::app_state::AppState;
 use actix_web::web;
 use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::{CACHE_NAME_12HR, IDX_APP_VERSION, RAUTHY_VERSION};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use reqwest::header::ACCEPT;
 use serde::{Deserialize, Serialize};
 use serde_json::value;
@@ -20,12 +21,11 @@ pub struct LatestAppVersion {
 
 impl LatestAppVersion {
     pub async fn find(app_state: &web::Data<AppState>) -> Option<Self> {
-        if let Ok(Some(slf)) = cache_get!(
-            Self,
+        if let Ok(Some(slf)) = cache_get::<Self>(
             CACHE_NAME_12HR.to_string(),
             IDX_APP_VERSION.to_string(),
             &app_state.caches.ha_cache_config,
-            false
+            false,
         )
         .await
         {
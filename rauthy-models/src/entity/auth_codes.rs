@@ -1,11 +1,18 @@
 use crate::app_state::AppState;
+use crate::entity::clients::Client;
+use crate::entity::jwk::{JwkKeyPair, JwkKeyPairAlg, JwkLifecycle};
+use crate::request::{ResponseMode, UserAttrValueRequest};
+use crate::sign_jwt;
 use actix_web::web;
+use jwt_simple::prelude::*;
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::CACHE_NAME_AUTH_CODES;
-use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::get_rand;
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_put};
-use serde::{Deserialize, Serialize};
+use redhac::cache_put;
+use std::fmt::Write;
 use std::ops::Add;
+use std::str::FromStr;
 use time::OffsetDateTime;
 
 // Struct for the codes from the 'authorization_code' flow
@@ -20,6 +27,47 @@ pub struct AuthCode {
     pub challenge_method: Option<String>,
     pub nonce: Option<String>,
     pub scopes: Vec<String>,
+    /// The `resource` from the authorization request (RFC 8707)
+    #[serde(default)]
+    pub resource: Option<String>,
+    /// The validated RFC 9396 `authorization_details` as a JSON array
+    #[serde(default)]
+    pub authorization_details: Option<String>,
+    /// Set after the code has been redeemed. The code is kept until it expires to revoke
+    /// everything issued for it on a second usage, which most probably means it has been stolen.
+    #[serde(default)]
+    pub used: Option<AuthCodeUsage>,
+    /// Values for missing required user attributes, which are only saved when the code is
+    /// redeemed, because the login might still need another factor until then.
+    #[serde(default)]
+    pub user_attrs: Option<Vec<UserAttrValueRequest>>,
+}
+
+/// Error responses are only needed until the client has parsed them.
+const ERROR_RESPONSE_LIFETIME: u64 = 60;
+
+/// The claims of a JWT secured authorization response (JARM)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JarmClaims {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthCodeUsage {
+    pub ip: Option<String>,
+    /// `jti` and `exp` of the issued access token. Only `None` for codes used before all access
+    /// tokens got a `jti`.
+    pub access_token: Option<(String, i64)>,
+    /// ID of the issued refresh token, if the client uses them
+    #[serde(default)]
+    pub refresh_token: Option<String>,
 }
 
 // CRUD
@@ -40,12 +88,11 @@ impl AuthCode {
         data: &web::Data<AppState>,
         id: String,
     ) -> Result<Option<Self>, ErrorResponse> {
-        cache_get!(
-            AuthCode,
+        cache_get::<AuthCode>(
             CACHE_NAME_AUTH_CODES.to_string(),
             id,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await
         .map_err(ErrorResponse::from)
@@ -74,6 +121,8 @@ impl AuthCode {
         challenge_method: Option<String>,
         nonce: Option<String>,
         scopes: Vec<String>,
+        resource: Option<String>,
+        authorization_details: Option<String>,
         lifetime_secs: i32,
     ) -> Self {
         let id = get_rand(64);
@@ -90,6 +139,163 @@ impl AuthCode {
             challenge_method,
             nonce,
             scopes,
+            resource,
+            authorization_details,
+            used: None,
+            user_attrs: None,
+        }
+    }
+
+    /// Builds the `Location` of the authorization response for the requested `response_mode`.
+    pub async fn response_location(
+        &self,
+        data: &web::Data<AppState>,
+        redirect_uri: &str,
+        state: Option<String>,
+        response_mode: ResponseMode,
+    ) -> Result<String, ErrorResponse> {
+        if response_mode == ResponseMode::Query {
+            let mut loc = format!("{}?code={}", redirect_uri, self.id);
+            if let Some(state) = state {
+                write!(loc, "&state={}", state).expect("`write!` to succeed");
+            }
+            return Ok(loc);
+        }
+
+        let client = Client::find_maybe_ephemeral(data, self.client_id.clone()).await?;
+        let lifetime = (self.exp - OffsetDateTime::now_utc().unix_timestamp()).max(1) as u64;
+        let claims = JarmClaims {
+            code: Some(self.id.clone()),
+            state,
+            error: None,
+            error_description: None,
+        };
+        JarmClaims::location(data, &client, claims, lifetime, redirect_uri, response_mode).await
+    }
+}
+
+impl JarmClaims {
+    /// Builds the `Location` of an error response for a client, which requested a JWT secured
+    /// `response_mode`. Must only be used after the `redirect_uri` has been validated.
+    pub async fn error_location(
+        data: &web::Data<AppState>,
+        client: &Client,
+        redirect_uri: &str,
+        state: Option<String>,
+        response_mode: ResponseMode,
+        error: &str,
+        error_description: Option<String>,
+    ) -> Result<String, ErrorResponse> {
+        let claims = Self {
+            code: None,
+            state,
+            error: Some(error.to_string()),
+            error_description,
+        };
+        Self::location(
+            data,
+            client,
+            claims,
+            ERROR_RESPONSE_LIFETIME,
+            redirect_uri,
+            response_mode,
+        )
+        .await
+    }
+
+    async fn location(
+        data: &web::Data<AppState>,
+        client: &Client,
+        claims: Self,
+        lifetime: u64,
+        redirect_uri: &str,
+        response_mode: ResponseMode,
+    ) -> Result<String, ErrorResponse> {
+        let response = claims.sign(data, client, lifetime).await?;
+        if response_mode != ResponseMode::FormPostJwt {
+            return Ok(format!("{}?response={}", redirect_uri, response));
         }
+
+        // The browser cannot be redirected with a POST. The response is kept here together
+        // with the validated `redirect_uri`, and our own endpoint renders the form for it once.
+        let form_post = JarmFormPost {
+            id: get_rand(48),
+            redirect_uri: redirect_uri.to_string(),
+            response,
+        };
+        form_post.save(data).await?;
+        Ok(format!(
+            "{}/oidc/authorize/form_post?id={}",
+            data.issuer, form_post.id
+        ))
+    }
+
+    /// Signs the authorization response with the `id_token_alg` of the client.
+    async fn sign(
+        self,
+        data: &web::Data<AppState>,
+        client: &Client,
+        lifetime: u64,
+    ) -> Result<String, ErrorResponse> {
+        let claims = Claims::with_custom_claims(self, coarsetime::Duration::from_secs(lifetime))
+            .with_issuer(data.issuer.clone())
+            .with_audience(client.id.clone());
+
+        let key_pair_type = JwkKeyPairAlg::from_str(&client.id_token_alg)?;
+        let kp = JwkKeyPair::find_latest(data, &client.id_token_alg, key_pair_type).await?;
+        JwkLifecycle::count_usage(&kp.kid);
+        sign_jwt!(kp, claims)
+    }
+}
+
+/// A signed authorization response for `response_mode=form_post.jwt`, which waits to be
+/// rendered as a form for the browser.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JarmFormPost {
+    pub id: String,
+    pub redirect_uri: String,
+    pub response: String,
+}
+
+impl JarmFormPost {
+    async fn save(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        cache_put(
+            CACHE_NAME_AUTH_CODES.to_string(),
+            Self::cache_idx(&self.id),
+            &data.caches.ha_cache_config,
+            self,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the form post with the given `id`, which can only be used once.
+    pub async fn take(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let idx = Self::cache_idx(id);
+        let slf = cache_get::<Self>(
+            CACHE_NAME_AUTH_CODES.to_string(),
+            idx.clone(),
+            &data.caches.ha_cache_config,
+            true,
+        )
+        .await?
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "The authorization response does not exist or has been used already".to_string(),
+            )
+        })?;
+        cache_del(
+            CACHE_NAME_AUTH_CODES.to_string(),
+            idx,
+            &data.caches.ha_cache_config,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+
+    fn cache_idx(id: &str) -> String {
+        format!("form_post_{}", id)
     }
 }
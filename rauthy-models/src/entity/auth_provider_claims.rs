@@ -0,0 +1,325 @@
+use crate::app_state::AppState;
+use crate::entity::user_attr::UserAttrConfigEntity;
+use actix_web::web;
+use rauthy_common::constants::RE_ATTR;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::base64_url_no_pad_encode;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use utoipa::ToSchema;
+use validator::Validate;
+
+const MAX_ATTR_MAPPINGS: usize = 32;
+
+/// Optional upstream claims, which Rauthy would persist for a federated user. The `email`,
+/// `given_name` and `family_name` are always needed and cannot be excluded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderClaim {
+    Address,
+    Birthdate,
+    Locale,
+    Phone,
+}
+
+/// Maps an upstream claim to a custom user attribute.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ProviderAttrMapping {
+    /// JSON path to the upstream claim, like `$.department`
+    #[validate(length(min = 2, max = 128))]
+    pub path: String,
+    /// Name of an existing custom user attribute - validation: `^[a-zA-Z0-9-_/]{2,32}$`
+    #[validate(regex(path = "RE_ATTR", code = "^[a-zA-Z0-9-_/]{2,32}$"))]
+    pub attr: String,
+    /// Transformation pipeline, applied to each value, like `lowercase | prefix:ext-`
+    #[validate(length(max = 256))]
+    pub transform: Option<String>,
+}
+
+/// Controls the attribute release for an upstream auth provider, so that only the needed
+/// personal data ends up in Rauthy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ProviderClaimMapping {
+    /// Optional standard claims which will be persisted. `None` persists all of them.
+    pub persist: Option<Vec<ProviderClaim>>,
+    /// Upstream claims, which will be persisted as custom user attributes. Like any other
+    /// custom attribute, they are only forwarded downstream to clients, if a requested scope
+    /// includes them.
+    #[validate]
+    pub attributes: Vec<ProviderAttrMapping>,
+}
+
+impl ProviderClaimMapping {
+    /// Validates all paths, transformations and custom attributes.
+    pub async fn validate_config(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        if self.attributes.len() > MAX_ATTR_MAPPINGS {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("max {} attribute mappings are allowed", MAX_ATTR_MAPPINGS),
+            ));
+        }
+
+        for mapping in &self.attributes {
+            JsonPath::parse(&mapping.path).map_err(|err| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("invalid JSON path '{}': {}", mapping.path, err),
+                )
+            })?;
+            if let Some(transform) = &mapping.transform {
+                ClaimTransform::parse(transform)?;
+            }
+            if UserAttrConfigEntity::find(data, mapping.attr.clone())
+                .await
+                .is_err()
+            {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("custom user attribute '{}' does not exist", mapping.attr),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the given standard claim may be persisted.
+    #[inline]
+    pub fn persists(&self, claim: ProviderClaim) -> bool {
+        self.persist
+            .as_ref()
+            .map(|claims| claims.contains(&claim))
+            .unwrap_or(true)
+    }
+
+    /// Extracts and transforms all mapped attributes from the upstream claims. Missing claims
+    /// will end up as `Value::Null`, which removes an existing attribute value for the user.
+    pub fn map_attributes(&self, claims: &Value) -> Result<Vec<(String, Value)>, ErrorResponse> {
+        let mut res = Vec::with_capacity(self.attributes.len());
+
+        for mapping in &self.attributes {
+            let path = JsonPath::parse(&mapping.path).map_err(|err| {
+                ErrorResponse::new(
+                    ErrorResponseType::Internal,
+                    format!("invalid JSON path '{}': {}", mapping.path, err),
+                )
+            })?;
+            let transform = match &mapping.transform {
+                Some(t) => ClaimTransform::parse(t)?,
+                None => ClaimTransform::default(),
+            };
+
+            let mut values = path
+                .query(claims)
+                .all()
+                .into_iter()
+                .filter_map(|v| transform.apply(v))
+                .collect::<Vec<_>>();
+            let value = match values.len() {
+                0 => Value::Null,
+                1 => values.remove(0),
+                _ => Value::Array(values),
+            };
+            res.push((mapping.attr.clone(), value));
+        }
+
+        Ok(res)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TransformOp {
+    Lowercase,
+    Uppercase,
+    Trim,
+    Prefix(String),
+    Suffix(String),
+    Replace(String, String),
+    Split(String, usize),
+    Truncate(usize),
+    Hash,
+}
+
+/// A pipeline of transformations, separated by `|`, which is applied to upstream claim values.
+///
+/// Available operations:
+/// - `lowercase`, `uppercase`, `trim`
+/// - `prefix:<value>`, `suffix:<value>`
+/// - `replace:<from>:<to>`
+/// - `split:<separator>:<index>` keeps only the part at the given index
+/// - `truncate:<length>`
+/// - `hash` replaces the value with its SHA256 hash for pseudonymization
+///
+/// Arrays are transformed element-wise, numbers and bools are converted to strings first.
+#[derive(Debug, Default)]
+pub struct ClaimTransform {
+    ops: Vec<TransformOp>,
+}
+
+impl ClaimTransform {
+    pub fn parse(expr: &str) -> Result<Self, ErrorResponse> {
+        let mut ops = Vec::new();
+
+        for op in expr.split('|').map(str::trim).filter(|op| !op.is_empty()) {
+            let (name, args) = op.split_once(':').unwrap_or((op, ""));
+            let op = match name {
+                "lowercase" => TransformOp::Lowercase,
+                "uppercase" => TransformOp::Uppercase,
+                "trim" => TransformOp::Trim,
+                "prefix" => TransformOp::Prefix(args.to_string()),
+                "suffix" => TransformOp::Suffix(args.to_string()),
+                "replace" => {
+                    let (from, to) = args
+                        .split_once(':')
+                        .ok_or_else(|| Self::err(op, "expected 'replace:<from>:<to>'"))?;
+                    if from.is_empty() {
+                        return Err(Self::err(op, "'from' must not be empty"));
+                    }
+                    TransformOp::Replace(from.to_string(), to.to_string())
+                }
+                "split" => {
+                    let (sep, idx) = args
+                        .rsplit_once(':')
+                        .ok_or_else(|| Self::err(op, "expected 'split:<separator>:<index>'"))?;
+                    if sep.is_empty() {
+                        return Err(Self::err(op, "the separator must not be empty"));
+                    }
+                    let idx = idx
+                        .parse::<usize>()
+                        .map_err(|_| Self::err(op, "the index must be a positive number"))?;
+                    TransformOp::Split(sep.to_string(), idx)
+                }
+                "truncate" => {
+                    let len = args
+                        .parse::<usize>()
+                        .map_err(|_| Self::err(op, "the length must be a positive number"))?;
+                    TransformOp::Truncate(len)
+                }
+                "hash" => TransformOp::Hash,
+                _ => return Err(Self::err(op, "unknown operation")),
+            };
+            ops.push(op);
+        }
+
+        Ok(Self { ops })
+    }
+
+    /// Applies the pipeline. Returns `None` if the value should be dropped, which is the case
+    /// for objects, `null`, and a `split` without a part at the index.
+    pub fn apply(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::Null | Value::Object(_) => None,
+            Value::Array(arr) => {
+                let values = arr.iter().filter_map(|v| self.apply(v)).collect::<Vec<_>>();
+                Some(Value::Array(values))
+            }
+            Value::String(s) if self.ops.is_empty() => Some(Value::String(s.clone())),
+            Value::Bool(_) | Value::Number(_) if self.ops.is_empty() => Some(value.clone()),
+            Value::String(s) => self.apply_str(s.clone()).map(Value::String),
+            _ => self.apply_str(value.to_string()).map(Value::String),
+        }
+    }
+
+    fn apply_str(&self, mut s: String) -> Option<String> {
+        for op in &self.ops {
+            s = match op {
+                TransformOp::Lowercase => s.to_lowercase(),
+                TransformOp::Uppercase => s.to_uppercase(),
+                TransformOp::Trim => s.trim().to_string(),
+                TransformOp::Prefix(p) => format!("{}{}", p, s),
+                TransformOp::Suffix(suf) => format!("{}{}", s, suf),
+                TransformOp::Replace(from, to) => s.replace(from, to),
+                TransformOp::Split(sep, idx) => s.split(sep.as_str()).nth(*idx)?.to_string(),
+                TransformOp::Truncate(len) => s.chars().take(*len).collect(),
+                TransformOp::Hash => {
+                    let hash = digest::digest(&digest::SHA256, s.as_bytes());
+                    base64_url_no_pad_encode(hash.as_ref())
+                }
+            };
+        }
+        Some(s)
+    }
+
+    fn err(op: &str, msg: &str) -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("invalid transformation '{}': {}", op, msg),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_claim_transform() {
+        let t = ClaimTransform::parse(" trim | lowercase | prefix:ext- ").unwrap();
+        assert_eq!(t.apply(&json!("  Sales ")), Some(json!("ext-sales")));
+        assert_eq!(t.apply(&json!(["A", "B"])), Some(json!(["ext-a", "ext-b"])));
+        assert_eq!(t.apply(&json!({"a": 1})), None);
+        assert_eq!(t.apply(&json!(23)), Some(json!("ext-23")));
+
+        // birthdate -> year only
+        let t = ClaimTransform::parse("split:-:0").unwrap();
+        assert_eq!(t.apply(&json!("1990-07-13")), Some(json!("1990")));
+        let t = ClaimTransform::parse("split:-:5").unwrap();
+        assert_eq!(t.apply(&json!("1990-07-13")), None);
+
+        let t = ClaimTransform::parse("replace:@:_at_|truncate:6").unwrap();
+        assert_eq!(t.apply(&json!("a@b.de")), Some(json!("a_at_b")));
+
+        let t = ClaimTransform::parse("hash").unwrap();
+        let hashed = t.apply(&json!("12345")).unwrap();
+        assert_ne!(hashed, json!("12345"));
+        assert_eq!(t.apply(&json!("12345")), Some(hashed));
+
+        // no transformation keeps the original type
+        let t = ClaimTransform::parse("").unwrap();
+        assert_eq!(t.apply(&json!(true)), Some(json!(true)));
+
+        assert!(ClaimTransform::parse("eval:rm").is_err());
+        assert!(ClaimTransform::parse("split:-").is_err());
+        assert!(ClaimTransform::parse("replace::x").is_err());
+        assert!(ClaimTransform::parse("truncate:-1").is_err());
+    }
+
+    #[test]
+    fn test_claim_mapping() {
+        let mapping = ProviderClaimMapping {
+            persist: Some(vec![ProviderClaim::Locale]),
+            attributes: vec![
+                ProviderAttrMapping {
+                    path: "$.department".to_string(),
+                    attr: "department".to_string(),
+                    transform: Some("uppercase".to_string()),
+                },
+                ProviderAttrMapping {
+                    path: "$.groups.*".to_string(),
+                    attr: "groups".to_string(),
+                    transform: None,
+                },
+                ProviderAttrMapping {
+                    path: "$.missing".to_string(),
+                    attr: "missing".to_string(),
+                    transform: None,
+                },
+            ],
+        };
+        assert!(mapping.persists(ProviderClaim::Locale));
+        assert!(!mapping.persists(ProviderClaim::Phone));
+        assert!(ProviderClaimMapping::default().persists(ProviderClaim::Phone));
+
+        let claims = json!({
+            "department": "sales",
+            "groups": ["a", "b"],
+        });
+        let attrs = mapping.map_attributes(&claims).unwrap();
+        assert_eq!(attrs[0], ("department".to_string(), json!("SALES")));
+        assert_eq!(attrs[1], ("groups".to_string(), json!(["a", "b"])));
+        assert_eq!(attrs[2], ("missing".to_string(), Value::Null));
+    }
+}
@@ -1,17 +1,26 @@
 use crate::app_state::AppState;
 use crate::entity::auth_codes::AuthCode;
+use crate::entity::auth_provider_claims::{ProviderClaim, ProviderClaimMapping};
 use crate::entity::clients::Client;
+use crate::entity::email_mfa::EmailMfaCode;
+use crate::entity::push_mfa::PushMfaRequest;
+use crate::entity::request_objects::{RequestObject, RequestObjectLogin};
+use crate::entity::resource_servers::ResourceServer;
 use crate::entity::sessions::Session;
+use crate::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
 use crate::entity::users::User;
 use crate::entity::users_values::UserValues;
 use crate::entity::webauthn::WebauthnLoginReq;
 use crate::language::Language;
+use crate::pii;
 use crate::request::{
     ProviderCallbackRequest, ProviderLoginRequest, ProviderLookupRequest, ProviderRequest,
-    UserValuesRequest,
+    ResponseMode, UserAttrValueRequest, UserAttrValuesUpdateRequest, UserValuesRequest,
 };
 use crate::response::{ProviderLinkedUserResponse, ProviderLookupResponse};
-use crate::{AuthStep, AuthStepAwaitWebauthn, AuthStepLoggedIn};
+use crate::{
+    AuthStep, AuthStepAwaitProfile, AuthStepAwaitWebauthn, AuthStepLoggedIn, AuthorizationDetail,
+};
 use actix_web::cookie::{Cookie, SameSite};
 use actix_web::http::header;
 use actix_web::http::header::HeaderValue;
@@ -20,6 +29,7 @@ use cryptr::utils::secure_random_alnum;
 use cryptr::EncValue;
 use image::EncodableLayout;
 use itertools::Itertools;
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::{
     APPLICATION_JSON, CACHE_NAME_12HR, CACHE_NAME_AUTH_PROVIDER_CALLBACK, COOKIE_UPSTREAM_CALLBACK,
     IDX_AUTH_PROVIDER, IDX_AUTH_PROVIDER_TEMPLATE, PROVIDER_CALLBACK_URI,
@@ -29,9 +39,9 @@ use rauthy_common::constants::{
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::{
     base64_decode, base64_encode, base64_url_decode, base64_url_encode, base64_url_no_pad_decode,
-    get_rand, new_store_id,
+    get_rand, new_store_id, real_ip_from_req,
 };
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use reqwest::header::{ACCEPT, AUTHORIZATION};
 use reqwest::tls;
 use ring::digest;
@@ -180,6 +190,8 @@ pub struct AuthProvider {
     pub use_pkce: bool,
 
     pub root_pem: Option<String>,
+    /// `ProviderClaimMapping` as JSON
+    pub claim_mapping: Option<String>,
 }
 
 impl AuthProvider {
@@ -187,6 +199,9 @@ impl AuthProvider {
         data: &web::Data<AppState>,
         payload: ProviderRequest,
     ) -> Result<Self, ErrorResponse> {
+        if let Some(mapping) = &payload.claim_mapping {
+            mapping.validate_config(data).await?;
+        }
         let slf = Self::try_from_id_req(new_store_id(), payload)?;
         let typ = slf.typ.as_str();
 
@@ -194,9 +209,10 @@ impl AuthProvider {
             r#"INSERT INTO
             auth_providers (id, name, enabled, typ, issuer, authorization_endpoint, token_endpoint,
             userinfo_endpoint, client_id, secret, scope, admin_claim_path, admin_claim_value,
-            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem)
+            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem,
+            claim_mapping)
             VALUES
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)"#,
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
             slf.id,
             slf.name,
             slf.enabled,
@@ -215,6 +231,7 @@ impl AuthProvider {
             slf.allow_insecure_requests,
             slf.use_pkce,
             slf.root_pem,
+            slf.claim_mapping,
         )
         .execute(&data.db)
         .await?;
@@ -233,12 +250,11 @@ impl AuthProvider {
     }
 
     pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
-        if let Some(res) = cache_get!(
-            Self,
+        if let Some(res) = cache_get::<Self>(
             CACHE_NAME_12HR.to_string(),
             Self::cache_idx(id),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -262,12 +278,11 @@ impl AuthProvider {
     }
 
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
-        if let Some(res) = cache_get!(
-            Vec<Self>,
+        if let Some(res) = cache_get::<Vec<Self>>(
             CACHE_NAME_12HR.to_string(),
             Self::cache_idx("all"),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -303,7 +318,13 @@ impl AuthProvider {
         .fetch_all(&data.db)
         .await?;
 
-        Ok(users)
+        users
+            .into_iter()
+            .map(|mut user| {
+                user.email = pii::decrypt_email(user.email)?;
+                Ok(user)
+            })
+            .collect()
     }
 
     pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
@@ -327,6 +348,9 @@ impl AuthProvider {
         id: String,
         payload: ProviderRequest,
     ) -> Result<(), ErrorResponse> {
+        if let Some(mapping) = &payload.claim_mapping {
+            mapping.validate_config(data).await?;
+        }
         Self::try_from_id_req(id, payload)?.save(data).await
     }
 
@@ -337,8 +361,9 @@ impl AuthProvider {
             SET name = $1, enabled = $2, issuer = $3, typ = $4, authorization_endpoint = $5,
             token_endpoint = $6, userinfo_endpoint = $7, client_id = $8, secret = $9, scope = $10,
             admin_claim_path = $11, admin_claim_value = $12, mfa_claim_path = $13,
-            mfa_claim_value = $14, allow_insecure_requests = $15, use_pkce = $16, root_pem = $17
-            WHERE id = $18"#,
+            mfa_claim_value = $14, allow_insecure_requests = $15, use_pkce = $16, root_pem = $17,
+            claim_mapping = $18
+            WHERE id = $19"#,
             self.name,
             self.enabled,
             self.issuer,
@@ -356,6 +381,7 @@ impl AuthProvider {
             self.allow_insecure_requests,
             self.use_pkce,
             self.root_pem,
+            self.claim_mapping,
             self.id,
         )
         .execute(&data.db)
@@ -425,6 +451,11 @@ impl AuthProvider {
     fn try_from_id_req(id: String, req: ProviderRequest) -> Result<Self, ErrorResponse> {
         let scope = Self::cleanup_scope(&req.scope);
         let secret = Self::secret_encrypted(&req.client_secret)?;
+        let claim_mapping = req
+            .claim_mapping
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
 
         Ok(Self {
             id,
@@ -448,6 +479,25 @@ impl AuthProvider {
             allow_insecure_requests: req.danger_allow_insecure.unwrap_or(false),
             use_pkce: req.use_pkce,
             root_pem: req.root_pem,
+            claim_mapping,
+        })
+    }
+
+    /// The configured attribute release, or the default which persists all standard claims.
+    pub fn get_claim_mapping(&self) -> ProviderClaimMapping {
+        let Some(json) = &self.claim_mapping else {
+            return ProviderClaimMapping::default();
+        };
+        serde_json::from_str(json).unwrap_or_else(|err| {
+            error!(
+                "Invalid claim mapping for auth provider {}: {}",
+                self.id, err
+            );
+            // never persist more than needed with a broken config
+            ProviderClaimMapping {
+                persist: Some(Vec::default()),
+                attributes: Vec::default(),
+            }
         })
     }
 
@@ -592,11 +642,26 @@ pub struct AuthProviderCallback {
     pub req_nonce: Option<String>,
     pub req_code_challenge: Option<String>,
     pub req_code_challenge_method: Option<String>,
+    #[serde(default)]
+    pub req_response_mode: ResponseMode,
+    #[serde(default)]
+    pub req_resource: Option<String>,
+    #[serde(default)]
+    pub req_authorization_details: Option<String>,
+    #[serde(default)]
+    pub req_request: Option<String>,
 
     pub provider_id: String,
 
     // TODO add a nonce upstream as well? -> improvement?
     pub pkce_challenge: String,
+
+    /// Set after the upstream login, while the user still has to provide values for required
+    /// attributes. The upstream code cannot be redeemed a second time.
+    #[serde(default)]
+    pub pending_user_id: Option<String>,
+    #[serde(default)]
+    pub pending_mfa: bool,
 }
 
 // CRUD
@@ -615,12 +680,11 @@ impl AuthProviderCallback {
     }
 
     async fn find(data: &web::Data<AppState>, callback_id: String) -> Result<Self, ErrorResponse> {
-        match cache_get!(
-            Self,
+        match cache_get::<Self>(
             CACHE_NAME_AUTH_PROVIDER_CALLBACK.to_string(),
             callback_id,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await?
         {
@@ -653,7 +717,10 @@ impl AuthProviderCallback {
         payload: ProviderLoginRequest,
     ) -> Result<(Cookie, String, HeaderValue), ErrorResponse> {
         let provider = AuthProvider::find(data, &payload.provider_id).await?;
+        let request_object = RequestObjectLogin::from(&payload);
         let client = Client::find(data, payload.client_id).await?;
+        RequestObject::verify_login(data, &client, request_object).await?;
+        AuthorizationDetail::from_param(&client, payload.authorization_details.as_deref())?;
 
         let slf = Self {
             callback_id: secure_random_alnum(32),
@@ -667,10 +734,17 @@ impl AuthProviderCallback {
             req_nonce: payload.nonce,
             req_code_challenge: payload.code_challenge,
             req_code_challenge_method: payload.code_challenge_method,
+            req_response_mode: payload.response_mode.unwrap_or_default(),
+            req_resource: payload.resource,
+            req_authorization_details: payload.authorization_details,
+            req_request: payload.request,
 
             provider_id: provider.id,
 
             pkce_challenge: payload.pkce_challenge,
+
+            pending_user_id: None,
+            pending_mfa: false,
         };
 
         let mut location = format!(
@@ -744,7 +818,7 @@ impl AuthProviderCallback {
         debug!("callback state is valid");
 
         // validate csrf token
-        let slf = Self::find(data, callback_id).await?;
+        let mut slf = Self::find(data, callback_id).await?;
         if slf.xsrf_token != payload.xsrf_token {
             Self::delete(data, slf.callback_id).await?;
 
@@ -770,8 +844,192 @@ impl AuthProviderCallback {
         }
         debug!("callback pkce verifier is valid");
 
+        // The upstream code can only be redeemed once. If the user had to provide required
+        // attributes after the upstream login, it continues with the user found before.
+        let user_attrs = payload.user_attrs.clone();
+        let (user, provider_mfa_login, link_cookie) = match slf.pending_user_id.clone() {
+            Some(user_id) => {
+                let mfa = if slf.pending_mfa {
+                    ProviderMfaLogin::Yes
+                } else {
+                    ProviderMfaLogin::No
+                };
+                (User::find(data, user_id).await?, mfa, None)
+            }
+            None => slf.fetch_upstream_user(data, req, payload).await?,
+        };
+
+        user.check_enabled()?;
+        user.check_expired()?;
+
+        if link_cookie.is_some() {
+            // If this is the case, we don't need to validate any further client values.
+            // We will not generate a new auth code at all -> this is just a request to federate
+            // an existing account. The federation has been done in the step above already.
+            return Ok((
+                AuthStep::ProviderLink,
+                AuthProviderLinkCookie::deletion_cookie(),
+            ));
+        }
+        user.check_login_policy(data, real_ip_from_req(req)).await?;
+
+        // validate client values
+        let client = Client::find_maybe_ephemeral(data, slf.req_client_id.clone()).await?;
+        let force_mfa = client.force_mfa();
+        if force_mfa {
+            if provider_mfa_login == ProviderMfaLogin::No && !user.has_mfa_enabled() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::MfaRequired,
+                    "MFA is required for this client".to_string(),
+                ));
+            }
+            session.set_mfa(data, true).await?;
+        }
+        client.validate_redirect_uri(&slf.req_redirect_uri)?;
+        client.validate_code_challenge(&slf.req_code_challenge, &slf.req_code_challenge_method)?;
+        let header_origin = client.validate_origin(req, &data.listen_scheme, &data.public_url)?;
+
+        // required user attributes must exist before any code will be issued
+        let (missing_attrs, user_attrs) =
+            UserAttrConfigEntity::collect_missing_required(data, &user.id, &client.id, user_attrs)
+                .await?;
+        if !missing_attrs.is_empty() {
+            slf.pending_user_id = Some(user.id);
+            slf.pending_mfa = provider_mfa_login == ProviderMfaLogin::Yes;
+            slf.save(data).await?;
+
+            // the callback cookie is needed again for submitting the values
+            let cookie =
+                cookie::Cookie::build(COOKIE_UPSTREAM_CALLBACK, cookie.value().to_string())
+                    .secure(true)
+                    .http_only(true)
+                    .same_site(cookie::SameSite::Lax)
+                    .max_age(cookie::time::Duration::seconds(
+                        UPSTREAM_AUTH_CALLBACK_TIMEOUT_SECS as i64,
+                    ))
+                    .path("/auth")
+                    .finish();
+            return Ok((
+                AuthStep::AwaitProfile(AuthStepAwaitProfile {
+                    has_password_been_hashed: false,
+                    header_csrf: Session::get_csrf_header(&session.csrf_token),
+                    header_origin,
+                    missing_attrs,
+                }),
+                cookie,
+            ));
+        }
+        Self::delete(data, slf.callback_id.clone()).await?;
+
+        // ######################################
+        // all good, we can generate an auth code
+
+        // authorization code
+        let code_lifetime = if force_mfa {
+            client.auth_code_lifetime + user.mfa_code_lifetime()
+        } else {
+            client.auth_code_lifetime
+        };
+        let scopes = client.sanitize_login_scopes(&slf.req_scopes)?;
+        ResourceServer::validate_login(data, slf.req_resource.as_deref(), &client.id, &scopes)
+            .await?;
+        AuthorizationDetail::from_param(&client, slf.req_authorization_details.as_deref())?;
+        RequestObject::consume(data, slf.req_request.as_deref()).await?;
+        let mut code = AuthCode::new(
+            user.id.clone(),
+            client.id,
+            Some(session.id.clone()),
+            slf.req_code_challenge,
+            slf.req_code_challenge_method,
+            slf.req_nonce,
+            scopes,
+            slf.req_resource,
+            slf.req_authorization_details,
+            code_lifetime,
+        );
+        code.user_attrs = user_attrs;
+        code.save(data).await?;
+
+        // location header
+        let loc = code
+            .response_location(
+                data,
+                &slf.req_redirect_uri,
+                slf.req_state,
+                slf.req_response_mode,
+            )
+            .await?;
+
+        let auth_step = if PushMfaRequest::is_available(data, &user).await? {
+            PushMfaRequest::await_login(
+                data,
+                &user,
+                &session,
+                loc,
+                header_origin,
+                false,
+                real_ip_from_req(req),
+            )
+            .await?
+        } else if user.has_webauthn_enabled() {
+            let step = AuthStepAwaitWebauthn {
+                has_password_been_hashed: false,
+                code: get_rand(48),
+                header_csrf: Session::get_csrf_header(&session.csrf_token),
+                header_origin,
+                user_id: user.id.clone(),
+                email: user.email,
+                exp: *WEBAUTHN_REQ_EXP,
+                session,
+            };
+
+            WebauthnLoginReq {
+                code: step.code.clone(),
+                user_id: user.id,
+                header_loc: loc,
+                header_origin: step
+                    .header_origin
+                    .as_ref()
+                    .map(|h| h.1.to_str().unwrap().to_string()),
+            }
+            .save(data)
+            .await?;
+
+            AuthStep::AwaitWebauthn(step)
+        } else if user.has_email_mfa_enabled() {
+            EmailMfaCode::await_login(data, &user, &session, loc, header_origin, false).await?
+        } else {
+            AuthStep::LoggedIn(AuthStepLoggedIn {
+                has_password_been_hashed: false,
+                email: user.email,
+                header_loc: (header::LOCATION, HeaderValue::from_str(&loc).unwrap()),
+                header_csrf: Session::get_csrf_header(&session.csrf_token),
+                header_origin,
+            })
+        };
+
+        // callback data deletion cookie
+        let cookie = cookie::Cookie::build(COOKIE_UPSTREAM_CALLBACK, "")
+            .secure(true)
+            .http_only(true)
+            .same_site(cookie::SameSite::Lax)
+            .max_age(cookie::time::Duration::ZERO)
+            .path("/auth")
+            .finish();
+
+        Ok((auth_step, cookie))
+    }
+
+    /// Redeems the upstream code and returns the matching user, which will be created or
+    /// updated with the upstream claims.
+    async fn fetch_upstream_user(
+        &self,
+        data: &web::Data<AppState>,
+        req: &HttpRequest,
+        payload: &ProviderCallbackRequest,
+    ) -> Result<(User, ProviderMfaLogin, Option<AuthProviderLinkCookie>), ErrorResponse> {
         // request is valid -> fetch token for the user
-        let provider = AuthProvider::find(data, &slf.provider_id).await?;
+        let provider = AuthProvider::find(data, &self.provider_id).await?;
         let client = AuthProvider::build_client(
             provider.allow_insecure_requests,
             provider.root_pem.as_deref(),
@@ -878,108 +1136,7 @@ impl AuthProviderCallback {
             }
         };
 
-        user.check_enabled()?;
-        user.check_expired()?;
-
-        if link_cookie.is_some() {
-            // If this is the case, we don't need to validate any further client values.
-            // We will not generate a new auth code at all -> this is just a request to federate
-            // an existing account. The federation has been done in the step above already.
-            return Ok((
-                AuthStep::ProviderLink,
-                AuthProviderLinkCookie::deletion_cookie(),
-            ));
-        }
-
-        // validate client values
-        let client = Client::find_maybe_ephemeral(data, slf.req_client_id).await?;
-        let force_mfa = client.force_mfa();
-        if force_mfa {
-            if provider_mfa_login == ProviderMfaLogin::No && !user.has_webauthn_enabled() {
-                return Err(ErrorResponse::new(
-                    ErrorResponseType::MfaRequired,
-                    "MFA is required for this client".to_string(),
-                ));
-            }
-            session.set_mfa(data, true).await?;
-        }
-        client.validate_redirect_uri(&slf.req_redirect_uri)?;
-        client.validate_code_challenge(&slf.req_code_challenge, &slf.req_code_challenge_method)?;
-        let header_origin = client.validate_origin(req, &data.listen_scheme, &data.public_url)?;
-
-        // ######################################
-        // all good, we can generate an auth code
-
-        // authorization code
-        let code_lifetime = if force_mfa && user.has_webauthn_enabled() {
-            client.auth_code_lifetime + *WEBAUTHN_REQ_EXP as i32
-        } else {
-            client.auth_code_lifetime
-        };
-        let scopes = client.sanitize_login_scopes(&slf.req_scopes)?;
-        let code = AuthCode::new(
-            user.id.clone(),
-            client.id,
-            Some(session.id.clone()),
-            slf.req_code_challenge,
-            slf.req_code_challenge_method,
-            slf.req_nonce,
-            scopes,
-            code_lifetime,
-        );
-        code.save(data).await?;
-
-        // location header
-        let mut loc = format!("{}?code={}", slf.req_redirect_uri, code.id);
-        if let Some(state) = slf.req_state {
-            write!(loc, "&state={}", state).expect("`write!` to succeed");
-        };
-
-        let auth_step = if user.has_webauthn_enabled() {
-            let step = AuthStepAwaitWebauthn {
-                has_password_been_hashed: false,
-                code: get_rand(48),
-                header_csrf: Session::get_csrf_header(&session.csrf_token),
-                header_origin,
-                user_id: user.id.clone(),
-                email: user.email,
-                exp: *WEBAUTHN_REQ_EXP,
-                session,
-            };
-
-            WebauthnLoginReq {
-                code: step.code.clone(),
-                user_id: user.id,
-                header_loc: loc,
-                header_origin: step
-                    .header_origin
-                    .as_ref()
-                    .map(|h| h.1.to_str().unwrap().to_string()),
-            }
-            .save(data)
-            .await?;
-
-            AuthStep::AwaitWebauthn(step)
-        } else {
-            AuthStep::LoggedIn(AuthStepLoggedIn {
-                has_password_been_hashed: false,
-                email: user.email,
-                header_loc: (header::LOCATION, HeaderValue::from_str(&loc).unwrap()),
-                header_csrf: Session::get_csrf_header(&session.csrf_token),
-                header_origin,
-            })
-        };
-
-        // callback data deletion cookie
-        let cookie = cookie::Cookie::build(COOKIE_UPSTREAM_CALLBACK, "")
-            .secure(true)
-            .http_only(true)
-            .same_site(cookie::SameSite::Lax)
-            .max_age(cookie::time::Duration::ZERO)
-            .path("/auth")
-            .finish();
-
-        Ok((auth_step, cookie))
+        Ok((user, provider_mfa_login, link_cookie))
     }
 }
 
@@ -996,12 +1153,11 @@ impl AuthProviderTemplate {
     pub async fn get_all_json_template(
         data: &web::Data<AppState>,
     ) -> Result<Option<String>, ErrorResponse> {
-        if let Some(res) = cache_get!(
-            Option<String>,
+        if let Some(res) = cache_get::<Option<String>>(
             CACHE_NAME_12HR.to_string(),
             IDX_AUTH_PROVIDER_TEMPLATE.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -1327,6 +1483,8 @@ impl AuthProviderIdClaims<'_> {
             }
         }
 
+        let claim_mapping = provider.get_claim_mapping();
+
         let now = OffsetDateTime::now_utc().unix_timestamp();
         let user = if let Some(mut user) = user_opt {
             let mut old_email = None;
@@ -1423,7 +1581,11 @@ impl AuthProviderIdClaims<'_> {
                 enabled: true,
                 email_verified: self.email_verified.unwrap_or(false),
                 last_login: Some(now),
-                language: self.locale.map(Language::from).unwrap_or_default(),
+                language: self
+                    .locale
+                    .filter(|_| claim_mapping.persists(ProviderClaim::Locale))
+                    .map(Language::from)
+                    .unwrap_or_default(),
                 auth_provider_id: Some(provider.id.clone()),
                 federation_uid: Some(claims_user_id.to_string()),
                 ..Default::default()
@@ -1451,15 +1613,25 @@ impl AuthProviderIdClaims<'_> {
                 country: None,
             },
         };
-        if let Some(bday) = self.birthdate {
+        if let Some(bday) = self
+            .birthdate
+            .filter(|_| claim_mapping.persists(ProviderClaim::Birthdate))
+        {
             user_values.birthdate = Some(bday.to_string());
             found_values = true;
         }
-        if let Some(phone) = self.phone {
+        if let Some(phone) = self
+            .phone
+            .filter(|_| claim_mapping.persists(ProviderClaim::Phone))
+        {
             user_values.phone = Some(phone.to_string());
             found_values = true;
         }
-        if let Some(addr) = &self.address {
+        if let Some(addr) = self
+            .address
+            .as_ref()
+            .filter(|_| claim_mapping.persists(ProviderClaim::Address))
+        {
             if let Some(street) = addr.street_address {
                 user_values.street = Some(street.to_string());
             }
@@ -1475,6 +1647,21 @@ impl AuthProviderIdClaims<'_> {
             UserValues::upsert(data, user.id.clone(), user_values).await?;
         }
 
+        if !claim_mapping.attributes.is_empty() {
+            let json = serde_json::from_slice::<value::Value>(self.json_bytes.unwrap())?;
+            let values = claim_mapping
+                .map_attributes(&json)?
+                .into_iter()
+                .map(|(key, value)| UserAttrValueRequest { key, value })
+                .collect();
+            UserAttrValueEntity::update_for_user(
+                data,
+                &user.id,
+                UserAttrValuesUpdateRequest { values },
+            )
+            .await?;
+        }
+
         Ok((user, provider_mfa_login))
     }
 }
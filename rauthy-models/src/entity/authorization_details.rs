@@ -0,0 +1,244 @@
+use crate::entity::clients::Client;
+use crate::AuthorizationDetail;
+use rauthy_common::constants::AUTHORIZATION_DETAILS_SCOPE_PREFIX;
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use reqwest::Url;
+
+/// The max amount of entries a single `authorization_details` param may contain
+const MAX_DETAILS: usize = 16;
+/// The max length of each single value of the common fields
+const MAX_VALUE_LEN: usize = 256;
+/// The max size of the serialized, `type` specific fields of a single entry. These are copied
+/// into each access and refresh token.
+const MAX_FIELDS_LEN: usize = 1024;
+
+// Rich Authorization Requests (RFC 9396)
+impl AuthorizationDetail {
+    /// Parses and validates the `authorization_details` param of an authorization or token
+    /// request. Each `type` must be one of the `authorization_details_types` of the client and
+    /// must not use the prefix of the details derived from parameterized scopes.
+    /// Returns `None`, if no details have been requested.
+    pub fn from_param(
+        client: &Client,
+        param: Option<&str>,
+    ) -> Result<Option<Vec<Self>>, ErrorResponse> {
+        let Some(param) = param else {
+            return Ok(None);
+        };
+
+        let details = Self::parse_param(param)?;
+        let types = client
+            .authorization_details_types
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .collect::<Vec<&str>>();
+        for detail in &details {
+            if detail.typ.is_empty()
+                || detail.typ.starts_with(AUTHORIZATION_DETAILS_SCOPE_PREFIX)
+                || !types.contains(&detail.typ.as_str())
+            {
+                return Err(err_invalid_details(&format!(
+                    "The client is not allowed to request the type '{}'",
+                    detail.typ
+                )));
+            }
+            detail.validate()?;
+        }
+
+        Ok(Some(details))
+    }
+
+    /// Parses the `authorization_details` param, which must be a non-empty JSON array.
+    pub fn parse_param(param: &str) -> Result<Vec<Self>, ErrorResponse> {
+        let details = serde_json::from_str::<Vec<Self>>(param)
+            .map_err(|_| err_invalid_details("Malformed 'authorization_details'"))?;
+        if details.is_empty() || details.len() > MAX_DETAILS {
+            return Err(err_invalid_details(&format!(
+                "'authorization_details' must contain 1 - {} entries",
+                MAX_DETAILS
+            )));
+        }
+        Ok(details)
+    }
+
+    /// Returns the `requested` details for a token request, which must all have been `granted`
+    /// during the authorization before. Without a request, all `granted` ones are returned.
+    pub fn narrow(
+        granted: Option<Vec<Self>>,
+        requested: Option<&str>,
+    ) -> Result<Option<Vec<Self>>, ErrorResponse> {
+        let Some(requested) = requested else {
+            return Ok(granted);
+        };
+
+        let requested = Self::parse_param(requested)?;
+        let granted = granted.unwrap_or_default();
+        if requested.iter().all(|d| granted.contains(d)) {
+            Ok(Some(requested))
+        } else {
+            Err(err_invalid_details(
+                "The 'authorization_details' have not been granted",
+            ))
+        }
+    }
+
+    /// Merges the details derived from parameterized scopes with the requested ones.
+    pub fn merge(
+        from_scopes: Option<Vec<Self>>,
+        requested: Option<Vec<Self>>,
+    ) -> Option<Vec<Self>> {
+        match (from_scopes, requested) {
+            (Some(mut from_scopes), Some(requested)) => {
+                from_scopes.extend(requested);
+                Some(from_scopes)
+            }
+            (from_scopes, None) => from_scopes,
+            (None, requested) => requested,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ErrorResponse> {
+        let values = self
+            .actions
+            .iter()
+            .chain(self.datatypes.iter())
+            .chain(self.privileges.iter())
+            .chain(self.locations.iter())
+            .chain(self.identifier.iter());
+        for value in values {
+            if value.is_empty() || value.len() > MAX_VALUE_LEN {
+                return Err(err_invalid_details(&format!(
+                    "Each value must have 1 - {} characters",
+                    MAX_VALUE_LEN
+                )));
+            }
+        }
+
+        if !self.fields.is_empty() {
+            let len = serde_json::to_string(&self.fields)
+                .map(|s| s.len())
+                .unwrap_or(usize::MAX);
+            if len > MAX_FIELDS_LEN {
+                return Err(err_invalid_details(&format!(
+                    "The type specific fields must not exceed {} bytes",
+                    MAX_FIELDS_LEN
+                )));
+            }
+        }
+
+        for location in &self.locations {
+            // RFC 9396 does not require absolute URIs, but relative ones would be ambiguous
+            if Url::parse(location).is_err() {
+                return Err(err_invalid_details(
+                    "Each 'locations' entry must be an absolute URI",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn err_invalid_details(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::BadRequest,
+        format!("invalid_authorization_details: {}", msg),
+    )
+    .with_code(ErrorCode::InvalidAuthorizationDetails)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_param() {
+        let client = Client {
+            authorization_details_types: Some("payment_initiation,account_information".to_string()),
+            ..Default::default()
+        };
+
+        let param = r#"[{
+            "type": "payment_initiation",
+            "actions": ["initiate"],
+            "locations": ["https://bank.example.com/payments"],
+            "instructedAmount": {"currency": "EUR", "amount": "123.50"}
+        }]"#;
+        let details = AuthorizationDetail::from_param(&client, Some(param))
+            .unwrap()
+            .unwrap();
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].typ, "payment_initiation");
+        assert_eq!(details[0].actions, vec!["initiate".to_string()]);
+        assert!(details[0].fields.contains_key("instructedAmount"));
+
+        // the type specific fields must survive a roundtrip into the tokens
+        let json = serde_json::to_value(&details[0]).unwrap();
+        assert_eq!(json["instructedAmount"]["currency"], "EUR");
+
+        assert!(AuthorizationDetail::from_param(&client, None)
+            .unwrap()
+            .is_none());
+
+        let err =
+            AuthorizationDetail::from_param(&client, Some(r#"[{"type":"other"}]"#)).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidAuthorizationDetails);
+        assert!(AuthorizationDetail::from_param(&client, Some("[]")).is_err());
+        assert!(AuthorizationDetail::from_param(&client, Some(r#"{"type":"x"}"#)).is_err());
+        assert!(AuthorizationDetail::from_param(
+            &client,
+            Some(r#"[{"type":"payment_initiation","locations":["/relative"]}]"#)
+        )
+        .is_err());
+
+        let too_big = format!(
+            r#"[{{"type":"payment_initiation","note":"{}"}}]"#,
+            "x".repeat(MAX_FIELDS_LEN)
+        );
+        assert!(AuthorizationDetail::from_param(&client, Some(&too_big)).is_err());
+
+        // types of parameterized scopes can never be requested, even if configured before
+        let client = Client {
+            authorization_details_types: Some("scope:account".to_string()),
+            ..Default::default()
+        };
+        assert!(AuthorizationDetail::from_param(
+            &client,
+            Some(r#"[{"type":"scope:account","identifier":"1234"}]"#)
+        )
+        .is_err());
+
+        let client = Client::default();
+        assert!(AuthorizationDetail::from_param(&client, Some(param)).is_err());
+        assert!(AuthorizationDetail::from_param(&client, Some(r#"[{"type":""}]"#)).is_err());
+    }
+
+    #[test]
+    fn test_narrow() {
+        let granted = AuthorizationDetail::parse_param(
+            r#"[{"type":"account_information","actions":["read"]},{"type":"payment_initiation"}]"#,
+        )
+        .unwrap();
+
+        let all = AuthorizationDetail::narrow(Some(granted.clone()), None).unwrap();
+        assert_eq!(all, Some(granted.clone()));
+
+        let subset = AuthorizationDetail::narrow(
+            Some(granted.clone()),
+            Some(r#"[{"type":"payment_initiation"}]"#),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(subset, vec![granted[1].clone()]);
+
+        assert!(AuthorizationDetail::narrow(
+            Some(granted.clone()),
+            Some(r#"[{"type":"account_information","actions":["write"]}]"#),
+        )
+        .is_err());
+        assert!(
+            AuthorizationDetail::narrow(None, Some(r#"[{"type":"payment_initiation"}]"#)).is_err()
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::CACHE_NAME_BREAK_GLASS;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::get_rand;
+use redhac::{cache_insert, AckLevel};
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+
+/// A short-lived, one-time code, which is exchanged for a break glass admin session.
+///
+/// It only lives inside the `break-glass` cache, which has a lifespan of 60 seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakGlassCode {
+    pub code: String,
+    pub user_id: String,
+}
+
+impl BreakGlassCode {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+    ) -> Result<Self, ErrorResponse> {
+        let slf = Self {
+            code: get_rand(64),
+            user_id,
+        };
+
+        cache_insert(
+            CACHE_NAME_BREAK_GLASS.to_string(),
+            slf.code.clone(),
+            &data.caches.ha_cache_config,
+            &slf,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+
+    /// Returns the code and removes it from the cache, so it can only be used once.
+    pub async fn consume(data: &web::Data<AppState>, code: String) -> Result<Self, ErrorResponse> {
+        let slf = cache_get::<Self>(
+            CACHE_NAME_BREAK_GLASS.to_string(),
+            code.clone(),
+            &data.caches.ha_cache_config,
+            true,
+        )
+        .await?
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Break glass code not found or expired".to_string(),
+            )
+        })?;
+
+        cache_remove(
+            CACHE_NAME_BREAK_GLASS.to_string(),
+            code,
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+}
+
+/// Keeps track of the last used break glass credential.
+///
+/// The hash of the credential is saved after a successful usage. As long as it is still configured,
+/// any further attempt will be rejected and a new credential must be provisioned.
+pub struct BreakGlassCredential;
+
+impl BreakGlassCredential {
+    pub async fn is_used(data: &web::Data<AppState>, hash: &str) -> Result<bool, ErrorResponse> {
+        let res = query!("select data from config where id = 'break_glass_used'")
+            .fetch_optional(&data.db)
+            .await?;
+
+        Ok(res
+            .and_then(|row| row.data)
+            .map(|used| used == hash.as_bytes())
+            .unwrap_or(false))
+    }
+
+    pub async fn set_used(data: &web::Data<AppState>, hash: &str) -> Result<(), ErrorResponse> {
+        let used = hash.as_bytes().to_vec();
+
+        #[cfg(not(feature = "postgres"))]
+        let q = query!(
+            "insert or replace into config (id, data) values ('break_glass_used', $1)",
+            used,
+        );
+        #[cfg(feature = "postgres")]
+        let q = query!(
+            r#"insert into config (id, data) values ('break_glass_used', $1)
+            on conflict(id) do update set data = $1"#,
+            used,
+        );
+        q.execute(&data.db).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use crate::app_state::AppState;
+use crate::events::event::Event;
+use crate::pii;
+use actix_web::web;
+use once_cell::sync::Lazy;
+use prometheus::{IntCounterVec, Opts, Registry};
+use rauthy_common::cache_metrics::cache_remove;
+use rauthy_common::constants::{
+    CACHE_NAME_USERS, IDX_USERS, SCHED_CLEANUP_MAGIC_LINKS_MINS,
+    SCHED_CLEANUP_PASSWORD_RESETS_MINS, SCHED_CLEANUP_REFRESH_TOKENS_MINS,
+    SCHED_CLEANUP_WEBAUTHN_MINS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use redhac::AckLevel;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+use time::OffsetDateTime;
+use tracing::{debug, error};
+use utoipa::ToSchema;
+
+static LAST_RUNS: Lazy<Mutex<HashMap<CleanupJob, CleanupRun>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static METRIC_PURGED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "rauthy_cleanup_rows_purged_total",
+            "Rows deleted or reset by the cleanup jobs",
+        ),
+        &["job"],
+    )
+    .expect("Cannot build rauthy_cleanup_rows_purged_total")
+});
+
+static METRIC_RUNS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("rauthy_cleanup_runs_total", "Cleanup job runs by result"),
+        &["job", "result"],
+    )
+    .expect("Cannot build rauthy_cleanup_runs_total")
+});
+
+/// Registers the cleanup job metrics with the given Prometheus registry.
+pub fn register_metrics(registry: &Registry) {
+    for metric in [METRIC_PURGED.clone(), METRIC_RUNS.clone()] {
+        if let Err(err) = registry.register(Box::new(metric)) {
+            error!("Cannot register cleanup metrics: {}", err);
+        }
+    }
+}
+
+/// Jobs, which hard delete expired or orphaned auth artifacts from the database.
+///
+/// PoW challenges, webauthn ceremony states and other short-lived values only live inside the
+/// caches and expire there on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupJob {
+    /// Expired magic links and MFA recoveries
+    MagicLinks,
+    /// Password and passkey reset links, which have been used already
+    PasswordResets,
+    /// Expired refresh tokens
+    RefreshTokens,
+    /// `webauthn_user_id`s of users without any passkey left
+    WebauthnRegistrations,
+}
+
+impl CleanupJob {
+    pub fn all() -> [Self; 4] {
+        [
+            Self::MagicLinks,
+            Self::PasswordResets,
+            Self::RefreshTokens,
+            Self::WebauthnRegistrations,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MagicLinks => "magic_links",
+            Self::PasswordResets => "password_resets",
+            Self::RefreshTokens => "refresh_tokens",
+            Self::WebauthnRegistrations => "webauthn_registrations",
+        }
+    }
+
+    /// The scheduled interval in minutes. `0` disables the scheduled runs.
+    pub fn interval_mins(&self) -> u64 {
+        match self {
+            Self::MagicLinks => *SCHED_CLEANUP_MAGIC_LINKS_MINS,
+            Self::PasswordResets => *SCHED_CLEANUP_PASSWORD_RESETS_MINS,
+            Self::RefreshTokens => *SCHED_CLEANUP_REFRESH_TOKENS_MINS,
+            Self::WebauthnRegistrations => *SCHED_CLEANUP_WEBAUTHN_MINS,
+        }
+    }
+
+    /// The latest run of this job on this instance, if any.
+    pub fn last_run(&self) -> Option<CleanupRun> {
+        LAST_RUNS
+            .lock()
+            .expect("cleanup runs lock to never be poisoned")
+            .get(self)
+            .cloned()
+    }
+
+    /// Executes the job and returns the amount of purged rows. The result is recorded in the
+    /// metrics and as the latest run.
+    pub async fn run(
+        &self,
+        data: &web::Data<AppState>,
+        manual: bool,
+    ) -> Result<u64, ErrorResponse> {
+        debug!("Running cleanup job {}", self);
+
+        let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+        let start = Instant::now();
+        let res = match self {
+            Self::MagicLinks => Self::cleanup_magic_links(data).await,
+            Self::PasswordResets => Self::cleanup_password_resets(data).await,
+            Self::RefreshTokens => Self::cleanup_refresh_tokens(data).await,
+            Self::WebauthnRegistrations => Self::cleanup_webauthn_registrations(data).await,
+        };
+
+        let run = CleanupRun {
+            timestamp,
+            duration_ms: start.elapsed().as_millis() as u64,
+            purged: *res.as_ref().unwrap_or(&0),
+            error: res.as_ref().err().map(|err| err.message.clone()),
+            manual,
+        };
+        match &res {
+            Ok(purged) => {
+                debug!("Cleanup job {} purged {} rows", self, purged);
+                METRIC_PURGED
+                    .with_label_values(&[self.as_str()])
+                    .inc_by(*purged);
+                METRIC_RUNS.with_label_values(&[self.as_str(), "ok"]).inc();
+            }
+            Err(err) => {
+                error!("Cleanup job {} error: {:?}", self, err);
+                METRIC_RUNS
+                    .with_label_values(&[self.as_str(), "error"])
+                    .inc();
+            }
+        }
+        LAST_RUNS
+            .lock()
+            .expect("cleanup runs lock to never be poisoned")
+            .insert(*self, run);
+
+        res
+    }
+
+    async fn cleanup_magic_links(data: &web::Data<AppState>) -> Result<u64, ErrorResponse> {
+        // allow 300 seconds of clock skew before cleaning up magic links
+        let exp = OffsetDateTime::now_utc().unix_timestamp() - 300;
+        let mut purged = 0;
+
+        // Check for expired and unused magic links that are bound to a user which has no password
+        // at all. These users should be deleted since they never cared about the (very important)
+        // password E-Mail.
+        let users = sqlx::query!(
+            r#"select id, email from users where
+            id in (select distinct user_id from magic_links where exp < $1 and used = false)
+            and password is null
+            and id not in (select user_id from legal_holds)"#,
+            exp
+        )
+        .fetch_all(&data.db)
+        .await?;
+        for user in users {
+            let id = user.id;
+            let email = pii::decrypt_email(user.email)?;
+            if let Err(err) = sqlx::query!("delete from users where id = $1", id)
+                .execute(&data.db)
+                .await
+            {
+                error!("Magic link / orphan users cleanup error: {:?}", err);
+                continue;
+            }
+            purged += 1;
+            if let Err(err) = Event::anonymize_user(&data.db, &id, &email).await {
+                error!("Error anonymizing events for user {}: {:?}", id, err);
+            }
+        }
+
+        // now we can just delete all expired magic links
+        let res = sqlx::query!("delete from magic_links where exp < $1", exp)
+            .execute(&data.db)
+            .await?;
+        purged += res.rows_affected();
+
+        // MFA recoveries are cleaned up together with the magic links they end up in
+        let res = sqlx::query!("delete from mfa_recoveries where exp < $1", exp)
+            .execute(&data.db)
+            .await?;
+        purged += res.rows_affected();
+
+        Ok(purged)
+    }
+
+    async fn cleanup_password_resets(data: &web::Data<AppState>) -> Result<u64, ErrorResponse> {
+        // Used reset links would otherwise be kept until they expire. They cannot be used twice
+        // anyway.
+        let res = sqlx::query!(
+            r#"delete from magic_links where used = true
+            and (usage like 'password_reset%' or usage like 'passkey_reset%')"#
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn cleanup_refresh_tokens(data: &web::Data<AppState>) -> Result<u64, ErrorResponse> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let res = sqlx::query!("delete from refresh_tokens where exp < $1", now)
+            .execute(&data.db)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn cleanup_webauthn_registrations(
+        data: &web::Data<AppState>,
+    ) -> Result<u64, ErrorResponse> {
+        let users = sqlx::query!(
+            r#"select id, email from users where webauthn_user_id is not null
+            and id not in (select distinct user_id from passkeys)"#
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        let mut purged = 0;
+        for user in users {
+            let (id, email) = (user.id, user.email);
+            // check again inside the update to not race with a new passkey registration
+            let res = sqlx::query!(
+                r#"update users set webauthn_user_id = null
+                where id = $1 and id not in (select distinct user_id from passkeys)"#,
+                id
+            )
+            .execute(&data.db)
+            .await?;
+            if res.rows_affected() == 0 {
+                continue;
+            }
+            purged += 1;
+
+            for idx in [id, pii::decrypt_email(email)?] {
+                cache_remove(
+                    CACHE_NAME_USERS.to_string(),
+                    format!("{}_{}", IDX_USERS, idx),
+                    &data.caches.ha_cache_config,
+                    AckLevel::Quorum,
+                )
+                .await?;
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+impl Display for CleanupJob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CleanupJob {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .into_iter()
+            .find(|job| job.as_str() == s)
+            .ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::NotFound,
+                    format!("Unknown cleanup job: {}", s),
+                )
+            })
+    }
+}
+
+/// A single run of a cleanup job on this instance.
+#[derive(Debug, Clone)]
+pub struct CleanupRun {
+    pub timestamp: i64,
+    pub duration_ms: u64,
+    /// Rows deleted or reset
+    pub purged: u64,
+    pub error: Option<String>,
+    /// `true`, if the run has been triggered via the API
+    pub manual: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup_job_from_str() {
+        for job in CleanupJob::all() {
+            assert_eq!(CleanupJob::from_str(job.as_str()).unwrap(), job);
+        }
+        assert!(CleanupJob::from_str("pow").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::ErrorResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Keeps track of who has read the cleartext secret of a client.
+///
+/// Each single read is logged as an event as well. This only holds the counter and the latest
+/// access for a quick overview in the client details.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ClientSecretRead {
+    pub client_id: String,
+    /// amount of secret reads in total
+    pub reads: i64,
+    /// unix timestamp of the latest read
+    pub last_read: i64,
+    /// user id or `api_key:<name>` of the latest reader
+    pub last_actor: String,
+    pub last_ip: Option<String>,
+}
+
+impl ClientSecretRead {
+    /// Records a read of the client secret.
+    pub async fn record(
+        data: &web::Data<AppState>,
+        client_id: &str,
+        actor: &str,
+        ip: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+
+        sqlx::query!(
+            r#"insert into client_secret_reads
+            (client_id, reads, last_read, last_actor, last_ip)
+            values ($1, 1, $2, $3, $4)
+            on conflict (client_id) do update
+            set reads = client_secret_reads.reads + 1, last_read = $2, last_actor = $3,
+                last_ip = $4"#,
+            client_id,
+            now,
+            actor,
+            ip,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find(
+        data: &web::Data<AppState>,
+        client_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from client_secret_reads where client_id = $1",
+            client_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,124 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::ErrorResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// The max length of a stored user agent. Anything longer will be truncated.
+const USER_AGENT_MAX_LEN: usize = 64;
+/// The max amount of distinct user agents per client. Any new one after that is counted as
+/// `OTHER`.
+const USER_AGENTS_MAX_PER_CLIENT: usize = 50;
+
+/// Aggregated telemetry about which user agents / client libraries request tokens for a client.
+///
+/// This makes it possible to find relying parties running outdated SDKs before older flows get
+/// deprecated.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ClientUserAgent {
+    pub client_id: String,
+    pub user_agent: String,
+    /// unix timestamp of the first token request with this user agent
+    pub first_seen: i64,
+    /// unix timestamp of the latest token request with this user agent
+    pub last_seen: i64,
+    /// amount of successful token requests with this user agent
+    pub requests: i64,
+}
+
+impl ClientUserAgent {
+    /// Records a successful token request for the given client and user agent.
+    pub async fn record(
+        data: &web::Data<AppState>,
+        client_id: &str,
+        user_agent: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let mut user_agent = Self::product(user_agent);
+        let known = sqlx::query_scalar::<_, String>(
+            "select user_agent from client_user_agents where client_id = $1",
+        )
+        .bind(client_id)
+        .fetch_all(&data.db)
+        .await?;
+        if known.len() >= USER_AGENTS_MAX_PER_CLIENT && !known.contains(&user_agent) {
+            user_agent = "OTHER".to_string();
+        }
+        let now = Utc::now().timestamp();
+
+        sqlx::query!(
+            r#"insert into client_user_agents
+            (client_id, user_agent, first_seen, last_seen, requests)
+            values ($1, $2, $3, $3, 1)
+            on conflict (client_id, user_agent) do update
+            set last_seen = $3, requests = client_user_agents.requests + 1"#,
+            client_id,
+            user_agent,
+            now,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Only the first product of a user agent is kept, like `rauthy-client/0.4.0`. This is the
+    /// one naming the SDK, while the rest would create a new entry for every OS or browser
+    /// version.
+    fn product(user_agent: Option<&str>) -> String {
+        match user_agent.and_then(|ua| ua.split_whitespace().next()) {
+            Some(product) => product.chars().take(USER_AGENT_MAX_LEN).collect(),
+            None => "UNKNOWN".to_string(),
+        }
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from client_user_agents order by client_id asc, last_seen desc"
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_for_client(
+        data: &web::Data<AppState>,
+        client_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from client_user_agents where client_id = $1 order by last_seen desc",
+            client_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent_product() {
+        assert_eq!(
+            ClientUserAgent::product(Some("rauthy-client/0.4.0 reqwest/0.11.27")),
+            "rauthy-client/0.4.0"
+        );
+        assert_eq!(
+            ClientUserAgent::product(Some(
+                " Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0"
+            )),
+            "Mozilla/5.0"
+        );
+        assert_eq!(ClientUserAgent::product(Some(" ")), "UNKNOWN");
+        assert_eq!(ClientUserAgent::product(None), "UNKNOWN");
+        assert_eq!(
+            ClientUserAgent::product(Some(&"a".repeat(100))).len(),
+            USER_AGENT_MAX_LEN
+        );
+    }
+}
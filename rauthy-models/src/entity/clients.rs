@@ -1,8 +1,12 @@
 use crate::app_state::{AppState, DbTxn};
+use crate::cache_invalidation::Invalidation;
+use crate::entity::app_attestation::AppAttestation;
 use crate::entity::clients_dyn::ClientDyn;
+use crate::entity::jwe;
 use crate::entity::jwk::JwkKeyPairAlg;
 use crate::entity::scopes::Scope;
 use crate::entity::users::User;
+use crate::events::event::Event;
 use crate::request::{DynamicClientRequest, EphemeralClientRequest, NewClientRequest};
 use crate::response::DynamicClientResponse;
 use crate::ListenScheme;
@@ -10,21 +14,23 @@ use actix_web::http::header;
 use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::{web, HttpRequest};
 use cryptr::{utils, EncKeys, EncValue};
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
 use rauthy_common::constants::{
     ADMIN_FORCE_MFA, APPLICATION_JSON, CACHE_NAME_12HR, CACHE_NAME_EPHEMERAL_CLIENTS,
     DYN_CLIENT_DEFAULT_TOKEN_LIFETIME, DYN_CLIENT_SECRET_AUTO_ROTATE, ENABLE_EPHEMERAL_CLIENTS,
     EPHEMERAL_CLIENTS_ALLOWED_FLOWS, EPHEMERAL_CLIENTS_ALLOWED_SCOPES, EPHEMERAL_CLIENTS_FORCE_MFA,
     IDX_CLIENTS, PROXY_MODE, RAUTHY_VERSION,
 };
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::{cache_entry_client, get_client_ip, get_rand};
-use redhac::{
-    cache_get, cache_get_from, cache_get_value, cache_insert, cache_put, cache_remove, AckLevel,
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{
+    cache_entry_client, get_client_ip, get_rand, is_ip_in_cidrs, real_ip_from_req, IpCidr,
 };
+use redhac::{cache_insert, cache_put, AckLevel};
 use reqwest::header::CONTENT_TYPE;
 use reqwest::{tls, Url};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::OnceLock;
 use std::time::Duration;
@@ -69,6 +75,24 @@ pub struct Client {
     pub force_mfa: bool,
     pub client_uri: Option<String>,
     pub contacts: Option<String>,
+    /// Comma separated source networks in CIDR notation, which are allowed to use the token
+    /// endpoint for a confidential client
+    pub allowed_cidrs: Option<String>,
+    /// The JWKS of the client to validate signed request objects (RFC 9101)
+    pub jwks_uri: Option<String>,
+    /// If set, the authorization endpoint only accepts signed request objects
+    pub require_signed_request_object: bool,
+    /// If set, the `id_token` is encrypted with a key from the `jwks_uri`
+    pub id_token_encrypted_response_alg: Option<String>,
+    pub id_token_encrypted_response_enc: Option<String>,
+    /// If set, userinfo responses are encrypted with a key from the `jwks_uri`
+    pub userinfo_encrypted_response_alg: Option<String>,
+    pub userinfo_encrypted_response_enc: Option<String>,
+    /// Comma separated `type`s of RFC 9396 `authorization_details` the client may request
+    pub authorization_details_types: Option<String>,
+    /// Comma separated client ids, which may be requested as `audience` for the
+    /// `token-exchange` grant
+    pub token_exchange_audiences: Option<String>,
 }
 
 // CRUD
@@ -95,9 +119,12 @@ impl Client {
             r#"insert into clients (id, name, enabled, confidential, secret, secret_kid,
             redirect_uris, post_logout_redirect_uris, allowed_origins, flows_enabled, access_token_alg,
             id_token_alg, refresh_token, auth_code_lifetime, access_token_lifetime, scopes, default_scopes,
-            challenge, force_mfa, client_uri, contacts)
+            challenge, force_mfa, client_uri, contacts, allowed_cidrs, jwks_uri,
+            require_signed_request_object, id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc, userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc, authorization_details_types, token_exchange_audiences)
             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
-            $18, $19, $20, $21)"#,
+            $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)"#,
             client.id,
             client.name,
             client.enabled,
@@ -119,6 +146,15 @@ impl Client {
             client.force_mfa,
             client.client_uri,
             client.contacts,
+            client.allowed_cidrs,
+            client.jwks_uri,
+            client.require_signed_request_object,
+            client.id_token_encrypted_response_alg,
+            client.id_token_encrypted_response_enc,
+            client.userinfo_encrypted_response_alg,
+            client.userinfo_encrypted_response_enc,
+            client.authorization_details_types,
+            client.token_exchange_audiences,
         )
             .execute(&data.db)
             .await?
@@ -160,9 +196,12 @@ impl Client {
             r#"INSERT INTO clients (id, name, enabled, confidential, secret, secret_kid,
             redirect_uris, post_logout_redirect_uris, allowed_origins, flows_enabled,
             access_token_alg, id_token_alg, refresh_token, auth_code_lifetime, access_token_lifetime,
-            scopes, default_scopes, challenge, force_mfa, client_uri, contacts)
+            scopes, default_scopes, challenge, force_mfa, client_uri, contacts, allowed_cidrs,
+            jwks_uri, require_signed_request_object, id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc, userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc, authorization_details_types, token_exchange_audiences)
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
-            $18, $19, $20, $21)"#,
+            $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)"#,
             client.id,
             client.name,
             client.enabled,
@@ -184,6 +223,15 @@ impl Client {
             client.force_mfa,
             client.client_uri,
             client.contacts,
+            client.allowed_cidrs,
+            client.jwks_uri,
+            client.require_signed_request_object,
+            client.id_token_encrypted_response_alg,
+            client.id_token_encrypted_response_enc,
+            client.userinfo_encrypted_response_alg,
+            client.userinfo_encrypted_response_enc,
+            client.authorization_details_types,
+            client.token_exchange_audiences,
         )
             .execute(&mut *txn)
             .await?;
@@ -219,17 +267,18 @@ impl Client {
             ClientDyn::delete_from_cache(data, &self.id).await?;
         }
 
+        Invalidation::Client(self.id.clone()).publish(data).await;
+
         Ok(())
     }
 
     // Returns a client by id without its secret.
     pub async fn find(data: &web::Data<AppState>, id: String) -> Result<Self, ErrorResponse> {
-        let client = cache_get!(
-            Client,
+        let client = cache_get::<Client>(
             CACHE_NAME_12HR.to_string(),
             cache_entry_client(&id),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(client) = client {
@@ -274,12 +323,11 @@ impl Client {
             return Self::find(data, id).await;
         }
 
-        if let Some(client) = cache_get!(
-            Client,
+        if let Some(client) = cache_get::<Client>(
             CACHE_NAME_EPHEMERAL_CLIENTS.to_string(),
             id.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -309,8 +357,12 @@ impl Client {
             secret_kid = $5, redirect_uris = $6, post_logout_redirect_uris = $7, allowed_origins = $8,
             flows_enabled = $9, access_token_alg = $10, id_token_alg = $11, refresh_token = $12,
             auth_code_lifetime = $13, access_token_lifetime = $14, scopes = $15, default_scopes = $16,
-            challenge = $17, force_mfa= $18, client_uri = $19, contacts = $20
-            where id = $21"#,
+            challenge = $17, force_mfa= $18, client_uri = $19, contacts = $20,
+            allowed_cidrs = $21, jwks_uri = $22, require_signed_request_object = $23,
+            id_token_encrypted_response_alg = $24, id_token_encrypted_response_enc = $25,
+            userinfo_encrypted_response_alg = $26, userinfo_encrypted_response_enc = $27,
+            authorization_details_types = $28, token_exchange_audiences = $29
+            where id = $30"#,
             self.name,
             self.enabled,
             self.confidential,
@@ -331,6 +383,15 @@ impl Client {
             self.force_mfa,
             self.client_uri,
             self.contacts,
+            self.allowed_cidrs,
+            self.jwks_uri,
+            self.require_signed_request_object,
+            self.id_token_encrypted_response_alg,
+            self.id_token_encrypted_response_enc,
+            self.userinfo_encrypted_response_alg,
+            self.userinfo_encrypted_response_enc,
+            self.authorization_details_types,
+            self.token_exchange_audiences,
             self.id,
         );
 
@@ -372,6 +433,8 @@ impl Client {
         )
         .await?;
 
+        Invalidation::Client(self.id.clone()).publish(data).await;
+
         Ok(())
     }
 
@@ -394,6 +457,12 @@ impl Client {
             ));
         }
 
+        // a new secret is only issued with auto rotation or if the client just became confidential
+        if !*DYN_CLIENT_SECRET_AUTO_ROTATE && current.confidential && new_client.confidential {
+            new_client.secret = current.secret;
+            new_client.secret_kid = current.secret_kid;
+        }
+
         // we need to keep some old and possibly user-modified values
         new_client.id = current.id;
         new_client.force_mfa = current.force_mfa;
@@ -507,6 +576,24 @@ impl Client {
         Some(res)
     }
 
+    pub fn get_allowed_cidrs(&self) -> Option<Vec<String>> {
+        self.allowed_cidrs
+            .as_ref()
+            .map(|cidrs| cidrs.split(',').map(String::from).collect())
+    }
+
+    pub fn get_authorization_details_types(&self) -> Option<Vec<String>> {
+        self.authorization_details_types
+            .as_ref()
+            .map(|types| types.split(',').map(String::from).collect())
+    }
+
+    pub fn get_token_exchange_audiences(&self) -> Option<Vec<String>> {
+        self.token_exchange_audiences
+            .as_ref()
+            .map(|aud| aud.split(',').map(String::from).collect())
+    }
+
     pub fn get_contacts(&self) -> Option<Vec<String>> {
         if let Some(contacts) = &self.contacts {
             let mut res = Vec::new();
@@ -590,19 +677,69 @@ impl Client {
         Url::from_str(&self.id).is_ok()
     }
 
+    /// Encrypts the signed `id_token` as a nested JWT, if the client has registered an
+    /// `id_token_encrypted_response_alg`. Otherwise, the token is returned unchanged.
+    pub async fn encrypt_id_token(
+        &self,
+        data: &web::Data<AppState>,
+        id_token: String,
+    ) -> Result<String, ErrorResponse> {
+        let Some(alg) = &self.id_token_encrypted_response_alg else {
+            return Ok(id_token);
+        };
+        jwe::encrypt_for_client(
+            data,
+            self,
+            alg,
+            self.id_token_encrypted_response_enc.as_deref(),
+            Some("JWT"),
+            id_token.as_bytes(),
+        )
+        .await
+    }
+
+    /// Returns the encrypted userinfo JSON, if the client has registered a
+    /// `userinfo_encrypted_response_alg`.
+    pub async fn encrypt_userinfo(
+        &self,
+        data: &web::Data<AppState>,
+        userinfo: &[u8],
+    ) -> Result<Option<String>, ErrorResponse> {
+        let Some(alg) = &self.userinfo_encrypted_response_alg else {
+            return Ok(None);
+        };
+        jwe::encrypt_for_client(
+            data,
+            self,
+            alg,
+            self.userinfo_encrypted_response_enc.as_deref(),
+            None,
+            userinfo,
+        )
+        .await
+        .map(Some)
+    }
+
     /// Sanitizes the current scopes and deletes everything, which does not exist in the `scopes`
     /// table in the database
     pub async fn sanitize_scopes(
         data: &web::Data<AppState>,
+        client_id: &str,
         scps: Vec<String>,
     ) -> Result<String, ErrorResponse> {
         let mut res = String::with_capacity(scps.len());
-        Scope::find_all(data).await?.into_iter().for_each(|s| {
+        for s in Scope::find_all(data).await? {
             if scps.contains(&s.name) {
+                if !s.is_allowed_for(client_id) {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::BadRequest,
+                        format!("The scope '{}' is restricted to other clients", s.name),
+                    ));
+                }
                 res.push_str(s.name.as_str());
                 res.push(',');
             }
-        });
+        }
         // remove the last comma
         if !res.is_empty() {
             res.remove(res.len() - 1);
@@ -618,6 +755,10 @@ impl Client {
 
     /// Sanitizes the requested scopes on the authorization endpoint and matches them to the
     /// allowed scopes for this client.
+    ///
+    /// Client scopes containing a `*` are patterns for parameterized scopes. A requested
+    /// `account:1234:read` is allowed for the pattern `account:*:read`, while a literal `*` is
+    /// never accepted.
     pub fn sanitize_login_scopes(
         &self,
         scopes: &Option<Vec<String>>,
@@ -639,11 +780,11 @@ impl Client {
         }
 
         for s in scopes {
-            if self.default_scopes.contains(s) {
+            if s.contains('*') || self.default_scopes.contains(s) {
                 continue;
             }
 
-            if self.scopes.contains(s) {
+            if self.scopes.contains(s) || Scope::find_pattern(self.scopes.split(','), s).is_some() {
                 res.push(s.clone());
             }
         }
@@ -657,6 +798,7 @@ impl Client {
     /// The "rauthy" client is the exception for this check to makes logging into the account
     /// possible without MFA. The force MFA for the Rauthy admin UI is done in
     /// Principal::validate_admin_session() depending on the `ADMIN_FORCE_MFA` config variable.
+    /// E-Mail one-time codes do not count as MFA here.
     pub fn validate_mfa(&self, user: &User) -> Result<(), ErrorResponse> {
         if &self.id != "rauthy" && self.force_mfa && !user.has_webauthn_enabled() {
             trace!("MFA required for this client but the user has none");
@@ -739,12 +881,49 @@ impl Client {
             Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
                 String::from("Invalid redirect uri"),
-            ))
+            )
+            .with_code(ErrorCode::RedirectUriMismatch))
         } else {
             Ok(())
         }
     }
 
+    /// Validates the `post_logout_redirect_uri` against the registered ones and returns the
+    /// final location with the `state` appended, if one was given.
+    pub fn post_logout_location(
+        &self,
+        post_logout_redirect_uri: &str,
+        state: Option<&str>,
+    ) -> Result<String, ErrorResponse> {
+        let is_valid = self
+            .get_post_logout_uris()
+            .unwrap_or_default()
+            .iter()
+            .any(|uri| {
+                (uri.ends_with('*')
+                    && post_logout_redirect_uri.starts_with(uri.split_once('*').unwrap().0))
+                    || uri.as_str().eq(post_logout_redirect_uri)
+            });
+        if !is_valid {
+            trace!("Invalid `post_logout_redirect_uri`");
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("Given 'post_logout_redirect_uri' is not allowed"),
+            ));
+        }
+
+        let mut location = Url::parse(post_logout_redirect_uri).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("Malformed 'post_logout_redirect_uri'"),
+            )
+        })?;
+        if let Some(state) = state {
+            location.query_pairs_mut().append_pair("state", state);
+        }
+        Ok(location.to_string())
+    }
+
     pub fn validate_code_challenge(
         &self,
         code_challenge: &Option<String>,
@@ -816,14 +995,26 @@ impl Client {
         Ok(())
     }
 
+    pub fn validate_enabled(&self) -> Result<(), ErrorResponse> {
+        if !self.enabled {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("client is disabled"),
+            )
+            .with_code(ErrorCode::ClientDisabled));
+        }
+        Ok(())
+    }
+
     pub fn validate_flow(&self, flow: &str) -> Result<(), ErrorResponse> {
         if flow.is_empty() || !self.flows_enabled.contains(flow) {
             return Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
                 format!("'{}' flow is not allowed for this client", flow),
-            ));
+            )
+            .with_code(ErrorCode::FlowNotAllowed));
         }
-        Ok(())
+        AppAttestation::validate_grant(&self.id, flow)
     }
 
     pub fn validate_secret(&self, secret: &str, req: &HttpRequest) -> Result<(), ErrorResponse> {
@@ -854,12 +1045,56 @@ impl Client {
             return Err(ErrorResponse::new(
                 ErrorResponseType::Unauthorized,
                 String::from("Invalid 'client_secret'"),
-            ));
+            )
+            .with_code(ErrorCode::ClientSecretInvalid));
         }
         Ok(())
     }
 }
 
+impl Client {
+    /// Validates the source IP of an authenticated token request against the `allowed_cidrs`.
+    /// A rejected request raises an event, because it is a strong indicator for a leaked secret.
+    pub async fn validate_source_ip(
+        &self,
+        data: &web::Data<AppState>,
+        req: &HttpRequest,
+    ) -> Result<(), ErrorResponse> {
+        let ip = real_ip_from_req(req);
+        if self.is_source_ip_allowed(ip.as_deref()) {
+            return Ok(());
+        }
+
+        warn!(
+            "Token request for client '{}' from not allowed IP '{}'",
+            self.id,
+            ip.as_deref().unwrap_or("unknown")
+        );
+        data.tx_events
+            .send_async(Event::client_ip_rejected(&self.id, ip))
+            .await
+            .unwrap();
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            String::from("Requests for this client are not allowed from this network"),
+        ))
+    }
+
+    fn is_source_ip_allowed(&self, ip: Option<&str>) -> bool {
+        let cidrs = match &self.allowed_cidrs {
+            None => return true,
+            Some(cidrs) => cidrs
+                .split(',')
+                .filter_map(|c| IpCidr::from_str(c).ok())
+                .collect::<Vec<IpCidr>>(),
+        };
+        ip.and_then(|ip| IpAddr::from_str(ip).ok())
+            .map(|ip| is_ip_in_cidrs(&ip, &cidrs))
+            .unwrap_or(false)
+    }
+}
+
 impl Client {
     async fn ephemeral_from_url(value: &str) -> Result<Self, ErrorResponse> {
         let client = HTTP_CLIENT.get_or_init(|| {
@@ -957,6 +1192,15 @@ impl From<EphemeralClientRequest> for Client {
             force_mfa: *EPHEMERAL_CLIENTS_FORCE_MFA,
             client_uri: value.client_uri,
             contacts: value.contacts.map(|c| c.join(",")),
+            allowed_cidrs: None,
+            jwks_uri: None,
+            require_signed_request_object: false,
+            id_token_encrypted_response_alg: None,
+            id_token_encrypted_response_enc: None,
+            userinfo_encrypted_response_alg: None,
+            userinfo_encrypted_response_enc: None,
+            authorization_details_types: None,
+            token_exchange_audiences: None,
         }
     }
 }
@@ -995,6 +1239,15 @@ impl Default for Client {
             force_mfa: false,
             client_uri: None,
             contacts: None,
+            allowed_cidrs: None,
+            jwks_uri: None,
+            require_signed_request_object: false,
+            id_token_encrypted_response_alg: None,
+            id_token_encrypted_response_enc: None,
+            userinfo_encrypted_response_alg: None,
+            userinfo_encrypted_response_enc: None,
+            authorization_details_types: None,
+            token_exchange_audiences: None,
         }
     }
 }
@@ -1018,6 +1271,28 @@ impl From<NewClientRequest> for Client {
 
 impl Client {
     fn try_from_dyn_reg(req: DynamicClientRequest) -> Result<Self, ErrorResponse> {
+        if req.require_signed_request_object == Some(true) && req.jwks_uri.is_none() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Signed request objects can only be required with a 'jwks_uri'".to_string(),
+            ));
+        }
+
+        let (id_token_encrypted_response_alg, id_token_encrypted_response_enc) =
+            jwe::encrypted_response_values(
+                "id_token",
+                req.id_token_encrypted_response_alg,
+                req.id_token_encrypted_response_enc,
+                req.jwks_uri.as_deref(),
+            )?;
+        let (userinfo_encrypted_response_alg, userinfo_encrypted_response_enc) =
+            jwe::encrypted_response_values(
+                "userinfo",
+                req.userinfo_encrypted_response_alg,
+                req.userinfo_encrypted_response_enc,
+                req.jwks_uri.as_deref(),
+            )?;
+
         let id = format!("dyn${}", get_rand(16));
 
         let confidential = req.token_endpoint_auth_method.as_deref() != Some("none");
@@ -1066,6 +1341,16 @@ impl Client {
             force_mfa: false,
             client_uri: req.client_uri,
             contacts: req.contacts.map(|c| c.join(",")),
+            jwks_uri: req.jwks_uri,
+            require_signed_request_object: req.require_signed_request_object.unwrap_or(false),
+            id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc,
+            userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc,
+            authorization_details_types: req
+                .authorization_details_types
+                .filter(|t| !t.is_empty())
+                .map(|t| t.join(",")),
             ..Default::default()
         })
     }
@@ -1146,7 +1431,9 @@ mod tests {
             secret: None,
             secret_kid: None,
             redirect_uris: "".to_string(),
-            post_logout_redirect_uris: None,
+            post_logout_redirect_uris: Some(
+                "https://app.example.com/logout,https://app.example.com/bye/*".to_string(),
+            ),
             allowed_origins: Some("http://localhost:8081,http://localhost:8082".to_string()),
             flows_enabled: "authorization_code,password".to_string(),
             access_token_alg: "EdDSA".to_string(),
@@ -1160,6 +1447,15 @@ mod tests {
             force_mfa: false,
             client_uri: Some("http://localhost:1337".to_string()),
             contacts: Some("batman@localhost.de,@alfred:matrix.org".to_string()),
+            allowed_cidrs: None,
+            jwks_uri: None,
+            require_signed_request_object: false,
+            id_token_encrypted_response_alg: None,
+            id_token_encrypted_response_enc: None,
+            userinfo_encrypted_response_alg: None,
+            userinfo_encrypted_response_enc: None,
+            authorization_details_types: None,
+            token_exchange_audiences: None,
         };
 
         assert_eq!(client.get_access_token_alg().unwrap(), JwkKeyPairAlg::EdDSA);
@@ -1242,6 +1538,26 @@ mod tests {
         assert!(client.validate_flow("blabla").is_err());
         assert!(client.validate_flow("").is_err());
 
+        // post logout redirects
+        assert_eq!(
+            client
+                .post_logout_location("https://app.example.com/logout", None)
+                .unwrap(),
+            "https://app.example.com/logout"
+        );
+        assert_eq!(
+            client
+                .post_logout_location("https://app.example.com/bye/now?a=1", Some("x y"))
+                .unwrap(),
+            "https://app.example.com/bye/now?a=1&state=x+y"
+        );
+        assert!(client
+            .post_logout_location("https://app.example.com/logout/other", None)
+            .is_err());
+        assert!(client
+            .post_logout_location("https://evil.example.com/logout", Some("abc"))
+            .is_err());
+
         // contacts
         assert_eq!(
             client.get_contacts().expect("contacts to be set"),
@@ -1251,6 +1567,22 @@ mod tests {
             ]
         );
 
+        // allowed source networks
+        assert!(client.is_source_ip_allowed(None));
+        assert!(client.is_source_ip_allowed(Some("192.168.1.1")));
+        client.allowed_cidrs = Some("10.0.0.0/8,2001:db8::/32".to_string());
+        assert!(client.is_source_ip_allowed(Some("10.1.2.3")));
+        assert!(client.is_source_ip_allowed(Some("2001:db8::1")));
+        assert!(client.is_source_ip_allowed(Some("::ffff:10.1.2.3")));
+        assert!(!client.is_source_ip_allowed(Some("192.168.1.1")));
+        assert!(!client.is_source_ip_allowed(Some("unknown")));
+        assert!(!client.is_source_ip_allowed(None));
+        assert_eq!(
+            client.get_allowed_cidrs().unwrap(),
+            vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()]
+        );
+        client.allowed_cidrs = None;
+
         // validate origin
         let listen_scheme = ListenScheme::Http;
         let pub_url = "localhost:8080";
@@ -3,11 +3,12 @@ use crate::entity::clients::Client;
 use actix_web::web;
 use chrono::Utc;
 use cryptr::EncValue;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
 use rauthy_common::constants::{
     CACHE_NAME_12HR, CACHE_NAME_CLIENTS_DYN, DYN_CLIENT_SECRET_AUTO_ROTATE,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, FromRow};
 
@@ -68,12 +69,11 @@ impl ClientDyn {
     }
 
     pub async fn find(data: &web::Data<AppState>, id: String) -> Result<Self, ErrorResponse> {
-        if let Some(slf) = cache_get!(
-            Self,
+        if let Some(slf) = cache_get::<Self>(
             CACHE_NAME_12HR.to_string(),
             ClientDyn::get_cache_entry(&id),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -160,12 +160,11 @@ impl ClientDyn {
         data: &web::Data<AppState>,
         ip: String,
     ) -> Result<(), ErrorResponse> {
-        if let Some(ts) = cache_get!(
-            i64,
+        if let Some(ts) = cache_get::<i64>(
             CACHE_NAME_CLIENTS_DYN.to_string(),
             ip.clone(),
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await?
         {
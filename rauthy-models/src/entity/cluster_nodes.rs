@@ -0,0 +1,234 @@
+use crate::app_state::AppState;
+use crate::entity::is_db_alive;
+use actix_web::web;
+use once_cell::sync::Lazy;
+use rauthy_common::constants::{
+    CLUSTER_HEARTBEAT_SECS, CLUSTER_NODE_RETENTION_HOURS, RAUTHY_VERSION,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::get_local_hostname;
+use redhac::QuorumState;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, FromRow};
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, error};
+use utoipa::ToSchema;
+
+/// The startup timestamp of this instance
+static STARTED_AT: Lazy<i64> = Lazy::new(|| OffsetDateTime::now_utc().unix_timestamp());
+
+/// The hostname this instance registers with. Matches the name used to identify the cache member.
+pub static NODE_HOSTNAME: Lazy<String> =
+    Lazy::new(|| env::var("HOSTNAME_OVERWRITE").unwrap_or_else(|_| get_local_hostname()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheRole {
+    /// Non-HA deployment
+    Single,
+    Leader,
+    /// Any HA member, which is not the leader, including members without a quorum
+    Follower,
+}
+
+impl CacheRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Single => "single",
+            Self::Leader => "leader",
+            Self::Follower => "follower",
+        }
+    }
+}
+
+impl Display for CacheRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CacheRole {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "single" => Ok(Self::Single),
+            "leader" => Ok(Self::Leader),
+            "follower" => Ok(Self::Follower),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown CacheRole: {}", s),
+            )),
+        }
+    }
+}
+
+/// A single Rauthy instance, which registered itself in the database.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct ClusterNode {
+    pub hostname: String,
+    pub version: String,
+    pub started_at: i64,
+    pub last_heartbeat: i64,
+    pub db_healthy: bool,
+    pub cache_role: String,
+    pub cache_health: Option<String>,
+    pub cache_connected_hosts: Option<i64>,
+}
+
+// CRUD
+impl ClusterNode {
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as::<_, Self>("select * from cluster_nodes order by hostname")
+            .fetch_all(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    /// Inserts or updates the entry for this node.
+    pub async fn upsert(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        query(
+            r#"insert into cluster_nodes
+            (hostname, version, started_at, last_heartbeat, db_healthy, cache_role, cache_health,
+            cache_connected_hosts)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)
+            on conflict(hostname) do update set
+            version = $2, started_at = $3, last_heartbeat = $4, db_healthy = $5, cache_role = $6,
+            cache_health = $7, cache_connected_hosts = $8"#,
+        )
+        .bind(&self.hostname)
+        .bind(&self.version)
+        .bind(self.started_at)
+        .bind(self.last_heartbeat)
+        .bind(self.db_healthy)
+        .bind(&self.cache_role)
+        .bind(&self.cache_health)
+        .bind(self.cache_connected_hosts)
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes all nodes without any heartbeat since `before`.
+    pub async fn delete_stale(
+        data: &web::Data<AppState>,
+        before: i64,
+    ) -> Result<u64, ErrorResponse> {
+        let res = query("delete from cluster_nodes where last_heartbeat < $1")
+            .bind(before)
+            .execute(&data.db)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+impl ClusterNode {
+    /// Builds the current state of this instance.
+    pub async fn current(data: &web::Data<AppState>) -> Self {
+        let db_healthy = is_db_alive(&data.db).await;
+
+        let (cache_role, cache_health, cache_connected_hosts) =
+            match data.caches.ha_cache_config.rx_health_state.borrow().clone() {
+                None => (CacheRole::Single, None, None),
+                Some(hs) => {
+                    let role = if hs.state == QuorumState::Leader {
+                        CacheRole::Leader
+                    } else {
+                        CacheRole::Follower
+                    };
+                    (
+                        role,
+                        Some(format!("{:?}", hs.health)),
+                        Some(hs.connected_hosts as i64),
+                    )
+                }
+            };
+
+        Self {
+            hostname: NODE_HOSTNAME.clone(),
+            version: RAUTHY_VERSION.to_string(),
+            started_at: *STARTED_AT,
+            last_heartbeat: OffsetDateTime::now_utc().unix_timestamp(),
+            db_healthy,
+            cache_role: cache_role.as_str().to_string(),
+            cache_health,
+            cache_connected_hosts,
+        }
+    }
+
+    pub fn cache_role(&self) -> CacheRole {
+        CacheRole::from_str(&self.cache_role).unwrap_or(CacheRole::Follower)
+    }
+
+    /// A node is stale, if it missed at least 3 heartbeats in a row.
+    pub fn is_stale(&self, now: i64) -> bool {
+        self.last_heartbeat < now - 3 * *CLUSTER_HEARTBEAT_SECS as i64
+    }
+
+    /// `true` if this entry belongs to the instance answering the request.
+    pub fn is_current(&self) -> bool {
+        self.hostname == *NODE_HOSTNAME
+    }
+}
+
+/// Registers this instance in the cluster registry and keeps sending heartbeats in the
+/// `CLUSTER_HEARTBEAT_SECS` interval. Stale nodes are removed after
+/// `CLUSTER_NODE_RETENTION_HOURS`.
+pub async fn heartbeat(data: web::Data<AppState>) {
+    debug!("Cluster node heartbeat started for {}", *NODE_HOSTNAME);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(*CLUSTER_HEARTBEAT_SECS));
+    // A heartbeat, which failed because of the database, cannot be written itself. The failure
+    // is reported with the next heartbeat, which makes it through.
+    let mut db_failed = false;
+
+    loop {
+        interval.tick().await;
+
+        let mut node = ClusterNode::current(&data).await;
+        node.db_healthy &= !db_failed;
+        if let Err(err) = node.upsert(&data).await {
+            error!("Error sending the cluster node heartbeat: {}", err.message);
+            db_failed = true;
+            continue;
+        }
+        db_failed = false;
+
+        // every node may clean up, the query is idempotent
+        let before = node.last_heartbeat - *CLUSTER_NODE_RETENTION_HOURS * 3600;
+        match ClusterNode::delete_stale(&data, before).await {
+            Ok(0) => {}
+            Ok(count) => debug!("Removed {} stale nodes from the cluster registry", count),
+            Err(err) => error!("Error removing stale cluster nodes: {}", err.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_node_stale() {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut node = ClusterNode {
+            hostname: "rauthy-0".to_string(),
+            version: RAUTHY_VERSION.to_string(),
+            started_at: now - 3600,
+            last_heartbeat: now,
+            db_healthy: true,
+            cache_role: CacheRole::Leader.as_str().to_string(),
+            cache_health: Some("Good".to_string()),
+            cache_connected_hosts: Some(2),
+        };
+        assert!(!node.is_stale(now));
+        assert_eq!(node.cache_role(), CacheRole::Leader);
+
+        node.last_heartbeat = now - 3 * *CLUSTER_HEARTBEAT_SECS as i64 - 1;
+        assert!(node.is_stale(now));
+    }
+}
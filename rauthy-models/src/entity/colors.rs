@@ -1,9 +1,11 @@
 use crate::app_state::AppState;
+use crate::cache_invalidation::Invalidation;
 use crate::request::ColorsRequest;
 use actix_web::web;
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::CACHE_NAME_12HR;
 use rauthy_common::error_response::ErrorResponse;
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_put};
+use redhac::cache_put;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use utoipa::ToSchema;
@@ -27,6 +29,9 @@ impl ColorEntity {
             &data.caches.ha_cache_config,
         )
         .await?;
+        Invalidation::Client(client_id.to_string())
+            .publish(data)
+            .await;
 
         Ok(())
     }
@@ -36,12 +41,11 @@ impl ColorEntity {
         client_id: &str,
     ) -> Result<Colors, ErrorResponse> {
         let idx = Self::cache_idx(client_id);
-        if let Some(colors) = cache_get!(
-            Colors,
+        if let Some(colors) = cache_get::<Colors>(
             CACHE_NAME_12HR.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -102,6 +106,9 @@ impl ColorEntity {
             &cols,
         )
         .await?;
+        Invalidation::Client(client_id.to_string())
+            .publish(data)
+            .await;
 
         Ok(())
     }
@@ -112,7 +119,7 @@ impl ColorEntity {
         Colors::from_bytes(self.data.as_slice())
     }
 
-    fn cache_idx(client_id: &str) -> String {
+    pub(crate) fn cache_idx(client_id: &str) -> String {
         format!("colors_{}", client_id)
     }
 }
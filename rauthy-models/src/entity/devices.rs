@@ -2,13 +2,14 @@ use crate::app_state::AppState;
 use crate::entity::refresh_tokens_devices::RefreshTokenDevice;
 use actix_web::web;
 use chrono::{DateTime, Utc};
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::{
     CACHE_NAME_DEVICE_CODES, DEVICE_GRANT_CODE_LIFETIME, DEVICE_GRANT_USER_CODE_LENGTH,
     DEVICE_KEY_LENGTH, PUB_URL_WITH_SCHEME,
 };
 use rauthy_common::error_response::ErrorResponse;
 use rauthy_common::utils::get_rand;
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_put};
+use redhac::cache_put;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, FromRow};
 use std::ops::{Add, Sub};
@@ -188,12 +189,11 @@ impl DeviceAuthCode {
         data: &web::Data<AppState>,
         user_code: String,
     ) -> Result<Option<Self>, ErrorResponse> {
-        match cache_get!(
-            Self,
+        match cache_get::<Self>(
             CACHE_NAME_DEVICE_CODES.to_string(),
             user_code,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await?
         {
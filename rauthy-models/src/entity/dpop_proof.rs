@@ -1,15 +1,19 @@
 use crate::app_state::AppState;
 use crate::entity::jwk::{JWKSPublicKey, JwkKeyPairAlg};
+use crate::entity::used_jtis::UsedJti;
 use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::Uri;
 use actix_web::{http, web, HttpRequest};
 use chrono::{DateTime, Utc};
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::{
-    CACHE_NAME_DPOP_NONCES, DPOP_FORCE_NONCE, DPOP_NONCE_EXP, DPOP_TOKEN_ENDPOINT, RE_TOKEN_68,
-    TOKEN_DPOP,
+    CACHE_NAME_DPOP_NONCES, CLOCK_SKEW_LEEWAY, DPOP_FORCE_NONCE, DPOP_NONCE_EXP,
+    DPOP_TOKEN_ENDPOINT, RE_TOKEN_68, TOKEN_DPOP,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::{base64_url_no_pad_decode, get_rand};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_put};
+use rauthy_common::utils::{base64_url_no_pad_decode, base64_url_no_pad_encode, get_rand};
+use redhac::cache_put;
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, Sub};
 use tracing::error;
@@ -52,12 +56,11 @@ impl DPoPNonce {
 
     /// Checks the validity of the given DPoP nonce value
     pub async fn is_valid(data: &web::Data<AppState>, value: String) -> bool {
-        cache_get!(
-            Self,
+        cache_get::<Self>(
             CACHE_NAME_DPOP_NONCES.to_string(),
             value,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await
         .is_ok()
@@ -66,12 +69,11 @@ impl DPoPNonce {
     /// Always returns the value of the latest valid DPoP nonce which is valid for at least
     /// 15 more seconds or longer.
     pub async fn get_latest(data: &web::Data<AppState>) -> Result<String, ErrorResponse> {
-        if let Some(slf) = cache_get!(
-            Self,
+        if let Some(slf) = cache_get::<Self>(
             CACHE_NAME_DPOP_NONCES.to_string(),
             "latest".to_string(),
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await?
         {
@@ -133,17 +135,15 @@ pub struct DPoPClaims {
     pub htu: String,
     /// Creation timestamp of the JWT (Section 4.1.6 of [RFC7519]).
     pub iat: i64,
-    // The 'ath' claim does not apply to Rauthy, only used by resource servers.
-    // It is just here for completeness.
-    //
-    // Hash of the access token. The value MUST be the result of a
-    // base64url encoding (as defined in Section 2 of [RFC7515])
-    // the SHA-256 [SHS] hash of the ASCII encoding of the associated
-    // access token's value.
-    //
-    // MUST be valid when used in conjunction with an access token
-    // #[serde(skip_serializing_if = "Option::is_none")]
-    // pub ath: Option<String>,
+    /// Hash of the access token. The value MUST be the result of a
+    /// base64url encoding (as defined in Section 2 of [RFC7515])
+    /// the SHA-256 [SHS] hash of the ASCII encoding of the associated
+    /// access token's value.
+    ///
+    /// MUST be valid when used in conjunction with an access token, which only applies to
+    /// Rauthy's own resource endpoints like `/userinfo`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ath: Option<String>,
     /// A recent nonce provided via the DPoP-Nonce HTTP header.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nonce: Option<String>,
@@ -241,6 +241,63 @@ impl DPoPProof {
         }
     }
 
+    /// Validates the mandatory DPoP proof for a request to one of Rauthy's own resource
+    /// endpoints with an access token, which is bound to the key with the given `jkt`.
+    pub async fn validated_for_resource(
+        data: &web::Data<AppState>,
+        req: &HttpRequest,
+        htu: &Uri,
+        access_token: &str,
+        jkt: &str,
+    ) -> Result<Self, ErrorResponse> {
+        let Some(v) = req.headers().get(TOKEN_DPOP) else {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::DPoP(None),
+                "DPoP proof is missing for a DPoP bound access token".to_string(),
+            ));
+        };
+        let b64 = v.to_str()?;
+        if !RE_TOKEN_68.is_match(b64) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::DPoP(None),
+                "DPoP header must be in Token68 format".to_string(),
+            ));
+        }
+
+        let slf = Self::try_from_str(None, b64)?;
+        if let Err(msg) = slf
+            .validate_for(b64, req.method().as_str(), htu)
+            .and_then(|_| slf.validate_access_token(access_token, jkt))
+        {
+            return Err(ErrorResponse::new(ErrorResponseType::DPoP(None), msg));
+        }
+        if let Err(nonce) = slf.validate_nonce(data).await {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::UseDpopNonce((None, nonce)),
+                "DPoP 'nonce' is required in DPoP proof".to_string(),
+            ));
+        }
+
+        // A proof for a resource request is not bound to a single-use nonce, which makes it
+        // replayable until its 'iat' is out of range. Each 'jti' may only be used once per key.
+        let exp = slf.claims.iat + 60 + *CLOCK_SKEW_LEEWAY as i64;
+        let scope = format!("dpop_{}", jkt);
+        if !UsedJti::try_consume(data, &scope, &slf.claims.jti, exp).await? {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::DPoP(None),
+                "The DPoP proof has been used already".to_string(),
+            ));
+        }
+
+        Ok(slf)
+    }
+
+    /// Validates the proof for a request against the token endpoint.
+    #[inline(always)]
+    pub fn validate(&self, raw_token: &str) -> Result<(), String> {
+        self.validate_for(raw_token, http::Method::POST.as_str(), &DPOP_TOKEN_ENDPOINT)
+    }
+
     /// Checking DPoP Proofs
     ///
     /// To validate a DPoP proof, the receiving server MUST ensure the following:
@@ -267,7 +324,7 @@ impl DPoPProof {
     /// - ensure that the value of the ath claim equals the hash of that access token, and
     /// - confirm that the public key to which the access token is bound matches the
     ///   public key from the DPoP proof.
-    pub fn validate(&self, raw_token: &str) -> Result<(), String> {
+    pub fn validate_for(&self, raw_token: &str, htm: &str, htu: &Uri) -> Result<(), String> {
         // 1. we do not need to validate that there is only one head field with DPoP since
         // actix serializes into a HashMap which implies this anyway
 
@@ -296,13 +353,13 @@ impl DPoPProof {
         // TODO ?
 
         // 8. The htm claim matches the HTTP method of the current request.
-        if self.claims.htm.as_str() != http::Method::POST.as_str() {
-            return Err("The 'htm' claim from the DPoP header != POST".to_string());
+        if self.claims.htm.as_str() != htm {
+            return Err(format!("The 'htm' claim from the DPoP header != {}", htm));
         }
 
         // 9. The htu claim matches the HTTP URI value for the HTTP request in
         // which the JWT was received, ignoring any query and fragment parts.
-        if self.claims.htu != DPOP_TOKEN_ENDPOINT.to_string() {
+        if self.claims.htu != htu.to_string() {
             return Err("Invalid 'htu' claim".to_string());
         }
 
@@ -316,18 +373,32 @@ impl DPoPProof {
         // claim or a server managed timestamp via the nonce claim, is within an
         // acceptable window (see Section 11.1).
         //
-        // We will accept an 'iat' of 1 minute old and it must not be in the future
+        // We will accept an 'iat' of 1 minute old and it must not be in the future,
+        // both extended by the allowed clock skew
         let now = Utc::now().timestamp();
-        let now_minus_1 = now - 60;
-        if self.claims.iat < now_minus_1 || self.claims.iat > now {
+        let leeway = *CLOCK_SKEW_LEEWAY as i64;
+        if self.claims.iat < now - 60 - leeway || self.claims.iat > now + leeway {
             return Err("DPoP 'iat' claim is out of range".to_string());
         }
 
         // 12. If presented to a protected resource in conjunction with an access token:
-        // - ensure that the value of the ath claim equals the hash of that access token, and
-        // - confirm that the public key to which the access token is bound matches the
-        //   public key from the DPoP proof.
-        // TODO does this even apply to Rauthy? Maybe when refreshing? -> double check
+        // -> Self::validate_access_token()
+
+        Ok(())
+    }
+
+    /// Makes sure that the `ath` claim matches the given access token and that the token is
+    /// bound to the key of this proof.
+    pub fn validate_access_token(&self, access_token: &str, jkt: &str) -> Result<(), String> {
+        let hash = digest::digest(&digest::SHA256, access_token.as_bytes());
+        let ath = base64_url_no_pad_encode(hash.as_ref());
+        if self.claims.ath.as_deref() != Some(ath.as_str()) {
+            return Err("DPoP 'ath' claim does not match the access token".to_string());
+        }
+
+        if self.jwk_fingerprint().map_err(|err| err.message)? != jkt {
+            return Err("The access token is bound to another DPoP key".to_string());
+        }
 
         Ok(())
     }
@@ -401,6 +472,7 @@ mod tests {
             htm: http::Method::POST.to_string(),
             htu: DPOP_TOKEN_ENDPOINT.clone().to_string(),
             iat: Utc::now().timestamp(),
+            ath: None,
             nonce: None,
         };
 
@@ -454,6 +526,7 @@ mod tests {
             htm: http::Method::POST.to_string(),
             htu: DPOP_TOKEN_ENDPOINT.clone().to_string(),
             iat: Utc::now().timestamp(),
+            ath: None,
             nonce: None,
         };
 
@@ -480,4 +553,45 @@ mod tests {
         // jwk::test_signature_validation
         // -> no need to test it again here
     }
+
+    #[test]
+    fn test_dpop_validate_access_token() {
+        let kp = ed25519_compact::KeyPair::generate();
+        let access_token = "eyJhbGciOiJFZERTQSJ9.eyJzdWIiOiJ0ZXN0In0.c2ln";
+        let hash = ring::digest::digest(&ring::digest::SHA256, access_token.as_bytes());
+
+        let mut dpop = DPoPProof {
+            header: DPoPHeader {
+                typ: "dpop+jwt".to_string(),
+                alg: JwkKeyPairAlg::EdDSA,
+                jwk: JWKSPublicKey {
+                    kty: JwkKeyPairType::OKP,
+                    alg: Some(JwkKeyPairAlg::EdDSA),
+                    crv: Some("Ed25519".to_string()),
+                    kid: None,
+                    n: None,
+                    e: None,
+                    x: Some(base64_url_encode(kp.pk.as_slice())),
+                },
+                kid: None,
+            },
+            claims: DPoPClaims {
+                jti: "-BwC3ESc6acc2lTc".to_string(),
+                htm: http::Method::GET.to_string(),
+                htu: DPOP_TOKEN_ENDPOINT.clone().to_string(),
+                iat: Utc::now().timestamp(),
+                ath: Some(base64_url_no_pad_encode(hash.as_ref())),
+                nonce: None,
+            },
+            signature: Vec::default(),
+        };
+        let jkt = dpop.jwk_fingerprint().unwrap();
+
+        dpop.validate_access_token(access_token, &jkt).unwrap();
+        assert!(dpop.validate_access_token(access_token, "other").is_err());
+        assert!(dpop.validate_access_token("other.token.sig", &jkt).is_err());
+
+        dpop.claims.ath = None;
+        assert!(dpop.validate_access_token(access_token, &jkt).is_err());
+    }
 }
@@ -0,0 +1,138 @@
+use crate::app_state::AppState;
+use crate::entity::users::User;
+use crate::pii;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::PII_ENCRYPTION_EMAIL;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// An additional E-Mail address, which can be used to log in as the user it belongs to.
+///
+/// Aliases share the same namespace as the users E-Mails. An address can either be the E-Mail
+/// of a user or an alias, but never both.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct EmailAlias {
+    pub email: String,
+    pub user_id: String,
+    pub created: i64,
+}
+
+impl EmailAlias {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        email: String,
+    ) -> Result<Self, ErrorResponse> {
+        let email = email.to_lowercase();
+        if User::find_by_email(data, email.clone()).await.is_ok()
+            || Self::find(data, &email).await?.is_some()
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "E-Mail is already in use".to_string(),
+            ));
+        }
+
+        let slf = Self {
+            email,
+            user_id,
+            created: Utc::now().timestamp(),
+        };
+        let email_enc = pii::encrypt_email(&slf.email)?;
+        sqlx::query!(
+            "insert into email_aliases (email, user_id, created) values ($1, $2, $3)",
+            email_enc,
+            slf.user_id,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        email: &str,
+    ) -> Result<(), ErrorResponse> {
+        let email = pii::encrypt_email(&email.to_lowercase())?;
+        let res = sqlx::query!(
+            "delete from email_aliases where email = $1 and user_id = $2",
+            email,
+            user_id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "E-Mail alias not found".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn find(
+        data: &web::Data<AppState>,
+        email: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let email = pii::encrypt_email(email)?;
+        let res = sqlx::query_as!(Self, "select * from email_aliases where email = $1", email)
+            .fetch_optional(&data.db)
+            .await?;
+        res.map(Self::email_dec).transpose()
+    }
+
+    pub async fn find_for_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from email_aliases where user_id = $1 order by created asc",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        res.into_iter().map(Self::email_dec).collect()
+    }
+
+    /// Encrypts or decrypts all stored aliases to match `PII_ENCRYPTION_EMAIL` and returns the
+    /// amount of modified rows.
+    pub async fn convert_emails(data: &web::Data<AppState>) -> Result<usize, ErrorResponse> {
+        let rows = if *PII_ENCRYPTION_EMAIL {
+            sqlx::query_as::<_, (String,)>(
+                "select email from email_aliases where email not like $1",
+            )
+            .bind(format!("{}%", pii::email_prefix_active()))
+        } else {
+            sqlx::query_as::<_, (String,)>(
+                "select email from email_aliases where email like 'denc:%'",
+            )
+        }
+        .fetch_all(&data.db)
+        .await?;
+
+        let mut modified = 0;
+        for (email,) in rows {
+            if let Some(converted) = pii::convert_email(&email)? {
+                sqlx::query("update email_aliases set email = $1 where email = $2")
+                    .bind(converted)
+                    .bind(email)
+                    .execute(&data.db)
+                    .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+
+    fn email_dec(mut self) -> Result<Self, ErrorResponse> {
+        self.email = pii::decrypt_email(self.email)?;
+        Ok(self)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::app_state::AppState;
+use crate::email::{EMail, SendError};
+use crate::entity::jobs::Job;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::JOB_MAX_ATTEMPTS;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, FromRow};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailDeliveryStatus {
+    /// Accepted by one of the SMTP relays
+    Sent,
+    /// Either the last attempt failed and will be retried, or the relay reported a deferral
+    Deferred,
+    /// Reported as bounced by the relay via the webhook
+    Bounced,
+    /// Failed `JOB_MAX_ATTEMPTS` times and will not be retried
+    Failed,
+}
+
+impl EmailDeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Deferred => "deferred",
+            Self::Bounced => "bounced",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl Display for EmailDeliveryStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for EmailDeliveryStatus {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(Self::Sent),
+            "deferred" => Ok(Self::Deferred),
+            "bounced" => Ok(Self::Bounced),
+            "failed" => Ok(Self::Failed),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown EmailDeliveryStatus: {}", s),
+            )),
+        }
+    }
+}
+
+/// The delivery status of a single E-Mail. The `id` is the same as the id of the E-Mail job
+/// and the local part of the `Message-ID` header.
+#[derive(Debug, Clone, FromRow)]
+pub struct EmailDelivery {
+    pub id: String,
+    pub address: String,
+    pub subject: String,
+    /// The SMTP relay, which accepted the E-Mail
+    pub relay: Option<String>,
+    pub status: String,
+    pub attempts: i64,
+    pub error: Option<String>,
+    pub created: i64,
+    pub updated: i64,
+}
+
+/// The amount of deliveries with a specific status over a single relay.
+#[derive(Debug)]
+pub struct EmailDeliveryCount {
+    pub relay: Option<String>,
+    pub status: String,
+    pub count: i64,
+}
+
+impl EmailDelivery {
+    /// Records the result of a send attempt for an E-Mail job. `res` contains the relay, which
+    /// accepted the E-Mail, or the error of the last relay tried.
+    pub async fn record(
+        data: &web::Data<AppState>,
+        job: &Job,
+        email: &EMail,
+        res: &Result<&str, SendError>,
+    ) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let attempts = job.attempts + 1;
+        let (status, relay, error) = match res {
+            Ok(relay) => (EmailDeliveryStatus::Sent, Some(*relay), None),
+            Err(err) if err.permanent || attempts >= *JOB_MAX_ATTEMPTS => (
+                EmailDeliveryStatus::Failed,
+                None,
+                Some(err.message.as_str()),
+            ),
+            Err(err) => (
+                EmailDeliveryStatus::Deferred,
+                None,
+                Some(err.message.as_str()),
+            ),
+        };
+        let status = status.as_str();
+
+        query!(
+            r#"INSERT INTO email_deliveries
+            (id, address, subject, relay, status, attempts, error, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+            ON CONFLICT (id) DO UPDATE
+            SET relay = $4, status = $5, attempts = $6, error = $7, updated = $8"#,
+            job.id,
+            email.address,
+            email.subject,
+            relay,
+            status,
+            attempts,
+            error,
+            now,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Updates the status of an already sent E-Mail, which has been reported by a relay.
+    pub async fn update_status(
+        data: &web::Data<AppState>,
+        id: &str,
+        status: EmailDeliveryStatus,
+        error: Option<String>,
+    ) -> Result<(), ErrorResponse> {
+        let status = status.as_str();
+        let now = Utc::now().timestamp();
+
+        let res = query!(
+            "UPDATE email_deliveries SET status = $1, error = $2, updated = $3 WHERE id = $4",
+            status,
+            error,
+            now,
+            id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Unknown E-Mail delivery".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Counts all deliveries, which have been updated since the given timestamp, grouped by
+    /// relay and status.
+    pub async fn count_since(
+        data: &web::Data<AppState>,
+        since: i64,
+    ) -> Result<Vec<EmailDeliveryCount>, ErrorResponse> {
+        let res = query_as!(
+            EmailDeliveryCount,
+            r#"SELECT relay, status, COUNT(*) AS "count!: i64" FROM email_deliveries
+            WHERE updated >= $1
+            GROUP BY relay, status"#,
+            since,
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the latest deliveries, which have not been sent successfully, newest first.
+    pub async fn find_recent_failures(
+        data: &web::Data<AppState>,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let sent = EmailDeliveryStatus::Sent.as_str();
+        let res = query_as!(
+            Self,
+            r#"SELECT * FROM email_deliveries
+            WHERE status != $1
+            ORDER BY updated DESC
+            LIMIT $2"#,
+            sent,
+            limit,
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Deletes all deliveries, which have not been updated since the given timestamp.
+    pub async fn delete_before(
+        data: &web::Data<AppState>,
+        updated: i64,
+    ) -> Result<u64, ErrorResponse> {
+        let res = query!("DELETE FROM email_deliveries WHERE updated < $1", updated)
+            .execute(&data.db)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Extracts the delivery id from a `Message-ID` like `<id@domain>`.
+    pub fn id_from_message_id(message_id: &str) -> Option<&str> {
+        let id = message_id
+            .trim()
+            .trim_start_matches('<')
+            .split_once('@')
+            .map(|(id, _)| id)?;
+        if id.is_empty() {
+            None
+        } else {
+            Some(id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_from_message_id() {
+        assert_eq!(
+            EmailDelivery::id_from_message_id("<Ab12Cd34@localhost.de>"),
+            Some("Ab12Cd34")
+        );
+        assert_eq!(
+            EmailDelivery::id_from_message_id(" Ab12Cd34@localhost.de "),
+            Some("Ab12Cd34")
+        );
+        assert_eq!(EmailDelivery::id_from_message_id("<@localhost.de>"), None);
+        assert_eq!(EmailDelivery::id_from_message_id("Ab12Cd34"), None);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::app_state::AppState;
+use crate::email::send_email_mfa;
+use crate::entity::sessions::Session;
+use crate::entity::users::User;
+use crate::{AuthStep, AuthStepAwaitEmailMfa};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::web;
+use chrono::Utc;
+use rand::Rng;
+use rauthy_common::constants::{
+    EMAIL_MFA_CODE_LENGTH, EMAIL_MFA_CODE_LIFETIME, EMAIL_MFA_LOCKOUT, EMAIL_MFA_MAX_ATTEMPTS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_url_no_pad_encode, get_rand};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailMfaPurpose {
+    /// The 2nd factor during a login
+    Login,
+    /// A fresh re-authentication for a session elevation
+    Elevate,
+}
+
+impl EmailMfaPurpose {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Login => "login",
+            Self::Elevate => "elevate",
+        }
+    }
+}
+
+impl From<String> for EmailMfaPurpose {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "login" => Self::Login,
+            // unknown values must never finish a login
+            _ => Self::Elevate,
+        }
+    }
+}
+
+/// A one-time code, which is sent via E-Mail as a fallback second factor for users, who cannot
+/// use passkeys. Only a hash of the code is stored, and it is bound to the session it has been
+/// requested for. Wrong inputs are counted per user, and after `EMAIL_MFA_MAX_ATTEMPTS`, all codes
+/// of the user are deleted and new ones are refused for `EMAIL_MFA_LOCKOUT`.
+///
+/// The codes are delivered via the same channel as a password reset. This is why they never
+/// mark a session as MFA, which means they do not satisfy `ADMIN_FORCE_MFA` or a client's
+/// `force_mfa`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailMfaCode {
+    pub id: String,
+    pub user_id: String,
+    pub session_id: String,
+    pub purpose: EmailMfaPurpose,
+    pub otp_hash: String,
+    /// The location with the authorization code after a successful login
+    pub header_loc: Option<String>,
+    pub header_origin: Option<String>,
+    pub exp: i64,
+}
+
+/// The failed E-Mail MFA attempts of a user across all of its codes.
+#[derive(Debug, Clone, FromRow)]
+struct EmailMfaFailures {
+    attempts: i64,
+    locked_until: Option<i64>,
+}
+
+impl EmailMfaCode {
+    /// Creates a new code and returns it together with the plain OTP, which must be sent to the
+    /// user. Any other code for the same session is replaced.
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        session_id: String,
+        purpose: EmailMfaPurpose,
+        header_loc: Option<String>,
+        header_origin: Option<String>,
+    ) -> Result<(Self, String), ErrorResponse> {
+        Self::check_lockout(data, &user_id).await?;
+
+        sqlx::query!(
+            "DELETE FROM email_mfa_codes WHERE session_id = $1",
+            session_id
+        )
+        .execute(&data.db)
+        .await?;
+
+        let id = get_rand(48);
+        let otp = Self::new_otp();
+        let slf = Self {
+            otp_hash: Self::hash(&id, &otp),
+            id,
+            user_id,
+            session_id,
+            purpose,
+            header_loc,
+            header_origin,
+            exp: Utc::now().timestamp() + *EMAIL_MFA_CODE_LIFETIME as i64 * 60,
+        };
+
+        let purpose = slf.purpose.as_str();
+        sqlx::query!(
+            r#"INSERT INTO email_mfa_codes
+            (id, user_id, session_id, purpose, otp_hash, header_loc, header_origin, exp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            slf.id,
+            slf.user_id,
+            slf.session_id,
+            purpose,
+            slf.otp_hash,
+            slf.header_loc,
+            slf.header_origin,
+            slf.exp,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok((slf, otp))
+    }
+
+    /// Sends a new login code to the user and returns the step, which awaits it. The
+    /// `header_loc` is only released after the code has been verified.
+    pub async fn await_login(
+        data: &web::Data<AppState>,
+        user: &User,
+        session: &Session,
+        header_loc: String,
+        header_origin: Option<(HeaderName, HeaderValue)>,
+        has_password_been_hashed: bool,
+    ) -> Result<AuthStep, ErrorResponse> {
+        let (slf, otp) = Self::create(
+            data,
+            user.id.clone(),
+            session.id.clone(),
+            EmailMfaPurpose::Login,
+            Some(header_loc),
+            header_origin
+                .as_ref()
+                .map(|h| h.1.to_str().unwrap().to_string()),
+        )
+        .await?;
+        send_email_mfa(data, user, &otp).await;
+
+        Ok(AuthStep::AwaitEmailMfa(AuthStepAwaitEmailMfa {
+            has_password_been_hashed,
+            code: slf.id,
+            header_csrf: Session::get_csrf_header(&session.csrf_token),
+            header_origin,
+            exp: slf.exp,
+        }))
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, user_id, session_id, purpose, otp_hash, header_loc, header_origin, exp
+            FROM email_mfa_codes WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+
+        res.ok_or_else(Self::err_invalid)
+    }
+
+    pub async fn delete(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        sqlx::query!("DELETE FROM email_mfa_codes WHERE id = $1", self.id)
+            .execute(&data.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired(data: &web::Data<AppState>) -> Result<u64, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = sqlx::query!("DELETE FROM email_mfa_codes WHERE exp < $1", now)
+            .execute(&data.db)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM email_mfa_failures WHERE locked_until < $1",
+            now
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Validates the given OTP and consumes the code on success. A wrong input counts as a
+    /// failed attempt for the user and locks out E-Mail MFA, when the max attempts are reached.
+    pub async fn verify(
+        self,
+        data: &web::Data<AppState>,
+        session_id: &str,
+        purpose: EmailMfaPurpose,
+        otp: &str,
+    ) -> Result<Self, ErrorResponse> {
+        if self.session_id != session_id
+            || self.purpose != purpose
+            || self.exp < Utc::now().timestamp()
+        {
+            return Err(Self::err_invalid());
+        }
+        Self::check_lockout(data, &self.user_id).await?;
+
+        if self.is_valid_otp(otp) {
+            self.delete(data).await?;
+            sqlx::query!(
+                "DELETE FROM email_mfa_failures WHERE user_id = $1",
+                self.user_id
+            )
+            .execute(&data.db)
+            .await?;
+            return Ok(self);
+        }
+
+        Self::add_failure(data, &self.user_id).await?;
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "Invalid E-Mail code".to_string(),
+        ))
+    }
+
+    /// Returns an error, if the user is currently locked out after too many wrong codes.
+    async fn check_lockout(data: &web::Data<AppState>, user_id: &str) -> Result<(), ErrorResponse> {
+        let failures = sqlx::query_as!(
+            EmailMfaFailures,
+            "SELECT attempts, locked_until FROM email_mfa_failures WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+
+        match failures.and_then(|f| f.locked_until) {
+            Some(ts) if ts > Utc::now().timestamp() => {
+                debug!("E-Mail MFA for user {} is locked until {}", user_id, ts);
+                Err(ErrorResponse::new(
+                    ErrorResponseType::TooManyRequests(ts),
+                    "Too many invalid E-Mail codes, please try again later".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts a failed attempt for the user. The counter is increased inside the database to
+    /// not lose any attempts from parallel requests.
+    async fn add_failure(data: &web::Data<AppState>, user_id: &str) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+        // an expired lockout starts a fresh count
+        let failures: EmailMfaFailures = sqlx::query_as(
+            r#"INSERT INTO email_mfa_failures (user_id, attempts) VALUES ($1, 1)
+            ON CONFLICT(user_id) DO UPDATE SET
+                attempts = CASE WHEN email_mfa_failures.locked_until < $2
+                    THEN 1 ELSE email_mfa_failures.attempts + 1 END,
+                locked_until = CASE WHEN email_mfa_failures.locked_until < $2
+                    THEN NULL ELSE email_mfa_failures.locked_until END
+            RETURNING attempts, locked_until"#,
+        )
+        .bind(user_id)
+        .bind(now)
+        .fetch_one(&data.db)
+        .await?;
+
+        if failures.attempts >= *EMAIL_MFA_MAX_ATTEMPTS && failures.locked_until.is_none() {
+            warn!(
+                "Max attempts for E-Mail MFA reached for user {} - locking it out",
+                user_id
+            );
+            let locked_until = now + *EMAIL_MFA_LOCKOUT as i64 * 60;
+            sqlx::query!(
+                "UPDATE email_mfa_failures SET locked_until = $1 WHERE user_id = $2",
+                locked_until,
+                user_id
+            )
+            .execute(&data.db)
+            .await?;
+            sqlx::query!("DELETE FROM email_mfa_codes WHERE user_id = $1", user_id)
+                .execute(&data.db)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl EmailMfaCode {
+    fn new_otp() -> String {
+        let mut rng = rand::thread_rng();
+        (0..*EMAIL_MFA_CODE_LENGTH)
+            .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+            .collect()
+    }
+
+    /// The id is used as a salt, so that the same OTP never results in the same hash.
+    fn hash(id: &str, otp: &str) -> String {
+        let hash = digest::digest(&digest::SHA256, format!("{}{}", id, otp).as_bytes());
+        base64_url_no_pad_encode(hash.as_ref())
+    }
+
+    fn is_valid_otp(&self, otp: &str) -> bool {
+        Self::hash(&self.id, otp.trim()) == self.otp_hash
+    }
+
+    fn err_invalid() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid or expired E-Mail code".to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_mfa_otp() {
+        let otp = EmailMfaCode::new_otp();
+        assert_eq!(otp.len(), *EMAIL_MFA_CODE_LENGTH);
+        assert!(otp.chars().all(|c| c.is_ascii_digit()));
+
+        let id = get_rand(48);
+        let code = EmailMfaCode {
+            otp_hash: EmailMfaCode::hash(&id, &otp),
+            id,
+            user_id: "user_1".to_string(),
+            session_id: "session_1".to_string(),
+            purpose: EmailMfaPurpose::Login,
+            header_loc: None,
+            header_origin: None,
+            exp: Utc::now().timestamp() + 60,
+        };
+        assert!(code.is_valid_otp(&otp));
+        assert!(code.is_valid_otp(&format!(" {} ", otp)));
+        assert!(!code.is_valid_otp("abc"));
+
+        // the same otp for another id must result in another hash
+        assert_ne!(EmailMfaCode::hash("other", &otp), code.otp_hash);
+
+        assert_eq!(
+            EmailMfaPurpose::from("something".to_string()),
+            EmailMfaPurpose::Elevate
+        );
+    }
+}
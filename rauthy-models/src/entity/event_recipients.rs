@@ -0,0 +1,212 @@
+use crate::app_state::{AppState, DbPool};
+use crate::entity::login_policies::{local_time, parse_hh_mm, parse_timezone, parse_utc_offset};
+use crate::events::event::EventLevel;
+use crate::events::notifier::EventNotifier;
+use crate::request::EventRecipientRequest;
+use actix_web::web;
+use chrono::{DateTime, Timelike, Utc};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+
+/// An E-Mail recipient for event notifications, which can be managed at runtime.
+///
+/// These are sent in addition to the static `EVENT_EMAIL` from the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecipient {
+    pub email: String,
+    /// The minimum `EventLevel::value()` to notify about
+    pub level: i16,
+    /// Start of the quiet hours in minutes since midnight in local time
+    pub quiet_from: Option<i64>,
+    /// End of the quiet hours in minutes since midnight in local time, exclusive.
+    /// If it is smaller than `quiet_from`, the quiet hours span over midnight.
+    pub quiet_to: Option<i64>,
+    /// The UTC offset of the local time in minutes, only used without a `timezone`
+    pub utc_offset: i64,
+    /// The IANA time zone of the local time, which follows daylight saving time
+    pub timezone: Option<String>,
+}
+
+// CRUD
+impl EventRecipient {
+    pub async fn find_all(db: &DbPool) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query!("select data from config where id = 'event_recipients'")
+            .fetch_optional(db)
+            .await?;
+
+        match res.and_then(|row| row.data) {
+            Some(bytes) => Ok(bincode::deserialize::<Vec<Self>>(&bytes)?),
+            None => Ok(Vec::default()),
+        }
+    }
+
+    /// Replaces all recipients and applies them to the local event notifier immediately.
+    /// Other instances in a HA deployment pick up the changes with their next reload.
+    pub async fn save_all(
+        data: &web::Data<AppState>,
+        recipients: Vec<Self>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let bytes = bincode::serialize(&recipients)?;
+
+        #[cfg(not(feature = "postgres"))]
+        let q = query!(
+            "insert or replace into config (id, data) values ('event_recipients', $1)",
+            bytes,
+        );
+        #[cfg(feature = "postgres")]
+        let q = query!(
+            r#"insert into config (id, data) values ('event_recipients', $1)
+            on conflict(id) do update set data = $1"#,
+            bytes,
+        );
+        q.execute(&data.db).await?;
+
+        EventNotifier::set_email_recipients(recipients.clone());
+        Ok(recipients)
+    }
+}
+
+impl EventRecipient {
+    pub fn from_req(req: EventRecipientRequest) -> Result<Self, ErrorResponse> {
+        let (quiet_from, quiet_to) = match (req.quiet_from, req.quiet_to) {
+            (Some(from), Some(to)) => (Some(parse_hh_mm(&from)?), Some(parse_hh_mm(&to)?)),
+            (None, None) => (None, None),
+            _ => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "quiet_from and quiet_to must be given together".to_string(),
+                ));
+            }
+        };
+
+        let (utc_offset, timezone) = match (req.timezone, req.utc_offset) {
+            (Some(tz), _) => (0, Some(parse_timezone(&tz)?.name().to_string())),
+            (None, Some(offset)) => (parse_utc_offset(&offset)?, None),
+            (None, None) => (0, None),
+        };
+
+        Ok(Self {
+            email: req.email.to_lowercase(),
+            level: req.level.value(),
+            quiet_from,
+            quiet_to,
+            utc_offset,
+            timezone,
+        })
+    }
+
+    /// Returns `true` if an event with the given level should be sent to this recipient.
+    /// Critical events and test events are delivered during quiet hours as well.
+    pub fn wants(&self, level: &EventLevel, is_test: bool, now: DateTime<Utc>) -> bool {
+        if is_test {
+            return true;
+        }
+        if level.value() < self.level {
+            return false;
+        }
+        *level == EventLevel::Critical || !self.is_quiet(now)
+    }
+
+    fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        let (Some(from), Some(to)) = (self.quiet_from, self.quiet_to) else {
+            return false;
+        };
+        let local = local_time(now, self.timezone.as_deref(), self.utc_offset);
+        let minute = (local.hour() * 60 + local.minute()) as i64;
+
+        if from == to {
+            false
+        } else if from < to {
+            minute >= from && minute < to
+        } else {
+            minute >= from || minute < to
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_event_recipient_wants() {
+        let recipient = EventRecipient::from_req(EventRecipientRequest {
+            email: "Admin@Localhost.de".to_string(),
+            level: EventLevel::Notice,
+            quiet_from: Some("22:00".to_string()),
+            quiet_to: Some("07:00".to_string()),
+            utc_offset: Some("+02:00".to_string()),
+            timezone: None,
+        })
+        .unwrap();
+        assert_eq!(recipient.email, "admin@localhost.de");
+
+        // 12:00 local
+        let day = Utc.with_ymd_and_hms(2024, 6, 3, 10, 0, 0).unwrap();
+        assert!(!recipient.wants(&EventLevel::Info, false, day));
+        assert!(recipient.wants(&EventLevel::Notice, false, day));
+        assert!(recipient.wants(&EventLevel::Info, true, day));
+
+        // 23:30 and 06:59 local
+        for night in [
+            Utc.with_ymd_and_hms(2024, 6, 3, 21, 30, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 6, 4, 4, 59, 0).unwrap(),
+        ] {
+            assert!(!recipient.wants(&EventLevel::Warning, false, night));
+            assert!(recipient.wants(&EventLevel::Critical, false, night));
+        }
+
+        // 07:00 local
+        let morning = Utc.with_ymd_and_hms(2024, 6, 4, 5, 0, 0).unwrap();
+        assert!(recipient.wants(&EventLevel::Warning, false, morning));
+
+        assert!(EventRecipient::from_req(EventRecipientRequest {
+            email: "admin@localhost.de".to_string(),
+            level: EventLevel::Notice,
+            quiet_from: Some("22:00".to_string()),
+            quiet_to: None,
+            utc_offset: None,
+            timezone: None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_event_recipient_timezone() {
+        let recipient = EventRecipient::from_req(EventRecipientRequest {
+            email: "admin@localhost.de".to_string(),
+            level: EventLevel::Info,
+            quiet_from: Some("22:00".to_string()),
+            quiet_to: Some("07:00".to_string()),
+            // the time zone wins over a given offset
+            utc_offset: Some("+05:00".to_string()),
+            timezone: Some("Europe/Berlin".to_string()),
+        })
+        .unwrap();
+        assert_eq!(recipient.timezone.as_deref(), Some("Europe/Berlin"));
+
+        // 21:30 UTC is 23:30 CEST in summer and 22:30 CET in winter
+        let summer = Utc.with_ymd_and_hms(2024, 7, 1, 21, 30, 0).unwrap();
+        let winter = Utc.with_ymd_and_hms(2024, 1, 15, 21, 30, 0).unwrap();
+        assert!(!recipient.wants(&EventLevel::Warning, false, summer));
+        assert!(!recipient.wants(&EventLevel::Warning, false, winter));
+
+        // 05:30 UTC is 07:30 CEST in summer, but still 06:30 CET in winter
+        let summer = Utc.with_ymd_and_hms(2024, 7, 2, 5, 30, 0).unwrap();
+        let winter = Utc.with_ymd_and_hms(2024, 1, 16, 5, 30, 0).unwrap();
+        assert!(recipient.wants(&EventLevel::Warning, false, summer));
+        assert!(!recipient.wants(&EventLevel::Warning, false, winter));
+
+        assert!(EventRecipient::from_req(EventRecipientRequest {
+            email: "admin@localhost.de".to_string(),
+            level: EventLevel::Info,
+            quiet_from: None,
+            quiet_to: None,
+            utc_offset: None,
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use crate::app_state::AppState;
+use crate::entity::user_attr::UserAttrValueEntity;
+use crate::entity::users::User;
+use crate::request::FeatureFlagRequest;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_12HR, IDX_FEATURE_FLAGS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::new_store_id;
+use redhac::{cache_insert, AckLevel};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A feature flag for dependent apps, which is evaluated per user.
+///
+/// A disabled flag is always off. An enabled flag without any targeting rules is on for
+/// everyone. Otherwise, it is on as soon as one of the rules matches:
+/// - the user is a member of one of the `groups`
+/// - the user attribute `attr_key` has the value `attr_value`
+/// - the user falls into the rollout `percentage`, which is stable for each user and flag
+///
+/// If `client_ids` is set, the flag will only be evaluated for these clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub enabled: bool,
+    pub percentage: Option<i64>,
+    pub groups: Option<String>,
+    pub attr_key: Option<String>,
+    pub attr_value: Option<String>,
+    pub client_ids: Option<String>,
+    pub created: i64,
+}
+
+// CRUD
+impl FeatureFlag {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        req: FeatureFlagRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut all = Self::find_all(data).await?;
+        if all.iter().any(|f| f.name == req.name) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A feature flag with this name already exists".to_string(),
+            ));
+        }
+
+        let mut slf = Self {
+            id: new_store_id(),
+            name: String::default(),
+            description: None,
+            enabled: false,
+            percentage: None,
+            groups: None,
+            attr_key: None,
+            attr_value: None,
+            client_ids: None,
+            created: Utc::now().timestamp(),
+        };
+        slf.apply_req(req)?;
+
+        sqlx::query!(
+            r#"INSERT INTO feature_flags
+            (id, name, description, enabled, percentage, groups, attr_key, attr_value, client_ids,
+            created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+            slf.id,
+            slf.name,
+            slf.description,
+            slf.enabled,
+            slf.percentage,
+            slf.groups,
+            slf.attr_key,
+            slf.attr_value,
+            slf.client_ids,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        all.push(slf.clone());
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!("DELETE FROM feature_flags WHERE id = $1", id)
+            .execute(&data.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Self::err_not_found());
+        }
+
+        let all = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|f| f.id != id)
+            .collect::<Vec<Self>>();
+        Self::update_cache(data, all).await?;
+
+        Ok(())
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = cache_get::<Vec<Self>>(
+            CACHE_NAME_12HR.to_string(),
+            IDX_FEATURE_FLAGS.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?;
+        if let Some(res) = res {
+            return Ok(res);
+        }
+
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, name, description, enabled, percentage, groups, attr_key, attr_value,
+            client_ids, created
+            FROM feature_flags ORDER BY name"#
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_FEATURE_FLAGS.to_string(),
+            &data.caches.ha_cache_config,
+            &res,
+            AckLevel::Leader,
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn update(
+        data: &web::Data<AppState>,
+        id: String,
+        req: FeatureFlagRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut all = Self::find_all(data).await?;
+        if all.iter().any(|f| f.name == req.name && f.id != id) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A feature flag with this name already exists".to_string(),
+            ));
+        }
+        let slf = all
+            .iter_mut()
+            .find(|f| f.id == id)
+            .ok_or_else(Self::err_not_found)?;
+        slf.apply_req(req)?;
+
+        sqlx::query!(
+            r#"UPDATE feature_flags
+            SET name = $1, description = $2, enabled = $3, percentage = $4, groups = $5,
+            attr_key = $6, attr_value = $7, client_ids = $8
+            WHERE id = $9"#,
+            slf.name,
+            slf.description,
+            slf.enabled,
+            slf.percentage,
+            slf.groups,
+            slf.attr_key,
+            slf.attr_value,
+            slf.client_ids,
+            slf.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        let slf = slf.clone();
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+}
+
+impl FeatureFlag {
+    /// Evaluates all flags, which are visible for the given client. Without a user, for instance
+    /// for a `client_credentials` token, only flags without targeting rules can be on.
+    pub async fn evaluate_all(
+        data: &web::Data<AppState>,
+        user: Option<&User>,
+        client_id: &str,
+    ) -> Result<BTreeMap<String, bool>, ErrorResponse> {
+        let flags = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|f| f.is_visible_for(client_id))
+            .collect::<Vec<Self>>();
+
+        let mut res = BTreeMap::new();
+        if flags.is_empty() {
+            return Ok(res);
+        }
+
+        let (user_id, groups, attrs) = match user {
+            None => (None, Vec::default(), Vec::default()),
+            Some(user) => {
+                // the attributes are only fetched if any flag targets them
+                let attrs = if flags.iter().any(|f| f.attr_key.is_some()) {
+                    UserAttrValueEntity::find_for_user(data, &user.id).await?
+                } else {
+                    Vec::default()
+                };
+                (Some(user.id.as_str()), user.get_groups(), attrs)
+            }
+        };
+
+        for flag in flags {
+            let is_on = flag.evaluate(user_id, &groups, &attrs);
+            res.insert(flag.name, is_on);
+        }
+        Ok(res)
+    }
+
+    /// Returns the names of all flags, which are on for the user, for the `flags` claim.
+    pub async fn find_enabled_names(
+        data: &web::Data<AppState>,
+        user: &User,
+        client_id: &str,
+    ) -> Result<Vec<String>, ErrorResponse> {
+        let res = Self::evaluate_all(data, Some(user), client_id)
+            .await?
+            .into_iter()
+            .filter_map(|(name, is_on)| is_on.then_some(name))
+            .collect();
+        Ok(res)
+    }
+
+    pub fn evaluate(
+        &self,
+        user_id: Option<&str>,
+        groups: &[String],
+        attrs: &[UserAttrValueEntity],
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.percentage.is_none() && self.groups.is_none() && self.attr_key.is_none() {
+            return true;
+        }
+
+        let Some(user_id) = user_id else {
+            return false;
+        };
+
+        if let Some(flag_groups) = &self.groups {
+            if flag_groups
+                .split(',')
+                .any(|g| groups.iter().any(|ug| ug == g))
+            {
+                return true;
+            }
+        }
+
+        if let (Some(key), Some(value)) = (&self.attr_key, &self.attr_value) {
+            let matches = attrs
+                .iter()
+                .filter(|a| &a.key == key)
+                .filter_map(|a| serde_json::from_slice::<serde_json::Value>(&a.value).ok())
+                .any(|v| match v {
+                    serde_json::Value::String(s) => &s == value,
+                    v => &v.to_string() == value,
+                });
+            if matches {
+                return true;
+            }
+        }
+
+        if let Some(percentage) = self.percentage {
+            return (self.bucket(user_id) as i64) < percentage;
+        }
+
+        false
+    }
+
+    pub fn is_visible_for(&self, client_id: &str) -> bool {
+        match &self.client_ids {
+            None => true,
+            Some(ids) => ids.split(',').any(|id| id == client_id),
+        }
+    }
+
+    /// Maps the user into a stable bucket between 0 and 99. The flag name is included, so that
+    /// the same users do not always end up in the first percent of each rollout.
+    fn bucket(&self, user_id: &str) -> u16 {
+        let hash = digest::digest(
+            &digest::SHA256,
+            format!("{}:{}", self.name, user_id).as_bytes(),
+        );
+        let bytes = hash.as_ref();
+        u16::from_be_bytes([bytes[0], bytes[1]]) % 100
+    }
+
+    fn apply_req(&mut self, req: FeatureFlagRequest) -> Result<(), ErrorResponse> {
+        if req.attr_key.is_some() != req.attr_value.is_some() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "'attr_key' and 'attr_value' must be given together".to_string(),
+            ));
+        }
+
+        self.name = req.name;
+        self.description = req.description;
+        self.enabled = req.enabled;
+        self.percentage = req.percentage;
+        self.groups = Self::join_csv(req.groups);
+        self.attr_key = req.attr_key;
+        self.attr_value = req.attr_value;
+        self.client_ids = Self::join_csv(req.client_ids);
+        Ok(())
+    }
+
+    fn join_csv(values: Option<Vec<String>>) -> Option<String> {
+        values.map(|v| v.join(",")).filter(|v| !v.is_empty())
+    }
+
+    async fn update_cache(data: &web::Data<AppState>, all: Vec<Self>) -> Result<(), ErrorResponse> {
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_FEATURE_FLAGS.to_string(),
+            &data.caches.ha_cache_config,
+            &all,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn err_not_found() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "Feature flag not found".to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_flag_evaluate() {
+        let mut flag = FeatureFlag {
+            id: new_store_id(),
+            name: "new-dashboard".to_string(),
+            description: None,
+            enabled: false,
+            percentage: None,
+            groups: None,
+            attr_key: None,
+            attr_value: None,
+            client_ids: None,
+            created: 0,
+        };
+        let groups = vec!["beta".to_string()];
+        let attrs = vec![UserAttrValueEntity {
+            user_id: "user_1".to_string(),
+            key: "plan".to_string(),
+            value: serde_json::to_vec(&serde_json::json!("pro")).unwrap(),
+        }];
+
+        // disabled flags are always off
+        assert!(!flag.evaluate(Some("user_1"), &groups, &attrs));
+
+        // no rules -> on for everyone
+        flag.enabled = true;
+        assert!(flag.evaluate(Some("user_1"), &[], &[]));
+        assert!(flag.evaluate(None, &[], &[]));
+
+        flag.groups = Some("beta,staff".to_string());
+        assert!(flag.evaluate(Some("user_1"), &groups, &[]));
+        assert!(!flag.evaluate(Some("user_1"), &[], &[]));
+        assert!(!flag.evaluate(None, &groups, &[]));
+
+        flag.groups = None;
+        flag.attr_key = Some("plan".to_string());
+        flag.attr_value = Some("pro".to_string());
+        assert!(flag.evaluate(Some("user_1"), &[], &attrs));
+        flag.attr_value = Some("free".to_string());
+        assert!(!flag.evaluate(Some("user_1"), &[], &attrs));
+
+        flag.attr_key = None;
+        flag.attr_value = None;
+        flag.percentage = Some(0);
+        assert!(!flag.evaluate(Some("user_1"), &[], &[]));
+        flag.percentage = Some(100);
+        assert!(flag.evaluate(Some("user_1"), &[], &[]));
+
+        // the rollout must be stable and roughly match the percentage
+        flag.percentage = Some(30);
+        let on = (0..1000)
+            .filter(|i| flag.evaluate(Some(&format!("user_{}", i)), &[], &[]))
+            .count();
+        assert!((200..400).contains(&on), "{} users in a 30% rollout", on);
+        assert_eq!(
+            flag.evaluate(Some("user_1"), &[], &[]),
+            flag.evaluate(Some("user_1"), &[], &[])
+        );
+
+        assert!(flag.is_visible_for("app"));
+        flag.client_ids = Some("app,other".to_string());
+        assert!(flag.is_visible_for("other"));
+        assert!(!flag.is_visible_for("unknown"));
+    }
+}
@@ -2,10 +2,11 @@ use crate::app_state::AppState;
 use crate::entity::users::User;
 use crate::request::NewGroupRequest;
 use actix_web::web;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
 use rauthy_common::constants::{CACHE_NAME_12HR, IDX_GROUPS, IDX_USERS};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::new_store_id;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use utoipa::ToSchema;
@@ -14,6 +15,8 @@ use utoipa::ToSchema;
 pub struct Group {
     pub id: String,
     pub name: String,
+    /// CSV of E-Mail domains
+    pub domains: Option<String>,
 }
 
 // CRUD
@@ -36,12 +39,14 @@ impl Group {
         let new_group = Group {
             id: new_store_id(),
             name: group_req.group,
+            domains: Self::domains_to_csv(group_req.domains),
         };
 
         sqlx::query!(
-            "insert into groups (id, name) values ($1, $2)",
+            "insert into groups (id, name, domains) values ($1, $2, $3)",
             new_group.id,
             new_group.name,
+            new_group.domains,
         )
         .execute(&data.db)
         .await?;
@@ -127,12 +132,11 @@ impl Group {
 
     // Returns all existing groups
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
-        let groups = cache_get!(
-            Vec<Group>,
+        let groups = cache_get::<Vec<Group>>(
             CACHE_NAME_12HR.to_string(),
             IDX_GROUPS.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(groups) = groups {
@@ -158,9 +162,10 @@ impl Group {
     pub async fn update(
         data: &web::Data<AppState>,
         id: String,
-        new_name: String,
+        group_req: NewGroupRequest,
     ) -> Result<Self, ErrorResponse> {
         let group = Group::find(data, id).await?;
+        let new_name = group_req.group;
 
         // find all users with the old_name assigned
         let mut users = vec![];
@@ -194,11 +199,13 @@ impl Group {
         let new_group = Group {
             id: group.id.clone(),
             name: new_name,
+            domains: Self::domains_to_csv(group_req.domains),
         };
 
         sqlx::query!(
-            "update groups set name = $1 where id = $2",
+            "update groups set name = $1, domains = $2 where id = $3",
             new_group.name,
+            new_group.domains,
             new_group.id,
         )
         .execute(&mut *txn)
@@ -212,6 +219,7 @@ impl Group {
             .map(|mut g| {
                 if g.id == group.id {
                     g.name.clone_from(&new_group.name);
+                    g.domains.clone_from(&new_group.domains);
                 }
                 g
             })
@@ -230,6 +238,19 @@ impl Group {
 }
 
 impl Group {
+    fn domains_to_csv(domains: Option<Vec<String>>) -> Option<String> {
+        domains
+            .filter(|d| !d.is_empty())
+            .map(|d| d.join(",").to_lowercase())
+    }
+
+    pub fn get_domains(&self) -> Vec<String> {
+        self.domains
+            .as_ref()
+            .map(|d| d.split(',').map(String::from).collect())
+            .unwrap_or_default()
+    }
+
     // Sanitizes any bad data from an API request for adding / modifying groups and silently
     // dismissed all bad data.
     pub async fn sanitize(
@@ -0,0 +1,265 @@
+use crate::app_state::AppState;
+use crate::events::ip_blacklist_handler::{IpBlacklistReq, IpFeedUpdate};
+use actix_web::web;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use rauthy_common::constants::{
+    IP_FEED_ABUSEIPDB_API_KEY, IP_FEED_ABUSEIPDB_CONFIDENCE_MIN, IP_FEED_ABUSEIPDB_LIMIT,
+    IP_FEED_ABUSEIPDB_TTL_HOURS, IP_FEED_INTERVAL_MINS, IP_FEED_SPAMHAUS_DROP,
+    IP_FEED_SPAMHAUS_TTL_HOURS, RAUTHY_VERSION,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::IpCidr;
+use reqwest::tls;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{debug, error, info};
+use utoipa::ToSchema;
+
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+static STATUS: Lazy<Mutex<HashMap<IpFeed, IpFeedStatus>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+const ABUSEIPDB_URL: &str = "https://api.abuseipdb.com/api/v2/blacklist";
+const SPAMHAUS_DROP_URLS: [&str; 2] = [
+    "https://www.spamhaus.org/drop/drop_v4.json",
+    "https://www.spamhaus.org/drop/drop_v6.json",
+];
+
+/// External IP reputation feeds, which are merged into the IP blacklist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFeed {
+    #[serde(rename = "abuseipdb")]
+    AbuseIpDb,
+    SpamhausDrop,
+}
+
+impl IpFeed {
+    pub fn all() -> [Self; 2] {
+        [Self::AbuseIpDb, Self::SpamhausDrop]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AbuseIpDb => "abuseipdb",
+            Self::SpamhausDrop => "spamhaus_drop",
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Self::AbuseIpDb => IP_FEED_ABUSEIPDB_API_KEY.is_some(),
+            Self::SpamhausDrop => *IP_FEED_SPAMHAUS_DROP,
+        }
+    }
+
+    pub fn ttl_hours(&self) -> i64 {
+        match self {
+            Self::AbuseIpDb => *IP_FEED_ABUSEIPDB_TTL_HOURS,
+            Self::SpamhausDrop => *IP_FEED_SPAMHAUS_TTL_HOURS,
+        }
+    }
+
+    /// The status of this feed on this instance, if it has been fetched at least once.
+    pub fn status(&self) -> Option<IpFeedStatus> {
+        STATUS
+            .lock()
+            .expect("ip feed status lock to never be poisoned")
+            .get(self)
+            .cloned()
+    }
+
+    /// Fetches the feed and replaces all of its entries inside the blacklist.
+    pub async fn update(&self, data: &web::Data<AppState>) -> Result<usize, ErrorResponse> {
+        debug!("Updating IP feed {}", self);
+
+        let now = Utc::now();
+        let res = self.fetch().await;
+
+        let mut status = STATUS
+            .lock()
+            .expect("ip feed status lock to never be poisoned");
+        let status = status.entry(*self).or_default();
+        status.last_attempt = now.timestamp();
+
+        let entries = match res {
+            Ok(entries) => entries,
+            Err(err) => {
+                status.error = Some(err.message.clone());
+                return Err(err);
+            }
+        };
+        let count = entries.len();
+        let exp = now + chrono::Duration::hours(self.ttl_hours());
+
+        status.last_update = Some(now.timestamp());
+        status.entries = count;
+        status.exp = Some(exp.timestamp());
+        status.error = None;
+
+        data.tx_ip_blacklist
+            .send(IpBlacklistReq::FeedUpdate(IpFeedUpdate {
+                feed: *self,
+                entries,
+                exp,
+            }))
+            .expect("ip blacklist handler to always be running");
+
+        Ok(count)
+    }
+
+    async fn fetch(&self) -> Result<Vec<IpCidr>, ErrorResponse> {
+        let client = HTTP_CLIENT.get_or_init(|| {
+            reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .timeout(Duration::from_secs(60))
+                .user_agent(format!("Rauthy v{} IP Feed Client", RAUTHY_VERSION))
+                .min_tls_version(tls::Version::TLS_1_2)
+                .build()
+                .unwrap()
+        });
+
+        let reqs = match self {
+            Self::AbuseIpDb => {
+                let key = IP_FEED_ABUSEIPDB_API_KEY.as_deref().unwrap_or_default();
+                vec![client
+                    .get(ABUSEIPDB_URL)
+                    .query(&[
+                        (
+                            "confidenceMinimum",
+                            IP_FEED_ABUSEIPDB_CONFIDENCE_MIN.to_string(),
+                        ),
+                        ("limit", IP_FEED_ABUSEIPDB_LIMIT.to_string()),
+                    ])
+                    .header("Key", key)
+                    .header("Accept", "text/plain")]
+            }
+            Self::SpamhausDrop => SPAMHAUS_DROP_URLS
+                .iter()
+                .map(|url| client.get(*url))
+                .collect(),
+        };
+
+        let mut entries = Vec::new();
+        for req in reqs {
+            let res = req.send().await?;
+            if !res.status().is_success() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Connection,
+                    format!("Error fetching IP feed {}: {}", self, res.status()),
+                ));
+            }
+            entries.append(&mut parse_list(&res.text().await?));
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Display for IpFeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The latest fetch of an IP feed on this instance.
+#[derive(Debug, Clone, Default)]
+pub struct IpFeedStatus {
+    pub last_attempt: i64,
+    /// The latest successful update
+    pub last_update: Option<i64>,
+    pub entries: usize,
+    /// The expiry of the entries from the latest successful update
+    pub exp: Option<i64>,
+    /// The error of the latest attempt, if it failed
+    pub error: Option<String>,
+}
+
+/// Parses an IP list with one IP or network per line. Comments after `;` or `#` are ignored, as
+/// well as lines which cannot be parsed. JSON lines with a `cidr` value are accepted too, like
+/// they are used by the Spamhaus DROP lists.
+pub fn parse_list(body: &str) -> Vec<IpCidr> {
+    #[derive(Deserialize)]
+    struct JsonLine {
+        cidr: String,
+    }
+
+    body.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with('{') {
+                let json = serde_json::from_str::<JsonLine>(line).ok()?;
+                return IpCidr::from_str(&json.cidr).ok();
+            }
+
+            let line = line.split([';', '#']).next().unwrap_or_default().trim();
+            if line.is_empty() {
+                return None;
+            }
+            IpCidr::from_str(line).ok()
+        })
+        .collect()
+}
+
+/// Updates all enabled IP feeds in the `IP_FEED_INTERVAL_MINS` interval. The blacklist lives in
+/// memory, so this runs on every instance.
+pub async fn run(data: web::Data<AppState>) {
+    let feeds = IpFeed::all()
+        .into_iter()
+        .filter(|feed| feed.is_enabled())
+        .collect::<Vec<_>>();
+    if feeds.is_empty() {
+        return;
+    }
+    info!("IP feeds enabled: {:?}", feeds);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(*IP_FEED_INTERVAL_MINS * 60));
+    loop {
+        interval.tick().await;
+
+        for feed in &feeds {
+            match feed.update(&data).await {
+                Ok(count) => info!("Updated IP feed {} with {} entries", feed, count),
+                Err(err) => error!("Error updating IP feed {}: {}", feed, err.message),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let body = r#"
+; Spamhaus DROP List
+1.10.16.0/20 ; SBL256894
+# a comment
+192.0.2.1
+2001:db8::/32
+not an ip
+{"cidr":"198.51.100.0/24","sblid":"SBL1","rir":"ripencc"}
+{"type":"metadata","timestamp":1700000000,"size":2}
+"#;
+        let entries = parse_list(body)
+            .into_iter()
+            .map(|cidr| cidr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                "1.10.16.0/20",
+                "192.0.2.1",
+                "2001:db8::/32",
+                "198.51.100.0/24"
+            ]
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use crate::app_state::AppState;
 use actix_web::web;
 use chrono::{DateTime, Utc};
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::DEVICE_GRANT_RATE_LIMIT;
 use rauthy_common::{constants::CACHE_NAME_IP_RATE_LIMIT, error_response::ErrorResponse};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 
 /// Caution: The `exp` on this struct does not define the timeout. It is only used
@@ -29,12 +30,11 @@ impl DeviceIpRateLimit {
     }
 
     pub async fn is_limited(data: &web::Data<AppState>, ip: String) -> Option<DateTime<Utc>> {
-        cache_get!(
-            DateTime<Utc>,
+        cache_get::<DateTime<Utc>>(
             CACHE_NAME_IP_RATE_LIMIT.to_string(),
             ip,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await
         .unwrap_or_default()
@@ -0,0 +1,507 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use cryptr::EncValue;
+use rauthy_common::constants::{JOB_BACKOFF_BASE_SECS, JOB_BACKOFF_MAX_SECS, JOB_MAX_ATTEMPTS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_decode, base64_encode, new_store_id};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, FromRow};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+/// How long a claimed job is locked for a single worker. If the instance dies in the meantime,
+/// any other worker may pick it up again after this time.
+const JOB_LOCK_SECS: i64 = 300;
+/// Payloads are always encrypted, independent of `PII_ENCRYPTION`. Jobs created before may
+/// still exist as plaintext and are read as they are.
+const PAYLOAD_ENC_PREFIX: &str = "enc:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobType {
+    Email,
+}
+
+impl JobType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Email => "email",
+        }
+    }
+}
+
+impl Display for JobType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for JobType {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(Self::Email),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown JobType: {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    /// The job has exceeded `JOB_MAX_ATTEMPTS` and will not be retried automatically.
+    Dead,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Dead => "dead",
+        }
+    }
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "running" => Ok(Self::Running),
+            "dead" => Ok(Self::Dead),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown JobStatus: {}", s),
+            )),
+        }
+    }
+}
+
+/// A persistent background job, which will be retried with an exponential backoff.
+///
+/// The `payload` is the JSON serialized input for the job and may contain sensitive data like
+/// magic links. It is stored encrypted with the `ENC_KEY_ACTIVE` and must never be returned
+/// via the API.
+#[derive(Debug, Clone, FromRow)]
+pub struct Job {
+    pub id: String,
+    pub typ: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i64,
+    /// For pending jobs, the earliest time of the next execution. For running jobs, the time
+    /// until which the job is locked by a worker.
+    pub next_run: i64,
+    pub last_error: Option<String>,
+    pub created: i64,
+}
+
+impl Job {
+    pub async fn enqueue<T: Serialize>(
+        data: &web::Data<AppState>,
+        typ: JobType,
+        payload: &T,
+    ) -> Result<Self, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let slf = Self {
+            id: new_store_id(),
+            typ: typ.as_str().to_string(),
+            payload: Self::encrypt_payload(&serde_json::to_string(payload)?, None)?,
+            status: JobStatus::Pending.as_str().to_string(),
+            attempts: 0,
+            next_run: now,
+            last_error: None,
+            created: now,
+        };
+
+        query!(
+            r#"INSERT INTO jobs
+            (id, typ, payload, status, attempts, next_run, last_error, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            slf.id,
+            slf.typ,
+            slf.payload,
+            slf.status,
+            slf.attempts,
+            slf.next_run,
+            slf.last_error,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM jobs WHERE id = $1", id)
+            .fetch_one(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all(
+        data: &web::Data<AppState>,
+        status: Option<JobStatus>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = if let Some(status) = status {
+            let status = status.as_str();
+            query_as!(
+                Self,
+                "SELECT * FROM jobs WHERE status = $1 ORDER BY created DESC",
+                status
+            )
+            .fetch_all(&data.db)
+            .await?
+        } else {
+            query_as!(Self, "SELECT * FROM jobs ORDER BY created DESC")
+                .fetch_all(&data.db)
+                .await?
+        };
+        Ok(res)
+    }
+
+    /// Returns the amount of jobs of the given type, which are not dead yet.
+    pub async fn count_backlog(
+        data: &web::Data<AppState>,
+        typ: JobType,
+    ) -> Result<i64, ErrorResponse> {
+        let typ = typ.as_str();
+        let dead = JobStatus::Dead.as_str();
+        let res = query!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM jobs WHERE typ = $1 AND status != $2"#,
+            typ,
+            dead,
+        )
+        .fetch_one(&data.db)
+        .await?;
+        Ok(res.count)
+    }
+
+    /// Returns up to `limit` jobs of the given type, which are ready to run.
+    ///
+    /// Running jobs with an expired lock are included to recover from crashed instances.
+    pub async fn find_due(
+        data: &web::Data<AppState>,
+        typ: JobType,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let typ = typ.as_str();
+        let dead = JobStatus::Dead.as_str();
+        let now = Utc::now().timestamp();
+
+        let res = query_as!(
+            Self,
+            r#"SELECT * FROM jobs
+            WHERE typ = $1 AND status != $2 AND next_run <= $3
+            ORDER BY next_run
+            LIMIT $4"#,
+            typ,
+            dead,
+            now,
+            limit,
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Tries to lock this job for the current worker.
+    ///
+    /// Returns `false` if another worker, possibly on another instance, was faster.
+    pub async fn claim(&mut self, data: &web::Data<AppState>) -> Result<bool, ErrorResponse> {
+        let status = JobStatus::Running.as_str();
+        let locked_until = Utc::now().timestamp() + JOB_LOCK_SECS;
+
+        // the old `next_run` works as an optimistic lock
+        let res = query!(
+            "UPDATE jobs SET status = $1, next_run = $2 WHERE id = $3 AND next_run = $4",
+            status,
+            locked_until,
+            self.id,
+            self.next_run,
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 1 {
+            self.status = status.to_string();
+            self.next_run = locked_until;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Successfully finished jobs are simply deleted.
+    pub async fn complete(self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM jobs WHERE id = $1", self.id)
+            .execute(&data.db)
+            .await?;
+        debug!("Job {} completed", self.id);
+        Ok(())
+    }
+
+    /// Schedules the next attempt with an exponential backoff or moves the job into the
+    /// dead letter state, if `JOB_MAX_ATTEMPTS` has been reached. `permanent` errors would not
+    /// change with another attempt and move the job into the dead letters right away.
+    pub async fn fail(
+        mut self,
+        data: &web::Data<AppState>,
+        err: String,
+        permanent: bool,
+    ) -> Result<(), ErrorResponse> {
+        self.attempts += 1;
+        self.last_error = Some(err);
+
+        if permanent {
+            warn!(
+                "Job {} of type {} failed permanently - moving it to the dead letters",
+                self.id, self.typ
+            );
+            self.status = JobStatus::Dead.as_str().to_string();
+        } else if self.attempts >= *JOB_MAX_ATTEMPTS {
+            warn!(
+                "Job {} of type {} failed {} times - moving it to the dead letters",
+                self.id, self.typ, self.attempts
+            );
+            self.status = JobStatus::Dead.as_str().to_string();
+        } else {
+            self.status = JobStatus::Pending.as_str().to_string();
+            self.next_run = Utc::now().timestamp() + Self::backoff_secs(self.attempts);
+        }
+
+        query!(
+            r#"UPDATE jobs SET status = $1, attempts = $2, next_run = $3, last_error = $4
+            WHERE id = $5"#,
+            self.status,
+            self.attempts,
+            self.next_run,
+            self.last_error,
+            self.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Releases a claimed job without counting an attempt, for instance when it has been
+    /// rate limited.
+    pub async fn postpone(
+        mut self,
+        data: &web::Data<AppState>,
+        secs: i64,
+    ) -> Result<(), ErrorResponse> {
+        self.status = JobStatus::Pending.as_str().to_string();
+        self.next_run = Utc::now().timestamp() + secs;
+
+        query!(
+            "UPDATE jobs SET status = $1, next_run = $2 WHERE id = $3",
+            self.status,
+            self.next_run,
+            self.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Resets a job, so it will be executed again as soon as possible.
+    pub async fn retry(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let status = JobStatus::Pending.as_str();
+        let now = Utc::now().timestamp();
+
+        let res = query!(
+            "UPDATE jobs SET status = $1, attempts = 0, next_run = $2 WHERE id = $3",
+            status,
+            now,
+            id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Job not found".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM jobs WHERE id = $1", id)
+            .execute(&data.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes all dead jobs, which have been created before the given timestamp.
+    pub async fn delete_dead_before(
+        data: &web::Data<AppState>,
+        created: i64,
+    ) -> Result<u64, ErrorResponse> {
+        let dead = JobStatus::Dead.as_str();
+        let res = query!(
+            "DELETE FROM jobs WHERE status = $1 AND created < $2",
+            dead,
+            created
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Encrypts all payloads, which are still stored in cleartext.
+    pub async fn encrypt_existing(data: &web::Data<AppState>) -> Result<usize, ErrorResponse> {
+        let jobs = query_as!(Self, "SELECT * FROM jobs WHERE payload NOT LIKE 'enc:%'")
+            .fetch_all(&data.db)
+            .await?;
+
+        for job in &jobs {
+            let payload = Self::encrypt_payload(&job.payload, None)?;
+            query!(
+                "UPDATE jobs SET payload = $1 WHERE id = $2",
+                payload,
+                job.id
+            )
+            .execute(&data.db)
+            .await?;
+        }
+        Ok(jobs.len())
+    }
+
+    /// Re-encrypts all payloads with the given key.
+    pub async fn migrate_enc_key(
+        data: &web::Data<AppState>,
+        key_id: &str,
+    ) -> Result<usize, ErrorResponse> {
+        let jobs = query_as!(Self, "SELECT * FROM jobs")
+            .fetch_all(&data.db)
+            .await?;
+
+        for job in &jobs {
+            let payload = Self::encrypt_payload(&job.decrypted_payload()?, Some(key_id))?;
+            query!(
+                "UPDATE jobs SET payload = $1 WHERE id = $2",
+                payload,
+                job.id
+            )
+            .execute(&data.db)
+            .await?;
+        }
+        Ok(jobs.len())
+    }
+
+    pub fn payload<T: for<'de> Deserialize<'de>>(&self) -> Result<T, ErrorResponse> {
+        Ok(serde_json::from_str(&self.decrypted_payload()?)?)
+    }
+
+    fn encrypt_payload(payload: &str, key_id: Option<&str>) -> Result<String, ErrorResponse> {
+        let enc = match key_id {
+            None => EncValue::encrypt(payload.as_bytes())?,
+            Some(kid) => EncValue::encrypt_with_key_id(payload.as_bytes(), kid.to_string())?,
+        }
+        .into_bytes();
+        Ok(format!("{}{}", PAYLOAD_ENC_PREFIX, base64_encode(&enc)))
+    }
+
+    fn decrypted_payload(&self) -> Result<String, ErrorResponse> {
+        match self.payload.strip_prefix(PAYLOAD_ENC_PREFIX) {
+            Some(b64) => {
+                let dec = EncValue::try_from(base64_decode(b64)?)?.decrypt()?;
+                Ok(String::from_utf8(dec.to_vec())?)
+            }
+            None => Ok(self.payload.clone()),
+        }
+    }
+
+    #[inline]
+    fn backoff_secs(attempts: i64) -> i64 {
+        let exp = attempts.saturating_sub(1).min(20) as u32;
+        JOB_BACKOFF_BASE_SECS
+            .saturating_mul(2i64.pow(exp))
+            .min(*JOB_BACKOFF_MAX_SECS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::entity::jobs::{Job, JobStatus, JobType};
+    use cryptr::EncKeys;
+    use std::env;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_job_conversions() {
+        let typ = JobType::Email;
+        assert_eq!(JobType::from_str(typ.as_str()).unwrap(), typ);
+
+        for status in [JobStatus::Pending, JobStatus::Running, JobStatus::Dead] {
+            assert_eq!(JobStatus::from_str(status.as_str()).unwrap(), status);
+        }
+        assert!(JobStatus::from_str("done").is_err());
+    }
+
+    #[test]
+    fn test_job_backoff() {
+        // defaults: 10s base, 3600s max
+        assert_eq!(Job::backoff_secs(1), 10);
+        assert_eq!(Job::backoff_secs(2), 20);
+        assert_eq!(Job::backoff_secs(4), 80);
+        assert_eq!(Job::backoff_secs(100), 3600);
+    }
+
+    #[test]
+    fn test_job_payload_encryption() {
+        env::set_var(
+            "ENC_KEYS",
+            "q6u26onRvXVG4427/M0NFQzhSSldCY01rckJNa1JYZ3g2NUFtSnNOVGdoU0E=\n\
+            bVCyTsGaggVy5yqQ/UzluN29DZW41M3hTSkx6Y3NtZmRuQkR2TnJxUTYzcjQ=",
+        );
+        env::set_var("ENC_KEY_ACTIVE", "bVCyTsGaggVy5yqQ");
+        // may have been initialized by another test already
+        let _ = EncKeys::from_env().unwrap().init();
+
+        let plain = r#"{"link":"https://localhost/auth/v1/users/id/reset/secret"}"#;
+        let mut job = Job {
+            id: "id".to_string(),
+            typ: JobType::Email.as_str().to_string(),
+            payload: plain.to_string(),
+            status: JobStatus::Pending.as_str().to_string(),
+            attempts: 0,
+            next_run: 0,
+            last_error: None,
+            created: 0,
+        };
+        // plaintext from before the encryption is still readable
+        assert_eq!(job.decrypted_payload().unwrap(), plain);
+
+        job.payload = Job::encrypt_payload(plain, None).unwrap();
+        assert!(job.payload.starts_with("enc:"));
+        assert!(!job.payload.contains("reset"));
+        assert_eq!(job.decrypted_payload().unwrap(), plain);
+
+        job.payload = Job::encrypt_payload(plain, Some("q6u26onRvXVG4427")).unwrap();
+        assert_eq!(job.decrypted_payload().unwrap(), plain);
+    }
+}
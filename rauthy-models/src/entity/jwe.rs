@@ -0,0 +1,550 @@
+use crate::app_state::AppState;
+use crate::entity::clients::Client;
+use crate::entity::remote_jwks::RemoteJwks;
+use actix_web::web;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_url_no_pad_decode, base64_url_no_pad_encode};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, digest};
+use rsa::{BigUint, Oaep, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// The key management algorithm for encrypted `id_token`s and userinfo responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum JweAlg {
+    #[serde(rename = "RSA-OAEP")]
+    RsaOaep,
+    #[serde(rename = "RSA-OAEP-256")]
+    RsaOaep256,
+    #[serde(rename = "ECDH-ES")]
+    EcdhEs,
+}
+
+impl JweAlg {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RsaOaep => "RSA-OAEP",
+            Self::RsaOaep256 => "RSA-OAEP-256",
+            Self::EcdhEs => "ECDH-ES",
+        }
+    }
+
+    pub fn all() -> [Self; 3] {
+        [Self::RsaOaep, Self::RsaOaep256, Self::EcdhEs]
+    }
+}
+
+impl Display for JweAlg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for JweAlg {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RSA-OAEP" => Ok(Self::RsaOaep),
+            "RSA-OAEP-256" => Ok(Self::RsaOaep256),
+            "ECDH-ES" => Ok(Self::EcdhEs),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown JweAlg: {}", s),
+            )),
+        }
+    }
+}
+
+/// The content encryption algorithm for encrypted `id_token`s and userinfo responses
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum JweEnc {
+    /// The default, if only an `alg` is registered (OpenID Connect Dynamic Client Registration 2)
+    #[default]
+    A128GCM,
+    A256GCM,
+}
+
+impl JweEnc {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::A128GCM => "A128GCM",
+            Self::A256GCM => "A256GCM",
+        }
+    }
+
+    pub fn all() -> [Self; 2] {
+        [Self::A128GCM, Self::A256GCM]
+    }
+
+    fn algorithm(&self) -> &'static aead::Algorithm {
+        match self {
+            Self::A128GCM => &aead::AES_128_GCM,
+            Self::A256GCM => &aead::AES_256_GCM,
+        }
+    }
+
+    fn key_len(&self) -> usize {
+        self.algorithm().key_len()
+    }
+}
+
+impl Display for JweEnc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for JweEnc {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A128GCM" => Ok(Self::A128GCM),
+            "A256GCM" => Ok(Self::A256GCM),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown JweEnc: {}", s),
+            )),
+        }
+    }
+}
+
+/// Validates the `*_encrypted_response_alg` and `*_encrypted_response_enc` of a client
+/// registration and returns the values to store.
+pub fn encrypted_response_values(
+    param: &str,
+    alg: Option<JweAlg>,
+    enc: Option<JweEnc>,
+    jwks_uri: Option<&str>,
+) -> Result<(Option<String>, Option<String>), ErrorResponse> {
+    match (alg, enc) {
+        (None, None) => Ok((None, None)),
+        (None, Some(_)) => Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "'{}_encrypted_response_enc' requires a '{}_encrypted_response_alg'",
+                param, param
+            ),
+        )),
+        (Some(_), _) if jwks_uri.is_none() => Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "'{}_encrypted_response_alg' requires a 'jwks_uri' with an encryption key",
+                param
+            ),
+        )),
+        (Some(alg), enc) => Ok((
+            Some(alg.to_string()),
+            Some(enc.unwrap_or_default().to_string()),
+        )),
+    }
+}
+
+/// The public part of an encryption key from the `jwks_uri` of a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JweEncryptionKey {
+    pub kty: String,
+    pub kid: Option<String>,
+    #[serde(rename = "use")]
+    pub key_use: Option<String>,
+    pub alg: Option<String>,
+    pub crv: Option<String>,
+    // RSA
+    pub n: Option<String>,
+    pub e: Option<String>,
+    // EC / OKP
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+impl JweEncryptionKey {
+    /// Returns `true`, if this key can be used for the given key management algorithm.
+    pub fn supports(&self, alg: JweAlg) -> bool {
+        if self.key_use.as_deref().map(|u| u != "enc").unwrap_or(false) {
+            return false;
+        }
+        if self
+            .alg
+            .as_deref()
+            .map(|a| a != alg.as_str())
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        match alg {
+            JweAlg::RsaOaep | JweAlg::RsaOaep256 => {
+                self.kty == "RSA" && self.n.is_some() && self.e.is_some()
+            }
+            JweAlg::EcdhEs => match (self.kty.as_str(), self.crv.as_deref()) {
+                ("EC", Some("P-256" | "P-384")) => self.x.is_some() && self.y.is_some(),
+                ("OKP", Some("X25519")) => self.x.is_some(),
+                _ => false,
+            },
+        }
+    }
+
+    /// Encrypts the payload into a JWE in compact serialization. `cty` must be set to `JWT`, if
+    /// the payload is a signed JWT itself.
+    pub fn encrypt(
+        &self,
+        alg: JweAlg,
+        enc: JweEnc,
+        cty: Option<&str>,
+        payload: &[u8],
+    ) -> Result<String, ErrorResponse> {
+        let rng = SystemRandom::new();
+
+        let mut header = json!({
+            "alg": alg.as_str(),
+            "enc": enc.as_str(),
+        });
+        if let Some(kid) = &self.kid {
+            header["kid"] = kid.as_str().into();
+        }
+        if let Some(cty) = cty {
+            header["cty"] = cty.into();
+        }
+
+        let (cek, encrypted_key) = match alg {
+            JweAlg::RsaOaep | JweAlg::RsaOaep256 => {
+                let mut cek = vec![0u8; enc.key_len()];
+                rng.fill(&mut cek).map_err(err_encrypt)?;
+
+                let key = self.rsa_key()?;
+                let mut rng = rand::thread_rng();
+                let encrypted_key = if alg == JweAlg::RsaOaep {
+                    key.encrypt(&mut rng, Oaep::new::<sha1::Sha1>(), &cek)
+                } else {
+                    key.encrypt(&mut rng, Oaep::new::<rsa::sha2::Sha256>(), &cek)
+                }
+                .map_err(err_encrypt)?;
+
+                (cek, encrypted_key)
+            }
+            JweAlg::EcdhEs => {
+                // with direct key agreement, the CEK is derived and not transmitted at all
+                let (cek, epk) = self.agree_ecdh_es(enc, &rng)?;
+                header["epk"] = epk;
+                (cek, Vec::new())
+            }
+        };
+        let header = base64_url_no_pad_encode(header.to_string().as_bytes());
+
+        let mut iv = [0u8; aead::NONCE_LEN];
+        rng.fill(&mut iv).map_err(err_encrypt)?;
+        let key = aead::UnboundKey::new(enc.algorithm(), &cek)
+            .map(aead::LessSafeKey::new)
+            .map_err(err_encrypt)?;
+        let mut in_out = payload.to_vec();
+        let tag = key
+            .seal_in_place_separate_tag(
+                aead::Nonce::assume_unique_for_key(iv),
+                aead::Aad::from(header.as_bytes()),
+                &mut in_out,
+            )
+            .map_err(err_encrypt)?;
+
+        Ok(format!(
+            "{}.{}.{}.{}.{}",
+            header,
+            base64_url_no_pad_encode(&encrypted_key),
+            base64_url_no_pad_encode(&iv),
+            base64_url_no_pad_encode(&in_out),
+            base64_url_no_pad_encode(tag.as_ref()),
+        ))
+    }
+
+    fn rsa_key(&self) -> Result<RsaPublicKey, ErrorResponse> {
+        let (Some(n), Some(e)) = (&self.n, &self.e) else {
+            return Err(err_key("RSA key without 'n' or 'e'"));
+        };
+        RsaPublicKey::new(
+            BigUint::from_bytes_be(&base64_url_no_pad_decode(n)?),
+            BigUint::from_bytes_be(&base64_url_no_pad_decode(e)?),
+        )
+        .map_err(|_| err_key("Invalid RSA key"))
+    }
+
+    /// Generates an ephemeral key, derives the CEK and returns it together with the `epk` for
+    /// the JWE header (RFC 7518 4.6).
+    fn agree_ecdh_es(
+        &self,
+        enc: JweEnc,
+        rng: &SystemRandom,
+    ) -> Result<(Vec<u8>, serde_json::Value), ErrorResponse> {
+        let crv = self.crv.as_deref().unwrap_or_default();
+        let x = base64_url_no_pad_decode(self.x.as_deref().unwrap_or_default())?;
+
+        let (algorithm, coord_len) = match (self.kty.as_str(), crv) {
+            ("EC", "P-256") => (&agreement::ECDH_P256, 32),
+            ("EC", "P-384") => (&agreement::ECDH_P384, 48),
+            ("OKP", "X25519") => (&agreement::X25519, 32),
+            _ => return Err(err_key("Unsupported key for 'ECDH-ES'")),
+        };
+        if x.len() != coord_len {
+            return Err(err_key("Invalid 'x' coordinate"));
+        }
+
+        let peer = if self.kty == "EC" {
+            let y = base64_url_no_pad_decode(self.y.as_deref().unwrap_or_default())?;
+            if y.len() != coord_len {
+                return Err(err_key("Invalid 'y' coordinate"));
+            }
+            // uncompressed point encoding
+            let mut point = Vec::with_capacity(1 + 2 * coord_len);
+            point.push(4);
+            point.extend(x);
+            point.extend(y);
+            point
+        } else {
+            x
+        };
+
+        let private = EphemeralPrivateKey::generate(algorithm, rng).map_err(err_encrypt)?;
+        let public = private.compute_public_key().map_err(err_encrypt)?;
+        let public = public.as_ref();
+        let epk = if self.kty == "EC" {
+            json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": base64_url_no_pad_encode(&public[1..1 + coord_len]),
+                "y": base64_url_no_pad_encode(&public[1 + coord_len..]),
+            })
+        } else {
+            json!({
+                "kty": "OKP",
+                "crv": crv,
+                "x": base64_url_no_pad_encode(public),
+            })
+        };
+
+        let cek =
+            agreement::agree_ephemeral(private, &UnparsedPublicKey::new(algorithm, peer), |z| {
+                concat_kdf(z, enc)
+            })
+            .map_err(|_| err_key("Invalid EC public key"))?;
+
+        Ok((cek, epk))
+    }
+}
+
+/// Concat KDF (NIST SP 800-56A) for `ECDH-ES` without `apu` and `apv` (RFC 7518 4.6.2).
+/// A single SHA-256 round is enough for the max key length of 256 bits.
+fn concat_kdf(z: &[u8], enc: JweEnc) -> Vec<u8> {
+    let alg_id = enc.as_str().as_bytes();
+    let key_len = enc.key_len();
+
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&1u32.to_be_bytes());
+    ctx.update(z);
+    ctx.update(&(alg_id.len() as u32).to_be_bytes());
+    ctx.update(alg_id);
+    // empty PartyUInfo and PartyVInfo
+    ctx.update(&0u32.to_be_bytes());
+    ctx.update(&0u32.to_be_bytes());
+    ctx.update(&((key_len * 8) as u32).to_be_bytes());
+
+    ctx.finish().as_ref()[..key_len].to_vec()
+}
+
+/// Encrypts the payload with a key from the `jwks_uri` of the client.
+pub async fn encrypt_for_client(
+    data: &web::Data<AppState>,
+    client: &Client,
+    alg: &str,
+    enc: Option<&str>,
+    cty: Option<&str>,
+    payload: &[u8],
+) -> Result<String, ErrorResponse> {
+    let alg = JweAlg::from_str(alg)?;
+    let enc = enc.map(JweEnc::from_str).transpose()?.unwrap_or_default();
+    let Some(jwks_uri) = &client.jwks_uri else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Internal,
+            format!(
+                "Client '{}' requires encrypted responses without a 'jwks_uri'",
+                client.id
+            ),
+        ));
+    };
+
+    let cache_idx = format!("client_jwks_enc_{}_{}", client.id, jwks_uri);
+    let key = RemoteJwks {
+        url: jwks_uri,
+        cache_idx: &cache_idx,
+    }
+    .find_encryption_key(data, alg)
+    .await?;
+
+    key.encrypt(alg, enc, cty, payload)
+}
+
+fn err_encrypt<E>(_: E) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::Internal,
+        "Error during JWE encryption".to_string(),
+    )
+}
+
+fn err_key(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::Internal,
+        format!("Invalid client encryption key: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+
+    fn open(jwe: &str, cek: &[u8], enc: JweEnc) -> Vec<u8> {
+        let parts = jwe.split('.').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 5);
+
+        let iv = base64_url_no_pad_decode(parts[2]).unwrap();
+        let mut in_out = base64_url_no_pad_decode(parts[3]).unwrap();
+        in_out.extend(base64_url_no_pad_decode(parts[4]).unwrap());
+
+        let key = aead::LessSafeKey::new(aead::UnboundKey::new(enc.algorithm(), cek).unwrap());
+        let nonce = aead::Nonce::try_assume_unique_for_key(&iv).unwrap();
+        key.open_in_place(nonce, aead::Aad::from(parts[0].as_bytes()), &mut in_out)
+            .unwrap()
+            .to_vec()
+    }
+
+    fn header(jwe: &str) -> serde_json::Value {
+        let header = jwe.split('.').next().unwrap();
+        serde_json::from_slice(&base64_url_no_pad_decode(header).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_encrypted_response_values() {
+        let uri = Some("https://client.localhost/jwks");
+
+        assert_eq!(
+            encrypted_response_values("id_token", None, None, None).unwrap(),
+            (None, None)
+        );
+        assert_eq!(
+            encrypted_response_values("id_token", Some(JweAlg::RsaOaep256), None, uri).unwrap(),
+            (
+                Some("RSA-OAEP-256".to_string()),
+                Some("A128GCM".to_string())
+            )
+        );
+        assert!(encrypted_response_values("id_token", None, Some(JweEnc::A256GCM), uri).is_err());
+        assert!(encrypted_response_values("userinfo", Some(JweAlg::EcdhEs), None, None).is_err());
+    }
+
+    #[test]
+    fn test_supports() {
+        let mut key = JweEncryptionKey {
+            kty: "RSA".to_string(),
+            kid: Some("enc".to_string()),
+            key_use: Some("enc".to_string()),
+            alg: None,
+            crv: None,
+            n: Some("AQAB".to_string()),
+            e: Some("AQAB".to_string()),
+            x: None,
+            y: None,
+        };
+        assert!(key.supports(JweAlg::RsaOaep));
+        assert!(key.supports(JweAlg::RsaOaep256));
+        assert!(!key.supports(JweAlg::EcdhEs));
+
+        key.alg = Some("RSA-OAEP-256".to_string());
+        assert!(!key.supports(JweAlg::RsaOaep));
+
+        key.key_use = Some("sig".to_string());
+        assert!(!key.supports(JweAlg::RsaOaep256));
+    }
+
+    #[test]
+    fn test_encrypt_rsa_oaep() {
+        let private = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let key = JweEncryptionKey {
+            kty: "RSA".to_string(),
+            kid: Some("enc".to_string()),
+            key_use: None,
+            alg: None,
+            crv: None,
+            n: Some(base64_url_no_pad_encode(&private.n().to_bytes_be())),
+            e: Some(base64_url_no_pad_encode(&private.e().to_bytes_be())),
+            x: None,
+            y: None,
+        };
+
+        for (alg, enc) in [
+            (JweAlg::RsaOaep, JweEnc::A128GCM),
+            (JweAlg::RsaOaep256, JweEnc::A256GCM),
+        ] {
+            let jwe = key.encrypt(alg, enc, Some("JWT"), b"a.b.c").unwrap();
+            let header = header(&jwe);
+            assert_eq!(header["alg"], alg.as_str());
+            assert_eq!(header["enc"], enc.as_str());
+            assert_eq!(header["kid"], "enc");
+            assert_eq!(header["cty"], "JWT");
+
+            let encrypted_key = base64_url_no_pad_decode(jwe.split('.').nth(1).unwrap()).unwrap();
+            let cek = if alg == JweAlg::RsaOaep {
+                private.decrypt(Oaep::new::<sha1::Sha1>(), &encrypted_key)
+            } else {
+                private.decrypt(Oaep::new::<rsa::sha2::Sha256>(), &encrypted_key)
+            }
+            .unwrap();
+            assert_eq!(cek.len(), enc.key_len());
+
+            assert_eq!(open(&jwe, &cek, enc), b"a.b.c");
+        }
+    }
+
+    #[test]
+    fn test_encrypt_ecdh_es() {
+        let rng = SystemRandom::new();
+        let private = EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
+        let public = private.compute_public_key().unwrap();
+        let key = JweEncryptionKey {
+            kty: "EC".to_string(),
+            kid: None,
+            key_use: Some("enc".to_string()),
+            alg: Some("ECDH-ES".to_string()),
+            crv: Some("P-256".to_string()),
+            n: None,
+            e: None,
+            x: Some(base64_url_no_pad_encode(&public.as_ref()[1..33])),
+            y: Some(base64_url_no_pad_encode(&public.as_ref()[33..])),
+        };
+        assert!(key.supports(JweAlg::EcdhEs));
+
+        let jwe = key
+            .encrypt(JweAlg::EcdhEs, JweEnc::A256GCM, None, b"{\"sub\":\"id\"}")
+            .unwrap();
+        assert!(jwe.split('.').nth(1).unwrap().is_empty());
+
+        let epk = &header(&jwe)["epk"];
+        assert_eq!(epk["kty"], "EC");
+        assert_eq!(epk["crv"], "P-256");
+        let mut peer = vec![4];
+        peer.extend(base64_url_no_pad_decode(epk["x"].as_str().unwrap()).unwrap());
+        peer.extend(base64_url_no_pad_decode(epk["y"].as_str().unwrap()).unwrap());
+
+        let cek = agreement::agree_ephemeral(
+            private,
+            &UnparsedPublicKey::new(&agreement::ECDH_P256, peer),
+            |z| concat_kdf(z, JweEnc::A256GCM),
+        )
+        .unwrap();
+
+        assert_eq!(open(&jwe, &cek, JweEnc::A256GCM), b"{\"sub\":\"id\"}");
+    }
+}
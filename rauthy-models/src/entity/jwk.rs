@@ -1,21 +1,33 @@
 use crate::app_state::{AppState, DbPool};
+use crate::cache_invalidation::Invalidation;
 use actix_web::web;
-use cryptr::EncValue;
+use chrono::Utc;
+use cryptr::{EncKeys, EncValue};
 use jwt_simple::algorithms;
+use jwt_simple::prelude::{Ed25519KeyPair, RS256KeyPair, RS384KeyPair, RS512KeyPair};
+use once_cell::sync::Lazy;
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::{CACHE_NAME_12HR, IDX_JWKS, IDX_JWK_KID, IDX_JWK_LATEST};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::{base64_url_encode, base64_url_no_pad_decode};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_put};
+use rauthy_common::utils::{base64_url_encode, base64_url_no_pad_decode, get_rand};
+use redhac::{cache_put, cache_remove, AckLevel};
 use rsa::BigUint;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
 use sqlx::sqlite::SqliteRow;
 use sqlx::{Error, FromRow, Row};
+use std::collections::HashMap;
 use std::default::Default;
 use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::error;
 use utoipa::ToSchema;
 
+/// Local, not yet persisted signing counters per `kid`. These will be flushed into the
+/// `jwks_lifecycle` table by a scheduler to not hit the database with each new token.
+static JWK_USAGE: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 #[macro_export]
 macro_rules! sign_jwt {
     ($key_pair:expr, $claims:expr) => {
@@ -54,6 +66,49 @@ macro_rules! sign_jwt {
     };
 }
 
+/// Same as `sign_jwt!`, but with custom `HeaderOptions` like a specific `typ`.
+#[macro_export]
+macro_rules! sign_jwt_with_options {
+    ($key_pair:expr, $claims:expr, $options:expr) => {
+        match $key_pair.typ {
+            JwkKeyPairAlg::RS256 => {
+                let key =
+                    jwt_simple::algorithms::RS256KeyPair::from_der($key_pair.bytes.as_slice())
+                        .unwrap();
+                key.with_key_id(&$key_pair.kid)
+                    .sign_with_options($claims, $options)
+            }
+            JwkKeyPairAlg::RS384 => {
+                let key =
+                    jwt_simple::algorithms::RS384KeyPair::from_der($key_pair.bytes.as_slice())
+                        .unwrap();
+                key.with_key_id(&$key_pair.kid)
+                    .sign_with_options($claims, $options)
+            }
+            JwkKeyPairAlg::RS512 => {
+                let key =
+                    jwt_simple::algorithms::RS512KeyPair::from_der($key_pair.bytes.as_slice())
+                        .unwrap();
+                key.with_key_id(&$key_pair.kid)
+                    .sign_with_options($claims, $options)
+            }
+            JwkKeyPairAlg::EdDSA => {
+                let key =
+                    jwt_simple::algorithms::Ed25519KeyPair::from_der($key_pair.bytes.as_slice())
+                        .unwrap();
+                key.with_key_id(&$key_pair.kid)
+                    .sign_with_options($claims, $options)
+            }
+        }
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "Error signing JWT Token".to_string(),
+            )
+        })
+    };
+}
+
 #[macro_export]
 macro_rules! validate_jwt {
     ($type:ty, $key_pair:expr, $token:expr, $options:expr) => {
@@ -97,7 +152,7 @@ macro_rules! validate_jwt {
 The Json Web Keys are saved encrypted inside the database. The encryption is the same as for a
 Client secret -> *ChaCha20Poly1305*
  */
-#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Jwk {
     pub kid: String,
     pub created_at: i64,
@@ -145,6 +200,367 @@ impl Jwk {
     }
 }
 
+/// Lifecycle information for a JWK. Keys without an entry here are the default, generated ones
+/// which are active from their `created_at` until they are cleaned up.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct JwkLifecycle {
+    pub kid: String,
+    pub imported: bool,
+    /// unix timestamp from which on the key will be used for signing new tokens
+    pub activate_at: Option<i64>,
+    /// unix timestamp when the key has been retired early
+    pub retired_at: Option<i64>,
+    pub usage_count: i64,
+}
+
+// CRUD
+impl JwkLifecycle {
+    pub async fn find_all(db: &DbPool) -> Result<HashMap<String, Self>, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from jwks_lifecycle")
+            .fetch_all(db)
+            .await?
+            .into_iter()
+            .map(|l| (l.kid.clone(), l))
+            .collect();
+        Ok(res)
+    }
+
+    pub async fn find_opt(db: &DbPool, kid: &str) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from jwks_lifecycle where kid = $1", kid)
+            .fetch_optional(db)
+            .await?;
+        Ok(res)
+    }
+
+    async fn upsert_activation(
+        db: &DbPool,
+        kid: &str,
+        imported: bool,
+        activate_at: Option<i64>,
+    ) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            r#"insert into jwks_lifecycle (kid, imported, activate_at, usage_count)
+            values ($1, $2, $3, 0)
+            on conflict (kid) do update set activate_at = $3"#,
+            kid,
+            imported,
+            activate_at,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    async fn upsert_retired(db: &DbPool, kid: &str, retired_at: i64) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            r#"insert into jwks_lifecycle (kid, imported, retired_at, usage_count)
+            values ($1, false, $2, 0)
+            on conflict (kid) do update set retired_at = $2"#,
+            kid,
+            retired_at,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Increases the local signing counter for the given `kid`.
+    pub fn count_usage(kid: &str) {
+        let mut usage = JWK_USAGE.lock().unwrap();
+        match usage.get_mut(kid) {
+            Some(count) => *count += 1,
+            None => {
+                usage.insert(kid.to_string(), 1);
+            }
+        }
+    }
+
+    /// Persists all local signing counters into the database.
+    pub async fn flush_usage(db: &DbPool) -> Result<(), ErrorResponse> {
+        let usage = std::mem::take(&mut *JWK_USAGE.lock().unwrap());
+        for (kid, count) in usage {
+            if let Err(err) = sqlx::query!(
+                r#"insert into jwks_lifecycle (kid, imported, usage_count)
+                values ($1, false, $2)
+                on conflict (kid) do update
+                set usage_count = jwks_lifecycle.usage_count + $2"#,
+                kid,
+                count,
+            )
+            .execute(db)
+            .await
+            {
+                // the key may have been cleaned up in the meantime
+                error!("Error flushing JWK usage for kid {}: {}", kid, err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JwkLifecycle {
+    /// The timestamp from which on the key will be used for signing.
+    fn active_from(&self, created_at: i64) -> i64 {
+        self.activate_at.unwrap_or(created_at)
+    }
+
+    fn is_retired(&self) -> bool {
+        self.retired_at.is_some()
+    }
+}
+
+/// Selects the currently active signing key for the given algorithm, which is the newest key
+/// that is neither scheduled for a future activation nor retired.
+fn select_active<'a>(
+    jwks: &'a [Jwk],
+    lifecycles: &HashMap<String, JwkLifecycle>,
+    alg: &JwkKeyPairAlg,
+    now: i64,
+) -> Option<&'a Jwk> {
+    jwks.iter()
+        .filter(|jwk| &jwk.signature == alg)
+        .filter_map(|jwk| match lifecycles.get(&jwk.kid) {
+            None => Some((jwk, jwk.created_at)),
+            Some(lc) if lc.is_retired() => None,
+            Some(lc) => {
+                let from = lc.active_from(jwk.created_at);
+                if from <= now {
+                    Some((jwk, from))
+                } else {
+                    None
+                }
+            }
+        })
+        .max_by_key(|(_, from)| *from)
+        .map(|(jwk, _)| jwk)
+}
+
+impl Jwk {
+    /// Returns the `kid`s of the currently active signing key for each algorithm.
+    pub fn active_kids(
+        jwks: &[Jwk],
+        lifecycles: &HashMap<String, JwkLifecycle>,
+        now: i64,
+    ) -> Vec<String> {
+        [
+            JwkKeyPairAlg::RS256,
+            JwkKeyPairAlg::RS384,
+            JwkKeyPairAlg::RS512,
+            JwkKeyPairAlg::EdDSA,
+        ]
+        .iter()
+        .filter_map(|alg| select_active(jwks, lifecycles, alg, now).map(|jwk| jwk.kid.clone()))
+        .collect()
+    }
+
+    /// Returns the full key history with lifecycle information for all algorithms.
+    pub async fn find_history(db: &DbPool) -> Result<Vec<JwkHistory>, ErrorResponse> {
+        let jwks = sqlx::query_as!(Jwk, "select * from jwks order by created_at desc")
+            .fetch_all(db)
+            .await?;
+        let lifecycles = JwkLifecycle::find_all(db).await?;
+        let active = Self::active_kids(&jwks, &lifecycles, Utc::now().timestamp());
+
+        let res = jwks
+            .iter()
+            .map(|jwk| {
+                let lc = lifecycles.get(&jwk.kid);
+                JwkHistory {
+                    kid: jwk.kid.clone(),
+                    alg: jwk.signature.clone(),
+                    created_at: jwk.created_at,
+                    imported: lc.map(|lc| lc.imported).unwrap_or(false),
+                    activate_at: lc.and_then(|lc| lc.activate_at),
+                    retired_at: lc.and_then(|lc| lc.retired_at),
+                    usage_count: lc.map(|lc| lc.usage_count).unwrap_or(0),
+                    active: active.contains(&jwk.kid),
+                }
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// Imports an externally generated private key in PEM format.
+    pub async fn import(
+        data: &web::Data<AppState>,
+        alg: JwkKeyPairAlg,
+        pem: &str,
+        kid: Option<String>,
+        activate_at: Option<i64>,
+    ) -> Result<Self, ErrorResponse> {
+        let kid = kid.unwrap_or_else(|| get_rand(24));
+        if sqlx::query!("select kid from jwks where kid = $1", kid)
+            .fetch_optional(&data.db)
+            .await?
+            .is_some()
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A JWK with this kid exists already".to_string(),
+            ));
+        }
+
+        let der = match alg {
+            JwkKeyPairAlg::RS256 => RS256KeyPair::from_pem(pem).and_then(|kp| kp.to_der()),
+            JwkKeyPairAlg::RS384 => RS384KeyPair::from_pem(pem).and_then(|kp| kp.to_der()),
+            JwkKeyPairAlg::RS512 => RS512KeyPair::from_pem(pem).and_then(|kp| kp.to_der()),
+            JwkKeyPairAlg::EdDSA => Ed25519KeyPair::from_pem(pem).map(|kp| kp.to_der()),
+        }
+        .map_err(|err| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Cannot parse the private key for {}: {}", alg, err),
+            )
+        })?;
+
+        let slf = Self {
+            kid,
+            created_at: Utc::now().timestamp(),
+            signature: alg,
+            enc_key_id: EncKeys::get_static().enc_key_active.clone(),
+            jwk: EncValue::encrypt(der.as_slice())?.into_bytes().to_vec(),
+        };
+        slf.save(&data.db).await?;
+        JwkLifecycle::upsert_activation(&data.db, &slf.kid, true, activate_at).await?;
+
+        Self::clear_caches(data, &slf.kid, &slf.signature).await?;
+        Ok(slf)
+    }
+
+    /// Schedules the activation of the given key. `None` activates the key from its creation on.
+    pub async fn schedule_activation(
+        data: &web::Data<AppState>,
+        kid: &str,
+        activate_at: Option<i64>,
+    ) -> Result<Self, ErrorResponse> {
+        let slf = sqlx::query_as!(Jwk, "select * from jwks where kid = $1", kid)
+            .fetch_one(&data.db)
+            .await?;
+        if let Some(lc) = JwkLifecycle::find_opt(&data.db, kid).await? {
+            if lc.is_retired() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "A retired JWK cannot be activated again".to_string(),
+                ));
+            }
+        }
+
+        JwkLifecycle::upsert_activation(&data.db, kid, false, activate_at).await?;
+        Self::clear_caches(data, &slf.kid, &slf.signature).await?;
+        Ok(slf)
+    }
+
+    /// Retires the given key early. It will neither be used for signing nor be published in
+    /// the JWKS anymore, which means that all tokens signed with it become invalid.
+    pub async fn retire(data: &web::Data<AppState>, kid: &str) -> Result<Self, ErrorResponse> {
+        let jwks = sqlx::query_as!(Jwk, "select * from jwks")
+            .fetch_all(&data.db)
+            .await?;
+        let slf = match jwks.iter().find(|jwk| jwk.kid == kid) {
+            None => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::NotFound,
+                    "JWK not found".to_string(),
+                ))
+            }
+            Some(jwk) => jwk.clone(),
+        };
+
+        // make sure that there is always another key left for signing
+        let mut lifecycles = JwkLifecycle::find_all(&data.db).await?;
+        let now = Utc::now().timestamp();
+        lifecycles.insert(
+            kid.to_string(),
+            JwkLifecycle {
+                kid: kid.to_string(),
+                imported: false,
+                activate_at: None,
+                retired_at: Some(now),
+                usage_count: 0,
+            },
+        );
+        if select_active(&jwks, &lifecycles, &slf.signature, now).is_none() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "Cannot retire the only active JWK for {} - rotate or import a new one first",
+                    slf.signature
+                ),
+            ));
+        }
+
+        JwkLifecycle::upsert_retired(&data.db, kid, now).await?;
+        Self::clear_caches(data, &slf.kid, &slf.signature).await?;
+        Ok(slf)
+    }
+
+    /// Clears the latest JWK caches for all algorithms. Used when a scheduled activation is due.
+    pub async fn clear_latest_caches(data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        for alg in [
+            JwkKeyPairAlg::RS256,
+            JwkKeyPairAlg::RS384,
+            JwkKeyPairAlg::RS512,
+            JwkKeyPairAlg::EdDSA,
+        ] {
+            cache_remove(
+                CACHE_NAME_12HR.to_string(),
+                format!("{}{}", IDX_JWK_LATEST, alg.as_str()),
+                &data.caches.ha_cache_config,
+                AckLevel::Quorum,
+            )
+            .await?;
+        }
+        Invalidation::Jwks.publish(data).await;
+        Ok(())
+    }
+
+    async fn clear_caches(
+        data: &web::Data<AppState>,
+        kid: &str,
+        alg: &JwkKeyPairAlg,
+    ) -> Result<(), ErrorResponse> {
+        cache_remove(
+            CACHE_NAME_12HR.to_string(),
+            format!("{}{}", IDX_JWK_LATEST, alg.as_str()),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+        cache_remove(
+            CACHE_NAME_12HR.to_string(),
+            format!("{}{}", IDX_JWK_KID, kid),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+        cache_remove(
+            CACHE_NAME_12HR.to_string(),
+            IDX_JWKS.to_string(),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Invalidation::Jwk(kid.to_string()).publish(data).await;
+        Ok(())
+    }
+}
+
+/// A single entry of the signing key history.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JwkHistory {
+    pub kid: String,
+    pub alg: JwkKeyPairAlg,
+    pub created_at: i64,
+    /// `true` if the key has been imported instead of being generated by Rauthy
+    pub imported: bool,
+    pub activate_at: Option<i64>,
+    pub retired_at: Option<i64>,
+    /// amount of tokens signed with this key
+    pub usage_count: i64,
+    /// `true` if this is the key currently used for signing new tokens
+    pub active: bool,
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct JWKS {
     pub keys: Vec<JWKSPublicKey>,
@@ -153,12 +569,11 @@ pub struct JWKS {
 // CRUD
 impl JWKS {
     pub async fn find_pk(data: &web::Data<AppState>) -> Result<JWKS, ErrorResponse> {
-        if let Some(jwks) = cache_get!(
-            JWKS,
+        if let Some(jwks) = cache_get::<JWKS>(
             CACHE_NAME_12HR.to_string(),
             IDX_JWKS.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -168,9 +583,17 @@ impl JWKS {
         let res = sqlx::query_as!(Jwk, "select * from jwks")
             .fetch_all(&data.db)
             .await?;
+        let lifecycles = JwkLifecycle::find_all(&data.db).await?;
 
         let mut jwks = JWKS::default();
         for cert in res {
+            if lifecycles
+                .get(&cert.kid)
+                .map(|lc| lc.is_retired())
+                .unwrap_or(false)
+            {
+                continue;
+            }
             // let key = data.enc_keys.get(&cert.enc_key_id).unwrap();
             // let jwk_decrypted = decrypt_legacy(&cert.jwk, key)?;
             let jwk_decrypted = EncValue::try_from(cert.jwk)?.decrypt()?.to_vec();
@@ -475,12 +898,11 @@ impl JwkKeyPair {
     // Returns a JWK by a given Key Identifier (kid)
     pub async fn find(data: &web::Data<AppState>, kid: String) -> Result<Self, ErrorResponse> {
         let idx = format!("{}{}", IDX_JWK_KID, kid);
-        let jwk_opt = cache_get!(
-            JwkKeyPair,
+        let jwk_opt = cache_get::<JwkKeyPair>(
             CACHE_NAME_12HR.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(jwk_opt) = jwk_opt {
@@ -490,6 +912,14 @@ impl JwkKeyPair {
         let jwk = sqlx::query_as!(Jwk, "select * from jwks where kid = $1", kid,)
             .fetch_one(&data.db)
             .await?;
+        if let Some(lc) = JwkLifecycle::find_opt(&data.db, &jwk.kid).await? {
+            if lc.is_retired() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Unauthorized,
+                    "The JWK has been retired".to_string(),
+                ));
+            }
+        }
 
         let kp = JwkKeyPair::decrypt(&jwk, jwk.signature.clone())?;
 
@@ -505,38 +935,36 @@ impl JwkKeyPair {
     }
 
     // Returns the latest JWK (especially important after a [JWK Rotation](crate::handlers::rotate_jwk)
-    // by a given algorithm.
+    // by a given algorithm. Keys scheduled for a future activation and retired keys are skipped.
     pub async fn find_latest(
         data: &web::Data<AppState>,
         alg: &str,
         key_pair_type: JwkKeyPairAlg,
     ) -> Result<Self, ErrorResponse> {
         let idx = format!("{}{}", IDX_JWK_LATEST, &alg);
-        let jwk_opt = cache_get!(
-            JwkKeyPair,
+        let jwk_opt = cache_get::<JwkKeyPair>(
             CACHE_NAME_12HR.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(jwk_opt) = jwk_opt {
             return Ok(jwk_opt);
         }
 
-        let mut jwks = sqlx::query_as!(Jwk, "select * from jwks")
+        let jwks = sqlx::query_as!(Jwk, "select * from jwks")
             .fetch_all(&data.db)
-            .await?
-            .into_iter()
-            .filter(|jwk| jwk.signature == key_pair_type)
-            .collect::<Vec<Jwk>>();
+            .await?;
+        let lifecycles = JwkLifecycle::find_all(&data.db).await?;
+        let now = Utc::now().timestamp();
 
-        jwks.sort_by(|a, b| a.created_at.cmp(&b.created_at));
-        if jwks.is_empty() {
-            panic!("No latest JWK found - database corrupted?");
-        }
+        let latest = match select_active(&jwks, &lifecycles, &key_pair_type, now) {
+            None => panic!("No latest JWK found - database corrupted?"),
+            Some(jwk) => jwk,
+        };
 
-        let jwk = JwkKeyPair::decrypt(jwks.first().unwrap(), key_pair_type)?;
+        let jwk = JwkKeyPair::decrypt(latest, key_pair_type)?;
 
         cache_put(
             CACHE_NAME_12HR.to_string(),
@@ -905,6 +1333,9 @@ mod tests {
                 typ: JwtTokenType::Refresh,
                 uid: "user_id_13337".to_string(),
                 cnf: None,
+                sid: None,
+                authorization_details: None,
+                resource: None,
             },
             coarsetime::Duration::from_secs(300),
         );
@@ -0,0 +1,365 @@
+use crate::app_state::AppState;
+use crate::entity::clients::Client;
+use crate::entity::sessions::Session;
+use crate::entity::users::User;
+use crate::pii;
+use crate::request::NewClientRequest;
+use crate::response::{LabClientCredentials, LabSeedResponse, LabUserCredentials};
+use actix_web::{web, HttpRequest};
+use cryptr::{EncKeys, EncValue};
+use rauthy_common::cache_metrics::cache_remove;
+use rauthy_common::constants::{
+    CACHE_NAME_12HR, CACHE_NAME_USERS, HEADER_X_DEBUG, IDX_USERS, LAB_CLIENT_PREFIX,
+    LAB_EMAIL_DOMAIN, LAB_MODE_SECRET, LAB_MODE_SEED, USER_COUNT_IDX,
+};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::password_hasher::HashPassword;
+use rauthy_common::utils::cache_entry_client;
+use redhac::AckLevel;
+use tracing::{info, warn};
+
+/// The redirect URI for all synthetic lab clients
+const LAB_REDIRECT_URI: &str = "http://localhost:8080/lab/callback";
+const TYP_USER: &str = "user";
+const TYP_CLIENT: &str = "client";
+
+/// The lab mode creates synthetic users and clients with deterministic credentials for security
+/// testing. It is only available when `LAB_MODE_SECRET` is set, and each request must provide
+/// this secret in the `X-Debug` header.
+///
+/// All synthetic data can be identified by the `lab.rauthy.invalid` E-Mail domain for users and
+/// the `lab-client-` prefix for clients. Each object is tagged in the `lab_objects` table as well,
+/// and only tagged objects will ever be modified or deleted. As soon as the lab mode is disabled,
+/// all of them will be purged at the next startup.
+///
+/// Synthetic users and clients are only accepted on requests with a valid `X-Debug` header.
+pub struct LabMode;
+
+impl LabMode {
+    #[inline(always)]
+    pub fn is_enabled() -> bool {
+        LAB_MODE_SECRET.is_some()
+    }
+
+    /// Returns `true` if the lab mode is enabled and the request provides the correct secret.
+    pub fn is_debug_request(req: &HttpRequest) -> bool {
+        let Some(secret) = LAB_MODE_SECRET.as_ref() else {
+            return false;
+        };
+        let Some(header) = req
+            .headers()
+            .get(HEADER_X_DEBUG)
+            .and_then(|h| h.to_str().ok())
+        else {
+            return false;
+        };
+
+        // compare the hashes to not leak the secret via timing
+        hmac_sha256::Hash::hash(header.as_bytes()) == hmac_sha256::Hash::hash(secret.as_bytes())
+    }
+
+    /// Rejects any request without the correct `X-Debug` header with a `404`, so the lab
+    /// endpoints stay invisible.
+    pub fn validate_request(req: &HttpRequest) -> Result<(), ErrorResponse> {
+        if Self::is_debug_request(req) {
+            Ok(())
+        } else {
+            Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Not found".to_string(),
+            ))
+        }
+    }
+
+    /// Synthetic lab users and clients are rejected like invalid credentials, if the request
+    /// does not provide a valid `X-Debug` header.
+    pub fn validate_principal(
+        req: &HttpRequest,
+        email: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let is_lab = email.map(Self::is_lab_user).unwrap_or(false)
+            || client_id.map(Self::is_lab_client).unwrap_or(false);
+        if is_lab && !Self::is_debug_request(req) {
+            warn!("Lab mode principal used without a valid X-Debug header");
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "Invalid user credentials".to_string(),
+            )
+            .with_code(ErrorCode::InvalidCredentials));
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn is_lab_user(email: &str) -> bool {
+        email
+            .to_lowercase()
+            .ends_with(&format!("@{}", LAB_EMAIL_DOMAIN))
+    }
+
+    #[inline(always)]
+    pub fn is_lab_client(client_id: &str) -> bool {
+        client_id.starts_with(LAB_CLIENT_PREFIX)
+    }
+
+    /// Deterministic credential for the given synthetic object, derived from `LAB_MODE_SEED`.
+    fn credential(name: &str) -> Result<String, ErrorResponse> {
+        let seed = LAB_MODE_SEED.as_ref().ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "LAB_MODE_SEED is not set".to_string(),
+            )
+        })?;
+        let mac = hmac_sha256::HMAC::mac(name.as_bytes(), seed.as_bytes());
+        Ok(hex::encode(mac))
+    }
+
+    async fn is_tagged(
+        data: &web::Data<AppState>,
+        typ: &str,
+        id: &str,
+    ) -> Result<bool, ErrorResponse> {
+        let res = sqlx::query!(
+            r#"select count(*) as "count!: i64" from lab_objects where typ = $1 and id = $2"#,
+            typ,
+            id
+        )
+        .fetch_one(&data.db)
+        .await?;
+        Ok(res.count > 0)
+    }
+
+    async fn tag(data: &web::Data<AppState>, typ: &str, id: &str) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            "insert into lab_objects (typ, id) values ($1, $2) on conflict do nothing",
+            typ,
+            id
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    fn err_not_lab_object(id: &str) -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "'{}' exists already and has not been created by the lab mode",
+                id
+            ),
+        )
+    }
+
+    /// Creates (or resets) the given amount of synthetic users and clients. Calling this
+    /// multiple times with the same `LAB_MODE_SEED` always results in the same credentials.
+    pub async fn seed(
+        data: &web::Data<AppState>,
+        users: u8,
+        clients: u8,
+    ) -> Result<LabSeedResponse, ErrorResponse> {
+        let mut resp = LabSeedResponse {
+            users: Vec::with_capacity(users as usize),
+            clients: Vec::with_capacity(clients as usize),
+        };
+
+        for i in 1..=users {
+            let email = format!("lab-user-{}@{}", i, LAB_EMAIL_DOMAIN);
+            // upper, lower, digits and special chars to match any password policy
+            let password = format!("Lab-{}!", &Self::credential(&format!("user:{}", i))?[..32]);
+
+            let mut user = match User::find_by_email(data, email.clone()).await {
+                Ok(user) => {
+                    if !Self::is_tagged(data, TYP_USER, &user.id).await? {
+                        return Err(Self::err_not_lab_object(&email));
+                    }
+                    user
+                }
+                Err(_) => {
+                    let new_user = User {
+                        email: email.clone(),
+                        email_verified: true,
+                        given_name: "Lab".to_string(),
+                        family_name: format!("User {}", i),
+                        ..Default::default()
+                    };
+                    let user = User::create_federated(data, new_user).await?;
+                    Self::tag(data, TYP_USER, &user.id).await?;
+                    user
+                }
+            };
+            user.password = Some(HashPassword::hash_password(password.clone()).await?);
+            user.enabled = true;
+            user.save(data, None, None).await?;
+
+            resp.users.push(LabUserCredentials {
+                id: user.id,
+                email,
+                password,
+            });
+        }
+
+        for i in 1..=clients {
+            let id = format!("{}{}", LAB_CLIENT_PREFIX, i);
+            let secret = Self::credential(&format!("client:{}", i))?;
+
+            let mut client = match Client::find(data, id.clone()).await {
+                Ok(client) => {
+                    if !Self::is_tagged(data, TYP_CLIENT, &client.id).await? {
+                        return Err(Self::err_not_lab_object(&id));
+                    }
+                    client
+                }
+                Err(_) => {
+                    let client = Client::create(
+                        data,
+                        NewClientRequest {
+                            id: id.clone(),
+                            secret: None,
+                            name: Some(format!("Lab Client {}", i)),
+                            confidential: true,
+                            redirect_uris: vec![LAB_REDIRECT_URI.to_string()],
+                            post_logout_redirect_uris: None,
+                        },
+                    )
+                    .await?;
+                    Self::tag(data, TYP_CLIENT, &client.id).await?;
+                    client
+                }
+            };
+            client.enabled = true;
+            client.confidential = true;
+            client.secret = Some(EncValue::encrypt(secret.as_bytes())?.into_bytes().to_vec());
+            client.secret_kid = Some(EncKeys::get_static().enc_key_active.clone());
+            client.flows_enabled =
+                "authorization_code,client_credentials,password,refresh_token".to_string();
+            client.save(data, None).await?;
+
+            resp.clients.push(LabClientCredentials {
+                id,
+                secret,
+                redirect_uri: LAB_REDIRECT_URI.to_string(),
+            });
+        }
+
+        info!(
+            "Lab mode: seeded {} synthetic users and {} synthetic clients",
+            users, clients
+        );
+
+        Ok(resp)
+    }
+
+    /// Removes all synthetic users and clients inside a single transaction.
+    /// Only objects, which have been tagged during the seed, are deleted.
+    pub async fn purge(data: &web::Data<AppState>) -> Result<(u64, u64), ErrorResponse> {
+        let users = sqlx::query!(
+            r#"select id, email from users
+            where id in (select id from lab_objects where typ = $1)"#,
+            TYP_USER
+        )
+        .fetch_all(&data.db)
+        .await?;
+        let clients = sqlx::query!(
+            "select id from clients where id in (select id from lab_objects where typ = $1)",
+            TYP_CLIENT
+        )
+        .fetch_all(&data.db)
+        .await?;
+        if users.is_empty() && clients.is_empty() {
+            sqlx::query!("delete from lab_objects")
+                .execute(&data.db)
+                .await?;
+            return Ok((0, 0));
+        }
+
+        // sessions live in the cache as well and cannot be cleaned up after the users are gone
+        for user in &users {
+            Session::delete_by_user(data, &user.id).await?;
+        }
+
+        let mut txn = data.db.begin().await?;
+        let deleted_users = sqlx::query!(
+            "delete from users where id in (select id from lab_objects where typ = $1)",
+            TYP_USER
+        )
+        .execute(&mut *txn)
+        .await?
+        .rows_affected();
+        let deleted_clients = sqlx::query!(
+            "delete from clients where id in (select id from lab_objects where typ = $1)",
+            TYP_CLIENT
+        )
+        .execute(&mut *txn)
+        .await?
+        .rows_affected();
+        sqlx::query!("delete from lab_objects")
+            .execute(&mut *txn)
+            .await?;
+        txn.commit().await?;
+
+        // the database is consistent at this point -> clean up the caches
+        for user in users {
+            for idx in [user.id, pii::decrypt_email(user.email)?] {
+                cache_remove(
+                    CACHE_NAME_USERS.to_string(),
+                    format!("{}_{}", IDX_USERS, idx),
+                    &data.caches.ha_cache_config,
+                    AckLevel::Quorum,
+                )
+                .await?;
+            }
+        }
+        for client in clients {
+            cache_remove(
+                CACHE_NAME_12HR.to_string(),
+                cache_entry_client(&client.id),
+                &data.caches.ha_cache_config,
+                AckLevel::Quorum,
+            )
+            .await?;
+        }
+        cache_remove(
+            CACHE_NAME_12HR.to_string(),
+            USER_COUNT_IDX.to_string(),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        warn!(
+            "Lab mode: purged {} synthetic users and {} synthetic clients",
+            deleted_users, deleted_clients
+        );
+
+        Ok((deleted_users, deleted_clients))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_validate_principal() {
+        // LAB_MODE_SECRET is not set in tests -> no request can be a valid debug request
+        let req = TestRequest::default()
+            .insert_header((HEADER_X_DEBUG, "some-guessed-secret"))
+            .to_http_request();
+
+        assert!(
+            LabMode::validate_principal(&req, Some("admin@localhost.de"), Some("rauthy")).is_ok()
+        );
+        assert!(LabMode::validate_principal(&req, None, None).is_ok());
+
+        let email = format!("lab-user-1@{}", LAB_EMAIL_DOMAIN);
+        let client_id = format!("{}1", LAB_CLIENT_PREFIX);
+        assert!(LabMode::validate_principal(&req, Some(&email), None).is_err());
+        assert!(LabMode::validate_principal(&req, None, Some(&client_id)).is_err());
+        assert!(
+            LabMode::validate_principal(&req, Some("admin@localhost.de"), Some(&client_id))
+                .is_err()
+        );
+    }
+}
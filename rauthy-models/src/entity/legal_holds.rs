@@ -0,0 +1,117 @@
+use crate::app_state::AppState;
+use crate::events::event::Event;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Exempts the audit data of a user from all retention purges and deletion flows.
+///
+/// As long as a hold exists, the user cannot be deleted and events mentioning the user's
+/// E-Mail are skipped by the events cleanup. The hold must be released explicitly.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct LegalHold {
+    pub user_id: String,
+    pub reason: String,
+    pub created: i64,
+}
+
+impl LegalHold {
+    /// Sets the hold and commits the given audit `event` together with it.
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        reason: String,
+        event: Event,
+    ) -> Result<Self, ErrorResponse> {
+        let mut txn = data.db.begin().await?;
+
+        // an existing hold only gets a new reason, the original creation time is kept
+        let slf = if let Some(mut slf) = Self::find(data, &user_id).await? {
+            sqlx::query!(
+                "update legal_holds set reason = $1 where user_id = $2",
+                reason,
+                user_id,
+            )
+            .execute(&mut *txn)
+            .await?;
+            slf.reason = reason;
+            slf
+        } else {
+            let slf = Self {
+                user_id,
+                reason,
+                created: Utc::now().timestamp(),
+            };
+            sqlx::query!(
+                "insert into legal_holds (user_id, reason, created) values ($1, $2, $3)",
+                slf.user_id,
+                slf.reason,
+                slf.created,
+            )
+            .execute(&mut *txn)
+            .await?;
+            slf
+        };
+
+        event.outbox_insert(&mut txn).await?;
+        txn.commit().await?;
+        data.tx_events.send_async(event).await.unwrap();
+
+        Ok(slf)
+    }
+
+    /// Releases the hold and commits the given audit `event` together with it.
+    pub async fn delete(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        event: Event,
+    ) -> Result<(), ErrorResponse> {
+        let mut txn = data.db.begin().await?;
+        sqlx::query!("delete from legal_holds where user_id = $1", user_id)
+            .execute(&mut *txn)
+            .await?;
+        event.outbox_insert(&mut txn).await?;
+        txn.commit().await?;
+
+        data.tx_events.send_async(event).await.unwrap();
+        Ok(())
+    }
+
+    pub async fn find(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from legal_holds where user_id = $1",
+            user_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from legal_holds")
+            .fetch_all(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns an error if the user is under a legal hold and must therefore not be deleted.
+    pub async fn check_deletable(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<(), ErrorResponse> {
+        if Self::find(data, user_id).await?.is_some() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "The user is under a legal hold and cannot be deleted until it is released"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
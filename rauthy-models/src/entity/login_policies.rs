@@ -0,0 +1,529 @@
+use crate::app_state::AppState;
+use crate::cache_invalidation::Invalidation;
+use crate::entity::groups::Group;
+use crate::entity::users::User;
+use crate::events::event::Event;
+use crate::request::GroupLoginPolicyRequest;
+use actix_web::web;
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::{CACHE_NAME_12HR, IDX_GROUP_LOGIN_POLICIES};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use redhac::{cache_insert, AckLevel};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::str::FromStr;
+use tracing::debug;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Restricts the times at which members of a group are allowed to log in.
+///
+/// All times are local times in an IANA time zone, which follows daylight saving time. Older
+/// policies without a time zone use a fixed UTC offset instead.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct GroupLoginPolicy {
+    pub group_id: String,
+    /// Bitmask of the allowed weekdays, starting with `1` for Monday up to `64` for Sunday
+    pub weekdays: i64,
+    /// Start of the allowed window in minutes since midnight
+    pub time_from: i64,
+    /// End of the allowed window in minutes since midnight, exclusive.
+    /// If it is smaller than `time_from`, the window spans over midnight into the next day.
+    /// If both are the same, the whole day is allowed.
+    pub time_to: i64,
+    /// The UTC offset of the local time in minutes, only used without a `timezone`
+    pub utc_offset: i64,
+    /// The IANA time zone of the local time like `Europe/Berlin`
+    pub timezone: Option<String>,
+}
+
+// CRUD
+impl GroupLoginPolicy {
+    pub async fn delete(data: &web::Data<AppState>, group_id: String) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!(
+            "delete from group_login_policies where group_id = $1",
+            group_id
+        )
+        .execute(&data.db)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "No login policy exists for this group".to_string(),
+            ));
+        }
+
+        Self::invalidate_cache(data).await
+    }
+
+    pub async fn find(data: &web::Data<AppState>, group_id: &str) -> Result<Self, ErrorResponse> {
+        Self::find_all(data)
+            .await?
+            .into_iter()
+            .find(|p| p.group_id == group_id)
+            .ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::NotFound,
+                    "No login policy exists for this group".to_string(),
+                )
+            })
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        if let Some(policies) = cache_get::<Vec<Self>>(
+            CACHE_NAME_12HR.to_string(),
+            IDX_GROUP_LOGIN_POLICIES.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        {
+            return Ok(policies);
+        }
+
+        let res = sqlx::query_as!(Self, "select * from group_login_policies")
+            .fetch_all(&data.db)
+            .await?;
+
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_GROUP_LOGIN_POLICIES.to_string(),
+            &data.caches.ha_cache_config,
+            &res,
+            AckLevel::Leader,
+        )
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn upsert(
+        data: &web::Data<AppState>,
+        group_id: String,
+        req: GroupLoginPolicyRequest,
+    ) -> Result<Self, ErrorResponse> {
+        // make sure the group exists for a nicer error than the constraint violation
+        let group = Group::find(data, group_id).await?;
+        let slf = Self::from_req(group.id, req)?;
+
+        #[cfg(not(feature = "postgres"))]
+        let q = sqlx::query!(
+            r#"insert or replace into group_login_policies
+            (group_id, weekdays, time_from, time_to, utc_offset, timezone)
+            values ($1, $2, $3, $4, $5, $6)"#,
+            slf.group_id,
+            slf.weekdays,
+            slf.time_from,
+            slf.time_to,
+            slf.utc_offset,
+            slf.timezone,
+        );
+
+        #[cfg(feature = "postgres")]
+        let q = sqlx::query!(
+            r#"insert into group_login_policies
+            (group_id, weekdays, time_from, time_to, utc_offset, timezone)
+            values ($1, $2, $3, $4, $5, $6)
+            on conflict(group_id) do update
+            set weekdays = $2, time_from = $3, time_to = $4, utc_offset = $5, timezone = $6"#,
+            slf.group_id,
+            slf.weekdays,
+            slf.time_from,
+            slf.time_to,
+            slf.utc_offset,
+            slf.timezone,
+        );
+
+        q.execute(&data.db).await?;
+
+        Self::invalidate_cache(data).await?;
+        Ok(slf)
+    }
+
+    async fn invalidate_cache(data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        cache_remove(
+            CACHE_NAME_12HR.to_string(),
+            IDX_GROUP_LOGIN_POLICIES.to_string(),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Invalidation::Policy.publish(data).await;
+        Ok(())
+    }
+}
+
+impl GroupLoginPolicy {
+    /// Checks all policies for the groups of the given user.
+    ///
+    /// A login is only allowed if each policy allows it at this time, or if an admin has
+    /// granted a still valid override for the user.
+    pub async fn check_user(data: &web::Data<AppState>, user: &User) -> Result<(), ErrorResponse> {
+        let policies = Self::find_all(data).await?;
+        if policies.is_empty() || user.groups.is_none() {
+            return Ok(());
+        }
+
+        let user_groups = user.get_groups();
+        let now = Utc::now();
+        let mut denied_by = None;
+        for group in Group::find_all(data).await? {
+            if !user_groups.contains(&group.name) {
+                continue;
+            }
+            if let Some(policy) = policies.iter().find(|p| p.group_id == group.id) {
+                if !policy.is_allowed(now) {
+                    denied_by = Some(group.name);
+                    break;
+                }
+            }
+        }
+
+        let Some(group_name) = denied_by else {
+            return Ok(());
+        };
+
+        if LoginPolicyOverride::find_valid(data, &user.id)
+            .await?
+            .is_some()
+        {
+            debug!(
+                "Login policy for group '{}' overridden for user {}",
+                group_name, user.id
+            );
+            return Ok(());
+        }
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            format!(
+                "Logins for members of the group '{}' are not allowed at this time",
+                group_name
+            ),
+        )
+        .with_code(ErrorCode::LoginRestricted))
+    }
+
+    pub fn from_req(group_id: String, req: GroupLoginPolicyRequest) -> Result<Self, ErrorResponse> {
+        let mut weekdays = 0;
+        for day in req.weekdays {
+            let idx = WEEKDAYS.iter().position(|d| *d == day).ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("Invalid weekday: {}", day),
+                )
+            })?;
+            weekdays |= 1 << idx;
+        }
+        if weekdays == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "At least one weekday must be allowed".to_string(),
+            ));
+        }
+
+        let (utc_offset, timezone) = match (req.timezone, req.utc_offset) {
+            (Some(tz), _) => (0, Some(parse_timezone(&tz)?.name().to_string())),
+            (None, Some(offset)) => (parse_utc_offset(&offset)?, None),
+            (None, None) => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "Either a 'timezone' or an 'utc_offset' is required".to_string(),
+                ));
+            }
+        };
+
+        Ok(Self {
+            group_id,
+            weekdays,
+            time_from: parse_hh_mm(&req.time_from)?,
+            time_to: parse_hh_mm(&req.time_to)?,
+            utc_offset,
+            timezone,
+        })
+    }
+
+    /// Returns `true` if a login is allowed at the given point in time.
+    pub fn is_allowed(&self, now: DateTime<Utc>) -> bool {
+        let local = local_time(now, self.timezone.as_deref(), self.utc_offset);
+        let minute = (local.hour() * 60 + local.minute()) as i64;
+        let today = local.weekday().num_days_from_monday();
+        let yesterday = (today + 6) % 7;
+
+        if self.time_from == self.time_to {
+            self.has_weekday(today)
+        } else if self.time_from < self.time_to {
+            self.has_weekday(today) && minute >= self.time_from && minute < self.time_to
+        } else {
+            // the window spans midnight and belongs to the day it started on
+            (self.has_weekday(today) && minute >= self.time_from)
+                || (self.has_weekday(yesterday) && minute < self.time_to)
+        }
+    }
+
+    pub fn weekdays_str(&self) -> Vec<String> {
+        WEEKDAYS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.has_weekday(*i as u32))
+            .map(|(_, d)| d.to_string())
+            .collect()
+    }
+
+    #[inline]
+    fn has_weekday(&self, days_from_monday: u32) -> bool {
+        self.weekdays & (1 << days_from_monday) != 0
+    }
+}
+
+/// A temporary exception from all group login policies for a single user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct LoginPolicyOverride {
+    pub user_id: String,
+    pub exp: i64,
+}
+
+impl LoginPolicyOverride {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        minutes: u32,
+        event: Event,
+    ) -> Result<Self, ErrorResponse> {
+        let slf = Self {
+            user_id,
+            exp: Utc::now().timestamp() + minutes as i64 * 60,
+        };
+
+        #[cfg(not(feature = "postgres"))]
+        let q = sqlx::query!(
+            "insert or replace into login_policy_overrides (user_id, exp) values ($1, $2)",
+            slf.user_id,
+            slf.exp,
+        );
+
+        #[cfg(feature = "postgres")]
+        let q = sqlx::query!(
+            r#"insert into login_policy_overrides (user_id, exp) values ($1, $2)
+            on conflict(user_id) do update set exp = $2"#,
+            slf.user_id,
+            slf.exp,
+        );
+
+        // the audit event is committed together with the override, so it cannot get lost
+        let mut txn = data.db.begin().await?;
+        q.execute(&mut *txn).await?;
+        event.outbox_insert(&mut txn).await?;
+        txn.commit().await?;
+        data.tx_events.send_async(event).await.unwrap();
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, user_id: &str) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            "delete from login_policy_overrides where user_id = $1",
+            user_id
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_valid(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = sqlx::query_as!(
+            Self,
+            "select * from login_policy_overrides where user_id = $1 and exp > $2",
+            user_id,
+            now,
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res)
+    }
+}
+
+pub(crate) fn parse_hh_mm(value: &str) -> Result<i64, ErrorResponse> {
+    let err = || {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Invalid time, expected HH:MM: {}", value),
+        )
+    };
+
+    let (h, m) = value.split_once(':').ok_or_else(err)?;
+    let h = h.parse::<i64>().map_err(|_| err())?;
+    let m = m.parse::<i64>().map_err(|_| err())?;
+    if !(0..24).contains(&h) || !(0..60).contains(&m) {
+        return Err(err());
+    }
+    Ok(h * 60 + m)
+}
+
+pub(crate) fn parse_utc_offset(value: &str) -> Result<i64, ErrorResponse> {
+    let (sign, hh_mm) = if let Some(rest) = value.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = value.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Invalid UTC offset, expected +HH:MM: {}", value),
+        ));
+    };
+    Ok(sign * parse_hh_mm(hh_mm)?)
+}
+
+pub(crate) fn parse_timezone(value: &str) -> Result<Tz, ErrorResponse> {
+    Tz::from_str(value).map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Unknown time zone: {}", value),
+        )
+    })
+}
+
+/// Returns the local time for `now` in the IANA `timezone`, or with the fixed `utc_offset`
+/// in minutes, if there is none.
+pub(crate) fn local_time(
+    now: DateTime<Utc>,
+    timezone: Option<&str>,
+    utc_offset: i64,
+) -> NaiveDateTime {
+    match timezone.and_then(|tz| Tz::from_str(tz).ok()) {
+        Some(tz) => now.with_timezone(&tz).naive_local(),
+        None => (now + Duration::minutes(utc_offset)).naive_utc(),
+    }
+}
+
+/// Formats minutes since midnight as `HH:MM`
+pub fn fmt_hh_mm(minutes: i64) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Formats an UTC offset in minutes as `+HH:MM`
+pub fn fmt_utc_offset(minutes: i64) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    format!("{}{}", sign, fmt_hh_mm(minutes.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn policy(weekdays: &[&str], from: &str, to: &str, offset: &str) -> GroupLoginPolicy {
+        GroupLoginPolicy::from_req(
+            "group".to_string(),
+            GroupLoginPolicyRequest {
+                weekdays: weekdays.iter().map(|d| d.to_string()).collect(),
+                time_from: from.to_string(),
+                time_to: to.to_string(),
+                utc_offset: Some(offset.to_string()),
+                timezone: None,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_login_policy_is_allowed() {
+        let weekdays = ["mon", "tue", "wed", "thu", "fri"];
+        let p = policy(&weekdays, "07:00", "19:00", "+02:00");
+        assert_eq!(p.weekdays, 0b0011111);
+        assert_eq!(p.weekdays_str(), weekdays);
+
+        // 2024-06-03 is a Monday
+        let at = |d, h, m| Utc.with_ymd_and_hms(2024, 6, d, h, m, 0).unwrap();
+        assert!(!p.is_allowed(at(3, 4, 59)));
+        assert!(p.is_allowed(at(3, 5, 0)));
+        assert!(p.is_allowed(at(3, 16, 59)));
+        assert!(!p.is_allowed(at(3, 17, 0)));
+        // Saturday
+        assert!(!p.is_allowed(at(8, 10, 0)));
+        // Sunday 23:00 UTC is already Monday 01:00 local, which is still outside the window
+        assert!(!p.is_allowed(at(9, 23, 0)));
+
+        // overnight window belongs to the day it started on
+        let p = policy(&["fri"], "22:00", "06:00", "+00:00");
+        assert!(p.is_allowed(at(7, 23, 0)));
+        assert!(p.is_allowed(at(8, 5, 59)));
+        assert!(!p.is_allowed(at(8, 6, 0)));
+        assert!(!p.is_allowed(at(8, 23, 0)));
+
+        // the same start and end allows the whole day
+        let p = policy(&["sat", "sun"], "00:00", "00:00", "-05:30");
+        assert!(p.is_allowed(at(8, 12, 0)));
+        assert!(p.is_allowed(at(10, 5, 29)));
+        assert!(!p.is_allowed(at(10, 5, 30)));
+    }
+
+    #[test]
+    fn test_login_policy_timezone() {
+        let p = GroupLoginPolicy::from_req(
+            "group".to_string(),
+            GroupLoginPolicyRequest {
+                weekdays: vec!["mon".to_string()],
+                time_from: "07:00".to_string(),
+                time_to: "19:00".to_string(),
+                utc_offset: Some("+05:00".to_string()),
+                timezone: Some("Europe/Berlin".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(p.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(p.utc_offset, 0);
+
+        // 2024-06-03 is a Monday in summer time with +02:00
+        let at = Utc.with_ymd_and_hms(2024, 6, 3, 5, 0, 0).unwrap();
+        assert!(p.is_allowed(at));
+        let at = Utc.with_ymd_and_hms(2024, 6, 3, 16, 59, 0).unwrap();
+        assert!(p.is_allowed(at));
+        let at = Utc.with_ymd_and_hms(2024, 6, 3, 17, 0, 0).unwrap();
+        assert!(!p.is_allowed(at));
+
+        // 2024-01-08 is a Monday in winter time with +01:00
+        let at = Utc.with_ymd_and_hms(2024, 1, 8, 5, 30, 0).unwrap();
+        assert!(!p.is_allowed(at));
+        let at = Utc.with_ymd_and_hms(2024, 1, 8, 6, 0, 0).unwrap();
+        assert!(p.is_allowed(at));
+        let at = Utc.with_ymd_and_hms(2024, 1, 8, 17, 59, 0).unwrap();
+        assert!(p.is_allowed(at));
+
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_login_policy_parse() {
+        assert_eq!(parse_hh_mm("07:30").unwrap(), 450);
+        assert!(parse_hh_mm("24:00").is_err());
+        assert!(parse_hh_mm("7").is_err());
+        assert_eq!(parse_utc_offset("-05:30").unwrap(), -330);
+        assert!(parse_utc_offset("05:30").is_err());
+        assert_eq!(fmt_hh_mm(450), "07:30");
+        assert_eq!(fmt_utc_offset(-330), "-05:30");
+
+        let req = GroupLoginPolicyRequest {
+            weekdays: vec!["someday".to_string()],
+            time_from: "07:00".to_string(),
+            time_to: "19:00".to_string(),
+            utc_offset: Some("+00:00".to_string()),
+            timezone: None,
+        };
+        assert!(GroupLoginPolicy::from_req("group".to_string(), req).is_err());
+
+        let req = GroupLoginPolicyRequest {
+            weekdays: vec!["mon".to_string()],
+            time_from: "07:00".to_string(),
+            time_to: "19:00".to_string(),
+            utc_offset: None,
+            timezone: None,
+        };
+        assert!(GroupLoginPolicy::from_req("group".to_string(), req).is_err());
+    }
+}
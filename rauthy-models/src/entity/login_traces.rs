@@ -0,0 +1,306 @@
+use crate::app_state::AppState;
+use crate::request::LoginTraceRequest;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::{
+    CACHE_NAME_LOGIN_TRACES, IDX_LOGIN_TRACES, LOGIN_TRACE_MAX_MINUTES, LOGIN_TRACE_MAX_RECORDS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{new_store_id, redact};
+use redhac::{cache_insert, AckLevel};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+use utoipa::ToSchema;
+
+/// An admin enabled, time limited debug trace for logins of a specific user and / or IP.
+///
+/// Traces and their records only live inside the (HA) cache and are never persisted.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginTrace {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub created: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginTraceOutcome {
+    Ok,
+    Failed,
+    Info,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginTraceRecord {
+    pub ts: i64,
+    pub ip: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub step: String,
+    pub outcome: LoginTraceOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl LoginTrace {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        req: LoginTraceRequest,
+    ) -> Result<Self, ErrorResponse> {
+        if req.email.is_none() && req.ip.is_none() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A login trace needs at least an 'email' or an 'ip'".to_string(),
+            ));
+        }
+        if req.minutes > *LOGIN_TRACE_MAX_MINUTES {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "A login trace cannot be active for more than {} minutes",
+                    *LOGIN_TRACE_MAX_MINUTES
+                ),
+            ));
+        }
+
+        let now = Utc::now().timestamp();
+        let slf = Self {
+            id: new_store_id(),
+            email: req.email.map(|e| e.to_lowercase()),
+            ip: req.ip.map(|ip| ip.to_string()),
+            created: now,
+            exp: now + req.minutes as i64 * 60,
+        };
+
+        let mut traces = Self::find_all(data).await?;
+        traces.push(slf.clone());
+        Self::save_all(data, &traces).await?;
+
+        cache_insert(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            Self::cache_idx_records(&slf.id),
+            &data.caches.ha_cache_config,
+            &Vec::<LoginTraceRecord>::default(),
+            AckLevel::Leader,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let traces = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|t| t.id != id)
+            .collect::<Vec<_>>();
+        Self::save_all(data, &traces).await?;
+
+        cache_remove(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            Self::cache_idx_records(id),
+            &data.caches.ha_cache_config,
+            AckLevel::Leader,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns all traces, including already expired ones which still have records available.
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let traces = cache_get::<Vec<LoginTrace>>(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            IDX_LOGIN_TRACES.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        .unwrap_or_default();
+        Ok(traces)
+    }
+
+    pub async fn find_records(
+        data: &web::Data<AppState>,
+        id: &str,
+    ) -> Result<Vec<LoginTraceRecord>, ErrorResponse> {
+        cache_get::<Vec<LoginTraceRecord>>(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            Self::cache_idx_records(id),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Login trace does not exist or has been cleaned up already".to_string(),
+            )
+        })
+    }
+
+    async fn save_all(data: &web::Data<AppState>, traces: &Vec<Self>) -> Result<(), ErrorResponse> {
+        cache_insert(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            IDX_LOGIN_TRACES.to_string(),
+            &data.caches.ha_cache_config,
+            traces,
+            AckLevel::Leader,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn append_records(
+        &self,
+        data: &web::Data<AppState>,
+        records: &[LoginTraceRecord],
+    ) -> Result<(), ErrorResponse> {
+        let idx = Self::cache_idx_records(&self.id);
+        let mut existing = cache_get::<Vec<LoginTraceRecord>>(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            idx.clone(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        .unwrap_or_default();
+
+        existing.extend_from_slice(records);
+        let max = *LOGIN_TRACE_MAX_RECORDS;
+        if existing.len() > max {
+            existing.drain(..existing.len() - max);
+        }
+
+        cache_insert(
+            CACHE_NAME_LOGIN_TRACES.to_string(),
+            idx,
+            &data.caches.ha_cache_config,
+            &existing,
+            AckLevel::Leader,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn cache_idx_records(id: &str) -> String {
+        format!("{}{}", IDX_LOGIN_TRACES, id)
+    }
+
+    #[inline]
+    fn matches(&self, now: i64, ip: &str, email: Option<&str>) -> bool {
+        if self.exp < now {
+            return false;
+        }
+        let ip_match = self.ip.as_deref().map(|i| i == ip).unwrap_or(true);
+        let email_match = match (&self.email, email) {
+            (Some(e), Some(given)) => e.eq_ignore_ascii_case(given),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        ip_match && email_match
+    }
+}
+
+/// Collects trace records during a single auth flow request.
+///
+/// If no active trace matches the request, this is a no-op and does not allocate anything.
+/// Records are flushed into the cache with [LoginTracer::finish](LoginTracer::finish).
+#[derive(Debug, Default)]
+pub struct LoginTracer {
+    traces: Vec<LoginTrace>,
+    ip: String,
+    email: Option<String>,
+    records: Vec<LoginTraceRecord>,
+}
+
+impl LoginTracer {
+    pub async fn start(data: &web::Data<AppState>, ip: String, email: Option<&str>) -> Self {
+        let traces = match LoginTrace::find_all(data).await {
+            Ok(traces) => traces,
+            Err(err) => {
+                error!("Error looking up active login traces: {:?}", err);
+                return Self::default();
+            }
+        };
+        if traces.is_empty() {
+            return Self::default();
+        }
+
+        let now = Utc::now().timestamp();
+        let traces = traces
+            .into_iter()
+            .filter(|t| t.matches(now, &ip, email))
+            .collect::<Vec<_>>();
+        if !traces.is_empty() {
+            debug!("Login trace active for ip {} / email {:?}", ip, email);
+        }
+
+        Self {
+            traces,
+            ip,
+            email: email.map(String::from),
+            records: Vec::default(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        !self.traces.is_empty()
+    }
+
+    /// Records an informational step like a policy decision.
+    pub fn info(&mut self, step: &str, detail: impl Into<String>) {
+        if self.is_active() {
+            self.push(step, LoginTraceOutcome::Info, Some(detail.into()));
+        }
+    }
+
+    /// Records the outcome of the given step and returns the result unchanged.
+    pub fn check<T>(
+        &mut self,
+        step: &str,
+        res: Result<T, ErrorResponse>,
+    ) -> Result<T, ErrorResponse> {
+        if self.is_active() {
+            match &res {
+                Ok(_) => self.push(step, LoginTraceOutcome::Ok, None),
+                Err(err) => self.push(
+                    step,
+                    LoginTraceOutcome::Failed,
+                    Some(format!("{}: {}", err.error, err.message)),
+                ),
+            }
+        }
+        res
+    }
+
+    /// Writes all collected records to each matching trace.
+    pub async fn finish(self, data: &web::Data<AppState>) {
+        if self.records.is_empty() {
+            return;
+        }
+        for trace in &self.traces {
+            if let Err(err) = trace.append_records(data, &self.records).await {
+                error!("Error saving login trace records: {:?}", err);
+            }
+        }
+    }
+
+    fn push(&mut self, step: &str, outcome: LoginTraceOutcome, detail: Option<String>) {
+        self.records.push(LoginTraceRecord {
+            ts: Utc::now().timestamp_millis(),
+            ip: self.ip.clone(),
+            email: self.email.clone(),
+            step: step.to_string(),
+            outcome,
+            detail: detail.map(|d| redact(&d)),
+        });
+    }
+}
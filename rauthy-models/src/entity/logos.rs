@@ -1,15 +1,19 @@
 use crate::app_state::AppState;
+use crate::asset_storage;
+use crate::cache_invalidation::Invalidation;
 use actix_web::web;
 use image::imageops::FilterType;
 use image::ImageFormat;
 use jwt_simple::prelude::{Deserialize, Serialize};
+use rauthy_common::cache_metrics::{cache_del, cache_get};
 use rauthy_common::constants::{
     CACHE_NAME_12HR, CONTENT_TYPE_WEBP, IDX_AUTH_PROVIDER_LOGO, IDX_CLIENT_LOGO,
 };
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use sqlx::{query, query_as};
 use std::io::Cursor;
+use std::mem;
 use tracing::debug;
 
 // The default height a client logo will be resized to
@@ -140,6 +144,21 @@ impl Logo {
             &data.caches.ha_cache_config,
         )
         .await?;
+        if typ == &LogoType::Client {
+            Invalidation::Client(id.to_string()).publish(data).await;
+        }
+
+        if asset_storage::is_s3() {
+            for res in [
+                LogoRes::Small,
+                LogoRes::Medium,
+                LogoRes::Large,
+                LogoRes::Custom,
+                LogoRes::Svg,
+            ] {
+                asset_storage::delete(&Self::object_key(typ, id, &res)).await?;
+            }
+        }
 
         Ok(())
     }
@@ -183,7 +202,7 @@ impl Logo {
         Self::delete(data, &id, typ).await?;
 
         // SVG's don't have a resolution, save them as they are
-        let slf = Self {
+        let mut slf = Self {
             id,
             res: LogoRes::Svg,
             content_type,
@@ -236,7 +255,7 @@ impl Logo {
 
             let mut buf = Cursor::new(Vec::with_capacity(48 * 1024));
             image_medium.write_to(&mut buf, ImageFormat::WebP)?;
-            let slf_medium = Self {
+            let mut slf_medium = Self {
                 id,
                 res: logo_res, // will not always be `Medium`, if the given size is smaller than that
                 content_type: CONTENT_TYPE_WEBP.to_string(),
@@ -281,11 +300,18 @@ impl Logo {
     }
 
     async fn upsert_self(
-        &self,
+        &mut self,
         data: &web::Data<AppState>,
         typ: &LogoType,
         with_cache: bool,
     ) -> Result<(), ErrorResponse> {
+        // logos in the S3 storage only keep their metadata inside the database
+        if asset_storage::is_s3() {
+            let bytes = mem::take(&mut self.data);
+            let key = Self::object_key(typ, &self.id, &self.res);
+            asset_storage::put(&key, &self.content_type, bytes).await?;
+        }
+
         let res = self.res.as_str();
 
         // SVGs don't have a resolution -> just save one version
@@ -406,12 +432,11 @@ impl Logo {
         id: &str,
         typ: &LogoType,
     ) -> Result<Self, ErrorResponse> {
-        if let Some(res) = cache_get!(
-            Self,
+        if let Some(res) = cache_get::<Self>(
             CACHE_NAME_12HR.to_string(),
             Self::cache_idx(typ, id),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -434,7 +459,23 @@ impl Logo {
 }
 
 impl Logo {
-    fn cache_idx(typ: &LogoType, id: &str) -> String {
+    /// Logos inside the S3 storage do not have any data in the database. For these, this returns
+    /// the URL they can be downloaded from and the `max-age` for caching the redirect.
+    pub fn s3_url(&self, typ: &LogoType) -> Result<Option<(String, u64)>, ErrorResponse> {
+        if !self.data.is_empty() {
+            return Ok(None);
+        }
+        asset_storage::url(&Self::object_key(typ, &self.id, &self.res)).map(Some)
+    }
+
+    fn object_key(typ: &LogoType, id: &str, res: &LogoRes) -> String {
+        match typ {
+            LogoType::Client => format!("client_logos/{}/{}", id, res.as_str()),
+            LogoType::AuthProvider => format!("auth_provider_logos/{}/{}", id, res.as_str()),
+        }
+    }
+
+    pub(crate) fn cache_idx(typ: &LogoType, id: &str) -> String {
         match typ {
             LogoType::Client => format!("{}_{}", IDX_CLIENT_LOGO, id),
             LogoType::AuthProvider => format!("{}_{}", IDX_AUTH_PROVIDER_LOGO, id),
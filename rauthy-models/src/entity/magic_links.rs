@@ -1,4 +1,5 @@
 use crate::app_state::AppState;
+use crate::entity::users::{AccountType, User};
 use actix_web::{web, HttpRequest};
 use rauthy_common::constants::{PASSWORD_RESET_COOKIE_BINDING, PWD_CSRF_HEADER, PWD_RESET_COOKIE};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
@@ -15,6 +16,11 @@ pub enum MagicLinkUsage {
     EmailChange(String),
     PasswordReset(Option<String>),
     NewUser(Option<String>),
+    /// Re-enrollment of a passkey for a passkey only account, which has lost access to its keys
+    PasskeyReset(Option<String>),
+    /// A one-time password issued by an admin, which can only be redeemed once for a new
+    /// password reset link
+    PasswordOtp,
 }
 
 impl TryFrom<&String> for MagicLinkUsage {
@@ -46,6 +52,14 @@ impl TryFrom<&str> for MagicLinkUsage {
                     MagicLinkUsage::PasswordReset(None)
                 }
             }
+            "passkey_reset" => {
+                if !v.is_empty() {
+                    MagicLinkUsage::PasskeyReset(Some(v.to_string()))
+                } else {
+                    MagicLinkUsage::PasskeyReset(None)
+                }
+            }
+            "password_otp" => MagicLinkUsage::PasswordOtp,
             _ => {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::BadRequest,
@@ -78,6 +92,29 @@ impl Display for MagicLinkUsage {
                     write!(f, "password_reset")
                 }
             }
+            MagicLinkUsage::PasskeyReset(redirect_uri) => {
+                if let Some(uri) = redirect_uri {
+                    write!(f, "passkey_reset${}", uri)
+                } else {
+                    write!(f, "passkey_reset")
+                }
+            }
+            MagicLinkUsage::PasswordOtp => write!(f, "password_otp"),
+        }
+    }
+}
+
+impl MagicLinkUsage {
+    /// Returns the usage for a reset link depending on the account type. Accounts without any
+    /// credentials set up yet get the new user flow, while passkey only accounts never get a
+    /// password set and will re-enroll a passkey instead.
+    pub fn for_reset(user: &User, redirect_uri: Option<String>) -> Self {
+        if user.password.is_none() && !user.has_webauthn_enabled() {
+            Self::NewUser(redirect_uri)
+        } else if user.account_type() == AccountType::Passkey {
+            Self::PasskeyReset(redirect_uri)
+        } else {
+            Self::PasswordReset(redirect_uri)
         }
     }
 }
@@ -152,6 +189,25 @@ impl MagicLink {
         Ok(res)
     }
 
+    /// Returns the pending one-time password, which has been issued by an admin for this user.
+    pub async fn find_password_otp(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let res = sqlx::query_as!(
+            Self,
+            r#"select * from magic_links
+            where user_id = $1 and usage = 'password_otp' and used = false and exp > $2"#,
+            user_id,
+            now,
+        )
+        .fetch_optional(&data.db)
+        .await?;
+
+        Ok(res)
+    }
+
     pub async fn invalidate_all_email_change(
         data: &web::Data<AppState>,
         user_id: &str,
@@ -166,6 +222,33 @@ impl MagicLink {
         Ok(())
     }
 
+    pub async fn invalidate_password_otp(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            "delete from magic_links where user_id = $1 and usage = 'password_otp'",
+            user_id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks this link as used. Returns `false` if it has been used by a concurrent request
+    /// already.
+    pub async fn try_use(&self, data: &web::Data<AppState>) -> Result<bool, ErrorResponse> {
+        let res = sqlx::query!(
+            "update magic_links set used = true where id = $1 and used = false",
+            self.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
     pub async fn save(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
         sqlx::query!(
             "update magic_links set cookie = $1, exp = $2, used = $3 where id = $4",
@@ -307,9 +390,25 @@ mod tests {
         let ml_from = MagicLinkUsage::try_from(&s).unwrap();
         assert_eq!(ml, ml_from);
 
+        let ml = MagicLinkUsage::PasskeyReset(None);
+        let s = ml.to_string();
+        let ml_from = MagicLinkUsage::try_from(&s).unwrap();
+        assert_eq!(ml, ml_from);
+
+        let ml = MagicLinkUsage::PasskeyReset(Some("custom.uri.com".to_string()));
+        let s = ml.to_string();
+        let ml_from = MagicLinkUsage::try_from(&s).unwrap();
+        assert_eq!(ml, ml_from);
+
         let ml = MagicLinkUsage::EmailChange("admin@localhost.de".to_string());
         let s = ml.to_string();
         let ml_from = MagicLinkUsage::try_from(&s).unwrap();
         assert_eq!(ml, ml_from);
+
+        let ml = MagicLinkUsage::PasswordOtp;
+        let s = ml.to_string();
+        assert_eq!(s, "password_otp");
+        let ml_from = MagicLinkUsage::try_from(&s).unwrap();
+        assert_eq!(ml, ml_from);
     }
 }
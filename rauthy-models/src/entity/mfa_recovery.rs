@@ -0,0 +1,301 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::{MFA_RECOVERY_CODE_LIFETIME, MFA_RECOVERY_WAIT_HOURS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::get_rand;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use utoipa::ToSchema;
+
+/// After the waiting period, the recovery can be finished within this time frame.
+const FINISH_WINDOW_SECS: i64 = 72 * 3600;
+/// A new confirmation code is sent out at most once in this interval.
+const REQUEST_INTERVAL_SECS: i64 = 300;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MfaRecoveryStatus {
+    /// Requested, but the E-Mail has not been confirmed yet
+    Pending,
+    /// E-Mail confirmed, the waiting period is running
+    Waiting,
+    Completed,
+    Cancelled,
+}
+
+impl MfaRecoveryStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Pending => "pending",
+            Self::Waiting => "waiting",
+            Self::Completed => "completed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_open(&self) -> bool {
+        matches!(self, Self::Pending | Self::Waiting)
+    }
+}
+
+impl Display for MfaRecoveryStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<String> for MfaRecoveryStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "pending" => Self::Pending,
+            "waiting" => Self::Waiting,
+            "completed" => Self::Completed,
+            // unknown values must never lead to an open recovery
+            _ => Self::Cancelled,
+        }
+    }
+}
+
+/// Account recovery for users, who lost access to all of their MFA factors.
+///
+/// A recovery needs to be confirmed via E-Mail first. Afterward, a waiting period of
+/// `MFA_RECOVERY_WAIT_HOURS` starts, during which the user gets notified and is able to cancel
+/// it. Optionally, an admin needs to approve the recovery as well. When it is finished, all
+/// passkeys and the password get removed, all sessions are revoked and the user can only
+/// re-enroll new factors via a fresh magic link.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaRecovery {
+    pub id: String,
+    pub user_id: String,
+    pub status: MfaRecoveryStatus,
+    pub created: i64,
+    pub confirmed: Option<i64>,
+    pub not_before: Option<i64>,
+    pub approved_by: Option<String>,
+    pub exp: i64,
+}
+
+impl MfaRecovery {
+    /// Creates a new pending recovery and cancels any other open one for this user.
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+    ) -> Result<Self, ErrorResponse> {
+        Self::cancel_for_user(data, &user_id).await?;
+
+        let now = Utc::now().timestamp();
+        let slf = Self {
+            id: get_rand(64),
+            user_id,
+            status: MfaRecoveryStatus::Pending,
+            created: now,
+            confirmed: None,
+            not_before: None,
+            approved_by: None,
+            exp: now + *MFA_RECOVERY_CODE_LIFETIME as i64 * 60,
+        };
+
+        let status = slf.status.as_str();
+        sqlx::query!(
+            r#"INSERT INTO mfa_recoveries
+            (id, user_id, status, created, confirmed, not_before, approved_by, exp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            slf.id,
+            slf.user_id,
+            status,
+            slf.created,
+            slf.confirmed,
+            slf.not_before,
+            slf.approved_by,
+            slf.exp,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, user_id, status, created, confirmed, not_before, approved_by, exp
+            FROM mfa_recoveries WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+
+        // an unknown code gets the same error as an invalid one
+        res.ok_or_else(Self::err_invalid)
+    }
+
+    /// Returns the latest open recovery for the given user, if any.
+    pub async fn find_open_by_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, user_id, status, created, confirmed, not_before, approved_by, exp
+            FROM mfa_recoveries
+            WHERE user_id = $1 AND status IN ('pending', 'waiting')
+            ORDER BY created DESC"#,
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        let now = Utc::now().timestamp();
+        Ok(res.into_iter().find(|r| r.exp > now))
+    }
+
+    pub async fn save(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        let status = self.status.as_str();
+        sqlx::query!(
+            r#"UPDATE mfa_recoveries
+            SET status = $1, confirmed = $2, not_before = $3, approved_by = $4, exp = $5
+            WHERE id = $6"#,
+            status,
+            self.confirmed,
+            self.not_before,
+            self.approved_by,
+            self.exp,
+            self.id,
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Cancels all open recoveries for the given user and returns how many there were.
+    pub async fn cancel_for_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<u64, ErrorResponse> {
+        let res = sqlx::query!(
+            r#"UPDATE mfa_recoveries SET status = 'cancelled'
+            WHERE user_id = $1 AND status IN ('pending', 'waiting')"#,
+            user_id
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+impl MfaRecovery {
+    /// Returns `true` if this open recovery must not be replaced by a new request.
+    ///
+    /// A running waiting period must never be reset. Otherwise, anyone with access to the
+    /// E-Mail could delay the notification about an ongoing recovery forever. Pending ones
+    /// are only replaced after `REQUEST_INTERVAL_SECS` to not flood the inbox.
+    pub fn blocks_new_request(&self) -> bool {
+        self.status == MfaRecoveryStatus::Waiting
+            || self.created > Utc::now().timestamp() - REQUEST_INTERVAL_SECS
+    }
+
+    /// Confirms the E-Mail ownership and starts the waiting period.
+    pub fn confirm(&mut self) -> Result<(), ErrorResponse> {
+        self.validate_status(MfaRecoveryStatus::Pending)?;
+
+        let now = Utc::now().timestamp();
+        let not_before = now + *MFA_RECOVERY_WAIT_HOURS as i64 * 3600;
+        self.status = MfaRecoveryStatus::Waiting;
+        self.confirmed = Some(now);
+        self.not_before = Some(not_before);
+        self.exp = not_before + FINISH_WINDOW_SECS;
+        Ok(())
+    }
+
+    /// Checks if the recovery may be finished right now.
+    pub fn validate_finish(&self, needs_approval: bool) -> Result<(), ErrorResponse> {
+        self.validate_status(MfaRecoveryStatus::Waiting)?;
+
+        let not_before = self.not_before.unwrap_or(i64::MAX);
+        if not_before > Utc::now().timestamp() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                format!(
+                    "The waiting period is still running. The recovery can be finished after {}",
+                    not_before
+                ),
+            ));
+        }
+        if needs_approval && self.approved_by.is_none() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "The recovery has not been approved by an admin yet".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates that the recovery is open, not expired and in the expected state.
+    pub fn validate_status(&self, expected: MfaRecoveryStatus) -> Result<(), ErrorResponse> {
+        if self.status != expected || self.exp < Utc::now().timestamp() {
+            return Err(Self::err_invalid());
+        }
+        Ok(())
+    }
+
+    fn err_invalid() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid or expired recovery code".to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mfa_recovery_state() {
+        let now = Utc::now().timestamp();
+        let mut rec = MfaRecovery {
+            id: get_rand(64),
+            user_id: "user_1".to_string(),
+            status: MfaRecoveryStatus::Pending,
+            created: now,
+            confirmed: None,
+            not_before: None,
+            approved_by: None,
+            exp: now + 3600,
+        };
+
+        // cannot be finished before the confirmation
+        assert!(rec.validate_finish(false).is_err());
+        // a fresh pending recovery is not replaced, an older one is
+        assert!(rec.blocks_new_request());
+        rec.created = now - REQUEST_INTERVAL_SECS - 1;
+        assert!(!rec.blocks_new_request());
+
+        rec.confirm().unwrap();
+        assert_eq!(rec.status, MfaRecoveryStatus::Waiting);
+        assert!(rec.confirm().is_err());
+        // a running waiting period is never replaced
+        assert!(rec.blocks_new_request());
+
+        // waiting period not over yet
+        if *MFA_RECOVERY_WAIT_HOURS > 0 {
+            assert!(rec.validate_finish(false).is_err());
+        }
+
+        rec.not_before = Some(now - 1);
+        assert!(rec.validate_finish(false).is_ok());
+        assert!(rec.validate_finish(true).is_err());
+        rec.approved_by = Some("admin@localhost.de".to_string());
+        assert!(rec.validate_finish(true).is_ok());
+
+        rec.exp = now - 1;
+        assert!(rec.validate_finish(true).is_err());
+
+        assert_eq!(
+            MfaRecoveryStatus::from("something".to_string()),
+            MfaRecoveryStatus::Cancelled
+        );
+    }
+}
@@ -1,38 +1,76 @@
 use crate::app_state::DbPool;
 use sqlx::query;
 
+pub mod announcements;
 pub mod api_keys;
+pub mod app_attestation;
 pub mod app_version;
 pub mod auth_codes;
+pub mod auth_provider_claims;
 pub mod auth_providers;
+pub mod authorization_details;
+pub mod break_glass;
+pub mod cleanup;
+pub mod client_secret_reads;
+pub mod client_user_agents;
 pub mod clients;
 pub mod clients_dyn;
+pub mod cluster_nodes;
 pub mod colors;
 pub mod config;
 pub mod continuation_token;
 pub mod db_version;
 pub mod devices;
 pub mod dpop_proof;
+pub mod email_aliases;
+pub mod email_deliveries;
+pub mod email_mfa;
+pub mod event_recipients;
+pub mod feature_flags;
 pub mod groups;
+pub mod ip_feeds;
 pub mod ip_rate_limit;
+pub mod jobs;
+pub mod jwe;
 pub mod jwk;
 pub mod jwk_token_validation;
+pub mod lab;
+pub mod legal_holds;
+pub mod login_policies;
+pub mod login_traces;
 pub mod logos;
 pub mod magic_links;
+pub mod mfa_recovery;
+pub mod organizations;
 pub mod password;
 pub mod pow;
 pub mod principal;
+pub mod push_mfa;
 pub mod refresh_tokens;
 pub mod refresh_tokens_devices;
+pub mod remote_jwks;
+pub mod request_objects;
+pub mod resource_servers;
+pub mod revoked_tokens;
 pub mod roles;
 pub mod scopes;
 pub mod sessions;
+pub mod spiffe;
+pub mod sso_handoff;
+pub mod used_jtis;
+pub mod user_activity;
 pub mod user_attr;
+pub mod user_client_grants;
+pub mod user_merges;
+pub mod user_notification_prefs;
+pub mod user_role_grants;
 pub mod users;
 pub mod users_values;
+pub mod web_push;
 pub mod webauthn;
 pub mod webids;
 pub mod well_known;
+pub mod workload_identity;
 
 pub async fn is_db_alive(db: &DbPool) -> bool {
     query("SELECT 1").execute(db).await.is_ok()
@@ -0,0 +1,286 @@
+use crate::app_state::AppState;
+use crate::request::OrganizationRequest;
+use crate::OrganizationClaim;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{is_email_in_domains, new_store_id};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// An organization groups users which belong to the same customer or team.
+///
+/// In contrast to groups, an organization has its own owners. Like groups, it may claim E-Mail
+/// domains. Users verifying an address from one of these domains join the organization
+/// automatically. Memberships end up in the `organizations` claim.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    /// CSV of E-Mail domains
+    pub domains: Option<String>,
+    pub created: i64,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct OrganizationMember {
+    pub org_id: String,
+    pub user_id: String,
+    pub is_owner: bool,
+    pub created: i64,
+}
+
+// CRUD
+impl Organization {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        req: OrganizationRequest,
+    ) -> Result<Self, ErrorResponse> {
+        Self::check_name_free(data, &req.name, None).await?;
+
+        let slf = Self {
+            id: new_store_id(),
+            name: req.name,
+            domains: Self::domains_to_csv(req.domains),
+            created: Utc::now().timestamp(),
+        };
+        sqlx::query!(
+            "insert into organizations (id, name, domains, created) values ($1, $2, $3, $4)",
+            slf.id,
+            slf.name,
+            slf.domains,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!("delete from organizations where id = $1", id)
+            .execute(&data.db)
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Organization not found".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from organizations where id = $1", id)
+            .fetch_one(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from organizations order by name asc")
+            .fetch_all(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns all organizations the given user is a member of, in the form they are added to
+    /// the tokens.
+    pub async fn find_for_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Vec<OrganizationClaim>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            OrganizationClaim,
+            r#"select o.id, o.name, m.is_owner as owner
+            from organizations o
+            join organization_members m on m.org_id = o.id
+            where m.user_id = $1
+            order by o.name asc"#,
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn update(
+        data: &web::Data<AppState>,
+        id: &str,
+        req: OrganizationRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut slf = Self::find(data, id).await?;
+        Self::check_name_free(data, &req.name, Some(&slf.id)).await?;
+
+        slf.name = req.name;
+        slf.domains = Self::domains_to_csv(req.domains);
+        sqlx::query!(
+            "update organizations set name = $1, domains = $2 where id = $3",
+            slf.name,
+            slf.domains,
+            slf.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+}
+
+impl Organization {
+    async fn check_name_free(
+        data: &web::Data<AppState>,
+        name: &str,
+        own_id: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let exists = Self::find_all(data)
+            .await?
+            .iter()
+            .any(|o| o.name == name && Some(o.id.as_str()) != own_id);
+        if exists {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Organization already exists".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn domains_to_csv(domains: Option<Vec<String>>) -> Option<String> {
+        domains
+            .filter(|d| !d.is_empty())
+            .map(|d| d.join(",").to_lowercase())
+    }
+
+    pub fn get_domains(&self) -> Vec<String> {
+        self.domains
+            .as_ref()
+            .map(|d| d.split(',').map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Adds the user to each organization, which claims the domain of the given verified E-Mail.
+    /// With an `old_email`, the user leaves all organizations, which only claim the old domain.
+    /// Owners are never removed automatically.
+    pub async fn sync_by_email_domain(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        email: &str,
+        old_email: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        for org in Self::find_all(data).await? {
+            let domains = org.get_domains();
+            if domains.is_empty() {
+                continue;
+            }
+
+            let member = OrganizationMember::find(data, &org.id, user_id).await?;
+            if is_email_in_domains(email, &domains) {
+                if member.is_none() {
+                    OrganizationMember::upsert(data, org.id, user_id.to_string(), false).await?;
+                }
+            } else if old_email.is_some_and(|e| is_email_in_domains(e, &domains)) {
+                if let Some(member) = member.filter(|m| !m.is_owner) {
+                    OrganizationMember::delete(data, &member.org_id, user_id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OrganizationMember {
+    pub async fn delete(
+        data: &web::Data<AppState>,
+        org_id: &str,
+        user_id: &str,
+    ) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!(
+            "delete from organization_members where org_id = $1 and user_id = $2",
+            org_id,
+            user_id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Organization member not found".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn find(
+        data: &web::Data<AppState>,
+        org_id: &str,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from organization_members where org_id = $1 and user_id = $2",
+            org_id,
+            user_id,
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all(
+        data: &web::Data<AppState>,
+        org_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from organization_members where org_id = $1 order by created asc",
+            org_id,
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Adds a new member or updates the owner flag of an existing one.
+    pub async fn upsert(
+        data: &web::Data<AppState>,
+        org_id: String,
+        user_id: String,
+        is_owner: bool,
+    ) -> Result<Self, ErrorResponse> {
+        // an existing member keeps the original join time
+        if let Some(mut slf) = Self::find(data, &org_id, &user_id).await? {
+            sqlx::query!(
+                "update organization_members set is_owner = $1 where org_id = $2 and user_id = $3",
+                is_owner,
+                org_id,
+                user_id,
+            )
+            .execute(&data.db)
+            .await?;
+            slf.is_owner = is_owner;
+            return Ok(slf);
+        }
+
+        let slf = Self {
+            org_id,
+            user_id,
+            is_owner,
+            created: Utc::now().timestamp(),
+        };
+        sqlx::query!(
+            r#"insert into organization_members (org_id, user_id, is_owner, created)
+            values ($1, $2, $3, $4)"#,
+            slf.org_id,
+            slf.user_id,
+            slf.is_owner,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+}
@@ -1,14 +1,16 @@
 use crate::app_state::AppState;
+use crate::cache_invalidation::Invalidation;
 use crate::request::{PasswordHashTimesRequest, PasswordPolicyRequest};
 use actix_web::web;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, PasswordHasher, Version};
 use rand_core::OsRng;
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::{
     ARGON2ID_M_COST_MIN, ARGON2ID_T_COST_MIN, CACHE_NAME_12HR, IDX_PASSWORD_RULES,
 };
 use rauthy_common::error_response::ErrorResponse;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Row};
 use std::cmp::max;
@@ -122,12 +124,11 @@ pub struct PasswordPolicy {
 // CRUD
 impl PasswordPolicy {
     pub async fn find(data: &web::Data<AppState>) -> Result<Self, ErrorResponse> {
-        let policy = cache_get!(
-            PasswordPolicy,
+        let policy = cache_get::<PasswordPolicy>(
             CACHE_NAME_12HR.to_string(),
             IDX_PASSWORD_RULES.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(policy) = policy {
@@ -170,6 +171,7 @@ impl PasswordPolicy {
             AckLevel::Quorum,
         )
         .await?;
+        Invalidation::Policy.publish(data).await;
 
         Ok(())
     }
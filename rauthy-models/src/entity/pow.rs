@@ -1,15 +1,18 @@
 use crate::app_state::AppState;
 use actix_web::web;
-use rauthy_common::constants::{CACHE_NAME_POW, POW_DIFFICULTY, POW_EXP};
+use rauthy_common::cache_metrics::{cache_del, cache_get};
+use rauthy_common::constants::{CACHE_NAME_POW, POW_EXP};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use redhac::{cache_del, cache_get, cache_get_from, cache_get_value, cache_put};
+use rauthy_common::pow_difficulty;
+use redhac::cache_put;
 use spow::pow::Pow;
 
 pub struct PowEntity;
 
 impl PowEntity {
     pub async fn create(data: &web::Data<AppState>) -> Result<Pow, ErrorResponse> {
-        let pow = Pow::with_difficulty(*POW_DIFFICULTY, *POW_EXP)?;
+        let difficulty = pow_difficulty::for_new_challenge();
+        let pow = Pow::with_difficulty(difficulty, *POW_EXP)?;
 
         cache_put(
             CACHE_NAME_POW.to_string(),
@@ -27,12 +30,11 @@ impl PowEntity {
         data: &web::Data<AppState>,
         challenge: String,
     ) -> Result<(), ErrorResponse> {
-        let pow = match cache_get!(
-            Pow,
+        let pow = match cache_get::<Pow>(
             CACHE_NAME_POW.to_string(),
             challenge,
             &data.caches.ha_cache_config,
-            true
+            true,
         )
         .await?
         {
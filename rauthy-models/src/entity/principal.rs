@@ -11,6 +11,9 @@ pub struct Principal {
     pub session: Option<Session>,
     pub api_key: Option<ApiKey>,
     pub roles: Vec<String>,
+    /// `true` if a separate admin listener is configured and this request did not come in
+    /// through it
+    pub admin_api_blocked: bool,
 }
 
 impl Principal {
@@ -78,12 +81,27 @@ impl Principal {
             })
     }
 
+    #[inline(always)]
+    pub fn validate_admin_listener(&self) -> Result<(), ErrorResponse> {
+        if self.admin_api_blocked {
+            trace!("Admin API access blocked on a non-admin listener");
+            Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Not found".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
     #[inline(always)]
     pub fn validate_api_key(
         &self,
         access_group: AccessGroup,
         access_rights: AccessRights,
     ) -> Result<(), ErrorResponse> {
+        self.validate_admin_listener()?;
+
         if let Some(api_key) = &self.api_key {
             match api_key.validate_access(&access_group, &access_rights) {
                 Ok(_) => Ok(()),
@@ -106,6 +124,7 @@ impl Principal {
     /// Validates the Principal's session to only allow authorized Rauthy admin access.
     #[inline(always)]
     pub fn validate_admin_session(&self) -> Result<(), ErrorResponse> {
+        self.validate_admin_listener()?;
         let _session = self.validate_session_auth()?;
         if !self.is_admin() {
             return Err(ErrorResponse::new(
@@ -154,7 +173,9 @@ impl Principal {
     #[inline(always)]
     pub fn validate_user_or_admin(&self, user_id: &str) -> Result<(), ErrorResponse> {
         let session = self.validate_session_auth()?;
-        if !self.is_admin() && session.user_id.as_deref() != Some(user_id) {
+        if session.user_id.as_deref() != Some(user_id)
+            && (!self.is_admin() || self.admin_api_blocked)
+        {
             Err(ErrorResponse::new(
                 ErrorResponseType::Forbidden,
                 "Access is forbidden with this user".to_string(),
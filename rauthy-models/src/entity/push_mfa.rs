@@ -0,0 +1,616 @@
+use crate::app_state::AppState;
+use crate::entity::email_mfa::EmailMfaCode;
+use crate::entity::sessions::Session;
+use crate::entity::users::User;
+use crate::entity::web_push::{WebPushResult, WebPushTarget};
+use crate::entity::webauthn::WebauthnLoginReq;
+use crate::i18n::push_mfa::I18nPushMfa;
+use crate::i18n::SsrJson;
+use crate::{AuthStep, AuthStepAwaitPushMfa, AuthStepAwaitWebauthn};
+use actix_web::http::header;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::web;
+use chrono::Utc;
+use rand::Rng;
+use rauthy_common::constants::{
+    PUB_URL_WITH_SCHEME, PUSH_MFA_ENABLE, PUSH_MFA_REQ_LIFETIME, WEBAUTHN_REQ_EXP,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_url_no_pad_encode, get_rand, new_store_id, resolve_public_addr};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{query, query_as, FromRow};
+use tracing::{debug, error, warn};
+
+/// The max amount of registered push subscriptions per user
+const MAX_SUBSCRIPTIONS: i64 = 10;
+/// Wrong numbers on the approval page, after which the request is denied
+const MAX_ATTEMPTS: i64 = 3;
+
+/// A browser Web Push subscription, which receives login approval requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct PushSubscription {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+// CRUD
+impl PushSubscription {
+    /// Registers a new subscription. An existing subscription for the same endpoint is replaced,
+    /// because a browser re-uses its endpoint, when it subscribes again.
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        name: String,
+        endpoint: String,
+        p256dh: String,
+        auth: String,
+    ) -> Result<Self, ErrorResponse> {
+        let url = reqwest::Url::parse(&endpoint).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Invalid push endpoint".to_string(),
+            )
+        })?;
+        resolve_public_addr(&url).await?;
+
+        Self::delete_by_endpoint(data, &endpoint).await?;
+
+        if Self::count_by_user(data, &user_id).await? >= MAX_SUBSCRIPTIONS {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "A user can register {} push subscriptions at most",
+                    MAX_SUBSCRIPTIONS
+                ),
+            ));
+        }
+
+        let slf = Self {
+            id: new_store_id(),
+            user_id,
+            name,
+            endpoint,
+            p256dh,
+            auth,
+            created: Utc::now().timestamp(),
+            last_used: None,
+        };
+
+        query!(
+            r#"INSERT INTO push_subscriptions
+            (id, user_id, name, endpoint, p256dh, auth, created, last_used)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            slf.id,
+            slf.user_id,
+            slf.name,
+            slf.endpoint,
+            slf.p256dh,
+            slf.auth,
+            slf.created,
+            slf.last_used,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub async fn count_by_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<i64, ErrorResponse> {
+        let res = query!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM push_subscriptions WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(&data.db)
+        .await?;
+        Ok(res.count)
+    }
+
+    pub async fn find_by_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM push_subscriptions WHERE user_id = $1 ORDER BY created",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn delete(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        id: &str,
+    ) -> Result<(), ErrorResponse> {
+        let res = query!(
+            "DELETE FROM push_subscriptions WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(&data.db)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Push subscription not found".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn delete_by_endpoint(
+        data: &web::Data<AppState>,
+        endpoint: &str,
+    ) -> Result<(), ErrorResponse> {
+        query!(
+            "DELETE FROM push_subscriptions WHERE endpoint = $1",
+            endpoint
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_last_used(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+        query!(
+            "UPDATE push_subscriptions SET last_used = $1 WHERE id = $2",
+            now,
+            self.id
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(())
+    }
+}
+
+impl PushSubscription {
+    /// Sends the payload to this subscription. Subscriptions, which have been removed by the
+    /// push service, are deleted.
+    async fn send(&self, data: &web::Data<AppState>, payload: &[u8]) {
+        let target = WebPushTarget {
+            endpoint: &self.endpoint,
+            p256dh: &self.p256dh,
+            auth: &self.auth,
+        };
+
+        match target.send(data, payload, *PUSH_MFA_REQ_LIFETIME).await {
+            Ok(WebPushResult::Sent) => {
+                if let Err(err) = self.update_last_used(data).await {
+                    error!("Error updating push subscription: {}", err.message);
+                }
+            }
+            Ok(WebPushResult::Gone) => {
+                if let Err(err) = Self::delete_by_endpoint(data, &self.endpoint).await {
+                    error!("Error deleting push subscription: {}", err.message);
+                }
+            }
+            Err(err) => error!(
+                "Error sending push message to subscription {}: {}",
+                self.id, err.message
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushMfaStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl PushMfaStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Denied => "denied",
+        }
+    }
+}
+
+impl From<&str> for PushMfaStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "pending" => Self::Pending,
+            "approved" => Self::Approved,
+            // unknown values must never finish a login
+            _ => Self::Denied,
+        }
+    }
+}
+
+/// A login, which waits for an approval via a push notification.
+///
+/// The login page shows a number, which must be entered on the approval page opened from the
+/// notification. The notification itself only contains a random token, and only a hash of it is
+/// stored. The request is bound to the session of the login and the user can always fall back
+/// to the existing factors.
+#[derive(Debug, Clone, FromRow)]
+pub struct PushMfaRequest {
+    pub id: String,
+    pub user_id: String,
+    pub session_id: String,
+    pub number: i64,
+    pub token_hash: String,
+    pub status: String,
+    pub ip: Option<String>,
+    /// The location with the authorization code after a successful login
+    pub header_loc: String,
+    pub header_origin: Option<String>,
+    pub attempts: i64,
+    pub exp: i64,
+}
+
+// CRUD
+impl PushMfaRequest {
+    async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        session_id: String,
+        header_loc: String,
+        header_origin: Option<String>,
+        ip: Option<String>,
+    ) -> Result<(Self, String), ErrorResponse> {
+        query!(
+            "DELETE FROM push_mfa_requests WHERE session_id = $1",
+            session_id
+        )
+        .execute(&data.db)
+        .await?;
+
+        let id = get_rand(48);
+        let token = get_rand(48);
+        let slf = Self {
+            token_hash: Self::hash(&id, &token),
+            id,
+            user_id,
+            session_id,
+            number: rand::thread_rng().gen_range(10..100),
+            status: PushMfaStatus::Pending.as_str().to_string(),
+            ip,
+            header_loc,
+            header_origin,
+            attempts: 0,
+            exp: Utc::now().timestamp() + *PUSH_MFA_REQ_LIFETIME as i64,
+        };
+
+        query!(
+            r#"INSERT INTO push_mfa_requests
+            (id, user_id, session_id, number, token_hash, status, ip, header_loc, header_origin,
+            attempts, exp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            slf.id,
+            slf.user_id,
+            slf.session_id,
+            slf.number,
+            slf.token_hash,
+            slf.status,
+            slf.ip,
+            slf.header_loc,
+            slf.header_origin,
+            slf.attempts,
+            slf.exp,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok((slf, token))
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM push_mfa_requests WHERE id = $1", id)
+            .fetch_optional(&data.db)
+            .await?;
+        res.ok_or_else(Self::err_invalid)
+    }
+
+    pub async fn delete(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM push_mfa_requests WHERE id = $1", self.id)
+            .execute(&data.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired(data: &web::Data<AppState>) -> Result<u64, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = query!("DELETE FROM push_mfa_requests WHERE exp < $1", now)
+            .execute(&data.db)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Sets the new status, if the request is still pending. Returns `false` if another request
+    /// has changed it in the meantime.
+    async fn update_status(
+        &self,
+        data: &web::Data<AppState>,
+        status: PushMfaStatus,
+    ) -> Result<bool, ErrorResponse> {
+        let status = status.as_str();
+        let pending = PushMfaStatus::Pending.as_str();
+        let res = query!(
+            r#"UPDATE push_mfa_requests SET status = $1
+            WHERE id = $2 AND status = $3 AND attempts < $4"#,
+            status,
+            self.id,
+            pending,
+            MAX_ATTEMPTS,
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Counts a wrong number inside the database to not lose any attempts from parallel
+    /// requests and returns the new count. A request is denied with its last attempt.
+    async fn add_failed_attempt(&self, data: &web::Data<AppState>) -> Result<i64, ErrorResponse> {
+        let denied = PushMfaStatus::Denied.as_str();
+        let pending = PushMfaStatus::Pending.as_str();
+        let res = query!(
+            r#"UPDATE push_mfa_requests SET attempts = attempts + 1,
+                status = CASE WHEN attempts + 1 >= $1 THEN $2 ELSE status END
+            WHERE id = $3 AND status = $4
+            RETURNING attempts AS "attempts!: i64""#,
+            MAX_ATTEMPTS,
+            denied,
+            self.id,
+            pending,
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        res.map(|r| r.attempts).ok_or_else(Self::err_invalid)
+    }
+}
+
+impl PushMfaRequest {
+    /// `true` if push approvals are enabled and the user has at least one subscription. Push is
+    /// never a factor on its own and requires another MFA factor as the fallback.
+    pub async fn is_available(
+        data: &web::Data<AppState>,
+        user: &User,
+    ) -> Result<bool, ErrorResponse> {
+        if !*PUSH_MFA_ENABLE || !user.has_mfa_enabled() {
+            return Ok(false);
+        }
+        Ok(PushSubscription::count_by_user(data, &user.id).await? > 0)
+    }
+
+    /// Sends a login approval request to all subscriptions of the user and returns the step,
+    /// which awaits it. The `header_loc` is only released after the login has been approved.
+    pub async fn await_login(
+        data: &web::Data<AppState>,
+        user: &User,
+        session: &Session,
+        header_loc: String,
+        header_origin: Option<(HeaderName, HeaderValue)>,
+        has_password_been_hashed: bool,
+        ip: Option<String>,
+    ) -> Result<AuthStep, ErrorResponse> {
+        let (slf, token) = Self::create(
+            data,
+            user.id.clone(),
+            session.id.clone(),
+            header_loc,
+            header_origin
+                .as_ref()
+                .map(|h| h.1.to_str().unwrap().to_string()),
+            ip,
+        )
+        .await?;
+
+        let subscriptions = PushSubscription::find_by_user(data, &user.id).await?;
+        let payload = slf.payload(user, &token);
+        let data = data.clone();
+        tokio::spawn(async move {
+            for sub in subscriptions {
+                sub.send(&data, payload.as_bytes()).await;
+            }
+        });
+
+        Ok(AuthStep::AwaitPushMfa(AuthStepAwaitPushMfa {
+            has_password_been_hashed,
+            code: slf.id,
+            number: slf.number,
+            header_csrf: Session::get_csrf_header(&session.csrf_token),
+            header_origin,
+            exp: slf.exp,
+        }))
+    }
+
+    /// Cancels this request and continues the login with the other factor of the user.
+    pub async fn fallback(
+        self,
+        data: &web::Data<AppState>,
+        mut session: Session,
+    ) -> Result<AuthStep, ErrorResponse> {
+        self.validate_session(&session.id)?;
+        self.delete(data).await?;
+
+        let user = User::find(data, self.user_id).await?;
+        let header_origin = self.header_origin.as_deref().map(|o| {
+            (
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_str(o).unwrap(),
+            )
+        });
+
+        if user.has_webauthn_enabled() {
+            session.set_mfa(data, true).await?;
+
+            let step = AuthStepAwaitWebauthn {
+                has_password_been_hashed: false,
+                code: get_rand(48),
+                header_csrf: Session::get_csrf_header(&session.csrf_token),
+                header_origin,
+                user_id: user.id.clone(),
+                email: user.email,
+                exp: *WEBAUTHN_REQ_EXP,
+                session,
+            };
+
+            WebauthnLoginReq {
+                code: step.code.clone(),
+                user_id: user.id,
+                header_loc: self.header_loc,
+                header_origin: self.header_origin,
+            }
+            .save(data)
+            .await?;
+
+            Ok(AuthStep::AwaitWebauthn(step))
+        } else if user.has_email_mfa_enabled() {
+            EmailMfaCode::await_login(data, &user, &session, self.header_loc, header_origin, false)
+                .await
+        } else {
+            // the other factor has been removed in the meantime
+            Err(ErrorResponse::new(
+                ErrorResponseType::MfaRequired,
+                "No other MFA factor available".to_string(),
+            ))
+        }
+    }
+
+    /// Approves the login, if the number from the login page matches. A wrong number counts as
+    /// a failed attempt and denies the request, when the max attempts are reached.
+    pub async fn approve(
+        self,
+        data: &web::Data<AppState>,
+        token: &str,
+        number: i64,
+    ) -> Result<(), ErrorResponse> {
+        self.validate_pending(token)?;
+
+        if self.number == number {
+            // a parallel request may have used up the last attempt in the meantime
+            if !self.update_status(data, PushMfaStatus::Approved).await? {
+                return Err(Self::err_invalid());
+            }
+            return Ok(());
+        }
+
+        let attempts = self.add_failed_attempt(data).await?;
+        if attempts >= MAX_ATTEMPTS {
+            warn!(
+                "Max attempts for the push MFA request reached for user {}",
+                self.user_id
+            );
+        }
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "Wrong number".to_string(),
+        ))
+    }
+
+    pub async fn deny(self, data: &web::Data<AppState>, token: &str) -> Result<(), ErrorResponse> {
+        self.validate_pending(token)?;
+        debug!("Push MFA request denied by user {}", self.user_id);
+        if !self.update_status(data, PushMfaStatus::Denied).await? {
+            return Err(Self::err_invalid());
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> PushMfaStatus {
+        PushMfaStatus::from(self.status.as_str())
+    }
+
+    /// Validates, that this request belongs to the given session and has not expired yet.
+    pub fn validate_session(&self, session_id: &str) -> Result<(), ErrorResponse> {
+        if self.session_id != session_id || self.exp < Utc::now().timestamp() {
+            return Err(Self::err_invalid());
+        }
+        Ok(())
+    }
+
+    fn validate_pending(&self, token: &str) -> Result<(), ErrorResponse> {
+        if self.status() != PushMfaStatus::Pending
+            || self.exp < Utc::now().timestamp()
+            || Self::hash(&self.id, token) != self.token_hash
+        {
+            return Err(Self::err_invalid());
+        }
+        Ok(())
+    }
+
+    /// The notification payload for the service worker. It must never contain the number.
+    fn payload(&self, user: &User, token: &str) -> String {
+        let i18n = I18nPushMfa::build(&user.language);
+        let url = format!(
+            "{}/auth/v1/push_mfa#id={}&token={}&ip={}&exp={}",
+            *PUB_URL_WITH_SCHEME,
+            self.id,
+            token,
+            self.ip.as_deref().unwrap_or_default(),
+            self.exp,
+        );
+        json!({
+            "title": i18n.notification_title,
+            "body": i18n.notification_body,
+            "url": url,
+        })
+        .to_string()
+    }
+
+    /// The id is used as a salt, so that the same token never results in the same hash.
+    fn hash(id: &str, token: &str) -> String {
+        let hash = digest::digest(&digest::SHA256, format!("{}{}", id, token).as_bytes());
+        base64_url_no_pad_encode(hash.as_ref())
+    }
+
+    fn err_invalid() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid or expired push MFA request".to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_mfa_validate_pending() {
+        let id = get_rand(48);
+        let token = get_rand(48);
+        let mut req = PushMfaRequest {
+            token_hash: PushMfaRequest::hash(&id, &token),
+            id,
+            user_id: "user_1".to_string(),
+            session_id: "session_1".to_string(),
+            number: 42,
+            status: PushMfaStatus::Pending.as_str().to_string(),
+            ip: None,
+            header_loc: "https://localhost".to_string(),
+            header_origin: None,
+            attempts: 0,
+            exp: Utc::now().timestamp() + 60,
+        };
+        assert!(req.validate_pending(&token).is_ok());
+        assert!(req.validate_pending("wrong").is_err());
+        assert!(req.validate_session("session_1").is_ok());
+        assert!(req.validate_session("session_2").is_err());
+
+        req.status = PushMfaStatus::Approved.as_str().to_string();
+        assert!(req.validate_pending(&token).is_err());
+
+        assert_eq!(PushMfaStatus::from("something"), PushMfaStatus::Denied);
+    }
+}
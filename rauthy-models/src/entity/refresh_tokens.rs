@@ -1,6 +1,7 @@
 use crate::app_state::AppState;
 use actix_web::web;
 use chrono::{DateTime, Utc};
+use rauthy_common::constants::REFRESH_TOKEN_IDLE_TIMEOUT;
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -13,10 +14,19 @@ pub struct RefreshToken {
     pub exp: i64,
     pub scope: Option<String>,
     pub is_mfa: bool,
+    /// `None` for tokens issued before the client has been recorded
+    pub client_id: Option<String>,
+    /// The absolute expiry of the whole rotation chain, only set with
+    /// `REFRESH_TOKEN_IDLE_TIMEOUT`
+    pub chain_exp: Option<i64>,
+    /// Fingerprint of the device the token has been issued to, only set with
+    /// `REFRESH_TOKEN_BIND_DEVICE` for tokens without a DPoP binding
+    pub device_fp: Option<String>,
 }
 
 // CRUD
 impl RefreshToken {
+    #[allow(clippy::too_many_arguments)]
     pub async fn create(
         data: &web::Data<AppState>,
         id: String,
@@ -28,6 +38,9 @@ impl RefreshToken {
         // even if the original token has been issued with mfa, the refresh
         // token not really is, because it can be given without user interaction.
         is_mfa: bool,
+        client_id: String,
+        chain_exp: Option<i64>,
+        device_fp: Option<String>,
     ) -> Result<Self, ErrorResponse> {
         let rt = Self {
             id,
@@ -36,6 +49,9 @@ impl RefreshToken {
             exp: exp.timestamp(),
             scope,
             is_mfa,
+            client_id: Some(client_id),
+            chain_exp,
+            device_fp,
         };
 
         rt.save(data).await?;
@@ -56,6 +72,44 @@ impl RefreshToken {
         Ok(res)
     }
 
+    /// Returns the amount of currently valid refresh tokens, optionally filtered by `user_id`
+    pub async fn count_valid(
+        data: &web::Data<AppState>,
+        user_id: Option<&str>,
+    ) -> Result<i64, ErrorResponse> {
+        let now = Utc::now().timestamp();
+
+        // sqlite returns an i32 for count while postgres returns an Option<i64>
+        let count = if let Some(uid) = user_id {
+            let res = sqlx::query!(
+                "SELECT COUNT (*) count FROM refresh_tokens WHERE exp > $1 AND user_id = $2",
+                now,
+                uid
+            )
+            .fetch_one(&data.db)
+            .await?;
+            #[cfg(feature = "postgres")]
+            let count = res.count.unwrap_or_default();
+            #[cfg(not(feature = "postgres"))]
+            let count = res.count as i64;
+            count
+        } else {
+            let res = sqlx::query!(
+                "SELECT COUNT (*) count FROM refresh_tokens WHERE exp > $1",
+                now
+            )
+            .fetch_one(&data.db)
+            .await?;
+            #[cfg(feature = "postgres")]
+            let count = res.count.unwrap_or_default();
+            #[cfg(not(feature = "postgres"))]
+            let count = res.count as i64;
+            count
+        };
+
+        Ok(count)
+    }
+
     pub async fn invalidate_all(data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
         let now = Utc::now().timestamp();
 
@@ -83,6 +137,20 @@ impl RefreshToken {
         Ok(())
     }
 
+    pub async fn invalidate(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+
+        sqlx::query!(
+            "UPDATE refresh_tokens SET exp = $1 WHERE exp > $1 AND id = $2",
+            now,
+            id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
         match sqlx::query_as!(Self, "SELECT * FROM refresh_tokens WHERE id = $1", id)
             .fetch_one(&data.db)
@@ -99,26 +167,35 @@ impl RefreshToken {
     pub async fn save(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
         #[cfg(not(feature = "postgres"))]
         let q = sqlx::query!(
-            r#"INSERT OR REPLACE INTO refresh_tokens (id, user_id, nbf, exp, scope, is_mfa)
-                VALUES ($1, $2, $3, $4, $5, $6)"#,
+            r#"INSERT OR REPLACE INTO refresh_tokens
+                (id, user_id, nbf, exp, scope, is_mfa, client_id, chain_exp, device_fp)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
             self.id,
             self.user_id,
             self.nbf,
             self.exp,
             self.scope,
             self.is_mfa,
+            self.client_id,
+            self.chain_exp,
+            self.device_fp,
         );
         #[cfg(feature = "postgres")]
         let q = sqlx::query!(
-            r#"INSERT INTO refresh_tokens (id, user_id, nbf, exp, scope, is_mfa)
-                VALUES ($1, $2, $3, $4, $5, $6)
-                ON CONFLICT(id) DO UPDATE SET user_id = $2, nbf = $3, exp = $4, scope = $5"#,
+            r#"INSERT INTO refresh_tokens
+                (id, user_id, nbf, exp, scope, is_mfa, client_id, chain_exp, device_fp)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT(id) DO UPDATE SET user_id = $2, nbf = $3, exp = $4, scope = $5,
+                    is_mfa = $6, client_id = $7, chain_exp = $8, device_fp = $9"#,
             self.id,
             self.user_id,
             self.nbf,
             self.exp,
             self.scope,
             self.is_mfa,
+            self.client_id,
+            self.chain_exp,
+            self.device_fp,
         );
 
         q.execute(&data.db).await?;
@@ -128,6 +205,19 @@ impl RefreshToken {
 }
 
 impl RefreshToken {
+    /// Returns `true` if the token has expired on its own because it has not been used within
+    /// `REFRESH_TOKEN_IDLE_TIMEOUT` or the chain has reached its absolute lifetime. Rotated
+    /// tokens get a shortened `exp` and will never match.
+    pub fn is_idle_expired(&self) -> bool {
+        let Some(chain_exp) = self.chain_exp else {
+            return false;
+        };
+        let idle_exp = REFRESH_TOKEN_IDLE_TIMEOUT
+            .map(|mins| self.nbf + mins as i64 * 60)
+            .unwrap_or(chain_exp);
+        self.exp == idle_exp.min(chain_exp)
+    }
+
     pub async fn invalidate_all_for_user(
         data: &web::Data<AppState>,
         id: &str,
@@ -139,3 +229,37 @@ impl RefreshToken {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_idle_expired() {
+        let now = Utc::now().timestamp();
+        let mut rt = RefreshToken {
+            id: "rt_1".to_string(),
+            user_id: "user_1".to_string(),
+            nbf: now - 120,
+            exp: now - 60,
+            scope: None,
+            is_mfa: false,
+            client_id: Some("client_1".to_string()),
+            chain_exp: None,
+            device_fp: None,
+        };
+        // tokens without a chain are never idle expired
+        assert!(!rt.is_idle_expired());
+
+        let idle_exp = REFRESH_TOKEN_IDLE_TIMEOUT
+            .map(|mins| rt.nbf + mins as i64 * 60)
+            .unwrap_or(i64::MAX);
+        rt.chain_exp = Some(now - 60);
+        rt.exp = idle_exp.min(now - 60);
+        assert!(rt.is_idle_expired());
+
+        // rotated tokens get a shortened exp
+        rt.exp = now - 90;
+        assert!(!rt.is_idle_expired());
+    }
+}
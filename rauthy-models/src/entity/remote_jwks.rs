@@ -0,0 +1,265 @@
+use crate::app_state::AppState;
+use crate::entity::jwe::{JweAlg, JweEncryptionKey};
+use crate::entity::jwk::{JWKSPublicKey, JwkKeyPairAlg};
+use actix_web::web;
+use once_cell::sync::Lazy;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_12HR, RAUTHY_VERSION};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::base64_url_no_pad_decode;
+use redhac::{cache_insert, AckLevel};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+
+/// An unknown key triggers a re-fetch of the JWKS only once within this interval, so that
+/// random `kid`s cannot be used to flood the remote issuer and the cache with requests.
+const REFETCH_INTERVAL_SECS: i64 = 60;
+
+static LAST_FETCH: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Deserialize)]
+struct RemoteJwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum JwtAudience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl JwtAudience {
+    pub fn contains(&self, aud: &str) -> bool {
+        match self {
+            Self::Single(a) => a == aud,
+            Self::Multiple(a) => a.iter().any(|a| a == aud),
+        }
+    }
+}
+
+/// A JWKS from a remote issuer, which Rauthy trusts for a specific purpose, like a SPIFFE trust
+/// bundle or the Google certs for workload identities.
+pub struct RemoteJwks<'a> {
+    pub url: &'a str,
+    pub cache_idx: &'a str,
+}
+
+impl RemoteJwks<'_> {
+    /// Validates the signature of the given JWT with the matching key from this JWKS and returns
+    /// the deserialized claims. Any further claim validation must be done by the caller.
+    pub async fn validate_jwt<C: DeserializeOwned>(
+        &self,
+        data: &web::Data<AppState>,
+        token: &str,
+    ) -> Result<C, ErrorResponse> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(_), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Self::err_invalid("Malformed JWT"));
+        };
+        let header = serde_json::from_slice::<RemoteJwtHeader>(&base64_url_no_pad_decode(header)?)?;
+        let claims = serde_json::from_slice::<C>(&base64_url_no_pad_decode(claims)?)?;
+
+        let key = self
+            .find_key(data, header.kid.as_deref(), &header.alg)
+            .await?;
+        key.validate_token_signature(token)
+            .map_err(|_| Self::err_invalid("Invalid JWT signature"))?;
+
+        Ok(claims)
+    }
+
+    /// Finds the public key for the given `kid`. The JWKS will be re-fetched, if the key is
+    /// unknown, to pick up key rotations, but only once within `REFETCH_INTERVAL_SECS`.
+    async fn find_key(
+        &self,
+        data: &web::Data<AppState>,
+        kid: Option<&str>,
+        alg: &str,
+    ) -> Result<JWKSPublicKey, ErrorResponse> {
+        let alg = match alg {
+            "RS256" => JwkKeyPairAlg::RS256,
+            "RS384" => JwkKeyPairAlg::RS384,
+            "RS512" => JwkKeyPairAlg::RS512,
+            "EdDSA" => JwkKeyPairAlg::EdDSA,
+            _ => return Err(Self::err_invalid("Unsupported JWT signature algorithm")),
+        };
+
+        let find = |keys: Vec<JWKSPublicKey>| {
+            keys.into_iter()
+                .find(|k| kid.is_none() || k.kid.as_deref() == kid)
+                .map(|mut k| {
+                    // SPIRE does not include the `alg` in its bundle
+                    if k.alg.is_none() {
+                        k.alg = Some(alg.clone());
+                    }
+                    k
+                })
+        };
+
+        if let Some(keys) = cache_get::<Vec<JWKSPublicKey>>(
+            CACHE_NAME_12HR.to_string(),
+            self.cache_idx.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        {
+            if let Some(key) = find(keys) {
+                return Ok(key);
+            }
+        }
+        if !self.may_refetch() {
+            return Err(Self::err_invalid("Unknown JWT signing key"));
+        }
+
+        let keys = self.fetch::<JWKSPublicKey>().await?;
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            self.cache_idx.to_string(),
+            &data.caches.ha_cache_config,
+            &keys,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        find(keys).ok_or_else(|| Self::err_invalid("Unknown JWT signing key"))
+    }
+
+    /// Finds a public key to encrypt responses with the given `alg`. Like for signing keys, the
+    /// JWKS will be re-fetched, if no matching key is cached.
+    pub async fn find_encryption_key(
+        &self,
+        data: &web::Data<AppState>,
+        alg: JweAlg,
+    ) -> Result<JweEncryptionKey, ErrorResponse> {
+        let find = |keys: Vec<JweEncryptionKey>| keys.into_iter().find(|k| k.supports(alg));
+        let err_missing = || {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!(
+                    "The JWKS from {} does not contain a key for '{}'",
+                    self.url, alg
+                ),
+            )
+        };
+
+        if let Some(keys) = cache_get::<Vec<JweEncryptionKey>>(
+            CACHE_NAME_12HR.to_string(),
+            self.cache_idx.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        {
+            if let Some(key) = find(keys) {
+                return Ok(key);
+            }
+        }
+        if !self.may_refetch() {
+            return Err(err_missing());
+        }
+
+        let keys = self.fetch::<JweEncryptionKey>().await?;
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            self.cache_idx.to_string(),
+            &data.caches.ha_cache_config,
+            &keys,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        find(keys).ok_or_else(err_missing)
+    }
+
+    /// Returns `true` and remembers the fetch, if the JWKS has not been fetched within the last
+    /// `REFETCH_INTERVAL_SECS`.
+    fn may_refetch(&self) -> bool {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut last_fetch = LAST_FETCH.lock().unwrap();
+        match last_fetch.get(self.cache_idx) {
+            Some(ts) if now - ts < REFETCH_INTERVAL_SECS => false,
+            _ => {
+                last_fetch.insert(self.cache_idx.to_string(), now);
+                true
+            }
+        }
+    }
+
+    async fn fetch<K: DeserializeOwned>(&self) -> Result<Vec<K>, ErrorResponse> {
+        debug!("Fetching the remote JWKS from {}", self.url);
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(10))
+            .user_agent(format!("Rauthy v{} JWKS Client", RAUTHY_VERSION))
+            .build()?;
+        let res = client.get(self.url).send().await?;
+        if !res.status().is_success() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!(
+                    "Error fetching the JWKS from {}: {}",
+                    self.url,
+                    res.status()
+                ),
+            ));
+        }
+        let json = res.json::<serde_json::Value>().await?;
+
+        // The JWKS may contain key types Rauthy does not support. These are skipped instead of
+        // failing the whole set.
+        let keys = json
+            .get("keys")
+            .and_then(|k| k.as_array())
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|k| serde_json::from_value::<K>(k.clone()).ok())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if keys.is_empty() {
+            warn!(
+                "The JWKS from {} does not contain any supported keys",
+                self.url
+            );
+        }
+
+        Ok(keys)
+    }
+
+    fn err_invalid(msg: &str) -> ErrorResponse {
+        ErrorResponse::new(ErrorResponseType::Unauthorized, msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_may_refetch() {
+        let jwks = RemoteJwks {
+            url: "https://example.org/keys",
+            cache_idx: "test_may_refetch",
+        };
+        assert!(jwks.may_refetch());
+        assert!(!jwks.may_refetch());
+
+        // the interval is tracked for each JWKS on its own
+        let other = RemoteJwks {
+            url: "https://example.org/keys",
+            cache_idx: "test_may_refetch_other",
+        };
+        assert!(other.may_refetch());
+    }
+}
@@ -0,0 +1,648 @@
+use crate::app_state::AppState;
+use crate::entity::clients::Client;
+use crate::entity::remote_jwks::{JwtAudience, RemoteJwks};
+use crate::entity::used_jtis::UsedJti;
+use crate::request::{
+    AuthRequest, LoginRefreshRequest, LoginRequest, ProviderLoginRequest, ResponseMode,
+};
+use crate::AuthorizationDetail;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_REQUEST_URIS, CLOCK_SKEW_LEEWAY, RAUTHY_VERSION};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{
+    base64_url_no_pad_decode, base64_url_no_pad_encode, extract_token_claims_unverified,
+    resolve_public_addr,
+};
+use redhac::cache_put;
+use reqwest::Url;
+use ring::digest;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::debug;
+
+/// The max size of a request object, which is fetched from a `request_uri`
+const REQUEST_URI_MAX_BYTES: usize = 64 * 1024;
+/// The max lifetime of a request object in seconds. Used objects are remembered until they
+/// expire, so the lifetime must be limited.
+const REQUEST_OBJECT_MAX_LIFETIME: i64 = 3600;
+
+/// The claims of a request object (JAR, RFC 9101). They replace the query params of the
+/// authorization request.
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestObjectClaims {
+    pub iss: Option<String>,
+    pub aud: Option<JwtAudience>,
+    pub exp: Option<i64>,
+    pub nbf: Option<i64>,
+    pub jti: Option<String>,
+    pub client_id: Option<String>,
+    pub response_type: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub max_age: Option<i64>,
+    pub prompt: Option<String>,
+    pub response_mode: Option<ResponseMode>,
+    pub login_hint: Option<String>,
+    pub resource: Option<String>,
+    /// A JSON array inside the request object instead of a string like in the query params
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JoseHeader {
+    alg: String,
+}
+
+/// A validated request object from the `request` or `request_uri` param.
+#[derive(Debug)]
+pub struct RequestObject {
+    /// The original JWT. It is passed through the whole login flow and validated again, when the
+    /// authorization code is issued.
+    pub jwt: String,
+    pub claims: RequestObjectClaims,
+    pub signed: bool,
+}
+
+/// The params of a login request, which must match the request object the authorization has
+/// been started with.
+#[derive(Debug)]
+pub struct RequestObjectLogin {
+    pub request: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: Option<Vec<String>>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub response_mode: Option<ResponseMode>,
+    pub resource: Option<String>,
+    pub authorization_details: Option<String>,
+}
+
+impl From<&LoginRequest> for RequestObjectLogin {
+    fn from(value: &LoginRequest) -> Self {
+        Self {
+            request: value.request.clone(),
+            redirect_uri: value.redirect_uri.clone(),
+            scopes: value.scopes.clone(),
+            state: value.state.clone(),
+            nonce: value.nonce.clone(),
+            code_challenge: value.code_challenge.clone(),
+            code_challenge_method: value.code_challenge_method.clone(),
+            response_mode: value.response_mode,
+            resource: value.resource.clone(),
+            authorization_details: value.authorization_details.clone(),
+        }
+    }
+}
+
+impl From<&LoginRefreshRequest> for RequestObjectLogin {
+    fn from(value: &LoginRefreshRequest) -> Self {
+        Self {
+            request: value.request.clone(),
+            redirect_uri: value.redirect_uri.clone(),
+            scopes: value.scopes.clone(),
+            state: value.state.clone(),
+            nonce: value.nonce.clone(),
+            code_challenge: value.code_challenge.clone(),
+            code_challenge_method: value.code_challenge_method.clone(),
+            response_mode: value.response_mode,
+            resource: value.resource.clone(),
+            authorization_details: value.authorization_details.clone(),
+        }
+    }
+}
+
+impl From<&ProviderLoginRequest> for RequestObjectLogin {
+    fn from(value: &ProviderLoginRequest) -> Self {
+        Self {
+            request: value.request.clone(),
+            redirect_uri: value.redirect_uri.clone(),
+            scopes: value.scopes.clone(),
+            state: value.state.clone(),
+            nonce: value.nonce.clone(),
+            code_challenge: value.code_challenge.clone(),
+            code_challenge_method: value.code_challenge_method.clone(),
+            response_mode: value.response_mode,
+            resource: value.resource.clone(),
+            authorization_details: value.authorization_details.clone(),
+        }
+    }
+}
+
+impl RequestObject {
+    /// Resolves the request object of an authorization request, if any.
+    ///
+    /// Returns the location of the expanded authorization request, if the query params do not
+    /// match the request object already. The expanded request contains all params from the
+    /// request object and the object itself as `request`, so the login can be validated against
+    /// it later on. Returns `None`, if the request can be processed as it is.
+    /// `path` is the authorization endpoint the request should be expanded for.
+    pub async fn expand(
+        data: &web::Data<AppState>,
+        req: &AuthRequest,
+        path: &str,
+    ) -> Result<Option<String>, ErrorResponse> {
+        let client = Client::find_maybe_ephemeral(data, req.client_id.clone()).await?;
+
+        let jwt = match (&req.request, &req.request_uri) {
+            (Some(_), Some(_)) => {
+                return Err(err_invalid_request(
+                    "Only one of 'request' and 'request_uri' can be given",
+                ));
+            }
+            (Some(jwt), None) => jwt.clone(),
+            (None, Some(uri)) => Self::fetch(data, &client, uri).await?,
+            (None, None) => {
+                if client.require_signed_request_object {
+                    return Err(err_invalid_request(
+                        "The client requires a signed request object",
+                    ));
+                }
+                return Ok(None);
+            }
+        };
+
+        let slf = Self::validate(data, &client, jwt).await?;
+        if req.request_uri.is_none() && slf.matches(req) {
+            return Ok(None);
+        }
+
+        slf.location(&data.issuer, path).map(Some)
+    }
+
+    /// Validates the login params against the request object the authorization has been
+    /// started with.
+    pub async fn verify_login(
+        data: &web::Data<AppState>,
+        client: &Client,
+        login: RequestObjectLogin,
+    ) -> Result<(), ErrorResponse> {
+        let Some(jwt) = login.request else {
+            if client.require_signed_request_object {
+                return Err(err_invalid_request(
+                    "The client requires a signed request object",
+                ));
+            }
+            return Ok(());
+        };
+
+        let slf = Self::validate(data, client, jwt).await?;
+        let claims = &slf.claims;
+
+        let scopes_match = {
+            let requested = claims
+                .scope
+                .as_deref()
+                .unwrap_or("openid")
+                .split_whitespace()
+                .collect::<HashSet<_>>();
+            let given = login
+                .scopes
+                .as_ref()
+                .map(|s| s.iter().map(String::as_str).collect::<HashSet<_>>())
+                .unwrap_or_default();
+            requested == given
+        };
+
+        if claims.redirect_uri.as_deref() != Some(login.redirect_uri.as_str())
+            || !scopes_match
+            || claims.state != login.state
+            || claims.nonce != login.nonce
+            || claims.code_challenge != login.code_challenge
+            || claims.code_challenge_method != login.code_challenge_method
+            || claims.response_mode != login.response_mode
+            || claims.resource != login.resource
+            || !details_match(
+                &claims.authorization_details,
+                login.authorization_details.as_deref(),
+            )
+        {
+            return Err(err_invalid_request(
+                "The login does not match the request object",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure that a request object can only be used for a single authorization code.
+    /// Must be called right before the code is issued, after the login has been validated
+    /// against the object with `verify_login()`.
+    ///
+    /// Objects without a `jti` are tracked by their hash.
+    pub async fn consume(
+        data: &web::Data<AppState>,
+        jwt: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        let Some(jwt) = jwt else {
+            return Ok(());
+        };
+        let claims = extract_token_claims_unverified::<RequestObjectClaims>(jwt)
+            .map_err(|_| err_invalid_object("Malformed request object"))?;
+        let Some(exp) = claims.exp else {
+            return Err(err_invalid_object("Missing 'exp' in request object"));
+        };
+        let jti = match claims.jti {
+            Some(jti) => jti,
+            None => {
+                base64_url_no_pad_encode(digest::digest(&digest::SHA256, jwt.as_bytes()).as_ref())
+            }
+        };
+
+        let scope = format!("request_object_{}", claims.client_id.unwrap_or_default());
+        if !UsedJti::try_consume(data, &scope, &jti, exp).await? {
+            return Err(err_invalid_object(
+                "The request object has been used already",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates the given request object for the client.
+    ///
+    /// Encrypted request objects are not supported. Rauthy's own keys are signing keys and must
+    /// not be used for decryption.
+    pub async fn validate(
+        data: &web::Data<AppState>,
+        client: &Client,
+        jwt: String,
+    ) -> Result<Self, ErrorResponse> {
+        let (claims, signed) = match jwt.split('.').count() {
+            3 => Self::validate_jws(data, client, &jwt).await?,
+            5 => {
+                return Err(err_invalid_object(
+                    "Encrypted request objects are not supported",
+                ))
+            }
+            _ => return Err(err_invalid_object("Malformed request object")),
+        };
+
+        if !signed && client.require_signed_request_object {
+            return Err(err_invalid_object(
+                "The client requires a signed request object",
+            ));
+        }
+
+        let slf = Self {
+            jwt,
+            claims,
+            signed,
+        };
+        slf.validate_claims(&client.id, &data.issuer)?;
+        Ok(slf)
+    }
+
+    async fn validate_jws(
+        data: &web::Data<AppState>,
+        client: &Client,
+        jwt: &str,
+    ) -> Result<(RequestObjectClaims, bool), ErrorResponse> {
+        let (header, claims) = jwt.split_once('.').unwrap_or_default();
+        let header = serde_json::from_slice::<JoseHeader>(&base64_url_no_pad_decode(header)?)
+            .map_err(|_| err_invalid_object("Malformed request object header"))?;
+
+        if header.alg == "none" {
+            let (claims, _) = claims.split_once('.').unwrap_or_default();
+            let claims =
+                serde_json::from_slice::<RequestObjectClaims>(&base64_url_no_pad_decode(claims)?)
+                    .map_err(|_| err_invalid_object("Malformed request object"))?;
+            return Ok((claims, false));
+        }
+
+        let Some(jwks_uri) = &client.jwks_uri else {
+            return Err(err_invalid_object(
+                "The client has no 'jwks_uri' to validate signed request objects",
+            ));
+        };
+        let cache_idx = format!("client_jwks_{}_{}", client.id, jwks_uri);
+        let claims = RemoteJwks {
+            url: jwks_uri,
+            cache_idx: &cache_idx,
+        }
+        .validate_jwt::<RequestObjectClaims>(data, jwt)
+        .await
+        .map_err(|err| err_invalid_object(&err.message))?;
+
+        Ok((claims, true))
+    }
+
+    fn validate_claims(&self, client_id: &str, issuer: &str) -> Result<(), ErrorResponse> {
+        let claims = &self.claims;
+        if claims.client_id.as_deref() != Some(client_id) {
+            return Err(err_invalid_object(
+                "'client_id' does not match the request object",
+            ));
+        }
+
+        if self.signed {
+            if claims.iss.as_deref() != Some(client_id) {
+                return Err(err_invalid_object("Invalid 'iss' in request object"));
+            }
+            if !claims
+                .aud
+                .as_ref()
+                .map(|a| a.contains(issuer))
+                .unwrap_or(false)
+            {
+                return Err(err_invalid_object("Invalid 'aud' in request object"));
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        let leeway = *CLOCK_SKEW_LEEWAY as i64;
+        let Some(exp) = claims.exp else {
+            return Err(err_invalid_object("Missing 'exp' in request object"));
+        };
+        if exp + leeway < now {
+            return Err(err_invalid_object("The request object has expired"));
+        }
+        if exp - leeway > now + REQUEST_OBJECT_MAX_LIFETIME {
+            return Err(err_invalid_object(
+                "The lifetime of the request object is too long",
+            ));
+        }
+        if claims.nbf.map(|nbf| nbf - leeway > now).unwrap_or(false) {
+            return Err(err_invalid_object("The request object is not valid yet"));
+        }
+
+        Ok(())
+    }
+
+    /// Fetches a request object from a `request_uri`. The URI must be `https` and on the same
+    /// host as the `jwks_uri` or one of the `redirect_uris` of the client. This host must resolve
+    /// to a public address.
+    ///
+    /// Fetched objects are cached for a short time to not hit the same URI over and over again.
+    async fn fetch(
+        data: &web::Data<AppState>,
+        client: &Client,
+        request_uri: &str,
+    ) -> Result<String, ErrorResponse> {
+        let err = err_invalid_uri;
+        let cache_idx = format!("{}_{}", client.id, request_uri);
+        if let Some(jwt) = cache_get::<String>(
+            CACHE_NAME_REQUEST_URIS.to_string(),
+            cache_idx.clone(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        {
+            return Ok(jwt);
+        }
+
+        let url = Url::parse(request_uri).map_err(|_| err("Invalid 'request_uri'"))?;
+        if url.scheme() != "https" {
+            return Err(err("The 'request_uri' must use https"));
+        }
+        let host_allowed = client
+            .get_redirect_uris()
+            .iter()
+            .map(String::as_str)
+            .chain(client.jwks_uri.as_deref())
+            .filter_map(|uri| Url::parse(uri).ok())
+            .any(|uri| uri.host_str().is_some() && uri.host_str() == url.host_str());
+        if !host_allowed {
+            return Err(err("The 'request_uri' host is not allowed for this client"));
+        }
+
+        let addr = resolve_public_addr(&url)
+            .await
+            .map_err(|e| err(&e.message))?;
+
+        debug!("Fetching the request object from {}", url);
+        let http_client = reqwest::Client::builder()
+            .resolve(url.host_str().unwrap_or_default(), addr)
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent(format!("Rauthy v{} Request Object Client", RAUTHY_VERSION))
+            .build()?;
+        let mut res = http_client
+            .get(url)
+            .send()
+            .await
+            .map_err(|_| err("Cannot fetch the 'request_uri'"))?;
+        if !res.status().is_success() {
+            return Err(err("Cannot fetch the 'request_uri'"));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|_| err("Cannot fetch the 'request_uri'"))?
+        {
+            body.extend_from_slice(&chunk);
+            if body.len() > REQUEST_URI_MAX_BYTES {
+                return Err(err("The request object is too big"));
+            }
+        }
+
+        let jwt = String::from_utf8(body)
+            .map(|jwt| jwt.trim().to_string())
+            .map_err(|_| err("Malformed request object"))?;
+
+        cache_put(
+            CACHE_NAME_REQUEST_URIS.to_string(),
+            cache_idx,
+            &data.caches.ha_cache_config,
+            &jwt,
+        )
+        .await?;
+
+        Ok(jwt)
+    }
+
+    /// Returns `true` if the query params of the request match the request object.
+    fn matches(&self, req: &AuthRequest) -> bool {
+        let claims = &self.claims;
+        claims.redirect_uri == req.redirect_uri
+            && claims.response_type == req.response_type
+            && claims.scope.as_deref().unwrap_or("openid") == req.scope
+            && claims.state == req.state
+            && claims.nonce == req.nonce
+            && claims.code_challenge == req.code_challenge
+            && claims.code_challenge_method == req.code_challenge_method
+            && claims.max_age == req.max_age
+            && claims.prompt == req.prompt
+            && claims.response_mode == req.response_mode
+            && claims.login_hint == req.login_hint
+            && claims.resource == req.resource
+            && details_match(
+                &claims.authorization_details,
+                req.authorization_details.as_deref(),
+            )
+    }
+
+    /// Builds the authorization request with all params from the request object.
+    fn location(&self, issuer: &str, path: &str) -> Result<String, ErrorResponse> {
+        let claims = &self.claims;
+        let mut url = Url::parse(&format!("{}{}", issuer, path)).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "Cannot build the authorization URI".to_string(),
+            )
+        })?;
+
+        let max_age = claims.max_age.map(|a| a.to_string());
+        let authorization_details = claims
+            .authorization_details
+            .as_ref()
+            .and_then(|d| serde_json::to_string(d).ok());
+        let params = [
+            ("client_id", claims.client_id.as_deref()),
+            ("redirect_uri", claims.redirect_uri.as_deref()),
+            ("response_type", claims.response_type.as_deref()),
+            ("scope", Some(claims.scope.as_deref().unwrap_or("openid"))),
+            ("state", claims.state.as_deref()),
+            ("nonce", claims.nonce.as_deref()),
+            ("code_challenge", claims.code_challenge.as_deref()),
+            (
+                "code_challenge_method",
+                claims.code_challenge_method.as_deref(),
+            ),
+            ("max_age", max_age.as_deref()),
+            ("prompt", claims.prompt.as_deref()),
+            ("response_mode", claims.response_mode.map(|m| m.as_str())),
+            ("login_hint", claims.login_hint.as_deref()),
+            ("resource", claims.resource.as_deref()),
+            ("authorization_details", authorization_details.as_deref()),
+            ("request", Some(self.jwt.as_str())),
+        ];
+        url.query_pairs_mut()
+            .extend_pairs(params.iter().filter_map(|(k, v)| v.map(|v| (*k, v))));
+
+        Ok(url.to_string())
+    }
+}
+
+/// The `authorization_details` are a JSON array inside the request object, but a string in the
+/// query params.
+fn details_match(claims: &Option<Vec<AuthorizationDetail>>, param: Option<&str>) -> bool {
+    match (claims, param) {
+        (None, None) => true,
+        (Some(claims), Some(param)) => AuthorizationDetail::parse_param(param)
+            .map(|details| &details == claims)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn err_invalid_request(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::BadRequest,
+        format!("invalid_request: {}", msg),
+    )
+}
+
+fn err_invalid_object(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::BadRequest,
+        format!("invalid_request_object: {}", msg),
+    )
+}
+
+fn err_invalid_uri(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::BadRequest,
+        format!("invalid_request_uri: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_object(signed: bool) -> RequestObject {
+        RequestObject {
+            jwt: "eyJhbGciOiJub25lIn0.e30.".to_string(),
+            claims: RequestObjectClaims {
+                iss: Some("app".to_string()),
+                aud: Some(JwtAudience::Single(
+                    "https://iam.example.com/auth/v1".to_string(),
+                )),
+                exp: Some(Utc::now().timestamp() + 60),
+                client_id: Some("app".to_string()),
+                redirect_uri: Some("https://app.example.com/cb".to_string()),
+                response_type: Some("code".to_string()),
+                scope: Some("openid email".to_string()),
+                state: Some("abc".to_string()),
+                ..Default::default()
+            },
+            signed,
+        }
+    }
+
+    #[test]
+    fn test_validate_claims() {
+        let issuer = "https://iam.example.com/auth/v1";
+
+        let mut obj = request_object(true);
+        assert!(obj.validate_claims("app", issuer).is_ok());
+        assert!(obj.validate_claims("other", issuer).is_err());
+        assert!(obj
+            .validate_claims("app", "https://other.example.com")
+            .is_err());
+
+        obj.claims.exp = Some(Utc::now().timestamp() - 3600);
+        assert!(obj.validate_claims("app", issuer).is_err());
+        obj.claims.exp = None;
+        assert!(obj.validate_claims("app", issuer).is_err());
+        obj.claims.exp = Some(Utc::now().timestamp() + 86400);
+        assert!(obj.validate_claims("app", issuer).is_err());
+
+        // unsigned objects do not need 'iss' and 'aud'
+        let mut obj = request_object(false);
+        obj.claims.iss = None;
+        obj.claims.aud = None;
+        assert!(obj.validate_claims("app", issuer).is_ok());
+    }
+
+    #[test]
+    fn test_location() {
+        let obj = request_object(true);
+        let location = obj
+            .location("https://iam.example.com/auth/v1", "/oidc/authorize")
+            .unwrap();
+
+        let url = Url::parse(&location).unwrap();
+        assert_eq!(url.path(), "/auth/v1/oidc/authorize");
+        let params = url.query_pairs().collect::<Vec<_>>();
+        assert_eq!(params.len(), 6);
+        assert!(params
+            .iter()
+            .any(|(k, v)| k == "scope" && v == "openid email"));
+        assert!(params
+            .iter()
+            .any(|(k, v)| k == "request" && v == obj.jwt.as_str()));
+    }
+
+    #[test]
+    fn test_details_match() {
+        let claims = serde_json::from_str::<Vec<AuthorizationDetail>>(
+            r#"[{"type":"payment_initiation","actions":["initiate"]}]"#,
+        )
+        .unwrap();
+
+        assert!(details_match(&None, None));
+        assert!(details_match(
+            &Some(claims.clone()),
+            Some(r#"[{"actions":["initiate"],"type":"payment_initiation"}]"#)
+        ));
+        assert!(!details_match(&Some(claims.clone()), None));
+        assert!(!details_match(
+            &Some(claims),
+            Some(r#"[{"type":"payment_initiation"}]"#)
+        ));
+        assert!(!details_match(&None, Some(r#"[{"type":"x"}]"#)));
+    }
+}
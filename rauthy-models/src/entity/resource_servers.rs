@@ -0,0 +1,388 @@
+use crate::app_state::AppState;
+use crate::entity::clients::Client;
+use crate::entity::scopes::Scope;
+use crate::request::ResourceServerRequest;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_12HR, IDX_RESOURCE_SERVERS};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::new_store_id;
+use redhac::{cache_insert, AckLevel};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// A resource server, which can be targeted with the `resource` param from RFC 8707.
+///
+/// Access tokens issued for it will have its `resource` as the only `aud` and only contain
+/// the requested scopes, which are allowed for it. Parameterized scopes are allowed, if their
+/// pattern is. Only the clients in `client_ids` may request tokens for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceServer {
+    pub id: String,
+    pub name: String,
+    /// The absolute URI, which must be given as the `resource` param
+    pub resource: String,
+    /// The allowed scopes as CSV
+    pub scopes: String,
+    /// The clients, which may request tokens for this resource, as CSV
+    pub client_ids: String,
+    pub created: i64,
+}
+
+// CRUD
+impl ResourceServer {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        req: ResourceServerRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut all = Self::find_all(data).await?;
+        if all.iter().any(|rs| rs.resource == req.resource) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A resource server with this resource already exists".to_string(),
+            ));
+        }
+
+        let mut slf = Self {
+            id: new_store_id(),
+            name: String::default(),
+            resource: String::default(),
+            scopes: String::default(),
+            client_ids: String::default(),
+            created: Utc::now().timestamp(),
+        };
+        slf.apply_req(data, req).await?;
+
+        sqlx::query!(
+            r#"INSERT INTO resource_servers (id, name, resource, scopes, client_ids, created)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            slf.id,
+            slf.name,
+            slf.resource,
+            slf.scopes,
+            slf.client_ids,
+            slf.created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        all.push(slf.clone());
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, id: &str) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!("DELETE FROM resource_servers WHERE id = $1", id)
+            .execute(&data.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Self::err_not_found());
+        }
+
+        let all = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|rs| rs.id != id)
+            .collect::<Vec<Self>>();
+        Self::update_cache(data, all).await?;
+
+        Ok(())
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let res = cache_get::<Vec<Self>>(
+            CACHE_NAME_12HR.to_string(),
+            IDX_RESOURCE_SERVERS.to_string(),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?;
+        if let Some(res) = res {
+            return Ok(res);
+        }
+
+        let res = sqlx::query_as!(
+            Self,
+            r#"SELECT id, name, resource, scopes, client_ids, created
+            FROM resource_servers
+            ORDER BY name"#
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_RESOURCE_SERVERS.to_string(),
+            &data.caches.ha_cache_config,
+            &res,
+            AckLevel::Leader,
+        )
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn update(
+        data: &web::Data<AppState>,
+        id: String,
+        req: ResourceServerRequest,
+    ) -> Result<Self, ErrorResponse> {
+        let mut all = Self::find_all(data).await?;
+        if all
+            .iter()
+            .any(|rs| rs.resource == req.resource && rs.id != id)
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A resource server with this resource already exists".to_string(),
+            ));
+        }
+        let slf = all
+            .iter_mut()
+            .find(|rs| rs.id == id)
+            .ok_or_else(Self::err_not_found)?;
+        slf.apply_req(data, req).await?;
+
+        sqlx::query!(
+            r#"UPDATE resource_servers
+            SET name = $1, resource = $2, scopes = $3, client_ids = $4
+            WHERE id = $5"#,
+            slf.name,
+            slf.resource,
+            slf.scopes,
+            slf.client_ids,
+            slf.id,
+        )
+        .execute(&data.db)
+        .await?;
+
+        let slf = slf.clone();
+        Self::update_cache(data, all).await?;
+
+        Ok(slf)
+    }
+}
+
+impl ResourceServer {
+    /// Resolves the `resource` param of an authorization or token request from the given
+    /// client. Returns `None`, if no `resource` has been requested.
+    pub async fn find_for_request(
+        data: &web::Data<AppState>,
+        resource: Option<&str>,
+        client_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let Some(resource) = resource else {
+            return Ok(None);
+        };
+        Self::validate_resource(resource).map_err(|_| {
+            err_invalid_target("The 'resource' must be an absolute URI without a fragment")
+        })?;
+
+        let rs = Self::find_all(data)
+            .await?
+            .into_iter()
+            .find(|rs| rs.resource == resource)
+            .ok_or_else(|| err_invalid_target("Unknown 'resource'"))?;
+        if !rs.is_client_allowed(client_id) {
+            return Err(err_invalid_target(
+                "The client is not allowed to request the 'resource'",
+            ));
+        }
+        Ok(Some(rs))
+    }
+
+    /// Validates the `resource` of a login before an authorization code will be issued for the
+    /// already sanitized `scopes`.
+    pub async fn validate_login(
+        data: &web::Data<AppState>,
+        resource: Option<&str>,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<(), ErrorResponse> {
+        if let Some(rs) = Self::find_for_request(data, resource, client_id).await? {
+            rs.filter_scopes(&scopes.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the `resource` for a refresh token, which is bound to the `resource` it has been
+    /// issued for. A different one can not be requested with it.
+    pub async fn find_for_refresh(
+        data: &web::Data<AppState>,
+        bound: Option<&str>,
+        requested: Option<&str>,
+        client_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        match (bound, requested) {
+            (None, None) => Ok(None),
+            (Some(bound), None) => Self::find_for_request(data, Some(bound), client_id).await,
+            (Some(bound), Some(requested)) if bound == requested => {
+                Self::find_for_request(data, Some(bound), client_id).await
+            }
+            _ => Err(err_invalid_target(
+                "The 'resource' does not match the refresh token",
+            )),
+        }
+    }
+
+    pub fn is_client_allowed(&self, client_id: &str) -> bool {
+        self.client_ids.split(',').any(|id| id == client_id)
+    }
+
+    /// Reduces the space separated `scopes` to the ones, which are allowed for this resource
+    /// server. Returns an `invalid_target` error, if none of them is allowed.
+    pub fn filter_scopes(&self, scopes: &str) -> Result<String, ErrorResponse> {
+        let allowed = self.scopes.split(',').collect::<Vec<&str>>();
+        let res = scopes
+            .split(' ')
+            .filter(|s| {
+                allowed.contains(s) || Scope::find_pattern(allowed.iter().copied(), s).is_some()
+            })
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        if res.is_empty() {
+            Err(err_invalid_target(
+                "The 'resource' does not accept any of the requested scopes",
+            ))
+        } else {
+            Ok(res)
+        }
+    }
+
+    async fn apply_req(
+        &mut self,
+        data: &web::Data<AppState>,
+        req: ResourceServerRequest,
+    ) -> Result<(), ErrorResponse> {
+        Self::validate_resource(&req.resource)?;
+
+        let existing = Scope::find_all(data).await?;
+        let scopes = req
+            .scopes
+            .into_iter()
+            .filter(|s| existing.iter().any(|e| &e.name == s))
+            .collect::<Vec<String>>();
+        if scopes.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "None of the given scopes exists".to_string(),
+            ));
+        }
+
+        let clients = Client::find_all(data).await?;
+        let client_ids = req
+            .client_ids
+            .into_iter()
+            .filter(|id| clients.iter().any(|c| &c.id == id))
+            .collect::<Vec<String>>();
+        if client_ids.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "None of the given clients exists".to_string(),
+            ));
+        }
+
+        self.name = req.name;
+        self.resource = req.resource;
+        self.scopes = scopes.join(",");
+        self.client_ids = client_ids.join(",");
+        Ok(())
+    }
+
+    /// RFC 8707 requires the `resource` to be an absolute URI, which must not include a
+    /// fragment.
+    fn validate_resource(resource: &str) -> Result<(), ErrorResponse> {
+        let url = Url::parse(resource).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The 'resource' is not a valid URI".to_string(),
+            )
+        })?;
+        if url.fragment().is_some() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The 'resource' must be an absolute URI without a fragment".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn update_cache(data: &web::Data<AppState>, all: Vec<Self>) -> Result<(), ErrorResponse> {
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            IDX_RESOURCE_SERVERS.to_string(),
+            &data.caches.ha_cache_config,
+            &all,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn err_not_found() -> ErrorResponse {
+        ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "Resource server not found".to_string(),
+        )
+    }
+}
+
+fn err_invalid_target(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::BadRequest,
+        format!("invalid_target: {}", msg),
+    )
+    .with_code(ErrorCode::InvalidTarget)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rs(scopes: &str) -> ResourceServer {
+        ResourceServer {
+            id: "rs1".to_string(),
+            name: "Accounts API".to_string(),
+            resource: "https://api.example.com/accounts".to_string(),
+            scopes: scopes.to_string(),
+            client_ids: "app,backend".to_string(),
+            created: 0,
+        }
+    }
+
+    #[test]
+    fn test_is_client_allowed() {
+        let rs = rs("accounts");
+        assert!(rs.is_client_allowed("app"));
+        assert!(rs.is_client_allowed("backend"));
+        assert!(!rs.is_client_allowed("ap"));
+        assert!(!rs.is_client_allowed("app,backend"));
+        assert!(!rs.is_client_allowed(""));
+    }
+
+    #[test]
+    fn test_validate_resource() {
+        assert!(ResourceServer::validate_resource("https://api.example.com").is_ok());
+        assert!(ResourceServer::validate_resource("https://api.example.com/v1?x=1").is_ok());
+        assert!(ResourceServer::validate_resource("urn:example:api").is_ok());
+        assert!(ResourceServer::validate_resource("https://api.example.com/#frag").is_err());
+        assert!(ResourceServer::validate_resource("/accounts").is_err());
+    }
+
+    #[test]
+    fn test_filter_scopes() {
+        let rs = rs("accounts,account:*:read");
+
+        assert_eq!(
+            rs.filter_scopes("openid email accounts account:1234:read account:1234:write")
+                .unwrap(),
+            "accounts account:1234:read"
+        );
+
+        let err = rs.filter_scopes("openid email").unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidTarget);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_REVOKED_TOKENS, IDX_REVOKED_TOKEN};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use redhac::{cache_insert, cache_put, AckLevel};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// An issuer-wide denylist entry for a single JWT by its `jti`.
+///
+/// Revoked tokens will be rejected during validation and introspection until they would have
+/// expired anyway. Lookups during validation are only cached locally in a size-bounded cache.
+/// A revocation itself is replicated to all cluster members, so it takes effect immediately.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct RevokedToken {
+    pub jti: String,
+    /// unix timestamp when the token expires and the entry can be cleaned up
+    pub exp: i64,
+    pub reason: String,
+    pub created: i64,
+}
+
+impl RevokedToken {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        jti: String,
+        exp: i64,
+        reason: String,
+    ) -> Result<Self, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        if exp < now {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The token has expired already".to_string(),
+            ));
+        }
+
+        let slf = Self {
+            jti,
+            exp,
+            reason,
+            created: now,
+        };
+
+        #[cfg(not(feature = "postgres"))]
+        let q = sqlx::query!(
+            r#"insert or replace into revoked_tokens (jti, exp, reason, created)
+            values ($1, $2, $3, $4)"#,
+            slf.jti,
+            slf.exp,
+            slf.reason,
+            slf.created,
+        );
+        #[cfg(feature = "postgres")]
+        let q = sqlx::query!(
+            r#"insert into revoked_tokens (jti, exp, reason, created)
+            values ($1, $2, $3, $4)
+            on conflict(jti) do update set exp = $2, reason = $3"#,
+            slf.jti,
+            slf.exp,
+            slf.reason,
+            slf.created,
+        );
+        q.execute(&data.db).await?;
+
+        Self::cache_revoked(data, &slf.jti, true).await?;
+
+        Ok(slf)
+    }
+
+    pub async fn delete(data: &web::Data<AppState>, jti: &str) -> Result<(), ErrorResponse> {
+        let res = sqlx::query!("delete from revoked_tokens where jti = $1", jti)
+            .execute(&data.db)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Revoked token not found".to_string(),
+            ));
+        }
+
+        Self::cache_revoked(data, jti, false).await?;
+
+        Ok(())
+    }
+
+    pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = sqlx::query_as!(
+            Self,
+            "select * from revoked_tokens where exp >= $1 order by created desc",
+            now
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns an error if the token with the given `jti` has been revoked.
+    pub async fn check(data: &web::Data<AppState>, jti: &str) -> Result<(), ErrorResponse> {
+        let revoked = if let Some(revoked) = cache_get::<bool>(
+            CACHE_NAME_REVOKED_TOKENS.to_string(),
+            format!("{}{}", IDX_REVOKED_TOKEN, jti),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?
+        {
+            revoked
+        } else if Self::is_revoked_db(data, jti).await? {
+            Self::cache_lookup(data, jti, true).await?;
+            true
+        } else {
+            Self::cache_lookup(data, jti, false).await?;
+            // A revocation could have happened in between, and its cache entry would have been
+            // overwritten by ours.
+            if Self::is_revoked_db(data, jti).await? {
+                Self::cache_lookup(data, jti, true).await?;
+                true
+            } else {
+                false
+            }
+        };
+
+        if revoked {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "The token has been revoked".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn is_revoked_db(data: &web::Data<AppState>, jti: &str) -> Result<bool, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = sqlx::query!(
+            "select jti from revoked_tokens where jti = $1 and exp >= $2",
+            jti,
+            now
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res.is_some())
+    }
+
+    /// Caches the result of a lookup on this node only.
+    async fn cache_lookup(
+        data: &web::Data<AppState>,
+        jti: &str,
+        revoked: bool,
+    ) -> Result<(), ErrorResponse> {
+        cache_put(
+            CACHE_NAME_REVOKED_TOKENS.to_string(),
+            format!("{}{}", IDX_REVOKED_TOKEN, jti),
+            &data.caches.ha_cache_config,
+            &revoked,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Replicates a changed revocation to all cluster members.
+    async fn cache_revoked(
+        data: &web::Data<AppState>,
+        jti: &str,
+        revoked: bool,
+    ) -> Result<(), ErrorResponse> {
+        cache_insert(
+            CACHE_NAME_REVOKED_TOKENS.to_string(),
+            format!("{}{}", IDX_REVOKED_TOKEN, jti),
+            &data.caches.ha_cache_config,
+            &revoked,
+            AckLevel::Quorum,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -2,10 +2,11 @@ use crate::app_state::AppState;
 use crate::entity::users::User;
 use crate::request::NewRoleRequest;
 use actix_web::web;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
 use rauthy_common::constants::{CACHE_NAME_12HR, IDX_ROLES, IDX_USERS};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::new_store_id;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use utoipa::ToSchema;
@@ -135,12 +136,11 @@ impl Role {
 
     // Returns all existing roles
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
-        let roles = cache_get!(
-            Vec<Role>,
+        let roles = cache_get::<Vec<Role>>(
             CACHE_NAME_12HR.to_string(),
             IDX_ROLES.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(roles) = roles {
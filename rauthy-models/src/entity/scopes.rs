@@ -2,12 +2,17 @@ use crate::app_state::{AppState, DbTxn};
 use crate::entity::clients::Client;
 use crate::entity::user_attr::UserAttrConfigEntity;
 use crate::entity::well_known::WellKnown;
-use crate::request::ScopeRequest;
+use crate::language::Language;
+use crate::request::{ScopeI18nRequest, ScopeRequest};
+use crate::AuthorizationDetail;
 use actix_web::web;
-use rauthy_common::constants::{CACHE_NAME_12HR, IDX_CLIENTS, IDX_SCOPES};
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::{
+    AUTHORIZATION_DETAILS_SCOPE_PREFIX, CACHE_NAME_12HR, IDX_CLIENTS, IDX_SCOPES,
+};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::new_store_id;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::collections::HashSet;
@@ -22,6 +27,29 @@ pub struct Scope {
     pub attr_include_access: Option<String>,
     // Custom user attributes as CSV to include in the id token
     pub attr_include_id: Option<String>,
+    // JSON array of `ScopeI18n` with display names and descriptions for the login screen
+    pub i18n: Option<String>,
+    // Client IDs as CSV, which are allowed to request this scope - all if `None`
+    pub client_ids: Option<String>,
+}
+
+/// A human-readable name and description of a scope for a specific language.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ScopeI18n {
+    pub lang: Language,
+    pub display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl From<ScopeI18nRequest> for ScopeI18n {
+    fn from(value: ScopeI18nRequest) -> Self {
+        Self {
+            lang: value.lang,
+            display_name: value.display_name,
+            description: value.description,
+        }
+    }
 }
 
 // CRUD
@@ -42,33 +70,35 @@ impl Scope {
             }
         }
 
-        if !Scope::is_custom(&scope_req.scope)
-            && (scope_req.attr_include_access.is_some() || scope_req.attr_include_id.is_some())
-        {
-            return Err(ErrorResponse::new(
-                ErrorResponseType::BadRequest,
-                "OpenID default scopes cannot have custom mappings".to_string(),
-            ));
-        }
+        Self::validate_custom_only(&scope_req)?;
 
         // check configured custom attributes and clean them up
         let attrs = UserAttrConfigEntity::find_all_as_set(data).await?;
         let attr_include_access = Self::clean_up_attrs(scope_req.attr_include_access, &attrs);
         let attr_include_id = Self::clean_up_attrs(scope_req.attr_include_id, &attrs);
+        let client_ids = Self::clean_up_client_ids(data, scope_req.client_ids).await?;
 
         let new_scope = Scope {
             id: new_store_id(),
             name: scope_req.scope,
             attr_include_access,
             attr_include_id,
+            i18n: Self::i18n_to_json(scope_req.i18n)?,
+            client_ids,
         };
-        sqlx::query!("insert into scopes (id, name, attr_include_access, attr_include_id) values ($1, $2, $3, $4)",
+        sqlx::query!(
+            r#"insert into scopes
+            (id, name, attr_include_access, attr_include_id, i18n, client_ids)
+            values ($1, $2, $3, $4, $5, $6)"#,
             new_scope.id,
             new_scope.name,
             new_scope.attr_include_access,
             new_scope.attr_include_id,
-            ).execute(&data.db)
-            .await?;
+            new_scope.i18n,
+            new_scope.client_ids,
+        )
+        .execute(&data.db)
+        .await?;
 
         scopes.push(new_scope.clone());
         cache_insert(
@@ -159,12 +189,11 @@ impl Scope {
 
     // Returns all existing scopes
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
-        let scopes = cache_get!(
-            Vec<Scope>,
+        let scopes = cache_get::<Vec<Scope>>(
             CACHE_NAME_12HR.to_string(),
             IDX_SCOPES.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(scopes) = scopes {
@@ -190,7 +219,7 @@ impl Scope {
     pub async fn update(
         data: &web::Data<AppState>,
         id: &str,
-        scope_req: ScopeRequest,
+        mut scope_req: ScopeRequest,
     ) -> Result<Self, ErrorResponse> {
         let scope = Scope::find(data, id).await?;
         if scope.name == "openid" {
@@ -200,53 +229,53 @@ impl Scope {
             ));
         }
 
-        if !Scope::is_custom(&scope_req.scope)
-            && (scope_req.attr_include_access.is_some() || scope_req.attr_include_id.is_some())
-        {
-            return Err(ErrorResponse::new(
-                ErrorResponseType::BadRequest,
-                "OpenID default scopes cannot have custom mappings".to_string(),
-            ));
-        }
+        Self::validate_custom_only(&scope_req)?;
+        let client_ids = Self::clean_up_client_ids(data, scope_req.client_ids.take()).await?;
 
         let mut txn = data.db.begin().await?;
 
-        // if the name has changed, we need to update all connected clients
-        let is_name_update = if scope.name != scope_req.scope {
-            // find all clients with the old_name assigned
-            let mut clients = vec![];
-            Client::find_all(data)
-                .await?
-                .into_iter()
-                .filter(|c| {
-                    c.scopes.contains(&scope.name) || c.default_scopes.contains(&scope.name)
-                })
-                .for_each(|mut c| {
+        // If the name has changed, we need to update all connected clients.
+        // If the scope is restricted, it will be removed from all clients not allowed anymore.
+        let is_name_update = scope.name != scope_req.scope;
+        let allowed = client_ids
+            .as_ref()
+            .map(|ids| ids.split(',').collect::<HashSet<&str>>());
+        let mut clients = vec![];
+        Client::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|c| c.scopes.contains(&scope.name) || c.default_scopes.contains(&scope.name))
+            .for_each(|mut c| {
+                if allowed
+                    .as_ref()
+                    .map(|a| !a.contains(c.id.as_str()))
+                    .unwrap_or(false)
+                {
+                    c.delete_scope(&scope.name);
+                } else if is_name_update {
                     c.scopes = c.scopes.replace(&scope.name, &scope_req.scope);
                     c.default_scopes = c.default_scopes.replace(&scope.name, &scope_req.scope);
-                    clients.push(c);
-                });
-
-            // no need to evict the cache if no clients are updated
-            if !clients.is_empty() {
-                cache_remove(
-                    CACHE_NAME_12HR.to_string(),
-                    IDX_CLIENTS.to_string(),
-                    &data.caches.ha_cache_config,
-                    AckLevel::Leader,
-                )
-                .await?;
-            }
+                } else {
+                    return;
+                }
+                clients.push(c);
+            });
 
-            // Not awaiting all at once to prevent resource spikes
-            for client in clients {
-                client.save(data, Some(&mut txn)).await?;
-            }
+        // no need to evict the cache if no clients are updated
+        if !clients.is_empty() {
+            cache_remove(
+                CACHE_NAME_12HR.to_string(),
+                IDX_CLIENTS.to_string(),
+                &data.caches.ha_cache_config,
+                AckLevel::Leader,
+            )
+            .await?;
+        }
 
-            true
-        } else {
-            false
-        };
+        // Not awaiting all at once to prevent resource spikes
+        for client in clients {
+            client.save(data, Some(&mut txn)).await?;
+        }
 
         debug!("scope_req: {:?}", scope_req);
         // check configured custom attributes and clean them up
@@ -261,13 +290,19 @@ impl Scope {
             name: scope_req.scope,
             attr_include_access,
             attr_include_id,
+            i18n: Self::i18n_to_json(scope_req.i18n)?,
+            client_ids,
         };
 
         sqlx::query!(
-            "update scopes set name = $1, attr_include_access = $2, attr_include_id = $3 where id = $4",
+            r#"update scopes
+            set name = $1, attr_include_access = $2, attr_include_id = $3, i18n = $4, client_ids = $5
+            where id = $6"#,
             new_scope.name,
             new_scope.attr_include_access,
             new_scope.attr_include_id,
+            new_scope.i18n,
+            new_scope.client_ids,
             new_scope.id,
         )
             .execute(&mut *txn)
@@ -345,6 +380,154 @@ impl Scope {
 }
 
 impl Scope {
+    /// Returns the client IDs which are allowed to request this scope, or `None` if there is no
+    /// restriction.
+    pub fn allowed_client_ids(&self) -> Option<Vec<&str>> {
+        self.client_ids.as_ref().map(|ids| ids.split(',').collect())
+    }
+
+    /// Returns `true` if the given client is allowed to request this scope.
+    pub fn is_allowed_for(&self, client_id: &str) -> bool {
+        self.allowed_client_ids()
+            .map(|ids| ids.contains(&client_id))
+            .unwrap_or(true)
+    }
+
+    /// Returns the display name and description for the given language. Falls back to english
+    /// and then to the first available translation.
+    pub fn i18n(&self, lang: &Language) -> Option<ScopeI18n> {
+        let values = serde_json::from_str::<Vec<ScopeI18n>>(self.i18n.as_deref()?).ok()?;
+        values
+            .iter()
+            .find(|v| &v.lang == lang)
+            .or_else(|| values.iter().find(|v| v.lang == Language::En))
+            .or_else(|| values.first())
+            .cloned()
+    }
+
+    /// Returns the translated scopes for the login screen as a JSON array. Only scopes, which are
+    /// requested and allowed for the client and which have a translation, are included.
+    pub async fn login_i18n_json(
+        data: &web::Data<AppState>,
+        client: &Client,
+        requested: &str,
+        lang: &Language,
+    ) -> Result<String, ErrorResponse> {
+        let res = Self::login_i18n(data, client, requested, lang).await?;
+        Ok(serde_json::to_string(&res)?)
+    }
+
+    /// The same as `login_i18n_json`, but without the serialization.
+    ///
+    /// Parameterized scopes use the translation of their pattern with the wildcard values
+    /// appended to the display name, so the user can see which resource is being shared.
+    pub async fn login_i18n(
+        data: &web::Data<AppState>,
+        client: &Client,
+        requested: &str,
+        lang: &Language,
+    ) -> Result<Vec<ScopeI18n>, ErrorResponse> {
+        let allowed = client
+            .scopes
+            .split(',')
+            .chain(client.default_scopes.split(','))
+            .collect::<HashSet<&str>>();
+        let mut requested_exact = HashSet::new();
+        let mut params = Vec::new();
+        for s in requested.split(' ') {
+            if s.contains('*') {
+                continue;
+            }
+            if allowed.contains(s) {
+                requested_exact.insert(s);
+            } else if let Some((pattern, values)) = Self::find_pattern(allowed.iter().copied(), s) {
+                params.push((pattern, values.join(":")));
+            }
+        }
+
+        let scopes = Self::find_all(data).await?;
+        let mut res = scopes
+            .iter()
+            .filter(|s| requested_exact.contains(s.name.as_str()) && s.is_allowed_for(&client.id))
+            .filter_map(|s| s.i18n(lang))
+            .collect::<Vec<ScopeI18n>>();
+
+        for (pattern, identifier) in params {
+            if let Some(mut i18n) = scopes
+                .iter()
+                .find(|s| s.name == pattern && s.is_allowed_for(&client.id))
+                .and_then(|s| s.i18n(lang))
+            {
+                i18n.display_name = format!("{} ({})", i18n.display_name, identifier);
+                res.push(i18n);
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub fn i18n_all(&self) -> Vec<ScopeI18n> {
+        self.i18n
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    fn i18n_to_json(
+        values: Option<Vec<ScopeI18nRequest>>,
+    ) -> Result<Option<String>, ErrorResponse> {
+        match values {
+            Some(values) if !values.is_empty() => {
+                let values = values.into_iter().map(ScopeI18n::from).collect::<Vec<_>>();
+                Ok(Some(serde_json::to_string(&values)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn clean_up_client_ids(
+        data: &web::Data<AppState>,
+        client_ids: Option<Vec<String>>,
+    ) -> Result<Option<String>, ErrorResponse> {
+        let Some(ids) = client_ids else {
+            return Ok(None);
+        };
+
+        let existing = Client::find_all(data)
+            .await?
+            .into_iter()
+            .map(|c| c.id)
+            .collect::<HashSet<String>>();
+        let res = ids
+            .into_iter()
+            .filter(|id| existing.contains(id))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        if res.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "None of the given client IDs exists".to_string(),
+            ));
+        }
+        Ok(Some(res))
+    }
+
+    fn validate_custom_only(scope_req: &ScopeRequest) -> Result<(), ErrorResponse> {
+        if !Scope::is_custom(&scope_req.scope)
+            && (scope_req.attr_include_access.is_some()
+                || scope_req.attr_include_id.is_some()
+                || scope_req.client_ids.is_some())
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "OpenID default scopes cannot have custom mappings or client restrictions"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
     pub fn clean_up_attrs(
         req_attrs: Option<Vec<String>>,
         existing_attrs: &HashSet<String>,
@@ -382,6 +565,122 @@ impl Scope {
     }
 }
 
+// parameterized scopes
+impl Scope {
+    /// Returns `true` if the given scope name is a pattern for parameterized scopes like
+    /// `account:*:read`. The first segment must always be a literal, because it defines the
+    /// `type` of the resulting `authorization_details`.
+    pub fn is_pattern(name: &str) -> bool {
+        name.contains('*') && !name.starts_with('*')
+    }
+
+    /// Matches a requested `scope` against a `pattern` like `account:*:read`. Each `*` matches
+    /// exactly one segment value. Returns the wildcard values on success.
+    pub fn match_pattern<'a>(pattern: &str, scope: &'a str) -> Option<Vec<&'a str>> {
+        if !Self::is_pattern(pattern) || scope.contains('*') {
+            return None;
+        }
+
+        let mut values = Vec::with_capacity(1);
+        let mut pattern_segments = pattern.split(':');
+        let mut scope_segments = scope.split(':');
+        loop {
+            match (pattern_segments.next(), scope_segments.next()) {
+                (None, None) => break,
+                (Some("*"), Some(value)) => {
+                    if !Self::is_valid_param(value) {
+                        return None;
+                    }
+                    values.push(value);
+                }
+                (Some(p), Some(s)) if p == s => {}
+                _ => return None,
+            }
+        }
+
+        Some(values)
+    }
+
+    /// Returns the first pattern out of the given names, which matches the `scope`, together
+    /// with the wildcard values.
+    pub fn find_pattern<'a, 'b>(
+        names: impl IntoIterator<Item = &'a str>,
+        scope: &'b str,
+    ) -> Option<(&'a str, Vec<&'b str>)> {
+        names
+            .into_iter()
+            .find_map(|name| Self::match_pattern(name, scope).map(|values| (name, values)))
+    }
+
+    /// Builds the RFC 9396 `authorization_details` for all scopes in the space separated
+    /// `scopes`, which match one of the patterns in `client.scopes`. Details with the same
+    /// `type` and `identifier` are merged. Returns `None` if there are no parameterized scopes.
+    pub fn authorization_details(
+        client: &Client,
+        scopes: &str,
+    ) -> Option<Vec<AuthorizationDetail>> {
+        let patterns = client
+            .scopes
+            .split(',')
+            .filter(|s| Self::is_pattern(s))
+            .collect::<Vec<&str>>();
+        if patterns.is_empty() {
+            return None;
+        }
+
+        let mut res: Vec<AuthorizationDetail> = Vec::new();
+        for scope in scopes.split(' ') {
+            let Some((pattern, values)) = Self::find_pattern(patterns.iter().copied(), scope)
+            else {
+                continue;
+            };
+
+            let (typ, actions) = pattern.split_once(":*").unwrap();
+            let identifier = values.join(":");
+            let action = actions
+                .trim_start_matches(':')
+                .split(':')
+                .filter(|s| !s.is_empty() && *s != "*")
+                .collect::<Vec<&str>>()
+                .join(":");
+
+            let typ = format!("{}{}", AUTHORIZATION_DETAILS_SCOPE_PREFIX, typ);
+            let idx = match res
+                .iter()
+                .position(|d| d.typ == typ && d.identifier.as_deref() == Some(identifier.as_str()))
+            {
+                Some(idx) => idx,
+                None => {
+                    res.push(AuthorizationDetail {
+                        typ,
+                        identifier: Some(identifier),
+                        ..Default::default()
+                    });
+                    res.len() - 1
+                }
+            };
+            if !action.is_empty() && !res[idx].actions.contains(&action) {
+                res[idx].actions.push(action);
+            }
+        }
+
+        if res.is_empty() {
+            None
+        } else {
+            Some(res)
+        }
+    }
+
+    #[inline]
+    fn is_valid_param(value: &str) -> bool {
+        !value.is_empty()
+            && value.len() <= 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -389,6 +688,116 @@ mod tests {
     use pretty_assertions::assert_eq;
     use serde_json::Value;
 
+    use super::*;
+
+    #[test]
+    fn test_match_pattern() {
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account:1234:read"),
+            Some(vec!["1234"])
+        );
+        assert_eq!(
+            Scope::match_pattern("bank:*:account:*", "bank:b-1:account:a_2"),
+            Some(vec!["b-1", "a_2"])
+        );
+
+        // a literal `*` must never match
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account:*:read"),
+            None
+        );
+        // segment count must be equal
+        assert_eq!(Scope::match_pattern("account:*:read", "account:1234"), None);
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account:1234:read:write"),
+            None
+        );
+        // literal segments must match exactly
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account:1234:write"),
+            None
+        );
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account::read"),
+            None
+        );
+        assert_eq!(
+            Scope::match_pattern("account:*:read", "account:A1:read"),
+            None
+        );
+        // leading wildcards are no patterns
+        assert_eq!(Scope::match_pattern("*:read", "account:read"), None);
+        // non-pattern scopes never match
+        assert_eq!(Scope::match_pattern("account", "account"), None);
+    }
+
+    #[test]
+    fn test_authorization_details() {
+        let client = Client {
+            scopes: "openid,account:*:read,account:*:write,payment:*".to_string(),
+            default_scopes: "openid".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(Scope::authorization_details(&client, "openid email"), None);
+
+        let details = Scope::authorization_details(
+            &client,
+            "openid account:1234:read account:1234:write account:99:read payment:p1 other:1:read",
+        )
+        .unwrap();
+        assert_eq!(
+            details,
+            vec![
+                AuthorizationDetail {
+                    typ: "scope:account".to_string(),
+                    identifier: Some("1234".to_string()),
+                    actions: vec!["read".to_string(), "write".to_string()],
+                    ..Default::default()
+                },
+                AuthorizationDetail {
+                    typ: "scope:account".to_string(),
+                    identifier: Some("99".to_string()),
+                    actions: vec!["read".to_string()],
+                    ..Default::default()
+                },
+                AuthorizationDetail {
+                    typ: "scope:payment".to_string(),
+                    identifier: Some("p1".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let json = serde_json::to_string(&details[2]).unwrap();
+        assert_eq!(json, r#"{"type":"scope:payment","identifier":"p1"}"#);
+    }
+
+    #[test]
+    fn test_sanitize_parameterized_scopes() {
+        let client = Client {
+            scopes: "openid,email,account:*:read".to_string(),
+            default_scopes: "openid".to_string(),
+            ..Default::default()
+        };
+
+        let req = Some(vec![
+            "email".to_string(),
+            "account:1234:read".to_string(),
+            "account:*:read".to_string(),
+            "account:1234:write".to_string(),
+        ]);
+        let scopes = client.sanitize_login_scopes(&req).unwrap();
+        assert_eq!(
+            scopes,
+            vec![
+                "openid".to_string(),
+                "email".to_string(),
+                "account:1234:read".to_string(),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_generic_json() {
         let obj =
@@ -1,27 +1,71 @@
-use crate::app_state::AppState;
+use crate::app_state::{AppState, DbTxn};
 use crate::entity::continuation_token::ContinuationToken;
 use crate::entity::users::User;
 use crate::request::SearchParamsIdx;
 use actix_web::cookie::{time, Cookie, SameSite};
 use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::{cookie, web, HttpRequest};
+use once_cell::sync::Lazy;
+use prometheus::{IntCounterVec, Opts, Registry};
+use rauthy_common::cache_metrics::{cache_del, cache_get, cache_remove};
 use rauthy_common::constants::{
-    CACHE_NAME_12HR, CACHE_NAME_SESSIONS, COOKIE_SESSION, CSRF_HEADER, DANGER_COOKIE_INSECURE,
-    IDX_SESSION,
+    CACHE_NAME_12HR, CACHE_NAME_SESSIONS, CACHE_SESSIONS_SHARDS, COOKIE_SESSION, CSRF_HEADER,
+    DANGER_COOKIE_INSECURE, IDX_SESSION, SESSION_ELEVATION_ENABLE, SESSION_ELEVATION_MINUTES,
 };
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::get_rand;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_url_no_pad_encode, cache_shard_name, get_rand};
+use redhac::{cache_get_from, cache_get_value, cache_insert, cache_put, AckLevel};
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
 use sqlx::sqlite::SqliteRow;
 use sqlx::{query_as, FromRow, Row};
+use std::collections::HashSet;
 use std::ops::Add;
 use std::str::FromStr;
+use std::sync::Mutex;
 use time::OffsetDateTime;
 use tracing::{error, warn};
 use utoipa::ToSchema;
 
+static METRIC_CACHE_DRIFT: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "rauthy_session_cache_drift_total",
+            "Locally cached sessions, which diverged from the database and have been repaired",
+        ),
+        &["kind"],
+    )
+    .expect("Cannot build rauthy_session_cache_drift_total")
+});
+
+/// Ids of the sessions, which have been served via `Session::find` on this instance since the
+/// last `Session::repair_cache` run. Sessions which are never read on this instance cannot be
+/// served with stale values and do not need to be checked.
+/// Seconds in which the old id of a rotated session can still be used.
+const ROTATE_GRACE_SECS: i64 = 10;
+
+static USED_SESSION_IDS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Registers the session metrics with the given Prometheus registry.
+pub fn register_metrics(registry: &Registry) {
+    if let Err(err) = registry.register(Box::new(METRIC_CACHE_DRIFT.clone())) {
+        error!("Cannot register session metrics: {}", err);
+    }
+}
+
+/// The result of a single `Session::repair_cache` run on this instance.
+#[derive(Debug, Default)]
+pub struct SessionCacheDrift {
+    /// amount of sessions, which exist in the local cache and have been compared
+    pub checked: usize,
+    /// cached sessions which are still valid, while they have been invalidated or deleted in
+    /// the database
+    pub stale: usize,
+    /// cached sessions with other differences like roles, groups or MFA state
+    pub diverged: usize,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Session {
     pub id: String,
@@ -35,6 +79,7 @@ pub struct Session {
     pub exp: i64,
     pub last_seen: i64,
     pub remote_ip: Option<String>, // TODO should we maybe force a linked remote_ip all the time?
+    pub elevated_until: Option<i64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
@@ -100,9 +145,10 @@ impl Session {
             .execute(&data.db)
             .await?;
 
+        let idx = Session::cache_idx(&self.id);
         cache_remove(
-            CACHE_NAME_SESSIONS.to_string(),
-            Session::cache_idx(&self.id),
+            Session::cache_name(&idx),
+            idx,
             &data.caches.ha_cache_config,
             AckLevel::Quorum,
         )
@@ -125,31 +171,54 @@ impl Session {
             .execute(&data.db)
             .await?;
 
+        Self::evict_all(data, &sessions).await
+    }
+
+    /// Deletes all sessions of a user inside the given transaction. The returned sessions must be
+    /// evicted from the cache with [Session::evict_all] after the commit.
+    pub async fn delete_by_user_txn(
+        user_id: &str,
+        txn: &mut DbTxn<'_>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let sessions: Vec<Self> =
+            sqlx::query_as!(Self, "SELECT * FROM sessions WHERE user_id = $1", user_id)
+                .fetch_all(&mut **txn)
+                .await?;
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+            .execute(&mut **txn)
+            .await?;
+        Ok(sessions)
+    }
+
+    pub async fn evict_all(
+        data: &web::Data<AppState>,
+        sessions: &[Self],
+    ) -> Result<(), ErrorResponse> {
         for s in sessions {
+            let idx = Session::cache_idx(&s.id);
             cache_remove(
-                CACHE_NAME_SESSIONS.to_string(),
-                Session::cache_idx(&s.id),
+                Session::cache_name(&idx),
+                idx,
                 &data.caches.ha_cache_config,
                 AckLevel::Quorum,
             )
             .await?;
         }
-
         Ok(())
     }
 
     // Returns a session by id
     pub async fn find(data: &web::Data<AppState>, id: String) -> Result<Self, ErrorResponse> {
         let idx = Session::cache_idx(&id);
-        let session = cache_get!(
-            Session,
-            CACHE_NAME_SESSIONS.to_string(),
+        let session = cache_get::<Session>(
+            Session::cache_name(&idx),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(session) = session {
+            Self::track_used(&session.id);
             return Ok(session);
         }
 
@@ -158,11 +227,15 @@ impl Session {
             "SELECT * FROM sessions WHERE id = $1 ORDER BY exp DESC",
             id
         )
-        .fetch_one(&data.db)
+        .fetch_optional(&data.db)
         .await?;
+        let Some(session) = session else {
+            return Self::find_rotated(data, &id).await;
+        };
+        Self::track_used(&session.id);
 
         cache_insert(
-            CACHE_NAME_SESSIONS.to_string(),
+            Session::cache_name(&idx),
             idx,
             &data.caches.ha_cache_config,
             &session,
@@ -173,6 +246,195 @@ impl Session {
         Ok(session)
     }
 
+    /// Requests which have been in-flight during a `Session::rotate` still use the old id. It
+    /// resolves to the new session for `ROTATE_GRACE_SECS`.
+    async fn find_rotated(data: &web::Data<AppState>, old_id: &str) -> Result<Self, ErrorResponse> {
+        let not_found = || {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "Session does not exist".to_string(),
+            )
+        };
+
+        let rotated = cache_get::<(String, i64)>(
+            CACHE_NAME_12HR.to_string(),
+            Self::cache_idx_rotated(old_id),
+            &data.caches.ha_cache_config,
+            false,
+        )
+        .await?;
+        let Some((new_id, until)) = rotated else {
+            return Err(not_found());
+        };
+        if until < OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(not_found());
+        }
+
+        sqlx::query_as!(Self, "SELECT * FROM sessions WHERE id = $1", new_id)
+            .fetch_optional(&data.db)
+            .await?
+            .ok_or_else(not_found)
+    }
+
+    /// Returns the session, which has been linked to the given `sid` claim
+    pub async fn find_by_sid(data: &web::Data<AppState>, sid: &str) -> Result<Self, ErrorResponse> {
+        let row = sqlx::query!("SELECT session_id FROM sessions_sid WHERE sid = $1", sid)
+            .fetch_optional(&data.db)
+            .await?
+            .ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::NotFound,
+                    "Session does not exist".to_string(),
+                )
+            })?;
+
+        Self::find(data, row.session_id).await
+    }
+
+    /// Links the `sid` claim to the session with the given id and returns the `sid`.
+    /// The link is removed together with the session. A session which has been linked already
+    /// keeps its `sid`, even after a `Session::rotate`.
+    pub async fn link_sid(
+        data: &web::Data<AppState>,
+        session_id: &str,
+    ) -> Result<String, ErrorResponse> {
+        let linked = sqlx::query!(
+            "SELECT sid FROM sessions_sid WHERE session_id = $1 LIMIT 1",
+            session_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        if let Some(row) = linked {
+            return Ok(row.sid);
+        }
+
+        let sid = Self::sid_from_id(session_id);
+        sqlx::query!(
+            r#"INSERT INTO sessions_sid (sid, session_id) VALUES ($1, $2)
+            ON CONFLICT(sid) DO NOTHING"#,
+            sid,
+            session_id,
+        )
+        .execute(&data.db)
+        .await?;
+        Ok(sid)
+    }
+
+    /// Loads up to `limit` valid sessions, which have been active within the last `minutes`,
+    /// into the local cache. Returns the amount of preloaded sessions.
+    pub async fn preload_recently_active(
+        data: &web::Data<AppState>,
+        minutes: i64,
+        limit: i64,
+    ) -> Result<usize, ErrorResponse> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let since = now - minutes * 60;
+        let sessions = sqlx::query_as!(
+            Self,
+            r#"SELECT * FROM sessions
+            WHERE exp > $1 AND last_seen > $2
+            ORDER BY last_seen DESC
+            LIMIT $3"#,
+            now,
+            since,
+            limit,
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        for session in &sessions {
+            let idx = Session::cache_idx(&session.id);
+            cache_put(
+                Session::cache_name(&idx),
+                idx,
+                &data.caches.ha_cache_config,
+                session,
+            )
+            .await?;
+        }
+
+        Ok(sessions.len())
+    }
+
+    /// Cross-checks the locally cached sessions with the database, which is always the source
+    /// of truth, and repairs any divergence. After a network partition between HA nodes, a
+    /// cache entry on this instance may have missed an invalidation or deletion, which would
+    /// keep the session alive on this node only.
+    ///
+    /// Only the sessions which have been used on this instance since the last run are checked,
+    /// with a lookup by id for each of them. Diverged entries are always removed instead of
+    /// overwritten. A concurrent update in between can then never be replaced with an older
+    /// value, and the next access simply loads the current one from the database.
+    pub async fn repair_cache(
+        data: &web::Data<AppState>,
+    ) -> Result<SessionCacheDrift, ErrorResponse> {
+        let ids = std::mem::take(&mut *USED_SESSION_IDS.lock().unwrap());
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let mut drift = SessionCacheDrift::default();
+        for id in ids {
+            let idx = Session::cache_idx(&id);
+            // not using the counting `cache_get` to keep the hit ratio meaningful
+            let cached = redhac::cache_get!(
+                Session,
+                Session::cache_name(&idx),
+                idx.clone(),
+                &data.caches.ha_cache_config,
+                false
+            )
+            .await?;
+            let Some(cached) = cached else {
+                continue;
+            };
+            drift.checked += 1;
+
+            let session = sqlx::query_as!(Self, "SELECT * FROM sessions WHERE id = $1", id)
+                .fetch_optional(&data.db)
+                .await?;
+
+            let kind = match session {
+                Some(session) if !session.diverges_from(&cached) => {
+                    Self::track_used(&id);
+                    continue;
+                }
+                Some(session) if session.exp > now && session.state != SessionState::LoggedOut => {
+                    drift.diverged += 1;
+                    "diverged"
+                }
+                _ => {
+                    drift.stale += 1;
+                    "stale"
+                }
+            };
+
+            warn!(
+                "Removing {} cached session {} from the local cache",
+                kind, id
+            );
+            cache_del(Session::cache_name(&idx), idx, &data.caches.ha_cache_config).await?;
+            METRIC_CACHE_DRIFT.with_label_values(&[kind]).inc();
+        }
+
+        Ok(drift)
+    }
+
+    /// Remembers the session id for the next `Session::repair_cache` run.
+    #[inline]
+    fn track_used(id: &str) {
+        USED_SESSION_IDS.lock().unwrap().insert(id.to_string());
+    }
+
+    /// Compares all security relevant values. `last_seen` is ignored on purpose.
+    fn diverges_from(&self, other: &Session) -> bool {
+        self.user_id != other.user_id
+            || self.roles != other.roles
+            || self.groups != other.groups
+            || self.is_mfa != other.is_mfa
+            || self.state != other.state
+            || self.exp != other.exp
+            || self.elevated_until != other.elevated_until
+    }
+
     // not cached, since this is only used in the admin ui
     /// Returns all sessions and an empty Vec if not a single session exists
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
@@ -182,6 +444,22 @@ impl Session {
         Ok(sessions)
     }
 
+    /// Returns all sessions which are not expired yet, optionally filtered by `user_id`.
+    /// These are exactly the sessions that an invalidation would affect.
+    pub async fn find_valid(
+        data: &web::Data<AppState>,
+        user_id: Option<&str>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let sessions = Session::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|s| s.exp > now)
+            .filter(|s| user_id.is_none() || s.user_id.as_deref() == user_id)
+            .collect();
+        Ok(sessions)
+    }
+
     pub async fn find_paginated(
         data: &web::Data<AppState>,
         continuation_token: Option<ContinuationToken>,
@@ -308,9 +586,10 @@ impl Session {
         }
 
         for id in removed {
+            let idx = Session::cache_idx(&id);
             cache_remove(
-                CACHE_NAME_SESSIONS.to_string(),
-                Session::cache_idx(&id),
+                Session::cache_name(&idx),
+                idx,
                 &data.caches.ha_cache_config,
                 AckLevel::Quorum,
             )
@@ -325,12 +604,23 @@ impl Session {
         data: &web::Data<AppState>,
         uid: &str,
     ) -> Result<(), ErrorResponse> {
+        Self::invalidate_for_user_except(data, uid, None).await?;
+        Ok(())
+    }
+
+    /// Invalidates all sessions for the given user except the one with the `keep_sid`.
+    /// Returns the amount of invalidated sessions.
+    pub async fn invalidate_for_user_except(
+        data: &web::Data<AppState>,
+        uid: &str,
+        keep_sid: Option<&str>,
+    ) -> Result<usize, ErrorResponse> {
         let now = OffsetDateTime::now_utc().unix_timestamp();
         let sessions = Session::find_all(data).await?;
         let mut removed = Vec::default();
 
         for mut s in sessions {
-            if s.user_id.is_none() {
+            if s.user_id.is_none() || keep_sid == Some(s.id.as_str()) {
                 continue;
             }
 
@@ -341,17 +631,19 @@ impl Session {
             }
         }
 
+        let count = removed.len();
         for id in removed {
+            let idx = Session::cache_idx(&id);
             cache_remove(
-                CACHE_NAME_SESSIONS.to_string(),
-                Session::cache_idx(&id),
+                Session::cache_name(&idx),
+                idx,
                 &data.caches.ha_cache_config,
                 AckLevel::Quorum,
             )
             .await?;
         }
 
-        Ok(())
+        Ok(count)
     }
 
     /// Saves a Session
@@ -361,8 +653,9 @@ impl Session {
         #[cfg(not(feature = "postgres"))]
         let q = sqlx::query!(
             r#"insert or replace into
-            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen, remote_ip)
-            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen, remote_ip,
+            elevated_until)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
             self.id,
             self.csrf_token,
             self.user_id,
@@ -373,15 +666,17 @@ impl Session {
             self.exp,
             self.last_seen,
             self.remote_ip,
+            self.elevated_until,
         );
 
         #[cfg(feature = "postgres")]
         let q = sqlx::query!(
             r#"insert into
-            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen, remote_ip)
-            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen, remote_ip,
+            elevated_until)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             on conflict(id) do update set user_id = $3, roles = $4, groups = $5, is_mfa = $6,
-            state = $7, exp = $8, last_seen = $9, remote_ip = $10"#,
+            state = $7, exp = $8, last_seen = $9, remote_ip = $10, elevated_until = $11"#,
             self.id,
             self.csrf_token,
             self.user_id,
@@ -392,13 +687,15 @@ impl Session {
             self.exp,
             self.last_seen,
             self.remote_ip,
+            self.elevated_until,
         );
 
         q.execute(&data.db).await?;
 
+        let idx = Session::cache_idx(&self.id);
         cache_insert(
-            CACHE_NAME_SESSIONS.to_string(),
-            Session::cache_idx(&self.id),
+            Session::cache_name(&idx),
+            idx,
             &data.caches.ha_cache_config,
             &self,
             AckLevel::Quorum,
@@ -480,6 +777,7 @@ impl Session {
                 .unix_timestamp(),
             last_seen: now.unix_timestamp(),
             remote_ip,
+            elevated_until: None,
         }
     }
 
@@ -487,6 +785,17 @@ impl Session {
         format!("{}{}", IDX_SESSION, id)
     }
 
+    #[inline]
+    fn cache_idx_rotated(old_id: &str) -> String {
+        format!("{}rotated_{}", IDX_SESSION, old_id)
+    }
+
+    /// Returns the name of the sessions cache shard for the given cache index.
+    #[inline]
+    fn cache_name(idx: &str) -> String {
+        cache_shard_name(CACHE_NAME_SESSIONS, idx, *CACHE_SESSIONS_SHARDS)
+    }
+
     /// exp_in will be the time in seconds when the session will expire
     pub fn try_new(
         user: &User,
@@ -507,7 +816,8 @@ impl Session {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Forbidden,
                     "User has expired".to_string(),
-                ));
+                )
+                .with_code(ErrorCode::UserExpired));
             } else {
                 let target = now
                     .add(time::Duration::seconds(exp_in as i64))
@@ -534,6 +844,7 @@ impl Session {
             exp,
             last_seen: now.unix_timestamp(),
             remote_ip,
+            elevated_until: None,
         })
     }
 
@@ -604,7 +915,7 @@ impl Session {
             .await?;
 
         cache_remove(
-            CACHE_NAME_12HR.to_string(),
+            Session::cache_name(&idx),
             idx,
             &data.caches.ha_cache_config,
             AckLevel::Quorum,
@@ -620,6 +931,20 @@ impl Session {
             .finish())
     }
 
+    /// The session id works as the bearer for the session cookie and must never leave Rauthy.
+    /// Tokens only contain a hash of it as the `sid` claim.
+    pub fn sid_from_id(id: &str) -> String {
+        let hash = digest::digest(&digest::SHA256, id.as_bytes());
+        base64_url_no_pad_encode(hash.as_ref())
+    }
+
+    /// Checks if a user has logged in with this session and it has neither expired, nor been
+    /// logged out or revoked. The idle timeout is ignored on purpose, since it only matters for
+    /// re-using the session inside Rauthy itself.
+    pub fn is_alive(&self) -> bool {
+        self.state == SessionState::Auth && self.exp > OffsetDateTime::now_utc().unix_timestamp()
+    }
+
     /// Checks if the current session is valid: has not expired and has not timed out (last_seen)
     pub fn is_valid(&self, session_timeout: u32, remote_ip: Option<String>) -> bool {
         let now = OffsetDateTime::now_utc().unix_timestamp();
@@ -677,6 +1002,81 @@ impl Session {
             .collect())
     }
 
+    /// Marks this session as freshly re-authenticated for the next `SESSION_ELEVATION_MINUTES`.
+    pub async fn elevate(&mut self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        let until = OffsetDateTime::now_utc()
+            .add(time::Duration::minutes(*SESSION_ELEVATION_MINUTES as i64))
+            .unix_timestamp();
+        // an elevation must never outlive the session itself
+        self.elevated_until = Some(until.min(self.exp));
+        self.save(data).await
+    }
+
+    /// Replaces the session id, which is the value of the session cookie, with a fresh one.
+    /// The CSRF token stays the same, and linked `sid`s follow the new id via the
+    /// `on update cascade` on `sessions_sid`, so that tokens issued earlier can still be
+    /// mapped to this session. The old id stays valid for another `ROTATE_GRACE_SECS` for
+    /// requests which have been in-flight already.
+    pub async fn rotate(&mut self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        let old_idx = Session::cache_idx(&self.id);
+        let new_id = get_rand(32);
+
+        sqlx::query!("UPDATE sessions SET id = $1 WHERE id = $2", new_id, self.id)
+            .execute(&data.db)
+            .await?;
+
+        let until = OffsetDateTime::now_utc().unix_timestamp() + ROTATE_GRACE_SECS;
+        cache_insert(
+            CACHE_NAME_12HR.to_string(),
+            Self::cache_idx_rotated(&self.id),
+            &data.caches.ha_cache_config,
+            &(new_id.clone(), until),
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        cache_remove(
+            Session::cache_name(&old_idx),
+            old_idx,
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        self.id = new_id;
+        let idx = Session::cache_idx(&self.id);
+        cache_insert(
+            Session::cache_name(&idx),
+            idx,
+            &data.caches.ha_cache_config,
+            &self,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Checks that the session has been elevated recently, if `SESSION_ELEVATION_ENABLE` is set.
+    /// Must be used in front of sensitive account operations like changing the email, the
+    /// password or passkeys.
+    pub fn validate_elevated(&self) -> Result<(), ErrorResponse> {
+        if !*SESSION_ELEVATION_ENABLE {
+            return Ok(());
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if self.elevated_until.unwrap_or(0) > now {
+            return Ok(());
+        }
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "This operation needs a recent re-authentication".to_string(),
+        )
+        .with_code(ErrorCode::ElevationRequired))
+    }
+
     #[inline]
     pub async fn set_mfa(
         &mut self,
@@ -711,7 +1111,8 @@ impl Session {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Forbidden,
                     "User has expired".to_string(),
-                ));
+                )
+                .with_code(ErrorCode::UserExpired));
             } else if ts < self.exp {
                 self.exp = ts;
             }
@@ -0,0 +1,75 @@
+use crate::app_state::AppState;
+use crate::entity::remote_jwks::{JwtAudience, RemoteJwks};
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::{
+    CLOCK_SKEW_LEEWAY, IDX_SPIFFE_BUNDLE, SPIFFE_CLIENTS, SPIFFE_JWKS_URL, SPIFFE_TRUST_DOMAIN,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::Deserialize;
+use tracing::warn;
+
+#[derive(Debug, Deserialize)]
+struct SvidClaims {
+    sub: String,
+    aud: JwtAudience,
+    exp: i64,
+}
+
+/// Validates SPIFFE JWT-SVIDs, which workloads can use as a `client_assertion` with the
+/// `client_credentials` grant instead of a static client secret. The public keys are fetched from
+/// the JWKS endpoint of the trust domain, usually the SPIRE OIDC Discovery Provider.
+pub struct SpiffeSvid;
+
+impl SpiffeSvid {
+    #[inline(always)]
+    pub fn is_enabled() -> bool {
+        SPIFFE_TRUST_DOMAIN.is_some() && SPIFFE_JWKS_URL.is_some()
+    }
+
+    /// Validates the given JWT-SVID and returns the `client_id` the SPIFFE ID is mapped to.
+    /// The SVID must have been issued for the Rauthy issuer as its audience.
+    pub async fn validate(data: &web::Data<AppState>, svid: &str) -> Result<String, ErrorResponse> {
+        if !Self::is_enabled() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "SPIFFE client authentication is not enabled".to_string(),
+            ));
+        }
+
+        let jwks = RemoteJwks {
+            url: SPIFFE_JWKS_URL.as_ref().unwrap(),
+            cache_idx: IDX_SPIFFE_BUNDLE,
+        };
+        let claims = jwks.validate_jwt::<SvidClaims>(data, svid).await?;
+
+        if claims.exp + (*CLOCK_SKEW_LEEWAY as i64) < Utc::now().timestamp() {
+            return Err(Self::err_invalid("The JWT-SVID has expired"));
+        }
+        if !claims.aud.contains(&data.issuer) {
+            return Err(Self::err_invalid(
+                "The JWT-SVID has not been issued for Rauthy",
+            ));
+        }
+
+        let trust_domain = format!("spiffe://{}/", SPIFFE_TRUST_DOMAIN.as_ref().unwrap());
+        if !claims.sub.starts_with(&trust_domain) {
+            return Err(Self::err_invalid(
+                "The SPIFFE ID does not belong to the trust domain",
+            ));
+        }
+
+        SPIFFE_CLIENTS
+            .iter()
+            .find(|(spiffe_id, _)| spiffe_id == &claims.sub)
+            .map(|(_, client_id)| client_id.clone())
+            .ok_or_else(|| {
+                warn!("No client mapping for SPIFFE ID {}", claims.sub);
+                Self::err_invalid("No client is mapped to this SPIFFE ID")
+            })
+    }
+
+    fn err_invalid(msg: &str) -> ErrorResponse {
+        ErrorResponse::new(ErrorResponseType::Unauthorized, msg.to_string())
+    }
+}
@@ -0,0 +1,387 @@
+use crate::app_state::AppState;
+use crate::entity::email_aliases::EmailAlias;
+use crate::entity::jwk::{JwkKeyPair, JwkKeyPairAlg, JwkLifecycle};
+use crate::entity::remote_jwks::{JwtAudience, RemoteJwks};
+use crate::entity::used_jtis::UsedJti;
+use crate::entity::users::User;
+use crate::sign_jwt;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::{cookie, web, HttpRequest};
+use chrono::Utc;
+use jwt_simple::prelude::*;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::{
+    CACHE_NAME_SSO_HANDOFF, CLOCK_SKEW_LEEWAY, COOKIE_SSO_HANDOFF, DANGER_COOKIE_INSECURE,
+    SSO_HANDOFF_CONFIRM_TIMEOUT_SECS, SSO_HANDOFF_PEERS, SSO_HANDOFF_TOKEN_LIFETIME,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_url_no_pad_decode, get_rand};
+use redhac::{cache_insert, AckLevel};
+use std::str::FromStr;
+use tracing::warn;
+
+const TOKEN_ALG: &str = "EdDSA";
+const TOKEN_TYP: &str = "LoginHint";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginHintClaims {
+    typ: String,
+    email: String,
+    mfa: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PeerLoginHintClaims {
+    iss: String,
+    sub: String,
+    aud: JwtAudience,
+    exp: i64,
+    jti: Option<String>,
+    typ: String,
+    email: String,
+    mfa: bool,
+}
+
+/// The user a login hint token from a peer has been validated for.
+#[derive(Debug)]
+pub struct SsoHandoffUser {
+    pub user: User,
+    /// If the session at the peer had been authenticated with MFA
+    pub mfa: bool,
+    /// The issuer of the peer
+    pub peer: String,
+}
+
+/// Signed login hint tokens, which hand over an authenticated session to a trusted peer
+/// Rauthy instance from `SSO_HANDOFF_PEERS`.
+///
+/// The tokens are signed with the `EdDSA` key of the issuing instance and validated by the
+/// peer with the keys from the issuer's `/oidc/certs`. Each one is only accepted once.
+pub struct SsoHandoffToken;
+
+impl SsoHandoffToken {
+    #[inline(always)]
+    pub fn is_enabled() -> bool {
+        !SSO_HANDOFF_PEERS.is_empty()
+    }
+
+    /// Issues a login hint token for the given `user` and the peer `audience`.
+    pub async fn issue(
+        data: &web::Data<AppState>,
+        user: &User,
+        mfa: bool,
+        audience: &str,
+    ) -> Result<String, ErrorResponse> {
+        let audience = Self::find_peer(audience).ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The 'audience' is not a trusted SSO handoff peer".to_string(),
+            )
+        })?;
+
+        let claims = Claims::with_custom_claims(
+            LoginHintClaims {
+                typ: TOKEN_TYP.to_string(),
+                email: user.email.clone(),
+                mfa,
+            },
+            coarsetime::Duration::from_secs(*SSO_HANDOFF_TOKEN_LIFETIME),
+        )
+        .with_issuer(data.issuer.clone())
+        .with_audience(audience.to_string())
+        .with_subject(user.id.clone())
+        .with_jwt_id(get_rand(32));
+
+        let key_pair_type = JwkKeyPairAlg::from_str(TOKEN_ALG)?;
+        let kp = JwkKeyPair::find_latest(data, TOKEN_ALG, key_pair_type).await?;
+        JwkLifecycle::count_usage(&kp.kid);
+        sign_jwt!(kp, claims)
+    }
+
+    /// Validates a login hint token from a peer and returns the matching local user.
+    ///
+    /// The user is resolved by the `sub` and the `email` must match as well. The `jti` is
+    /// consumed atomically, so each token can only be used once.
+    pub async fn validate(
+        data: &web::Data<AppState>,
+        token: &str,
+    ) -> Result<SsoHandoffUser, ErrorResponse> {
+        if !Self::is_enabled() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "The SSO handoff is not enabled".to_string(),
+            ));
+        }
+
+        // The issuer must be known before the signature can be validated with its keys.
+        let peer = Self::unverified_issuer(token)
+            .and_then(|iss| Self::find_peer(&iss))
+            .ok_or_else(|| Self::err_invalid("The token has not been issued by a trusted peer"))?;
+        let jwks_url = format!("{}/oidc/certs", peer);
+        let cache_idx = format!("sso_handoff_jwks_{}", peer);
+        let claims = RemoteJwks {
+            url: &jwks_url,
+            cache_idx: &cache_idx,
+        }
+        .validate_jwt::<PeerLoginHintClaims>(data, token)
+        .await?;
+
+        if claims.iss != peer {
+            return Err(Self::err_invalid("Invalid 'iss'"));
+        }
+        if claims.typ != TOKEN_TYP {
+            return Err(Self::err_invalid("The token is no login hint token"));
+        }
+        if !claims.aud.contains(&data.issuer) {
+            return Err(Self::err_invalid(
+                "The token has not been issued for this instance",
+            ));
+        }
+        if claims.exp + (*CLOCK_SKEW_LEEWAY as i64) < Utc::now().timestamp() {
+            return Err(Self::err_invalid("The token has expired"));
+        }
+
+        let Some(jti) = claims.jti else {
+            return Err(Self::err_invalid("Missing 'jti'"));
+        };
+        let scope = format!("sso_handoff_{}", peer);
+        let exp = claims.exp + *CLOCK_SKEW_LEEWAY as i64;
+        if !UsedJti::try_consume(data, &scope, &jti, exp).await? {
+            warn!("Login hint token from {} has been used already", peer);
+            return Err(Self::err_invalid("The token has been used already"));
+        }
+
+        let user = User::find_by_sub(data, claims.sub).await.map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "The user does not exist at this instance".to_string(),
+            )
+        })?;
+        // the E-Mail of a merged user lives on as an alias of the one, which has been kept
+        let email_matches = user.email == claims.email
+            || EmailAlias::find(data, &claims.email)
+                .await?
+                .map(|alias| alias.user_id == user.id)
+                .unwrap_or(false);
+        if !email_matches {
+            warn!(
+                "Login hint token from {} for user {} with a different E-Mail",
+                peer, user.id
+            );
+            return Err(Self::err_invalid(
+                "The user does not match at this instance",
+            ));
+        }
+
+        Ok(SsoHandoffUser {
+            user,
+            mfa: claims.mfa,
+            peer: peer.to_string(),
+        })
+    }
+
+    fn find_peer(issuer: &str) -> Option<&'static str> {
+        let issuer = issuer.trim_end_matches('/');
+        SSO_HANDOFF_PEERS
+            .iter()
+            .find(|p| p.as_str() == issuer)
+            .map(|p| p.as_str())
+    }
+
+    fn unverified_issuer(token: &str) -> Option<String> {
+        #[derive(Deserialize)]
+        struct Issuer {
+            iss: String,
+        }
+
+        let claims = token.split('.').nth(1)?;
+        let bytes = base64_url_no_pad_decode(claims).ok()?;
+        serde_json::from_slice::<Issuer>(&bytes)
+            .ok()
+            .map(|claims| claims.iss)
+    }
+
+    fn err_invalid(msg: &str) -> ErrorResponse {
+        ErrorResponse::new(ErrorResponseType::Unauthorized, msg.to_string())
+    }
+}
+
+/// A validated SSO handoff, which waits for the confirmation of the user.
+///
+/// It is bound to the browser, which opened the handoff link, with a `SameSite=Lax` cookie.
+/// This cookie is not sent with a cross-site `POST`, so a foreign page can not confirm the
+/// handoff and log the user in with another account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsoHandoffPending {
+    pub id: String,
+    pub user_id: String,
+    pub email: String,
+    pub mfa: bool,
+    pub peer: String,
+    pub redirect_uri: String,
+    pub exp: i64,
+}
+
+impl SsoHandoffPending {
+    pub async fn create(
+        data: &web::Data<AppState>,
+        handoff: &SsoHandoffUser,
+        redirect_uri: String,
+    ) -> Result<Self, ErrorResponse> {
+        let slf = Self {
+            id: get_rand(48),
+            user_id: handoff.user.id.clone(),
+            email: handoff.user.email.clone(),
+            mfa: handoff.mfa,
+            peer: handoff.peer.clone(),
+            redirect_uri,
+            exp: Utc::now().timestamp() + SSO_HANDOFF_CONFIRM_TIMEOUT_SECS as i64,
+        };
+
+        cache_insert(
+            CACHE_NAME_SSO_HANDOFF.to_string(),
+            slf.id.clone(),
+            &data.caches.ha_cache_config,
+            &slf,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+
+    /// Consumes the pending handoff with the given `id`, which must match the cookie of the
+    /// request. Each one can only be confirmed once.
+    pub async fn consume(
+        data: &web::Data<AppState>,
+        req: &HttpRequest,
+        id: &str,
+    ) -> Result<Self, ErrorResponse> {
+        let cookie = req.cookie(COOKIE_SSO_HANDOFF).ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "Missing SSO handoff cookie".to_string(),
+            )
+        })?;
+        if cookie.value() != id {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "The SSO handoff has been started in another browser".to_string(),
+            ));
+        }
+
+        let slf = cache_get::<Self>(
+            CACHE_NAME_SSO_HANDOFF.to_string(),
+            id.to_string(),
+            &data.caches.ha_cache_config,
+            true,
+        )
+        .await?
+        .filter(|slf| slf.exp > Utc::now().timestamp())
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "The SSO handoff does not exist or has expired".to_string(),
+            )
+        })?;
+
+        if !UsedJti::try_consume(data, "sso_handoff_confirm", &slf.id, slf.exp).await? {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "The SSO handoff has been confirmed already".to_string(),
+            ));
+        }
+        cache_remove(
+            CACHE_NAME_SSO_HANDOFF.to_string(),
+            slf.id.clone(),
+            &data.caches.ha_cache_config,
+            AckLevel::Quorum,
+        )
+        .await?;
+
+        Ok(slf)
+    }
+
+    pub fn cookie(&self) -> Cookie<'_> {
+        Self::build_cookie(
+            self.id.clone(),
+            cookie::time::Duration::seconds(SSO_HANDOFF_CONFIRM_TIMEOUT_SECS as i64),
+        )
+    }
+
+    pub fn cookie_remove<'a>() -> Cookie<'a> {
+        Self::build_cookie(String::default(), cookie::time::Duration::ZERO)
+    }
+
+    fn build_cookie<'a>(value: String, max_age: cookie::time::Duration) -> Cookie<'a> {
+        Cookie::build(COOKIE_SSO_HANDOFF, value)
+            .secure(!*DANGER_COOKIE_INSECURE)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(max_age)
+            .path("/auth/v1/oidc/sso_handoff")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rauthy_common::utils::base64_url_no_pad_encode;
+
+    #[test]
+    fn test_unverified_issuer() {
+        let claims = base64_url_no_pad_encode(br#"{"iss":"https://eu.example.com/auth/v1"}"#);
+        let token = format!("e30.{}.sig", claims);
+        assert_eq!(
+            SsoHandoffToken::unverified_issuer(&token).as_deref(),
+            Some("https://eu.example.com/auth/v1")
+        );
+
+        assert!(SsoHandoffToken::unverified_issuer("e30").is_none());
+        assert!(SsoHandoffToken::unverified_issuer("e30.e30.sig").is_none());
+        assert!(SsoHandoffToken::unverified_issuer("e30.%%%.sig").is_none());
+    }
+
+    #[test]
+    fn test_peer_claims_require_sub() {
+        let claims = r#"{
+            "iss": "https://eu.example.com/auth/v1",
+            "aud": "https://us.example.com/auth/v1",
+            "exp": 1700000000,
+            "jti": "abc",
+            "typ": "LoginHint",
+            "email": "admin@localhost.de",
+            "mfa": true
+        }"#;
+        assert!(serde_json::from_str::<PeerLoginHintClaims>(claims).is_err());
+
+        let claims = claims.replace(r#""jti""#, r#""sub": "za9UxpH7XVxqrtpEbThoqvn2", "jti""#);
+        let claims = serde_json::from_str::<PeerLoginHintClaims>(&claims).unwrap();
+        assert_eq!(claims.sub, "za9UxpH7XVxqrtpEbThoqvn2");
+    }
+
+    #[test]
+    fn test_pending_cookie() {
+        let pending = SsoHandoffPending {
+            id: get_rand(48),
+            user_id: "za9UxpH7XVxqrtpEbThoqvn2".to_string(),
+            email: "admin@localhost.de".to_string(),
+            mfa: false,
+            peer: "https://eu.example.com/auth/v1".to_string(),
+            redirect_uri: "/auth/v1/account".to_string(),
+            exp: 0,
+        };
+
+        // a cross-site POST must never carry the binding cookie
+        let cookie = pending.cookie();
+        assert_eq!(cookie.value(), pending.id);
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.path(), Some("/auth/v1/oidc/sso_handoff"));
+
+        let cookie = SsoHandoffPending::cookie_remove();
+        assert_eq!(cookie.value(), "");
+        assert_eq!(cookie.max_age(), Some(cookie::time::Duration::ZERO));
+    }
+}
@@ -0,0 +1,31 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use rauthy_common::error_response::ErrorResponse;
+
+/// One-time identifiers like the `jti` of a token, which must only ever be accepted once.
+///
+/// The check relies on the primary key in the database, which makes it atomic, even if the same
+/// value is used on multiple cluster members at the same time.
+pub struct UsedJti;
+
+impl UsedJti {
+    /// Marks the `jti` inside the given `scope` as used until `exp`.
+    ///
+    /// Returns `false` if it has been used already.
+    pub async fn try_consume(
+        data: &web::Data<AppState>,
+        scope: &str,
+        jti: &str,
+        exp: i64,
+    ) -> Result<bool, ErrorResponse> {
+        let id = format!("{}_{}", scope, jti);
+        let res = sqlx::query(
+            "insert into used_jtis (id, exp) values ($1, $2) on conflict(id) do nothing",
+        )
+        .bind(id)
+        .bind(exp)
+        .execute(&data.db)
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+}
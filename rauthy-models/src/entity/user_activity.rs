@@ -0,0 +1,197 @@
+use crate::app_state::AppState;
+use crate::pii;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::RAUTHY_ADMIN_ROLE;
+use rauthy_common::error_response::ErrorResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::error;
+
+/// Activity timestamps are only updated once within this amount of seconds to not write to the
+/// database with each single request.
+const ACTIVITY_RESOLUTION_SECS: i64 = 60;
+
+/// The kind of activity, which is tracked in addition to the `last_login` of a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserActivityKind {
+    Refresh,
+    Userinfo,
+}
+
+/// The latest token refresh and userinfo access of a user.
+#[derive(Debug, Clone, Default, FromRow, Serialize, Deserialize)]
+pub struct UserActivity {
+    pub user_id: String,
+    pub last_refresh: Option<i64>,
+    pub last_userinfo: Option<i64>,
+}
+
+/// A user without any login, token refresh or userinfo access since a given timestamp.
+#[derive(Debug, Clone, FromRow)]
+pub struct InactiveUser {
+    pub id: String,
+    pub email: String,
+    pub roles: String,
+    pub enabled: bool,
+    pub created_at: i64,
+    pub last_login: Option<i64>,
+    pub last_refresh: Option<i64>,
+    pub last_userinfo: Option<i64>,
+}
+
+impl InactiveUser {
+    /// The timestamp of the latest activity, or the creation, if the user has never been active.
+    pub fn last_activity(&self) -> i64 {
+        [self.last_login, self.last_refresh, self.last_userinfo]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(self.created_at)
+    }
+
+    /// Only enabled users are disabled automatically, and never the admins to not lock out
+    /// everyone.
+    pub fn should_disable(&self) -> bool {
+        self.enabled
+            && !self
+                .roles
+                .split(',')
+                .any(|r| r == RAUTHY_ADMIN_ROLE.as_str())
+    }
+}
+
+impl UserActivity {
+    /// Records an activity for the given user in the background to not slow down the token and
+    /// userinfo endpoints.
+    pub fn record(data: &web::Data<AppState>, user_id: String, kind: UserActivityKind) {
+        let data = data.clone();
+        tokio::spawn(async move {
+            if let Err(err) = Self::upsert(&data, &user_id, kind).await {
+                error!(
+                    "Error recording the {:?} activity for user {}: {:?}",
+                    kind, user_id, err
+                );
+            }
+        });
+    }
+
+    async fn upsert(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        kind: UserActivityKind,
+    ) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let threshold = now - ACTIVITY_RESOLUTION_SECS;
+
+        let q = match kind {
+            UserActivityKind::Refresh => sqlx::query!(
+                r#"insert into user_activity (user_id, last_refresh) values ($1, $2)
+                on conflict (user_id) do update set last_refresh = $2
+                where user_activity.last_refresh is null or user_activity.last_refresh < $3"#,
+                user_id,
+                now,
+                threshold,
+            ),
+            UserActivityKind::Userinfo => sqlx::query!(
+                r#"insert into user_activity (user_id, last_userinfo) values ($1, $2)
+                on conflict (user_id) do update set last_userinfo = $2
+                where user_activity.last_userinfo is null or user_activity.last_userinfo < $3"#,
+                user_id,
+                now,
+                threshold,
+            ),
+        };
+        q.execute(&data.db).await?;
+
+        Ok(())
+    }
+
+    pub async fn find(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from user_activity where user_id = $1",
+            user_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns all users without any activity since the given timestamp, oldest first.
+    pub async fn find_inactive(
+        data: &web::Data<AppState>,
+        since: i64,
+    ) -> Result<Vec<InactiveUser>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            InactiveUser,
+            r#"select u.id, u.email, u.roles, u.enabled, u.created_at, u.last_login,
+                a.last_refresh, a.last_userinfo
+            from users u
+            left join user_activity a on a.user_id = u.id
+            where u.created_at < $1
+            and (u.last_login is null or u.last_login < $1)
+            and (a.last_refresh is null or a.last_refresh < $1)
+            and (a.last_userinfo is null or a.last_userinfo < $1)
+            order by u.created_at"#,
+            since
+        )
+        .fetch_all(&data.db)
+        .await?;
+        res.into_iter()
+            .map(|mut user| {
+                user.email = pii::decrypt_email(user.email)?;
+                Ok(user)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_activity() {
+        let mut user = InactiveUser {
+            id: "id".to_string(),
+            email: "admin@localhost.de".to_string(),
+            roles: "user".to_string(),
+            enabled: true,
+            created_at: 100,
+            last_login: None,
+            last_refresh: None,
+            last_userinfo: None,
+        };
+        assert_eq!(user.last_activity(), 100);
+
+        user.last_login = Some(200);
+        user.last_userinfo = Some(300);
+        assert_eq!(user.last_activity(), 300);
+    }
+
+    #[test]
+    fn test_should_disable() {
+        let mut user = InactiveUser {
+            id: "id".to_string(),
+            email: "user@localhost.de".to_string(),
+            roles: "user,admin".to_string(),
+            enabled: true,
+            created_at: 100,
+            last_login: None,
+            last_refresh: None,
+            last_userinfo: None,
+        };
+        assert!(user.should_disable());
+
+        user.enabled = false;
+        assert!(!user.should_disable());
+
+        user.enabled = true;
+        user.roles = "user,rauthy_admin".to_string();
+        assert!(!user.should_disable());
+    }
+}
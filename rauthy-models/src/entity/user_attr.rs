@@ -1,11 +1,13 @@
 use crate::app_state::{AppState, DbTxn};
 use crate::entity::scopes::Scope;
 use crate::entity::users::User;
-use crate::request::{UserAttrConfigRequest, UserAttrValuesUpdateRequest};
+use crate::pii;
+use crate::request::{UserAttrConfigRequest, UserAttrValueRequest, UserAttrValuesUpdateRequest};
 use actix_web::web;
-use rauthy_common::constants::{CACHE_NAME_USERS, IDX_USER_ATTR_CONFIG};
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
+use rauthy_common::constants::{CACHE_NAME_USERS, IDX_USER_ATTR_CONFIG, PII_ENCRYPTION};
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::FromRow;
@@ -20,6 +22,12 @@ pub struct UserAttrConfigEntity {
     pub name: String,
     // Description for the attribute
     pub desc: Option<String>,
+    // If set, users without a value must provide it during their next login
+    pub required: bool,
+    // Comma separated client IDs, which skip the collection of this attribute during the login
+    pub skip_client_ids: Option<String>,
+    // If set, values are encrypted inside the database with `PII_ENCRYPTION`
+    pub sensitive: bool,
 }
 
 // CRUD
@@ -35,27 +43,39 @@ impl UserAttrConfigEntity {
             ));
         }
 
+        let slf = Self {
+            name: new_attr.name,
+            desc: new_attr.desc,
+            required: new_attr.required.unwrap_or(false),
+            skip_client_ids: Self::join_client_ids(new_attr.skip_client_ids),
+            sensitive: new_attr.sensitive.unwrap_or(false),
+        };
+
         #[cfg(not(feature = "postgres"))]
         let q = sqlx::query!(
-            "insert into user_attr_config (name, desc) values ($1, $2)",
-            new_attr.name,
-            new_attr.desc,
+            r#"insert into user_attr_config (name, desc, required, skip_client_ids, sensitive)
+            values ($1, $2, $3, $4, $5)"#,
+            slf.name,
+            slf.desc,
+            slf.required,
+            slf.skip_client_ids,
+            slf.sensitive,
         );
 
         #[cfg(feature = "postgres")]
         let q = sqlx::query!(
-            "insert into user_attr_config (name, \"desc\") values ($1, $2)",
-            new_attr.name,
-            new_attr.desc,
+            r#"insert into user_attr_config (name, "desc", required, skip_client_ids, sensitive)
+            values ($1, $2, $3, $4, $5)"#,
+            slf.name,
+            slf.desc,
+            slf.required,
+            slf.skip_client_ids,
+            slf.sensitive,
         );
 
         q.execute(&data.db).await?;
 
         let mut attrs = UserAttrConfigEntity::find_all(data).await?;
-        let slf = Self {
-            name: new_attr.name.clone(),
-            desc: new_attr.desc.clone(),
-        };
         attrs.push(slf.clone());
         cache_insert(
             CACHE_NAME_USERS.to_string(),
@@ -158,12 +178,11 @@ impl UserAttrConfigEntity {
     }
 
     pub async fn find(data: &web::Data<AppState>, name: String) -> Result<Self, ErrorResponse> {
-        let attr_opt = cache_get!(
-            Self,
+        let attr_opt = cache_get::<Self>(
             CACHE_NAME_USERS.to_string(),
             name.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(attr_opt) = attr_opt {
@@ -186,12 +205,11 @@ impl UserAttrConfigEntity {
     }
 
     pub async fn find_all(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
-        let attrs = cache_get!(
-            Vec<Self>,
+        let attrs = cache_get::<Vec<Self>>(
             CACHE_NAME_USERS.to_string(),
             IDX_USER_ATTR_CONFIG.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(attrs) = attrs {
@@ -222,6 +240,11 @@ impl UserAttrConfigEntity {
 
         slf.name.clone_from(&req_data.name);
         slf.desc.clone_from(&req_data.desc);
+        slf.required = req_data.required.unwrap_or(false);
+        slf.skip_client_ids = Self::join_client_ids(req_data.skip_client_ids.clone());
+        // Existing values are encrypted below, when an attribute becomes sensitive. They are not
+        // decrypted in the other direction, but they can always be read.
+        slf.sensitive = req_data.sensitive.unwrap_or(false);
 
         let is_name_update = name != req_data.name;
 
@@ -245,17 +268,27 @@ impl UserAttrConfigEntity {
 
         #[cfg(not(feature = "postgres"))]
         let q = sqlx::query!(
-            "update user_attr_config set name  = $1, desc = $2 where name = $3",
+            r#"update user_attr_config
+            set name  = $1, desc = $2, required = $3, skip_client_ids = $4, sensitive = $5
+            where name = $6"#,
             slf.name,
             slf.desc,
+            slf.required,
+            slf.skip_client_ids,
+            slf.sensitive,
             name,
         );
 
         #[cfg(feature = "postgres")]
         let q = sqlx::query!(
-            "update user_attr_config set name  = $1, \"desc\" = $2 where name = $3",
+            r#"update user_attr_config
+            set name  = $1, "desc" = $2, required = $3, skip_client_ids = $4, sensitive = $5
+            where name = $6"#,
             slf.name,
             slf.desc,
+            slf.required,
+            slf.skip_client_ids,
+            slf.sensitive,
             name,
         );
 
@@ -309,13 +342,7 @@ impl UserAttrConfigEntity {
         let attrs = Self::find_all(data)
             .await?
             .into_iter()
-            .map(|mut attr| {
-                if attr.name == name {
-                    attr.name.clone_from(&slf.name);
-                    attr.desc.clone_from(&slf.desc);
-                }
-                attr
-            })
+            .map(|attr| if attr.name == name { slf.clone() } else { attr })
             .collect::<Vec<Self>>();
 
         cache_insert(
@@ -339,6 +366,10 @@ impl UserAttrConfigEntity {
             }
         }
 
+        if slf.sensitive {
+            UserAttrValueEntity::encrypt_existing(data, &HashSet::from([slf.name.clone()])).await?;
+        }
+
         Ok(slf)
     }
 }
@@ -364,6 +395,121 @@ impl UserAttrConfigEntity {
         });
         res
     }
+
+    /// Returns `true` if users must provide a value for this attribute when they log in to the
+    /// given client.
+    pub fn is_required_for(&self, client_id: &str) -> bool {
+        self.required
+            && !self
+                .skip_client_ids
+                .as_deref()
+                .map(|ids| ids.split(',').any(|id| id == client_id))
+                .unwrap_or(false)
+    }
+
+    /// Returns all attributes, which are required for the given client, but have no value for
+    /// the user yet.
+    pub async fn find_missing_required(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        client_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let required = Self::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|a| a.is_required_for(client_id))
+            .collect::<Vec<Self>>();
+        if required.is_empty() {
+            return Ok(required);
+        }
+
+        let existing = UserAttrValueEntity::find_for_user(data, user_id)
+            .await?
+            .into_iter()
+            .map(|v| v.key)
+            .collect::<HashSet<String>>();
+        Ok(required
+            .into_iter()
+            .filter(|a| !existing.contains(&a.name))
+            .collect())
+    }
+
+    /// Returns the required attributes, which are still missing after applying the given
+    /// values, together with the values for the missing ones.
+    ///
+    /// Nothing is saved here, because the login may still need another factor. The values must
+    /// be kept with the authorization code and saved with `save_missing_required` when it is
+    /// redeemed.
+    pub async fn collect_missing_required(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        client_id: &str,
+        values: Option<Vec<UserAttrValueRequest>>,
+    ) -> Result<(Vec<Self>, Option<Vec<UserAttrValueRequest>>), ErrorResponse> {
+        let missing = Self::find_missing_required(data, user_id, client_id).await?;
+        let Some(values) = values else {
+            return Ok((missing, None));
+        };
+        if missing.is_empty() {
+            return Ok((missing, None));
+        }
+
+        let values = Self::filter_missing(&missing, values);
+        if values.is_empty() {
+            return Ok((missing, None));
+        }
+
+        let provided = values
+            .iter()
+            .map(|v| v.key.as_str())
+            .collect::<HashSet<&str>>();
+        let missing = missing
+            .into_iter()
+            .filter(|a| !provided.contains(a.name.as_str()))
+            .collect();
+        Ok((missing, Some(values)))
+    }
+
+    /// Saves the given values for required attributes, which are still missing for the user.
+    /// Values for any other attribute are ignored, because users are not allowed to modify
+    /// their attributes themselves.
+    pub async fn save_missing_required(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        client_id: &str,
+        values: Vec<UserAttrValueRequest>,
+    ) -> Result<(), ErrorResponse> {
+        let missing = Self::find_missing_required(data, user_id, client_id).await?;
+        let values = Self::filter_missing(&missing, values);
+        if !values.is_empty() {
+            UserAttrValueEntity::update_for_user(
+                data,
+                user_id,
+                UserAttrValuesUpdateRequest { values },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn filter_missing(
+        missing: &[Self],
+        values: Vec<UserAttrValueRequest>,
+    ) -> Vec<UserAttrValueRequest> {
+        values
+            .into_iter()
+            .filter(|v| {
+                let is_empty = v.value == Value::Null || v.value.as_str() == Some("");
+                !is_empty && missing.iter().any(|a| a.name == v.key)
+            })
+            .collect()
+    }
+
+    fn join_client_ids(client_ids: Option<Vec<String>>) -> Option<String> {
+        client_ids
+            .map(|ids| ids.join(","))
+            .filter(|ids| !ids.is_empty())
+    }
 }
 
 /// The value for a pre-defined UserAttrConfig with all `serde_json::Value` being valid values.
@@ -411,25 +557,26 @@ impl UserAttrValueEntity {
         user_id: &str,
     ) -> Result<Vec<Self>, ErrorResponse> {
         let idx = Self::cache_idx(user_id);
-        let attrs = cache_get!(
-            Vec<Self>,
+        let attrs = cache_get::<Vec<Self>>(
             CACHE_NAME_USERS.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(attrs) = attrs {
             return Ok(attrs);
         }
 
-        let res = sqlx::query_as!(
-            Self,
-            "select * from user_attr_values where user_id = $1",
-            user_id
-        )
-        .fetch_all(&data.db)
-        .await?;
+        let res = Self::decrypt_all(
+            sqlx::query_as!(
+                Self,
+                "select * from user_attr_values where user_id = $1",
+                user_id
+            )
+            .fetch_all(&data.db)
+            .await?,
+        )?;
 
         cache_insert(
             CACHE_NAME_USERS.to_string(),
@@ -451,6 +598,12 @@ impl UserAttrValueEntity {
         // Not necessary for the operation and correctness, but look up the user first and return
         // an error, if it does not exist at all, for a better user experience.
         User::exists(data, user_id.to_string()).await?;
+        let sensitive = UserAttrConfigEntity::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|attr| attr.sensitive)
+            .map(|attr| attr.name)
+            .collect::<HashSet<String>>();
 
         for value in req_data.values {
             let del = if let Some(s) = value.value.as_str() {
@@ -468,7 +621,10 @@ impl UserAttrValueEntity {
                 .execute(&data.db)
                 .await?;
             } else {
-                let v = serde_json::to_vec(&value.value).unwrap();
+                let v = pii::encrypt_bytes(
+                    serde_json::to_vec(&value.value).unwrap(),
+                    sensitive.contains(&value.key),
+                )?;
 
                 #[cfg(not(feature = "postgres"))]
                 let q = sqlx::query!(
@@ -494,13 +650,15 @@ impl UserAttrValueEntity {
         }
 
         // 2nd query again to have more compatibility
-        let res = sqlx::query_as!(
-            Self,
-            "select * from user_attr_values where user_id = $1",
-            user_id
-        )
-        .fetch_all(&data.db)
-        .await?;
+        let res = Self::decrypt_all(
+            sqlx::query_as!(
+                Self,
+                "select * from user_attr_values where user_id = $1",
+                user_id
+            )
+            .fetch_all(&data.db)
+            .await?,
+        )?;
 
         let idx = Self::cache_idx(user_id);
         cache_insert(
@@ -517,7 +675,105 @@ impl UserAttrValueEntity {
 }
 
 impl UserAttrValueEntity {
-    fn cache_idx(user_id: &str) -> String {
+    pub(crate) fn cache_idx(user_id: &str) -> String {
         format!("{}{}", IDX_USER_ATTR_CONFIG, user_id)
     }
+
+    fn decrypt_all(mut values: Vec<Self>) -> Result<Vec<Self>, ErrorResponse> {
+        for v in values.iter_mut() {
+            v.value = pii::decrypt_bytes(std::mem::take(&mut v.value))?;
+        }
+        Ok(values)
+    }
+
+    /// Encrypts all values for the given sensitive keys, which are still stored in cleartext,
+    /// if `PII_ENCRYPTION` is enabled. Returns the amount of modified values.
+    pub async fn encrypt_existing(
+        data: &web::Data<AppState>,
+        sensitive: &HashSet<String>,
+    ) -> Result<usize, ErrorResponse> {
+        if !*PII_ENCRYPTION || sensitive.is_empty() {
+            return Ok(0);
+        }
+
+        let values = sqlx::query_as!(Self, "select * from user_attr_values")
+            .fetch_all(&data.db)
+            .await?;
+
+        let mut modified = 0;
+        for v in values {
+            if !sensitive.contains(&v.key) {
+                continue;
+            }
+            let enc = pii::encrypt_bytes(v.value.clone(), true)?;
+            if enc != v.value {
+                sqlx::query!(
+                    "update user_attr_values set value = $1 where user_id = $2 and key = $3",
+                    enc,
+                    v.user_id,
+                    v.key,
+                )
+                .execute(&data.db)
+                .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+
+    /// Re-encrypts all encrypted values with the given key and returns the amount of modified
+    /// values. The cached values are in cleartext and do not need to be touched.
+    pub async fn migrate_pii_key(
+        data: &web::Data<AppState>,
+        key_id: &str,
+    ) -> Result<usize, ErrorResponse> {
+        let values = sqlx::query_as!(Self, "select * from user_attr_values")
+            .fetch_all(&data.db)
+            .await?;
+
+        let mut modified = 0;
+        for v in values {
+            if let Some(enc) = pii::reencrypt_bytes(&v.value, key_id)? {
+                sqlx::query!(
+                    "update user_attr_values set value = $1 where user_id = $2 and key = $3",
+                    enc,
+                    v.user_id,
+                    v.key,
+                )
+                .execute(&data.db)
+                .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_required_for() {
+        let mut attr = UserAttrConfigEntity {
+            name: "department".to_string(),
+            desc: None,
+            required: false,
+            skip_client_ids: Some("kiosk,legacy".to_string()),
+            sensitive: false,
+        };
+        assert!(!attr.is_required_for("app"));
+
+        attr.required = true;
+        assert!(attr.is_required_for("app"));
+        assert!(attr.is_required_for("kiosk-2"));
+        assert!(!attr.is_required_for("kiosk"));
+        assert!(!attr.is_required_for("legacy"));
+
+        assert_eq!(UserAttrConfigEntity::join_client_ids(Some(vec![])), None);
+        assert_eq!(
+            UserAttrConfigEntity::join_client_ids(Some(vec!["a".to_string(), "b".to_string()])),
+            Some("a,b".to_string())
+        );
+    }
 }
@@ -0,0 +1,115 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::error;
+
+/// A client a user has logged in to and which has been issued tokens on behalf of the user.
+///
+/// These grants are shown as "connected applications" in the account portal, where a user can
+/// revoke them, which will invalidate all refresh tokens the client holds for this user.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserClientGrant {
+    pub user_id: String,
+    pub client_id: String,
+    /// the space separated scopes of the latest token issuance
+    pub scope: Option<String>,
+    /// unix timestamp of the first token issuance
+    pub created: i64,
+    /// unix timestamp of the latest token issuance
+    pub last_used: i64,
+}
+
+impl UserClientGrant {
+    /// Records a token issuance for the given user and client in the background to not slow
+    /// down the token endpoint.
+    pub fn record(data: &web::Data<AppState>, user_id: String, client_id: String, scope: String) {
+        let data = data.clone();
+        tokio::spawn(async move {
+            if let Err(err) = Self::upsert(&data, &user_id, &client_id, &scope).await {
+                error!(
+                    "Error recording the client grant for user {} and client {}: {:?}",
+                    user_id, client_id, err
+                );
+            }
+        });
+    }
+
+    async fn upsert(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        client_id: &str,
+        scope: &str,
+    ) -> Result<(), ErrorResponse> {
+        let now = Utc::now().timestamp();
+
+        sqlx::query!(
+            r#"insert into user_client_grants (user_id, client_id, scope, created, last_used)
+            values ($1, $2, $3, $4, $4)
+            on conflict (user_id, client_id) do update
+            set scope = $3, last_used = $4"#,
+            user_id,
+            client_id,
+            scope,
+            now,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_for_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from user_client_grants where user_id = $1 order by last_used desc",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Revokes the grant and deletes all refresh tokens, that have been issued for this client
+    /// on behalf of the user.
+    pub async fn revoke(
+        data: &web::Data<AppState>,
+        user_id: &str,
+        client_id: &str,
+    ) -> Result<(), ErrorResponse> {
+        let mut txn = data.db.begin().await?;
+
+        let res = sqlx::query!(
+            "delete from user_client_grants where user_id = $1 and client_id = $2",
+            user_id,
+            client_id
+        )
+        .execute(&mut *txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "The user has no grant for this client".to_string(),
+            ));
+        }
+
+        // Refresh tokens issued before the client has been recorded with them have no
+        // `client_id`. They could belong to this client and must be revoked as well.
+        sqlx::query!(
+            r#"delete from refresh_tokens
+            where user_id = $1 and (client_id = $2 or client_id is null)"#,
+            user_id,
+            client_id
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,662 @@
+use crate::app_state::AppState;
+use crate::entity::email_aliases::EmailAlias;
+use crate::entity::legal_holds::LegalHold;
+use crate::entity::sessions::Session;
+use crate::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
+use crate::entity::user_client_grants::UserClientGrant;
+use crate::entity::user_role_grants::UserRoleGrant;
+use crate::entity::users::User;
+use crate::entity::users_values::UserValues;
+use crate::entity::webauthn::PasskeyEntity;
+use crate::events::event::Event;
+use crate::pii;
+use crate::request::UserMergeWinner;
+use crate::response::UserMergeResponse;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::cache_metrics::cache_remove;
+use rauthy_common::constants::{CACHE_NAME_USERS, CACHE_NAME_WEBAUTHN, IDX_USERS_VALUES};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use redhac::AckLevel;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashSet;
+
+/// The tombstone of a user, which has been merged into another one. Lookups by token subject
+/// follow it to the user, which has been kept.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserMerge {
+    pub old_id: String,
+    pub new_id: String,
+    pub merged_by: String,
+    pub merged_at: i64,
+}
+
+/// Everything, which needs to be changed for the target user during a merge.
+#[derive(Debug)]
+struct MergePlan {
+    user: User,
+    values: Option<UserValues>,
+    /// custom attributes, which need to be written for the target
+    attrs: Vec<UserAttrValueEntity>,
+    /// (source name, new name) for each passkey of the source
+    passkeys: Vec<(String, String)>,
+    /// the ids of the time-limited role grants, which are moved to the target
+    role_grants: Vec<String>,
+    attributes_from_source: Vec<String>,
+}
+
+impl UserMerge {
+    /// Returns the id of the user, which the given one has been merged into.
+    pub async fn find_new_id(
+        data: &web::Data<AppState>,
+        old_id: &str,
+    ) -> Result<Option<String>, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "select * from user_merges where old_id = $1", old_id)
+            .fetch_optional(&data.db)
+            .await?;
+        Ok(res.map(|m| m.new_id))
+    }
+
+    /// Merges the user `source_id` into `target_id`. Roles, groups, passkeys, consents,
+    /// organization memberships and E-Mail aliases are united, and the `winner` decides about
+    /// attributes, which are set on both sides. Attributes, which are only set for the source,
+    /// are always taken over. The password, the MFA settings and the account state of the target
+    /// are kept as they are.
+    ///
+    /// The source is deleted afterward and only a tombstone is kept, which redirects its id to
+    /// the target. With `dry_run`, the result is computed without changing anything.
+    pub async fn merge(
+        data: &web::Data<AppState>,
+        target_id: String,
+        source_id: String,
+        winner: UserMergeWinner,
+        merged_by: String,
+        dry_run: bool,
+        ip: Option<String>,
+    ) -> Result<UserMergeResponse, ErrorResponse> {
+        if target_id == source_id {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "A user cannot be merged into itself".to_string(),
+            ));
+        }
+
+        let target = User::find(data, target_id).await?;
+        let source = User::find(data, source_id).await?;
+        LegalHold::check_deletable(data, &source.id).await?;
+
+        let target_pks = PasskeyEntity::find_for_user(data, &target.id).await?;
+        let source_pks = PasskeyEntity::find_for_user(data, &source.id).await?;
+        let target_grants = UserClientGrant::find_for_user(data, &target.id).await?;
+        let source_grants = UserClientGrant::find_for_user(data, &source.id).await?;
+        let source_role_grants = UserRoleGrant::find_for_user(data, &source.id).await?;
+        let source_aliases = EmailAlias::find_for_user(data, &source.id).await?;
+        let source_orgs = sqlx::query_scalar!(
+            "select org_id from organization_members where user_id = $1",
+            source.id
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        let plan = Self::plan(
+            &target,
+            &source,
+            UserValues::find(data, &target.id).await?,
+            UserValues::find(data, &source.id).await?,
+            UserAttrValueEntity::find_for_user(data, &target.id).await?,
+            UserAttrValueEntity::find_for_user(data, &source.id).await?,
+            &target_pks
+                .iter()
+                .map(|pk| pk.name.clone())
+                .collect::<Vec<_>>(),
+            &source_pks
+                .iter()
+                .map(|pk| pk.name.clone())
+                .collect::<Vec<_>>(),
+            &source_role_grants,
+            winner,
+        );
+
+        let mut email_aliases = Vec::with_capacity(source_aliases.len() + 1);
+        email_aliases.push(source.email.clone());
+        email_aliases.extend(source_aliases.into_iter().map(|a| a.email));
+
+        let resp = UserMergeResponse {
+            dry_run,
+            target_id: target.id.clone(),
+            source_id: source.id.clone(),
+            roles: plan.user.get_roles(),
+            groups: plan.user.get_groups(),
+            passkeys: plan.passkeys.iter().map(|(_, new)| new.clone()).collect(),
+            client_grants: source_grants
+                .iter()
+                .filter(|g| !target_grants.iter().any(|t| t.client_id == g.client_id))
+                .map(|g| g.client_id.clone())
+                .collect(),
+            organizations: source_orgs,
+            email_aliases,
+            attributes_from_source: plan.attributes_from_source.clone(),
+        };
+        if dry_run {
+            return Ok(resp);
+        }
+
+        let mut events = vec![Event::user_merged(
+            format!(
+                "{} ({}) -> {} ({}) by {}",
+                source.email, source.id, target.email, target.id, merged_by
+            ),
+            ip.clone(),
+        )
+        .for_user(target.id.clone())];
+        if !target.is_admin() && plan.user.is_admin() {
+            events.push(
+                Event::new_rauthy_admin(plan.user.email.clone(), ip).for_user(target.id.clone()),
+            );
+        }
+
+        // the plan holds cleartext values, which must be encrypted again before being saved
+        let sensitive = UserAttrConfigEntity::find_all(data)
+            .await?
+            .into_iter()
+            .filter(|attr| attr.sensitive)
+            .map(|attr| attr.name)
+            .collect::<HashSet<String>>();
+        let values_phone = match &plan.values {
+            Some(v) => pii::encrypt_str(v.phone.clone())?,
+            None => None,
+        };
+
+        let now = Utc::now().timestamp();
+        let mut txn = data.db.begin().await?;
+
+        // the sessions would be deleted via cascade as well, but are needed for the cache
+        let source_sessions = Session::delete_by_user_txn(&source.id, &mut txn).await?;
+
+        for (name, new_name) in &plan.passkeys {
+            sqlx::query!(
+                "update passkeys set user_id = $1, name = $2 where user_id = $3 and name = $4",
+                target.id,
+                new_name,
+                source.id,
+                name,
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"insert into user_client_grants (user_id, client_id, scope, created, last_used)
+            select $1, client_id, scope, created, last_used
+            from user_client_grants where user_id = $2
+            on conflict (user_id, client_id) do nothing"#,
+            target.id,
+            source.id,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        sqlx::query!(
+            r#"insert into organization_members (org_id, user_id, is_owner, created)
+            select org_id, $1, is_owner, created
+            from organization_members where user_id = $2
+            on conflict (org_id, user_id) do nothing"#,
+            target.id,
+            source.id,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        for id in &plan.role_grants {
+            sqlx::query!(
+                "update user_role_grants set user_id = $1 where id = $2",
+                target.id,
+                id,
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+
+        sqlx::query!(
+            "update email_aliases set user_id = $1 where user_id = $2",
+            target.id,
+            source.id,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        // older tombstones pointing to the source are redirected as well to never need more
+        // than a single hop
+        sqlx::query!(
+            "update user_merges set new_id = $1 where new_id = $2",
+            target.id,
+            source.id,
+        )
+        .execute(&mut *txn)
+        .await?;
+        sqlx::query!(
+            r#"insert into user_merges (old_id, new_id, merged_by, merged_at)
+            values ($1, $2, $3, $4)"#,
+            source.id,
+            target.id,
+            merged_by,
+            now,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        // The source must be gone before the target is saved, because the E-Mail, the username
+        // and the `webauthn_user_id` are unique. Everything, which has not been moved, is
+        // deleted via cascade.
+        sqlx::query!("delete from users where id = $1", source.id)
+            .execute(&mut *txn)
+            .await?;
+
+        let source_email = pii::encrypt_email(&source.email)?;
+        sqlx::query!(
+            "insert into email_aliases (email, user_id, created) values ($1, $2, $3)",
+            source_email,
+            target.id,
+            now,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        for attr in &plan.attrs {
+            sqlx::query!(
+                "delete from user_attr_values where user_id = $1 and key = $2",
+                attr.user_id,
+                attr.key,
+            )
+            .execute(&mut *txn)
+            .await?;
+            let value = pii::encrypt_bytes(attr.value.clone(), sensitive.contains(&attr.key))?;
+            sqlx::query!(
+                "insert into user_attr_values (user_id, key, value) values ($1, $2, $3)",
+                attr.user_id,
+                attr.key,
+                value,
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+
+        if let Some(v) = &plan.values {
+            sqlx::query!("delete from users_values where id = $1", v.id)
+                .execute(&mut *txn)
+                .await?;
+            sqlx::query!(
+                r#"insert into users_values (id, birthdate, phone, street, zip, city, country)
+                values ($1, $2, $3, $4, $5, $6, $7)"#,
+                v.id,
+                v.birthdate,
+                values_phone,
+                v.street,
+                v.zip,
+                v.city,
+                v.country,
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+
+        plan.user.save(data, None, Some(&mut txn)).await?;
+        for event in &events {
+            event.outbox_insert(&mut txn).await?;
+        }
+        txn.commit().await?;
+
+        for event in events {
+            data.tx_events.send_async(event).await.unwrap();
+        }
+
+        Session::evict_all(data, &source_sessions).await?;
+        source.cleanup_after_delete(data).await?;
+        Self::cleanup_caches(data, &target.id, &source.id, &source_pks, &plan).await?;
+
+        Ok(resp)
+    }
+}
+
+impl UserMerge {
+    #[allow(clippy::too_many_arguments)]
+    fn plan(
+        target: &User,
+        source: &User,
+        target_values: Option<UserValues>,
+        source_values: Option<UserValues>,
+        target_attrs: Vec<UserAttrValueEntity>,
+        source_attrs: Vec<UserAttrValueEntity>,
+        target_passkeys: &[String],
+        source_passkeys: &[String],
+        source_role_grants: &[UserRoleGrant],
+        winner: UserMergeWinner,
+    ) -> MergePlan {
+        let mut from_source = Vec::new();
+        let mut user = target.clone();
+
+        let mut roles = target.get_roles();
+        for role in source.get_roles() {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+        user.roles = roles.join(",");
+
+        let mut groups = target.get_groups();
+        for group in source.get_groups() {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        user.groups = if groups.is_empty() {
+            None
+        } else {
+            Some(groups.join(","))
+        };
+
+        let non_empty = |s: &String| Some(s.clone()).filter(|s| !s.is_empty());
+        user.given_name = pick(
+            "given_name",
+            non_empty(&target.given_name),
+            non_empty(&source.given_name),
+            winner,
+            &mut from_source,
+        )
+        .unwrap_or_default();
+        user.family_name = pick(
+            "family_name",
+            non_empty(&target.family_name),
+            non_empty(&source.family_name),
+            winner,
+            &mut from_source,
+        )
+        .unwrap_or_default();
+        user.language = pick(
+            "language",
+            Some(target.language),
+            Some(source.language),
+            winner,
+            &mut from_source,
+        )
+        .unwrap_or(target.language);
+        user.username = pick(
+            "username",
+            target.username.clone(),
+            source.username.clone(),
+            winner,
+            &mut from_source,
+        );
+
+        // a federation link is only taken over as a whole and never replaces an existing one
+        if target.federation_uid.is_none() && source.federation_uid.is_some() {
+            user.auth_provider_id = source.auth_provider_id.clone();
+            user.federation_uid = source.federation_uid.clone();
+            from_source.push("federation".to_string());
+        }
+
+        // passkeys are bound to the `webauthn_user_id` they have been registered with
+        if target.webauthn_user_id.is_none() && !source_passkeys.is_empty() {
+            user.webauthn_user_id = source.webauthn_user_id.clone();
+        }
+
+        let values = match (target_values, source_values) {
+            (t, None) => t,
+            (t, Some(s)) => {
+                let t = t.unwrap_or_else(|| UserValues {
+                    id: target.id.clone(),
+                    birthdate: None,
+                    phone: None,
+                    street: None,
+                    zip: None,
+                    city: None,
+                    country: None,
+                });
+                Some(UserValues {
+                    birthdate: pick(
+                        "birthdate",
+                        t.birthdate,
+                        s.birthdate,
+                        winner,
+                        &mut from_source,
+                    ),
+                    phone: pick("phone", t.phone, s.phone, winner, &mut from_source),
+                    street: pick("street", t.street, s.street, winner, &mut from_source),
+                    zip: pick("zip", t.zip, s.zip, winner, &mut from_source),
+                    city: pick("city", t.city, s.city, winner, &mut from_source),
+                    country: pick("country", t.country, s.country, winner, &mut from_source),
+                    id: t.id,
+                })
+            }
+        };
+
+        let mut attrs = Vec::new();
+        for attr in source_attrs {
+            let current = target_attrs
+                .iter()
+                .find(|a| a.key == attr.key)
+                .map(|a| a.value.clone());
+            let is_from_source = current.is_none()
+                || (winner == UserMergeWinner::Source && current.as_ref() != Some(&attr.value));
+            if is_from_source {
+                from_source.push(attr.key.clone());
+                attrs.push(UserAttrValueEntity {
+                    user_id: target.id.clone(),
+                    key: attr.key,
+                    value: attr.value,
+                });
+            }
+        }
+
+        let mut taken = target_passkeys.to_vec();
+        let passkeys = source_passkeys
+            .iter()
+            .map(|name| {
+                let mut new_name = name.clone();
+                let mut i = 2;
+                while taken.contains(&new_name) {
+                    new_name = format!("{}-{}", name, i);
+                    i += 1;
+                }
+                taken.push(new_name.clone());
+                (name.clone(), new_name)
+            })
+            .collect();
+
+        // a running grant for a role, which the target has anyway, would remove this role
+        // after its expiry
+        let target_roles = target.get_roles();
+        let role_grants = source_role_grants
+            .iter()
+            .filter(|g| !target_roles.contains(&g.role))
+            .map(|g| g.id.clone())
+            .collect();
+
+        MergePlan {
+            user,
+            values,
+            attrs,
+            passkeys,
+            role_grants,
+            attributes_from_source: from_source,
+        }
+    }
+
+    async fn cleanup_caches(
+        data: &web::Data<AppState>,
+        target_id: &str,
+        source_id: &str,
+        source_pks: &[PasskeyEntity],
+        plan: &MergePlan,
+    ) -> Result<(), ErrorResponse> {
+        let mut webauthn_idxs = vec![
+            PasskeyEntity::cache_idx_user(target_id),
+            PasskeyEntity::cache_idx_creds(target_id),
+            PasskeyEntity::cache_idx_user(source_id),
+            PasskeyEntity::cache_idx_creds(source_id),
+        ];
+        for pk in source_pks {
+            webauthn_idxs.push(PasskeyEntity::cache_idx_single(source_id, &pk.name));
+        }
+        for (_, new_name) in &plan.passkeys {
+            webauthn_idxs.push(PasskeyEntity::cache_idx_single(target_id, new_name));
+        }
+        for idx in webauthn_idxs {
+            cache_remove(
+                CACHE_NAME_WEBAUTHN.to_string(),
+                idx,
+                &data.caches.ha_cache_config,
+                AckLevel::Quorum,
+            )
+            .await?;
+        }
+
+        let users_idxs = [
+            UserAttrValueEntity::cache_idx(target_id),
+            UserAttrValueEntity::cache_idx(source_id),
+            format!("{}_{}", IDX_USERS_VALUES, target_id),
+            format!("{}_{}", IDX_USERS_VALUES, source_id),
+        ];
+        for idx in users_idxs {
+            cache_remove(
+                CACHE_NAME_USERS.to_string(),
+                idx,
+                &data.caches.ha_cache_config,
+                AckLevel::Quorum,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the value for an attribute, which may be set on both sides of a merge and records,
+/// if the value of the source has been taken.
+fn pick<T: PartialEq>(
+    name: &str,
+    target: Option<T>,
+    source: Option<T>,
+    winner: UserMergeWinner,
+    from_source: &mut Vec<String>,
+) -> Option<T> {
+    match (target, source) {
+        (None, Some(s)) => {
+            from_source.push(name.to_string());
+            Some(s)
+        }
+        (Some(t), Some(s)) if winner == UserMergeWinner::Source && t != s => {
+            from_source.push(name.to_string());
+            Some(s)
+        }
+        (t, _) => t,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Language;
+
+    fn user(id: &str) -> User {
+        User {
+            id: id.to_string(),
+            email: format!("{}@localhost.de", id),
+            given_name: "Given".to_string(),
+            family_name: "".to_string(),
+            password: None,
+            roles: "user".to_string(),
+            groups: None,
+            enabled: true,
+            email_verified: true,
+            password_expires: None,
+            created_at: 0,
+            last_login: None,
+            last_failed_login: None,
+            failed_login_attempts: None,
+            language: Language::En,
+            webauthn_user_id: None,
+            user_expires: None,
+            auth_provider_id: None,
+            federation_uid: None,
+            username: None,
+            email_mfa: false,
+        }
+    }
+
+    #[test]
+    fn test_user_merge_plan() {
+        let target = User {
+            groups: Some("a".to_string()),
+            ..user("target")
+        };
+        let source = User {
+            given_name: "Other".to_string(),
+            family_name: "Family".to_string(),
+            roles: "user,admin".to_string(),
+            groups: Some("a,b".to_string()),
+            webauthn_user_id: Some("pk_user".to_string()),
+            language: Language::De,
+            ..user("source")
+        };
+        let attr = |user_id: &str, key: &str, value: &[u8]| UserAttrValueEntity {
+            user_id: user_id.to_string(),
+            key: key.to_string(),
+            value: value.to_vec(),
+        };
+
+        let plan = UserMerge::plan(
+            &target,
+            &source,
+            None,
+            None,
+            vec![attr("target", "shared", b"t")],
+            vec![attr("source", "shared", b"s"), attr("source", "only", b"s")],
+            &["key".to_string(), "key-2".to_string()],
+            &["key".to_string(), "other".to_string()],
+            &[],
+            UserMergeWinner::Target,
+        );
+        assert_eq!(plan.user.roles, "user,admin");
+        assert_eq!(plan.user.groups.as_deref(), Some("a,b"));
+        assert_eq!(plan.user.given_name, "Given");
+        assert_eq!(plan.user.family_name, "Family");
+        assert_eq!(plan.user.language, Language::En);
+        assert_eq!(plan.user.webauthn_user_id.as_deref(), Some("pk_user"));
+        assert!(plan.values.is_none());
+        assert_eq!(plan.attrs.len(), 1);
+        assert_eq!(plan.attrs[0].key, "only");
+        assert_eq!(plan.attrs[0].user_id, "target");
+        assert_eq!(
+            plan.passkeys,
+            vec![
+                ("key".to_string(), "key-3".to_string()),
+                ("other".to_string(), "other".to_string()),
+            ]
+        );
+        assert_eq!(plan.attributes_from_source, vec!["family_name", "only"]);
+
+        let plan = UserMerge::plan(
+            &target,
+            &source,
+            None,
+            None,
+            vec![attr("target", "shared", b"t")],
+            vec![attr("source", "shared", b"s")],
+            &[],
+            &[],
+            &[],
+            UserMergeWinner::Source,
+        );
+        assert_eq!(plan.user.given_name, "Other");
+        assert_eq!(plan.user.language, Language::De);
+        // without any passkeys to move, the target must not get a `webauthn_user_id`
+        assert!(plan.user.webauthn_user_id.is_none());
+        assert_eq!(plan.attrs[0].value, b"s".to_vec());
+        assert_eq!(
+            plan.attributes_from_source,
+            vec!["given_name", "family_name", "language", "shared"]
+        );
+    }
+}
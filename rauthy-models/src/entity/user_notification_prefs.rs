@@ -0,0 +1,54 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use rauthy_common::error_response::ErrorResponse;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Controls which informational E-Mails a user wants to receive.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserNotificationPrefs {
+    pub user_id: String,
+    /// reminder before the password expires
+    pub password_expiry: bool,
+    /// info after all sessions have been revoked because of a credential change
+    pub sessions_revoked: bool,
+}
+
+impl UserNotificationPrefs {
+    /// Returns the preferences for the given user, or the defaults if none have been saved yet.
+    pub async fn find(data: &web::Data<AppState>, user_id: &str) -> Result<Self, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "select * from user_notification_prefs where user_id = $1",
+            user_id
+        )
+        .fetch_optional(&data.db)
+        .await?;
+
+        Ok(res.unwrap_or_else(|| Self::new(user_id.to_string())))
+    }
+
+    pub async fn save(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        sqlx::query!(
+            r#"insert into user_notification_prefs (user_id, password_expiry, sessions_revoked)
+            values ($1, $2, $3)
+            on conflict (user_id) do update
+            set password_expiry = $2, sessions_revoked = $3"#,
+            self.user_id,
+            self.password_expiry,
+            self.sessions_revoked,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(())
+    }
+
+    fn new(user_id: String) -> Self {
+        Self {
+            user_id,
+            password_expiry: true,
+            sessions_revoked: true,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::app_state::{AppState, DbTxn};
+use crate::entity::roles::Role;
+use crate::entity::sessions::Session;
+use crate::entity::users::User;
+use crate::events::event::Event;
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::{RAUTHY_ADMIN_ROLE, ROLE_GRANT_MAX_HOURS};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::new_store_id;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// A role, which has been granted to a user for a limited time only, for instance for
+/// just-in-time admin access.
+///
+/// The role is added to the user like any other role and will therefore show up in all new
+/// tokens right away. The `role_grants_cleanup` scheduler removes it again after `exp`.
+///
+/// When an admin removes the role from the user in the meantime, the grant ends as well. If the
+/// role is assigned again afterward, it is a permanent one and will not be removed anymore.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserRoleGrant {
+    pub id: String,
+    pub user_id: String,
+    pub role: String,
+    pub reason: String,
+    pub approved_by: String,
+    pub created: i64,
+    pub exp: i64,
+}
+
+impl UserRoleGrant {
+    /// Grants the role to the user until `now + duration_mins`. An already running grant for
+    /// the same role will be replaced, which can be used to extend or shorten it.
+    ///
+    /// The `elevated_role` audit event is committed together with the grant.
+    pub async fn create(
+        data: &web::Data<AppState>,
+        user_id: String,
+        role: String,
+        reason: String,
+        approved_by: String,
+        duration_mins: u32,
+        ip: Option<String>,
+    ) -> Result<Self, ErrorResponse> {
+        Self::validate_duration(duration_mins)?;
+
+        if !Role::find_all(data).await?.iter().any(|r| r.name == role) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                format!("The role '{}' does not exist", role),
+            ));
+        }
+
+        let mut txn = data.db.begin().await?;
+        let mut user = User::find_for_update(&user_id, &mut txn)
+            .await?
+            .ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::NotFound,
+                    "The user does not exist".to_string(),
+                )
+            })?;
+        let existing = Self::find_for_user(data, &user.id)
+            .await?
+            .into_iter()
+            .find(|g| g.role == role);
+        if existing.is_none() && user.get_roles().contains(&role) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("The user is permanently assigned to the role '{}'", role),
+            ));
+        }
+
+        let now = Utc::now().timestamp();
+        let slf = Self {
+            id: new_store_id(),
+            user_id: user.id.clone(),
+            role,
+            reason,
+            approved_by,
+            created: now,
+            exp: now + duration_mins as i64 * 60,
+        };
+
+        if let Some(existing) = existing {
+            sqlx::query!("DELETE FROM user_role_grants WHERE id = $1", existing.id)
+                .execute(&mut *txn)
+                .await?;
+        } else {
+            user.push_role(&slf.role);
+            user.save(data, None, Some(&mut txn)).await?;
+        }
+        sqlx::query!(
+            r#"INSERT INTO user_role_grants
+            (id, user_id, role, reason, approved_by, created, exp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            slf.id,
+            slf.user_id,
+            slf.role,
+            slf.reason,
+            slf.approved_by,
+            slf.created,
+            slf.exp,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        let text = format!(
+            "'{}' granted to {} until {} by {}: {}",
+            slf.role, user.email, slf.exp, slf.approved_by, slf.reason
+        );
+        let event = Event::elevated_role(text, ip).for_user(user.id);
+        event.outbox_insert(&mut txn).await?;
+        txn.commit().await?;
+        data.tx_events.send_async(event).await.unwrap();
+
+        Ok(slf)
+    }
+
+    pub async fn find(data: &web::Data<AppState>, id: &str) -> Result<Self, ErrorResponse> {
+        let res = sqlx::query_as!(Self, "SELECT * FROM user_role_grants WHERE id = $1", id)
+            .fetch_one(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_for_user(
+        data: &web::Data<AppState>,
+        user_id: &str,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = sqlx::query_as!(
+            Self,
+            "SELECT * FROM user_role_grants WHERE user_id = $1 ORDER BY exp",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_expired(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
+        let now = Utc::now().timestamp();
+        let res = sqlx::query_as!(Self, "SELECT * FROM user_role_grants WHERE exp <= $1", now)
+            .fetch_all(&data.db)
+            .await?;
+        Ok(res)
+    }
+
+    /// Removes the role from the user and deletes the grant.
+    ///
+    /// Sessions only carry the roles from the login, which matters for the rauthy admin role
+    /// only. In this case, all sessions of the user will be invalidated, so the admin access
+    /// cannot outlive the grant.
+    ///
+    /// The given audit `event` is committed together with the revocation. It is dropped, if
+    /// the grant was gone already.
+    pub async fn revoke(
+        &self,
+        data: &web::Data<AppState>,
+        event: Event,
+    ) -> Result<(), ErrorResponse> {
+        let mut txn = data.db.begin().await?;
+
+        // The grant is gone already, if the role has been removed from the user in the meantime.
+        // The role must be kept in this case, since it may have been assigned permanently since.
+        let res = sqlx::query!("DELETE FROM user_role_grants WHERE id = $1", self.id)
+            .execute(&mut *txn)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Ok(());
+        }
+
+        if let Some(mut user) = User::find_for_update(&self.user_id, &mut txn).await? {
+            user.roles = user
+                .get_roles()
+                .into_iter()
+                .filter(|r| r != &self.role)
+                .collect::<Vec<_>>()
+                .join(",");
+            user.save(data, None, Some(&mut txn)).await?;
+        }
+        event.outbox_insert(&mut txn).await?;
+        txn.commit().await?;
+        data.tx_events.send_async(event).await.unwrap();
+
+        if self.role == *RAUTHY_ADMIN_ROLE {
+            Session::invalidate_for_user(data, &self.user_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends all grants of the user for the given roles without touching the user itself, because
+    /// the roles have been removed from the user directly.
+    pub async fn delete_for_roles(
+        user_id: &str,
+        roles: &[String],
+        txn: &mut DbTxn<'_>,
+    ) -> Result<(), ErrorResponse> {
+        for role in roles {
+            sqlx::query!(
+                "DELETE FROM user_role_grants WHERE user_id = $1 AND role = $2",
+                user_id,
+                role
+            )
+            .execute(&mut **txn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn validate_duration(duration_mins: u32) -> Result<(), ErrorResponse> {
+        if duration_mins == 0 || duration_mins > *ROLE_GRANT_MAX_HOURS * 60 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The duration must be between 1 minute and {} hours",
+                    *ROLE_GRANT_MAX_HOURS
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_duration() {
+        assert!(UserRoleGrant::validate_duration(0).is_err());
+        assert!(UserRoleGrant::validate_duration(1).is_ok());
+        assert!(UserRoleGrant::validate_duration(*ROLE_GRANT_MAX_HOURS * 60).is_ok());
+        assert!(UserRoleGrant::validate_duration(*ROLE_GRANT_MAX_HOURS * 60 + 1).is_err());
+    }
+}
@@ -1,41 +1,60 @@
 use crate::app_state::{AppState, Argon2Params, DbTxn};
-use crate::email::{send_email_change_info_new, send_email_confirm_change, send_pwd_reset};
+use crate::cache_invalidation::Invalidation;
+use crate::email::{
+    send_email_change_info_new, send_email_confirm_change, send_pwd_reset,
+    send_sessions_revoked_info,
+};
 use crate::entity::colors::ColorEntity;
 use crate::entity::continuation_token::ContinuationToken;
+use crate::entity::email_aliases::EmailAlias;
 use crate::entity::groups::Group;
+use crate::entity::legal_holds::LegalHold;
+use crate::entity::login_policies::GroupLoginPolicy;
 use crate::entity::magic_links::{MagicLink, MagicLinkUsage};
+use crate::entity::organizations::Organization;
 use crate::entity::password::PasswordPolicy;
 use crate::entity::password::RecentPasswordsEntity;
 use crate::entity::refresh_tokens::RefreshToken;
 use crate::entity::roles::Role;
 use crate::entity::sessions::Session;
+use crate::entity::user_merges::UserMerge;
+use crate::entity::user_notification_prefs::UserNotificationPrefs;
+use crate::entity::user_role_grants::UserRoleGrant;
 use crate::entity::users_values::UserValues;
 use crate::entity::webauthn::{PasskeyEntity, WebauthnServiceReq};
 use crate::events::event::Event;
 use crate::language::Language;
+use crate::pii;
 use crate::request::{
-    NewUserRegistrationRequest, NewUserRequest, SearchParamsIdx, UpdateUserRequest,
-    UpdateUserSelfRequest,
+    NewUserRegistrationRequest, NewUserRequest, ProvisioningAction, ProvisioningRequest,
+    SearchParamsIdx, UpdateUserRequest, UpdateUserSelfRequest,
 };
 use crate::response::UserResponseSimple;
 use crate::templates::UserEmailChangeConfirmHtml;
 use actix_web::{web, HttpRequest};
 use argon2::PasswordHash;
+use rauthy_common::cache_metrics::{cache_del, cache_get, cache_remove};
 use rauthy_common::constants::{
-    CACHE_NAME_12HR, CACHE_NAME_USERS, IDX_USERS, RAUTHY_ADMIN_ROLE, USER_COUNT_IDX,
-    WEBAUTHN_NO_PASSWORD_EXPIRY,
+    CACHE_NAME_12HR, CACHE_NAME_USERS, EMAIL_MFA_CODE_LIFETIME, EMAIL_MFA_ENABLE, EMAIL_NORMALIZE,
+    IDX_USERS, PII_ENCRYPTION_EMAIL, PUSH_MFA_ENABLE, PUSH_MFA_REQ_LIFETIME, RAUTHY_ADMIN_ROLE,
+    SESSION_REVOKE_NOTIFY_USER, SESSION_REVOKE_ON_CREDENTIAL_CHANGE, USERNAME_LOGIN_ENABLE,
+    USERNAME_PREFERRED_CLAIM, USERNAME_SELF_EDIT, USER_COUNT_IDX, WEBAUTHN_NO_PASSWORD_EXPIRY,
+    WEBAUTHN_PASSKEY_RESET_VIA_EMAIL, WEBAUTHN_REQ_EXP,
 };
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::login_latency;
+use rauthy_common::login_latency::LatencyStage;
 use rauthy_common::password_hasher::{ComparePasswords, HashPassword};
-use rauthy_common::utils::{get_client_ip, new_store_id, real_ip_from_req};
-use redhac::{
-    cache_del, cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel,
+use rauthy_common::utils::{
+    get_client_ip, is_email_in_domains, new_store_id, normalize_email, real_ip_from_req,
 };
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::{query_as, FromRow};
 use std::ops::Add;
+use std::time::Instant;
 use time::OffsetDateTime;
-use tracing::{error, trace, warn};
+use tracing::{debug, error, trace, warn};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AccountType {
@@ -50,6 +69,24 @@ pub enum AccountType {
     FederatedPasskey,
 }
 
+/// A change of the users credentials, which may revoke its other sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialChange {
+    Password,
+    PasskeyRemoved,
+    PasskeyReset,
+}
+
+impl CredentialChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Password => "Password change",
+            Self::PasskeyRemoved => "Passkey removal",
+            Self::PasskeyReset => "Passkey re-enrollment",
+        }
+    }
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
@@ -71,17 +108,18 @@ pub struct User {
     pub user_expires: Option<i64>,
     pub auth_provider_id: Option<String>,
     pub federation_uid: Option<String>,
+    pub username: Option<String>,
+    pub email_mfa: bool,
 }
 
 // CRUD
 impl User {
     pub async fn count(data: &web::Data<AppState>) -> Result<i64, ErrorResponse> {
-        if let Some(count) = cache_get!(
-            i64,
+        if let Some(count) = cache_get::<i64>(
             CACHE_NAME_12HR.to_string(),
             USER_COUNT_IDX.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -184,20 +222,29 @@ impl User {
         req_data: NewUserRegistrationRequest,
         lang: Language,
     ) -> Result<User, ErrorResponse> {
+        let email = req_data.email.to_lowercase();
+        let email = if *EMAIL_NORMALIZE {
+            // the original address might belong to a user from before the normalization
+            User::is_email_free(data, email.clone()).await?;
+            normalize_email(&email)
+        } else {
+            email
+        };
+
         let mut new_user = Self {
-            email: req_data.email.to_lowercase(),
+            email,
             given_name: req_data.given_name,
             family_name: req_data.family_name,
             ..Default::default()
         };
         new_user.language = lang;
-        let new_user = User::create(data, new_user, req_data.redirect_uri).await?;
-
-        Ok(new_user)
+        User::create(data, new_user, req_data.redirect_uri).await
     }
 
     // Deletes a user
     pub async fn delete(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
+        LegalHold::check_deletable(data, &self.id).await?;
+
         // Clean up all possibly existing sessions from the cache
         Session::delete_by_user(data, &self.id).await?;
 
@@ -206,6 +253,21 @@ impl User {
             .execute(&data.db)
             .await?;
 
+        // Keep the audit trail, but replace the E-Mail with a stable pseudonym
+        let anonymized = Event::anonymize_user(&data.db, &self.id, &self.email).await?;
+        debug!(
+            "Anonymized {} events for deleted user {}",
+            anonymized, self.id
+        );
+
+        self.cleanup_after_delete(data).await
+    }
+
+    /// Removes a deleted user from the caches and decreases the user count.
+    pub(crate) async fn cleanup_after_delete(
+        &self,
+        data: &web::Data<AppState>,
+    ) -> Result<(), ErrorResponse> {
         let idx = format!("{}_{}", IDX_USERS, &self.id);
         cache_remove(
             CACHE_NAME_USERS.to_string(),
@@ -232,12 +294,11 @@ impl User {
     // Checks if a user exists in the database without fetching data
     pub async fn exists(data: &web::Data<AppState>, id: String) -> Result<(), ErrorResponse> {
         let idx = format!("{}_{}", IDX_USERS, id);
-        let user_opt = cache_get!(
-            User,
+        let user_opt = cache_get::<User>(
             CACHE_NAME_USERS.to_string(),
             idx.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if user_opt.is_some() {
@@ -253,12 +314,11 @@ impl User {
 
     pub async fn find(data: &web::Data<AppState>, id: String) -> Result<Self, ErrorResponse> {
         let idx = format!("{}_{}", IDX_USERS, id);
-        let user_opt = cache_get!(
-            User,
+        let user_opt = cache_get::<User>(
             CACHE_NAME_USERS.to_string(),
             idx.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(user_opt) = user_opt {
@@ -267,7 +327,8 @@ impl User {
 
         let user = sqlx::query_as!(Self, "select * from users where id = $1", id)
             .fetch_one(&data.db)
-            .await?;
+            .await?
+            .email_dec()?;
 
         cache_insert(
             CACHE_NAME_USERS.to_string(),
@@ -280,6 +341,38 @@ impl User {
         Ok(user)
     }
 
+    /// Reads the user from the database inside the given transaction, bypassing the cache.
+    /// With Postgres, the row stays locked until the transaction ends.
+    pub async fn find_for_update(
+        id: &str,
+        txn: &mut DbTxn<'_>,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        #[cfg(not(feature = "postgres"))]
+        let q = sqlx::query_as!(Self, "select * from users where id = $1", id);
+        #[cfg(feature = "postgres")]
+        let q = sqlx::query_as!(Self, "select * from users where id = $1 for update", id);
+
+        q.fetch_optional(&mut **txn)
+            .await?
+            .map(Self::email_dec)
+            .transpose()
+    }
+
+    /// Finds a user by the `sub` of a token. If the user has been merged into another one in the
+    /// meantime, the tombstone is followed to the user, which has been kept.
+    pub async fn find_by_sub(
+        data: &web::Data<AppState>,
+        id: String,
+    ) -> Result<Self, ErrorResponse> {
+        match Self::find(data, id.clone()).await {
+            Ok(user) => Ok(user),
+            Err(err) => match UserMerge::find_new_id(data, &id).await? {
+                Some(new_id) => Self::find(data, new_id).await,
+                None => Err(err),
+            },
+        }
+    }
+
     pub async fn find_by_email(
         data: &web::Data<AppState>,
         email: String,
@@ -287,21 +380,24 @@ impl User {
         let email = email.to_lowercase();
 
         let idx = format!("{}_{}", IDX_USERS, email);
-        let user_opt = cache_get!(
-            User,
+        let user_opt = cache_get::<User>(
             CACHE_NAME_USERS.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(user_opt) = user_opt {
             return Ok(user_opt);
         }
 
-        let user = sqlx::query_as!(Self, "select * from users where email = $1", email)
+        let email_enc = pii::encrypt_email(&email)?;
+        let start = Instant::now();
+        let user = sqlx::query_as!(Self, "select * from users where email = $1", email_enc)
             .fetch_one(&data.db)
-            .await?;
+            .await;
+        login_latency::record(LatencyStage::Db, start.elapsed());
+        let user = user?.email_dec()?;
 
         cache_insert(
             CACHE_NAME_USERS.to_string(),
@@ -314,6 +410,52 @@ impl User {
         Ok(user)
     }
 
+    pub async fn find_by_username(
+        data: &web::Data<AppState>,
+        username: &str,
+    ) -> Result<Self, ErrorResponse> {
+        let username = username.to_lowercase();
+        let start = Instant::now();
+        let user = sqlx::query_as!(Self, "select * from users where username = $1", username)
+            .fetch_one(&data.db)
+            .await;
+        login_latency::record(LatencyStage::Db, start.elapsed());
+        let user = user?.email_dec()?;
+        Ok(user)
+    }
+
+    /// Finds a user by the identifier given during the login. This is always the E-Mail, or the
+    /// username, if `USERNAME_LOGIN_ENABLE` is set. A username can never contain an `@`.
+    ///
+    /// An E-Mail matches the users E-Mail, its normalized form if `EMAIL_NORMALIZE` is set,
+    /// or one of the users aliases.
+    pub async fn find_by_login(
+        data: &web::Data<AppState>,
+        login: String,
+    ) -> Result<Self, ErrorResponse> {
+        if *USERNAME_LOGIN_ENABLE && !login.contains('@') {
+            return Self::find_by_username(data, &login).await;
+        }
+
+        let email = login.to_lowercase();
+        if *EMAIL_NORMALIZE {
+            let normalized = normalize_email(&email);
+            if normalized != email {
+                if let Ok(user) = Self::find_by_email(data, normalized).await {
+                    return Ok(user);
+                }
+            }
+        }
+
+        match Self::find_by_email(data, email.clone()).await {
+            Ok(user) => Ok(user),
+            Err(err) => match EmailAlias::find(data, &email).await? {
+                Some(alias) => Self::find(data, alias.user_id).await,
+                None => Err(err),
+            },
+        }
+    }
+
     pub async fn find_by_federation(
         data: &web::Data<AppState>,
         auth_provider_id: &str,
@@ -326,7 +468,8 @@ impl User {
             federation_uid
         )
         .fetch_one(&data.db)
-        .await?;
+        .await?
+        .email_dec()?;
         Ok(user)
     }
 
@@ -334,7 +477,7 @@ impl User {
         let res = sqlx::query_as!(Self, "SELECT * FROM users ORDER BY created_at ASC")
             .fetch_all(&data.db)
             .await?;
-        Ok(res)
+        res.into_iter().map(Self::email_dec).collect()
     }
 
     pub async fn find_all_simple(
@@ -346,7 +489,7 @@ impl User {
         )
         .fetch_all(&data.db)
         .await?;
-        Ok(res)
+        res.into_iter().map(UserResponseSimple::email_dec).collect()
     }
 
     pub async fn find_expired(data: &web::Data<AppState>) -> Result<Vec<Self>, ErrorResponse> {
@@ -356,7 +499,7 @@ impl User {
         let res = sqlx::query_as!(Self, "select * from users where user_expires < $1", now)
             .fetch_all(&data.db)
             .await?;
-        Ok(res)
+        res.into_iter().map(Self::email_dec).collect()
     }
 
     pub async fn find_paginated(
@@ -392,7 +535,7 @@ impl User {
                 for row in rows {
                     res.push(UserResponseSimple {
                         id: row.id,
-                        email: row.email,
+                        email: pii::decrypt_email(row.email)?,
                     });
                     latest_ts = row.created_at;
                 }
@@ -415,7 +558,7 @@ impl User {
                 for row in rows {
                     res.push(UserResponseSimple {
                         id: row.id,
-                        email: row.email,
+                        email: pii::decrypt_email(row.email)?,
                     });
                     latest_ts = row.created_at;
                 }
@@ -440,7 +583,7 @@ impl User {
             for row in rows {
                 res.push(UserResponseSimple {
                     id: row.id,
-                    email: row.email,
+                    email: pii::decrypt_email(row.email)?,
                 });
                 latest_ts = row.created_at;
             }
@@ -460,7 +603,7 @@ impl User {
             for row in rows {
                 res.push(UserResponseSimple {
                     id: row.id,
-                    email: row.email,
+                    email: pii::decrypt_email(row.email)?,
                 });
                 latest_ts = row.created_at;
             }
@@ -474,16 +617,21 @@ impl User {
     }
 
     async fn insert(data: &web::Data<AppState>, new_user: User) -> Result<Self, ErrorResponse> {
+        User::is_email_free(data, new_user.email.clone()).await?;
+
         let lang = new_user.language.as_str();
+        let email = pii::encrypt_email(&new_user.email)?;
         sqlx::query!(
             r#"INSERT INTO USERS
-            (id, email, given_name, family_name, roles, groups, enabled, email_verified, created_at,
-            last_login, language, user_expires, auth_provider_id, federation_uid)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)"#,
+            (id, email, given_name, family_name, password, roles, groups, enabled, email_verified,
+            created_at, last_login, language, user_expires, auth_provider_id, federation_uid,
+            username)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)"#,
             new_user.id,
-            new_user.email,
+            email,
             new_user.given_name,
             new_user.family_name,
+            new_user.password,
             new_user.roles,
             new_user.groups,
             new_user.enabled,
@@ -494,6 +642,7 @@ impl User {
             new_user.user_expires,
             new_user.auth_provider_id,
             new_user.federation_uid,
+            new_user.username,
         )
         .execute(&data.db)
         .await?;
@@ -535,15 +684,17 @@ impl User {
         }
 
         let lang = self.language.as_str();
+        let email = pii::encrypt_email(&self.email)?;
         let q = sqlx::query(
             r#"update users set
             email = $1, given_name = $2, family_name = $3, password = $4, roles = $5, groups = $6,
             enabled = $7, email_verified = $8, password_expires = $9, last_login = $10,
             last_failed_login = $11, failed_login_attempts = $12, language = $13,
-            webauthn_user_id = $14, user_expires = $15, auth_provider_id = $16, federation_uid = $17
-            where id = $18"#,
+            webauthn_user_id = $14, user_expires = $15, auth_provider_id = $16, federation_uid = $17,
+            username = $18, email_mfa = $19
+            where id = $20"#,
         )
-        .bind(&self.email)
+        .bind(email)
         .bind(&self.given_name)
         .bind(&self.family_name)
         .bind(&self.password)
@@ -560,6 +711,8 @@ impl User {
         .bind(self.user_expires)
         .bind(&self.auth_provider_id)
         .bind(&self.federation_uid)
+        .bind(&self.username)
+        .bind(self.email_mfa)
         .bind(&self.id);
 
         if let Some(txn) = txn {
@@ -582,6 +735,7 @@ impl User {
                 &data.caches.ha_cache_config,
             )
             .await?;
+            Invalidation::UserEmail(email).publish(data).await;
         }
 
         let idx = format!("{}_{}", IDX_USERS, &self.id);
@@ -607,6 +761,36 @@ impl User {
         Ok(())
     }
 
+    /// Encrypts or decrypts all stored E-Mails to match `PII_ENCRYPTION_EMAIL` and returns the
+    /// amount of modified users. The cached users are in cleartext and do not need to be touched.
+    pub async fn convert_emails(data: &web::Data<AppState>) -> Result<usize, ErrorResponse> {
+        let rows = if *PII_ENCRYPTION_EMAIL {
+            sqlx::query_as::<_, (String, String)>(
+                "select id, email from users where email not like $1",
+            )
+            .bind(format!("{}%", pii::email_prefix_active()))
+        } else {
+            sqlx::query_as::<_, (String, String)>(
+                "select id, email from users where email like 'denc:%'",
+            )
+        }
+        .fetch_all(&data.db)
+        .await?;
+
+        let mut modified = 0;
+        for (id, email) in rows {
+            if let Some(converted) = pii::convert_email(&email)? {
+                sqlx::query("update users set email = $1 where id = $2")
+                    .bind(converted)
+                    .bind(id)
+                    .execute(&data.db)
+                    .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+
     /// Caution: Uses regex / LIKE on the database -> very costly query
     pub async fn search(
         data: &web::Data<AppState>,
@@ -614,6 +798,8 @@ impl User {
         q: &str,
         limit: i64,
     ) -> Result<Vec<UserResponseSimple>, ErrorResponse> {
+        // encrypted E-Mails can only be found with an exact match
+        let email_enc = pii::encrypt_email(&q.to_lowercase())?;
         let q = format!("%{}%", q);
 
         let res = match idx {
@@ -630,7 +816,8 @@ impl User {
             SearchParamsIdx::Email => {
                 query_as!(
                 UserResponseSimple,
-                "SELECT id, email FROM users WHERE email LIKE $1 ORDER BY created_at ASC LIMIT $2",
+                "SELECT id, email FROM users WHERE email = $1 OR email LIKE $2 ORDER BY created_at ASC LIMIT $3",
+                email_enc,
                 q,
                 limit
             )
@@ -645,7 +832,7 @@ impl User {
             }
         };
 
-        Ok(res)
+        res.into_iter().map(UserResponseSimple::email_dec).collect()
     }
 
     pub async fn update(
@@ -665,9 +852,15 @@ impl User {
             None
         };
 
+        let username = upd_user.username.map(|u| u.to_lowercase());
+        if username.is_some() && username != user.username {
+            User::is_username_free(data, username.as_deref().unwrap_or_default()).await?;
+        }
+
         user.email = upd_user.email;
         user.given_name = upd_user.given_name;
         user.family_name = upd_user.family_name;
+        user.username = username;
 
         if let Some(lang) = upd_user.language {
             user.language = lang;
@@ -678,23 +871,46 @@ impl User {
         }
 
         let is_admin_before_update = user.is_admin();
+        let roles_before = user.get_roles();
         user.roles = Role::sanitize(data, upd_user.roles).await?;
+        let roles_after = user.get_roles();
+        let roles_removed = roles_before
+            .into_iter()
+            .filter(|r| !roles_after.contains(r))
+            .collect::<Vec<String>>();
         user.groups = Group::sanitize(data, upd_user.groups).await?;
 
         user.enabled = upd_user.enabled;
         user.email_verified = upd_user.email_verified;
         user.user_expires = upd_user.user_expires;
 
-        user.save(data, old_email.clone(), None).await?;
-
+        // the events are committed together with the user, so they cannot get lost
+        let mut events = Vec::with_capacity(2);
         if upd_user.password.is_some() {
-            data.tx_events
-                .send_async(Event::user_password_reset(
+            events.push(
+                Event::user_password_reset(
                     format!("Reset done by admin for user {}", user.email),
                     None,
-                ))
-                .await
-                .unwrap();
+                )
+                .for_user(user.id.clone()),
+            );
+        }
+        if let Some(old_email) = old_email.as_ref() {
+            let event_text = format!("Change by admin: {} -> {}", old_email, user.email);
+            events.push(Event::user_email_change(event_text, None).for_user(user.id.clone()));
+        }
+
+        let mut txn = data.db.begin().await?;
+        user.save(data, old_email.clone(), Some(&mut txn)).await?;
+        // a removed role ends its grant, so assigning it again later will be permanent
+        UserRoleGrant::delete_for_roles(&user.id, &roles_removed, &mut txn).await?;
+        for event in &events {
+            event.outbox_insert(&mut txn).await?;
+        }
+        txn.commit().await?;
+
+        for event in events {
+            data.tx_events.send_async(event).await.unwrap();
         }
 
         if let Some(old_email) = old_email.as_ref() {
@@ -705,12 +921,6 @@ impl User {
             // send out confirmation E-Mails to both addresses
             send_email_confirm_change(data, &user, &user.email, &user.email, true).await;
             send_email_confirm_change(data, &user, old_email, &user.email, true).await;
-
-            let event_text = format!("Change by admin: {} -> {}", old_email, user.email);
-            data.tx_events
-                .send_async(Event::user_email_change(event_text, None))
-                .await
-                .unwrap();
         }
 
         let is_new_admin = !is_admin_before_update && user.is_admin();
@@ -725,6 +935,71 @@ impl User {
         Ok((user, user_values, is_new_admin))
     }
 
+    /// Applies a user lifecycle notice from an external system. The returned flag is `true`,
+    /// if the user has become a rauthy admin with this notice.
+    pub async fn provision(
+        data: &web::Data<AppState>,
+        req: ProvisioningRequest,
+    ) -> Result<(Self, bool), ErrorResponse> {
+        match req.action {
+            ProvisioningAction::Create => {
+                let (Some(given_name), Some(family_name)) = (req.given_name, req.family_name)
+                else {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::BadRequest,
+                        "'given_name' and 'family_name' are mandatory for 'create'".to_string(),
+                    ));
+                };
+
+                let new_user = NewUserRequest {
+                    email: req.email,
+                    family_name,
+                    given_name,
+                    language: req.language.unwrap_or_default(),
+                    groups: req.groups,
+                    roles: req.roles.unwrap_or_default(),
+                    user_expires: req.user_expires,
+                    username: None,
+                };
+                let user = Self::create_from_new(data, new_user).await?;
+                let is_admin = user.is_admin();
+                Ok((user, is_admin))
+            }
+
+            ProvisioningAction::Update => {
+                let user = Self::find_by_email(data, req.email).await?;
+                let upd_user = UpdateUserRequest {
+                    email: req.new_email.unwrap_or_else(|| user.email.clone()),
+                    given_name: req.given_name.unwrap_or_else(|| user.given_name.clone()),
+                    family_name: req.family_name.unwrap_or_else(|| user.family_name.clone()),
+                    language: req.language,
+                    password: None,
+                    roles: req.roles.unwrap_or_else(|| user.get_roles()),
+                    groups: req.groups.or_else(|| Some(user.get_groups())),
+                    enabled: user.enabled,
+                    email_verified: user.email_verified,
+                    user_expires: req.user_expires.or(user.user_expires),
+                    user_values: None,
+                    username: user.username.clone(),
+                };
+
+                let (user, _, is_new_admin) =
+                    Self::update(data, user.id.clone(), upd_user, Some(user)).await?;
+                Ok((user, is_new_admin))
+            }
+
+            ProvisioningAction::Deactivate => {
+                let mut user = Self::find_by_email(data, req.email).await?;
+                if user.enabled {
+                    // saving a disabled user invalidates all its sessions and refresh tokens
+                    user.enabled = false;
+                    user.save(data, None, None).await?;
+                }
+                Ok((user, false))
+            }
+        }
+    }
+
     pub async fn update_language(&self, data: &web::Data<AppState>) -> Result<(), ErrorResponse> {
         let lang = self.language.as_str();
         sqlx::query(r#"update users set language = $1 where id = $2"#)
@@ -805,6 +1080,18 @@ impl User {
         } else {
             None
         };
+        let username = match upd_user.username.map(|u| u.to_lowercase()) {
+            Some(username) if Some(&username) != user.username.as_ref() => {
+                if !*USERNAME_SELF_EDIT {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::Forbidden,
+                        "You are not allowed to change your username".to_string(),
+                    ));
+                }
+                Some(username)
+            }
+            _ => user.username.clone(),
+        };
         let req = UpdateUserRequest {
             // never update the email directly here, only via email confirmation action from the user
             email: user.email.clone(),
@@ -818,6 +1105,7 @@ impl User {
             email_verified: user.email_verified,
             user_expires: user.user_expires,
             user_values: upd_user.user_values,
+            username,
         };
 
         // a user cannot become a new admin from a self-req
@@ -866,6 +1154,41 @@ impl User {
         user.save(data, None, None).await?;
         Ok(())
     }
+
+    /// Revokes all sessions except `keep_sid` and all refresh tokens of this user after a
+    /// credential change, if `SESSION_REVOKE_ON_CREDENTIAL_CHANGE` is enabled.
+    pub async fn revoke_sessions_on_credential_change(
+        &self,
+        data: &web::Data<AppState>,
+        change: CredentialChange,
+        keep_sid: Option<&str>,
+        ip: Option<String>,
+    ) -> Result<(), ErrorResponse> {
+        if !*SESSION_REVOKE_ON_CREDENTIAL_CHANGE {
+            return Ok(());
+        }
+
+        let sessions = Session::invalidate_for_user_except(data, &self.id, keep_sid).await?;
+        RefreshToken::invalidate_for_user(data, &self.id).await?;
+
+        let text = format!("{} for user {}", change.as_str(), self.email);
+        data.tx_events
+            .send_async(
+                Event::user_sessions_revoked(text, sessions as i64, ip).for_user(self.id.clone()),
+            )
+            .await
+            .unwrap();
+
+        if *SESSION_REVOKE_NOTIFY_USER
+            && UserNotificationPrefs::find(data, &self.id)
+                .await?
+                .sessions_revoked
+        {
+            send_sessions_revoked_info(data, self, change).await;
+        }
+
+        Ok(())
+    }
 }
 
 impl User {
@@ -1033,7 +1356,8 @@ impl User {
             return Err(ErrorResponse::new(
                 ErrorResponseType::Disabled,
                 String::from("User is not enabled"),
-            ));
+            )
+            .with_code(ErrorCode::UserDisabled));
         }
         Ok(())
     }
@@ -1045,12 +1369,36 @@ impl User {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Disabled,
                     String::from("User has expired"),
-                ));
+                )
+                .with_code(ErrorCode::UserExpired));
             }
         }
         Ok(())
     }
 
+    /// Checks the login policies of all groups of this user and emits a `LoginRestricted`
+    /// event, if the login is not allowed at this time.
+    pub async fn check_login_policy(
+        &self,
+        data: &web::Data<AppState>,
+        ip: Option<String>,
+    ) -> Result<(), ErrorResponse> {
+        if let Err(err) = GroupLoginPolicy::check_user(data, self).await {
+            if err.error == ErrorResponseType::Forbidden {
+                trace!("Login restricted by a group login policy");
+                data.tx_events
+                    .send_async(
+                        Event::login_restricted(format!("{} - {}", self.email, err.message), ip)
+                            .for_user(self.id.clone()),
+                    )
+                    .await
+                    .unwrap();
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
     pub async fn confirm_email_address(
         data: &web::Data<AppState>,
         req: HttpRequest,
@@ -1062,7 +1410,10 @@ impl User {
 
         let usage = MagicLinkUsage::try_from(&ml.usage)?;
         let new_email = match usage {
-            MagicLinkUsage::NewUser(_) | MagicLinkUsage::PasswordReset(_) => {
+            MagicLinkUsage::NewUser(_)
+            | MagicLinkUsage::PasswordReset(_)
+            | MagicLinkUsage::PasskeyReset(_)
+            | MagicLinkUsage::PasswordOtp => {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::BadRequest,
                     "The Magic Link is not meant to be used to confirm an E-Mail address"
@@ -1083,6 +1434,8 @@ impl User {
         let old_email = user.email;
         user.email = new_email;
         user.email_verified = true;
+        user.sync_email_domain_memberships(data, Some(&old_email))
+            .await?;
         user.save(data, Some(old_email.clone()), None).await?;
         ml.invalidate(data).await?;
 
@@ -1096,13 +1449,42 @@ impl User {
         let event_text = format!("{} -> {}", old_email, user.email);
         let ip = real_ip_from_req(&req);
         data.tx_events
-            .send_async(Event::user_email_change(event_text, ip))
+            .send_async(Event::user_email_change(event_text, ip).for_user(user.id.clone()))
             .await
             .unwrap();
 
         Ok(html)
     }
 
+    /// Joins all organizations and groups, which claim the domain of the verified E-Mail, and
+    /// leaves the ones, which only claim the domain of the `old_email`. Group changes are only
+    /// applied to `self` and need to be saved afterward.
+    pub async fn sync_email_domain_memberships(
+        &mut self,
+        data: &web::Data<AppState>,
+        old_email: Option<&str>,
+    ) -> Result<(), ErrorResponse> {
+        Organization::sync_by_email_domain(data, &self.id, &self.email, old_email).await?;
+
+        for group in Group::find_all(data).await? {
+            let domains = group.get_domains();
+            if domains.is_empty() {
+                continue;
+            }
+
+            if is_email_in_domains(&self.email, &domains) {
+                let mut groups = self.get_groups();
+                if !groups.contains(&group.name) {
+                    groups.push(group.name);
+                    self.groups = Some(groups.join(","));
+                }
+            } else if old_email.is_some_and(|e| is_email_in_domains(e, &domains)) {
+                self.delete_group(&group.name);
+            }
+        }
+        Ok(())
+    }
+
     pub fn delete_group(&mut self, group: &str) {
         if self.groups.is_none() {
             return;
@@ -1166,6 +1548,11 @@ impl User {
         let roles = Role::sanitize(data, new_user.roles).await?;
         let groups = Group::sanitize(data, new_user.groups).await?;
 
+        let username = new_user.username.map(|u| u.to_lowercase());
+        if let Some(username) = &username {
+            User::is_username_free(data, username).await?;
+        }
+
         let user = Self {
             email: new_user.email.to_lowercase(),
             email_verified: false,
@@ -1175,6 +1562,7 @@ impl User {
             roles,
             groups,
             user_expires: new_user.user_expires,
+            username,
             ..Default::default()
         };
 
@@ -1208,6 +1596,36 @@ impl User {
         self.webauthn_user_id.is_some()
     }
 
+    /// E-Mail one-time codes are only a fallback for users without any passkey.
+    #[inline(always)]
+    pub fn has_email_mfa_enabled(&self) -> bool {
+        *EMAIL_MFA_ENABLE && self.email_mfa && !self.has_webauthn_enabled()
+    }
+
+    /// Returns `true` if the user has any second factor.
+    #[inline(always)]
+    pub fn has_mfa_enabled(&self) -> bool {
+        self.has_webauthn_enabled() || self.has_email_mfa_enabled()
+    }
+
+    /// The additional lifetime in seconds for an authorization code, while the 2nd factor is
+    /// awaited. A push approval request may be answered with a fallback to the other factor.
+    pub fn mfa_code_lifetime(&self) -> i32 {
+        let lifetime = if self.has_webauthn_enabled() {
+            *WEBAUTHN_REQ_EXP as i32
+        } else if self.has_email_mfa_enabled() {
+            *EMAIL_MFA_CODE_LIFETIME as i32 * 60
+        } else {
+            return 0;
+        };
+
+        if *PUSH_MFA_ENABLE {
+            lifetime + *PUSH_MFA_REQ_LIFETIME as i32
+        } else {
+            lifetime
+        }
+    }
+
     pub fn is_argon2_uptodate(&self, params: &Argon2Params) -> Result<bool, ErrorResponse> {
         if self.password.is_none() {
             error!(
@@ -1237,11 +1655,46 @@ impl User {
         self.get_roles().contains(&RAUTHY_ADMIN_ROLE)
     }
 
+    /// Returns the value for the `preferred_username` claim. This is the username, if one is set
+    /// and `USERNAME_PREFERRED_CLAIM` is enabled, and the E-Mail otherwise.
+    pub fn preferred_username(&self) -> &str {
+        match &self.username {
+            Some(username) if *USERNAME_PREFERRED_CLAIM => username,
+            _ => &self.email,
+        }
+    }
+
+    /// Decrypts the E-Mail after the user has been read from the database.
+    fn email_dec(mut self) -> Result<Self, ErrorResponse> {
+        self.email = pii::decrypt_email(self.email)?;
+        Ok(self)
+    }
+
     async fn is_email_free(data: &web::Data<AppState>, email: String) -> Result<(), ErrorResponse> {
+        let err = ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "E-Mail is already in use".to_string(),
+        );
+        if EmailAlias::find(data, &email.to_lowercase())
+            .await?
+            .is_some()
+        {
+            return Err(err);
+        }
         match User::find_by_email(data, email).await {
+            Ok(_) => Err(err),
+            Err(_) => Ok(()),
+        }
+    }
+
+    async fn is_username_free(
+        data: &web::Data<AppState>,
+        username: &str,
+    ) -> Result<(), ErrorResponse> {
+        match User::find_by_username(data, username).await {
             Ok(_) => Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
-                "E-Mail is already in use".to_string(),
+                "Username is already in use".to_string(),
             )),
             Err(_) => Ok(()),
         }
@@ -1249,7 +1702,7 @@ impl User {
 
     /// Returns `true` if the passwords match and `false` if they don't.
     /// It only returns an Err(ErrorResponse) in case of a hash parsing issue or corrupted data.
-    async fn match_passwords(&self, plain: String) -> Result<bool, ErrorResponse> {
+    pub async fn match_passwords(&self, plain: String) -> Result<bool, ErrorResponse> {
         if self.password.is_none() {
             return Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
@@ -1283,9 +1736,8 @@ impl User {
         req: HttpRequest,
         redirect_uri: Option<String>,
     ) -> Result<(), ErrorResponse> {
-        // TODO implement something with a Backup Code for passkey only accounts?
-        // deny for passkey only accounts
-        if self.account_type() == AccountType::Passkey {
+        // passkey only accounts can only re-enroll a passkey on their own, if it is allowed
+        if self.account_type() == AccountType::Passkey && !*WEBAUTHN_PASSKEY_RESET_VIA_EMAIL {
             return Ok(());
         }
 
@@ -1301,11 +1753,7 @@ impl User {
             }
         }
 
-        let usage = if self.password.is_none() && !self.has_webauthn_enabled() {
-            MagicLinkUsage::NewUser(redirect_uri)
-        } else {
-            MagicLinkUsage::PasswordReset(redirect_uri)
-        };
+        let usage = MagicLinkUsage::for_reset(self, redirect_uri);
         let new_ml =
             MagicLink::create(data, self.id.clone(), data.ml_lt_pwd_reset as i64, usage).await?;
         send_pwd_reset(data, &new_ml, self).await;
@@ -1325,6 +1773,19 @@ impl User {
             ));
         }
 
+        // A one-time password from an admin can only be redeemed once with a login in the UI,
+        // which forces the user to set a new password right away.
+        if MagicLink::find_password_otp(data, &self.id)
+            .await?
+            .is_some()
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                String::from("Invalid user credentials"),
+            )
+            .with_code(ErrorCode::InvalidCredentials));
+        }
+
         if let Some(exp) = self.password_expires {
             if exp < OffsetDateTime::now_utc().unix_timestamp() {
                 // TODO introduce some "is allowed to refresh" variable
@@ -1335,26 +1796,18 @@ impl User {
                     ));
                 }
 
-                // if the given password does match, send out a reset link to set a new one
+                // only tell that the password has expired, if the given one does match
                 return if self.match_passwords(plain_password.clone()).await? {
-                    let magic_link = MagicLink::create(
-                        data,
-                        self.id.clone(),
-                        data.ml_lt_pwd_reset as i64,
-                        MagicLinkUsage::PasswordReset(None),
-                    )
-                    .await?;
-                    send_pwd_reset(data, &magic_link, self).await;
-
                     Err(ErrorResponse::new(
                         ErrorResponseType::PasswordRefresh,
-                        String::from("The password has expired. A reset E-Mail has been sent out."),
+                        String::from("The password has expired"),
                     ))
                 } else {
                     Err(ErrorResponse::new(
                         ErrorResponseType::Unauthorized,
                         String::from("Invalid user credentials"),
-                    ))
+                    )
+                    .with_code(ErrorCode::InvalidCredentials))
                 };
             }
         }
@@ -1365,7 +1818,35 @@ impl User {
             Err(ErrorResponse::new(
                 ErrorResponseType::Unauthorized,
                 String::from("Invalid user credentials"),
-            ))
+            )
+            .with_code(ErrorCode::InvalidCredentials))
+        }
+    }
+
+    /// Validates the password for a login. If an expired password does match, a reset link will
+    /// be sent out to set a new one.
+    pub async fn validate_password_login(
+        &self,
+        data: &web::Data<AppState>,
+        plain_password: String,
+    ) -> Result<(), ErrorResponse> {
+        match self.validate_password(data, plain_password).await {
+            Err(err) if err.error == ErrorResponseType::PasswordRefresh => {
+                let magic_link = MagicLink::create(
+                    data,
+                    self.id.clone(),
+                    data.ml_lt_pwd_reset as i64,
+                    MagicLinkUsage::PasswordReset(None),
+                )
+                .await?;
+                send_pwd_reset(data, &magic_link, self).await;
+
+                Err(ErrorResponse::new(
+                    ErrorResponseType::PasswordRefresh,
+                    String::from("The password has expired. A reset E-Mail has been sent out."),
+                ))
+            }
+            res => res,
         }
     }
 }
@@ -1392,6 +1873,8 @@ impl Default for User {
             user_expires: None,
             auth_provider_id: None,
             federation_uid: None,
+            username: None,
+            email_mfa: false,
         }
     }
 }
@@ -1429,6 +1912,8 @@ mod tests {
             ),
             auth_provider_id: None,
             federation_uid: None,
+            username: None,
+            email_mfa: false,
         };
         let session = Session::try_new(&user, 1, None);
         assert!(session.is_err());
@@ -1487,6 +1972,8 @@ mod tests {
             user_expires: None,
             auth_provider_id: None,
             federation_uid: None,
+            username: None,
+            email_mfa: false,
         };
 
         // enabled
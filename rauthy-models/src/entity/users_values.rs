@@ -1,10 +1,12 @@
 use crate::app_state::AppState;
+use crate::pii;
 use crate::request::UserValuesRequest;
 use actix_web::web;
 use jwt_simple::prelude::{Deserialize, Serialize};
-use rauthy_common::constants::{CACHE_NAME_USERS, IDX_USERS_VALUES};
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{CACHE_NAME_USERS, IDX_USERS_VALUES, PII_ENCRYPTION};
 use rauthy_common::error_response::ErrorResponse;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use sqlx::FromRow;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
@@ -24,22 +26,24 @@ impl UserValues {
         user_id: &str,
     ) -> Result<Option<Self>, ErrorResponse> {
         let idx = format!("{}_{}", IDX_USERS_VALUES, user_id);
-        if let Ok(Some(values)) = cache_get!(
-            Option<Self>,
+        if let Ok(Some(values)) = cache_get::<Option<Self>>(
             CACHE_NAME_USERS.to_string(),
             idx.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await
         {
             return Ok(values);
         }
 
-        let slf = sqlx::query_as::<_, Self>("SELECT * FROM users_values WHERE id = $1")
+        let mut slf = sqlx::query_as::<_, Self>("SELECT * FROM users_values WHERE id = $1")
             .bind(user_id)
             .fetch_optional(&data.db)
             .await?;
+        if let Some(values) = slf.as_mut() {
+            values.phone = pii::decrypt_str(values.phone.take())?;
+        }
 
         cache_insert(
             CACHE_NAME_USERS.to_string(),
@@ -58,6 +62,8 @@ impl UserValues {
         user_id: String,
         values: UserValuesRequest,
     ) -> Result<Option<Self>, ErrorResponse> {
+        let phone = pii::encrypt_str(values.phone.clone())?;
+
         #[cfg(not(feature = "postgres"))]
         let q = sqlx::query!(
             r#"INSERT OR REPLACE INTO
@@ -65,7 +71,7 @@ impl UserValues {
                 VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
             user_id,
             values.birthdate,
-            values.phone,
+            phone,
             values.street,
             values.zip,
             values.city,
@@ -80,7 +86,7 @@ impl UserValues {
                 SET birthdate = $2, phone = $3, street = $4, zip = $5, city = $6, country = $7"#,
             user_id,
             values.birthdate,
-            values.phone,
+            phone,
             values.street,
             values.zip,
             values.city,
@@ -110,4 +116,62 @@ impl UserValues {
 
         Ok(slf)
     }
+
+    /// Encrypts all phone numbers, which are still stored in cleartext, if `PII_ENCRYPTION` is
+    /// enabled. Returns the amount of modified rows.
+    pub async fn encrypt_existing(data: &web::Data<AppState>) -> Result<usize, ErrorResponse> {
+        if !*PII_ENCRYPTION {
+            return Ok(0);
+        }
+
+        let rows = sqlx::query!(
+            "SELECT id, phone FROM users_values WHERE phone IS NOT NULL AND phone NOT LIKE 'enc:%'"
+        )
+        .fetch_all(&data.db)
+        .await?;
+
+        let mut modified = 0;
+        for row in rows {
+            if let Some(enc) = pii::encrypt_str(row.phone)? {
+                sqlx::query!(
+                    "UPDATE users_values SET phone = $1 WHERE id = $2",
+                    enc,
+                    row.id,
+                )
+                .execute(&data.db)
+                .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+
+    /// Re-encrypts all encrypted phone numbers with the given key and returns the amount of
+    /// modified rows. The cached values are in cleartext and do not need to be touched.
+    pub async fn migrate_pii_key(
+        data: &web::Data<AppState>,
+        key_id: &str,
+    ) -> Result<usize, ErrorResponse> {
+        let rows = sqlx::query!("SELECT id, phone FROM users_values WHERE phone LIKE 'enc:%'")
+            .fetch_all(&data.db)
+            .await?;
+
+        let mut modified = 0;
+        for row in rows {
+            let Some(phone) = row.phone else {
+                continue;
+            };
+            if let Some(enc) = pii::reencrypt_str(&phone, key_id)? {
+                sqlx::query!(
+                    "UPDATE users_values SET phone = $1 WHERE id = $2",
+                    enc,
+                    row.id,
+                )
+                .execute(&data.db)
+                .await?;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
 }
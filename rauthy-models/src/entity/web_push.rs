@@ -0,0 +1,359 @@
+use crate::app_state::AppState;
+use actix_web::web;
+use cryptr::EncValue;
+use rauthy_common::constants::{PUSH_MFA_VAPID_SUBJECT, RAUTHY_VERSION};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{
+    base64_url_no_pad_decode, base64_url_no_pad_encode, resolve_public_addr,
+};
+use reqwest::{tls, StatusCode};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use ring::{aead, hkdf};
+use serde_json::json;
+use sqlx::Row;
+use std::sync::OnceLock;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::debug;
+
+static VAPID_KEY: OnceLock<VapidKey> = OnceLock::new();
+
+const VAPID_CONFIG_ID: &str = "push_vapid_key";
+// Push messages are small enough to always fit into a single record.
+const RECORD_SIZE: u32 = 4096;
+
+/// The VAPID key pair (RFC 8292), which identifies this deployment against the push services.
+///
+/// It is generated on first use and stored encrypted in the database, so all instances share
+/// it. It must never change, because each subscription is bound to its public key.
+pub struct VapidKey {
+    key_pair: EcdsaKeyPair,
+}
+
+impl VapidKey {
+    /// Loads the key from the database, or generates a new one if none exists yet.
+    pub async fn get(data: &web::Data<AppState>) -> Result<&'static Self, ErrorResponse> {
+        if let Some(key) = VAPID_KEY.get() {
+            return Ok(key);
+        }
+
+        let pkcs8 = match Self::load(data).await? {
+            Some(pkcs8) => pkcs8,
+            None => {
+                let rng = SystemRandom::new();
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(err_push)?;
+                let enc = EncValue::encrypt(pkcs8.as_ref())?.into_bytes().to_vec();
+
+                // Another instance may have been faster. Always read the key back to make sure
+                // all of them use the same one.
+                sqlx::query(
+                    "insert into config (id, data) values ($1, $2) on conflict(id) do nothing",
+                )
+                .bind(VAPID_CONFIG_ID)
+                .bind(enc)
+                .execute(&data.db)
+                .await?;
+
+                Self::load(data).await?.ok_or_else(|| {
+                    ErrorResponse::new(
+                        ErrorResponseType::Internal,
+                        "VAPID key missing after generation".to_string(),
+                    )
+                })?
+            }
+        };
+
+        let key_pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &pkcs8,
+            &SystemRandom::new(),
+        )
+        .map_err(err_push)?;
+        Ok(VAPID_KEY.get_or_init(|| Self { key_pair }))
+    }
+
+    async fn load(data: &web::Data<AppState>) -> Result<Option<Vec<u8>>, ErrorResponse> {
+        let res = sqlx::query("select data from config where id = $1")
+            .bind(VAPID_CONFIG_ID)
+            .fetch_optional(&data.db)
+            .await?;
+
+        match res {
+            Some(row) => {
+                let enc: Vec<u8> = row.get("data");
+                Ok(Some(EncValue::try_from(enc)?.decrypt()?.to_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Re-encrypts the stored key with the given `ENC_KEYS` id. Returns the amount of modified
+    /// values.
+    pub async fn migrate_enc_key(
+        data: &web::Data<AppState>,
+        key_id: &str,
+    ) -> Result<usize, ErrorResponse> {
+        let Some(pkcs8) = Self::load(data).await? else {
+            return Ok(0);
+        };
+        let enc = EncValue::encrypt_with_key_id(&pkcs8, key_id.to_string())?
+            .into_bytes()
+            .to_vec();
+
+        sqlx::query("update config set data = $1 where id = $2")
+            .bind(enc)
+            .bind(VAPID_CONFIG_ID)
+            .execute(&data.db)
+            .await?;
+        Ok(1)
+    }
+
+    /// The uncompressed public key as base64 url safe without padding, which is the
+    /// `applicationServerKey` for `PushManager.subscribe()`.
+    pub fn public_key_b64(&self) -> String {
+        base64_url_no_pad_encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// Builds the `Authorization` header value for a push service.
+    fn auth_header(&self, endpoint: &str) -> Result<String, ErrorResponse> {
+        let url = reqwest::Url::parse(endpoint).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Invalid push endpoint".to_string(),
+            )
+        })?;
+        let aud = url.origin().ascii_serialization();
+
+        let header = json!({ "typ": "JWT", "alg": "ES256" });
+        let claims = json!({
+            "aud": aud,
+            // must not be more than 24h in the future
+            "exp": OffsetDateTime::now_utc().unix_timestamp() + 12 * 3600,
+            "sub": *PUSH_MFA_VAPID_SUBJECT,
+        });
+        let signing_input = format!(
+            "{}.{}",
+            base64_url_no_pad_encode(header.to_string().as_bytes()),
+            base64_url_no_pad_encode(claims.to_string().as_bytes()),
+        );
+        let sig = self
+            .key_pair
+            .sign(&SystemRandom::new(), signing_input.as_bytes())
+            .map_err(err_push)?;
+
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            base64_url_no_pad_encode(sig.as_ref()),
+            self.public_key_b64(),
+        ))
+    }
+}
+
+/// The receiving end of a push subscription from `PushSubscription.toJSON()`.
+pub struct WebPushTarget<'a> {
+    pub endpoint: &'a str,
+    /// The P-256 public key of the user agent
+    pub p256dh: &'a str,
+    /// The authentication secret of the user agent
+    pub auth: &'a str,
+}
+
+/// The result of a single push message.
+#[derive(Debug, PartialEq)]
+pub enum WebPushResult {
+    Sent,
+    /// The subscription has expired or was removed and should be deleted.
+    Gone,
+}
+
+impl WebPushTarget<'_> {
+    /// Encrypts the payload and sends it to the push service.
+    ///
+    /// The endpoint is user controlled. It is resolved before each message and the connection
+    /// is pinned to the checked address, so it can never reach an internal host.
+    pub async fn send(
+        &self,
+        data: &web::Data<AppState>,
+        payload: &[u8],
+        ttl_secs: u32,
+    ) -> Result<WebPushResult, ErrorResponse> {
+        let url = reqwest::Url::parse(self.endpoint).map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Invalid push endpoint".to_string(),
+            )
+        })?;
+        let addr = resolve_public_addr(&url).await?;
+
+        let vapid = VapidKey::get(data).await?;
+        let body = self.encrypt(payload)?;
+
+        let client = reqwest::Client::builder()
+            .resolve(url.host_str().unwrap_or_default(), addr)
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(15))
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent(format!("Rauthy v{} Web Push Client", RAUTHY_VERSION))
+            .min_tls_version(tls::Version::TLS_1_2)
+            .build()?;
+
+        let res = client
+            .post(self.endpoint)
+            .header("Authorization", vapid.auth_header(self.endpoint)?)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("TTL", ttl_secs.to_string())
+            .header("Urgency", "high")
+            .body(body)
+            .send()
+            .await?;
+
+        match res.status() {
+            s if s.is_success() => Ok(WebPushResult::Sent),
+            StatusCode::NOT_FOUND | StatusCode::GONE => {
+                debug!("Push subscription is gone: {}", self.endpoint);
+                Ok(WebPushResult::Gone)
+            }
+            s => Err(ErrorResponse::new(
+                ErrorResponseType::Connection,
+                format!("Error sending push message: {}", s),
+            )),
+        }
+    }
+
+    /// Message encryption for Web Push (RFC 8291) with the `aes128gcm` content coding
+    /// (RFC 8188).
+    fn encrypt(&self, payload: &[u8]) -> Result<Vec<u8>, ErrorResponse> {
+        let rng = SystemRandom::new();
+        let ua_public = base64_url_no_pad_decode(self.p256dh)?;
+        let auth_secret = base64_url_no_pad_decode(self.auth)?;
+
+        let private =
+            EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).map_err(err_push)?;
+        let as_public = private.compute_public_key().map_err(err_push)?;
+        let as_public = as_public.as_ref().to_vec();
+
+        let ecdh_secret = agreement::agree_ephemeral(
+            private,
+            &UnparsedPublicKey::new(&agreement::ECDH_P256, &ua_public),
+            |z| z.to_vec(),
+        )
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Invalid push subscription key".to_string(),
+            )
+        })?;
+
+        let mut salt = [0u8; 16];
+        rng.fill(&mut salt).map_err(err_push)?;
+        let (cek, nonce) = derive_keys(&ecdh_secret, &auth_secret, &ua_public, &as_public, &salt)?;
+
+        // a single and therefore last record -> padding delimiter 0x02
+        let mut in_out = Vec::with_capacity(payload.len() + 1 + aead::AES_128_GCM.tag_len());
+        in_out.extend_from_slice(payload);
+        in_out.push(2);
+        if in_out.len() + aead::AES_128_GCM.tag_len() > RECORD_SIZE as usize {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "Push message payload is too big".to_string(),
+            ));
+        }
+
+        let key = aead::UnboundKey::new(&aead::AES_128_GCM, &cek)
+            .map(aead::LessSafeKey::new)
+            .map_err(err_push)?;
+        key.seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::empty(),
+            &mut in_out,
+        )
+        .map_err(err_push)?;
+
+        // header: salt | rs | idlen | keyid
+        let mut body = Vec::with_capacity(16 + 4 + 1 + as_public.len() + in_out.len());
+        body.extend_from_slice(&salt);
+        body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+        body.push(as_public.len() as u8);
+        body.extend_from_slice(&as_public);
+        body.extend(in_out);
+        Ok(body)
+    }
+}
+
+struct HkdfLen(usize);
+
+impl hkdf::KeyType for HkdfLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf_expand(prk: &hkdf::Prk, info: &[&[u8]], out: &mut [u8]) -> Result<(), ErrorResponse> {
+    prk.expand(info, HkdfLen(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .map_err(err_push)
+}
+
+/// Derives the content encryption key and the nonce (RFC 8291 3.4).
+fn derive_keys(
+    ecdh_secret: &[u8],
+    auth_secret: &[u8],
+    ua_public: &[u8],
+    as_public: &[u8],
+    salt: &[u8],
+) -> Result<([u8; 16], [u8; aead::NONCE_LEN]), ErrorResponse> {
+    let prk_key = hkdf::Salt::new(hkdf::HKDF_SHA256, auth_secret).extract(ecdh_secret);
+    let mut ikm = [0u8; 32];
+    hkdf_expand(
+        &prk_key,
+        &[b"WebPush: info\0", ua_public, as_public],
+        &mut ikm,
+    )?;
+
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&ikm);
+    let mut cek = [0u8; 16];
+    hkdf_expand(&prk, &[b"Content-Encoding: aes128gcm\0"], &mut cek)?;
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    hkdf_expand(&prk, &[b"Content-Encoding: nonce\0"], &mut nonce)?;
+
+    Ok((cek, nonce))
+}
+
+fn err_push<E>(_: E) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::Internal,
+        "Error during Web Push encryption".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vector from RFC 8291 Section 5
+    #[test]
+    fn test_web_push_derive_keys() {
+        let ua_public = base64_url_no_pad_decode(
+            "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4",
+        )
+        .unwrap();
+        let as_public = base64_url_no_pad_decode(
+            "BP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8",
+        )
+        .unwrap();
+        let auth_secret = base64_url_no_pad_decode("BTBZMqHH6r4Tts7J_aSIgg").unwrap();
+        let ecdh_secret =
+            base64_url_no_pad_decode("kyrL1jIIOHEzg3sM2ZWRHDRB62YACZhhSlknJ672kSs").unwrap();
+        let salt = base64_url_no_pad_decode("DGv6ra1nlYgDCS1FRnbzlw").unwrap();
+
+        let (cek, nonce) =
+            derive_keys(&ecdh_secret, &auth_secret, &ua_public, &as_public, &salt).unwrap();
+        assert_eq!(base64_url_no_pad_encode(&cek), "oIhVW04MRdy2XN9CiKLxTg");
+        assert_eq!(base64_url_no_pad_encode(&nonce), "4h_95klXJ5E_qnoN");
+    }
+}
@@ -9,6 +9,7 @@ use actix_web::http::header;
 use actix_web::http::header::HeaderValue;
 use actix_web::{cookie, web, HttpResponse};
 use cryptr::EncValue;
+use rauthy_common::cache_metrics::{cache_get, cache_remove};
 use rauthy_common::constants::{
     CACHE_NAME_WEBAUTHN, CACHE_NAME_WEBAUTHN_DATA, COOKIE_MFA, IDX_WEBAUTHN, WEBAUTHN_FORCE_UV,
     WEBAUTHN_NO_PASSWORD_EXPIRY, WEBAUTHN_RENEW_EXP, WEBAUTHN_REQ_EXP,
@@ -16,7 +17,7 @@ use rauthy_common::constants::{
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::base64_decode;
 use rauthy_common::utils::{base64_encode, get_rand};
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, cache_remove, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::ops::Add;
@@ -158,12 +159,11 @@ impl PasskeyEntity {
         name: &str,
     ) -> Result<Self, ErrorResponse> {
         let idx = Self::cache_idx_single(user_id, name);
-        let pk = cache_get!(
-            PasskeyEntity,
+        let pk = cache_get::<PasskeyEntity>(
             CACHE_NAME_WEBAUTHN.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(pk) = pk {
@@ -196,12 +196,11 @@ impl PasskeyEntity {
         user_id: &str,
     ) -> Result<Vec<CredentialID>, ErrorResponse> {
         let idx = Self::cache_idx_creds(user_id);
-        let pk = cache_get!(
-            Vec<Vec<u8>>,
+        let pk = cache_get::<Vec<Vec<u8>>>(
             CACHE_NAME_WEBAUTHN.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(pk) = pk {
@@ -235,12 +234,11 @@ impl PasskeyEntity {
         user_id: &str,
     ) -> Result<Vec<Self>, ErrorResponse> {
         let idx = Self::cache_idx_user(user_id);
-        let pk = cache_get!(
-            Vec<PasskeyEntity>,
+        let pk = cache_get::<Vec<PasskeyEntity>>(
             CACHE_NAME_WEBAUTHN.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(pk) = pk {
@@ -268,12 +266,11 @@ impl PasskeyEntity {
         user_id: &str,
     ) -> Result<Vec<Self>, ErrorResponse> {
         let idx = Self::cache_idx_user(user_id);
-        let pk = cache_get!(
-            Vec<PasskeyEntity>,
+        let pk = cache_get::<Vec<PasskeyEntity>>(
             CACHE_NAME_WEBAUTHN.to_string(),
             idx.clone(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
         if let Some(pk) = pk {
@@ -345,15 +342,15 @@ impl PasskeyEntity {
         serde_json::from_str(&self.passkey).unwrap()
     }
 
-    fn cache_idx_single(user_id: &str, name: &str) -> String {
+    pub(crate) fn cache_idx_single(user_id: &str, name: &str) -> String {
         format!("{}{}{}", IDX_WEBAUTHN, user_id, name)
     }
 
-    fn cache_idx_user(user_id: &str) -> String {
+    pub(crate) fn cache_idx_user(user_id: &str) -> String {
         format!("{}{}", IDX_WEBAUTHN, user_id)
     }
 
-    fn cache_idx_creds(user_id: &str) -> String {
+    pub(crate) fn cache_idx_creds(user_id: &str) -> String {
         format!("{}{}_creds", IDX_WEBAUTHN, user_id)
     }
 }
@@ -430,12 +427,11 @@ impl WebauthnData {
     }
 
     pub async fn find(data: &web::Data<AppState>, code: String) -> Result<Self, ErrorResponse> {
-        let res = cache_get!(
-            WebauthnData,
+        let res = cache_get::<WebauthnData>(
             CACHE_NAME_WEBAUTHN_DATA.to_string(),
             code,
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
 
@@ -531,12 +527,11 @@ impl WebauthnLoginReq {
     }
 
     pub async fn find(data: &web::Data<AppState>, code: String) -> Result<Self, ErrorResponse> {
-        let res = cache_get!(
-            WebauthnLoginReq,
+        let res = cache_get::<WebauthnLoginReq>(
             CACHE_NAME_WEBAUTHN_DATA.to_string(),
             code,
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
 
@@ -590,12 +585,11 @@ impl WebauthnServiceReq {
     }
 
     pub async fn find(data: &web::Data<AppState>, code: String) -> Result<Self, ErrorResponse> {
-        let res = cache_get!(
-            Self,
+        let res = cache_get::<Self>(
             CACHE_NAME_WEBAUTHN_DATA.to_string(),
             code,
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?;
 
@@ -837,20 +831,22 @@ pub async fn reg_start(
     }
 }
 
+/// Finishes a passkey registration. With `replace_existing`, all other passkeys of the user are
+/// deleted in the same transaction, which only happens after a successful registration.
 pub async fn reg_finish(
     data: &web::Data<AppState>,
     id: String,
     req: WebauthnRegFinishRequest,
+    replace_existing: bool,
 ) -> Result<(), ErrorResponse> {
     let mut user = User::find(data, id).await?;
 
     let idx = format!("reg_{:?}_{}", req.passkey_name, user.id);
-    let res = cache_get!(
-        WebauthnReg,
+    let res = cache_get::<WebauthnReg>(
         CACHE_NAME_WEBAUTHN.to_string(),
         idx.clone(),
         &data.caches.ha_cache_config,
-        true
+        true,
     )
     .await?;
     if res.is_none() {
@@ -889,8 +885,19 @@ pub async fn reg_finish(
                 ));
             }
 
+            let replaced = if replace_existing {
+                PasskeyEntity::find_for_user(data, &user.id).await?
+            } else {
+                Vec::default()
+            };
+
             let mut txn = data.db.begin().await?;
 
+            // deleted first, so the new key may even reuse an old name
+            for pk in replaced {
+                pk.delete(data, Some(&mut txn)).await?;
+            }
+
             if user.webauthn_user_id.is_none() {
                 user.webauthn_user_id = Some(reg_data.passkey_user_id.to_string());
                 if user.password.is_none() || *WEBAUTHN_NO_PASSWORD_EXPIRY {
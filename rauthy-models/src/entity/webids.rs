@@ -1,8 +1,9 @@
 use crate::app_state::AppState;
 use actix_web::web;
+use rauthy_common::cache_metrics::cache_get;
 use rauthy_common::constants::{CACHE_NAME_USERS, PUB_URL_WITH_SCHEME};
 use rauthy_common::error_response::ErrorResponse;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_insert, AckLevel};
+use redhac::{cache_insert, AckLevel};
 use rio_api::formatter::TriplesFormatter;
 use rio_api::parser::TriplesParser;
 use rio_turtle::{NTriplesFormatter, NTriplesParser, TurtleFormatter, TurtleParser};
@@ -31,12 +32,11 @@ impl WebId {
 
     /// Returns the WebId from the database, if it exists, and a default otherwise.
     pub async fn find(data: &web::Data<AppState>, user_id: String) -> Result<Self, ErrorResponse> {
-        if let Some(web_id) = cache_get!(
-            Self,
+        if let Some(web_id) = cache_get::<Self>(
             CACHE_NAME_USERS.to_string(),
             Self::cache_idx(&user_id),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -1,9 +1,16 @@
 use crate::app_state::AppState;
+use crate::entity::jwe::{JweAlg, JweEnc};
 use crate::entity::scopes::Scope;
+use crate::entity::spiffe::SpiffeSvid;
+use crate::request::ResponseMode;
 use actix_web::web;
-use rauthy_common::constants::{CACHE_NAME_12HR, ENABLE_DYN_CLIENT_REG, GRANT_TYPE_DEVICE_CODE};
+use rauthy_common::cache_metrics::cache_get;
+use rauthy_common::constants::{
+    CACHE_NAME_12HR, ENABLE_DYN_CLIENT_REG, GRANT_TYPE_DEVICE_CODE, GRANT_TYPE_TOKEN_EXCHANGE,
+    OPEN_USER_REG, WORKLOAD_AWS_CLIENTS, WORKLOAD_GCP_CLIENTS,
+};
 use rauthy_common::error_response::ErrorResponse;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_put};
+use redhac::cache_put;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -22,30 +29,41 @@ pub struct WellKnown {
     pub jwks_uri: String,
     pub grant_types_supported: Vec<String>,
     pub response_types_supported: Vec<String>,
+    pub response_modes_supported: Vec<String>,
+    pub authorization_signing_alg_values_supported: Vec<String>,
     pub subject_types_supported: Vec<String>,
     pub id_token_signing_alg_values_supported: Vec<String>,
     pub token_endpoint_auth_methods_supported: Vec<String>,
     pub token_endpoint_auth_signing_alg_values_supported: Vec<String>,
+    pub introspection_endpoint_auth_methods_supported: Vec<String>,
     pub claims_supported: Vec<String>,
     pub claim_types_supported: Vec<String>,
     pub scopes_supported: Vec<String>,
     pub code_challenge_methods_supported: Vec<String>,
+    pub prompt_values_supported: Vec<String>,
     pub dpop_signing_alg_values_supported: Vec<String>,
     pub service_documentation: String,
     pub ui_locales_supported: Vec<String>,
     pub claims_parameter_supported: bool,
+    pub request_parameter_supported: bool,
+    pub request_uri_parameter_supported: bool,
+    pub require_request_uri_registration: bool,
+    pub request_object_signing_alg_values_supported: Vec<String>,
+    pub id_token_encryption_alg_values_supported: Vec<String>,
+    pub id_token_encryption_enc_values_supported: Vec<String>,
+    pub userinfo_encryption_alg_values_supported: Vec<String>,
+    pub userinfo_encryption_enc_values_supported: Vec<String>,
 }
 
 const IDX: &str = ".well-known";
 
 impl WellKnown {
     pub async fn json(data: &web::Data<AppState>) -> Result<String, ErrorResponse> {
-        if let Some(wk) = cache_get!(
-            String,
+        if let Some(wk) = cache_get::<String>(
             CACHE_NAME_12HR.to_string(),
             IDX.to_string(),
             &data.caches.ha_cache_config,
-            false
+            false,
         )
         .await?
         {
@@ -99,7 +117,7 @@ impl WellKnown {
         let authorization_endpoint = format!("{}/oidc/authorize", issuer);
         let device_authorization_endpoint = format!("{}/oidc/device", issuer);
         let token_endpoint = format!("{}/oidc/token", issuer);
-        let introspection_endpoint = format!("{}/oidc/tokenInfo", issuer);
+        let introspection_endpoint = format!("{}/oidc/introspect", issuer);
         let userinfo_endpoint = format!("{}/oidc/userinfo", issuer);
         let registration_endpoint =
             ENABLE_DYN_CLIENT_REG.then_some(format!("{}/clients_dyn", issuer));
@@ -111,8 +129,25 @@ impl WellKnown {
             "password".to_string(),
             "refresh_token".to_string(),
             GRANT_TYPE_DEVICE_CODE.to_string(),
+            GRANT_TYPE_TOKEN_EXCHANGE.to_string(),
         ];
         let response_types_supported = vec!["code".to_string()];
+        let response_modes_supported = [
+            ResponseMode::Query,
+            ResponseMode::Jwt,
+            ResponseMode::QueryJwt,
+            ResponseMode::FormPostJwt,
+        ]
+        .iter()
+        .map(|mode| mode.as_str().to_string())
+        .collect();
+        // the authorization response is signed with the `id_token_alg` of the client
+        let authorization_signing_alg_values_supported = vec![
+            "RS256".to_string(),
+            "RS384".to_string(),
+            "RS512".to_string(),
+            "EdDSA".to_string(),
+        ];
         let subject_types_supported = vec!["public".to_string()];
         let id_token_signing_alg_values_supported = vec![
             "RS256".to_string(),
@@ -120,16 +155,26 @@ impl WellKnown {
             "RS512".to_string(),
             "EdDSA".to_string(),
         ];
-        let token_endpoint_auth_methods_supported = vec![
+        let mut token_endpoint_auth_methods_supported = vec![
             "client_secret_post".to_string(),
             "client_secret_basic".to_string(),
         ];
+        if SpiffeSvid::is_enabled() {
+            token_endpoint_auth_methods_supported.push("spiffe_jwt".to_string());
+        }
+        if !WORKLOAD_AWS_CLIENTS.is_empty() || !WORKLOAD_GCP_CLIENTS.is_empty() {
+            token_endpoint_auth_methods_supported.push("workload_identity".to_string());
+        }
         let token_endpoint_auth_signing_alg_values_supported = vec![
             "RS256".to_string(),
             "RS384".to_string(),
             "RS512".to_string(),
             "EdDSA".to_string(),
         ];
+        let introspection_endpoint_auth_methods_supported = vec![
+            "client_secret_post".to_string(),
+            "client_secret_basic".to_string(),
+        ];
         let claims_supported = vec![
             "iss".to_string(),
             "azp".to_string(),
@@ -143,6 +188,7 @@ impl WellKnown {
             "roles".to_string(),
             "groups".to_string(),
             "custom".to_string(),
+            "sid".to_string(),
         ];
         let claim_types_supported = vec![
             "normal".to_string(),
@@ -156,6 +202,10 @@ impl WellKnown {
         //     claims_supported.push("webid".to_string());
         // }
         let code_challenge_methods_supported = vec!["plain".to_string(), "S256".to_string()];
+        let mut prompt_values_supported = vec!["none".to_string(), "login".to_string()];
+        if *OPEN_USER_REG {
+            prompt_values_supported.push("create".to_string());
+        }
         let dpop_signing_alg_values_supported = vec![
             "RS256".to_string(),
             "RS384".to_string(),
@@ -163,6 +213,23 @@ impl WellKnown {
             "EdDSA".to_string(),
         ];
 
+        let request_object_signing_alg_values_supported = vec![
+            "none".to_string(),
+            "RS256".to_string(),
+            "RS384".to_string(),
+            "RS512".to_string(),
+            "EdDSA".to_string(),
+        ];
+        // responses are encrypted with a key from the `jwks_uri` of the client
+        let encryption_alg_values_supported = JweAlg::all()
+            .iter()
+            .map(|alg| alg.to_string())
+            .collect::<Vec<_>>();
+        let encryption_enc_values_supported = JweEnc::all()
+            .iter()
+            .map(|enc| enc.to_string())
+            .collect::<Vec<_>>();
+
         let service_documentation = "https://sebadob.github.io/rauthy/".to_string();
         let ui_locales_supported = vec!["de".to_string(), "en".to_string()];
 
@@ -178,18 +245,30 @@ impl WellKnown {
             jwks_uri,
             grant_types_supported,
             response_types_supported,
+            response_modes_supported,
+            authorization_signing_alg_values_supported,
             subject_types_supported,
             id_token_signing_alg_values_supported,
             token_endpoint_auth_methods_supported,
             token_endpoint_auth_signing_alg_values_supported,
+            introspection_endpoint_auth_methods_supported,
             claims_supported,
             claim_types_supported,
             scopes_supported,
             code_challenge_methods_supported,
+            prompt_values_supported,
             dpop_signing_alg_values_supported,
             service_documentation,
             ui_locales_supported,
             claims_parameter_supported: true,
+            request_parameter_supported: true,
+            request_uri_parameter_supported: true,
+            require_request_uri_registration: false,
+            request_object_signing_alg_values_supported,
+            id_token_encryption_alg_values_supported: encryption_alg_values_supported.clone(),
+            id_token_encryption_enc_values_supported: encryption_enc_values_supported.clone(),
+            userinfo_encryption_alg_values_supported: encryption_alg_values_supported,
+            userinfo_encryption_enc_values_supported: encryption_enc_values_supported,
         }
     }
 }
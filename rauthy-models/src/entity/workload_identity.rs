@@ -0,0 +1,245 @@
+use crate::app_state::AppState;
+use crate::entity::remote_jwks::{JwtAudience, RemoteJwks};
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::{
+    CLOCK_SKEW_LEEWAY, HEADER_X_RAUTHY_SERVER_ID, IDX_GCP_CERTS, RAUTHY_VERSION, RE_AWS_STS_HOST,
+    WORKLOAD_AWS_CLIENTS, WORKLOAD_GCP_CLIENTS,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use reqwest::header::ACCEPT;
+use reqwest::Url;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const GCP_CERTS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StsCallerIdentityResponse {
+    get_caller_identity_response: StsCallerIdentityResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StsCallerIdentityResult {
+    get_caller_identity_result: StsCallerIdentity,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StsCallerIdentity {
+    arn: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GcpIdTokenClaims {
+    iss: String,
+    aud: JwtAudience,
+    exp: i64,
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+}
+
+/// Cloud workload identities, which can be used as a `client_assertion` with the
+/// `client_credentials` grant instead of a static client secret.
+pub struct WorkloadIdentity;
+
+impl WorkloadIdentity {
+    /// Validates a presigned AWS STS `GetCallerIdentity` URL by executing it and returns the
+    /// `client_id` the caller's IAM ARN is mapped to.
+    ///
+    /// The `x-rauthy-server-id` header with the Rauthy issuer as value must be part of the
+    /// signed headers, so that the URL cannot be replayed against any other service.
+    pub async fn validate_aws(
+        data: &web::Data<AppState>,
+        presigned_url: &str,
+    ) -> Result<String, ErrorResponse> {
+        if WORKLOAD_AWS_CLIENTS.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "AWS workload identities are not enabled".to_string(),
+            ));
+        }
+
+        let url = Self::validate_sts_url(presigned_url)?;
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(10))
+            .user_agent(format!("Rauthy v{} Workload Identity", RAUTHY_VERSION))
+            .build()?;
+        let res = client
+            .get(url)
+            .header(ACCEPT, "application/json")
+            .header(HEADER_X_RAUTHY_SERVER_ID, &data.issuer)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            debug!(
+                "AWS STS GetCallerIdentity failed: {}",
+                res.text().await.unwrap_or_default()
+            );
+            return Err(Self::err_invalid("Invalid AWS STS presigned request"));
+        }
+        let arn = res
+            .json::<StsCallerIdentityResponse>()
+            .await?
+            .get_caller_identity_response
+            .get_caller_identity_result
+            .arn;
+
+        // an assumed role session should match its role as well
+        let role_arn = Self::role_arn_from_assumed(&arn);
+        WORKLOAD_AWS_CLIENTS
+            .iter()
+            .find(|(mapped, _)| mapped == &arn || Some(mapped) == role_arn.as_ref())
+            .map(|(_, client_id)| client_id.clone())
+            .ok_or_else(|| {
+                warn!("No client mapping for AWS ARN {}", arn);
+                Self::err_invalid("No client is mapped to this AWS identity")
+            })
+    }
+
+    /// Validates a Google signed GCP ID token and returns the `client_id` the service account
+    /// is mapped to. The token must have been issued for the Rauthy issuer as its audience.
+    pub async fn validate_gcp(
+        data: &web::Data<AppState>,
+        id_token: &str,
+    ) -> Result<String, ErrorResponse> {
+        if WORKLOAD_GCP_CLIENTS.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "GCP workload identities are not enabled".to_string(),
+            ));
+        }
+
+        let jwks = RemoteJwks {
+            url: GCP_CERTS_URL,
+            cache_idx: IDX_GCP_CERTS,
+        };
+        let claims = jwks
+            .validate_jwt::<GcpIdTokenClaims>(data, id_token)
+            .await?;
+
+        if claims.iss != "https://accounts.google.com" && claims.iss != "accounts.google.com" {
+            return Err(Self::err_invalid(
+                "The ID token has not been issued by Google",
+            ));
+        }
+        if claims.exp + (*CLOCK_SKEW_LEEWAY as i64) < Utc::now().timestamp() {
+            return Err(Self::err_invalid("The ID token has expired"));
+        }
+        if !claims.aud.contains(&data.issuer) {
+            return Err(Self::err_invalid(
+                "The ID token has not been issued for Rauthy",
+            ));
+        }
+
+        let email = claims
+            .email
+            .filter(|_| claims.email_verified.unwrap_or(false));
+        WORKLOAD_GCP_CLIENTS
+            .iter()
+            .find(|(account, _)| account == &claims.sub || Some(account) == email.as_ref())
+            .map(|(_, client_id)| client_id.clone())
+            .ok_or_else(|| {
+                warn!("No client mapping for GCP service account {}", claims.sub);
+                Self::err_invalid("No client is mapped to this GCP service account")
+            })
+    }
+
+    /// Only presigned `GetCallerIdentity` requests to the official STS endpoints are allowed,
+    /// to never let Rauthy send requests to arbitrary hosts.
+    fn validate_sts_url(presigned_url: &str) -> Result<Url, ErrorResponse> {
+        let url = Url::parse(presigned_url)
+            .map_err(|_| Self::err_invalid("Malformed AWS STS presigned URL"))?;
+
+        let host = url.host_str().unwrap_or_default();
+        if url.scheme() != "https" || !RE_AWS_STS_HOST.is_match(host) || url.port().is_some() {
+            return Err(Self::err_invalid("Not an AWS STS endpoint"));
+        }
+
+        let mut action = None;
+        let mut signed_headers = None;
+        for (k, v) in url.query_pairs() {
+            match k.as_ref() {
+                "Action" => action = Some(v.to_string()),
+                "X-Amz-SignedHeaders" => signed_headers = Some(v.to_string()),
+                _ => {}
+            }
+        }
+        if action.as_deref() != Some("GetCallerIdentity") {
+            return Err(Self::err_invalid(
+                "Only the 'GetCallerIdentity' action is allowed",
+            ));
+        }
+        if !signed_headers
+            .unwrap_or_default()
+            .split(';')
+            .any(|h| h == HEADER_X_RAUTHY_SERVER_ID)
+        {
+            return Err(Self::err_invalid(
+                "The 'x-rauthy-server-id' header must be signed",
+            ));
+        }
+
+        Ok(url)
+    }
+
+    /// `arn:aws:sts::123456789012:assumed-role/my-role/session`
+    /// -> `arn:aws:iam::123456789012:role/my-role`
+    fn role_arn_from_assumed(arn: &str) -> Option<String> {
+        let (prefix, resource) = arn.split_once(":assumed-role/")?;
+        let (role, _session) = resource.split_once('/')?;
+        let prefix = prefix.replacen(":sts:", ":iam:", 1);
+        Some(format!("{}:role/{}", prefix, role))
+    }
+
+    fn err_invalid(msg: &str) -> ErrorResponse {
+        ErrorResponse::new(ErrorResponseType::Unauthorized, msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_arn_from_assumed() {
+        assert_eq!(
+            WorkloadIdentity::role_arn_from_assumed(
+                "arn:aws:sts::123456789012:assumed-role/my-role/i-0123456789"
+            )
+            .as_deref(),
+            Some("arn:aws:iam::123456789012:role/my-role")
+        );
+        assert!(
+            WorkloadIdentity::role_arn_from_assumed("arn:aws:iam::123456789012:user/me").is_none()
+        );
+    }
+
+    #[test]
+    fn test_validate_sts_url() {
+        let ok = "https://sts.eu-central-1.amazonaws.com/?Action=GetCallerIdentity&Version=2011-06-15&X-Amz-SignedHeaders=host%3Bx-rauthy-server-id";
+        assert!(WorkloadIdentity::validate_sts_url(ok).is_ok());
+
+        // not signed for rauthy
+        let url = "https://sts.amazonaws.com/?Action=GetCallerIdentity&X-Amz-SignedHeaders=host";
+        assert!(WorkloadIdentity::validate_sts_url(url).is_err());
+
+        // wrong action
+        let url = "https://sts.amazonaws.com/?Action=AssumeRole&X-Amz-SignedHeaders=host%3Bx-rauthy-server-id";
+        assert!(WorkloadIdentity::validate_sts_url(url).is_err());
+
+        // foreign host
+        let url = "https://sts.s3.amazonaws.com/?Action=GetCallerIdentity&X-Amz-SignedHeaders=host%3Bx-rauthy-server-id";
+        assert!(WorkloadIdentity::validate_sts_url(url).is_err());
+        let url = "https://sts.evil.com/?Action=GetCallerIdentity&X-Amz-SignedHeaders=host%3Bx-rauthy-server-id";
+        assert!(WorkloadIdentity::validate_sts_url(url).is_err());
+        let url = "http://sts.amazonaws.com/?Action=GetCallerIdentity&X-Amz-SignedHeaders=host%3Bx-rauthy-server-id";
+        assert!(WorkloadIdentity::validate_sts_url(url).is_err());
+    }
+}
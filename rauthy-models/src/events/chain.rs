@@ -0,0 +1,673 @@
+use crate::app_state::{AppState, DbPool};
+use crate::entity::jwk::{JwkKeyPair, JwkKeyPairAlg, JwkLifecycle};
+use crate::events::event::{Event, EventLevel, EventType};
+use crate::response::{EventChainGap, EventChainVerifyResponse};
+use crate::{sign_jwt, validate_jwt};
+use actix_web::web;
+use chrono::Utc;
+use jwt_simple::prelude::*;
+use once_cell::sync::Lazy;
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::base64_url_no_pad_encode;
+use ring::digest;
+use sqlx::{query, query_as};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::{debug, warn};
+
+/// Serializes appending to the chain inside this instance. Multiple instances on the same
+/// Postgres are serialized by the row lock on the chain head.
+static CHAIN_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+const ANCHOR_ALG: &str = "EdDSA";
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AnchorClaims {
+    seq: i64,
+    hash: String,
+}
+
+#[derive(Debug)]
+struct ChainedEvent {
+    id: String,
+    timestamp: i64,
+    level: EventLevel,
+    typ: EventType,
+    ip: Option<String>,
+    data: Option<i64>,
+    text: Option<String>,
+    user_id: Option<String>,
+    seq: Option<i64>,
+    prev_hash: Option<String>,
+    hash: Option<String>,
+    anonymized: bool,
+}
+
+/// The link of an event, which has been deleted by the retention cleanup.
+#[derive(Debug)]
+struct PrunedLink {
+    seq: i64,
+    prev_hash: String,
+    hash: String,
+}
+
+#[derive(Debug)]
+enum ChainLink {
+    Event(ChainedEvent),
+    Pruned(PrunedLink),
+}
+
+impl ChainLink {
+    fn seq(&self) -> i64 {
+        match self {
+            Self::Event(event) => event.seq.unwrap_or_default(),
+            Self::Pruned(link) => link.seq,
+        }
+    }
+}
+
+impl From<&ChainedEvent> for Event {
+    fn from(value: &ChainedEvent) -> Self {
+        Self {
+            id: value.id.clone(),
+            timestamp: value.timestamp,
+            level: value.level.clone(),
+            typ: value.typ.clone(),
+            ip: value.ip.clone(),
+            data: value.data,
+            text: value.text.clone(),
+            user_id: value.user_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EventAnchor {
+    seq: i64,
+    hash: String,
+    token: String,
+}
+
+/// Makes persisted events tamper-evident. Each event is linked to its predecessor by including
+/// the predecessors hash in its own one. The current chain head is signed with the latest
+/// `EdDSA` key periodically, so the chain cannot be re-computed after a modification without
+/// invalidating these anchors.
+///
+/// Anonymizing the events of a deleted user modifies their content on purpose. These events are
+/// flagged, and only their link is verified afterward. Events deleted by the retention cleanup
+/// leave their link behind in `event_chain_pruned`, so that only a deletion outside of the
+/// cleanup shows up as a gap.
+pub struct EventChain;
+
+impl EventChain {
+    /// The hash for an event at position `seq`, linked to the hash of the previous one.
+    pub fn hash(event: &Event, seq: i64, prev_hash: &str) -> String {
+        // a JSON array keeps the input unambiguous, no matter what the text contains
+        let fields = (
+            seq,
+            prev_hash,
+            &event.id,
+            event.timestamp,
+            event.level.value(),
+            event.typ.value(),
+            &event.ip,
+            event.data,
+            &event.text,
+        );
+        // the user link is only part of the hash when it exists, which keeps the hashes of
+        // events from before it had been introduced valid
+        let input = match &event.user_id {
+            None => serde_json::to_string(&fields),
+            Some(user_id) => serde_json::to_string(&(fields, user_id)),
+        }
+        .unwrap();
+        let hash = digest::digest(&digest::SHA256, input.as_bytes());
+        base64_url_no_pad_encode(hash.as_ref())
+    }
+
+    /// Persists the event as the new head of the chain. Does nothing if it has been persisted
+    /// already, for instance when it has been re-sent from the outbox.
+    pub async fn append(db: &DbPool, event: &Event) -> Result<(), ErrorResponse> {
+        let _lock = CHAIN_LOCK.lock().await;
+        let mut txn = db.begin().await?;
+
+        #[cfg(feature = "postgres")]
+        let head = query!("SELECT seq, hash FROM event_chain_head WHERE id = 1 FOR UPDATE")
+            .fetch_one(&mut *txn)
+            .await?;
+        #[cfg(not(feature = "postgres"))]
+        let head = query!("SELECT seq, hash FROM event_chain_head WHERE id = 1")
+            .fetch_one(&mut *txn)
+            .await?;
+
+        let seq = head.seq + 1;
+        let hash = Self::hash(event, seq, &head.hash);
+        let level = event.level.value();
+        let typ = event.typ.value();
+
+        let res = query!(
+            r#"INSERT INTO events
+            (id, timestamp, level, typ, ip, data, text, user_id, seq, prev_hash, hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT(id, timestamp) DO NOTHING"#,
+            event.id,
+            event.timestamp,
+            level,
+            typ,
+            event.ip,
+            event.data,
+            event.text,
+            event.user_id,
+            seq,
+            head.hash,
+            hash,
+        )
+        .execute(&mut *txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            txn.rollback().await?;
+            return Ok(());
+        }
+
+        query!(
+            "UPDATE event_chain_head SET seq = $1, hash = $2 WHERE id = 1",
+            seq,
+            hash,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// Deletes all events with the given `level` older than `threshold` in millis, except the
+    /// ones for or mentioning a user under a legal hold. With `PII_ENCRYPTION_EMAIL`, only the
+    /// events linked to the user can be found. The links of the deleted events are kept to
+    /// be able to verify the chain across them. Returns the amount of deleted events.
+    pub async fn prune(db: &DbPool, level: i16, threshold: i64) -> Result<u64, ErrorResponse> {
+        let mut txn = db.begin().await?;
+
+        query!(
+            r#"INSERT INTO event_chain_pruned (seq, prev_hash, hash)
+            SELECT seq, prev_hash, hash FROM events
+            WHERE level = $1 AND timestamp < $2
+            AND seq IS NOT NULL AND prev_hash IS NOT NULL AND hash IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM legal_holds h JOIN users u ON u.id = h.user_id
+                WHERE events.user_id = h.user_id OR events.text LIKE '%' || u.email || '%'
+            )
+            ON CONFLICT(seq) DO NOTHING"#,
+            level,
+            threshold
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        let res = query!(
+            r#"DELETE FROM events WHERE level = $1 AND timestamp < $2
+            AND NOT EXISTS (
+                SELECT 1 FROM legal_holds h JOIN users u ON u.id = h.user_id
+                WHERE events.user_id = h.user_id OR events.text LIKE '%' || u.email || '%'
+            )"#,
+            level,
+            threshold
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        // Links in front of the oldest remaining event are not needed anymore. The chain
+        // simply starts later then.
+        let compacted = query!(
+            r#"DELETE FROM event_chain_pruned
+            WHERE seq < (SELECT MIN(seq) FROM events WHERE seq IS NOT NULL)"#
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+        debug!(
+            "Pruned {} events with level {} from the chain, compacted {} links",
+            res.rows_affected(),
+            level,
+            compacted.rows_affected()
+        );
+
+        Ok(res.rows_affected())
+    }
+
+    /// Signs the current chain head with the latest `EdDSA` key and saves it as a new anchor.
+    /// Returns `None` if no events have been appended since the last anchor.
+    pub async fn anchor(data: &web::Data<AppState>) -> Result<Option<i64>, ErrorResponse> {
+        let head = query!("SELECT seq, hash FROM event_chain_head WHERE id = 1")
+            .fetch_one(&data.db)
+            .await?;
+        if head.seq == 0 {
+            return Ok(None);
+        }
+        let exists = query!("SELECT seq FROM event_anchors WHERE seq = $1", head.seq)
+            .fetch_optional(&data.db)
+            .await?;
+        if exists.is_some() {
+            return Ok(None);
+        }
+
+        let mut claims = Claims::with_custom_claims(
+            AnchorClaims {
+                seq: head.seq,
+                hash: head.hash.clone(),
+            },
+            coarsetime::Duration::from_secs(0),
+        )
+        .with_issuer(data.issuer.clone());
+        // anchors must stay verifiable for as long as the signing key exists
+        claims.expires_at = None;
+
+        let key_pair_type = JwkKeyPairAlg::from_str(ANCHOR_ALG)?;
+        let kp = JwkKeyPair::find_latest(data, ANCHOR_ALG, key_pair_type).await?;
+        JwkLifecycle::count_usage(&kp.kid);
+        let token = sign_jwt!(kp, claims)?;
+
+        let created = Utc::now().timestamp();
+        query!(
+            r#"INSERT INTO event_anchors (seq, hash, token, created)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT(seq) DO NOTHING"#,
+            head.seq,
+            head.hash,
+            token,
+            created,
+        )
+        .execute(&data.db)
+        .await?;
+
+        Ok(Some(head.seq))
+    }
+
+    /// Verifies all persisted events and anchors.
+    pub async fn verify(
+        data: &web::Data<AppState>,
+    ) -> Result<EventChainVerifyResponse, ErrorResponse> {
+        let head = query!("SELECT seq FROM event_chain_head WHERE id = 1")
+            .fetch_one(&data.db)
+            .await?;
+        debug!("Verifying the event chain up to seq {}", head.seq);
+        let mut verifier = ChainVerifier::new(head.seq);
+
+        let unchained = query!("SELECT COUNT(*) AS count FROM events WHERE seq IS NULL")
+            .fetch_one(&data.db)
+            .await?;
+        // sqlite returns an i32 for count while postgres returns an Option<i64>
+        #[cfg(feature = "postgres")]
+        let unchained = unchained.count.unwrap_or_default();
+        #[cfg(not(feature = "postgres"))]
+        let unchained = unchained.count as i64;
+        verifier.resp.unchained = unchained as u64;
+
+        let mut after: i64 = 0;
+        loop {
+            let batch = query_as!(
+                ChainedEvent,
+                r#"SELECT id, timestamp, level, typ, ip, data, text, user_id, seq, prev_hash, hash,
+                    anonymized
+                FROM events
+                WHERE seq > $1
+                ORDER BY seq ASC
+                LIMIT $2"#,
+                after,
+                VERIFY_BATCH_SIZE,
+            )
+            .fetch_all(&data.db)
+            .await?;
+            let is_last_batch = (batch.len() as i64) < VERIFY_BATCH_SIZE;
+
+            // the pruned links in between the events of this batch, or up to the head at the end
+            let until = if is_last_batch {
+                head.seq.max(after)
+            } else {
+                batch.last().and_then(|e| e.seq).unwrap_or(after)
+            };
+            let pruned = query_as!(
+                PrunedLink,
+                r#"SELECT seq, prev_hash, hash FROM event_chain_pruned
+                WHERE seq > $1 AND seq <= $2
+                ORDER BY seq ASC"#,
+                after,
+                until,
+            )
+            .fetch_all(&data.db)
+            .await?;
+
+            let mut links = batch
+                .into_iter()
+                .map(ChainLink::Event)
+                .chain(pruned.into_iter().map(ChainLink::Pruned))
+                .collect::<Vec<_>>();
+            links.sort_by_key(|link| link.seq());
+            for link in links {
+                verifier.check(link);
+            }
+            after = until;
+
+            if is_last_batch {
+                break;
+            }
+        }
+        let (mut resp, hashes) = verifier.finish();
+
+        let anchors = query_as!(
+            EventAnchor,
+            "SELECT seq, hash, token FROM event_anchors ORDER BY seq ASC"
+        )
+        .fetch_all(&data.db)
+        .await?;
+        for anchor in anchors {
+            let Ok(kid) = JwkKeyPair::kid_from_token(&anchor.token) else {
+                resp.anchors_invalid.push(anchor.seq);
+                continue;
+            };
+            let Ok(kp) = JwkKeyPair::find(data, kid).await else {
+                resp.anchors_unverifiable += 1;
+                continue;
+            };
+
+            let options = VerificationOptions {
+                allowed_issuers: Some(HashSet::from_strings(&[&data.issuer])),
+                ..Default::default()
+            };
+            let is_valid = match validate_jwt!(AnchorClaims, kp, &anchor.token, options) {
+                Ok(claims) => {
+                    claims.custom.seq == anchor.seq
+                        && claims.custom.hash == anchor.hash
+                        // anchored links may have been compacted in the meantime
+                        && hashes
+                            .get(&anchor.seq)
+                            .map(|hash| hash == &anchor.hash)
+                            .unwrap_or(true)
+                }
+                Err(err) => {
+                    debug!("Invalid event chain anchor {}: {:?}", anchor.seq, err);
+                    false
+                }
+            };
+            if is_valid {
+                resp.anchors_valid += 1;
+            } else {
+                warn!("Event chain anchor {} is invalid", anchor.seq);
+                resp.anchors_invalid.push(anchor.seq);
+            }
+        }
+
+        resp.valid = resp.valid && resp.anchors_invalid.is_empty();
+        debug!(
+            "Event chain verification finished - valid: {}, checked: {}, pruned: {}",
+            resp.valid, resp.checked, resp.pruned
+        );
+        Ok(resp)
+    }
+}
+
+/// Walks through all links of the chain in ascending order.
+struct ChainVerifier {
+    resp: EventChainVerifyResponse,
+    /// seq -> hash of all checked links, needed to compare with the anchors afterward
+    hashes: HashMap<i64, String>,
+    prev: Option<(i64, String)>,
+}
+
+impl ChainVerifier {
+    fn new(head_seq: i64) -> Self {
+        Self {
+            resp: EventChainVerifyResponse {
+                head_seq,
+                ..Default::default()
+            },
+            hashes: HashMap::new(),
+            prev: None,
+        }
+    }
+
+    fn check(&mut self, link: ChainLink) {
+        let seq = link.seq();
+        let (prev_hash, hash) = match &link {
+            ChainLink::Event(event) => (
+                event.prev_hash.as_deref().unwrap_or_default(),
+                event.hash.as_deref().unwrap_or_default(),
+            ),
+            ChainLink::Pruned(pruned) => (pruned.prev_hash.as_str(), pruned.hash.as_str()),
+        };
+
+        // Only the start of the chain may be missing after the retention cleanup. Any other
+        // missing link means, that events have been deleted.
+        let link_ok = match &self.prev {
+            None => {
+                if seq > 1 {
+                    debug!("Event chain starts at seq {}", seq);
+                    self.resp.gaps.push(EventChainGap {
+                        from: 1,
+                        until: seq - 1,
+                    });
+                }
+                true
+            }
+            Some((prev_seq, prev_stored)) if *prev_seq == seq - 1 => prev_stored == prev_hash,
+            Some((prev_seq, _)) => {
+                warn!(
+                    "Events {} to {} are missing from the chain",
+                    prev_seq + 1,
+                    seq - 1
+                );
+                self.resp.gaps.push(EventChainGap {
+                    from: prev_seq + 1,
+                    until: seq - 1,
+                });
+                false
+            }
+        };
+        if !link_ok {
+            warn!("The event chain is broken at seq {}", seq);
+            self.resp.broken_links.push(seq);
+        }
+
+        match &link {
+            ChainLink::Event(chained) => {
+                // The content of anonymized events has been modified on purpose. Their link
+                // is still verified with the stored hash.
+                if chained.anonymized {
+                    self.resp.anonymized += 1;
+                } else if EventChain::hash(&Event::from(chained), seq, prev_hash) != hash {
+                    warn!("Event {} with seq {} has been modified", chained.id, seq);
+                    self.resp.modified.push(chained.id.clone());
+                }
+                self.resp.checked += 1;
+            }
+            ChainLink::Pruned(_) => self.resp.pruned += 1,
+        }
+
+        if self.resp.first_seq.is_none() {
+            self.resp.first_seq = Some(seq);
+        }
+        self.resp.last_seq = Some(seq);
+        self.hashes.insert(seq, hash.to_string());
+        self.prev = Some((seq, hash.to_string()));
+    }
+
+    /// Returns the result without the anchors, and all checked hashes.
+    fn finish(mut self) -> (EventChainVerifyResponse, HashMap<i64, String>) {
+        // If there are no events left at all, the chain will show up as truncated as well.
+        self.resp.truncated = self.resp.last_seq.unwrap_or_default() < self.resp.head_seq;
+        self.resp.valid = self.resp.modified.is_empty()
+            && self.resp.broken_links.is_empty()
+            && !self.resp.truncated;
+        (self.resp, self.hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_chain_hash() {
+        let event = Event {
+            id: "event_1".to_string(),
+            timestamp: 1700000000000,
+            level: EventLevel::Notice,
+            typ: EventType::UserEmailChange,
+            ip: None,
+            data: None,
+            text: Some("old@example.com -> new@example.com".to_string()),
+            user_id: None,
+        };
+
+        let hash = EventChain::hash(&event, 1, "");
+        assert_eq!(hash, EventChain::hash(&event, 1, ""));
+
+        // position and link must be part of the hash
+        assert_ne!(hash, EventChain::hash(&event, 2, ""));
+        assert_ne!(hash, EventChain::hash(&event, 1, &hash));
+
+        let mut modified = event.clone();
+        modified.text = Some("old@example.com -> evil@example.com".to_string());
+        assert_ne!(hash, EventChain::hash(&modified, 1, ""));
+
+        let mut modified = event.clone();
+        modified.user_id = Some("user_1".to_string());
+        assert_ne!(hash, EventChain::hash(&modified, 1, ""));
+    }
+
+    fn build_chain(len: i64) -> Vec<ChainedEvent> {
+        let mut prev_hash = String::default();
+        (1..=len)
+            .map(|seq| {
+                let event = Event {
+                    id: format!("event_{}", seq),
+                    timestamp: 1700000000000 + seq,
+                    level: EventLevel::Notice,
+                    typ: EventType::UserEmailChange,
+                    ip: None,
+                    data: None,
+                    text: Some(format!("user_{}@example.com", seq)),
+                    user_id: Some(format!("user_{}", seq)),
+                };
+                let hash = EventChain::hash(&event, seq, &prev_hash);
+                let chained = ChainedEvent {
+                    id: event.id,
+                    timestamp: event.timestamp,
+                    level: event.level,
+                    typ: event.typ,
+                    ip: event.ip,
+                    data: event.data,
+                    text: event.text,
+                    user_id: event.user_id,
+                    seq: Some(seq),
+                    prev_hash: Some(prev_hash.clone()),
+                    hash: Some(hash.clone()),
+                    anonymized: false,
+                };
+                prev_hash = hash;
+                chained
+            })
+            .collect()
+    }
+
+    fn verify_chain(links: Vec<ChainLink>, head_seq: i64) -> EventChainVerifyResponse {
+        let mut verifier = ChainVerifier::new(head_seq);
+        for link in links {
+            verifier.check(link);
+        }
+        verifier.finish().0
+    }
+
+    fn as_pruned(event: ChainedEvent) -> ChainLink {
+        ChainLink::Pruned(PrunedLink {
+            seq: event.seq.unwrap(),
+            prev_hash: event.prev_hash.unwrap(),
+            hash: event.hash.unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_event_chain_verify() {
+        let resp = verify_chain(
+            build_chain(5).into_iter().map(ChainLink::Event).collect(),
+            5,
+        );
+        assert!(resp.valid);
+        assert_eq!(resp.checked, 5);
+        assert_eq!(resp.last_seq, Some(5));
+
+        // a modified event in the middle
+        let mut chain = build_chain(5);
+        chain[2].text = Some("evil@example.com".to_string());
+        let resp = verify_chain(chain.into_iter().map(ChainLink::Event).collect(), 5);
+        assert!(!resp.valid);
+        assert_eq!(resp.modified, vec!["event_3".to_string()]);
+        assert!(resp.broken_links.is_empty());
+
+        // a modified event with a re-computed hash breaks the link to its successor
+        let mut chain = build_chain(5);
+        chain[2].text = Some("evil@example.com".to_string());
+        let event = Event::from(&chain[2]);
+        chain[2].hash = Some(EventChain::hash(
+            &event,
+            3,
+            chain[2].prev_hash.as_deref().unwrap(),
+        ));
+        let resp = verify_chain(chain.into_iter().map(ChainLink::Event).collect(), 5);
+        assert!(!resp.valid);
+        assert!(resp.modified.is_empty());
+        assert_eq!(resp.broken_links, vec![4]);
+
+        // a deleted event in the middle
+        let mut chain = build_chain(5);
+        chain.remove(2);
+        let resp = verify_chain(chain.into_iter().map(ChainLink::Event).collect(), 5);
+        assert!(!resp.valid);
+        assert_eq!(resp.broken_links, vec![4]);
+        assert_eq!(resp.gaps.len(), 1);
+        assert_eq!(resp.gaps[0].from, 3);
+        assert_eq!(resp.gaps[0].until, 3);
+
+        // deleted newest events
+        let resp = verify_chain(
+            build_chain(4).into_iter().map(ChainLink::Event).collect(),
+            5,
+        );
+        assert!(!resp.valid);
+        assert!(resp.truncated);
+
+        // the retention cleanup keeps the links of deleted events
+        let links = build_chain(5)
+            .into_iter()
+            .map(|event| {
+                if event.seq == Some(3) {
+                    as_pruned(event)
+                } else {
+                    ChainLink::Event(event)
+                }
+            })
+            .collect();
+        let resp = verify_chain(links, 5);
+        assert!(resp.valid);
+        assert_eq!(resp.checked, 4);
+        assert_eq!(resp.pruned, 1);
+
+        // the start of the chain may be compacted
+        let mut chain = build_chain(5);
+        chain.drain(0..2);
+        let resp = verify_chain(chain.into_iter().map(ChainLink::Event).collect(), 5);
+        assert!(resp.valid);
+        assert_eq!(resp.first_seq, Some(3));
+        assert_eq!(resp.gaps.len(), 1);
+
+        // anonymized events only have their link checked
+        let mut chain = build_chain(5);
+        chain[2].text = Some("deleted-user".to_string());
+        chain[2].anonymized = true;
+        let resp = verify_chain(chain.into_iter().map(ChainLink::Event).collect(), 5);
+        assert!(resp.valid);
+        assert_eq!(resp.anonymized, 1);
+    }
+}
@@ -1,17 +1,25 @@
-use crate::app_state::DbPool;
+use crate::app_state::{DbPool, DbTxn};
+use crate::events::chain::EventChain;
 use crate::events::{
-    EVENT_LEVEL_FAILED_LOGIN, EVENT_LEVEL_FAILED_LOGINS_10, EVENT_LEVEL_FAILED_LOGINS_15,
-    EVENT_LEVEL_FAILED_LOGINS_20, EVENT_LEVEL_FAILED_LOGINS_25, EVENT_LEVEL_FAILED_LOGINS_7,
-    EVENT_LEVEL_IP_BLACKLISTED, EVENT_LEVEL_JWKS_ROTATE, EVENT_LEVEL_NEW_RAUTHY_ADMIN,
-    EVENT_LEVEL_NEW_RAUTHY_VERSION, EVENT_LEVEL_NEW_USER, EVENT_LEVEL_RAUTHY_HEALTHY,
-    EVENT_LEVEL_RAUTHY_START, EVENT_LEVEL_RAUTHY_UNHEALTHY, EVENT_LEVEL_SECRETS_MIGRATED,
-    EVENT_LEVEL_USER_EMAIL_CHANGE, EVENT_LEVEL_USER_PASSWORD_RESET,
+    EVENT_LEVEL_ADMIN_RESET, EVENT_LEVEL_ADMIN_RESET_OTP, EVENT_LEVEL_AUTH_CODE_REUSE,
+    EVENT_LEVEL_BREAK_GLASS, EVENT_LEVEL_CLIENT_IP_REJECTED, EVENT_LEVEL_CLIENT_SECRET_READ,
+    EVENT_LEVEL_ELEVATED_ROLE, EVENT_LEVEL_EVENT_RECIPIENTS, EVENT_LEVEL_FAILED_LOGIN,
+    EVENT_LEVEL_FAILED_LOGINS_10, EVENT_LEVEL_FAILED_LOGINS_15, EVENT_LEVEL_FAILED_LOGINS_20,
+    EVENT_LEVEL_FAILED_LOGINS_25, EVENT_LEVEL_FAILED_LOGINS_7, EVENT_LEVEL_IP_BLACKLISTED,
+    EVENT_LEVEL_JWKS_ROTATE, EVENT_LEVEL_LEGAL_HOLD, EVENT_LEVEL_LOGIN_POLICY_OVERRIDE,
+    EVENT_LEVEL_LOGIN_RESTRICTED, EVENT_LEVEL_LOGIN_SLO_BREACH, EVENT_LEVEL_MFA_RECOVERY,
+    EVENT_LEVEL_NEW_RAUTHY_ADMIN, EVENT_LEVEL_NEW_RAUTHY_VERSION, EVENT_LEVEL_NEW_USER,
+    EVENT_LEVEL_RAUTHY_HEALTHY, EVENT_LEVEL_RAUTHY_START, EVENT_LEVEL_RAUTHY_UNHEALTHY,
+    EVENT_LEVEL_SECRETS_MIGRATED, EVENT_LEVEL_USER_EMAIL_CHANGE,
+    EVENT_LEVEL_USER_INACTIVE_DISABLED, EVENT_LEVEL_USER_MERGED, EVENT_LEVEL_USER_PASSWORD_RESET,
+    EVENT_LEVEL_USER_SESSIONS_REVOKED,
 };
 use chrono::{DateTime, Timelike, Utc};
 use rauthy_common::constants::EMAIL_SUB_PREFIX;
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
-use rauthy_common::utils::{get_local_hostname, get_rand};
+use rauthy_common::utils::{base64_url_no_pad_encode, get_local_hostname, get_rand};
 use rauthy_notify::{Notification, NotificationLevel};
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as};
 use std::fmt::{Display, Formatter};
@@ -123,10 +131,27 @@ impl EventLevel {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub enum EventType {
+    AdminResetEmail,
+    AdminResetOtp,
+    AdminResetSms,
+    ClientIpRejected,
+    UserMerged,
+    LoginSloBreach,
+    AuthCodeReuse,
+    ClientSecretRead,
+    UserInactiveDisabled,
+    EventRecipientsChanged,
+    JwkChanged,
+    BreakGlass,
+    ElevatedRole,
+    LegalHold,
     InvalidLogins,
+    MfaRecovery,
     IpBlacklisted,
     IpBlacklistRemoved,
     JwksRotated,
+    LoginPolicyOverride,
+    LoginRestricted,
     NewUserRegistered,
     NewRauthyAdmin,
     NewRauthyVersion,
@@ -137,6 +162,7 @@ pub enum EventType {
     SecretsMigrated,
     UserEmailChange,
     UserPasswordReset,
+    UserSessionsRevoked,
     Test,
 }
 
@@ -164,6 +190,24 @@ impl Display for EventType {
             EventType::SecretsMigrated => write!(f, "Secrets have been migrated"),
             EventType::UserEmailChange => write!(f, "User's E-Mail has been changed"),
             EventType::UserPasswordReset => write!(f, "User has reset its password"),
+            EventType::UserSessionsRevoked => write!(f, "User's sessions have been revoked"),
+            EventType::LoginRestricted => write!(f, "Login restricted by a group policy"),
+            EventType::LoginPolicyOverride => write!(f, "Group login policy override granted"),
+            EventType::BreakGlass => write!(f, "Break glass admin access"),
+            EventType::LegalHold => write!(f, "Legal hold changed"),
+            EventType::AdminResetEmail => write!(f, "Password reset link sent by admin"),
+            EventType::AdminResetOtp => write!(f, "One-time password issued by admin"),
+            EventType::AdminResetSms => write!(f, "Password reset SMS sent by admin"),
+            EventType::JwkChanged => write!(f, "JWK lifecycle changed"),
+            EventType::MfaRecovery => write!(f, "MFA account recovery"),
+            EventType::ElevatedRole => write!(f, "Time-limited role grant"),
+            EventType::ClientIpRejected => write!(f, "Client request from a disallowed network"),
+            EventType::UserMerged => write!(f, "User accounts merged"),
+            EventType::LoginSloBreach => write!(f, "Login latency SLO breached"),
+            EventType::AuthCodeReuse => write!(f, "Authorization code reused"),
+            EventType::ClientSecretRead => write!(f, "Client secret read"),
+            EventType::UserInactiveDisabled => write!(f, "User disabled after inactivity"),
+            EventType::EventRecipientsChanged => write!(f, "Event E-Mail recipients changed"),
             EventType::Test => write!(f, "TEST"),
         }
     }
@@ -186,6 +230,24 @@ impl EventType {
             Self::SecretsMigrated => "SecretsMigrated",
             Self::UserEmailChange => "UserEmailChange",
             Self::UserPasswordReset => "UserPasswordReset",
+            Self::UserSessionsRevoked => "UserSessionsRevoked",
+            Self::LoginRestricted => "LoginRestricted",
+            Self::LoginPolicyOverride => "LoginPolicyOverride",
+            Self::BreakGlass => "BreakGlass",
+            Self::LegalHold => "LegalHold",
+            Self::AdminResetEmail => "AdminResetEmail",
+            Self::AdminResetOtp => "AdminResetOtp",
+            Self::AdminResetSms => "AdminResetSms",
+            Self::JwkChanged => "JwkChanged",
+            Self::MfaRecovery => "MfaRecovery",
+            Self::ElevatedRole => "ElevatedRole",
+            Self::ClientIpRejected => "ClientIpRejected",
+            Self::UserMerged => "UserMerged",
+            Self::LoginSloBreach => "LoginSloBreach",
+            Self::AuthCodeReuse => "AuthCodeReuse",
+            Self::ClientSecretRead => "ClientSecretRead",
+            Self::UserInactiveDisabled => "UserInactiveDisabled",
+            Self::EventRecipientsChanged => "EventRecipientsChanged",
             Self::Test => "TEST",
         }
     }
@@ -207,6 +269,24 @@ impl EventType {
             EventType::UserEmailChange => 12,
             EventType::UserPasswordReset => 13,
             EventType::Test => 14,
+            EventType::UserSessionsRevoked => 15,
+            EventType::LoginRestricted => 16,
+            EventType::LoginPolicyOverride => 17,
+            EventType::BreakGlass => 18,
+            EventType::LegalHold => 19,
+            EventType::AdminResetEmail => 20,
+            EventType::AdminResetOtp => 21,
+            EventType::AdminResetSms => 22,
+            EventType::JwkChanged => 23,
+            EventType::MfaRecovery => 24,
+            EventType::ElevatedRole => 25,
+            EventType::ClientIpRejected => 26,
+            EventType::UserMerged => 27,
+            EventType::LoginSloBreach => 28,
+            EventType::AuthCodeReuse => 29,
+            EventType::ClientSecretRead => 30,
+            EventType::UserInactiveDisabled => 31,
+            EventType::EventRecipientsChanged => 32,
         }
     }
 }
@@ -228,6 +308,24 @@ impl From<String> for EventType {
             "SecretsMigrated" => Self::SecretsMigrated,
             "UserEmailChange" => Self::UserEmailChange,
             "UserPasswordReset" => Self::UserPasswordReset,
+            "UserSessionsRevoked" => Self::UserSessionsRevoked,
+            "LoginRestricted" => Self::LoginRestricted,
+            "LoginPolicyOverride" => Self::LoginPolicyOverride,
+            "BreakGlass" => Self::BreakGlass,
+            "LegalHold" => Self::LegalHold,
+            "AdminResetEmail" => Self::AdminResetEmail,
+            "AdminResetOtp" => Self::AdminResetOtp,
+            "AdminResetSms" => Self::AdminResetSms,
+            "JwkChanged" => Self::JwkChanged,
+            "MfaRecovery" => Self::MfaRecovery,
+            "ElevatedRole" => Self::ElevatedRole,
+            "ClientIpRejected" => Self::ClientIpRejected,
+            "UserMerged" => Self::UserMerged,
+            "LoginSloBreach" => Self::LoginSloBreach,
+            "AuthCodeReuse" => Self::AuthCodeReuse,
+            "ClientSecretRead" => Self::ClientSecretRead,
+            "UserInactiveDisabled" => Self::UserInactiveDisabled,
+            "EventRecipientsChanged" => Self::EventRecipientsChanged,
             "TEST" => Self::Test,
             // just return test to never panic
             _ => Self::Test,
@@ -259,6 +357,24 @@ impl From<i64> for EventType {
             12 => EventType::UserEmailChange,
             13 => EventType::UserPasswordReset,
             14 => EventType::Test,
+            15 => EventType::UserSessionsRevoked,
+            16 => EventType::LoginRestricted,
+            17 => EventType::LoginPolicyOverride,
+            18 => EventType::BreakGlass,
+            19 => EventType::LegalHold,
+            20 => EventType::AdminResetEmail,
+            21 => EventType::AdminResetOtp,
+            22 => EventType::AdminResetSms,
+            23 => EventType::JwkChanged,
+            24 => EventType::MfaRecovery,
+            25 => EventType::ElevatedRole,
+            26 => EventType::ClientIpRejected,
+            27 => EventType::UserMerged,
+            28 => EventType::LoginSloBreach,
+            29 => EventType::AuthCodeReuse,
+            30 => EventType::ClientSecretRead,
+            31 => EventType::UserInactiveDisabled,
+            32 => EventType::EventRecipientsChanged,
             _ => EventType::Test,
         }
     }
@@ -273,6 +389,9 @@ pub struct Event {
     pub ip: Option<String>,
     pub data: Option<i64>,
     pub text: Option<String>,
+    /// The user this event is about, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
 }
 
 impl From<&Event> for Notification {
@@ -329,6 +448,28 @@ impl From<&Event> for Notification {
             EventType::SecretsMigrated => value.ip.clone(),
             EventType::UserEmailChange => value.text.clone(),
             EventType::UserPasswordReset => value.text.clone(),
+            EventType::UserSessionsRevoked => value.text.clone(),
+            EventType::LoginRestricted => value.text.clone(),
+            EventType::LoginPolicyOverride => value.text.clone(),
+            EventType::BreakGlass => value.text.clone(),
+            EventType::LegalHold => value.text.clone(),
+            EventType::AdminResetEmail => value.text.clone(),
+            EventType::AdminResetOtp => value.text.clone(),
+            EventType::AdminResetSms => value.text.clone(),
+            EventType::JwkChanged => value.text.clone(),
+            EventType::MfaRecovery => value.text.clone(),
+            EventType::ElevatedRole => value.text.clone(),
+            EventType::ClientIpRejected => Some(format!(
+                "{} from IP: `{}`",
+                value.text.as_deref().unwrap_or_default(),
+                value.ip.as_deref().unwrap_or_default()
+            )),
+            EventType::UserMerged => value.text.clone(),
+            EventType::LoginSloBreach => value.text.clone(),
+            EventType::AuthCodeReuse => value.text.clone(),
+            EventType::ClientSecretRead => value.text.clone(),
+            EventType::UserInactiveDisabled => value.text.clone(),
+            EventType::EventRecipientsChanged => value.text.clone(),
             EventType::Test => value.text.clone(),
         };
 
@@ -360,24 +501,9 @@ impl Display for Event {
 }
 
 impl Event {
+    /// Persists this event as the new head of the tamper-evident `EventChain`.
     pub async fn insert(&self, db: &DbPool) -> Result<(), ErrorResponse> {
-        let level = self.level.value();
-        let typ = self.typ.value();
-
-        query!(
-            r#"INSERT INTO events (id, timestamp, level, typ, ip, data, text)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
-            self.id,
-            self.timestamp,
-            level,
-            typ,
-            self.ip,
-            self.data,
-            self.text,
-        )
-        .execute(db)
-        .await?;
-        Ok(())
+        EventChain::append(db, self).await
     }
 
     pub async fn find_all(
@@ -398,7 +524,7 @@ impl Event {
             let typ = typ.value();
             query_as!(
                 Self,
-                r#"SELECT * FROM events
+                r#"SELECT id, timestamp, level, typ, ip, data, text, user_id FROM events
                 WHERE timestamp >= $1 AND timestamp <= $2 AND level >= $3 AND typ = $4
                 ORDER BY timestamp DESC"#,
                 from,
@@ -411,7 +537,7 @@ impl Event {
         } else {
             query_as!(
                 Self,
-                r#"SELECT * FROM events
+                r#"SELECT id, timestamp, level, typ, ip, data, text, user_id FROM events
                 WHERE timestamp >= $1 AND timestamp <= $2 AND level >= $3
                 ORDER BY timestamp DESC"#,
                 from,
@@ -428,7 +554,27 @@ impl Event {
     pub async fn find_latest(db: &DbPool, limit: i64) -> Result<Vec<Self>, ErrorResponse> {
         let res = query_as!(
             Self,
-            "SELECT * FROM events ORDER BY timestamp DESC LIMIT $1",
+            r#"SELECT id, timestamp, level, typ, ip, data, text, user_id FROM events
+            ORDER BY timestamp DESC LIMIT $1"#,
+            limit
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the latest events, which have been linked to the given user.
+    pub async fn find_for_user(
+        db: &DbPool,
+        user_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"SELECT id, timestamp, level, typ, ip, data, text, user_id FROM events
+            WHERE user_id = $1
+            ORDER BY timestamp DESC LIMIT $2"#,
+            user_id,
             limit
         )
         .fetch_all(db)
@@ -439,6 +585,127 @@ impl Event {
     pub fn as_json(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
+
+    /// Writes this event into the outbox inside the same transaction as the change it
+    /// describes. After the commit, it must still be sent to the `EventListener` as usual,
+    /// which will remove it from the outbox once it has been delivered to all sinks.
+    /// If the instance dies in between, the listener picks it up from the outbox again.
+    pub async fn outbox_insert(&self, txn: &mut DbTxn<'_>) -> Result<(), ErrorResponse> {
+        let payload = self.as_json();
+        let created = Utc::now().timestamp();
+
+        query!(
+            "INSERT INTO events_outbox (id, payload, created) VALUES ($1, $2, $3)",
+            self.id,
+            payload,
+            created,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn outbox_delete(db: &DbPool, id: &str) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM events_outbox WHERE id = $1", id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes all the given events from the outbox with a single query.
+    pub async fn outbox_delete_all(db: &DbPool, ids: &[String]) -> Result<(), ErrorResponse> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let placeholders = (1..=ids.len())
+            .map(|i| format!("${}", i))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let sql = format!("DELETE FROM events_outbox WHERE id IN ({})", placeholders);
+        let mut q = sqlx::query(&sql);
+        for id in ids {
+            q = q.bind(id);
+        }
+        q.execute(db).await?;
+
+        Ok(())
+    }
+
+    /// Returns a stable pseudonym for a deleted user, which replaces the E-Mail inside the
+    /// audit trail. All events of the same user keep referencing the same pseudonym.
+    pub fn user_pseudonym(user_id: &str) -> String {
+        let hash = digest::digest(&digest::SHA256, user_id.as_bytes());
+        format!("deleted-{}", base64_url_no_pad_encode(&hash.as_ref()[..12]))
+    }
+
+    /// Replaces the E-Mail of a deleted user with its pseudonym in all persisted events and
+    /// not yet delivered outbox entries. Returns the number of anonymized events.
+    /// These events are flagged, and only their links are verified by the `EventChain`.
+    ///
+    /// Only events linked to this exact `user_id` are touched. Another user's E-Mail may
+    /// contain this one as a substring, which must never be rewritten.
+    pub async fn anonymize_user(
+        db: &DbPool,
+        user_id: &str,
+        email: &str,
+    ) -> Result<u64, ErrorResponse> {
+        let pseudonym = Self::user_pseudonym(user_id);
+
+        let res = query!(
+            r#"UPDATE events SET text = REPLACE(text, $1, $2), anonymized = true
+            WHERE user_id = $3 AND text LIKE '%' || $1 || '%'"#,
+            email,
+            pseudonym,
+            user_id,
+        )
+        .execute(db)
+        .await?;
+
+        // the outbox only holds a few undelivered entries at most
+        let entries = query_as!(EventOutbox, "SELECT * FROM events_outbox")
+            .fetch_all(db)
+            .await?;
+        for entry in entries {
+            let Ok(mut event) = serde_json::from_str::<Event>(&entry.payload) else {
+                continue;
+            };
+            if event.user_id.as_deref() != Some(user_id) {
+                continue;
+            }
+            let Some(text) = event.text.as_deref().filter(|t| t.contains(email)) else {
+                continue;
+            };
+            event.text = Some(text.replace(email, &pseudonym));
+
+            let payload = event.as_json();
+            query!(
+                "UPDATE events_outbox SET payload = $1 WHERE id = $2",
+                payload,
+                entry.id,
+            )
+            .execute(db)
+            .await?;
+        }
+
+        Ok(res.rows_affected())
+    }
+
+    pub const CSV_HEADER: &'static str = "id,timestamp,level,typ,ip,data,text";
+
+    /// Returns this event as a single CSV row without a trailing newline.
+    pub fn as_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.id,
+            self.timestamp,
+            self.level.as_str(),
+            self.typ.as_str(),
+            self.ip.as_deref().unwrap_or_default(),
+            self.data.map(|d| d.to_string()).unwrap_or_default(),
+            csv_escape(self.text.as_deref().unwrap_or_default()),
+        )
+    }
 }
 
 impl Event {
@@ -460,9 +727,16 @@ impl Event {
             ip,
             data,
             text,
+            user_id: None,
         }
     }
 
+    /// Links this event to the user it is about, which makes it show up in their security view.
+    pub fn for_user(mut self, user_id: String) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
     /// The EventLevel will change depending on the amount of invalid logins
     pub fn invalid_login(failed_logins: u32, ip: String) -> Self {
         let level = match failed_logins {
@@ -598,6 +872,21 @@ impl Event {
         )
     }
 
+    pub fn rauthy_unhealthy_email_backlog(backlog: i64) -> Self {
+        let text = format!(
+            "The E-Mail backlog has grown to {} on host {}",
+            backlog,
+            get_local_hostname()
+        );
+        Self::new(
+            EVENT_LEVEL_RAUTHY_UNHEALTHY.get().cloned().unwrap(),
+            EventType::RauthyUnhealthy,
+            None,
+            Some(backlog),
+            Some(text),
+        )
+    }
+
     pub fn secrets_migrated(ip: Option<String>) -> Self {
         Self::new(
             EVENT_LEVEL_SECRETS_MIGRATED.get().cloned().unwrap(),
@@ -638,6 +927,205 @@ impl Event {
         )
     }
 
+    pub fn break_glass(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_BREAK_GLASS.get().cloned().unwrap(),
+            EventType::BreakGlass,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn legal_hold(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_LEGAL_HOLD.get().cloned().unwrap(),
+            EventType::LegalHold,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn admin_reset_email(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_ADMIN_RESET.get().cloned().unwrap(),
+            EventType::AdminResetEmail,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn admin_reset_otp(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_ADMIN_RESET_OTP.get().cloned().unwrap(),
+            EventType::AdminResetOtp,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn admin_reset_sms(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_ADMIN_RESET.get().cloned().unwrap(),
+            EventType::AdminResetSms,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn jwk_changed(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_JWKS_ROTATE.get().cloned().unwrap(),
+            EventType::JwkChanged,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn mfa_recovery(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_MFA_RECOVERY.get().cloned().unwrap(),
+            EventType::MfaRecovery,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn client_ip_rejected(client_id: &str, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_CLIENT_IP_REJECTED.get().cloned().unwrap(),
+            EventType::ClientIpRejected,
+            ip,
+            None,
+            Some(format!("Token request for client '{}'", client_id)),
+        )
+    }
+
+    pub fn login_slo_breach(stage: &str, p95: u64, slo: u64) -> Self {
+        let text = format!(
+            "{}: p95 {} ms > {} ms on host {}",
+            stage,
+            p95,
+            slo,
+            get_local_hostname()
+        );
+        Self::new(
+            EVENT_LEVEL_LOGIN_SLO_BREACH.get().cloned().unwrap(),
+            EventType::LoginSloBreach,
+            None,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn auth_code_reuse(
+        client_id: &str,
+        user_id: &str,
+        first_ip: Option<&str>,
+        ip: Option<String>,
+    ) -> Self {
+        let text = format!(
+            "client '{}', user '{}', first redeemed from {} - all tokens have been revoked",
+            client_id,
+            user_id,
+            first_ip.unwrap_or("unknown"),
+        );
+        Self::new(
+            EVENT_LEVEL_AUTH_CODE_REUSE.get().cloned().unwrap(),
+            EventType::AuthCodeReuse,
+            ip,
+            None,
+            Some(text),
+        )
+        .for_user(user_id.to_string())
+    }
+
+    pub fn client_secret_read(client_id: &str, actor: &str, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_CLIENT_SECRET_READ.get().cloned().unwrap(),
+            EventType::ClientSecretRead,
+            ip,
+            None,
+            Some(format!("client '{}' by {}", client_id, actor)),
+        )
+    }
+
+    pub fn user_merged(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_USER_MERGED.get().cloned().unwrap(),
+            EventType::UserMerged,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn elevated_role(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_ELEVATED_ROLE.get().cloned().unwrap(),
+            EventType::ElevatedRole,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn login_policy_override(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_LOGIN_POLICY_OVERRIDE.get().cloned().unwrap(),
+            EventType::LoginPolicyOverride,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn login_restricted(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_LOGIN_RESTRICTED.get().cloned().unwrap(),
+            EventType::LoginRestricted,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn user_sessions_revoked(text: String, sessions: i64, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_USER_SESSIONS_REVOKED.get().cloned().unwrap(),
+            EventType::UserSessionsRevoked,
+            ip,
+            Some(sessions),
+            Some(text),
+        )
+    }
+
+    pub fn user_inactive_disabled(text: String) -> Self {
+        Self::new(
+            EVENT_LEVEL_USER_INACTIVE_DISABLED.get().cloned().unwrap(),
+            EventType::UserInactiveDisabled,
+            None,
+            None,
+            Some(text),
+        )
+    }
+
+    pub fn event_recipients_changed(text: String, ip: Option<String>) -> Self {
+        Self::new(
+            EVENT_LEVEL_EVENT_RECIPIENTS.get().cloned().unwrap(),
+            EventType::EventRecipientsChanged,
+            ip,
+            None,
+            Some(text),
+        )
+    }
+
     pub fn fmt_data(&self) -> String {
         match self.typ {
             EventType::InvalidLogins => format!("Counter: {}", self.data.unwrap_or_default()),
@@ -675,6 +1163,92 @@ impl Event {
                     self.text.as_deref().unwrap_or_default()
                 )
             }
+            EventType::UserSessionsRevoked => format!(
+                "{} sessions revoked: {}",
+                self.data.unwrap_or_default(),
+                self.text.as_deref().unwrap_or_default()
+            ),
+            EventType::LoginRestricted => {
+                format!("Login denied: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::LoginPolicyOverride => {
+                format!("Override: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::BreakGlass => {
+                format!("Break glass: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::LegalHold => {
+                format!("Legal hold: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::AdminResetEmail => {
+                format!(
+                    "Reset link via E-Mail: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::AdminResetOtp => {
+                format!(
+                    "One-time password: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::AdminResetSms => {
+                format!(
+                    "Reset link via SMS: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::JwkChanged => {
+                format!("JWK: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::MfaRecovery => {
+                format!("MFA recovery: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::ElevatedRole => {
+                format!(
+                    "Elevated role: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::ClientIpRejected => {
+                format!(
+                    "Client IP rejected: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::UserMerged => {
+                format!("User merged: {}", self.text.as_deref().unwrap_or_default())
+            }
+            EventType::LoginSloBreach => {
+                format!(
+                    "Login SLO breach: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::AuthCodeReuse => {
+                format!(
+                    "Authorization code reused: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::ClientSecretRead => {
+                format!(
+                    "Client secret read: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::UserInactiveDisabled => {
+                format!(
+                    "User disabled after inactivity: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
+            EventType::EventRecipientsChanged => {
+                format!(
+                    "Event recipients changed: {}",
+                    self.text.as_deref().unwrap_or_default()
+                )
+            }
             EventType::Test => {
                 format!("Test Message: {}", self.text.as_deref().unwrap_or_default())
             }
@@ -695,3 +1269,80 @@ impl Event {
         }
     }
 }
+
+/// An event, which has been committed together with its change, but not yet been delivered.
+#[derive(Debug)]
+pub struct EventOutbox {
+    pub id: String,
+    pub payload: String,
+    pub created: i64,
+}
+
+impl EventOutbox {
+    /// Returns outbox entries, which have been created before `created_before`.
+    pub async fn find_stale(
+        db: &DbPool,
+        created_before: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"SELECT * FROM events_outbox
+            WHERE created < $1
+            ORDER BY created ASC
+            LIMIT $2"#,
+            created_before,
+            limit,
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(res)
+    }
+
+    /// Tries to lock this entry for redelivery by the current instance.
+    ///
+    /// Returns `false` if another instance was faster.
+    pub async fn claim(&mut self, db: &DbPool) -> Result<bool, ErrorResponse> {
+        let now = Utc::now().timestamp();
+
+        // the old `created` works as an optimistic lock
+        let res = query!(
+            "UPDATE events_outbox SET created = $1 WHERE id = $2 AND created = $3",
+            now,
+            self.id,
+            self.created,
+        )
+        .execute(db)
+        .await?;
+
+        if res.rows_affected() == 1 {
+            self.created = now;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Quotes a CSV value, if it contains any special characters.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_pseudonym() {
+        let p1 = Event::user_pseudonym("user_id_1");
+        assert_eq!(p1, Event::user_pseudonym("user_id_1"));
+        assert_ne!(p1, Event::user_pseudonym("user_id_2"));
+        assert!(p1.starts_with("deleted-"));
+        assert!(!p1.contains("user_id_1"));
+    }
+}
@@ -1,5 +1,10 @@
+use crate::entity::ip_feeds::IpFeed;
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
+use rauthy_common::constants::IP_BLACKLIST_V6_PREFIX;
+use rauthy_common::utils::IpCidr;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::sync::oneshot;
 use tracing::{debug, error};
@@ -10,6 +15,8 @@ pub enum IpBlacklistReq {
     Blacklist(IpBlacklist),
     BlacklistCheck(IpBlacklistCheck),
     BlacklistDelete(String),
+    FeedCheck(IpFeedCheck),
+    FeedUpdate(IpFeedUpdate),
     LoginCheck(IpFailedLoginCheck),
     LoginFailedSet(IpLoginFailedSet),
     LoginFailedDelete(String),
@@ -28,6 +35,21 @@ pub struct IpBlacklistCheck {
     pub tx: oneshot::Sender<Option<DateTime<Utc>>>,
 }
 
+/// Checks if an IP is listed by one of the IP feeds.
+#[derive(Debug)]
+pub struct IpFeedCheck {
+    pub ip: String,
+    pub tx: oneshot::Sender<Option<IpFeed>>,
+}
+
+/// Replaces all entries of the given feed.
+#[derive(Debug)]
+pub struct IpFeedUpdate {
+    pub feed: IpFeed,
+    pub entries: Vec<IpCidr>,
+    pub exp: DateTime<Utc>,
+}
+
 #[derive(Debug)]
 pub struct IpFailedLoginCheck {
     pub ip: String,
@@ -42,9 +64,81 @@ pub struct IpLoginFailedSet {
     pub invalid_logins: u32,
 }
 
+/// Normalizes an IP or network into the key it is tracked with.
+///
+/// IPv4 mapped IPv6 addresses are converted to IPv4 and IPv6 addresses are aggregated into their
+/// network with `IP_BLACKLIST_V6_PREFIX`. Anything that cannot be parsed is returned as it is.
+pub fn blacklist_key(ip: &str) -> String {
+    match IpCidr::from_str(ip) {
+        Ok(cidr) => cidr.aggregate(*IP_BLACKLIST_V6_PREFIX).to_string(),
+        Err(_) => ip.to_string(),
+    }
+}
+
+/// `true` if the network is wider than the one a single IP is tracked with, which means it
+/// needs a lookup by prefix.
+fn is_wide_network(cidr: &IpCidr) -> bool {
+    match cidr.addr() {
+        IpAddr::V4(_) => !cidr.is_host(),
+        IpAddr::V6(_) => cidr.prefix() < *IP_BLACKLIST_V6_PREFIX,
+    }
+}
+
+/// The entries of a single IP feed, which are kept apart from the other blacklisted IPs.
+#[derive(Debug, Default)]
+struct FeedEntries {
+    exp: DateTime<Utc>,
+    hosts: HashSet<String>,
+    networks: Vec<IpCidr>,
+}
+
+impl FeedEntries {
+    fn new(entries: Vec<IpCidr>, exp: DateTime<Utc>) -> Self {
+        let mut slf = Self {
+            exp,
+            ..Default::default()
+        };
+        for cidr in entries {
+            let cidr = cidr.aggregate(*IP_BLACKLIST_V6_PREFIX);
+            if is_wide_network(&cidr) {
+                slf.networks.push(cidr);
+            } else {
+                slf.hosts.insert(cidr.to_string());
+            }
+        }
+        slf
+    }
+
+    fn contains(&self, key: &str, cidr: Option<&IpCidr>) -> bool {
+        self.hosts.contains(key)
+            || cidr
+                .map(|cidr| self.networks.iter().any(|net| net.contains(cidr.addr())))
+                .unwrap_or(false)
+    }
+}
+
+/// Returns the feed listing the given blacklist key, if any.
+fn feed_for(feeds: &HashMap<IpFeed, FeedEntries>, key: &str) -> Option<(IpFeed, DateTime<Utc>)> {
+    if feeds.is_empty() {
+        return None;
+    }
+    let cidr = IpCidr::from_str(key).ok();
+    feeds
+        .iter()
+        .find(|(_, entries)| entries.contains(key, cidr.as_ref()))
+        .map(|(feed, entries)| (*feed, entries.exp))
+}
+
 /// Handles blacklisted IP's and IP's with failed logins
+///
+/// All IPs are normalized with [blacklist_key] first. Manually blacklisted networks which are
+/// wider than that are additionally kept in a list, which is checked by prefix.
+/// Entries from IP feeds are kept separately per feed and can only be replaced by the next update
+/// of the same feed.
 pub async fn run(tx: flume::Sender<IpBlacklistReq>, rx: flume::Receiver<IpBlacklistReq>) {
     let mut data_blacklist: HashMap<String, DateTime<Utc>> = HashMap::with_capacity(2);
+    let mut data_networks: Vec<(IpCidr, String)> = Vec::new();
+    let mut data_feeds: HashMap<IpFeed, FeedEntries> = HashMap::new();
     let mut data_failed_logins: HashMap<String, u32> = HashMap::with_capacity(2);
 
     let mut exp_checker_handle = tokio::spawn(spawn_exp_checker(tx.clone()));
@@ -66,15 +160,33 @@ pub async fn run(tx: flume::Sender<IpBlacklistReq>, rx: flume::Receiver<IpBlackl
                     for key in remove {
                         data_blacklist.remove(&key);
                     }
+                    data_networks.retain(|(_, key)| data_blacklist.contains_key(key));
+                    data_feeds.retain(|feed, entries| {
+                        if now > entries.exp {
+                            debug!("Removing expired entries of IP feed {}", feed);
+                            false
+                        } else {
+                            true
+                        }
+                    });
 
-                    if data_blacklist.is_empty() && !exp_checker_handle.is_finished() {
+                    if data_blacklist.is_empty()
+                        && data_feeds.is_empty()
+                        && !exp_checker_handle.is_finished()
+                    {
                         exp_checker_handle.abort();
                         debug!("IpBlacklist ExpChecker has been stopped");
                     }
                 }
 
                 IpBlacklistReq::Blacklist(req) => {
-                    data_blacklist.insert(req.ip, req.exp);
+                    let key = blacklist_key(&req.ip);
+                    if let Ok(cidr) = IpCidr::from_str(&key) {
+                        if is_wide_network(&cidr) && !data_networks.iter().any(|(_, k)| k == &key) {
+                            data_networks.push((cidr, key.clone()));
+                        }
+                    }
+                    data_blacklist.insert(key, req.exp);
 
                     if exp_checker_handle.is_finished() {
                         exp_checker_handle = tokio::spawn(spawn_exp_checker(tx.clone()));
@@ -82,13 +194,48 @@ pub async fn run(tx: flume::Sender<IpBlacklistReq>, rx: flume::Receiver<IpBlackl
                 }
 
                 IpBlacklistReq::BlacklistCheck(req) => {
+                    let key = blacklist_key(&req.ip);
+                    let mut exp = data_blacklist.get(&key).cloned();
+                    if exp.is_none() && !data_networks.is_empty() {
+                        if let Ok(cidr) = IpCidr::from_str(&key) {
+                            exp = data_networks
+                                .iter()
+                                .filter(|(net, _)| net.contains(cidr.addr()))
+                                .filter_map(|(_, k)| data_blacklist.get(k))
+                                .max()
+                                .cloned();
+                        }
+                    }
+                    if let Some((_, feed_exp)) = feed_for(&data_feeds, &key) {
+                        exp = exp.max(Some(feed_exp));
+                    }
+
+                    req.tx.send(exp).expect("oneshot receiver to not be closed");
+                }
+
+                IpBlacklistReq::FeedCheck(req) => {
+                    let feed = feed_for(&data_feeds, &blacklist_key(&req.ip)).map(|(f, _)| f);
                     req.tx
-                        .send(data_blacklist.get(&req.ip).cloned())
+                        .send(feed)
                         .expect("oneshot receiver to not be closed");
                 }
 
+                IpBlacklistReq::FeedUpdate(req) => {
+                    debug!(
+                        "Replacing IP feed {} with {} entries",
+                        req.feed,
+                        req.entries.len()
+                    );
+                    data_feeds.insert(req.feed, FeedEntries::new(req.entries, req.exp));
+
+                    if exp_checker_handle.is_finished() {
+                        exp_checker_handle = tokio::spawn(spawn_exp_checker(tx.clone()));
+                    }
+                }
+
                 IpBlacklistReq::LoginCheck(req) => {
-                    let counter = if let Some(counter) = data_failed_logins.get_mut(&req.ip) {
+                    let key = blacklist_key(&req.ip);
+                    let counter = if let Some(counter) = data_failed_logins.get_mut(&key) {
                         if req.increase_counter {
                             *counter += 1;
                             Some(*counter)
@@ -96,7 +243,7 @@ pub async fn run(tx: flume::Sender<IpBlacklistReq>, rx: flume::Receiver<IpBlackl
                             Some(*counter)
                         }
                     } else if req.increase_counter {
-                        data_failed_logins.insert(req.ip, 1);
+                        data_failed_logins.insert(key, 1);
                         Some(1)
                     } else {
                         None
@@ -108,21 +255,25 @@ pub async fn run(tx: flume::Sender<IpBlacklistReq>, rx: flume::Receiver<IpBlackl
                 }
 
                 IpBlacklistReq::LoginFailedSet(req) => {
-                    if let Some(counter) = data_failed_logins.get_mut(&req.ip) {
+                    let key = blacklist_key(&req.ip);
+                    if let Some(counter) = data_failed_logins.get_mut(&key) {
                         if req.invalid_logins > *counter {
                             *counter = req.invalid_logins;
                         }
                     } else {
-                        data_failed_logins.insert(req.ip, req.invalid_logins);
+                        data_failed_logins.insert(key, req.invalid_logins);
                     }
                 }
 
+                // feed entries are not affected on purpose
                 IpBlacklistReq::BlacklistDelete(ip) => {
-                    data_blacklist.remove(&ip);
+                    let key = blacklist_key(&ip);
+                    data_blacklist.remove(&key);
+                    data_networks.retain(|(_, k)| k != &key);
                 }
 
                 IpBlacklistReq::LoginFailedDelete(ip) => {
-                    data_failed_logins.remove(&ip);
+                    data_failed_logins.remove(&blacklist_key(&ip));
                 }
 
                 IpBlacklistReq::GetBlacklistedIps(tx) => {
@@ -1,19 +1,31 @@
 use crate::app_state::DbPool;
-use crate::events::event::{Event, EventLevel, EventType};
+use crate::events::event::{Event, EventLevel, EventOutbox, EventType};
 use crate::events::ip_blacklist_handler::{IpBlacklist, IpBlacklistReq, IpLoginFailedSet};
 use crate::events::notifier::EventNotifier;
+use crate::events::sse_hub::SseHub;
 use crate::events::EVENT_PERSIST_LEVEL;
 use actix_web_lab::sse;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use rauthy_common::constants::HA_MODE;
-use rauthy_common::constants::{DATABASE_URL, EVENTS_LATEST_LIMIT};
+use rauthy_common::constants::{DATABASE_URL, EVENTS_LATEST_LIMIT, READ_ONLY_MODE};
 use rauthy_common::error_response::ErrorResponse;
 use sqlx::postgres::PgListener;
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::time;
-use tracing::{debug, error, info};
+use tracing::{debug, error, warn};
+
+/// How often the outbox is checked for events, which have never been delivered.
+const OUTBOX_INTERVAL: Duration = Duration::from_secs(30);
+/// Outbox entries younger than this are most probably still in flight and will be removed
+/// by the instance, which created them.
+const OUTBOX_GRACE_SECS: i64 = 30;
+const OUTBOX_BATCH_SIZE: i64 = 100;
+/// Delivered events are collected for this long and then removed from the outbox together.
+const OUTBOX_REMOVE_DELAY: Duration = Duration::from_millis(500);
+/// How often other HA instances check for changed event E-Mail recipients.
+const RECIPIENTS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub enum EventRouterMsg {
@@ -24,6 +36,8 @@ pub enum EventRouterMsg {
         latest: Option<u16>,
         level: EventLevel,
     },
+    /// Removes disconnected subscribers and answers on the given channel when it is done.
+    Prune(mpsc::Sender<()>),
 }
 
 pub struct EventListener;
@@ -44,14 +58,36 @@ impl EventListener {
 
         if is_ha {
             tokio::spawn(Self::pg_listener(tx_router.clone()));
+            tokio::spawn(Self::recipients_reload(db.clone()));
+        } else if let Err(err) = EventNotifier::reload_email_recipients(&db).await {
+            error!("Loading the event E-Mail recipients: {:?}", err);
         }
         tokio::spawn(Self::router(db.clone(), rx_router, tx_ip_blacklist));
 
+        let (tx_delivered, rx_delivered) = flume::unbounded();
+        if !*READ_ONLY_MODE {
+            tokio::spawn(Self::outbox_drain(
+                db.clone(),
+                tx_router.clone(),
+                tx_delivered.clone(),
+            ));
+            tokio::spawn(Self::outbox_remove(db.clone(), rx_delivered));
+        }
+
         while let Ok(event) = rx_event.recv_async().await {
             if is_ha {
-                tokio::spawn(Self::handle_event_ha(event, db.clone()));
+                tokio::spawn(Self::handle_event_ha(
+                    event,
+                    db.clone(),
+                    tx_delivered.clone(),
+                ));
             } else {
-                tokio::spawn(Self::handle_event_si(event, db.clone(), tx_router.clone()));
+                tokio::spawn(Self::handle_event_si(
+                    event,
+                    db.clone(),
+                    tx_router.clone(),
+                    tx_delivered.clone(),
+                ));
             }
         }
 
@@ -59,9 +95,14 @@ impl EventListener {
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
-    async fn handle_event_si(event: Event, db: DbPool, tx: flume::Sender<EventRouterMsg>) {
+    async fn handle_event_si(
+        event: Event,
+        db: DbPool,
+        tx: flume::Sender<EventRouterMsg>,
+        tx_delivered: flume::Sender<String>,
+    ) {
         // insert into DB
-        if &event.level.value() >= EVENT_PERSIST_LEVEL.get().unwrap() {
+        if !*READ_ONLY_MODE && &event.level.value() >= EVENT_PERSIST_LEVEL.get().unwrap() {
             while let Err(err) = event.insert(&db).await {
                 error!("Inserting Event into Database: {:?}", err);
                 time::sleep(Duration::from_secs(1)).await;
@@ -81,12 +122,14 @@ impl EventListener {
             error!("Sending Event Notification: {:?}", err);
             time::sleep(Duration::from_secs(1)).await;
         }
+
+        Self::delivered(event, &tx_delivered);
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
-    async fn handle_event_ha(event: Event, db: DbPool) {
+    async fn handle_event_ha(event: Event, db: DbPool, tx_delivered: flume::Sender<String>) {
         // insert into DB
-        if &event.level.value() >= EVENT_PERSIST_LEVEL.get().unwrap() {
+        if !*READ_ONLY_MODE && &event.level.value() >= EVENT_PERSIST_LEVEL.get().unwrap() {
             while let Err(err) = event.insert(&db).await {
                 error!("Inserting Event into Database: {:?}", err);
                 time::sleep(Duration::from_secs(1)).await;
@@ -111,6 +154,134 @@ impl EventListener {
             error!("Sending Event Notification: {:?}", err);
             time::sleep(Duration::from_secs(1)).await;
         }
+
+        Self::delivered(event, &tx_delivered);
+    }
+
+    /// Marks the event as delivered to all sinks, which removes it from the outbox.
+    fn delivered(event: Event, tx_delivered: &flume::Sender<String>) {
+        if !*READ_ONLY_MODE {
+            // can only fail if the receiver is gone, which happens on shutdown
+            let _ = tx_delivered.send(event.id);
+        }
+    }
+
+    /// Removes delivered events from the outbox. Most events never go through the outbox,
+    /// which makes the removal a no-op for them. The events are collected for a short time
+    /// and then removed in batches, so this does not cost a query for each single event.
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn outbox_remove(db: DbPool, rx_delivered: flume::Receiver<String>) {
+        debug!("EventListener::outbox_remove has been started");
+
+        let batch_size = OUTBOX_BATCH_SIZE as usize;
+        let mut ids = Vec::with_capacity(batch_size);
+
+        while let Ok(id) = rx_delivered.recv_async().await {
+            ids.push(id);
+            time::sleep(OUTBOX_REMOVE_DELAY).await;
+
+            loop {
+                while ids.len() < batch_size {
+                    match rx_delivered.try_recv() {
+                        Ok(id) => ids.push(id),
+                        Err(_) => break,
+                    }
+                }
+
+                while let Err(err) = Event::outbox_delete_all(&db, &ids).await {
+                    error!("Removing Events from the outbox: {:?}", err);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+                ids.clear();
+
+                if rx_delivered.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Re-delivers events from the outbox, which have been committed, but were never handled,
+    /// for instance because the instance died right after the commit. This guarantees
+    /// at-least-once delivery for these events. Duplicates are possible for the notifiers and
+    /// the live events stream, while the events table ignores them.
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn outbox_drain(
+        db: DbPool,
+        tx_router: flume::Sender<EventRouterMsg>,
+        tx_delivered: flume::Sender<String>,
+    ) {
+        debug!("EventListener::outbox_drain has been started");
+
+        let is_ha = *HA_MODE;
+        let mut interval = time::interval(OUTBOX_INTERVAL);
+
+        loop {
+            // the first tick completes immediately, which drains leftovers from a crash on startup
+            interval.tick().await;
+
+            let created_before = Utc::now().timestamp() - OUTBOX_GRACE_SECS;
+            let entries =
+                match EventOutbox::find_stale(&db, created_before, OUTBOX_BATCH_SIZE).await {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        error!("Looking up the Events outbox: {:?}", err);
+                        continue;
+                    }
+                };
+
+            for mut entry in entries {
+                match entry.claim(&db).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(err) => {
+                        error!("Claiming Event {} from the outbox: {:?}", entry.id, err);
+                        continue;
+                    }
+                }
+
+                let event = match serde_json::from_str::<Event>(&entry.payload) {
+                    Ok(event) => event,
+                    Err(err) => {
+                        error!(
+                            "Cannot deserialize Event {} from the outbox - dropping it: {:?}",
+                            entry.id, err
+                        );
+                        if let Err(err) = Event::outbox_delete(&db, &entry.id).await {
+                            error!("Removing Event from the outbox: {:?}", err);
+                        }
+                        continue;
+                    }
+                };
+
+                warn!("Re-delivering undelivered Event from the outbox: {}", event);
+                if is_ha {
+                    Self::handle_event_ha(event, db.clone(), tx_delivered.clone()).await;
+                } else {
+                    Self::handle_event_si(
+                        event,
+                        db.clone(),
+                        tx_router.clone(),
+                        tx_delivered.clone(),
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// Keeps the event E-Mail recipients in sync, when they have been changed on another instance.
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn recipients_reload(db: DbPool) {
+        debug!("EventListener::recipients_reload has been started");
+
+        let mut interval = time::interval(RECIPIENTS_RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = EventNotifier::reload_email_recipients(&db).await {
+                error!("Reloading the event E-Mail recipients: {:?}", err);
+            }
+        }
     }
 
     #[tracing::instrument(level = "debug", skip_all)]
@@ -164,9 +335,7 @@ impl EventListener {
     ) {
         debug!("EventListener::router_si has been started");
 
-        let mut clients: HashMap<String, (i16, mpsc::Sender<sse::Event>)> =
-            HashMap::with_capacity(4);
-        let mut ips_to_remove = Vec::with_capacity(1);
+        let mut hub = SseHub::default();
         // Event::find_latest returns the latest events ordered by timestamp desc
         let mut events = Event::find_latest(&db, EVENTS_LATEST_LIMIT as i64)
             .await
@@ -228,6 +397,24 @@ impl EventListener {
                         EventType::SecretsMigrated => {}
                         EventType::UserEmailChange => {}
                         EventType::UserPasswordReset => {}
+                        EventType::UserSessionsRevoked => {}
+                        EventType::LoginRestricted => {}
+                        EventType::LoginPolicyOverride => {}
+                        EventType::BreakGlass => {}
+                        EventType::LegalHold => {}
+                        EventType::AdminResetEmail => {}
+                        EventType::AdminResetOtp => {}
+                        EventType::AdminResetSms => {}
+                        EventType::JwkChanged => {}
+                        EventType::MfaRecovery => {}
+                        EventType::ElevatedRole => {}
+                        EventType::ClientIpRejected => {}
+                        EventType::UserMerged => {}
+                        EventType::LoginSloBreach => {}
+                        EventType::AuthCodeReuse => {}
+                        EventType::ClientSecretRead => {}
+                        EventType::UserInactiveDisabled => {}
+                        EventType::EventRecipientsChanged => {}
                         EventType::Test => {}
                     }
 
@@ -236,43 +423,13 @@ impl EventListener {
                     let payload = sse::Event::Data(sse::Data::new(event));
                     let event_level_value = evt.level.value();
 
-                    // send payload to all clients
-                    for (ip, (client_level, tx)) in &clients {
-                        if *client_level > event_level_value {
-                            // skip the event if the client does not want to receive its level
-                            continue;
-                        }
-
-                        match time::timeout(Duration::from_secs(5), tx.send(payload.clone())).await
-                        {
-                            Ok(tx_res) => {
-                                if let Err(err) = tx_res {
-                                    error!(
-                                        "sending event to client {} from event listener - removing client\n{:?}",
-                                        ip, err
-                                    );
-                                    ips_to_remove.push(ip.clone());
-                                }
-                            }
-                            Err(_) => {
-                                error!(
-                                    "Timeout reached sending event to client {} - removing client",
-                                    ip
-                                );
-                                ips_to_remove.push(ip.clone());
-                            }
-                        }
-                    }
+                    hub.broadcast(event_level_value, &payload);
 
                     // keep current events max size and push payload
                     if events.len() > EVENTS_LATEST_LIMIT as usize {
                         events.pop_front();
                     }
                     events.push_back((event_level_value, payload));
-
-                    while let Some(ip) = ips_to_remove.pop() {
-                        clients.remove(&ip);
-                    }
                 }
 
                 EventRouterMsg::ClientReg {
@@ -281,55 +438,27 @@ impl EventListener {
                     latest,
                     level,
                 } => {
-                    info!("New client {} registered for the event listener", ip);
                     let client_level_val = level.value();
 
-                    let mut is_err = false;
-                    if let Some(latest) = latest {
-                        let latest = latest as usize;
-
-                        let events_filtered = events
-                            .iter()
-                            .filter(|(level, _payload)| *level >= client_level_val)
-                            .map(|(_level, payload)| payload)
-                            .collect::<Vec<&sse::Event>>();
-
-                        let evt_len = events_filtered.len();
-                        let skip = if latest > evt_len {
-                            0
-                        } else {
-                            evt_len - latest
-                        };
-
-                        for event in events_filtered.iter().skip(skip) {
-                            match time::timeout(Duration::from_secs(5), tx.send((*event).clone()))
-                                .await
-                            {
-                                Ok(tx_res) => {
-                                    if let Err(err) = tx_res {
-                                        error!(
-                                        "sending latest event to client {} after ClientReg - removing client\n{:?}",
-                                        ip, err
-                                    );
-                                        is_err = true;
-                                        break;
-                                    }
-                                }
-                                Err(_) => {
-                                    error!(
-                                    "Timeout reached sending latest events to client {} - removing client",
-                                    ip
-                                );
-                                    is_err = true;
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                    let events_filtered = events
+                        .iter()
+                        .filter(|(level, _payload)| *level >= client_level_val)
+                        .map(|(_level, payload)| payload)
+                        .collect::<Vec<&sse::Event>>();
+                    let latest = latest.unwrap_or_default() as usize;
+                    let skip = events_filtered.len().saturating_sub(latest);
+
+                    hub.subscribe(
+                        ip,
+                        client_level_val,
+                        tx,
+                        events_filtered.into_iter().skip(skip),
+                    );
+                }
 
-                    if !is_err {
-                        clients.insert(ip, (client_level_val, tx));
-                    }
+                EventRouterMsg::Prune(tx) => {
+                    hub.prune();
+                    let _ = tx.send(()).await;
                 }
             }
         }
@@ -4,16 +4,34 @@ use std::env;
 use std::sync::OnceLock;
 use tracing::info;
 
+pub mod chain;
 pub mod event;
 pub mod health_watch;
 pub mod ip_blacklist_handler;
 pub mod listener;
 pub mod notifier;
+pub mod sse_hub;
 
 pub static EVENT_PERSIST_LEVEL: OnceLock<i16> = OnceLock::new();
 pub static EVENT_LEVEL_NEW_USER: OnceLock<EventLevel> = OnceLock::new();
 pub static EVENT_LEVEL_USER_EMAIL_CHANGE: OnceLock<EventLevel> = OnceLock::new();
 pub static EVENT_LEVEL_USER_PASSWORD_RESET: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_USER_SESSIONS_REVOKED: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_LOGIN_RESTRICTED: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_LOGIN_POLICY_OVERRIDE: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_BREAK_GLASS: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_LEGAL_HOLD: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_MFA_RECOVERY: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_ELEVATED_ROLE: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_CLIENT_IP_REJECTED: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_USER_MERGED: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_LOGIN_SLO_BREACH: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_AUTH_CODE_REUSE: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_CLIENT_SECRET_READ: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_USER_INACTIVE_DISABLED: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_EVENT_RECIPIENTS: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_ADMIN_RESET: OnceLock<EventLevel> = OnceLock::new();
+pub static EVENT_LEVEL_ADMIN_RESET_OTP: OnceLock<EventLevel> = OnceLock::new();
 pub static EVENT_LEVEL_NEW_RAUTHY_ADMIN: OnceLock<EventLevel> = OnceLock::new();
 pub static EVENT_LEVEL_NEW_RAUTHY_VERSION: OnceLock<EventLevel> = OnceLock::new();
 pub static EVENT_LEVEL_JWKS_ROTATE: OnceLock<EventLevel> = OnceLock::new();
@@ -49,6 +67,102 @@ pub fn init_event_vars() -> Result<(), ErrorResponse> {
             EventLevel::Notice,
         ))
         .unwrap();
+    EVENT_LEVEL_USER_SESSIONS_REVOKED
+        .set(map_env_var_level(
+            "EVENT_LEVEL_USER_SESSIONS_REVOKED",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_LOGIN_RESTRICTED
+        .set(map_env_var_level(
+            "EVENT_LEVEL_LOGIN_RESTRICTED",
+            EventLevel::Info,
+        ))
+        .unwrap();
+    EVENT_LEVEL_LOGIN_POLICY_OVERRIDE
+        .set(map_env_var_level(
+            "EVENT_LEVEL_LOGIN_POLICY_OVERRIDE",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_BREAK_GLASS
+        .set(map_env_var_level(
+            "EVENT_LEVEL_BREAK_GLASS",
+            EventLevel::Critical,
+        ))
+        .unwrap();
+    EVENT_LEVEL_LEGAL_HOLD
+        .set(map_env_var_level(
+            "EVENT_LEVEL_LEGAL_HOLD",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_MFA_RECOVERY
+        .set(map_env_var_level(
+            "EVENT_LEVEL_MFA_RECOVERY",
+            EventLevel::Warning,
+        ))
+        .unwrap();
+    EVENT_LEVEL_ELEVATED_ROLE
+        .set(map_env_var_level(
+            "EVENT_LEVEL_ELEVATED_ROLE",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_CLIENT_IP_REJECTED
+        .set(map_env_var_level(
+            "EVENT_LEVEL_CLIENT_IP_REJECTED",
+            EventLevel::Warning,
+        ))
+        .unwrap();
+    EVENT_LEVEL_USER_MERGED
+        .set(map_env_var_level(
+            "EVENT_LEVEL_USER_MERGED",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_LOGIN_SLO_BREACH
+        .set(map_env_var_level(
+            "EVENT_LEVEL_LOGIN_SLO_BREACH",
+            EventLevel::Warning,
+        ))
+        .unwrap();
+    EVENT_LEVEL_AUTH_CODE_REUSE
+        .set(map_env_var_level(
+            "EVENT_LEVEL_AUTH_CODE_REUSE",
+            EventLevel::Critical,
+        ))
+        .unwrap();
+    EVENT_LEVEL_CLIENT_SECRET_READ
+        .set(map_env_var_level(
+            "EVENT_LEVEL_CLIENT_SECRET_READ",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_USER_INACTIVE_DISABLED
+        .set(map_env_var_level(
+            "EVENT_LEVEL_USER_INACTIVE_DISABLED",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_EVENT_RECIPIENTS
+        .set(map_env_var_level(
+            "EVENT_LEVEL_EVENT_RECIPIENTS",
+            EventLevel::Warning,
+        ))
+        .unwrap();
+    EVENT_LEVEL_ADMIN_RESET
+        .set(map_env_var_level(
+            "EVENT_LEVEL_ADMIN_RESET",
+            EventLevel::Notice,
+        ))
+        .unwrap();
+    EVENT_LEVEL_ADMIN_RESET_OTP
+        .set(map_env_var_level(
+            "EVENT_LEVEL_ADMIN_RESET_OTP",
+            EventLevel::Warning,
+        ))
+        .unwrap();
     EVENT_LEVEL_NEW_RAUTHY_ADMIN
         .set(map_env_var_level(
             "EVENT_LEVEL_RAUTHY_ADMIN",
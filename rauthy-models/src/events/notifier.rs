@@ -1,16 +1,21 @@
+use crate::app_state::DbPool;
 use crate::email;
 use crate::email::EMail;
+use crate::entity::event_recipients::EventRecipient;
 use crate::events::event::{Event, EventLevel, EventType};
 use async_trait::async_trait;
+use chrono::Utc;
 use rauthy_common::error_response::ErrorResponse;
 use rauthy_notify::matrix::NotifierMatrix;
 use rauthy_notify::slack::NotifierSlack;
 use rauthy_notify::{Notification, Notify};
 use std::env;
-use std::sync::OnceLock;
+use std::sync::{OnceLock, RwLock};
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
+static TX_EMAIL: OnceLock<mpsc::Sender<EMail>> = OnceLock::new();
+static EMAIL_RECIPIENTS: RwLock<Vec<EventRecipient>> = RwLock::new(Vec::new());
 static NOTIFIER_EMAIL: OnceLock<(i16, NotifierEmail)> = OnceLock::new();
 static NOTIFIER_MATRIX: OnceLock<(i16, NotifierMatrix)> = OnceLock::new();
 static NOTIFIER_SLACK: OnceLock<(i16, NotifierSlack)> = OnceLock::new();
@@ -39,6 +44,8 @@ impl EventNotifier {
             }
         }
 
+        Self::send_email_recipients(event, &notification).await;
+
         if let Some((level, notifier)) = NOTIFIER_MATRIX.get() {
             if event.typ == EventType::Test || &event.level.value() >= level {
                 if let Err(err) = notifier.notify(&notification).await {
@@ -60,7 +67,74 @@ impl EventNotifier {
         Ok(())
     }
 
+    async fn send_email_recipients(event: &Event, notification: &Notification) {
+        let Some(tx_email) = TX_EMAIL.get() else {
+            return;
+        };
+
+        let now = Utc::now();
+        let is_test = event.typ == EventType::Test;
+        let addresses = EMAIL_RECIPIENTS
+            .read()
+            .expect("event recipients lock to never be poisoned")
+            .iter()
+            .filter(|r| r.wants(&event.level, is_test, now))
+            .map(|r| r.email.clone())
+            .collect::<Vec<_>>();
+
+        for address in addresses {
+            email::send_email_notification(address, tx_email, notification).await;
+        }
+    }
+
+    /// Replaces the runtime E-Mail recipients, which are used for all following events.
+    pub fn set_email_recipients(recipients: Vec<EventRecipient>) {
+        debug!("Applying {} event E-Mail recipients", recipients.len());
+        *EMAIL_RECIPIENTS
+            .write()
+            .expect("event recipients lock to never be poisoned") = recipients;
+    }
+
+    /// Reloads the runtime E-Mail recipients from the database.
+    pub async fn reload_email_recipients(db: &DbPool) -> Result<(), ErrorResponse> {
+        let recipients = EventRecipient::find_all(db).await?;
+        let is_changed = *EMAIL_RECIPIENTS
+            .read()
+            .expect("event recipients lock to never be poisoned")
+            != recipients;
+        if is_changed {
+            Self::set_email_recipients(recipients);
+        }
+        Ok(())
+    }
+
+    /// Returns the names and minimum levels of all configured notification sinks.
+    pub fn configured_sinks() -> Vec<(&'static str, EventLevel)> {
+        let mut res = Vec::with_capacity(3);
+        if let Some((level, _)) = NOTIFIER_EMAIL.get() {
+            res.push(("email", EventLevel::from(*level)));
+        }
+        for recipient in EMAIL_RECIPIENTS
+            .read()
+            .expect("event recipients lock to never be poisoned")
+            .iter()
+        {
+            res.push(("email", EventLevel::from(recipient.level)));
+        }
+        if let Some((level, _)) = NOTIFIER_MATRIX.get() {
+            res.push(("matrix", EventLevel::from(*level)));
+        }
+        if let Some((level, _)) = NOTIFIER_SLACK.get() {
+            res.push(("slack", EventLevel::from(*level)));
+        }
+        res
+    }
+
     pub async fn init_notifiers(tx_email: mpsc::Sender<EMail>) -> Result<(), ErrorResponse> {
+        TX_EMAIL
+            .set(tx_email.clone())
+            .expect("init_notifiers should only be called once");
+
         // E-Mail
         if let Ok(email) = env::var("EVENT_EMAIL") {
             let level = env::var("EVENT_NOTIFY_LEVEL_EMAIL")
@@ -0,0 +1,272 @@
+use actix_web_lab::sse;
+use once_cell::sync::Lazy;
+use prometheus::{IntCounter, IntGauge, Registry};
+use rauthy_common::constants::{SSE_CLIENT_MAX_DROPS, SSE_MAX_CLIENTS};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{error, info, warn};
+
+/// The amount of currently registered subscribers, readable outside the router.
+static SUBSCRIBERS: AtomicUsize = AtomicUsize::new(0);
+
+static METRIC_SUBSCRIBERS: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "rauthy_sse_subscribers",
+        "The amount of currently connected events stream subscribers",
+    )
+    .expect("Cannot build rauthy_sse_subscribers")
+});
+static METRIC_SENT: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "rauthy_sse_events_sent_total",
+        "Events queued for events stream subscribers",
+    )
+    .expect("Cannot build rauthy_sse_events_sent_total")
+});
+static METRIC_DROPPED: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "rauthy_sse_events_dropped_total",
+        "Events dropped because the buffer of a subscriber was full",
+    )
+    .expect("Cannot build rauthy_sse_events_dropped_total")
+});
+static METRIC_EVICTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "rauthy_sse_evictions_total",
+        "Events stream subscribers disconnected for being too slow",
+    )
+    .expect("Cannot build rauthy_sse_evictions_total")
+});
+
+/// Registers the events stream metrics with the given Prometheus registry.
+pub fn register(registry: &Registry) {
+    let metrics: [Box<dyn prometheus::core::Collector>; 4] = [
+        Box::new(METRIC_SUBSCRIBERS.clone()),
+        Box::new(METRIC_SENT.clone()),
+        Box::new(METRIC_DROPPED.clone()),
+        Box::new(METRIC_EVICTIONS.clone()),
+    ];
+    for metric in metrics {
+        if let Err(err) = registry.register(metric) {
+            error!("Cannot register events stream metrics: {}", err);
+        }
+    }
+}
+
+/// Returns `true` if no more subscribers are accepted.
+pub fn is_full() -> bool {
+    SUBSCRIBERS.load(Ordering::Relaxed) >= *SSE_MAX_CLIENTS
+}
+
+struct Subscriber {
+    ip: String,
+    level: i16,
+    tx: mpsc::Sender<sse::Event>,
+    /// consecutive events, which did not fit into the buffer
+    drops: u32,
+}
+
+enum Offer {
+    Sent,
+    Dropped,
+    Gone,
+}
+
+impl Subscriber {
+    fn offer(&mut self, payload: &sse::Event) -> Offer {
+        match self.tx.try_send(payload.clone()) {
+            Ok(_) => {
+                METRIC_SENT.inc();
+                self.drops = 0;
+                Offer::Sent
+            }
+            Err(TrySendError::Full(_)) => {
+                METRIC_DROPPED.inc();
+                self.drops += 1;
+                Offer::Dropped
+            }
+            Err(TrySendError::Closed(_)) => Offer::Gone,
+        }
+    }
+}
+
+/// Fans out live events to all SSE subscribers.
+///
+/// Each subscriber has its own bounded buffer and events are never awaited, so a single slow
+/// client cannot block the others. If an event does not fit into a buffer, it is dropped for
+/// this subscriber. After too many consecutive drops, the subscriber is evicted. Its stream ends
+/// and the browser will reconnect and replay the latest events.
+pub struct SseHub {
+    subscribers: HashMap<u64, Subscriber>,
+    next_id: u64,
+    max_drops: u32,
+}
+
+impl Default for SseHub {
+    fn default() -> Self {
+        Self::new(*SSE_CLIENT_MAX_DROPS)
+    }
+}
+
+impl SseHub {
+    pub fn new(max_drops: u32) -> Self {
+        Self {
+            subscribers: HashMap::with_capacity(4),
+            next_id: 0,
+            max_drops,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Adds a new subscriber and sends the given latest events to it first. These are expected to
+    /// be already filtered by the level of the subscriber.
+    pub fn subscribe<'a>(
+        &mut self,
+        ip: String,
+        level: i16,
+        tx: mpsc::Sender<sse::Event>,
+        latest: impl Iterator<Item = &'a sse::Event>,
+    ) {
+        self.prune();
+
+        let mut sub = Subscriber {
+            ip,
+            level,
+            tx,
+            drops: 0,
+        };
+        for payload in latest {
+            match sub.offer(payload) {
+                Offer::Sent => {}
+                Offer::Dropped => break,
+                Offer::Gone => return,
+            }
+        }
+
+        info!("New client {} registered for the event listener", sub.ip);
+        self.subscribers.insert(self.next_id, sub);
+        self.next_id += 1;
+        self.update_count();
+    }
+
+    /// Queues the payload for each subscriber, which wants events of this level.
+    pub fn broadcast(&mut self, level: i16, payload: &sse::Event) {
+        let max_drops = self.max_drops;
+        self.subscribers.retain(|_, sub| {
+            if sub.level > level {
+                return true;
+            }
+
+            match sub.offer(payload) {
+                Offer::Sent => true,
+                Offer::Dropped if sub.drops > max_drops => {
+                    warn!(
+                        "Events stream client {} is too slow - dropped {} events in a row, evicting",
+                        sub.ip, sub.drops
+                    );
+                    METRIC_EVICTIONS.inc();
+                    false
+                }
+                Offer::Dropped => true,
+                Offer::Gone => {
+                    info!("Events stream client {} disconnected", sub.ip);
+                    false
+                }
+            }
+        });
+        self.update_count();
+    }
+
+    /// Removes all subscribers, whose stream has been closed. Without this, they would only be
+    /// noticed with the next broadcast and still count towards the max clients until then.
+    pub fn prune(&mut self) {
+        self.subscribers.retain(|_, sub| {
+            if sub.tx.is_closed() {
+                info!("Events stream client {} disconnected", sub.ip);
+                false
+            } else {
+                true
+            }
+        });
+        self.update_count();
+    }
+
+    #[inline]
+    fn update_count(&self) {
+        SUBSCRIBERS.store(self.subscribers.len(), Ordering::Relaxed);
+        METRIC_SUBSCRIBERS.set(self.subscribers.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(i: usize) -> sse::Event {
+        sse::Event::Data(sse::Data::new(i.to_string()))
+    }
+
+    #[test]
+    fn test_sse_hub_slow_consumer() {
+        let mut hub = SseHub::new(2);
+
+        let (tx_fast, mut rx_fast) = mpsc::channel(2);
+        let (tx_slow, _rx_slow) = mpsc::channel(2);
+        let (tx_warn, mut rx_warn) = mpsc::channel(2);
+        let latest = [payload(0)];
+        hub.subscribe("fast".to_string(), 1, tx_fast, latest.iter());
+        hub.subscribe("slow".to_string(), 1, tx_slow, latest.iter());
+        hub.subscribe("warn".to_string(), 3, tx_warn, [].iter());
+        assert_eq!(hub.len(), 3);
+
+        for i in 1..=4 {
+            hub.broadcast(1, &payload(i));
+            while rx_fast.try_recv().is_ok() {}
+        }
+        // 1 replayed + 1 queued, then 3 drops in a row with max 2
+        assert_eq!(hub.len(), 2);
+
+        // the level filter must not count as drops
+        assert!(rx_warn.try_recv().is_err());
+        hub.broadcast(3, &payload(5));
+        assert!(rx_fast.try_recv().is_ok());
+        assert!(rx_warn.try_recv().is_ok());
+
+        drop(rx_fast);
+        hub.broadcast(3, &payload(6));
+        assert_eq!(hub.len(), 1);
+    }
+
+    #[test]
+    fn test_sse_hub_prune() {
+        let mut hub = SseHub::new(2);
+
+        let (tx_gone, rx_gone) = mpsc::channel(2);
+        let (tx_alive, _rx_alive) = mpsc::channel(2);
+        hub.subscribe("gone".to_string(), 1, tx_gone, [].iter());
+        hub.subscribe("alive".to_string(), 1, tx_alive, [].iter());
+        assert_eq!(hub.len(), 2);
+
+        // closed streams are removed without any broadcast
+        drop(rx_gone);
+        hub.prune();
+        assert_eq!(hub.len(), 1);
+
+        // and before each new subscriber
+        let (tx_new, rx_new) = mpsc::channel(2);
+        hub.subscribe("new".to_string(), 1, tx_new, [].iter());
+        drop(rx_new);
+        let (tx_last, _rx_last) = mpsc::channel(2);
+        hub.subscribe("last".to_string(), 1, tx_last, [].iter());
+        assert_eq!(hub.len(), 2);
+    }
+}
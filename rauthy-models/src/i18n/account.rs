@@ -306,6 +306,13 @@ pub struct I18nAccountMfa<'a> {
     registerd_keys: &'a str,
     passkey_name: &'a str,
     passkey_name_err: &'a str,
+    push_desc: &'a str,
+    push_device_name: &'a str,
+    push_devices: &'a str,
+    push_err_permission: &'a str,
+    push_err_unsupported: &'a str,
+    push_register: &'a str,
+    push_title: &'a str,
     test: &'a str,
     test_error: &'a str,
     test_success: &'a str,
@@ -344,6 +351,14 @@ impl I18nAccountMfa<'_> {
             registerd_keys: "Registered Keys",
             passkey_name: "Passkey Name",
             passkey_name_err: "2 - 32 non-special characters",
+            push_desc: "Approve logins with a notification on this device. Your other MFA \
+            factors can always be used as a fallback.",
+            push_device_name: "Device Name",
+            push_devices: "Devices",
+            push_err_permission: "Notifications have been blocked for this site",
+            push_err_unsupported: "This browser does not support push notifications",
+            push_register: "Enable on this device",
+            push_title: "Push Approvals",
             test: "Test",
             test_error: "Error starting the Test",
             test_success: "Test successful",
@@ -369,6 +384,14 @@ impl I18nAccountMfa<'_> {
             registerd_keys: "Registrierte Keys",
             passkey_name: "Passkey Name",
             passkey_name_err: "2 - 32 Buchstaben, keine Sonderzeichen",
+            push_desc: "Logins mit einer Benachrichtigung auf diesem Gerät bestätigen. Ihre \
+            anderen MFA Faktoren können immer als Alternative genutzt werden.",
+            push_device_name: "Gerätename",
+            push_devices: "Geräte",
+            push_err_permission: "Benachrichtigungen wurden für diese Seite blockiert",
+            push_err_unsupported: "Dieser Browser unterstützt keine Push Benachrichtigungen",
+            push_register: "Auf diesem Gerät aktivieren",
+            push_title: "Push Bestätigungen",
             test: "Test",
             test_error: "Fehler beim Starten des Tests",
             test_success: "Test erfolgreich",
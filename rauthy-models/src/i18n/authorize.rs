@@ -5,9 +5,13 @@ use serde::Serialize;
 #[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct I18nAuthorize<'a> {
+    attrs_required: &'a str,
     client_force_mfa: &'a str,
     email: &'a str,
     email_bad_format: &'a str,
+    email_mfa_code: &'a str,
+    email_mfa_sent: &'a str,
+    email_or_username: &'a str,
     email_required: &'a str,
     email_sent_msg: &'a str,
     http_429: &'a str,
@@ -20,7 +24,11 @@ pub struct I18nAuthorize<'a> {
     password_request: &'a str,
     password_required: &'a str,
     provide_mfa: &'a str,
+    push_mfa_denied: &'a str,
+    push_mfa_fallback: &'a str,
+    push_mfa_sent: &'a str,
     request_expires: &'a str,
+    scopes_requested: &'a str,
     sign_up: &'a str,
 }
 
@@ -40,10 +48,14 @@ impl SsrJson for I18nAuthorize<'_> {
 impl I18nAuthorize<'_> {
     fn build_en() -> Self {
         Self {
+            attrs_required: "Please complete your profile to continue",
             client_force_mfa: r#"This login forces MFA to achieve higher security.
 To get access, you need to log in to your account and add at least one additional Passkey"#,
             email: "E-Mail",
             email_bad_format: "Bad E-Mail format",
+            email_mfa_code: "E-Mail Code",
+            email_mfa_sent: "A one-time code has been sent to your E-Mail address",
+            email_or_username: "E-Mail / Username",
             email_required: "E-Mail is required",
             email_sent_msg: "If your E-Mail exists, a request has been sent",
             http_429: "Too many invalid inputs. Locked until:",
@@ -56,18 +68,27 @@ To get access, you need to log in to your account and add at least one additiona
             password_request: "Request",
             password_required: "Password is required",
             provide_mfa: "Please login with your MFA device",
+            push_mfa_denied: "The login has been denied",
+            push_mfa_fallback: "Use another method",
+            push_mfa_sent:
+                "A login request has been sent to your devices. Enter this number to approve it:",
             request_expires: "Request expires",
+            scopes_requested: "This application requests access to",
             sign_up: "User Registration",
         }
     }
 
     fn build_de() -> Self {
         Self {
+            attrs_required: "Bitte vervollständigen Sie Ihr Profil, um fortzufahren",
             client_force_mfa: r#"Dieser Login setzt MFA voraus für eine erhöhte Sicherheit.
 Um Zugang zu bekommen, müssen Sie sie in Ihren Account einloggen und mindestens einen Passkey
 hinzufügen."#,
             email: "E-Mail",
             email_bad_format: "Inkorrektes E-Mail Format",
+            email_mfa_code: "E-Mail Code",
+            email_mfa_sent: "Ein Einmal-Code wurde an Ihre E-Mail Adresse gesendet",
+            email_or_username: "E-Mail / Benutzername",
             email_required: "E-Mail ist notwendig",
             email_sent_msg: "Sollte Ihre Adresse registriert sein, wurde eine Nachricht versandt",
             http_429: "Zu viele ungültige Versuche. Gesperrt bis:",
@@ -80,7 +101,11 @@ hinzufügen."#,
             password_request: "Anfordern",
             password_required: "Password ist notwendig",
             provide_mfa: "Bitte stellen Sie Ihr MFA Gerät zur Verfügung",
+            push_mfa_denied: "Der Login wurde abgelehnt",
+            push_mfa_fallback: "Andere Methode verwenden",
+            push_mfa_sent: "Eine Login Anfrage wurde an Ihre Geräte gesendet. Geben Sie zur Bestätigung diese Zahl ein:",
             request_expires: "Anfrage läuft ab",
+            scopes_requested: "Diese Anwendung fordert Zugriff auf",
             sign_up: "Benutzer Registrierung",
         }
     }
@@ -0,0 +1,44 @@
+use crate::i18n::SsrJson;
+use crate::language::Language;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct I18nEmailMfa<'a> {
+    pub subject: &'a str,
+    pub code: &'a str,
+    pub expires: &'a str,
+    pub hint: &'a str,
+}
+
+impl SsrJson for I18nEmailMfa<'_> {
+    fn build(lang: &Language) -> Self {
+        match lang {
+            Language::En => Self::build_en(),
+            Language::De => Self::build_de(),
+        }
+    }
+
+    fn as_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl I18nEmailMfa<'_> {
+    fn build_en() -> Self {
+        Self {
+            subject: "Login code",
+            code: "Your one-time login code:",
+            expires: "The code expires in minutes:",
+            hint: "If this was not you, someone knows your password. Change it immediately.",
+        }
+    }
+
+    fn build_de() -> Self {
+        Self {
+            subject: "Login Code",
+            code: "Ihr einmaliger Login Code:",
+            expires: "Der Code läuft ab in Minuten:",
+            hint: "Falls Sie das nicht waren, kennt jemand Ihr Passwort. Ändern Sie es umgehend.",
+        }
+    }
+}
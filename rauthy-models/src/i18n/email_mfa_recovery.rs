@@ -0,0 +1,57 @@
+use crate::i18n::SsrJson;
+use crate::language::Language;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct I18nEmailMfaRecovery<'a> {
+    pub subject: &'a str,
+    pub requested: &'a str,
+    pub requested_hint: &'a str,
+    pub waiting: &'a str,
+    pub waiting_hint: &'a str,
+}
+
+impl SsrJson for I18nEmailMfaRecovery<'_> {
+    fn build(lang: &Language) -> Self {
+        match lang {
+            Language::En => Self::build_en(),
+            Language::De => Self::build_de(),
+        }
+    }
+
+    fn as_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl I18nEmailMfaRecovery<'_> {
+    fn build_en() -> Self {
+        Self {
+            subject: "Account recovery",
+            requested: "A recovery for your account has been requested. Confirm it with the \
+            following code:",
+            requested_hint: "If this was not you, you can just ignore this E-Mail.",
+            waiting: "The account recovery has been confirmed. When the waiting period is over, \
+            all passkeys and the password will be removed from your account and you will be able \
+            to register new ones. Earliest time:",
+            waiting_hint: "If this was not you, cancel the recovery immediately with the code \
+            from the previous E-Mail or inside your account, and contact your administrator.",
+        }
+    }
+
+    fn build_de() -> Self {
+        Self {
+            subject: "Kontowiederherstellung",
+            requested: "Für Ihr Konto wurde eine Wiederherstellung angefordert. Bestätigen Sie \
+            diese mit dem folgenden Code:",
+            requested_hint: "Falls Sie das nicht waren, können Sie diese E-Mail einfach \
+            ignorieren.",
+            waiting: "Die Kontowiederherstellung wurde bestätigt. Nach Ablauf der Wartezeit \
+            werden alle Passkeys und das Passwort von Ihrem Konto entfernt und Sie können neue \
+            registrieren. Frühester Zeitpunkt:",
+            waiting_hint: "Falls Sie das nicht waren, brechen Sie die Wiederherstellung umgehend \
+            mit dem Code aus der vorherigen E-Mail oder in Ihrem Konto ab und kontaktieren Sie \
+            Ihren Administrator.",
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::i18n::SsrJson;
+use crate::language::Language;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct I18nEmailSessionsRevoked<'a> {
+    pub subject: &'a str,
+    pub password: &'a str,
+    pub passkey: &'a str,
+    pub passkey_reset: &'a str,
+    pub hint: &'a str,
+}
+
+impl SsrJson for I18nEmailSessionsRevoked<'_> {
+    fn build(lang: &Language) -> Self {
+        match lang {
+            Language::En => Self::build_en(),
+            Language::De => Self::build_de(),
+        }
+    }
+
+    fn as_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl I18nEmailSessionsRevoked<'_> {
+    fn build_en() -> Self {
+        Self {
+            subject: "Sessions revoked",
+            password: "The password for your account has been changed.",
+            passkey: "A passkey has been removed from your account.",
+            passkey_reset: "All passkeys have been replaced by a new one via E-Mail recovery.",
+            hint: "All other sessions have been logged out. If this was not you, please contact \
+            your administrator immediately.",
+        }
+    }
+
+    fn build_de() -> Self {
+        Self {
+            subject: "Sitzungen beendet",
+            password: "Das Passwort für Ihr Konto wurde geändert.",
+            passkey: "Ein Passkey wurde von Ihrem Konto entfernt.",
+            passkey_reset: "Alle Passkeys wurden über die E-Mail Wiederherstellung durch einen \
+            neuen ersetzt.",
+            hint: "Alle anderen Sitzungen wurden abgemeldet. Falls Sie das nicht waren, \
+            kontaktieren Sie bitte umgehend Ihren Administrator.",
+        }
+    }
+}
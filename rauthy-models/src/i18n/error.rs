@@ -1,7 +1,11 @@
 use crate::i18n::SsrJson;
 use crate::language::Language;
 use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use rauthy_common::error_response::{ErrorCode, ErrorResponse};
+use rauthy_common::utils::get_rand;
 use serde::Serialize;
+use tracing::info;
 
 #[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -10,6 +14,16 @@ pub struct I18nError<'a> {
     error_text: String,
     details: &'a str,
     details_text: Option<String>,
+    correlation: &'a str,
+    correlation_id: Option<String>,
+}
+
+/// Creates a new correlation ID for an error, which is shown to the user, and logs it together
+/// with the error. This makes it possible to find the exact error from a user report.
+pub fn new_correlation_id(code: ErrorCode, message: &str) -> String {
+    let correlation_id = get_rand(12);
+    info!(correlation_id, code = code.as_str(), "{}", message);
+    correlation_id
 }
 
 impl I18nError<'_> {
@@ -23,6 +37,21 @@ impl I18nError<'_> {
             Language::De => Self::build_de(status_code, details_text),
         }
     }
+
+    /// Builds the translation for an error page with a more specific text for the well-known
+    /// errors and a correlation ID.
+    pub fn build_for_err(lang: &Language, err: &ErrorResponse) -> Self {
+        let mut slf = Self::build_with(lang, err.status_code(), Some(err.message.clone()));
+        let text = match lang {
+            Language::En => Self::code_text_en(err.code),
+            Language::De => Self::code_text_de(err.code),
+        };
+        if let Some(text) = text {
+            slf.error_text = text.to_string();
+        }
+        slf.correlation_id = Some(new_correlation_id(err.code, &err.message));
+        slf
+    }
 }
 
 impl SsrJson for I18nError<'_> {
@@ -56,6 +85,33 @@ impl I18nError<'_> {
             error_text,
             details: "Show Details",
             details_text,
+            correlation: "Correlation ID",
+            correlation_id: None,
+        }
+    }
+
+    fn code_text_en(code: ErrorCode) -> Option<&'static str> {
+        match code {
+            ErrorCode::RedirectUriMismatch => Some(
+                "The application sent an invalid redirect address. Please contact its operator.",
+            ),
+            ErrorCode::GrantExpired => {
+                Some("This link or code has expired or has already been used. Please start again.")
+            }
+            ErrorCode::ClientDisabled => Some("This application has been disabled."),
+            ErrorCode::InvalidTarget => Some(
+                "The application requested access to an unknown resource. Please contact its operator.",
+            ),
+            ErrorCode::InvalidAuthorizationDetails => Some(
+                "The application requested permissions, which it is not allowed to ask for. Please contact its operator.",
+            ),
+            ErrorCode::UserDisabled => {
+                Some("Your account has been disabled. Please contact your administrator.")
+            }
+            ErrorCode::TooManyRequests => {
+                Some("Your IP has been blocked temporarily because of too many requests.")
+            }
+            _ => None,
         }
     }
 
@@ -72,6 +128,71 @@ impl I18nError<'_> {
             error_text,
             details: "Details Anzeigen",
             details_text,
+            correlation: "Korrelations-ID",
+            correlation_id: None,
+        }
+    }
+
+    fn code_text_de(code: ErrorCode) -> Option<&'static str> {
+        match code {
+            ErrorCode::RedirectUriMismatch => Some(
+                "Die Anwendung hat eine ungültige Weiterleitungsadresse gesendet. Bitte kontaktieren Sie den Betreiber.",
+            ),
+            ErrorCode::GrantExpired => Some(
+                "Dieser Link oder Code ist abgelaufen oder wurde bereits verwendet. Bitte beginnen Sie erneut.",
+            ),
+            ErrorCode::ClientDisabled => Some("Diese Anwendung wurde deaktiviert."),
+            ErrorCode::InvalidTarget => Some(
+                "Die Anwendung hat Zugriff auf eine unbekannte Ressource angefordert. Bitte kontaktieren Sie den Betreiber.",
+            ),
+            ErrorCode::InvalidAuthorizationDetails => Some(
+                "Die Anwendung hat Berechtigungen angefordert, die ihr nicht erlaubt sind. Bitte kontaktieren Sie den Betreiber.",
+            ),
+            ErrorCode::UserDisabled => Some(
+                "Ihr Konto wurde deaktiviert. Bitte kontaktieren Sie Ihren Administrator.",
+            ),
+            ErrorCode::TooManyRequests => Some(
+                "Ihre IP wurde wegen zu vieler Anfragen vorübergehend gesperrt.",
+            ),
+            _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rauthy_common::error_response::ErrorResponseType;
+
+    #[test]
+    fn test_build_for_err() {
+        let err = ErrorResponse::new(ErrorResponseType::BadRequest, "expired".to_string())
+            .with_code(ErrorCode::GrantExpired);
+        let i18n = I18nError::build_for_err(&Language::En, &err);
+        assert_eq!(
+            i18n.error_text,
+            I18nError::code_text_en(ErrorCode::GrantExpired).unwrap()
+        );
+        assert_eq!(i18n.details_text.as_deref(), Some("expired"));
+        assert_eq!(i18n.correlation_id.as_ref().unwrap().len(), 12);
+
+        let i18n = I18nError::build_for_err(&Language::De, &err);
+        assert_eq!(
+            i18n.error_text,
+            I18nError::code_text_de(ErrorCode::GrantExpired).unwrap()
+        );
+
+        // codes without a specific text keep the one for the status code
+        let err = ErrorResponse::new(ErrorResponseType::NotFound, "unknown".to_string());
+        let i18n = I18nError::build_for_err(&Language::En, &err);
+        assert_eq!(
+            i18n.error_text,
+            I18nError::build_en(StatusCode::NOT_FOUND, None).error_text
+        );
+        assert!(i18n.correlation_id.is_some());
+
+        // every correlation ID is unique
+        let other = I18nError::build_for_err(&Language::En, &err);
+        assert_ne!(i18n.correlation_id, other.correlation_id);
+    }
+}
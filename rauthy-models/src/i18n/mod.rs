@@ -7,14 +7,18 @@ pub mod email_change_info_new;
 pub mod email_change_info_old;
 pub mod email_confirm_change;
 pub mod email_confirm_change_html;
+pub mod email_mfa;
+pub mod email_mfa_recovery;
 pub mod email_password_new;
 pub mod email_reset;
 pub mod email_reset_info;
+pub mod email_sessions_revoked;
 pub mod error;
 pub mod index;
 pub mod logout;
 pub mod password_policy;
 pub mod password_reset;
+pub mod push_mfa;
 pub mod register;
 
 pub trait SsrJson {
@@ -11,12 +11,15 @@ pub struct I18nPasswordReset<'a> {
 
     account_login: &'a str,
     bad_format: &'a str,
+    email: &'a str,
     fido_link: &'a str,
     generate: &'a str,
     mfa: I18nAccountMfa<'a>,
     new_acc_desc_1: &'a str,
     new_acc_desc_2: &'a str,
     new_account: &'a str,
+    passkey_reset: &'a str,
+    passkey_reset_desc: &'a str,
     password_reset: &'a str,
     password: &'a str,
     passwordless: &'a str,
@@ -54,6 +57,7 @@ impl I18nPasswordReset<'_> {
 
             account_login: "Account Login",
             bad_format: "Bad Format",
+            email: "E-Mail",
             fido_link: "https://fidoalliance.org/fido2",
             generate: "Generate",
             mfa: I18nAccountMfa::build_en(),
@@ -64,6 +68,9 @@ a way stronger security. You will need at least one passkey (Yubikey, Apple Touc
 ...) to create such an account. Your device must embreace the Fido2 standard. For more information
 about this, you may follow this link: "#,
             new_account: "New Account",
+            passkey_reset: "Passkey Reset",
+            passkey_reset_desc: r#"Your account does not have a password. Instead, you can register a
+new passkey. All of your existing passkeys will be removed."#,
             password_reset: "Password Reset",
             password: "Password",
             passwordless: "FIDO Passkey",
@@ -76,8 +83,8 @@ about this, you may follow this link: "#,
             success_3: "If you are not being redirected, please click here:",
             success_passkey_1: "Your new passkey has been registered successfully.",
             success_passkey_2: r#"Please log into your account and register a second backup key as
-soon as possible. With a passkey only account, you wil not be able to use a password reset via
-E-Mail in case you lose your current key."#,
+soon as possible. If you lose all of your keys, a reset via E-Mail will remove them and you will
+need to register a new one."#,
         }
     }
 
@@ -87,6 +94,7 @@ E-Mail in case you lose your current key."#,
 
             account_login: "Account Login",
             bad_format: "Ungültiges Format",
+            email: "E-Mail",
             fido_link: "https://fidoalliance.org/fido2",
             generate: "Generieren",
             mfa: I18nAccountMfa::build_de(),
@@ -98,6 +106,9 @@ und schnelleren Login.
 Dazu wird mindestens ein Passkey (Yubikey, Apple Touch ID, Windows Hello, ...) benötigt, welcher dem
 FIDO2 Standard gerecht wird. Für weitere Informationen können Sie diesem Link folgen: "#,
             new_account: "Neuer Account",
+            passkey_reset: "Passkey Zurücksetzen",
+            passkey_reset_desc: r#"Ihr Account hat kein Passwort. Stattdessen können Sie einen neuen
+Passkey registrieren. Alle bestehenden Passkeys werden dabei entfernt."#,
             password_reset: "Passwort Zurücksetzen",
             password: "Passwort",
             passwordless: "FIDO Passkey",
@@ -110,8 +121,8 @@ FIDO2 Standard gerecht wird. Für weitere Informationen können Sie diesem Link
             success_3: "Sollte Sie nicht weitergeleitet werden, klicken Sie bitte hier:",
             success_passkey_1: "Der neue Passkey wurde erfolgreich registriert.",
             success_passkey_2: r#"Bitte loggen Sie sich direkt in Ihren Account ein und registrieren
-Sie mindestens einen weiteren Backup Passkey. Ein passwortloser Account kann nicht den Passwort
-Reset via E-Mail nutzen für den Fall, dass der derzeitige Passkey abhanden kommt."#,
+Sie mindestens einen weiteren Backup Passkey. Sollten alle Passkeys abhanden kommen, werden diese
+bei einem Reset via E-Mail entfernt und ein neuer muss registriert werden."#,
         }
     }
 }
@@ -0,0 +1,73 @@
+use crate::i18n::SsrJson;
+use crate::language::Language;
+use serde::Serialize;
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct I18nPushMfa<'a> {
+    pub approve: &'a str,
+    pub close_window: &'a str,
+    pub deny: &'a str,
+    pub desc: &'a str,
+    pub from_ip: &'a str,
+    pub invalid_input: &'a str,
+    pub is_approved: &'a str,
+    pub is_denied: &'a str,
+    pub notification_body: &'a str,
+    pub notification_title: &'a str,
+    pub number: &'a str,
+    pub title: &'a str,
+    pub wrong_or_expired: &'a str,
+}
+
+impl SsrJson for I18nPushMfa<'_> {
+    fn build(lang: &Language) -> Self {
+        match lang {
+            Language::En => Self::build_en(),
+            Language::De => Self::build_de(),
+        }
+    }
+
+    fn as_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl I18nPushMfa<'_> {
+    fn build_en() -> Self {
+        Self {
+            approve: "Approve",
+            close_window: "You can close this window now.",
+            deny: "Deny",
+            desc: "Enter the number shown on the login page to approve the login.",
+            from_ip: "Login attempt from IP:",
+            invalid_input: "Invalid Input",
+            is_approved: "The login has been approved.",
+            is_denied: "The login has been denied.",
+            notification_body: "Tap to approve or deny a login to your account.",
+            notification_title: "Login request",
+            number: "Number",
+            title: "Approve Login",
+            wrong_or_expired: "Wrong number or expired request",
+        }
+    }
+
+    fn build_de() -> Self {
+        Self {
+            approve: "Bestätigen",
+            close_window: "Dieses Fenster kann nun geschlossen werden.",
+            deny: "Ablehnen",
+            desc: "Die auf der Login Seite angezeigte Zahl eingeben, um den Login zu bestätigen.",
+            from_ip: "Login Versuch von IP:",
+            invalid_input: "Ungültige Eingabe",
+            is_approved: "Der Login wurde bestätigt.",
+            is_denied: "Der Login wurde abgelehnt.",
+            notification_body: "Antippen, um einen Login in Ihren Account zu bestätigen oder \
+                abzulehnen.",
+            notification_title: "Login Anfrage",
+            number: "Zahl",
+            title: "Login Bestätigen",
+            wrong_or_expired: "Falsche Zahl oder abgelaufene Anfrage",
+        }
+    }
+}
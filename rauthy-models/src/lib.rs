@@ -3,6 +3,7 @@
 #![forbid(unsafe_code)]
 
 use crate::entity::sessions::Session;
+use crate::entity::user_attr::UserAttrConfigEntity;
 use crate::entity::users::User;
 use crate::entity::users_values::UserValues;
 use actix_web::http::header::{HeaderName, HeaderValue};
@@ -15,19 +16,27 @@ use std::str::FromStr;
 use utoipa::ToSchema;
 
 pub mod app_state;
+pub mod asset_storage;
+pub mod cache_invalidation;
 pub mod email;
 pub mod entity;
 pub mod events;
 pub mod i18n;
 pub mod language;
 pub mod migration;
+pub mod pii;
 pub mod request;
 pub mod response;
+pub mod sms;
 pub mod templates;
+pub mod user_store;
 
 pub enum AuthStep {
     LoggedIn(AuthStepLoggedIn),
     AwaitWebauthn(AuthStepAwaitWebauthn),
+    AwaitEmailMfa(AuthStepAwaitEmailMfa),
+    AwaitPushMfa(AuthStepAwaitPushMfa),
+    AwaitProfile(AuthStepAwaitProfile),
     ProviderLink,
 }
 
@@ -50,6 +59,30 @@ pub struct AuthStepAwaitWebauthn {
     pub session: Session,
 }
 
+pub struct AuthStepAwaitEmailMfa {
+    pub has_password_been_hashed: bool,
+    pub code: String,
+    pub header_csrf: (HeaderName, HeaderValue),
+    pub header_origin: Option<(HeaderName, HeaderValue)>,
+    pub exp: i64,
+}
+
+pub struct AuthStepAwaitPushMfa {
+    pub has_password_been_hashed: bool,
+    pub code: String,
+    pub number: i64,
+    pub header_csrf: (HeaderName, HeaderValue),
+    pub header_origin: Option<(HeaderName, HeaderValue)>,
+    pub exp: i64,
+}
+
+pub struct AuthStepAwaitProfile {
+    pub has_password_been_hashed: bool,
+    pub header_csrf: (HeaderName, HeaderValue),
+    pub header_origin: Option<(HeaderName, HeaderValue)>,
+    pub missing_attrs: Vec<UserAttrConfigEntity>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RequestId {
     pub id: String,
@@ -78,10 +111,18 @@ pub struct JwtCommonClaims {
     pub typ: JwtTokenType,
     pub azp: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub did: Option<String>,
     pub cnf: Option<JktClaim>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActClaim>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -89,6 +130,57 @@ pub struct JktClaim {
     pub jkt: String,
 }
 
+/// The `act` claim from RFC 8693 for exchanged tokens, which identifies the party acting on
+/// behalf of the subject. Prior actors of a delegation chain are nested inside.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActClaim {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<Box<ActClaim>>,
+}
+
+impl ActClaim {
+    /// The amount of actors in the whole chain
+    pub fn depth(&self) -> usize {
+        1 + self.act.as_ref().map(|act| act.depth()).unwrap_or(0)
+    }
+}
+
+/// A single entry of the `authorization_details` from RFC 9396. These are either requested by
+/// the client directly, or derived from parameterized scopes like `account:1234:read`, which
+/// match a client scope pattern like `account:*:read`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct AuthorizationDetail {
+    /// Must be one of the `authorization_details_types` of the client. For parameterized
+    /// scopes, the literal segments in front of the first wildcard with a `scope:` prefix.
+    #[serde(rename = "type")]
+    pub typ: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<String>,
+    /// For parameterized scopes, the literal segments after the first wildcard
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub datatypes: Vec<String>,
+    /// For parameterized scopes, the values of all wildcards, joined with `:`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privileges: Vec<String>,
+    /// All other, `type` specific fields
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// An organization membership, which will be added to the tokens with the `organizations` scope
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrganizationClaim {
+    pub id: String,
+    pub name: String,
+    pub owner: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AddressClaim {
     pub formatted: String,
@@ -150,8 +242,13 @@ impl AddressClaim {
 pub struct JwtAccessClaims {
     pub typ: JwtTokenType,
     pub azp: String,
+    /// Only set with `ACCESS_TOKEN_JWT_PROFILE`, as required by RFC 9068
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
     pub scope: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub allowed_origins: Option<Vec<String>>,
     // user part
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -165,9 +262,17 @@ pub struct JwtAccessClaims {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groups: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub organizations: Option<Vec<OrganizationClaim>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub cnf: Option<JktClaim>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<HashMap<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActClaim>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -198,11 +303,17 @@ pub struct JwtIdClaims {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groups: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub organizations: Option<Vec<OrganizationClaim>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub cnf: Option<JktClaim>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom: Option<HashMap<String, serde_json::Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub webid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -212,6 +323,14 @@ pub struct JwtRefreshClaims {
     pub uid: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cnf: Option<JktClaim>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// The RFC 8707 `resource` the token has been issued for, which a refresh can not change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    /// All granted RFC 9396 details, so a refresh can ask for any subset of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
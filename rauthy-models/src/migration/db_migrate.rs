@@ -21,19 +21,31 @@ use rauthy_common::DbType;
 use crate::app_state::DbPool;
 use crate::entity::api_keys::ApiKeyEntity;
 use crate::entity::auth_providers::AuthProvider;
+use crate::entity::client_secret_reads::ClientSecretRead;
+use crate::entity::client_user_agents::ClientUserAgent;
 use crate::entity::clients::Client;
 use crate::entity::clients_dyn::ClientDyn;
 use crate::entity::colors::ColorEntity;
 use crate::entity::config::ConfigEntity;
+use crate::entity::email_aliases::EmailAlias;
+use crate::entity::email_deliveries::EmailDelivery;
 use crate::entity::groups::Group;
-use crate::entity::jwk::{Jwk, JwkKeyPairAlg};
+use crate::entity::jobs::Job;
+use crate::entity::jwk::{Jwk, JwkKeyPairAlg, JwkLifecycle};
+use crate::entity::legal_holds::LegalHold;
+use crate::entity::login_policies::{GroupLoginPolicy, LoginPolicyOverride};
 use crate::entity::magic_links::MagicLink;
+use crate::entity::organizations::{Organization, OrganizationMember};
 use crate::entity::password::RecentPasswordsEntity;
 use crate::entity::refresh_tokens::RefreshToken;
+use crate::entity::revoked_tokens::RevokedToken;
 use crate::entity::roles::Role;
 use crate::entity::scopes::Scope;
 use crate::entity::sessions::Session;
+use crate::entity::user_activity::UserActivity;
 use crate::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
+use crate::entity::user_client_grants::UserClientGrant;
+use crate::entity::user_notification_prefs::UserNotificationPrefs;
 use crate::entity::users::User;
 use crate::entity::users_values::UserValues;
 use crate::entity::webauthn::PasskeyEntity;
@@ -85,6 +97,15 @@ pub async fn anti_lockout(db: &DbPool, issuer: &str) -> Result<(), ErrorResponse
         force_mfa: *ADMIN_FORCE_MFA,
         client_uri: Some(PUB_URL_WITH_SCHEME.to_string()),
         contacts: env::var("RAUTHY_ADMIN_EMAIL").ok(),
+        allowed_cidrs: None,
+        jwks_uri: None,
+        require_signed_request_object: false,
+        id_token_encrypted_response_alg: None,
+        id_token_encrypted_response_enc: None,
+        userinfo_encrypted_response_alg: None,
+        userinfo_encrypted_response_enc: None,
+        authorization_details_types: None,
+        token_exchange_audiences: None,
     };
 
     // MUST NOT use `insert or replace` syntax
@@ -392,9 +413,10 @@ pub async fn migrate_from_sqlite(
             r#"INSERT INTO
             auth_providers (id, enabled, name, typ, issuer, authorization_endpoint, token_endpoint,
             userinfo_endpoint, client_id, secret, scope, admin_claim_path, admin_claim_value,
-            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem)
+            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem,
+            claim_mapping)
             VALUES
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)"#,
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
         )
         .bind(b.id)
         .bind(b.enabled)
@@ -414,6 +436,7 @@ pub async fn migrate_from_sqlite(
         .bind(b.allow_insecure_requests)
         .bind(b.use_pkce)
         .bind(b.root_pem)
+        .bind(b.claim_mapping)
         .execute(db_to)
         .await?;
     }
@@ -458,9 +481,11 @@ pub async fn migrate_from_sqlite(
             r#"insert into users
             (id, email, given_name, family_name, password, roles, groups, enabled, email_verified,
             password_expires, created_at, last_login, last_failed_login, failed_login_attempts,
-            language, webauthn_user_id, user_expires, auth_provider_id, federation_uid)
+            language, webauthn_user_id, user_expires, auth_provider_id, federation_uid, username,
+            email_mfa)
             values
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
+            $20, $21)"#,
         )
         .bind(b.id)
         .bind(b.email)
@@ -481,6 +506,8 @@ pub async fn migrate_from_sqlite(
         .bind(b.user_expires)
         .bind(b.auth_provider_id)
         .bind(b.federation_uid)
+        .bind(b.username)
+        .bind(b.email_mfa)
         .execute(db_to)
         .await?;
     }
@@ -521,10 +548,13 @@ pub async fn migrate_from_sqlite(
             r#"insert into clients (id, name, enabled, confidential, secret, secret_kid,
             redirect_uris, post_logout_redirect_uris, allowed_origins, flows_enabled, access_token_alg,
             id_token_alg, refresh_token, auth_code_lifetime, access_token_lifetime, scopes, default_scopes,
-            challenge, force_mfa, client_uri, contacts)
+            challenge, force_mfa, client_uri, contacts, allowed_cidrs, jwks_uri,
+            require_signed_request_object, id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc, userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc, authorization_details_types, token_exchange_audiences)
             values
             ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
-            $20, $21)"#)
+            $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)"#)
             .bind(&b.id)
             .bind(&b.name)
             .bind(b.enabled)
@@ -546,6 +576,15 @@ pub async fn migrate_from_sqlite(
             .bind(b.force_mfa)
             .bind(b.client_uri)
             .bind(b.contacts)
+            .bind(b.allowed_cidrs)
+            .bind(b.jwks_uri)
+            .bind(b.require_signed_request_object)
+            .bind(b.id_token_encrypted_response_alg)
+            .bind(b.id_token_encrypted_response_enc)
+            .bind(b.userinfo_encrypted_response_alg)
+            .bind(b.userinfo_encrypted_response_enc)
+            .bind(b.authorization_details_types)
+            .bind(b.token_exchange_audiences)
             .execute(db_to)
             .await?;
     }
@@ -619,9 +658,10 @@ pub async fn migrate_from_sqlite(
         .await?;
     sqlx::query("delete from groups").execute(db_to).await?;
     for b in before {
-        sqlx::query("insert into groups (id, name) values ($1, $2)")
+        sqlx::query("insert into groups (id, name, domains) values ($1, $2, $3)")
             .bind(b.id)
             .bind(b.name)
+            .bind(b.domains)
             .execute(db_to)
             .await?;
     }
@@ -692,14 +732,19 @@ pub async fn migrate_from_sqlite(
         .await?;
     for b in before {
         sqlx::query(
-            r#"insert into refresh_tokens (id, user_id, nbf, exp, scope)
-            values ($1, $2, $3, $4, $5)"#,
+            r#"insert into refresh_tokens
+            (id, user_id, nbf, exp, scope, is_mfa, client_id, chain_exp, device_fp)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
         )
         .bind(&b.id)
         .bind(&b.user_id)
         .bind(b.nbf)
         .bind(b.exp)
         .bind(&b.scope)
+        .bind(b.is_mfa)
+        .bind(&b.client_id)
+        .bind(b.chain_exp)
+        .bind(&b.device_fp)
         .execute(db_to)
         .await?;
     }
@@ -726,13 +771,16 @@ pub async fn migrate_from_sqlite(
     sqlx::query("delete from scopes").execute(db_to).await?;
     for b in before {
         sqlx::query(
-            r#"insert into scopes (id, name, attr_include_access, attr_include_id)
-            values ($1, $2, $3, $4)"#,
+            r#"insert into scopes
+            (id, name, attr_include_access, attr_include_id, i18n, client_ids)
+            values ($1, $2, $3, $4, $5, $6)"#,
         )
         .bind(b.id)
         .bind(b.name)
         .bind(b.attr_include_access)
         .bind(b.attr_include_id)
+        .bind(b.i18n)
+        .bind(b.client_ids)
         .execute(db_to)
         .await?;
     }
@@ -750,10 +798,16 @@ pub async fn migrate_from_sqlite(
         let ip: Option<String> = b.get("ip");
         let data: Option<i64> = b.get("data");
         let text: Option<String> = b.get("text");
+        let seq: Option<i64> = b.get("seq");
+        let prev_hash: Option<String> = b.get("prev_hash");
+        let hash: Option<String> = b.get("hash");
+        let anonymized: bool = b.get("anonymized");
+        let user_id: Option<String> = b.get("user_id");
 
         sqlx::query(
-            r#"INSERT INTO events (id, timestamp, level, typ, ip, data, text)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            r#"INSERT INTO events
+            (id, timestamp, level, typ, ip, data, text, seq, prev_hash, hash, anonymized, user_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
         )
         .bind(id)
         .bind(timestamp)
@@ -762,6 +816,11 @@ pub async fn migrate_from_sqlite(
         .bind(ip)
         .bind(data)
         .bind(text)
+        .bind(seq)
+        .bind(prev_hash)
+        .bind(hash)
+        .bind(anonymized)
+        .bind(user_id)
         .execute(db_to)
         .await?;
     }
@@ -776,15 +835,20 @@ pub async fn migrate_from_sqlite(
         .await?;
     for b in before {
         match *DB_TYPE {
-            DbType::Sqlite => {
-                sqlx::query("insert into user_attr_config (name, desc) values ($1, $2)")
-            }
-            DbType::Postgres => {
-                sqlx::query("insert into user_attr_config (name, \"desc\") values ($1, $2)")
-            }
+            DbType::Sqlite => sqlx::query(
+                r#"insert into user_attr_config (name, desc, required, skip_client_ids, sensitive)
+                values ($1, $2, $3, $4, $5)"#,
+            ),
+            DbType::Postgres => sqlx::query(
+                r#"insert into user_attr_config (name, "desc", required, skip_client_ids, sensitive)
+                values ($1, $2, $3, $4, $5)"#,
+            ),
         }
         .bind(b.name)
         .bind(b.desc)
+        .bind(b.required)
+        .bind(b.skip_client_ids)
+        .bind(b.sensitive)
         .execute(db_to)
         .await?;
     }
@@ -840,8 +904,9 @@ pub async fn migrate_from_sqlite(
     for b in before {
         sqlx::query(
             r#"insert into
-            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen)
-            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen,
+                elevated_until)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
         )
         .bind(&b.id)
         .bind(&b.csrf_token)
@@ -852,8 +917,28 @@ pub async fn migrate_from_sqlite(
         .bind(b.state.as_str())
         .bind(b.exp)
         .bind(b.last_seen)
+        .bind(b.elevated_until)
+        .execute(db_to)
+        .await?;
+    }
+
+    // SESSIONS SID
+    debug!("Migrating table: sessions_sid");
+    let before = sqlx::query("select * from sessions_sid")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from sessions_sid")
         .execute(db_to)
         .await?;
+    for b in before {
+        let sid: String = b.get("sid");
+        let session_id: String = b.get("session_id");
+
+        sqlx::query("insert into sessions_sid (sid, session_id) values ($1, $2)")
+            .bind(sid)
+            .bind(session_id)
+            .execute(db_to)
+            .await?;
     }
 
     // RECENT PASSWORDS
@@ -889,551 +974,2015 @@ pub async fn migrate_from_sqlite(
         .await?;
     }
 
-    Ok(())
-}
-
-pub async fn migrate_from_postgres(
-    db_from: sqlx::PgPool,
-    db_to: &DbPool,
-) -> Result<(), ErrorResponse> {
-    info!("Starting migration to another DB");
-
-    // CONFIG
-    debug!("Migrating table: config");
-    let before = sqlx::query_as::<_, ConfigEntity>("SELECT id, data FROM rauthy.config")
+    // GROUP LOGIN POLICIES
+    debug!("Migrating table: group_login_policies");
+    let before = sqlx::query_as::<_, GroupLoginPolicy>("select * from group_login_policies")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("DELETE FROM config").execute(db_to).await?;
+    sqlx::query("delete from group_login_policies")
+        .execute(db_to)
+        .await?;
     for b in before {
-        sqlx::query!(
-            "INSERT INTO config (id, data) VALUES ($1, $2)",
-            b.id,
-            b.data,
+        sqlx::query(
+            r#"INSERT INTO group_login_policies
+            (group_id, weekdays, time_from, time_to, utc_offset, timezone)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
         )
+        .bind(b.group_id)
+        .bind(b.weekdays)
+        .bind(b.time_from)
+        .bind(b.time_to)
+        .bind(b.utc_offset)
+        .bind(b.timezone)
         .execute(db_to)
         .await?;
     }
 
-    // API KEYS
-    debug!("Migrating table: api_keys");
-    let before = sqlx::query_as::<_, ApiKeyEntity>("SELECT * FROM api_keys")
+    // LOGIN POLICY OVERRIDES
+    debug!("Migrating table: login_policy_overrides");
+    let before = sqlx::query_as::<_, LoginPolicyOverride>("select * from login_policy_overrides")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query!("DELETE FROM api_keys").execute(db_to).await?;
+    sqlx::query("delete from login_policy_overrides")
+        .execute(db_to)
+        .await?;
     for b in before {
-        sqlx::query(
-            r#"INSERT INTO
-            api_keys (name, secret, created, expires, enc_key_id, access)
-            VALUES ($1, $2, $3, $4, $5, $6)"#,
-        )
-        .bind(b.name)
-        .bind(b.secret)
-        .bind(b.created)
-        .bind(b.expires)
-        .bind(b.enc_key_id)
-        .bind(b.access)
+        sqlx::query("INSERT INTO login_policy_overrides (user_id, exp) VALUES ($1, $2)")
+            .bind(b.user_id)
+            .bind(b.exp)
+            .execute(db_to)
+            .await?;
+    }
+
+    // LEGAL HOLDS
+    debug!("Migrating table: legal_holds");
+    let before = sqlx::query_as::<_, LegalHold>("select * from legal_holds")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from legal_holds")
         .execute(db_to)
         .await?;
+    for b in before {
+        sqlx::query("INSERT INTO legal_holds (user_id, reason, created) VALUES ($1, $2, $3)")
+            .bind(b.user_id)
+            .bind(b.reason)
+            .bind(b.created)
+            .execute(db_to)
+            .await?;
     }
 
-    // The users table has a FK to auth_providers - the order is important here!
-    // AUTH PROVIDERS
-    debug!("Migrating table: auth_providers");
-    let before = sqlx::query_as::<_, AuthProvider>("select * from rauthy.auth_providers")
+    // EMAIL ALIASES
+    debug!("Migrating table: email_aliases");
+    let before = sqlx::query_as::<_, EmailAlias>("select * from email_aliases")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from auth_providers")
+    sqlx::query("delete from email_aliases")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("INSERT INTO email_aliases (email, user_id, created) VALUES ($1, $2, $3)")
+            .bind(b.email)
+            .bind(b.user_id)
+            .bind(b.created)
+            .execute(db_to)
+            .await?;
+    }
+
+    // ORGANIZATIONS
+    debug!("Migrating table: organizations");
+    let before = sqlx::query_as::<_, Organization>("select * from organizations")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from organizations")
         .execute(db_to)
         .await?;
     for b in before {
         sqlx::query(
-            r#"INSERT INTO
-            auth_providers (id, enabled, name, typ, issuer, authorization_endpoint, token_endpoint,
-            userinfo_endpoint, client_id, secret, scope, admin_claim_path, admin_claim_value,
-            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem)
-            VALUES
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)"#,
+            "INSERT INTO organizations (id, name, domains, created) VALUES ($1, $2, $3, $4)",
         )
         .bind(b.id)
-        .bind(b.enabled)
         .bind(b.name)
-        .bind(b.typ.as_str())
-        .bind(b.issuer)
-        .bind(b.authorization_endpoint)
-        .bind(b.token_endpoint)
-        .bind(b.userinfo_endpoint)
-        .bind(b.client_id)
-        .bind(b.secret)
-        .bind(b.scope)
-        .bind(b.admin_claim_path)
-        .bind(b.admin_claim_value)
-        .bind(b.mfa_claim_path)
-        .bind(b.mfa_claim_value)
-        .bind(b.allow_insecure_requests)
-        .bind(b.use_pkce)
-        .bind(b.root_pem)
+        .bind(b.domains)
+        .bind(b.created)
         .execute(db_to)
         .await?;
     }
 
-    // AUTH PROVIDER LOGOS
-    debug!("Migrating table: auth_provider_logos");
-    let before = sqlx::query(
-        "select auth_provider_id as id, res, content_type, data from rauthy.auth_provider_logos",
-    )
-    .fetch_all(&db_from)
-    .await?;
-    sqlx::query("delete from auth_provider_logos")
+    // ORGANIZATION MEMBERS
+    debug!("Migrating table: organization_members");
+    let before = sqlx::query_as::<_, OrganizationMember>("select * from organization_members")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from organization_members")
         .execute(db_to)
         .await?;
     for b in before {
-        let id: String = b.get("id");
-        let res: String = b.get("res");
-        let content_type: String = b.get("content_type");
-        let data: Vec<u8> = b.get("data");
-
         sqlx::query(
-            r#"INSERT OR REPLACE INTO
-            auth_provider_logos (auth_provider_id, res, content_type, data)
+            r#"INSERT INTO organization_members (org_id, user_id, is_owner, created)
             VALUES ($1, $2, $3, $4)"#,
         )
-        .bind(id)
-        .bind(res)
-        .bind(content_type)
-        .bind(data)
+        .bind(b.org_id)
+        .bind(b.user_id)
+        .bind(b.is_owner)
+        .bind(b.created)
         .execute(db_to)
         .await?;
     }
 
-    // USERS
-    debug!("Migrating table: users");
-    let before = sqlx::query_as::<_, User>("select * from rauthy.users")
+    // CLIENT USER AGENTS
+    debug!("Migrating table: client_user_agents");
+    let before = sqlx::query_as::<_, ClientUserAgent>("select * from client_user_agents")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from users").execute(db_to).await?;
+    sqlx::query("delete from client_user_agents")
+        .execute(db_to)
+        .await?;
     for b in before {
         sqlx::query(
-            r#"insert into users
-            (id, email, given_name, family_name, password, roles, groups, enabled, email_verified,
-            password_expires, created_at, last_login, last_failed_login, failed_login_attempts,
-            language, webauthn_user_id, user_expires, auth_provider_id, federation_uid)
-            values
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
+            r#"INSERT INTO client_user_agents
+            (client_id, user_agent, first_seen, last_seen, requests)
+            VALUES ($1, $2, $3, $4, $5)"#,
         )
-        .bind(b.id)
-        .bind(b.email)
-        .bind(b.given_name)
-        .bind(b.family_name)
-        .bind(b.password)
-        .bind(b.roles)
-        .bind(b.groups)
-        .bind(b.enabled)
-        .bind(b.email_verified)
-        .bind(b.password_expires)
-        .bind(b.created_at)
-        .bind(b.last_login)
-        .bind(b.last_failed_login)
-        .bind(b.failed_login_attempts)
-        .bind(b.language)
-        .bind(b.webauthn_user_id)
-        .bind(b.user_expires)
-        .bind(b.auth_provider_id)
-        .bind(b.federation_uid)
+        .bind(b.client_id)
+        .bind(b.user_agent)
+        .bind(b.first_seen)
+        .bind(b.last_seen)
+        .bind(b.requests)
         .execute(db_to)
         .await?;
     }
 
-    // PASSKEYS
-    debug!("Migrating table: passkeys");
-    let before = sqlx::query_as::<_, PasskeyEntity>("SELECT * FROM rauthy.passkeys")
+    // CLIENT SECRET READS
+    debug!("Migrating table: client_secret_reads");
+    let before = sqlx::query_as::<_, ClientSecretRead>("select * from client_secret_reads")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("DELETE FROM passkeys").execute(db_to).await?;
-    for b in before {
-        sqlx::query!(
-            r#"INSERT INTO passkeys
-            (user_id, name, passkey_user_id, passkey, credential_id, registered, last_used, user_verified)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
-            b.user_id,
-            b.name,
-            b.passkey_user_id,
-            b.passkey,
-            b.credential_id,
-            b.registered,
-            b.last_used,
-            b.user_verified,
-        )
-            .execute(db_to)
-            .await?;
-    }
-
-    // Do not change the order - tables below have FKs to clients
-    // CLIENTS
-    debug!("Migrating table: clients");
-    let before = sqlx::query_as::<_, Client>("select * from rauthy.clients")
-        .fetch_all(&db_from)
+    sqlx::query("delete from client_secret_reads")
+        .execute(db_to)
         .await?;
-    sqlx::query("delete from clients").execute(db_to).await?;
     for b in before {
         sqlx::query(
-            r#"insert into clients (id, name, enabled, confidential, secret, secret_kid,
-            redirect_uris, post_logout_redirect_uris, allowed_origins, flows_enabled, access_token_alg,
-            id_token_alg, refresh_token, auth_code_lifetime, access_token_lifetime, scopes, default_scopes,
-            challenge, force_mfa, client_uri, contacts)
-            values
-            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
-            $20, $21)"#)
-            .bind(&b.id)
-            .bind(&b.name)
-            .bind(b.enabled)
-            .bind(b.confidential)
-            .bind(&b.secret)
-            .bind(&b.secret_kid)
-            .bind(&b.redirect_uris)
-            .bind(&b.post_logout_redirect_uris)
-            .bind(&b.allowed_origins)
-            .bind(&b.flows_enabled)
-            .bind(&b.access_token_alg)
-            .bind(&b.id_token_alg)
-            .bind(b.refresh_token)
-            .bind(b.auth_code_lifetime)
-            .bind(b.access_token_lifetime)
-            .bind(&b.scopes)
-            .bind(&b.default_scopes)
-            .bind(&b.challenge)
-            .bind(b.force_mfa)
-            .bind(b.client_uri)
-            .bind(b.contacts)
-            .execute(db_to)
-            .await?;
+            r#"INSERT INTO client_secret_reads
+            (client_id, reads, last_read, last_actor, last_ip)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.client_id)
+        .bind(b.reads)
+        .bind(b.last_read)
+        .bind(b.last_actor)
+        .bind(b.last_ip)
+        .execute(db_to)
+        .await?;
     }
 
-    // CLIENTS DYN
-    debug!("Migrating table: clients_dyn");
-    let before = sqlx::query_as::<_, ClientDyn>("select * from rauthy.clients_dyn")
+    // REVOKED TOKENS
+    debug!("Migrating table: revoked_tokens");
+    let before = sqlx::query_as::<_, RevokedToken>("select * from revoked_tokens")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from clients_dyn")
+    sqlx::query("delete from revoked_tokens")
         .execute(db_to)
         .await?;
     for b in before {
         sqlx::query(
-            r#"INSERT INTO
-            clients_dyn (id, created, registration_token, token_endpoint_auth_method)
-            VALUES ($1, $2, $3, $4)"#,
+            "INSERT INTO revoked_tokens (jti, exp, reason, created) VALUES ($1, $2, $3, $4)",
         )
-        .bind(&b.id)
+        .bind(b.jti)
+        .bind(b.exp)
+        .bind(b.reason)
         .bind(b.created)
-        .bind(&b.registration_token)
-        .bind(&b.token_endpoint_auth_method)
         .execute(db_to)
         .await?;
     }
 
-    // CLIENT LOGOS
-    let before = sqlx::query("select * from rauthy.client_logos")
+    // JWKS LIFECYCLE
+    debug!("Migrating table: jwks_lifecycle");
+    let before = sqlx::query_as::<_, JwkLifecycle>("select * from jwks_lifecycle")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from client_logos")
+    sqlx::query("delete from jwks_lifecycle")
         .execute(db_to)
         .await?;
     for b in before {
-        let id: String = b.get("client_id");
-        let res: String = b.get("res");
-        let content_type: String = b.get("content_type");
-        let data: Vec<u8> = b.get("data");
-
         sqlx::query(
-            r#"INSERT INTO client_logos (client_id, res, content_type, data)
-                VALUES ($1, $2, $3, $4)"#,
+            r#"insert into jwks_lifecycle (kid, imported, activate_at, retired_at, usage_count)
+            values ($1, $2, $3, $4, $5)"#,
         )
-        .bind(id)
-        .bind(res)
-        .bind(content_type)
-        .bind(data)
+        .bind(b.kid)
+        .bind(b.imported)
+        .bind(b.activate_at)
+        .bind(b.retired_at)
+        .bind(b.usage_count)
         .execute(db_to)
         .await?;
     }
 
-    // COLORS
-    let before = sqlx::query_as::<_, ColorEntity>("select * from rauthy.colors")
+    // USER CLIENT GRANTS
+    debug!("Migrating table: user_client_grants");
+    let before = sqlx::query_as::<_, UserClientGrant>("select * from user_client_grants")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from colors").execute(db_to).await?;
+    sqlx::query("delete from user_client_grants")
+        .execute(db_to)
+        .await?;
     for b in before {
-        sqlx::query("insert into colors (client_id, data) values ($1, $2)")
+        sqlx::query(
+            r#"insert into user_client_grants (user_id, client_id, scope, created, last_used)
+            values ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.user_id)
+        .bind(b.client_id)
+        .bind(b.scope)
+        .bind(b.created)
+        .bind(b.last_used)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER NOTIFICATION PREFS
+    debug!("Migrating table: user_notification_prefs");
+    let before =
+        sqlx::query_as::<_, UserNotificationPrefs>("select * from user_notification_prefs")
+            .fetch_all(&db_from)
+            .await?;
+    sqlx::query("delete from user_notification_prefs")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into user_notification_prefs (user_id, password_expiry, sessions_revoked)
+            values ($1, $2, $3)"#,
+        )
+        .bind(b.user_id)
+        .bind(b.password_expiry)
+        .bind(b.sessions_revoked)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER ACTIVITY
+    debug!("Migrating table: user_activity");
+    let before = sqlx::query_as::<_, UserActivity>("select * from user_activity")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from user_activity")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into user_activity (user_id, last_refresh, last_userinfo)
+            values ($1, $2, $3)"#,
+        )
+        .bind(b.user_id)
+        .bind(b.last_refresh)
+        .bind(b.last_userinfo)
+        .execute(db_to)
+        .await?;
+    }
+
+    // EVENT CHAIN HEAD
+    debug!("Migrating table: event_chain_head");
+    let before = sqlx::query("select seq, hash from event_chain_head where id = 1")
+        .fetch_one(&db_from)
+        .await?;
+    let seq: i64 = before.get("seq");
+    let hash: String = before.get("hash");
+    sqlx::query("update event_chain_head set seq = $1, hash = $2 where id = 1")
+        .bind(seq)
+        .bind(hash)
+        .execute(db_to)
+        .await?;
+
+    // EVENT ANCHORS
+    debug!("Migrating table: event_anchors");
+    let before = sqlx::query("select * from event_anchors")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from event_anchors")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let seq: i64 = b.get("seq");
+        let hash: String = b.get("hash");
+        let token: String = b.get("token");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into event_anchors (seq, hash, token, created)
+            values ($1, $2, $3, $4)"#,
+        )
+        .bind(seq)
+        .bind(hash)
+        .bind(token)
+        .bind(created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // EVENT CHAIN PRUNED
+    debug!("Migrating table: event_chain_pruned");
+    let before = sqlx::query("select * from event_chain_pruned")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from event_chain_pruned")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let seq: i64 = b.get("seq");
+        let prev_hash: String = b.get("prev_hash");
+        let hash: String = b.get("hash");
+
+        sqlx::query("insert into event_chain_pruned (seq, prev_hash, hash) values ($1, $2, $3)")
+            .bind(seq)
+            .bind(prev_hash)
+            .bind(hash)
+            .execute(db_to)
+            .await?;
+    }
+
+    // MFA RECOVERIES
+    debug!("Migrating table: mfa_recoveries");
+    let before = sqlx::query("select * from mfa_recoveries")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from mfa_recoveries")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let user_id: String = b.get("user_id");
+        let status: String = b.get("status");
+        let created: i64 = b.get("created");
+        let confirmed: Option<i64> = b.get("confirmed");
+        let not_before: Option<i64> = b.get("not_before");
+        let approved_by: Option<String> = b.get("approved_by");
+        let exp: i64 = b.get("exp");
+
+        sqlx::query(
+            r#"insert into mfa_recoveries
+            (id, user_id, status, created, confirmed, not_before, approved_by, exp)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(status)
+        .bind(created)
+        .bind(confirmed)
+        .bind(not_before)
+        .bind(approved_by)
+        .bind(exp)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER ROLE GRANTS
+    debug!("Migrating table: user_role_grants");
+    let before = sqlx::query("select * from user_role_grants")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from user_role_grants")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let user_id: String = b.get("user_id");
+        let role: String = b.get("role");
+        let reason: String = b.get("reason");
+        let approved_by: String = b.get("approved_by");
+        let created: i64 = b.get("created");
+        let exp: i64 = b.get("exp");
+
+        sqlx::query(
+            r#"insert into user_role_grants
+            (id, user_id, role, reason, approved_by, created, exp)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(role)
+        .bind(reason)
+        .bind(approved_by)
+        .bind(created)
+        .bind(exp)
+        .execute(db_to)
+        .await?;
+    }
+
+    // ANNOUNCEMENTS
+    debug!("Migrating table: announcements");
+    let before = sqlx::query("select * from announcements")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from announcements")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let message: String = b.get("message");
+        let severity: String = b.get("severity");
+        let nbf: i64 = b.get("nbf");
+        let exp: Option<i64> = b.get("exp");
+        let client_ids: Option<String> = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into announcements
+            (id, message, severity, nbf, exp, client_ids, created)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(id)
+        .bind(message)
+        .bind(severity)
+        .bind(nbf)
+        .bind(exp)
+        .bind(client_ids)
+        .bind(created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // FEATURE FLAGS
+    debug!("Migrating table: feature_flags");
+    let before = sqlx::query("select * from feature_flags")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from feature_flags")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let name: String = b.get("name");
+        let description: Option<String> = b.get("description");
+        let enabled: bool = b.get("enabled");
+        let percentage: Option<i64> = b.get("percentage");
+        let groups: Option<String> = b.get("groups");
+        let attr_key: Option<String> = b.get("attr_key");
+        let attr_value: Option<String> = b.get("attr_value");
+        let client_ids: Option<String> = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into feature_flags
+            (id, name, description, enabled, percentage, groups, attr_key, attr_value, client_ids,
+            created)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(description)
+        .bind(enabled)
+        .bind(percentage)
+        .bind(groups)
+        .bind(attr_key)
+        .bind(attr_value)
+        .bind(client_ids)
+        .bind(created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER MERGES
+    debug!("Migrating table: user_merges");
+    let before = sqlx::query("select * from user_merges")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from user_merges")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let old_id: String = b.get("old_id");
+        let new_id: String = b.get("new_id");
+        let merged_by: String = b.get("merged_by");
+        let merged_at: i64 = b.get("merged_at");
+
+        sqlx::query(
+            r#"insert into user_merges (old_id, new_id, merged_by, merged_at)
+            values ($1, $2, $3, $4)"#,
+        )
+        .bind(old_id)
+        .bind(new_id)
+        .bind(merged_by)
+        .bind(merged_at)
+        .execute(db_to)
+        .await?;
+    }
+
+    // PUSH SUBSCRIPTIONS
+    debug!("Migrating table: push_subscriptions");
+    let before = sqlx::query("select * from push_subscriptions")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from push_subscriptions")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let user_id: String = b.get("user_id");
+        let name: String = b.get("name");
+        let endpoint: String = b.get("endpoint");
+        let p256dh: String = b.get("p256dh");
+        let auth: String = b.get("auth");
+        let created: i64 = b.get("created");
+        let last_used: Option<i64> = b.get("last_used");
+
+        sqlx::query(
+            r#"insert into push_subscriptions
+            (id, user_id, name, endpoint, p256dh, auth, created, last_used)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(endpoint)
+        .bind(p256dh)
+        .bind(auth)
+        .bind(created)
+        .bind(last_used)
+        .execute(db_to)
+        .await?;
+    }
+
+    // APP ATTEST KEYS
+    debug!("Migrating table: app_attest_keys");
+    let before = sqlx::query("select * from app_attest_keys")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from app_attest_keys")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let key_id: String = b.get("key_id");
+        let client_id: String = b.get("client_id");
+        let app_id: String = b.get("app_id");
+        let public_key: String = b.get("public_key");
+        let counter: i64 = b.get("counter");
+        let created: i64 = b.get("created");
+        let last_used: i64 = b.get("last_used");
+
+        sqlx::query(
+            r#"insert into app_attest_keys
+            (key_id, client_id, app_id, public_key, counter, created, last_used)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(key_id)
+        .bind(client_id)
+        .bind(app_id)
+        .bind(public_key)
+        .bind(counter)
+        .bind(created)
+        .bind(last_used)
+        .execute(db_to)
+        .await?;
+    }
+
+    // RESOURCE SERVERS
+    debug!("Migrating table: resource_servers");
+    let before = sqlx::query("select * from resource_servers")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from resource_servers")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let name: String = b.get("name");
+        let resource: String = b.get("resource");
+        let scopes: String = b.get("scopes");
+        let client_ids: String = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into resource_servers (id, name, resource, scopes, client_ids, created)
+            values ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(resource)
+        .bind(scopes)
+        .bind(client_ids)
+        .bind(created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USED JTIS
+    debug!("Migrating table: used_jtis");
+    let before = sqlx::query("select * from used_jtis")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from used_jtis").execute(db_to).await?;
+    for b in before {
+        let id: String = b.get("id");
+        let exp: i64 = b.get("exp");
+
+        sqlx::query("insert into used_jtis (id, exp) values ($1, $2)")
+            .bind(id)
+            .bind(exp)
+            .execute(db_to)
+            .await?;
+    }
+
+    // LAB OBJECTS
+    debug!("Migrating table: lab_objects");
+    let before = sqlx::query("select * from lab_objects")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from lab_objects")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let typ: String = b.get("typ");
+        let id: String = b.get("id");
+
+        sqlx::query("insert into lab_objects (typ, id) values ($1, $2)")
+            .bind(typ)
+            .bind(id)
+            .execute(db_to)
+            .await?;
+    }
+
+    // JOBS
+    debug!("Migrating table: jobs");
+    let before = sqlx::query_as::<_, Job>("select * from jobs")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from jobs").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO jobs
+            (id, typ, payload, status, attempts, next_run, last_error, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(b.id)
+        .bind(b.typ)
+        .bind(b.payload)
+        .bind(b.status)
+        .bind(b.attempts)
+        .bind(b.next_run)
+        .bind(b.last_error)
+        .bind(b.created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // EVENTS OUTBOX
+    debug!("Migrating table: events_outbox");
+    let before = sqlx::query("select * from events_outbox")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from events_outbox")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let payload: String = b.get("payload");
+        let created: i64 = b.get("created");
+
+        sqlx::query("insert into events_outbox (id, payload, created) values ($1, $2, $3)")
+            .bind(id)
+            .bind(payload)
+            .bind(created)
+            .execute(db_to)
+            .await?;
+    }
+
+    // EMAIL DELIVERIES
+    debug!("Migrating table: email_deliveries");
+    let before = sqlx::query_as::<_, EmailDelivery>("select * from email_deliveries")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from email_deliveries")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO email_deliveries
+            (id, address, subject, relay, status, attempts, error, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(b.id)
+        .bind(b.address)
+        .bind(b.subject)
+        .bind(b.relay)
+        .bind(b.status)
+        .bind(b.attempts)
+        .bind(b.error)
+        .bind(b.created)
+        .bind(b.updated)
+        .execute(db_to)
+        .await?;
+    }
+
+    // Skipped on purpose, because their data is transient:
+    // - cluster_nodes: each node registers itself again with its next heartbeat
+    // - email_mfa_codes, email_mfa_failures: short-lived codes and lockouts
+    // - push_mfa_requests: short-lived login approvals
+
+    Ok(())
+}
+
+pub async fn migrate_from_postgres(
+    db_from: sqlx::PgPool,
+    db_to: &DbPool,
+) -> Result<(), ErrorResponse> {
+    info!("Starting migration to another DB");
+
+    // CONFIG
+    debug!("Migrating table: config");
+    let before = sqlx::query_as::<_, ConfigEntity>("SELECT id, data FROM rauthy.config")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("DELETE FROM config").execute(db_to).await?;
+    for b in before {
+        sqlx::query!(
+            "INSERT INTO config (id, data) VALUES ($1, $2)",
+            b.id,
+            b.data,
+        )
+        .execute(db_to)
+        .await?;
+    }
+
+    // API KEYS
+    debug!("Migrating table: api_keys");
+    let before = sqlx::query_as::<_, ApiKeyEntity>("SELECT * FROM api_keys")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query!("DELETE FROM api_keys").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO
+            api_keys (name, secret, created, expires, enc_key_id, access)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(b.name)
+        .bind(b.secret)
+        .bind(b.created)
+        .bind(b.expires)
+        .bind(b.enc_key_id)
+        .bind(b.access)
+        .execute(db_to)
+        .await?;
+    }
+
+    // The users table has a FK to auth_providers - the order is important here!
+    // AUTH PROVIDERS
+    debug!("Migrating table: auth_providers");
+    let before = sqlx::query_as::<_, AuthProvider>("select * from rauthy.auth_providers")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from auth_providers")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO
+            auth_providers (id, enabled, name, typ, issuer, authorization_endpoint, token_endpoint,
+            userinfo_endpoint, client_id, secret, scope, admin_claim_path, admin_claim_value,
+            mfa_claim_path, mfa_claim_value, allow_insecure_requests, use_pkce, root_pem,
+            claim_mapping)
+            VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)"#,
+        )
+        .bind(b.id)
+        .bind(b.enabled)
+        .bind(b.name)
+        .bind(b.typ.as_str())
+        .bind(b.issuer)
+        .bind(b.authorization_endpoint)
+        .bind(b.token_endpoint)
+        .bind(b.userinfo_endpoint)
+        .bind(b.client_id)
+        .bind(b.secret)
+        .bind(b.scope)
+        .bind(b.admin_claim_path)
+        .bind(b.admin_claim_value)
+        .bind(b.mfa_claim_path)
+        .bind(b.mfa_claim_value)
+        .bind(b.allow_insecure_requests)
+        .bind(b.use_pkce)
+        .bind(b.root_pem)
+        .bind(b.claim_mapping)
+        .execute(db_to)
+        .await?;
+    }
+
+    // AUTH PROVIDER LOGOS
+    debug!("Migrating table: auth_provider_logos");
+    let before = sqlx::query(
+        "select auth_provider_id as id, res, content_type, data from rauthy.auth_provider_logos",
+    )
+    .fetch_all(&db_from)
+    .await?;
+    sqlx::query("delete from auth_provider_logos")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let res: String = b.get("res");
+        let content_type: String = b.get("content_type");
+        let data: Vec<u8> = b.get("data");
+
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO
+            auth_provider_logos (auth_provider_id, res, content_type, data)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(id)
+        .bind(res)
+        .bind(content_type)
+        .bind(data)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USERS
+    debug!("Migrating table: users");
+    let before = sqlx::query_as::<_, User>("select * from rauthy.users")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from users").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into users
+            (id, email, given_name, family_name, password, roles, groups, enabled, email_verified,
+            password_expires, created_at, last_login, last_failed_login, failed_login_attempts,
+            language, webauthn_user_id, user_expires, auth_provider_id, federation_uid, username,
+            email_mfa)
+            values
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
+            $20, $21)"#,
+        )
+        .bind(b.id)
+        .bind(b.email)
+        .bind(b.given_name)
+        .bind(b.family_name)
+        .bind(b.password)
+        .bind(b.roles)
+        .bind(b.groups)
+        .bind(b.enabled)
+        .bind(b.email_verified)
+        .bind(b.password_expires)
+        .bind(b.created_at)
+        .bind(b.last_login)
+        .bind(b.last_failed_login)
+        .bind(b.failed_login_attempts)
+        .bind(b.language)
+        .bind(b.webauthn_user_id)
+        .bind(b.user_expires)
+        .bind(b.auth_provider_id)
+        .bind(b.federation_uid)
+        .bind(b.username)
+        .bind(b.email_mfa)
+        .execute(db_to)
+        .await?;
+    }
+
+    // PASSKEYS
+    debug!("Migrating table: passkeys");
+    let before = sqlx::query_as::<_, PasskeyEntity>("SELECT * FROM rauthy.passkeys")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("DELETE FROM passkeys").execute(db_to).await?;
+    for b in before {
+        sqlx::query!(
+            r#"INSERT INTO passkeys
+            (user_id, name, passkey_user_id, passkey, credential_id, registered, last_used, user_verified)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            b.user_id,
+            b.name,
+            b.passkey_user_id,
+            b.passkey,
+            b.credential_id,
+            b.registered,
+            b.last_used,
+            b.user_verified,
+        )
+            .execute(db_to)
+            .await?;
+    }
+
+    // Do not change the order - tables below have FKs to clients
+    // CLIENTS
+    debug!("Migrating table: clients");
+    let before = sqlx::query_as::<_, Client>("select * from rauthy.clients")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from clients").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into clients (id, name, enabled, confidential, secret, secret_kid,
+            redirect_uris, post_logout_redirect_uris, allowed_origins, flows_enabled, access_token_alg,
+            id_token_alg, refresh_token, auth_code_lifetime, access_token_lifetime, scopes, default_scopes,
+            challenge, force_mfa, client_uri, contacts, allowed_cidrs, jwks_uri,
+            require_signed_request_object, id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc, userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc, authorization_details_types, token_exchange_audiences)
+            values
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19,
+            $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)"#)
+            .bind(&b.id)
+            .bind(&b.name)
+            .bind(b.enabled)
+            .bind(b.confidential)
+            .bind(&b.secret)
+            .bind(&b.secret_kid)
+            .bind(&b.redirect_uris)
+            .bind(&b.post_logout_redirect_uris)
+            .bind(&b.allowed_origins)
+            .bind(&b.flows_enabled)
+            .bind(&b.access_token_alg)
+            .bind(&b.id_token_alg)
+            .bind(b.refresh_token)
+            .bind(b.auth_code_lifetime)
+            .bind(b.access_token_lifetime)
+            .bind(&b.scopes)
+            .bind(&b.default_scopes)
+            .bind(&b.challenge)
+            .bind(b.force_mfa)
+            .bind(b.client_uri)
+            .bind(b.contacts)
+            .bind(b.allowed_cidrs)
+            .bind(b.jwks_uri)
+            .bind(b.require_signed_request_object)
+            .bind(b.id_token_encrypted_response_alg)
+            .bind(b.id_token_encrypted_response_enc)
+            .bind(b.userinfo_encrypted_response_alg)
+            .bind(b.userinfo_encrypted_response_enc)
+            .bind(b.authorization_details_types)
+            .bind(b.token_exchange_audiences)
+            .execute(db_to)
+            .await?;
+    }
+
+    // CLIENTS DYN
+    debug!("Migrating table: clients_dyn");
+    let before = sqlx::query_as::<_, ClientDyn>("select * from rauthy.clients_dyn")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from clients_dyn")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO
+            clients_dyn (id, created, registration_token, token_endpoint_auth_method)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(&b.id)
+        .bind(b.created)
+        .bind(&b.registration_token)
+        .bind(&b.token_endpoint_auth_method)
+        .execute(db_to)
+        .await?;
+    }
+
+    // CLIENT LOGOS
+    let before = sqlx::query("select * from rauthy.client_logos")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from client_logos")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("client_id");
+        let res: String = b.get("res");
+        let content_type: String = b.get("content_type");
+        let data: Vec<u8> = b.get("data");
+
+        sqlx::query(
+            r#"INSERT INTO client_logos (client_id, res, content_type, data)
+                VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(id)
+        .bind(res)
+        .bind(content_type)
+        .bind(data)
+        .execute(db_to)
+        .await?;
+    }
+
+    // COLORS
+    let before = sqlx::query_as::<_, ColorEntity>("select * from rauthy.colors")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from colors").execute(db_to).await?;
+    for b in before {
+        sqlx::query("insert into colors (client_id, data) values ($1, $2)")
             .bind(b.client_id)
             .bind(b.data)
             .execute(db_to)
             .await?;
     }
 
-    // GROUPS
-    let before = sqlx::query_as::<_, Group>("select * from rauthy.groups")
+    // GROUPS
+    let before = sqlx::query_as::<_, Group>("select * from rauthy.groups")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from groups").execute(db_to).await?;
+    for b in before {
+        sqlx::query("insert into groups (id, name, domains) values ($1, $2, $3)")
+            .bind(b.id)
+            .bind(b.name)
+            .bind(b.domains)
+            .execute(db_to)
+            .await?;
+    }
+
+    // JWKS
+    let before = sqlx::query_as::<_, Jwk>("select * from rauthy.jwks")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from jwks").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into jwks (kid, created_at, signature, enc_key_id, jwk)
+            values ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(&b.kid)
+        .bind(b.created_at)
+        .bind(b.signature.as_str())
+        .bind(&b.enc_key_id)
+        .bind(&b.jwk)
+        .execute(db_to)
+        .await?;
+    }
+
+    // MAGIC LINKS
+    let before = sqlx::query_as::<_, MagicLink>("select * from rauthy.magic_links")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from magic_links")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into magic_links
+            (id, user_id, csrf_token, cookie, exp, used, usage)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(&b.id)
+        .bind(&b.user_id)
+        .bind(&b.csrf_token)
+        .bind(&b.cookie)
+        .bind(b.exp)
+        .bind(b.used)
+        .bind(b.usage)
+        .execute(db_to)
+        .await?;
+    }
+
+    // PASSWORD POLICY
+    let res = sqlx::query("select data from rauthy.config where id = 'password_policy'")
+        .fetch_one(&db_from)
+        .await?;
+    let bytes: Vec<u8> = res.get("data");
+    sqlx::query("update config set data = $1 where id = 'password_policy'")
+        .bind(bytes)
+        .execute(db_to)
+        .await?;
+
+    // REFRESH TOKENS
+    let before = sqlx::query_as::<_, RefreshToken>("select * from rauthy.refresh_tokens")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from refresh_tokens")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into refresh_tokens
+            (id, user_id, nbf, exp, scope, is_mfa, client_id, chain_exp, device_fp)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(&b.id)
+        .bind(&b.user_id)
+        .bind(b.nbf)
+        .bind(b.exp)
+        .bind(&b.scope)
+        .bind(b.is_mfa)
+        .bind(&b.client_id)
+        .bind(b.chain_exp)
+        .bind(&b.device_fp)
+        .execute(db_to)
+        .await?;
+    }
+
+    // ROLES
+    let before = sqlx::query_as::<_, Role>("select * from rauthy.roles")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from roles").execute(db_to).await?;
+    for b in before {
+        sqlx::query("insert into roles (id, name) values ($1, $2)")
+            .bind(b.id)
+            .bind(b.name)
+            .execute(db_to)
+            .await?;
+    }
+
+    // SCOPES
+    let before = sqlx::query_as::<_, Scope>("select * from rauthy.scopes")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from scopes").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into scopes
+            (id, name, attr_include_access, attr_include_id, i18n, client_ids)
+            values ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(b.id)
+        .bind(b.name)
+        .bind(b.attr_include_access)
+        .bind(b.attr_include_id)
+        .bind(b.i18n)
+        .bind(b.client_ids)
+        .execute(db_to)
+        .await?;
+    }
+
+    // EVENTS
+    let before = sqlx::query("select * from rauthy.events")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from events").execute(db_to).await?;
+    for b in before {
+        let id: String = b.get("id");
+        let timestamp: i64 = b.get("timestamp");
+        let level: i16 = b.get("level");
+        let typ: i16 = b.get("typ");
+        let ip: Option<String> = b.get("ip");
+        let data: Option<i64> = b.get("data");
+        let text: Option<String> = b.get("text");
+        let seq: Option<i64> = b.get("seq");
+        let prev_hash: Option<String> = b.get("prev_hash");
+        let hash: Option<String> = b.get("hash");
+        let anonymized: bool = b.get("anonymized");
+        let user_id: Option<String> = b.get("user_id");
+
+        sqlx::query(
+            r#"INSERT INTO events
+            (id, timestamp, level, typ, ip, data, text, seq, prev_hash, hash, anonymized, user_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
+        )
+        .bind(id)
+        .bind(timestamp)
+        .bind(level)
+        .bind(typ)
+        .bind(ip)
+        .bind(data)
+        .bind(text)
+        .bind(seq)
+        .bind(prev_hash)
+        .bind(hash)
+        .bind(anonymized)
+        .bind(user_id)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER ATTR CONFIG
+    let before = sqlx::query_as::<_, UserAttrConfigEntity>("select * from rauthy.user_attr_config")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from user_attr_config")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        match *DB_TYPE {
+            DbType::Sqlite => sqlx::query(
+                r#"insert into user_attr_config (name, desc, required, skip_client_ids, sensitive)
+                values ($1, $2, $3, $4, $5)"#,
+            ),
+            DbType::Postgres => sqlx::query(
+                r#"insert into user_attr_config (name, "desc", required, skip_client_ids, sensitive)
+                values ($1, $2, $3, $4, $5)"#,
+            ),
+        }
+        .bind(b.name)
+        .bind(b.desc)
+        .bind(b.required)
+        .bind(b.skip_client_ids)
+        .bind(b.sensitive)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER ATTR VALUES
+    let before = sqlx::query_as::<_, UserAttrValueEntity>("select * from rauthy.user_attr_values")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from user_attr_values")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("insert into user_attr_values (user_id, key, value) values ($1, $2, $3)")
+            .bind(b.user_id)
+            .bind(b.key)
+            .bind(b.value)
+            .execute(db_to)
+            .await?;
+    }
+
+    // USERS VALUES
+    debug!("Migrating table: users_values");
+    let before = sqlx::query_as::<_, UserValues>("select * from rauthy.users_values")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from users_values")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO
+            users_values (id, birthdate, phone, street, zip, city, country)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(b.id)
+        .bind(b.birthdate)
+        .bind(b.phone)
+        .bind(b.street)
+        .bind(b.zip)
+        .bind(b.city)
+        .bind(b.country)
+        .execute(db_to)
+        .await?;
+    }
+
+    // SESSIONS
+    let before = sqlx::query_as::<_, Session>("select * from rauthy.sessions")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from sessions").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into
+            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen,
+                elevated_until)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+        )
+        .bind(&b.id)
+        .bind(&b.csrf_token)
+        .bind(&b.user_id)
+        .bind(&b.roles)
+        .bind(&b.groups)
+        .bind(b.is_mfa)
+        .bind(b.state.as_str())
+        .bind(b.exp)
+        .bind(b.last_seen)
+        .bind(b.elevated_until)
+        .execute(db_to)
+        .await?;
+    }
+
+    // SESSIONS SID
+    let before = sqlx::query("select * from rauthy.sessions_sid")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from sessions_sid")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let sid: String = b.get("sid");
+        let session_id: String = b.get("session_id");
+
+        sqlx::query("insert into sessions_sid (sid, session_id) values ($1, $2)")
+            .bind(sid)
+            .bind(session_id)
+            .execute(db_to)
+            .await?;
+    }
+
+    // RECENT PASSWORDS
+    let before =
+        sqlx::query_as::<_, RecentPasswordsEntity>("select * from rauthy.recent_passwords")
+            .fetch_all(&db_from)
+            .await?;
+    sqlx::query("delete from recent_passwords")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("insert into recent_passwords (user_id, passwords) values ($1, $2)")
+            .bind(b.user_id)
+            .bind(b.passwords)
+            .execute(db_to)
+            .await?;
+    }
+
+    // WEBIDS
+    debug!("Migrating table: webids");
+    let before = sqlx::query_as::<_, WebId>("select * from rauthy.webids")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from webids").execute(db_to).await?;
+    for b in before {
+        sqlx::query(
+            "INSERT INTO webids (user_id, custom_triples, expose_email) VALUES ($1, $2, $3)",
+        )
+        .bind(b.user_id)
+        .bind(b.custom_triples)
+        .bind(b.expose_email)
+        .execute(db_to)
+        .await?;
+    }
+
+    // GROUP LOGIN POLICIES
+    debug!("Migrating table: group_login_policies");
+    let before = sqlx::query_as::<_, GroupLoginPolicy>("select * from rauthy.group_login_policies")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from group_login_policies")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO group_login_policies
+            (group_id, weekdays, time_from, time_to, utc_offset, timezone)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(b.group_id)
+        .bind(b.weekdays)
+        .bind(b.time_from)
+        .bind(b.time_to)
+        .bind(b.utc_offset)
+        .bind(b.timezone)
+        .execute(db_to)
+        .await?;
+    }
+
+    // LOGIN POLICY OVERRIDES
+    debug!("Migrating table: login_policy_overrides");
+    let before =
+        sqlx::query_as::<_, LoginPolicyOverride>("select * from rauthy.login_policy_overrides")
+            .fetch_all(&db_from)
+            .await?;
+    sqlx::query("delete from login_policy_overrides")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("INSERT INTO login_policy_overrides (user_id, exp) VALUES ($1, $2)")
+            .bind(b.user_id)
+            .bind(b.exp)
+            .execute(db_to)
+            .await?;
+    }
+
+    // LEGAL HOLDS
+    debug!("Migrating table: legal_holds");
+    let before = sqlx::query_as::<_, LegalHold>("select * from rauthy.legal_holds")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from legal_holds")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("INSERT INTO legal_holds (user_id, reason, created) VALUES ($1, $2, $3)")
+            .bind(b.user_id)
+            .bind(b.reason)
+            .bind(b.created)
+            .execute(db_to)
+            .await?;
+    }
+
+    // EMAIL ALIASES
+    debug!("Migrating table: email_aliases");
+    let before = sqlx::query_as::<_, EmailAlias>("select * from rauthy.email_aliases")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from email_aliases")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query("INSERT INTO email_aliases (email, user_id, created) VALUES ($1, $2, $3)")
+            .bind(b.email)
+            .bind(b.user_id)
+            .bind(b.created)
+            .execute(db_to)
+            .await?;
+    }
+
+    // ORGANIZATIONS
+    debug!("Migrating table: organizations");
+    let before = sqlx::query_as::<_, Organization>("select * from rauthy.organizations")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from organizations")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            "INSERT INTO organizations (id, name, domains, created) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(b.id)
+        .bind(b.name)
+        .bind(b.domains)
+        .bind(b.created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // ORGANIZATION MEMBERS
+    debug!("Migrating table: organization_members");
+    let before =
+        sqlx::query_as::<_, OrganizationMember>("select * from rauthy.organization_members")
+            .fetch_all(&db_from)
+            .await?;
+    sqlx::query("delete from organization_members")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO organization_members (org_id, user_id, is_owner, created)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(b.org_id)
+        .bind(b.user_id)
+        .bind(b.is_owner)
+        .bind(b.created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // CLIENT USER AGENTS
+    let before = sqlx::query_as::<_, ClientUserAgent>("select * from rauthy.client_user_agents")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from client_user_agents")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO client_user_agents
+            (client_id, user_agent, first_seen, last_seen, requests)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.client_id)
+        .bind(b.user_agent)
+        .bind(b.first_seen)
+        .bind(b.last_seen)
+        .bind(b.requests)
+        .execute(db_to)
+        .await?;
+    }
+
+    // CLIENT SECRET READS
+    debug!("Migrating table: client_secret_reads");
+    let before = sqlx::query_as::<_, ClientSecretRead>("select * from rauthy.client_secret_reads")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from client_secret_reads")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO client_secret_reads
+            (client_id, reads, last_read, last_actor, last_ip)
+            VALUES ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.client_id)
+        .bind(b.reads)
+        .bind(b.last_read)
+        .bind(b.last_actor)
+        .bind(b.last_ip)
+        .execute(db_to)
+        .await?;
+    }
+
+    // REVOKED TOKENS
+    let before = sqlx::query_as::<_, RevokedToken>("select * from rauthy.revoked_tokens")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from revoked_tokens")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, exp, reason, created) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(b.jti)
+        .bind(b.exp)
+        .bind(b.reason)
+        .bind(b.created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // JWKS LIFECYCLE
+    let before = sqlx::query_as::<_, JwkLifecycle>("select * from rauthy.jwks_lifecycle")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from jwks_lifecycle")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"insert into jwks_lifecycle (kid, imported, activate_at, retired_at, usage_count)
+            values ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.kid)
+        .bind(b.imported)
+        .bind(b.activate_at)
+        .bind(b.retired_at)
+        .bind(b.usage_count)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USER CLIENT GRANTS
+    let before = sqlx::query_as::<_, UserClientGrant>("select * from rauthy.user_client_grants")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from groups").execute(db_to).await?;
+    sqlx::query("delete from user_client_grants")
+        .execute(db_to)
+        .await?;
     for b in before {
-        sqlx::query("insert into groups (id, name) values ($1, $2)")
-            .bind(b.id)
-            .bind(b.name)
-            .execute(db_to)
-            .await?;
+        sqlx::query(
+            r#"insert into user_client_grants (user_id, client_id, scope, created, last_used)
+            values ($1, $2, $3, $4, $5)"#,
+        )
+        .bind(b.user_id)
+        .bind(b.client_id)
+        .bind(b.scope)
+        .bind(b.created)
+        .bind(b.last_used)
+        .execute(db_to)
+        .await?;
     }
 
-    // JWKS
-    let before = sqlx::query_as::<_, Jwk>("select * from rauthy.jwks")
-        .fetch_all(&db_from)
+    // USER NOTIFICATION PREFS
+    let before =
+        sqlx::query_as::<_, UserNotificationPrefs>("select * from rauthy.user_notification_prefs")
+            .fetch_all(&db_from)
+            .await?;
+    sqlx::query("delete from user_notification_prefs")
+        .execute(db_to)
         .await?;
-    sqlx::query("delete from jwks").execute(db_to).await?;
     for b in before {
         sqlx::query(
-            r#"insert into jwks (kid, created_at, signature, enc_key_id, jwk)
-            values ($1, $2, $3, $4, $5)"#,
+            r#"insert into user_notification_prefs (user_id, password_expiry, sessions_revoked)
+            values ($1, $2, $3)"#,
         )
-        .bind(&b.kid)
-        .bind(b.created_at)
-        .bind(b.signature.as_str())
-        .bind(&b.enc_key_id)
-        .bind(&b.jwk)
+        .bind(b.user_id)
+        .bind(b.password_expiry)
+        .bind(b.sessions_revoked)
         .execute(db_to)
         .await?;
     }
 
-    // MAGIC LINKS
-    let before = sqlx::query_as::<_, MagicLink>("select * from rauthy.magic_links")
+    // USER ACTIVITY
+    let before = sqlx::query_as::<_, UserActivity>("select * from rauthy.user_activity")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from magic_links")
+    sqlx::query("delete from user_activity")
         .execute(db_to)
         .await?;
     for b in before {
         sqlx::query(
-            r#"insert into magic_links
-            (id, user_id, csrf_token, cookie, exp, used, usage)
-            values ($1, $2, $3, $4, $5, $6, $7)"#,
+            r#"insert into user_activity (user_id, last_refresh, last_userinfo)
+            values ($1, $2, $3)"#,
         )
-        .bind(&b.id)
-        .bind(&b.user_id)
-        .bind(&b.csrf_token)
-        .bind(&b.cookie)
-        .bind(b.exp)
-        .bind(b.used)
-        .bind(b.usage)
+        .bind(b.user_id)
+        .bind(b.last_refresh)
+        .bind(b.last_userinfo)
         .execute(db_to)
         .await?;
     }
 
-    // PASSWORD POLICY
-    let res = sqlx::query("select data from rauthy.config where id = 'password_policy'")
+    // EVENT CHAIN HEAD
+    let before = sqlx::query("select seq, hash from rauthy.event_chain_head where id = 1")
         .fetch_one(&db_from)
         .await?;
-    let bytes: Vec<u8> = res.get("data");
-    sqlx::query("update config set data = $1 where id = 'password_policy'")
-        .bind(bytes)
+    let seq: i64 = before.get("seq");
+    let hash: String = before.get("hash");
+    sqlx::query("update event_chain_head set seq = $1, hash = $2 where id = 1")
+        .bind(seq)
+        .bind(hash)
         .execute(db_to)
         .await?;
 
-    // REFRESH TOKENS
-    let before = sqlx::query_as::<_, RefreshToken>("select * from rauthy.refresh_tokens")
+    // EVENT ANCHORS
+    let before = sqlx::query("select * from rauthy.event_anchors")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from refresh_tokens")
+    sqlx::query("delete from event_anchors")
         .execute(db_to)
         .await?;
     for b in before {
+        let seq: i64 = b.get("seq");
+        let hash: String = b.get("hash");
+        let token: String = b.get("token");
+        let created: i64 = b.get("created");
+
         sqlx::query(
-            r#"insert into refresh_tokens (id, user_id, nbf, exp, scope)
-            values ($1, $2, $3, $4, $5)"#,
+            r#"insert into event_anchors (seq, hash, token, created)
+            values ($1, $2, $3, $4)"#,
         )
-        .bind(&b.id)
-        .bind(&b.user_id)
-        .bind(b.nbf)
-        .bind(b.exp)
-        .bind(&b.scope)
+        .bind(seq)
+        .bind(hash)
+        .bind(token)
+        .bind(created)
         .execute(db_to)
         .await?;
     }
 
-    // ROLES
-    let before = sqlx::query_as::<_, Role>("select * from rauthy.roles")
+    // EVENT CHAIN PRUNED
+    let before = sqlx::query("select * from rauthy.event_chain_pruned")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from roles").execute(db_to).await?;
+    sqlx::query("delete from event_chain_pruned")
+        .execute(db_to)
+        .await?;
     for b in before {
-        sqlx::query("insert into roles (id, name) values ($1, $2)")
-            .bind(b.id)
-            .bind(b.name)
+        let seq: i64 = b.get("seq");
+        let prev_hash: String = b.get("prev_hash");
+        let hash: String = b.get("hash");
+
+        sqlx::query("insert into event_chain_pruned (seq, prev_hash, hash) values ($1, $2, $3)")
+            .bind(seq)
+            .bind(prev_hash)
+            .bind(hash)
             .execute(db_to)
             .await?;
     }
 
-    // SCOPES
-    let before = sqlx::query_as::<_, Scope>("select * from rauthy.scopes")
+    // MFA RECOVERIES
+    debug!("Migrating table: mfa_recoveries");
+    let before = sqlx::query("select * from rauthy.mfa_recoveries")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from scopes").execute(db_to).await?;
+    sqlx::query("delete from mfa_recoveries")
+        .execute(db_to)
+        .await?;
     for b in before {
+        let id: String = b.get("id");
+        let user_id: String = b.get("user_id");
+        let status: String = b.get("status");
+        let created: i64 = b.get("created");
+        let confirmed: Option<i64> = b.get("confirmed");
+        let not_before: Option<i64> = b.get("not_before");
+        let approved_by: Option<String> = b.get("approved_by");
+        let exp: i64 = b.get("exp");
+
         sqlx::query(
-            r#"insert into scopes (id, name, attr_include_access, attr_include_id)
-            values ($1, $2, $3, $4)"#,
+            r#"insert into mfa_recoveries
+            (id, user_id, status, created, confirmed, not_before, approved_by, exp)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)"#,
         )
-        .bind(b.id)
-        .bind(b.name)
-        .bind(b.attr_include_access)
-        .bind(b.attr_include_id)
+        .bind(id)
+        .bind(user_id)
+        .bind(status)
+        .bind(created)
+        .bind(confirmed)
+        .bind(not_before)
+        .bind(approved_by)
+        .bind(exp)
         .execute(db_to)
         .await?;
     }
 
-    // EVENTS
-    let before = sqlx::query("select * from rauthy.events")
+    // USER ROLE GRANTS
+    debug!("Migrating table: user_role_grants");
+    let before = sqlx::query("select * from rauthy.user_role_grants")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from events").execute(db_to).await?;
+    sqlx::query("delete from user_role_grants")
+        .execute(db_to)
+        .await?;
     for b in before {
         let id: String = b.get("id");
-        let timestamp: i64 = b.get("timestamp");
-        let level: i16 = b.get("level");
-        let typ: i16 = b.get("typ");
-        let ip: Option<String> = b.get("ip");
-        let data: Option<i64> = b.get("data");
-        let text: Option<String> = b.get("text");
+        let user_id: String = b.get("user_id");
+        let role: String = b.get("role");
+        let reason: String = b.get("reason");
+        let approved_by: String = b.get("approved_by");
+        let created: i64 = b.get("created");
+        let exp: i64 = b.get("exp");
 
         sqlx::query(
-            r#"INSERT INTO events (id, timestamp, level, typ, ip, data, text)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            r#"insert into user_role_grants
+            (id, user_id, role, reason, approved_by, created, exp)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
         )
         .bind(id)
-        .bind(timestamp)
-        .bind(level)
-        .bind(typ)
-        .bind(ip)
-        .bind(data)
-        .bind(text)
+        .bind(user_id)
+        .bind(role)
+        .bind(reason)
+        .bind(approved_by)
+        .bind(created)
+        .bind(exp)
         .execute(db_to)
         .await?;
     }
 
-    // USER ATTR CONFIG
-    let before = sqlx::query_as::<_, UserAttrConfigEntity>("select * from rauthy.user_attr_config")
+    // ANNOUNCEMENTS
+    debug!("Migrating table: announcements");
+    let before = sqlx::query("select * from rauthy.announcements")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from user_attr_config")
+    sqlx::query("delete from announcements")
         .execute(db_to)
         .await?;
     for b in before {
-        match *DB_TYPE {
-            DbType::Sqlite => {
-                sqlx::query("insert into user_attr_config (name, desc) values ($1, $2)")
-            }
-            DbType::Postgres => {
-                sqlx::query("insert into user_attr_config (name, \"desc\") values ($1, $2)")
-            }
-        }
-        .bind(b.name)
-        .bind(b.desc)
+        let id: String = b.get("id");
+        let message: String = b.get("message");
+        let severity: String = b.get("severity");
+        let nbf: i64 = b.get("nbf");
+        let exp: Option<i64> = b.get("exp");
+        let client_ids: Option<String> = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into announcements
+            (id, message, severity, nbf, exp, client_ids, created)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
+        )
+        .bind(id)
+        .bind(message)
+        .bind(severity)
+        .bind(nbf)
+        .bind(exp)
+        .bind(client_ids)
+        .bind(created)
         .execute(db_to)
         .await?;
     }
 
-    // USER ATTR VALUES
-    let before = sqlx::query_as::<_, UserAttrValueEntity>("select * from rauthy.user_attr_values")
+    // FEATURE FLAGS
+    debug!("Migrating table: feature_flags");
+    let before = sqlx::query("select * from rauthy.feature_flags")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from user_attr_values")
+    sqlx::query("delete from feature_flags")
         .execute(db_to)
         .await?;
     for b in before {
-        sqlx::query("insert into user_attr_values (user_id, key, value) values ($1, $2, $3)")
-            .bind(b.user_id)
-            .bind(b.key)
-            .bind(b.value)
-            .execute(db_to)
-            .await?;
+        let id: String = b.get("id");
+        let name: String = b.get("name");
+        let description: Option<String> = b.get("description");
+        let enabled: bool = b.get("enabled");
+        let percentage: Option<i64> = b.get("percentage");
+        let groups: Option<String> = b.get("groups");
+        let attr_key: Option<String> = b.get("attr_key");
+        let attr_value: Option<String> = b.get("attr_value");
+        let client_ids: Option<String> = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into feature_flags
+            (id, name, description, enabled, percentage, groups, attr_key, attr_value, client_ids,
+            created)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(description)
+        .bind(enabled)
+        .bind(percentage)
+        .bind(groups)
+        .bind(attr_key)
+        .bind(attr_value)
+        .bind(client_ids)
+        .bind(created)
+        .execute(db_to)
+        .await?;
     }
 
-    // USERS VALUES
-    debug!("Migrating table: users_values");
-    let before = sqlx::query_as::<_, UserValues>("select * from rauthy.users_values")
+    // USER MERGES
+    debug!("Migrating table: user_merges");
+    let before = sqlx::query("select * from rauthy.user_merges")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from users_values")
+    sqlx::query("delete from user_merges")
         .execute(db_to)
         .await?;
     for b in before {
+        let old_id: String = b.get("old_id");
+        let new_id: String = b.get("new_id");
+        let merged_by: String = b.get("merged_by");
+        let merged_at: i64 = b.get("merged_at");
+
         sqlx::query(
-            r#"INSERT OR REPLACE INTO
-            users_values (id, birthdate, phone, street, zip, city, country)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            r#"insert into user_merges (old_id, new_id, merged_by, merged_at)
+            values ($1, $2, $3, $4)"#,
         )
-        .bind(b.id)
-        .bind(b.birthdate)
-        .bind(b.phone)
-        .bind(b.street)
-        .bind(b.zip)
-        .bind(b.city)
-        .bind(b.country)
+        .bind(old_id)
+        .bind(new_id)
+        .bind(merged_by)
+        .bind(merged_at)
         .execute(db_to)
         .await?;
     }
 
-    // SESSIONS
-    let before = sqlx::query_as::<_, Session>("select * from rauthy.sessions")
+    // PUSH SUBSCRIPTIONS
+    debug!("Migrating table: push_subscriptions");
+    let before = sqlx::query("select * from rauthy.push_subscriptions")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from sessions").execute(db_to).await?;
+    sqlx::query("delete from push_subscriptions")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let user_id: String = b.get("user_id");
+        let name: String = b.get("name");
+        let endpoint: String = b.get("endpoint");
+        let p256dh: String = b.get("p256dh");
+        let auth: String = b.get("auth");
+        let created: i64 = b.get("created");
+        let last_used: Option<i64> = b.get("last_used");
+
+        sqlx::query(
+            r#"insert into push_subscriptions
+            (id, user_id, name, endpoint, p256dh, auth, created, last_used)
+            values ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(endpoint)
+        .bind(p256dh)
+        .bind(auth)
+        .bind(created)
+        .bind(last_used)
+        .execute(db_to)
+        .await?;
+    }
+
+    // APP ATTEST KEYS
+    debug!("Migrating table: app_attest_keys");
+    let before = sqlx::query("select * from rauthy.app_attest_keys")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from app_attest_keys")
+        .execute(db_to)
+        .await?;
     for b in before {
+        let key_id: String = b.get("key_id");
+        let client_id: String = b.get("client_id");
+        let app_id: String = b.get("app_id");
+        let public_key: String = b.get("public_key");
+        let counter: i64 = b.get("counter");
+        let created: i64 = b.get("created");
+        let last_used: i64 = b.get("last_used");
+
         sqlx::query(
-            r#"insert into
-            sessions (id, csrf_token, user_id, roles, groups, is_mfa, state, exp, last_seen)
-            values ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+            r#"insert into app_attest_keys
+            (key_id, client_id, app_id, public_key, counter, created, last_used)
+            values ($1, $2, $3, $4, $5, $6, $7)"#,
         )
-        .bind(&b.id)
-        .bind(&b.csrf_token)
-        .bind(&b.user_id)
-        .bind(&b.roles)
-        .bind(&b.groups)
-        .bind(b.is_mfa)
-        .bind(b.state.as_str())
-        .bind(b.exp)
-        .bind(b.last_seen)
+        .bind(key_id)
+        .bind(client_id)
+        .bind(app_id)
+        .bind(public_key)
+        .bind(counter)
+        .bind(created)
+        .bind(last_used)
         .execute(db_to)
         .await?;
     }
 
-    // RECENT PASSWORDS
-    let before =
-        sqlx::query_as::<_, RecentPasswordsEntity>("select * from rauthy.recent_passwords")
-            .fetch_all(&db_from)
+    // RESOURCE SERVERS
+    debug!("Migrating table: resource_servers");
+    let before = sqlx::query("select * from rauthy.resource_servers")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from resource_servers")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let name: String = b.get("name");
+        let resource: String = b.get("resource");
+        let scopes: String = b.get("scopes");
+        let client_ids: String = b.get("client_ids");
+        let created: i64 = b.get("created");
+
+        sqlx::query(
+            r#"insert into resource_servers (id, name, resource, scopes, client_ids, created)
+            values ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(id)
+        .bind(name)
+        .bind(resource)
+        .bind(scopes)
+        .bind(client_ids)
+        .bind(created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // USED JTIS
+    debug!("Migrating table: used_jtis");
+    let before = sqlx::query("select * from rauthy.used_jtis")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from used_jtis").execute(db_to).await?;
+    for b in before {
+        let id: String = b.get("id");
+        let exp: i64 = b.get("exp");
+
+        sqlx::query("insert into used_jtis (id, exp) values ($1, $2)")
+            .bind(id)
+            .bind(exp)
+            .execute(db_to)
             .await?;
-    sqlx::query("delete from recent_passwords")
+    }
+
+    // LAB OBJECTS
+    debug!("Migrating table: lab_objects");
+    let before = sqlx::query("select * from rauthy.lab_objects")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from lab_objects")
         .execute(db_to)
         .await?;
     for b in before {
-        sqlx::query("insert into recent_passwords (user_id, passwords) values ($1, $2)")
-            .bind(b.user_id)
-            .bind(b.passwords)
+        let typ: String = b.get("typ");
+        let id: String = b.get("id");
+
+        sqlx::query("insert into lab_objects (typ, id) values ($1, $2)")
+            .bind(typ)
+            .bind(id)
             .execute(db_to)
             .await?;
     }
 
-    // WEBIDS
-    debug!("Migrating table: webids");
-    let before = sqlx::query_as::<_, WebId>("select * from rauthy.webids")
+    // JOBS
+    debug!("Migrating table: jobs");
+    let before = sqlx::query_as::<_, Job>("select * from rauthy.jobs")
         .fetch_all(&db_from)
         .await?;
-    sqlx::query("delete from webids").execute(db_to).await?;
+    sqlx::query("delete from jobs").execute(db_to).await?;
     for b in before {
         sqlx::query(
-            "INSERT INTO webids (user_id, custom_triples, expose_email) VALUES ($1, $2, $3)",
+            r#"INSERT INTO jobs
+            (id, typ, payload, status, attempts, next_run, last_error, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
         )
-        .bind(b.user_id)
-        .bind(b.custom_triples)
-        .bind(b.expose_email)
+        .bind(b.id)
+        .bind(b.typ)
+        .bind(b.payload)
+        .bind(b.status)
+        .bind(b.attempts)
+        .bind(b.next_run)
+        .bind(b.last_error)
+        .bind(b.created)
+        .execute(db_to)
+        .await?;
+    }
+
+    // EVENTS OUTBOX
+    debug!("Migrating table: events_outbox");
+    let before = sqlx::query("select * from rauthy.events_outbox")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from events_outbox")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        let id: String = b.get("id");
+        let payload: String = b.get("payload");
+        let created: i64 = b.get("created");
+
+        sqlx::query("insert into events_outbox (id, payload, created) values ($1, $2, $3)")
+            .bind(id)
+            .bind(payload)
+            .bind(created)
+            .execute(db_to)
+            .await?;
+    }
+
+    // EMAIL DELIVERIES
+    debug!("Migrating table: email_deliveries");
+    let before = sqlx::query_as::<_, EmailDelivery>("select * from rauthy.email_deliveries")
+        .fetch_all(&db_from)
+        .await?;
+    sqlx::query("delete from email_deliveries")
+        .execute(db_to)
+        .await?;
+    for b in before {
+        sqlx::query(
+            r#"INSERT INTO email_deliveries
+            (id, address, subject, relay, status, attempts, error, created, updated)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+        )
+        .bind(b.id)
+        .bind(b.address)
+        .bind(b.subject)
+        .bind(b.relay)
+        .bind(b.status)
+        .bind(b.attempts)
+        .bind(b.error)
+        .bind(b.created)
+        .bind(b.updated)
         .execute(db_to)
         .await?;
     }
 
+    // Skipped on purpose, because their data is transient:
+    // - cluster_nodes: each node registers itself again with its next heartbeat
+    // - email_mfa_codes, email_mfa_failures: short-lived codes and lockouts
+    // - push_mfa_requests: short-lived login approvals
+
     Ok(())
 }
@@ -0,0 +1,297 @@
+//! Field-level encryption for personal data with the `ENC_KEYS`.
+//!
+//! Encrypted values are stored with an `enc:` prefix inside the same column. This makes it
+//! possible to read plaintext and encrypted values side by side, so `PII_ENCRYPTION` can be
+//! enabled on an existing database without any migration. Reading never depends on the config.
+//!
+//! E-Mail addresses are used for lookups and need a deterministic encryption instead. They are
+//! stored with a `denc:{key_id}:` prefix. The nonce is derived from the value itself (SIV), so
+//! the same address always results in the same ciphertext for the same key.
+
+use cryptr::{EncKeys, EncValue};
+use rauthy_common::constants::{PII_ENCRYPTION, PII_ENCRYPTION_EMAIL};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::{base64_decode, base64_encode};
+use ring::{aead, hmac};
+
+const ENC_PREFIX: &str = "enc:";
+const DET_PREFIX: &str = "denc:";
+const NONCE_LEN: usize = 12;
+
+/// Encrypts the value with the active key, if `PII_ENCRYPTION` is enabled.
+pub fn encrypt_str(value: Option<String>) -> Result<Option<String>, ErrorResponse> {
+    match value {
+        Some(v) if *PII_ENCRYPTION && !is_encrypted_str(&v) => {
+            let enc = EncValue::encrypt(v.as_bytes())?.into_bytes();
+            Ok(Some(format!("{}{}", ENC_PREFIX, base64_encode(&enc))))
+        }
+        v => Ok(v),
+    }
+}
+
+/// Returns the cleartext for a possibly encrypted value.
+pub fn decrypt_str(value: Option<String>) -> Result<Option<String>, ErrorResponse> {
+    match value {
+        Some(v) => match v.strip_prefix(ENC_PREFIX) {
+            Some(b64) => {
+                let dec = EncValue::try_from(base64_decode(b64)?)?.decrypt()?;
+                Ok(Some(String::from_utf8(dec.to_vec())?))
+            }
+            None => Ok(Some(v)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Encrypts the value with the active key, if `PII_ENCRYPTION` is enabled and `sensitive`
+/// is set.
+pub fn encrypt_bytes(value: Vec<u8>, sensitive: bool) -> Result<Vec<u8>, ErrorResponse> {
+    if !sensitive || !*PII_ENCRYPTION || is_encrypted_bytes(&value) {
+        return Ok(value);
+    }
+
+    let enc = EncValue::encrypt(&value)?.into_bytes();
+    let mut res = Vec::with_capacity(ENC_PREFIX.len() + enc.len());
+    res.extend_from_slice(ENC_PREFIX.as_bytes());
+    res.extend_from_slice(&enc);
+    Ok(res)
+}
+
+/// Returns the cleartext for possibly encrypted bytes.
+pub fn decrypt_bytes(value: Vec<u8>) -> Result<Vec<u8>, ErrorResponse> {
+    match value.strip_prefix(ENC_PREFIX.as_bytes()) {
+        Some(enc) => Ok(EncValue::try_from(enc.to_vec())?.decrypt()?.to_vec()),
+        None => Ok(value),
+    }
+}
+
+/// Re-encrypts an already encrypted value with the given key. Plaintext is returned as it is.
+pub fn reencrypt_str(value: &str, key_id: &str) -> Result<Option<String>, ErrorResponse> {
+    if !is_encrypted_str(value) {
+        return Ok(None);
+    }
+    let dec = decrypt_str(Some(value.to_string()))?.unwrap_or_default();
+    let enc = EncValue::encrypt_with_key_id(dec.as_bytes(), key_id.to_string())?.into_bytes();
+    Ok(Some(format!("{}{}", ENC_PREFIX, base64_encode(&enc))))
+}
+
+/// Re-encrypts already encrypted bytes with the given key. Returns `None` for plaintext.
+pub fn reencrypt_bytes(value: &[u8], key_id: &str) -> Result<Option<Vec<u8>>, ErrorResponse> {
+    if !is_encrypted_bytes(value) {
+        return Ok(None);
+    }
+    let dec = decrypt_bytes(value.to_vec())?;
+    let enc = EncValue::encrypt_with_key_id(&dec, key_id.to_string())?.into_bytes();
+    let mut res = Vec::with_capacity(ENC_PREFIX.len() + enc.len());
+    res.extend_from_slice(ENC_PREFIX.as_bytes());
+    res.extend_from_slice(&enc);
+    Ok(Some(res))
+}
+
+/// Encrypts an E-Mail deterministically with the active key, if `PII_ENCRYPTION_EMAIL` is
+/// enabled. The result can be used for lookups.
+pub fn encrypt_email(email: &str) -> Result<String, ErrorResponse> {
+    if !*PII_ENCRYPTION_EMAIL || is_encrypted_email(email) {
+        return Ok(email.to_string());
+    }
+    encrypt_deterministic(email, &EncKeys::get_static().enc_key_active)
+}
+
+/// Returns the cleartext for a possibly encrypted E-Mail.
+pub fn decrypt_email(value: String) -> Result<String, ErrorResponse> {
+    let Some(rest) = value.strip_prefix(DET_PREFIX) else {
+        return Ok(value);
+    };
+    let (key_id, b64) = rest.split_once(':').ok_or_else(err_invalid)?;
+    let mut buf = base64_decode(b64)?;
+    if buf.len() < NONCE_LEN {
+        return Err(err_invalid());
+    }
+    let mut ct = buf.split_off(NONCE_LEN);
+    let (enc_key, mac_key) = det_keys(key_id)?;
+    let nonce = aead::Nonce::try_assume_unique_for_key(&buf).map_err(|_| err_invalid())?;
+    let plain = enc_key
+        .open_in_place(nonce, aead::Aad::empty(), &mut ct)
+        .map_err(|_| err_invalid())?;
+
+    // the nonce must be the one for this value, or it has not been encrypted deterministically
+    if hmac::sign(&mac_key, plain).as_ref()[..NONCE_LEN] != buf[..] {
+        return Err(err_invalid());
+    }
+    Ok(String::from_utf8(plain.to_vec())?)
+}
+
+/// The prefix of all E-Mails, which are encrypted with the active key.
+pub fn email_prefix_active() -> String {
+    format!("{}{}:", DET_PREFIX, EncKeys::get_static().enc_key_active)
+}
+
+/// Converts a stored E-Mail to match the current `PII_ENCRYPTION_EMAIL`. Encrypted values are
+/// re-encrypted, if they do not use the active key, because lookups would not find them.
+/// Returns `None`, if nothing needs to be changed.
+pub fn convert_email(value: &str) -> Result<Option<String>, ErrorResponse> {
+    if !*PII_ENCRYPTION_EMAIL {
+        return if is_encrypted_email(value) {
+            Ok(Some(decrypt_email(value.to_string())?))
+        } else {
+            Ok(None)
+        };
+    }
+
+    if value.starts_with(&email_prefix_active()) {
+        return Ok(None);
+    }
+    let dec = decrypt_email(value.to_string())?;
+    Ok(Some(encrypt_deterministic(
+        &dec,
+        &EncKeys::get_static().enc_key_active,
+    )?))
+}
+
+fn encrypt_deterministic(value: &str, key_id: &str) -> Result<String, ErrorResponse> {
+    let (enc_key, mac_key) = det_keys(key_id)?;
+    let siv = hmac::sign(&mac_key, value.as_bytes());
+    let nonce_bytes = &siv.as_ref()[..NONCE_LEN];
+
+    let mut in_out = value.as_bytes().to_vec();
+    enc_key
+        .seal_in_place_append_tag(
+            aead::Nonce::try_assume_unique_for_key(nonce_bytes).map_err(|_| err_invalid())?,
+            aead::Aad::empty(),
+            &mut in_out,
+        )
+        .map_err(|_| err_invalid())?;
+
+    let mut res = Vec::with_capacity(NONCE_LEN + in_out.len());
+    res.extend_from_slice(nonce_bytes);
+    res.extend_from_slice(&in_out);
+    Ok(format!("{}{}:{}", DET_PREFIX, key_id, base64_encode(&res)))
+}
+
+/// Derives separate keys for the encryption and the synthetic nonce from the given `ENC_KEYS`
+/// entry.
+fn det_keys(key_id: &str) -> Result<(aead::LessSafeKey, hmac::Key), ErrorResponse> {
+    let key = EncKeys::get_static().get_key(key_id)?;
+    let master = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let enc = hmac::sign(&master, b"rauthy_pii_enc");
+    let mac = hmac::sign(&master, b"rauthy_pii_siv");
+
+    let enc_key =
+        aead::UnboundKey::new(&aead::AES_256_GCM, enc.as_ref()).map_err(|_| err_invalid())?;
+    Ok((
+        aead::LessSafeKey::new(enc_key),
+        hmac::Key::new(hmac::HMAC_SHA256, mac.as_ref()),
+    ))
+}
+
+fn err_invalid() -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorResponseType::Internal,
+        "Invalid encrypted E-Mail value".to_string(),
+    )
+}
+
+#[inline]
+fn is_encrypted_email(value: &str) -> bool {
+    value.starts_with(DET_PREFIX)
+}
+
+#[inline]
+fn is_encrypted_str(value: &str) -> bool {
+    value.starts_with(ENC_PREFIX)
+}
+
+/// User attribute values are JSON, which can never start with the prefix.
+#[inline]
+fn is_encrypted_bytes(value: &[u8]) -> bool {
+    value.starts_with(ENC_PREFIX.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    fn init_keys() {
+        INIT.call_once(|| {
+            env::set_var(
+                "ENC_KEYS",
+                "q6u26onRvXVG4427/M0NFQzhSSldCY01rckJNa1JYZ3g2NUFtSnNOVGdoU0E=\n\
+                bVCyTsGaggVy5yqQ/UzluN29DZW41M3hTSkx6Y3NtZmRuQkR2TnJxUTYzcjQ=",
+            );
+            env::set_var("ENC_KEY_ACTIVE", "bVCyTsGaggVy5yqQ");
+            env::set_var("PII_ENCRYPTION_EMAIL", "true");
+            let _ = EncKeys::from_env().unwrap().init();
+        });
+    }
+
+    #[test]
+    fn test_pii_plaintext_passthrough() {
+        let phone = Some("+49 123 456789".to_string());
+        assert_eq!(decrypt_str(phone.clone()).unwrap(), phone);
+        assert_eq!(decrypt_str(None).unwrap(), None);
+        assert_eq!(reencrypt_str("+49 123 456789", "key").unwrap(), None);
+
+        let value = serde_json::to_vec(&serde_json::json!({"id": 1})).unwrap();
+        assert!(!is_encrypted_bytes(&value));
+        assert_eq!(decrypt_bytes(value.clone()).unwrap(), value);
+        assert_eq!(encrypt_bytes(value.clone(), false).unwrap(), value);
+        assert_eq!(reencrypt_bytes(&value, "key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_pii_email_deterministic() {
+        init_keys();
+
+        let email = "alice@example.com";
+        let enc = encrypt_email(email).unwrap();
+        assert!(enc.starts_with("denc:bVCyTsGaggVy5yqQ:"));
+        assert!(!enc.contains(email));
+
+        // lookups depend on the same ciphertext for the same input
+        assert_eq!(encrypt_email(email).unwrap(), enc);
+        assert_ne!(encrypt_email("bob@example.com").unwrap(), enc);
+        // already encrypted values are not encrypted twice
+        assert_eq!(encrypt_email(&enc).unwrap(), enc);
+
+        assert_eq!(decrypt_email(enc.clone()).unwrap(), email);
+        assert_eq!(decrypt_email(email.to_string()).unwrap(), email);
+        assert_eq!(convert_email(email).unwrap(), Some(enc.clone()));
+        assert_eq!(convert_email(&enc).unwrap(), None);
+
+        // values with an older key are moved to the active one
+        let old = encrypt_deterministic(email, "q6u26onRvXVG4427").unwrap();
+        assert!(old.starts_with("denc:q6u26onRvXVG4427:"));
+        assert_ne!(old, enc);
+        assert_eq!(decrypt_email(old.clone()).unwrap(), email);
+        assert_eq!(convert_email(&old).unwrap(), Some(enc.clone()));
+
+        // a modified ciphertext must never decrypt
+        let (prefix, b64) = enc.rsplit_once(':').unwrap();
+        let mut bytes = base64_decode(b64).unwrap();
+        bytes[NONCE_LEN] ^= 1;
+        let tampered = format!("{}:{}", prefix, base64_encode(&bytes));
+        assert!(decrypt_email(tampered).is_err());
+    }
+
+    #[test]
+    fn test_pii_encrypt_decrypt() {
+        init_keys();
+
+        let enc = EncValue::encrypt(b"+49 123 456789").unwrap().into_bytes();
+        let value = format!("{}{}", ENC_PREFIX, base64_encode(&enc));
+        assert_eq!(
+            decrypt_str(Some(value.clone())).unwrap().as_deref(),
+            Some("+49 123 456789")
+        );
+        let rotated = reencrypt_str(&value, "q6u26onRvXVG4427").unwrap().unwrap();
+        assert_ne!(rotated, value);
+        assert_eq!(
+            decrypt_str(Some(rotated)).unwrap().as_deref(),
+            Some("+49 123 456789")
+        );
+    }
+}
@@ -1,30 +1,179 @@
 use crate::app_state::AppState;
+use crate::entity::announcements::{Announcement, AnnouncementSeverity};
 use crate::entity::api_keys::{ApiKey, ApiKeyAccess};
+use crate::entity::auth_provider_claims::ProviderClaimMapping;
 use crate::entity::auth_providers::{AuthProvider, AuthProviderType};
+use crate::entity::cleanup::{CleanupJob, CleanupRun};
+use crate::entity::client_secret_reads::ClientSecretRead;
 use crate::entity::clients::Client;
 use crate::entity::clients_dyn::ClientDyn;
+use crate::entity::cluster_nodes::{CacheRole, ClusterNode};
 use crate::entity::devices::DeviceEntity;
+use crate::entity::email_aliases::EmailAlias;
+use crate::entity::email_deliveries::{EmailDelivery, EmailDeliveryCount, EmailDeliveryStatus};
+use crate::entity::event_recipients::EventRecipient;
+use crate::entity::feature_flags::FeatureFlag;
+use crate::entity::ip_feeds::IpFeed;
+use crate::entity::jobs::{Job, JobStatus, JobType};
 use crate::entity::jwk::{JWKSPublicKey, JwkKeyPairAlg, JwkKeyPairType, JWKS};
+use crate::entity::legal_holds::LegalHold;
+use crate::entity::login_policies::{fmt_hh_mm, fmt_utc_offset, GroupLoginPolicy};
+use crate::entity::mfa_recovery::{MfaRecovery, MfaRecoveryStatus};
+use crate::entity::organizations::{Organization, OrganizationMember};
 use crate::entity::password::PasswordPolicy;
-use crate::entity::scopes::Scope;
+use crate::entity::push_mfa::PushSubscription;
+use crate::entity::resource_servers::ResourceServer;
+use crate::entity::scopes::{Scope, ScopeI18n};
 use crate::entity::sessions::SessionState;
+use crate::entity::user_activity::{InactiveUser, UserActivity};
 use crate::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
+use crate::entity::user_role_grants::UserRoleGrant;
 use crate::entity::users::{AccountType, User};
 use crate::entity::users_values::UserValues;
 use crate::entity::webauthn::PasskeyEntity;
 use crate::entity::webids::WebId;
+use crate::events::event::{Event, EventLevel, EventType};
 use crate::language::Language;
-use crate::{AddressClaim, JktClaim};
+use crate::pii;
+use crate::request::PasswordResetChannel;
+use crate::{AddressClaim, AuthorizationDetail, JktClaim, OrganizationClaim};
 use actix_web::web;
+use rauthy_common::cache_metrics::CacheCounters;
 use rauthy_common::error_response::ErrorResponse;
+use rauthy_common::login_latency;
+use rauthy_common::login_latency::LatencyStage;
+use rauthy_common::token_errors::TokenError;
 use rio_api::formatter::TriplesFormatter;
 use rio_api::model::{Literal, NamedNode, Subject, Term, Triple};
 use rio_turtle::TurtleFormatter;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
 use time::OffsetDateTime;
 use tracing::debug;
 use utoipa::ToSchema;
+use validator::ValidationErrors;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LabSeedResponse {
+    pub users: Vec<LabUserCredentials>,
+    pub clients: Vec<LabClientCredentials>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LabUserCredentials {
+    pub id: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LabClientCredentials {
+    pub id: String,
+    pub secret: String,
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LabPurgeResponse {
+    pub users: u64,
+    pub clients: u64,
+}
+
+/// A point-in-time compliance report for audits like SOC2 or ISO 27001
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReport {
+    /// Unix timestamp when this report has been generated
+    pub generated_at: i64,
+    pub issuer: String,
+    /// `true` if `ADMIN_FORCE_MFA` is set
+    pub admin_force_mfa: bool,
+    pub admins: Vec<ComplianceReportAdmin>,
+    /// All clients with at least one finding
+    pub clients: Vec<ComplianceReportClient>,
+    pub password_policy: PasswordPolicyResponse,
+    pub signing_keys: Vec<ComplianceReportKey>,
+    pub event_sinks: Vec<ComplianceReportEventSink>,
+    /// The minimum level for events to be persisted in the database
+    pub event_persist_level: EventLevel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReportAdmin {
+    pub id: String,
+    pub email: String,
+    pub enabled: bool,
+    pub mfa: bool,
+    pub last_login: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReportClient {
+    pub id: String,
+    pub name: Option<String>,
+    pub enabled: bool,
+    pub confidential: bool,
+    pub findings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReportKey {
+    pub kid: String,
+    pub alg: JwkKeyPairAlg,
+    pub created_at: i64,
+    pub age_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ComplianceReportEventSink {
+    /// `email`, `matrix` or `slack`
+    pub sink: String,
+    /// The minimum level for events to be sent to this sink
+    pub level: EventLevel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AdminPasswordResetResponse {
+    pub channel: PasswordResetChannel,
+    /// Only set for the `otp` channel. It will be shown exactly once and cannot be retrieved
+    /// again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otp: Option<String>,
+    /// unix timestamp when the reset link or one-time password expires
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AnnouncementResponse {
+    pub id: String,
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    /// unix timestamp
+    pub nbf: i64,
+    /// unix timestamp
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ids: Option<Vec<String>>,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<Announcement> for AnnouncementResponse {
+    fn from(value: Announcement) -> Self {
+        Self {
+            id: value.id,
+            message: value.message,
+            severity: value.severity,
+            nbf: value.nbf,
+            exp: value.exp,
+            client_ids: value
+                .client_ids
+                .map(|ids| ids.split(',').map(String::from).collect()),
+            created: value.created,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ApiKeysResponse {
@@ -64,12 +213,57 @@ pub struct BlacklistResponse {
     pub ips: Vec<BlacklistedIp>,
 }
 
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlacklistDryRunResponse {
+    pub ip: String,
+    pub exp: i64,
+    /// Set if the IP is currently blacklisted already. Contains the current expiry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blacklisted_until: Option<i64>,
+}
+
 #[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct BlacklistedIp {
     pub ip: String,
     pub exp: i64,
 }
 
+/// An IP reputation feed with its latest update on the instance answering the request.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IpFeedResponse {
+    pub feed: IpFeed,
+    pub enabled: bool,
+    pub ttl_hours: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_attempt: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_update: Option<i64>,
+    pub entries: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<IpFeed> for IpFeedResponse {
+    fn from(feed: IpFeed) -> Self {
+        let status = feed.status();
+        let last_attempt = status.as_ref().map(|s| s.last_attempt);
+        let status = status.unwrap_or_default();
+
+        Self {
+            feed,
+            enabled: feed.is_enabled(),
+            ttl_hours: feed.ttl_hours(),
+            last_attempt,
+            last_update: status.last_update,
+            entries: status.entries,
+            exp: status.exp,
+            error: status.error,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ClientResponse {
     pub id: String,
@@ -95,6 +289,26 @@ pub struct ClientResponse {
     pub force_mfa: bool,
     pub client_uri: Option<String>,
     pub contacts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_cidrs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    pub require_signed_request_object: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token_encrypted_response_alg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token_encrypted_response_enc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo_encrypted_response_alg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo_encrypted_response_enc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details_types: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_exchange_audiences: Option<Vec<String>>,
+    /// Only set for a single client, if its secret has ever been read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_reads: Option<ClientSecretRead>,
 }
 
 impl From<Client> for ClientResponse {
@@ -107,6 +321,9 @@ impl From<Client> for ClientResponse {
         let default_scopes = client.get_default_scopes();
         let challenges = client.get_challenges();
         let contacts = client.get_contacts();
+        let allowed_cidrs = client.get_allowed_cidrs();
+        let authorization_details_types = client.get_authorization_details_types();
+        let token_exchange_audiences = client.get_token_exchange_audiences();
 
         Self {
             id: client.id,
@@ -128,10 +345,33 @@ impl From<Client> for ClientResponse {
             force_mfa: client.force_mfa,
             client_uri: client.client_uri,
             contacts,
+            allowed_cidrs,
+            jwks_uri: client.jwks_uri,
+            require_signed_request_object: client.require_signed_request_object,
+            id_token_encrypted_response_alg: client.id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc: client.id_token_encrypted_response_enc,
+            userinfo_encrypted_response_alg: client.userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc: client.userinfo_encrypted_response_enc,
+            authorization_details_types,
+            token_exchange_audiences,
+            secret_reads: None,
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConnectedAppResponse {
+    pub client_id: String,
+    pub client_name: Option<String>,
+    pub client_uri: Option<String>,
+    /// the scopes of the latest token issuance
+    pub scope: Option<String>,
+    /// unix timestamp of the first login
+    pub created: i64,
+    /// unix timestamp of the latest token issuance
+    pub last_used: i64,
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct DeviceResponse {
     pub id: String,
@@ -161,6 +401,281 @@ impl From<DeviceEntity> for DeviceResponse {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BreakGlassResponse {
+    /// Open this URL in the browser to get the admin session. It can only be used once.
+    pub url: String,
+    /// unix timestamp
+    pub exp: i64,
+}
+
+/// Counters for a single cache since the start of this instance.
+///
+/// Evictions only include explicitly removed entries, not expired ones.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CacheStatsResponse {
+    pub name: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl From<CacheCounters> for CacheStatsResponse {
+    fn from(value: CacheCounters) -> Self {
+        Self {
+            name: value.name.to_string(),
+            hits: value.hits,
+            misses: value.misses,
+            evictions: value.evictions,
+        }
+    }
+}
+
+/// A cleanup job with its latest run on the instance answering the request.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CleanupJobResponse {
+    pub job: CleanupJob,
+    /// `0` if the scheduled runs are disabled
+    pub interval_mins: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<CleanupRunResponse>,
+}
+
+impl From<CleanupJob> for CleanupJobResponse {
+    fn from(job: CleanupJob) -> Self {
+        Self {
+            job,
+            interval_mins: job.interval_mins(),
+            last_run: job.last_run().map(CleanupRunResponse::from),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CleanupRunResponse {
+    pub timestamp: i64,
+    pub duration_ms: u64,
+    pub purged: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub manual: bool,
+}
+
+impl From<CleanupRun> for CleanupRunResponse {
+    fn from(run: CleanupRun) -> Self {
+        Self {
+            timestamp: run.timestamp,
+            duration_ms: run.duration_ms,
+            purged: run.purged,
+            error: run.error,
+            manual: run.manual,
+        }
+    }
+}
+
+/// All registered Rauthy instances. `version_skew` is `true`, if the non-stale nodes are running
+/// different versions, which is expected during a rolling upgrade only.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClusterResponse {
+    pub nodes: Vec<ClusterNodeResponse>,
+    /// All versions running on non-stale nodes
+    pub versions: Vec<String>,
+    pub version_skew: bool,
+}
+
+impl From<Vec<ClusterNode>> for ClusterResponse {
+    fn from(nodes: Vec<ClusterNode>) -> Self {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let nodes = nodes
+            .into_iter()
+            .map(|node| ClusterNodeResponse::build(node, now))
+            .collect::<Vec<_>>();
+
+        let mut versions = nodes
+            .iter()
+            .filter(|node| !node.stale)
+            .map(|node| node.version.clone())
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions.dedup();
+
+        Self {
+            version_skew: versions.len() > 1,
+            versions,
+            nodes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClusterNodeResponse {
+    pub hostname: String,
+    pub version: String,
+    pub started_at: i64,
+    pub last_heartbeat: i64,
+    /// `false` if the database was unreachable at the last heartbeat, or if the heartbeat
+    /// before could not be written
+    pub db_healthy: bool,
+    pub cache_role: CacheRole,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_health: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_connected_hosts: Option<i64>,
+    /// `true` if the node missed at least 3 heartbeats
+    pub stale: bool,
+    /// `true` for the node answering the request
+    pub current: bool,
+}
+
+impl ClusterNodeResponse {
+    fn build(node: ClusterNode, now: i64) -> Self {
+        Self {
+            stale: node.is_stale(now),
+            current: node.is_current(),
+            cache_role: node.cache_role(),
+            hostname: node.hostname,
+            version: node.version,
+            started_at: node.started_at,
+            last_heartbeat: node.last_heartbeat,
+            db_healthy: node.db_healthy,
+            cache_health: node.cache_health,
+            cache_connected_hosts: node.cache_connected_hosts,
+        }
+    }
+}
+
+/// A background job without its payload, which may contain sensitive data.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct JobResponse {
+    pub id: String,
+    pub typ: JobType,
+    pub status: JobStatus,
+    pub attempts: i64,
+    pub next_run: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created: i64,
+}
+
+impl TryFrom<Job> for JobResponse {
+    type Error = ErrorResponse;
+
+    fn try_from(value: Job) -> Result<Self, Self::Error> {
+        Ok(Self {
+            typ: JobType::from_str(&value.typ)?,
+            status: JobStatus::from_str(&value.status)?,
+            id: value.id,
+            attempts: value.attempts,
+            next_run: value.next_run,
+            last_error: value.last_error,
+            created: value.created,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmailDeliveryResponse {
+    pub id: String,
+    pub address: String,
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    pub status: EmailDeliveryStatus,
+    pub attempts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created: i64,
+    pub updated: i64,
+}
+
+impl TryFrom<EmailDelivery> for EmailDeliveryResponse {
+    type Error = ErrorResponse;
+
+    fn try_from(value: EmailDelivery) -> Result<Self, Self::Error> {
+        Ok(Self {
+            status: EmailDeliveryStatus::from_str(&value.status)?,
+            id: value.id,
+            address: value.address,
+            subject: value.subject,
+            relay: value.relay,
+            attempts: value.attempts,
+            error: value.error,
+            created: value.created,
+            updated: value.updated,
+        })
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EmailDeliveryStats {
+    /// The SMTP relay, or `None` for the totals and E-Mails, which have not been accepted by
+    /// any relay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    pub sent: i64,
+    pub deferred: i64,
+    pub bounced: i64,
+    pub failed: i64,
+}
+
+impl EmailDeliveryStats {
+    fn add(&mut self, status: EmailDeliveryStatus, count: i64) {
+        match status {
+            EmailDeliveryStatus::Sent => self.sent += count,
+            EmailDeliveryStatus::Deferred => self.deferred += count,
+            EmailDeliveryStatus::Bounced => self.bounced += count,
+            EmailDeliveryStatus::Failed => self.failed += count,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmailDeliveriesResponse {
+    /// Unix timestamp, since when the stats have been counted
+    pub since: i64,
+    pub total: EmailDeliveryStats,
+    pub relays: Vec<EmailDeliveryStats>,
+    pub recent_failures: Vec<EmailDeliveryResponse>,
+}
+
+impl EmailDeliveriesResponse {
+    pub fn build(
+        since: i64,
+        counts: Vec<EmailDeliveryCount>,
+        recent_failures: Vec<EmailDelivery>,
+    ) -> Result<Self, ErrorResponse> {
+        let mut total = EmailDeliveryStats::default();
+        let mut relays: Vec<EmailDeliveryStats> = Vec::new();
+        for count in counts {
+            let status = EmailDeliveryStatus::from_str(&count.status)?;
+            total.add(status, count.count);
+
+            match relays.iter_mut().find(|stats| stats.relay == count.relay) {
+                Some(stats) => stats.add(status, count.count),
+                None => {
+                    let mut stats = EmailDeliveryStats {
+                        relay: count.relay,
+                        ..Default::default()
+                    };
+                    stats.add(status, count.count);
+                    relays.push(stats);
+                }
+            }
+        }
+        relays.sort_by(|a, b| a.relay.cmp(&b.relay));
+
+        Ok(Self {
+            since,
+            total,
+            relays,
+            recent_failures: recent_failures
+                .into_iter()
+                .map(EmailDeliveryResponse::try_from)
+                .collect::<Result<Vec<_>, ErrorResponse>>()?,
+        })
+    }
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct DeviceCodeResponse<'a> {
     pub device_code: &'a str,
@@ -182,6 +697,8 @@ pub struct DeviceVerifyResponse {
 #[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct DynamicClientResponse {
     pub client_id: String,
+    /// unix timestamp
+    pub client_id_issued_at: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -212,6 +729,19 @@ pub struct DynamicClientResponse {
     pub id_token_signed_response_alg: String,
     pub token_endpoint_auth_method: String,
     pub token_endpoint_auth_signing_alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    pub require_signed_request_object: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token_encrypted_response_alg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token_encrypted_response_enc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo_encrypted_response_alg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userinfo_encrypted_response_enc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details_types: Option<Vec<String>>,
 }
 
 impl DynamicClientResponse {
@@ -222,10 +752,13 @@ impl DynamicClientResponse {
         map_registration_client_uri: bool,
     ) -> Result<Self, ErrorResponse> {
         let contacts = client.get_contacts();
+        let authorization_details_types = client.get_authorization_details_types();
 
         let redirect_uris = client.get_redirect_uris();
         let grant_types = client.get_flows();
-        let post_logout_redirect_uri = client.get_redirect_uris().first().cloned();
+        let post_logout_redirect_uri = client
+            .get_post_logout_uris()
+            .and_then(|uris| uris.into_iter().next());
 
         let client_secret = client.get_secret_cleartext()?;
         let (registration_access_token, registration_client_uri) = if map_registration_client_uri {
@@ -239,6 +772,7 @@ impl DynamicClientResponse {
 
         Ok(Self {
             client_id: client.id,
+            client_id_issued_at: client_dyn.created,
             client_name: client.name,
             client_uri: client.client_uri,
             contacts,
@@ -253,6 +787,13 @@ impl DynamicClientResponse {
             id_token_signed_response_alg: client.id_token_alg,
             token_endpoint_auth_method: client_dyn.token_endpoint_auth_method,
             token_endpoint_auth_signing_alg: client.access_token_alg,
+            jwks_uri: client.jwks_uri,
+            require_signed_request_object: client.require_signed_request_object,
+            id_token_encrypted_response_alg: client.id_token_encrypted_response_alg,
+            id_token_encrypted_response_enc: client.id_token_encrypted_response_enc,
+            userinfo_encrypted_response_alg: client.userinfo_encrypted_response_alg,
+            userinfo_encrypted_response_enc: client.userinfo_encrypted_response_enc,
+            authorization_details_types,
         })
     }
 }
@@ -264,10 +805,261 @@ pub struct ClientSecretResponse {
     pub secret: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
-pub struct EncKeysResponse<'a> {
-    pub active: &'a str,
-    pub keys: Vec<&'a str>,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmailAliasResponse {
+    pub email: String,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<EmailAlias> for EmailAliasResponse {
+    fn from(value: EmailAlias) -> Self {
+        Self {
+            email: value.email,
+            created: value.created,
+        }
+    }
+}
+
+/// A one-time code has been sent via E-Mail. It must be submitted together with the `code`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EmailMfaResponse {
+    pub code: String,
+    /// unix timestamp
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EncKeysResponse<'a> {
+    pub active: &'a str,
+    pub keys: Vec<&'a str>,
+}
+
+/// Result of a full verification of the persisted event hash chain
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct EventChainVerifyResponse {
+    /// `true` if no modified events, broken links, invalid anchors or a truncated chain have
+    /// been found
+    pub valid: bool,
+    /// amount of checked chained events
+    pub checked: u64,
+    /// amount of events, which have been deleted by the retention cleanup, and whose links
+    /// have been checked
+    pub pruned: u64,
+    /// amount of anonymized events of deleted users, whose content cannot be checked anymore
+    pub anonymized: u64,
+    /// amount of events which have been persisted before the chain existed
+    pub unchained: u64,
+    pub first_seq: Option<i64>,
+    pub last_seq: Option<i64>,
+    /// the latest `seq` the chain head knows about
+    pub head_seq: i64,
+    /// `true` if the newest events are missing compared to the chain head
+    pub truncated: bool,
+    /// Missing ranges of events. Only a missing start of the chain is expected, all other
+    /// gaps show up in `broken_links` as well.
+    pub gaps: Vec<EventChainGap>,
+    /// `seq` of all links, whose predecessor is missing or has another hash
+    pub broken_links: Vec<i64>,
+    /// IDs of events whose content does not match their hash
+    pub modified: Vec<String>,
+    pub anchors_valid: u64,
+    /// `seq` of all anchors with an invalid signature or a hash mismatch
+    pub anchors_invalid: Vec<i64>,
+    /// Anchors whose signing key has been cleaned up already
+    pub anchors_unverifiable: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EventChainGap {
+    /// first missing `seq`
+    pub from: i64,
+    /// last missing `seq`
+    pub until: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EventRecipientResponse {
+    pub email: String,
+    pub level: EventLevel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet_to: Option<String>,
+    pub utc_offset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl From<EventRecipient> for EventRecipientResponse {
+    fn from(value: EventRecipient) -> Self {
+        Self {
+            email: value.email,
+            level: EventLevel::from(value.level),
+            quiet_from: value.quiet_from.map(fmt_hh_mm),
+            quiet_to: value.quiet_to.map(fmt_hh_mm),
+            utc_offset: fmt_utc_offset(value.utc_offset),
+            timezone: value.timezone,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenErrorResponse {
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+    pub grant_type: String,
+    /// `attestation`, `client_disabled`, `client_secret_invalid`, `dpop`, `flow_not_allowed`,
+    /// `grant_expired`, `invalid_authorization_details`, `invalid_credentials`,
+    /// `invalid_target`, `pkce_mismatch`, `redirect_uri_mismatch` or `other`
+    pub cause: String,
+    pub message: String,
+}
+
+impl From<TokenError> for TokenErrorResponse {
+    fn from(value: TokenError) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            grant_type: value.grant_type,
+            cause: value.cause.as_str().to_string(),
+            message: value.message,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FeatureFlagResponse {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attr_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attr_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ids: Option<Vec<String>>,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<FeatureFlag> for FeatureFlagResponse {
+    fn from(value: FeatureFlag) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            enabled: value.enabled,
+            percentage: value.percentage,
+            groups: value
+                .groups
+                .map(|g| g.split(',').map(String::from).collect()),
+            attr_key: value.attr_key,
+            attr_value: value.attr_value,
+            client_ids: value
+                .client_ids
+                .map(|ids| ids.split(',').map(String::from).collect()),
+            created: value.created,
+        }
+    }
+}
+
+/// The evaluated feature flags for the user and client of the given access token
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FeatureFlagsEvaluatedResponse {
+    pub flags: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GroupLoginPolicyResponse {
+    pub group_id: String,
+    pub weekdays: Vec<String>,
+    pub time_from: String,
+    pub time_to: String,
+    pub utc_offset: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl From<GroupLoginPolicy> for GroupLoginPolicyResponse {
+    fn from(value: GroupLoginPolicy) -> Self {
+        Self {
+            weekdays: value.weekdays_str(),
+            time_from: fmt_hh_mm(value.time_from),
+            time_to: fmt_hh_mm(value.time_to),
+            utc_offset: fmt_utc_offset(value.utc_offset),
+            timezone: value.timezone,
+            group_id: value.group_id,
+        }
+    }
+}
+
+/// The next step a headless login client has to take.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadlessLoginStep {
+    /// `POST /oidc/authorize/headless` with the user credentials
+    Credentials,
+    /// The session is still valid and `POST /oidc/authorize/refresh` can be used
+    Refresh,
+    /// Continue with the Webauthn login via `/users/{id}/webauthn/auth/start`
+    Webauthn,
+    /// Submit the one-time code from the E-Mail to `POST /oidc/authorize/email_mfa`
+    EmailMfa,
+    /// Show the `number` to the user and poll `POST /oidc/authorize/push_mfa` until the login
+    /// has been approved
+    PushMfa,
+    /// Repeat the login request with values for the `missing_attrs` as `user_attrs`
+    Profile,
+    /// The login is finished and `location` contains the `code` and `state`
+    LoggedIn,
+    /// The user has been linked to an upstream auth provider
+    ProviderLink,
+    /// `prompt=create` has been requested and the user should be sent to the `register_uri`
+    Register,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HeadlessAuthorizeResponse {
+    pub next: HeadlessLoginStep,
+    /// Must be sent as `csrf-token` header with each following request
+    pub csrf_token: String,
+    pub client_id: String,
+    pub client_name: Option<String>,
+    pub client_uri: Option<String>,
+    /// The requested scopes, which the user will consent to with the login
+    pub scopes: Vec<ScopeI18n>,
+    pub pow_required: bool,
+    pub username_login: bool,
+    /// Set if a valid MFA cookie exists and the user must do a Webauthn login
+    pub mfa_email: Option<String>,
+    /// The user registration with the original authorization request as `redirect_uri`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub register_uri: Option<String>,
+    /// The requested RFC 9396 `authorization_details`, which the user will consent to as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HeadlessLoginResponse {
+    pub next: HeadlessLoginStep,
+    /// The `redirect_uri` with the `code` and `state` appended after a successful login
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webauthn: Option<WebauthnLoginResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_mfa: Option<EmailMfaResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_mfa: Option<PushMfaResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_attrs: Option<Vec<UserAttrMissingResponse>>,
 }
 
 #[derive(Debug, Default, Serialize, ToSchema)]
@@ -328,11 +1120,136 @@ impl From<JWKSPublicKey> for JWKSPublicKeyCerts {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LegalHoldResponse {
+    pub user_id: String,
+    pub reason: String,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<LegalHold> for LegalHoldResponse {
+    fn from(value: LegalHold) -> Self {
+        Self {
+            user_id: value.user_id,
+            reason: value.reason,
+            created: value.created,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MfaRecoveryResponse {
+    pub user_id: String,
+    pub status: MfaRecoveryStatus,
+    /// unix timestamp
+    pub created: i64,
+    /// unix timestamp of the E-Mail confirmation
+    pub confirmed: Option<i64>,
+    /// unix timestamp after which the recovery can be finished
+    pub not_before: Option<i64>,
+    /// `true` if `MFA_RECOVERY_ADMIN_APPROVAL` is set
+    pub approval_required: bool,
+    pub approved_by: Option<String>,
+    /// unix timestamp
+    pub exp: i64,
+}
+
+impl MfaRecoveryResponse {
+    pub fn new(value: MfaRecovery, approval_required: bool) -> Self {
+        Self {
+            user_id: value.user_id,
+            status: value.status,
+            created: value.created,
+            confirmed: value.confirmed,
+            not_before: value.not_before,
+            approval_required,
+            approved_by: value.approved_by,
+            exp: value.exp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LoginPolicyOverrideResponse {
+    pub user_id: String,
+    /// unix timestamp
+    pub exp: i64,
+}
+
 #[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct LoginTimeResponse {
     pub argon2_params: Argon2ParamsResponse,
     pub login_time: u32,
     pub num_cpus: usize,
+    /// Latency percentiles for each stage of a successful login on this instance
+    pub latency: Vec<LoginLatencyResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LoginLatencyResponse {
+    /// `password_hash`, `db` or `authorize`
+    pub stage: String,
+    /// `None` without any samples inside the window
+    pub p50: Option<u64>,
+    pub p95: Option<u64>,
+    pub p99: Option<u64>,
+    pub samples: usize,
+    /// The configured p95 SLO in ms
+    pub slo_p95: Option<u64>,
+}
+
+impl From<LatencyStage> for LoginLatencyResponse {
+    fn from(stage: LatencyStage) -> Self {
+        let p = login_latency::percentiles(stage);
+        Self {
+            stage: stage.as_str().to_string(),
+            p50: p.as_ref().map(|p| p.p50),
+            p95: p.as_ref().map(|p| p.p95),
+            p99: p.as_ref().map(|p| p.p99),
+            samples: p.map(|p| p.samples).unwrap_or_default(),
+            slo_p95: stage.slo_p95(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OrganizationResponse {
+    pub id: String,
+    pub name: String,
+    pub domains: Vec<String>,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<Organization> for OrganizationResponse {
+    fn from(value: Organization) -> Self {
+        let domains = value.get_domains();
+        Self {
+            id: value.id,
+            name: value.name,
+            domains,
+            created: value.created,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OrganizationMemberResponse {
+    pub user_id: String,
+    pub is_owner: bool,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<OrganizationMember> for OrganizationMemberResponse {
+    fn from(value: OrganizationMember) -> Self {
+        Self {
+            user_id: value.user_id,
+            is_owner: value.is_owner,
+            created: value.created,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -356,6 +1273,38 @@ pub enum OAuth2ErrorTypeResponse {
     SlowDown,
     AccessDenied,
     ExpiredToken,
+    // specific to the dynamic client registration
+    InvalidRedirectUri,
+    InvalidClientMetadata,
+    // specific to resource indicators
+    InvalidTarget,
+    // specific to rich authorization requests
+    InvalidAuthorizationDetails,
+}
+
+impl OAuth2ErrorResponse<'static> {
+    /// Maps the validation errors of a dynamic client registration to the error response
+    /// defined in RFC7591 section 3.2.2
+    pub fn client_metadata(err: ValidationErrors) -> Self {
+        let fields = err.field_errors();
+        let error = if fields.contains_key("redirect_uris")
+            || fields.contains_key("post_logout_redirect_uri")
+        {
+            OAuth2ErrorTypeResponse::InvalidRedirectUri
+        } else {
+            OAuth2ErrorTypeResponse::InvalidClientMetadata
+        };
+
+        let mut invalid = fields.keys().copied().collect::<Vec<&str>>();
+        invalid.sort_unstable();
+        Self {
+            error,
+            error_description: Some(Cow::Owned(format!(
+                "Invalid client metadata: {}",
+                invalid.join(", ")
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -437,6 +1386,7 @@ pub struct ProviderResponse {
     pub use_pkce: bool,
 
     pub root_pem: Option<String>,
+    pub claim_mapping: Option<ProviderClaimMapping>,
 }
 
 impl TryFrom<AuthProvider> for ProviderResponse {
@@ -444,6 +1394,10 @@ impl TryFrom<AuthProvider> for ProviderResponse {
 
     fn try_from(value: AuthProvider) -> Result<Self, Self::Error> {
         let secret = AuthProvider::get_secret_cleartext(&value.secret)?;
+        let claim_mapping = value
+            .claim_mapping
+            .is_some()
+            .then(|| value.get_claim_mapping());
         Ok(Self {
             id: value.id,
             name: value.name,
@@ -463,6 +1417,7 @@ impl TryFrom<AuthProvider> for ProviderResponse {
             danger_allow_insecure: value.allow_insecure_requests,
             use_pkce: value.use_pkce,
             root_pem: value.root_pem,
+            claim_mapping,
         })
     }
 }
@@ -485,6 +1440,71 @@ pub struct ProviderLookupResponse<'a> {
     pub danger_allow_insecure: bool,
 }
 
+/// A push approval request has been sent. The `number` must be shown to the user, who has to
+/// enter it on the approval page.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PushMfaResponse {
+    pub code: String,
+    pub number: i64,
+    /// unix timestamp
+    pub exp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PushSubscriptionResponse {
+    pub id: String,
+    pub name: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+impl From<PushSubscription> for PushSubscriptionResponse {
+    fn from(value: PushSubscription) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            created: value.created,
+            last_used: value.last_used,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PushSubscriptionsResponse {
+    /// The `applicationServerKey` for new subscriptions
+    pub vapid_key: String,
+    pub subscriptions: Vec<PushSubscriptionResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResourceServerResponse {
+    pub id: String,
+    pub name: String,
+    pub resource: String,
+    pub scopes: Vec<String>,
+    pub client_ids: Vec<String>,
+    /// unix timestamp
+    pub created: i64,
+}
+
+impl From<ResourceServer> for ResourceServerResponse {
+    fn from(value: ResourceServer) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            resource: value.resource,
+            scopes: value.scopes.split(',').map(String::from).collect(),
+            client_ids: value
+                .client_ids
+                .split(',')
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect(),
+            created: value.created,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ScopeResponse {
     pub id: String,
@@ -493,22 +1513,54 @@ pub struct ScopeResponse {
     pub attr_include_access: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub attr_include_id: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub i18n: Vec<ScopeI18n>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ids: Option<Vec<String>>,
 }
 
 impl From<Scope> for ScopeResponse {
     fn from(value: Scope) -> Self {
+        let i18n = value.i18n_all();
         let attr_include_access = value
             .attr_include_access
             .map(|attr| attr.split(',').map(String::from).collect());
         let attr_include_id = value
             .attr_include_id
             .map(|attr| attr.split(',').map(String::from).collect());
+        let client_ids = value
+            .client_ids
+            .map(|ids| ids.split(',').map(String::from).collect());
 
         Self {
             id: value.id,
             name: value.name,
             attr_include_access,
             attr_include_id,
+            i18n,
+            client_ids,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SecurityEventResponse {
+    /// unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub level: EventLevel,
+    pub typ: EventType,
+    pub ip: Option<String>,
+    pub text: Option<String>,
+}
+
+impl From<Event> for SecurityEventResponse {
+    fn from(value: Event) -> Self {
+        Self {
+            timestamp: value.timestamp,
+            level: value.level,
+            typ: value.typ,
+            ip: value.ip,
+            text: value.text,
         }
     }
 }
@@ -526,6 +1578,20 @@ pub struct SessionResponse<'a> {
     pub remote_ip: Option<&'a str>,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionElevationResponse {
+    /// unix timestamp until the session counts as elevated
+    pub elevated_until: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionsDryRunResponse<'a> {
+    /// All sessions which would be invalidated
+    pub sessions: Vec<SessionResponse<'a>>,
+    /// The amount of refresh tokens which would be invalidated
+    pub refresh_tokens: i64,
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct SessionInfoResponse<'a> {
     pub id: &'a String,
@@ -548,18 +1614,58 @@ pub struct SessionInfoResponse<'a> {
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SsoHandoffResponse {
+    /// The signed login hint token, which can only be used once at the peer
+    pub login_hint_token: String,
+    /// Open this URL in the browser to get a session at the peer
+    pub url: String,
+    /// unix timestamp
+    pub exp: i64,
+}
+
+/// Token introspection response as defined in RFC 7662. Inactive tokens only contain
+/// `active: false`.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
 pub struct TokenInfo {
     pub active: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub scope: Option<String>,
+    /// RFC 9396 details for parameterized scopes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_id: Option<String>,
+    /// Same as the `sub` for backwards compatibility
     #[serde(skip_serializing_if = "Option::is_none")]
     pub username: Option<String>,
+    /// `Bearer`, `DPoP`, `Id` or `Refresh`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exp: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub cnf: Option<JktClaim>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SidInfo {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -567,6 +1673,29 @@ pub struct UserAttrConfigResponse {
     pub values: Vec<UserAttrConfigEntity>,
 }
 
+/// A required user attribute, which must be provided during the login
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserAttrMissingResponse {
+    pub name: String,
+    pub desc: Option<String>,
+}
+
+impl From<UserAttrConfigEntity> for UserAttrMissingResponse {
+    fn from(value: UserAttrConfigEntity) -> Self {
+        Self {
+            name: value.name,
+            desc: value.desc,
+        }
+    }
+}
+
+/// The user is missing required attributes. The login request must be repeated with values for
+/// them as `user_attrs`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserAttrsMissingResponse {
+    pub missing_attrs: Vec<UserAttrMissingResponse>,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserAttrValueResponse {
     pub key: String,
@@ -608,6 +1737,10 @@ pub struct Userinfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email_verified: Option<bool>,
 
+    // scope: flags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<String>>,
+
     // scope: groups
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groups: Option<Vec<String>>,
@@ -624,6 +1757,10 @@ pub struct Userinfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub locale: Option<String>,
 
+    // scope: organizations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organizations: Option<Vec<OrganizationClaim>>,
+
     // scope: phone
     #[serde(skip_serializing_if = "Option::is_none")]
     pub phone: Option<String>,
@@ -681,15 +1818,24 @@ pub struct UserResponse {
     /// format: `NaiveDateTime`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_failed_login: Option<i64>,
+    /// format: `NaiveDateTime`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh: Option<i64>,
+    /// format: `NaiveDateTime`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_userinfo: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub failed_login_attempts: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_expires: Option<i64>,
     pub account_type: UserAccountTypeResponse,
     pub webauthn_user_id: Option<String>, // TODO get rid of the webauthn user id ? Not needed at all?
+    pub email_mfa: bool,
     pub user_values: UserValuesResponse,
     pub auth_provider_id: Option<String>,
     pub federation_uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
 }
 
 impl UserResponse {
@@ -716,13 +1862,58 @@ impl UserResponse {
             created_at: u.created_at,
             last_login: u.last_login,
             last_failed_login: u.last_failed_login,
+            last_refresh: None,
+            last_userinfo: None,
             failed_login_attempts: u.failed_login_attempts,
             user_expires: u.user_expires,
             account_type,
             webauthn_user_id: u.webauthn_user_id,
+            email_mfa: u.email_mfa,
             user_values: v.map(UserValuesResponse::from).unwrap_or_default(),
             auth_provider_id: u.auth_provider_id,
             federation_uid: u.federation_uid,
+            username: u.username,
+        }
+    }
+
+    pub fn with_activity(mut self, activity: Option<UserActivity>) -> Self {
+        if let Some(activity) = activity {
+            self.last_refresh = activity.last_refresh;
+            self.last_userinfo = activity.last_userinfo;
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InactiveUserResponse {
+    pub id: String,
+    pub email: String,
+    pub enabled: bool,
+    /// format: `NaiveDateTime`
+    pub created_at: i64,
+    /// format: `NaiveDateTime`
+    pub last_login: Option<i64>,
+    /// format: `NaiveDateTime`
+    pub last_refresh: Option<i64>,
+    /// format: `NaiveDateTime`
+    pub last_userinfo: Option<i64>,
+    /// The latest of all activities, or `created_at` if the user has never been active
+    /// format: `NaiveDateTime`
+    pub last_activity: i64,
+}
+
+impl From<InactiveUser> for InactiveUserResponse {
+    fn from(value: InactiveUser) -> Self {
+        Self {
+            last_activity: value.last_activity(),
+            id: value.id,
+            email: value.email,
+            enabled: value.enabled,
+            created_at: value.created_at,
+            last_login: value.last_login,
+            last_refresh: value.last_refresh,
+            last_userinfo: value.last_userinfo,
         }
     }
 }
@@ -742,6 +1933,102 @@ impl From<User> for UserResponseSimple {
     }
 }
 
+impl UserResponseSimple {
+    /// Decrypts the E-Mail, if it has been read directly from the database.
+    pub fn email_dec(mut self) -> Result<Self, ErrorResponse> {
+        self.email = pii::decrypt_email(self.email)?;
+        Ok(self)
+    }
+}
+
+/// The result of a user merge. With `dry_run`, nothing has been changed yet.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserMergeResponse {
+    pub dry_run: bool,
+    /// The user, which is kept
+    pub target_id: String,
+    /// The user, which has been merged and is now only a tombstone redirecting to the target
+    pub source_id: String,
+    pub roles: Vec<String>,
+    pub groups: Vec<String>,
+    /// The names of the passkeys moved from the source, renamed on conflicts
+    pub passkeys: Vec<String>,
+    /// The `client_id`s of the consents moved from the source
+    pub client_grants: Vec<String>,
+    /// The ids of the organizations the source has been a member of
+    pub organizations: Vec<String>,
+    /// The source E-Mail and all of its aliases, which will become aliases of the target
+    pub email_aliases: Vec<String>,
+    /// All attributes, for which the value of the source has been taken
+    pub attributes_from_source: Vec<String>,
+}
+
+/// Everything Rauthy stores about a user, for the self-service data export.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserDataExportResponse {
+    /// unix timestamp
+    pub exported_at: i64,
+    pub user: UserResponse,
+    pub attributes: Vec<UserAttrValueResponse>,
+    pub email_aliases: Vec<EmailAliasResponse>,
+    pub passkeys: Vec<PasskeyResponse>,
+    pub devices: Vec<DeviceResponse>,
+    pub connected_apps: Vec<ConnectedAppResponse>,
+    pub preferences: UserPreferencesResponse,
+    pub events: Vec<SecurityEventResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserPreferencesResponse {
+    pub language: Language,
+    /// E-Mail reminder before the password expires
+    pub notify_password_expiry: bool,
+    /// E-Mail info after all sessions have been revoked because of a credential change
+    pub notify_sessions_revoked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserSecurityResponse {
+    pub account_type: UserAccountTypeResponse,
+    pub mfa_enabled: bool,
+    pub passkeys: Vec<PasskeyResponse>,
+    pub password_expires: Option<i64>,
+    pub last_login: Option<i64>,
+    pub last_failed_login: Option<i64>,
+    pub failed_login_attempts: Option<i64>,
+    pub active_refresh_tokens: i64,
+    /// the latest security relevant events for this user
+    pub events: Vec<SecurityEventResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserRoleGrantResponse {
+    pub id: String,
+    pub user_id: String,
+    pub role: String,
+    pub reason: String,
+    /// The admin or API key, which approved the grant
+    pub approved_by: String,
+    /// unix timestamp
+    pub created: i64,
+    /// unix timestamp after which the role will be removed
+    pub exp: i64,
+}
+
+impl From<UserRoleGrant> for UserRoleGrantResponse {
+    fn from(value: UserRoleGrant) -> Self {
+        Self {
+            id: value.id,
+            user_id: value.user_id,
+            role: value.role,
+            reason: value.reason,
+            approved_by: value.approved_by,
+            created: value.created,
+            exp: value.exp,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct UserValuesResponse {
     pub birthdate: Option<String>,
@@ -906,6 +2193,8 @@ mod tests {
     use rstest::rstest;
     use std::env;
 
+    use crate::entity::email_deliveries::EmailDeliveryCount;
+    use crate::response::EmailDeliveriesResponse;
     use crate::{entity::webids::WebId, response::WebIdResponse};
 
     #[rstest]
@@ -935,4 +2224,35 @@ mod tests {
         // TODO we actually need real test cases with complex custom_triples to make sure
         // the outcome is as expected
     }
+
+    #[test]
+    fn test_email_deliveries_response() {
+        let count = |relay: Option<&str>, status: &str, count: i64| EmailDeliveryCount {
+            relay: relay.map(String::from),
+            status: status.to_string(),
+            count,
+        };
+        let counts = vec![
+            count(Some("smtp-2.localhost"), "sent", 3),
+            count(Some("smtp-1.localhost"), "sent", 10),
+            count(Some("smtp-1.localhost"), "bounced", 2),
+            count(None, "deferred", 4),
+        ];
+
+        let resp = EmailDeliveriesResponse::build(0, counts, Vec::default()).unwrap();
+        assert_eq!(resp.total.sent, 13);
+        assert_eq!(resp.total.bounced, 2);
+        assert_eq!(resp.total.deferred, 4);
+        assert_eq!(resp.total.failed, 0);
+        assert_eq!(resp.relays.len(), 3);
+        assert_eq!(resp.relays[0].relay, None);
+        assert_eq!(resp.relays[0].deferred, 4);
+        assert_eq!(resp.relays[1].relay.as_deref(), Some("smtp-1.localhost"));
+        assert_eq!(resp.relays[1].sent, 10);
+        assert_eq!(resp.relays[1].bounced, 2);
+        assert_eq!(resp.relays[2].sent, 3);
+
+        let invalid = vec![count(None, "unknown", 1)];
+        assert!(EmailDeliveriesResponse::build(0, invalid, Vec::default()).is_err());
+    }
 }
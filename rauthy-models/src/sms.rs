@@ -0,0 +1,78 @@
+use crate::app_state::AppState;
+use crate::entity::magic_links::MagicLink;
+use actix_web::web;
+use rauthy_common::constants::{
+    EMAIL_SUB_PREFIX, RAUTHY_VERSION, SMS_GATEWAY_TOKEN, SMS_GATEWAY_URL,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use reqwest::tls;
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::error;
+
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+struct SmsPayload<'a> {
+    to: &'a str,
+    text: &'a str,
+}
+
+#[inline]
+pub fn is_sms_configured() -> bool {
+    SMS_GATEWAY_URL.is_some()
+}
+
+/// Sends the password reset link for the given magic link via the configured `SMS_GATEWAY_URL`.
+pub async fn send_pwd_reset_sms(
+    data: &web::Data<AppState>,
+    magic_link: &MagicLink,
+    phone: &str,
+) -> Result<(), ErrorResponse> {
+    let Some(url) = SMS_GATEWAY_URL.as_deref() else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "SMS delivery is not configured".to_string(),
+        ));
+    };
+
+    let link = format!(
+        "{}/users/{}/reset/{}?type={}",
+        data.issuer, magic_link.user_id, &magic_link.id, magic_link.usage,
+    );
+    let text = format!("{} - Password Reset: {}", *EMAIL_SUB_PREFIX, link);
+
+    let client = HTTP_CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(10))
+            .user_agent(format!("Rauthy v{} SMS Gateway Client", RAUTHY_VERSION))
+            .min_tls_version(tls::Version::TLS_1_2)
+            .build()
+            .unwrap()
+    });
+
+    let mut req = client.post(url).json(&SmsPayload {
+        to: phone,
+        text: &text,
+    });
+    if let Some(token) = SMS_GATEWAY_TOKEN.as_deref() {
+        req = req.bearer_auth(token);
+    }
+
+    let res = req.send().await.map_err(|err| {
+        ErrorResponse::new(
+            ErrorResponseType::Connection,
+            format!("Cannot reach the SMS gateway: {}", err),
+        )
+    })?;
+
+    if !res.status().is_success() {
+        let msg = format!("SMS gateway responded with status {}", res.status());
+        error!("{}", msg);
+        return Err(ErrorResponse::new(ErrorResponseType::Connection, msg));
+    }
+
+    Ok(())
+}
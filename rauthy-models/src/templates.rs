@@ -4,19 +4,23 @@ use crate::i18n::account::I18nAccount;
 use crate::i18n::authorize::I18nAuthorize;
 use crate::i18n::device::I18nDevice;
 use crate::i18n::email_confirm_change_html::I18nEmailConfirmChangeHtml;
-use crate::i18n::error::I18nError;
+use crate::i18n::error::{new_correlation_id, I18nError};
 use crate::i18n::index::I18nIndex;
 use crate::i18n::logout::I18nLogout;
 use crate::i18n::password_reset::I18nPasswordReset;
+use crate::i18n::push_mfa::I18nPushMfa;
 use crate::i18n::register::I18nRegister;
 use crate::i18n::SsrJson;
 use crate::language::Language;
+use crate::response::ComplianceReport;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, HttpResponseBuilder};
 use askama_actix::Template;
+use chrono::DateTime;
 use rauthy_common::constants::{
     DEVICE_GRANT_USER_CODE_LENGTH, HEADER_HTML, OPEN_USER_REG, USER_REG_DOMAIN_RESTRICTION,
 };
+use rauthy_common::error_response::{ErrorCode, ErrorResponse};
 use std::fmt::{Debug, Display, Formatter};
 
 #[derive(Debug, Clone)]
@@ -235,6 +239,56 @@ impl DeviceHtml<'_> {
     }
 }
 
+#[derive(Default, Template)]
+#[template(path = "html/push_mfa.html")]
+pub struct PushMfaHtml<'a> {
+    pub lang: &'a str,
+    pub csrf_token: &'a str,
+    pub data: &'a str,
+    pub action: bool,
+    pub col_act1: &'a str,
+    pub col_act1a: &'a str,
+    pub col_act2: &'a str,
+    pub col_act2a: &'a str,
+    pub col_acnt: &'a str,
+    pub col_acnta: &'a str,
+    pub col_ok: &'a str,
+    pub col_err: &'a str,
+    pub col_glow: &'a str,
+    pub col_gmid: &'a str,
+    pub col_ghigh: &'a str,
+    pub col_text: &'a str,
+    pub col_bg: &'a str,
+    pub i18n: String,
+    pub auth_providers: &'a str,
+}
+
+impl PushMfaHtml<'_> {
+    pub fn build(colors: &Colors, lang: &Language) -> String {
+        let res = PushMfaHtml {
+            lang: lang.as_str(),
+            csrf_token: "",
+            col_act1: &colors.act1,
+            col_act1a: &colors.act1a,
+            col_act2: &colors.act2,
+            col_act2a: &colors.act2a,
+            col_acnt: &colors.acnt,
+            col_acnta: &colors.acnta,
+            col_ok: &colors.ok,
+            col_err: &colors.err,
+            col_glow: &colors.glow,
+            col_gmid: &colors.gmid,
+            col_ghigh: &colors.ghigh,
+            col_text: &colors.text,
+            col_bg: &colors.bg,
+            i18n: I18nPushMfa::build(lang).as_json(),
+            ..Default::default()
+        };
+
+        res.render().unwrap()
+    }
+}
+
 #[derive(Debug, Default, Template)]
 #[template(path = "html/error.html")]
 pub struct ErrorHtml<'a> {
@@ -295,6 +349,43 @@ impl ErrorHtml<'_> {
     }
 }
 
+/// Implements `build_for_err` for the given error templates, which builds the error page for an
+/// [ErrorResponse] with a localized text and a correlation ID.
+macro_rules! impl_build_for_err {
+    ($($tpl:ident),+) => {
+        $(
+            impl $tpl<'_> {
+                pub fn build_for_err(
+                    colors: &Colors,
+                    lang: &Language,
+                    err: &ErrorResponse,
+                ) -> String {
+                    let res = $tpl {
+                        lang: lang.as_str(),
+                        col_act1: &colors.act1,
+                        col_act1a: &colors.act1a,
+                        col_act2: &colors.act2,
+                        col_act2a: &colors.act2a,
+                        col_acnt: &colors.acnt,
+                        col_acnta: &colors.acnta,
+                        col_ok: &colors.ok,
+                        col_err: &colors.err,
+                        col_glow: &colors.glow,
+                        col_gmid: &colors.gmid,
+                        col_ghigh: &colors.ghigh,
+                        col_text: &colors.text,
+                        col_bg: &colors.bg,
+                        i18n: I18nError::build_for_err(lang, err).as_json(),
+                        ..Default::default()
+                    };
+
+                    res.render().unwrap()
+                }
+            }
+        )+
+    };
+}
+
 // The error template is defined 3 more times.
 // This may look a bit ugly here in the code, but is actually better for the efficiency and
 // performance down the road. The same error template is being pre-compiled 4 times with just
@@ -463,6 +554,8 @@ impl Error3Html<'_> {
     }
 }
 
+impl_build_for_err!(Error1Html, Error3Html);
+
 #[derive(Default, Template)]
 #[template(path = "html/admin/api_keys.html")]
 pub struct AdminApiKeysHtml<'a> {
@@ -1272,10 +1365,17 @@ pub struct LogoutHtml<'a> {
 }
 
 impl LogoutHtml<'_> {
-    pub fn build(csrf_token: &str, set_logout: bool, colors: &Colors, lang: &Language) -> String {
+    pub fn build(
+        csrf_token: &str,
+        set_logout: bool,
+        post_logout_location: &str,
+        colors: &Colors,
+        lang: &Language,
+    ) -> String {
         let res = LogoutHtml {
             lang: lang.as_str(),
             csrf_token,
+            data: post_logout_location,
             action: set_logout,
             col_act1: &colors.act1,
             col_act1a: &colors.act1a,
@@ -1369,16 +1469,74 @@ impl PwdResetHtml<'_> {
     }
 }
 
+#[derive(Default, Template)]
+#[template(path = "oidc/form_post.html")]
+pub struct FormPostHtml<'a> {
+    pub redirect_uri: &'a str,
+    pub response: &'a str,
+}
+
+impl FormPostHtml<'_> {
+    pub fn build(redirect_uri: &str, response: &str) -> String {
+        FormPostHtml {
+            redirect_uri,
+            response,
+        }
+        .render()
+        .unwrap()
+    }
+}
+
+/// Lets the user confirm an SSO handoff from a peer before the session will be created.
+#[derive(Default, Template)]
+#[template(path = "oidc/sso_handoff.html")]
+pub struct SsoHandoffHtml<'a> {
+    pub id: &'a str,
+    pub email: &'a str,
+    pub peer: &'a str,
+}
+
+impl SsoHandoffHtml<'_> {
+    pub fn build(id: &str, email: &str, peer: &str) -> String {
+        SsoHandoffHtml { id, email, peer }.render().unwrap()
+    }
+}
+
 #[derive(Default, Template)]
 #[template(path = "error/429.html")]
 pub struct TooManyRequestsHtml<'a> {
+    pub lang: &'a str,
     pub ip: &'a str,
     pub exp: i64,
+    pub correlation_id: &'a str,
 }
 
 impl TooManyRequestsHtml<'_> {
     pub fn build(ip: &str, exp: i64) -> String {
-        TooManyRequestsHtml { ip, exp }.render().unwrap()
+        TooManyRequestsHtml {
+            lang: "en",
+            ip,
+            exp,
+            ..Default::default()
+        }
+        .render()
+        .unwrap()
+    }
+
+    /// Builds the localized page for a blacklisted IP with a correlation ID.
+    pub fn build_for_lang(lang: &Language, ip: &str, exp: i64) -> String {
+        let correlation_id = new_correlation_id(
+            ErrorCode::TooManyRequests,
+            &format!("IP {} is blacklisted until {}", ip, exp),
+        );
+        TooManyRequestsHtml {
+            lang: lang.as_str(),
+            ip,
+            exp,
+            correlation_id: &correlation_id,
+        }
+        .render()
+        .unwrap()
     }
 }
 
@@ -1486,3 +1644,28 @@ impl UserRegisterHtml<'_> {
         .expect("rendering register.html")
     }
 }
+
+#[derive(Template)]
+#[template(path = "report/compliance.html")]
+pub struct ComplianceReportHtml<'a> {
+    pub report: &'a ComplianceReport,
+}
+
+impl ComplianceReportHtml<'_> {
+    pub fn build(report: &ComplianceReport) -> String {
+        ComplianceReportHtml { report }.render().unwrap()
+    }
+
+    // askama passes the arguments by reference
+    fn fmt_ts(&self, ts: &i64) -> String {
+        DateTime::from_timestamp(*ts, 0)
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default()
+    }
+
+    fn fmt_opt(&self, value: &Option<i32>) -> String {
+        value
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
@@ -0,0 +1,367 @@
+use crate::app_state::AppState;
+use crate::entity::groups::Group;
+use crate::entity::roles::Role;
+use crate::entity::users::User;
+use crate::events::event::Event;
+use actix_web::web;
+use async_trait::async_trait;
+use rauthy_common::constants::{
+    APPLICATION_JSON, EMAIL_NORMALIZE, RAUTHY_ADMIN_ROLE, RAUTHY_VERSION, USERNAME_LOGIN_ENABLE,
+};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::password_hasher::{ComparePasswords, HashPassword};
+use rauthy_common::utils::normalize_email;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{tls, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+static USER_STORE: OnceLock<Box<dyn UserStore>> = OnceLock::new();
+static DEFAULT_ROLES: OnceLock<Vec<String>> = OnceLock::new();
+
+const DEFAULT_PG_QUERY: &str =
+    "select email, given_name, family_name, password_hash from users where email = $1";
+
+/// A user, which has been authenticated successfully by an external user store.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct ExternalUser {
+    pub email: String,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub roles: Option<Vec<String>>,
+    pub groups: Option<Vec<String>>,
+}
+
+/// A legacy user database, which is asked on a login for a user, that does not exist in Rauthy.
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Returns the user, if the credentials are valid, and `None` for unknown users or invalid
+    /// credentials.
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<ExternalUser>, ErrorResponse>;
+}
+
+/// Initializes the external user store from the config, if any has been configured.
+pub fn init_user_store() -> Result<(), ErrorResponse> {
+    let Ok(typ) = env::var("USER_STORE_TYPE") else {
+        return Ok(());
+    };
+    let url = env::var("USER_STORE_URL").map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            "USER_STORE_URL is required with USER_STORE_TYPE".to_string(),
+        )
+    })?;
+
+    let store: Box<dyn UserStore> = match typ.as_str() {
+        "rest" => Box::new(RestUserStore::new(
+            url,
+            env::var("USER_STORE_AUTH_HEADER").ok(),
+        )),
+        "postgres" => Box::new(PostgresUserStore::new(
+            &url,
+            env::var("USER_STORE_PG_QUERY").unwrap_or_else(|_| DEFAULT_PG_QUERY.to_string()),
+        )?),
+        _ => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Unknown USER_STORE_TYPE: {}", typ),
+            ));
+        }
+    };
+
+    let default_roles = env::var("USER_STORE_DEFAULT_ROLES")
+        .map(|r| {
+            r.split(',')
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let _ = DEFAULT_ROLES.set(default_roles);
+
+    info!("External user store of type '{}' enabled", typ);
+    let _ = USER_STORE.set(store);
+    Ok(())
+}
+
+/// Checks the credentials for a login, which has not been found in Rauthy, against the external
+/// user store. On success, the user is imported with the given password and returned.
+pub async fn import_on_miss(
+    data: &web::Data<AppState>,
+    login: &str,
+    password: &str,
+    ip: Option<String>,
+) -> Result<Option<User>, ErrorResponse> {
+    let Some(store) = USER_STORE.get() else {
+        return Ok(None);
+    };
+    let Some(ext_user) = store.authenticate(login, password).await? else {
+        return Ok(None);
+    };
+    let Some(email) = imported_email(login, &ext_user.email) else {
+        warn!(
+            "The external user store returned '{}' for the login '{}' - ignoring the user",
+            ext_user.email, login
+        );
+        return Ok(None);
+    };
+
+    let roles = ext_user
+        .roles
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| DEFAULT_ROLES.get().cloned().unwrap_or_default());
+    let new_user = User {
+        email,
+        given_name: ext_user.given_name.unwrap_or_default(),
+        family_name: ext_user.family_name.unwrap_or_default(),
+        password: Some(HashPassword::hash_password(password.to_string()).await?),
+        roles: Role::sanitize(data, filter_roles(roles)).await?,
+        groups: Group::sanitize(data, ext_user.groups).await?,
+        // the external store is trusted like an upstream auth provider
+        email_verified: true,
+        username: (*USERNAME_LOGIN_ENABLE && !login.contains('@')).then(|| login.to_string()),
+        ..Default::default()
+    };
+    // the password is part of the insert, which never leaves a user without it behind
+    let user = User::create_federated(data, new_user).await?;
+
+    info!("Imported user {} from the external user store", user.email);
+    data.tx_events
+        .send_async(
+            Event::new_user(
+                format!("{} (imported from the external user store)", user.email),
+                ip,
+            )
+            .for_user(user.id.clone()),
+        )
+        .await
+        .unwrap();
+
+    Ok(Some(user))
+}
+
+/// Returns the E-Mail for an imported user. If the login is an E-Mail itself, the one from the
+/// external store must be the same, so that a store can never hand out another account.
+fn imported_email(login: &str, ext_email: &str) -> Option<String> {
+    let email = ext_email.trim().to_lowercase();
+    if !email.contains('@') {
+        return None;
+    }
+    if login.contains('@') && login.trim().to_lowercase() != email {
+        return None;
+    }
+
+    if *EMAIL_NORMALIZE {
+        Some(normalize_email(&email))
+    } else {
+        Some(email)
+    }
+}
+
+/// External roles must never grant admin access to Rauthy itself.
+fn filter_roles(roles: Vec<String>) -> Vec<String> {
+    roles
+        .into_iter()
+        .filter(|r| {
+            if r == RAUTHY_ADMIN_ROLE.as_str() {
+                debug!("Ignoring the role '{}' from the external user store", r);
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct RestAuthRequest<'a> {
+    login: &'a str,
+    password: &'a str,
+}
+
+struct RestUserStore {
+    url: String,
+    auth_header: Option<String>,
+    client: reqwest::Client,
+}
+
+impl RestUserStore {
+    fn new(url: String, auth_header: Option<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(10))
+            .user_agent(format!("Rauthy v{} User Store", RAUTHY_VERSION))
+            .min_tls_version(tls::Version::TLS_1_2)
+            .build()
+            .unwrap();
+        Self {
+            url,
+            auth_header,
+            client,
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for RestUserStore {
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<ExternalUser>, ErrorResponse> {
+        let mut req = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, APPLICATION_JSON)
+            .json(&RestAuthRequest { login, password });
+        if let Some(header) = &self.auth_header {
+            req = req.header(AUTHORIZATION, header);
+        }
+
+        let res = req.send().await.map_err(|err| {
+            ErrorResponse::new(
+                ErrorResponseType::Connection,
+                format!("Cannot reach the external user store: {}", err),
+            )
+        })?;
+
+        match res.status() {
+            StatusCode::OK => {
+                let user = res.json::<ExternalUser>().await.map_err(|err| {
+                    ErrorResponse::new(
+                        ErrorResponseType::Internal,
+                        format!("Invalid response from the external user store: {}", err),
+                    )
+                })?;
+                Ok(Some(user))
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(None),
+            status => Err(ErrorResponse::new(
+                ErrorResponseType::Connection,
+                format!("External user store responded with {}", status),
+            )),
+        }
+    }
+}
+
+struct PostgresUserStore {
+    pool: PgPool,
+    query: String,
+}
+
+impl PostgresUserStore {
+    fn new(url: &str, query: String) -> Result<Self, ErrorResponse> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .acquire_timeout(Duration::from_secs(10))
+            .connect_lazy(url)
+            .map_err(|err| {
+                ErrorResponse::new(
+                    ErrorResponseType::Internal,
+                    format!("Invalid USER_STORE_URL: {}", err),
+                )
+            })?;
+        Ok(Self { pool, query })
+    }
+}
+
+#[async_trait]
+impl UserStore for PostgresUserStore {
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<ExternalUser>, ErrorResponse> {
+        let Some(row) = sqlx::query(&self.query)
+            .bind(login)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let hash: Option<String> = row.try_get("password_hash")?;
+        let Some(hash) = hash else {
+            return Ok(None);
+        };
+        if !ComparePasswords::is_match(password.to_string(), hash).await? {
+            warn!(
+                "Invalid credentials for {} in the external user store",
+                login
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(ExternalUser {
+            email: row.try_get("email")?,
+            given_name: row.try_get("given_name")?,
+            family_name: row.try_get("family_name")?,
+            roles: None,
+            groups: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_user_deserialize() {
+        let user = serde_json::from_str::<ExternalUser>(r#"{"email": "Foo@Legacy.io"}"#).unwrap();
+        assert_eq!(
+            user,
+            ExternalUser {
+                email: "Foo@Legacy.io".to_string(),
+                given_name: None,
+                family_name: None,
+                roles: None,
+                groups: None,
+            }
+        );
+
+        let user = serde_json::from_str::<ExternalUser>(
+            r#"{"email": "foo@legacy.io", "given_name": "Foo", "roles": ["user"], "unknown": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(user.given_name.as_deref(), Some("Foo"));
+        assert_eq!(user.roles, Some(vec!["user".to_string()]));
+    }
+
+    #[test]
+    fn test_imported_email() {
+        assert_eq!(
+            imported_email("Foo@Legacy.io", "foo@legacy.io").as_deref(),
+            Some("foo@legacy.io")
+        );
+        assert!(imported_email("foo@legacy.io", "admin@legacy.io").is_none());
+        assert!(imported_email("foo", "no-email").is_none());
+        // a username login cannot be compared, but the E-Mail must still be a valid one
+        assert_eq!(
+            imported_email("foo", "foo@legacy.io").as_deref(),
+            Some("foo@legacy.io")
+        );
+    }
+
+    #[test]
+    fn test_filter_roles() {
+        let roles = vec![
+            "user".to_string(),
+            "rauthy_admin".to_string(),
+            "admin".to_string(),
+        ];
+        assert_eq!(
+            filter_roles(roles),
+            vec!["user".to_string(), "admin".to_string()]
+        );
+    }
+}
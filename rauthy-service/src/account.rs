@@ -0,0 +1,173 @@
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::devices::DeviceEntity;
+use rauthy_models::entity::email_aliases::EmailAlias;
+use rauthy_models::entity::refresh_tokens::RefreshToken;
+use rauthy_models::entity::user_attr::UserAttrValueEntity;
+use rauthy_models::entity::user_client_grants::UserClientGrant;
+use rauthy_models::entity::user_notification_prefs::UserNotificationPrefs;
+use rauthy_models::entity::users::User;
+use rauthy_models::entity::users_values::UserValues;
+use rauthy_models::entity::webauthn::PasskeyEntity;
+use rauthy_models::events::event::Event;
+use rauthy_models::request::UserPreferencesRequest;
+use rauthy_models::response::{
+    ConnectedAppResponse, DeviceResponse, EmailAliasResponse, PasskeyResponse,
+    SecurityEventResponse, UserAttrValueResponse, UserDataExportResponse, UserPreferencesResponse,
+    UserResponse, UserSecurityResponse,
+};
+
+/// The max amount of security events, which will be returned for a single user
+const SECURITY_EVENTS_LIMIT: i64 = 50;
+
+/// All clients the user has logged in to, which have not been revoked.
+pub async fn connected_apps(
+    data: &web::Data<AppState>,
+    user_id: &str,
+) -> Result<Vec<ConnectedAppResponse>, ErrorResponse> {
+    let grants = UserClientGrant::find_for_user(data, user_id).await?;
+
+    let mut res = Vec::with_capacity(grants.len());
+    for grant in grants {
+        let client = Client::find(data, grant.client_id.clone()).await.ok();
+        res.push(ConnectedAppResponse {
+            client_id: grant.client_id,
+            client_name: client.as_ref().and_then(|c| c.name.clone()),
+            client_uri: client.and_then(|c| c.client_uri),
+            scope: grant.scope,
+            created: grant.created,
+            last_used: grant.last_used,
+        });
+    }
+
+    Ok(res)
+}
+
+pub async fn security_overview(
+    data: &web::Data<AppState>,
+    user: User,
+) -> Result<UserSecurityResponse, ErrorResponse> {
+    let passkeys = passkeys(data, &user.id).await?;
+    let active_refresh_tokens = RefreshToken::count_valid(data, Some(&user.id)).await?;
+    let events = security_events(data, &user.id).await?;
+
+    Ok(UserSecurityResponse {
+        account_type: user.account_type().into(),
+        mfa_enabled: user.has_mfa_enabled(),
+        passkeys,
+        password_expires: user.password_expires,
+        last_login: user.last_login,
+        last_failed_login: user.last_failed_login,
+        failed_login_attempts: user.failed_login_attempts,
+        active_refresh_tokens,
+        events,
+    })
+}
+
+/// Collects all personal data for the given user.
+pub async fn data_export(
+    data: &web::Data<AppState>,
+    user: User,
+) -> Result<UserDataExportResponse, ErrorResponse> {
+    let attributes = UserAttrValueEntity::find_for_user(data, &user.id)
+        .await?
+        .into_iter()
+        .map(UserAttrValueResponse::from)
+        .collect();
+    let email_aliases = EmailAlias::find_for_user(data, &user.id)
+        .await?
+        .into_iter()
+        .map(EmailAliasResponse::from)
+        .collect();
+    let devices = DeviceEntity::find_for_user(data, &user.id)
+        .await?
+        .into_iter()
+        .map(DeviceResponse::from)
+        .collect();
+
+    let passkeys = passkeys(data, &user.id).await?;
+    let connected_apps = connected_apps(data, &user.id).await?;
+    let preferences = preferences(data, &user).await?;
+    let events = security_events(data, &user.id).await?;
+
+    let values = UserValues::find(data, &user.id).await?;
+
+    Ok(UserDataExportResponse {
+        exported_at: Utc::now().timestamp(),
+        user: UserResponse::build(user, values),
+        attributes,
+        email_aliases,
+        passkeys,
+        devices,
+        connected_apps,
+        preferences,
+        events,
+    })
+}
+
+pub async fn preferences(
+    data: &web::Data<AppState>,
+    user: &User,
+) -> Result<UserPreferencesResponse, ErrorResponse> {
+    let prefs = UserNotificationPrefs::find(data, &user.id).await?;
+    Ok(UserPreferencesResponse {
+        language: user.language,
+        notify_password_expiry: prefs.password_expiry,
+        notify_sessions_revoked: prefs.sessions_revoked,
+    })
+}
+
+pub async fn update_preferences(
+    data: &web::Data<AppState>,
+    mut user: User,
+    payload: UserPreferencesRequest,
+) -> Result<UserPreferencesResponse, ErrorResponse> {
+    if let Some(language) = payload.language {
+        if language != user.language {
+            user.language = language;
+            user.save(data, None, None).await?;
+        }
+    }
+
+    let mut prefs = UserNotificationPrefs::find(data, &user.id).await?;
+    if let Some(notify) = payload.notify_password_expiry {
+        prefs.password_expiry = notify;
+    }
+    if let Some(notify) = payload.notify_sessions_revoked {
+        prefs.sessions_revoked = notify;
+    }
+    prefs.save(data).await?;
+
+    Ok(UserPreferencesResponse {
+        language: user.language,
+        notify_password_expiry: prefs.password_expiry,
+        notify_sessions_revoked: prefs.sessions_revoked,
+    })
+}
+
+async fn passkeys(
+    data: &web::Data<AppState>,
+    user_id: &str,
+) -> Result<Vec<PasskeyResponse>, ErrorResponse> {
+    let res = PasskeyEntity::find_for_user(data, user_id)
+        .await?
+        .into_iter()
+        .map(PasskeyResponse::from)
+        .collect();
+    Ok(res)
+}
+
+async fn security_events(
+    data: &web::Data<AppState>,
+    user_id: &str,
+) -> Result<Vec<SecurityEventResponse>, ErrorResponse> {
+    let res = Event::find_for_user(&data.db, user_id, SECURITY_EVENTS_LIMIT)
+        .await?
+        .into_iter()
+        .map(SecurityEventResponse::from)
+        .collect();
+    Ok(res)
+}
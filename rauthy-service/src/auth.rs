@@ -1,54 +1,88 @@
+use crate::password_reset;
 use crate::token_set::{
-    AtHash, AuthCodeFlow, DeviceCodeFlow, DpopFingerprint, TokenNonce, TokenScopes, TokenSet,
+    AtHash, AuthCodeFlow, DeviceCodeFlow, DpopFingerprint, RefreshTokenChain,
+    TokenAuthorizationDetails, TokenExchange, TokenNonce, TokenScopes, TokenSet, TokenSid,
 };
 use actix_web::http::header;
 use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
 use actix_web::{web, HttpRequest, HttpResponse};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use cryptr::{EncKeys, EncValue};
 use jwt_simple::algorithms::{
     EdDSAKeyPairLike, EdDSAPublicKeyLike, RSAKeyPairLike, RSAPublicKeyLike,
 };
 use jwt_simple::claims;
 use jwt_simple::prelude::*;
+use rauthy_common::cache_metrics::cache_del;
 use rauthy_common::constants::{
-    CACHE_NAME_12HR, CACHE_NAME_LOGIN_DELAY, COOKIE_MFA, DEVICE_GRANT_POLL_INTERVAL,
-    DEVICE_GRANT_REFRESH_TOKEN_LIFETIME, ENABLE_SOLID_AUD, ENABLE_WEB_ID, HEADER_DPOP_NONCE,
-    IDX_JWKS, IDX_JWK_LATEST, IDX_LOGIN_TIME, REFRESH_TOKEN_LIFETIME, SESSION_LIFETIME,
-    SESSION_RENEW_MFA, TOKEN_BEARER, USERINFO_STRICT, WEBAUTHN_REQ_EXP,
+    ACCESS_TOKEN_JWT_PROFILE, CACHE_NAME_12HR, CLIENT_ASSERTION_TYPE_AWS_STS,
+    CLIENT_ASSERTION_TYPE_GCP_ID_TOKEN, CLIENT_ASSERTION_TYPE_SPIFFE, CLOCK_SKEW_LEEWAY,
+    COOKIE_MFA, DEVICE_GRANT_POLL_INTERVAL, DEVICE_GRANT_REFRESH_TOKEN_LIFETIME,
+    DPOP_USERINFO_ENDPOINT, ENABLE_SOLID_AUD, ENABLE_WEB_ID, GRANT_TYPE_DEVICE_CODE,
+    GRANT_TYPE_TOKEN_EXCHANGE, HEADER_DPOP_NONCE, IDX_JWKS, IDX_JWK_LATEST, JWT_TYP_ACCESS_TOKEN,
+    REFRESH_TOKEN_IDLE_TIMEOUT, REFRESH_TOKEN_LIFETIME, SESSION_LIFETIME, SESSION_RENEW_MFA,
+    SESSION_TOKEN_CLIENTS, SESSION_TOKEN_LIFETIME, SESSION_TOKEN_ROTATE, TOKEN_BEARER, TOKEN_DPOP,
+    TOKEN_EXCHANGE_MAX_ACTORS, TOKEN_TYPE_ACCESS_TOKEN, USERINFO_STRICT, WEBAUTHN_REQ_EXP,
 };
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::login_delay;
+use rauthy_common::login_latency;
+use rauthy_common::login_latency::LatencyStage;
 use rauthy_common::password_hasher::HashPassword;
-use rauthy_common::utils::{base64_url_encode, get_client_ip, get_rand, new_store_id};
+use rauthy_common::token_errors;
+use rauthy_common::utils::{
+    base64_url_encode, get_client_ip, get_rand, is_trusted_login_ip, new_store_id, real_ip_from_req,
+};
 use rauthy_models::app_state::AppState;
-use rauthy_models::entity::auth_codes::AuthCode;
+use rauthy_models::cache_invalidation::Invalidation;
+use rauthy_models::entity::app_attestation::AppAttestation;
+use rauthy_models::entity::auth_codes::{AuthCode, AuthCodeUsage};
 use rauthy_models::entity::clients::Client;
 use rauthy_models::entity::clients_dyn::ClientDyn;
 use rauthy_models::entity::colors::ColorEntity;
 use rauthy_models::entity::devices::{DeviceAuthCode, DeviceEntity};
 use rauthy_models::entity::dpop_proof::DPoPProof;
-use rauthy_models::entity::jwk::{Jwk, JwkKeyPair, JwkKeyPairAlg};
+use rauthy_models::entity::email_mfa::EmailMfaCode;
+use rauthy_models::entity::feature_flags::FeatureFlag;
+use rauthy_models::entity::jwk::{Jwk, JwkKeyPair, JwkKeyPairAlg, JwkLifecycle};
+use rauthy_models::entity::lab::LabMode;
+use rauthy_models::entity::login_traces::LoginTracer;
+use rauthy_models::entity::magic_links::MagicLink;
+use rauthy_models::entity::organizations::Organization;
+use rauthy_models::entity::push_mfa::PushMfaRequest;
 use rauthy_models::entity::refresh_tokens::RefreshToken;
 use rauthy_models::entity::refresh_tokens_devices::RefreshTokenDevice;
+use rauthy_models::entity::request_objects::{RequestObject, RequestObjectLogin};
+use rauthy_models::entity::resource_servers::ResourceServer;
+use rauthy_models::entity::revoked_tokens::RevokedToken;
 use rauthy_models::entity::scopes::Scope;
 use rauthy_models::entity::sessions::{Session, SessionState};
+use rauthy_models::entity::spiffe::SpiffeSvid;
+use rauthy_models::entity::used_jtis::UsedJti;
+use rauthy_models::entity::user_activity::{UserActivity, UserActivityKind};
+use rauthy_models::entity::user_attr::UserAttrConfigEntity;
+use rauthy_models::entity::user_merges::UserMerge;
 use rauthy_models::entity::users::{AccountType, User};
 use rauthy_models::entity::users_values::UserValues;
 use rauthy_models::entity::webauthn::{WebauthnCookie, WebauthnLoginReq};
 use rauthy_models::entity::webids::WebId;
+use rauthy_models::entity::workload_identity::WorkloadIdentity;
 use rauthy_models::events::event::Event;
 use rauthy_models::events::ip_blacklist_handler::{IpBlacklistReq, IpFailedLoginCheck};
 use rauthy_models::language::Language;
-use rauthy_models::request::{LoginRefreshRequest, LoginRequest, LogoutRequest, TokenRequest};
-use rauthy_models::response::{OAuth2ErrorResponse, OAuth2ErrorTypeResponse, TokenInfo, Userinfo};
+use rauthy_models::request::{
+    LoginRefreshRequest, LoginRequest, LogoutRequest, SessionTokenRequest, TokenRequest,
+};
+use rauthy_models::response::{
+    FeatureFlagsEvaluatedResponse, OAuth2ErrorResponse, OAuth2ErrorTypeResponse, SidInfo,
+    TokenInfo, Userinfo,
+};
 use rauthy_models::templates::{LogoutHtml, TooManyRequestsHtml};
 use rauthy_models::{
-    sign_jwt, validate_jwt, AddressClaim, AuthStep, AuthStepAwaitWebauthn, AuthStepLoggedIn,
-    JktClaim, JwtAccessClaims, JwtAmrValue, JwtCommonClaims, JwtIdClaims, JwtRefreshClaims,
-    JwtTokenType,
+    sign_jwt, sign_jwt_with_options, user_store, validate_jwt, ActClaim, AddressClaim, AuthStep,
+    AuthStepAwaitProfile, AuthStepAwaitWebauthn, AuthStepLoggedIn, AuthorizationDetail, JktClaim,
+    JwtAccessClaims, JwtAmrValue, JwtCommonClaims, JwtIdClaims, JwtRefreshClaims, JwtTokenType,
 };
-use redhac::cache_del;
-use redhac::{cache_get, cache_get_from, cache_get_value, cache_put};
 use ring::digest;
 use std::borrow::Cow;
 use std::cmp::PartialEq;
@@ -66,24 +100,88 @@ pub async fn authorize(
     data: &web::Data<AppState>,
     req: &HttpRequest,
     req_data: LoginRequest,
-    mut session: Session,
+    session: Session,
     // the second argument with the error will be 'true' if a login delay should be added
 ) -> Result<AuthStep, (ErrorResponse, bool)> {
+    let ip = real_ip_from_req(req).unwrap_or_default();
+    let mut tracer = LoginTracer::start(data, ip, Some(&req_data.email)).await;
+    tracer.info(
+        "login_request",
+        format!(
+            "client_id={}, redirect_uri={}, scopes={:?}, password_given={}, code_challenge_method={:?}",
+            req_data.client_id,
+            req_data.redirect_uri,
+            req_data.scopes,
+            req_data.password.is_some(),
+            req_data.code_challenge_method,
+        ),
+    );
+
+    let res = authorize_traced(data, req, req_data, session, &mut tracer).await;
+    match &res {
+        Ok(AuthStep::LoggedIn(_)) => tracer.info("result", "logged in"),
+        Ok(AuthStep::AwaitWebauthn(_)) => tracer.info("result", "awaiting webauthn"),
+        Ok(AuthStep::AwaitEmailMfa(_)) => tracer.info("result", "awaiting email mfa"),
+        Ok(AuthStep::AwaitPushMfa(_)) => tracer.info("result", "awaiting push mfa approval"),
+        Ok(AuthStep::AwaitProfile(_)) => tracer.info("result", "awaiting missing user attributes"),
+        Ok(AuthStep::ProviderLink) => tracer.info("result", "provider link"),
+        Err((err, login_delay)) => tracer.info(
+            "result",
+            format!(
+                "{}: {} - login delay: {}",
+                err.error, err.message, login_delay
+            ),
+        ),
+    }
+    tracer.finish(data).await;
+
+    res
+}
+
+async fn authorize_traced(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    req_data: LoginRequest,
+    mut session: Session,
+    tracer: &mut LoginTracer,
+) -> Result<AuthStep, (ErrorResponse, bool)> {
+    let request_object = RequestObjectLogin::from(&req_data);
+
+    tracer
+        .check(
+            "lab_principal",
+            LabMode::validate_principal(req, Some(&req_data.email), Some(&req_data.client_id)),
+        )
+        .map_err(|err| (err, true))?;
+
     // This Error must be the same if user does not exist AND passwords do not match to prevent
     // username enumeration
-    let mut user = User::find_by_email(data, req_data.email)
-        .await
-        .map_err(|e| {
-            error!("{:?}", e);
-            // be careful, that this Err and the one in User::validate_password are exactly the same
-            (
-                ErrorResponse::new(
-                    ErrorResponseType::Unauthorized,
-                    String::from("Invalid user credentials"),
-                ),
-                false,
+    let mut user_res = User::find_by_login(data, req_data.email.clone()).await;
+    if let (Err(_), Some(password)) = (&user_res, &req_data.password) {
+        // users, which have not been migrated yet, are imported on their first login
+        match user_store::import_on_miss(data, &req_data.email, password, real_ip_from_req(req))
+            .await
+        {
+            Ok(Some(user)) => {
+                tracer.info("user_store", "imported from the external user store");
+                user_res = Ok(user);
+            }
+            Ok(None) => {}
+            Err(err) => error!("External user store lookup: {:?}", err),
+        }
+    }
+    let mut user = tracer.check("user_lookup", user_res).map_err(|e| {
+        error!("{:?}", e);
+        // be careful, that this Err and the one in User::validate_password are exactly the same
+        (
+            ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                String::from("Invalid user credentials"),
             )
-        })?;
+            .with_code(ErrorCode::InvalidCredentials),
+            false,
+        )
+    })?;
 
     let mfa_cookie = if let Ok(c) = WebauthnCookie::parse_validate(&req.cookie(COOKIE_MFA)) {
         if c.email == user.email && user.has_webauthn_enabled() {
@@ -98,6 +196,14 @@ pub async fn authorize(
     };
 
     let account_type = user.account_type();
+    tracer.info(
+        "user_account",
+        format!(
+            "account_type={:?}, mfa_cookie_valid={}",
+            account_type,
+            mfa_cookie.is_some()
+        ),
+    );
 
     // this allows a user without the mfa cookie to login anyway if it is an only passkey account
     // in this case, UV is always enforced, not matter what -> safe to login without cookie
@@ -105,11 +211,13 @@ pub async fn authorize(
         req_data.password.is_none() && account_type != AccountType::Passkey && mfa_cookie.is_none();
     if user_must_provide_password {
         trace!("No user password has been provided");
+        tracer.info("password_required", "no password has been provided");
         return Err((
             ErrorResponse::new(
                 ErrorResponseType::Unauthorized,
                 String::from("Invalid user credentials"),
-            ),
+            )
+            .with_code(ErrorCode::InvalidCredentials),
             false,
         ));
     }
@@ -119,20 +227,45 @@ pub async fn authorize(
             ErrorResponse::new(
                 ErrorResponseType::Unauthorized,
                 String::from("Invalid user credentials"),
-            ),
+            )
+            .with_code(ErrorCode::InvalidCredentials),
             // this basically means, if the user did the first login in the UI with just username,
             // do not add any login delay afterwards for a better UX
             !user_must_provide_password,
         ));
     }
 
-    user.check_enabled()
+    tracer
+        .check("user_enabled", user.check_enabled())
         .map_err(|err| (err, !user_must_provide_password))?;
-    user.check_expired()
+    tracer
+        .check("user_expired", user.check_expired())
         .map_err(|err| (err, !user_must_provide_password))?;
 
     let has_password_been_hashed = if let Some(pwd) = req_data.password {
-        match user.validate_password(data, pwd).await {
+        let otp_link = MagicLink::find_password_otp(data, &user.id)
+            .await
+            .map_err(|err| (err, true))?;
+        if let Some(otp_link) = otp_link {
+            let loc = tracer
+                .check(
+                    "password_otp",
+                    password_reset::redeem_admin_otp(data, &mut user, otp_link, pwd).await,
+                )
+                .map_err(|err| (err, true))?;
+            return Ok(AuthStep::LoggedIn(AuthStepLoggedIn {
+                has_password_been_hashed: true,
+                email: user.email,
+                header_loc: (header::LOCATION, HeaderValue::from_str(&loc).unwrap()),
+                header_csrf: Session::get_csrf_header(&session.csrf_token),
+                header_origin: None,
+            }));
+        }
+
+        match tracer.check(
+            "password_validation",
+            user.validate_password_login(data, pwd).await,
+        ) {
             Ok(_) => {
                 // update user info
                 // in case of webauthn login, the info will be updates in the auth finish step
@@ -153,33 +286,103 @@ pub async fn authorize(
         false
     };
 
+    tracer
+        .check(
+            "login_policy",
+            user.check_login_policy(data, real_ip_from_req(req)).await,
+        )
+        .map_err(|err| (err, has_password_been_hashed))?;
+
     // client validations
-    let client = Client::find_maybe_ephemeral(data, req_data.client_id)
-        .await
+    let client = tracer
+        .check(
+            "client_lookup",
+            Client::find_maybe_ephemeral(data, req_data.client_id).await,
+        )
         .map_err(|err| (err, !user_must_provide_password))?;
-    client
-        .validate_mfa(&user)
+    tracer
+        .check("client_force_mfa", client.validate_mfa(&user))
         .map_err(|err| (err, has_password_been_hashed))?;
-    client
-        .validate_redirect_uri(&req_data.redirect_uri)
+    tracer
+        .check(
+            "client_redirect_uri",
+            client.validate_redirect_uri(&req_data.redirect_uri),
+        )
         .map_err(|err| (err, !user_must_provide_password))?;
-    client
-        .validate_code_challenge(&req_data.code_challenge, &req_data.code_challenge_method)
+    tracer
+        .check(
+            "client_code_challenge",
+            client
+                .validate_code_challenge(&req_data.code_challenge, &req_data.code_challenge_method),
+        )
+        .map_err(|err| (err, !user_must_provide_password))?;
+    tracer
+        .check(
+            "client_request_object",
+            RequestObject::verify_login(data, &client, request_object).await,
+        )
         .map_err(|err| (err, !user_must_provide_password))?;
-    let header_origin = client
-        .validate_origin(req, &data.listen_scheme, &data.public_url)
+    let header_origin = tracer
+        .check(
+            "client_origin",
+            client.validate_origin(req, &data.listen_scheme, &data.public_url),
+        )
         .map_err(|err| (err, !user_must_provide_password))?;
 
+    // required user attributes must exist before any code will be issued
+    let (missing_attrs, user_attrs) = tracer
+        .check(
+            "user_attrs_required",
+            UserAttrConfigEntity::collect_missing_required(
+                data,
+                &user.id,
+                &client.id,
+                req_data.user_attrs,
+            )
+            .await,
+        )
+        .map_err(|err| (err, !user_must_provide_password))?;
+    if !missing_attrs.is_empty() {
+        tracer.info(
+            "user_attrs_required",
+            format!("{} required attributes missing", missing_attrs.len()),
+        );
+        return Ok(AuthStep::AwaitProfile(AuthStepAwaitProfile {
+            has_password_been_hashed,
+            header_csrf: Session::get_csrf_header(&session.csrf_token),
+            header_origin,
+            missing_attrs,
+        }));
+    }
+
     // build authorization code
-    let code_lifetime = if user.has_webauthn_enabled() {
-        client.auth_code_lifetime + *WEBAUTHN_REQ_EXP as i32
-    } else {
-        client.auth_code_lifetime
-    };
-    let scopes = client
-        .sanitize_login_scopes(&req_data.scopes)
+    let code_lifetime = client.auth_code_lifetime + user.mfa_code_lifetime();
+    let scopes = tracer
+        .check(
+            "client_scopes",
+            client.sanitize_login_scopes(&req_data.scopes),
+        )
+        .map_err(|err| (err, !user_must_provide_password))?;
+    tracer
+        .check(
+            "client_resource",
+            ResourceServer::validate_login(data, req_data.resource.as_deref(), &client.id, &scopes)
+                .await,
+        )
         .map_err(|err| (err, !user_must_provide_password))?;
-    let code = AuthCode::new(
+    tracer
+        .check(
+            "client_authorization_details",
+            AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref()),
+        )
+        .map_err(|err| (err, !user_must_provide_password))?;
+    tracer
+        .check(
+            "client_request_object_used",
+            RequestObject::consume(data, req_data.request.as_deref()).await,
+        )
+        .map_err(|err| (err, !user_must_provide_password))?;
+    let mut code = AuthCode::new(
         user.id.clone(),
         client.id,
         Some(session.id.clone()),
@@ -187,22 +390,51 @@ pub async fn authorize(
         req_data.code_challenge_method,
         req_data.nonce,
         scopes,
+        req_data.resource,
+        req_data.authorization_details,
         code_lifetime,
     );
+    code.user_attrs = user_attrs;
     code.save(data)
         .await
         .map_err(|err| (err, !user_must_provide_password))?;
 
     // build location header
-    let mut loc = format!("{}?code={}", req_data.redirect_uri, code.id);
-    if let Some(state) = req_data.state {
-        loc = format!("{}&state={}", loc, state);
-    };
+    let loc = code
+        .response_location(
+            data,
+            &req_data.redirect_uri,
+            req_data.state,
+            req_data.response_mode.unwrap_or_default(),
+        )
+        .await
+        .map_err(|err| (err, !user_must_provide_password))?;
 
     // TODO double check that we do not have any problems with the direct webauthn login here
     // TODO should we allow to skip this step if set so in the config?
+    let push_mfa = PushMfaRequest::is_available(data, &user)
+        .await
+        .map_err(|err| (err, !user_must_provide_password))?;
+
     // check if we need to validate the 2nd factor
-    if user.has_webauthn_enabled() {
+    if push_mfa {
+        tracer.info(
+            "mfa",
+            "user has push subscriptions - sending approval request",
+        );
+        PushMfaRequest::await_login(
+            data,
+            &user,
+            &session,
+            loc,
+            header_origin,
+            has_password_been_hashed,
+            real_ip_from_req(req),
+        )
+        .await
+        .map_err(|err| (err, !user_must_provide_password))
+    } else if user.has_webauthn_enabled() {
+        tracer.info("mfa", "user has webauthn enabled - awaiting 2nd factor");
         session
             .set_mfa(data, true)
             .await
@@ -233,6 +465,18 @@ pub async fn authorize(
         .map_err(|err| (err, !user_must_provide_password))?;
 
         Ok(AuthStep::AwaitWebauthn(step))
+    } else if user.has_email_mfa_enabled() {
+        tracer.info("mfa", "user has email mfa enabled - sending one-time code");
+        EmailMfaCode::await_login(
+            data,
+            &user,
+            &session,
+            loc,
+            header_origin,
+            has_password_been_hashed,
+        )
+        .await
+        .map_err(|err| (err, !user_must_provide_password))
     } else {
         Ok(AuthStep::LoggedIn(AuthStepLoggedIn {
             has_password_been_hashed,
@@ -251,6 +495,7 @@ pub async fn authorize_refresh(
     client: Client,
     header_origin: Option<(HeaderName, HeaderValue)>,
     req_data: LoginRefreshRequest,
+    ip: Option<String>,
 ) -> Result<AuthStep, ErrorResponse> {
     let user_id = session.user_id.as_ref().ok_or_else(|| {
         ErrorResponse::new(
@@ -258,20 +503,37 @@ pub async fn authorize_refresh(
             String::from("No linked user_id for already validated session"),
         )
     })?;
-    let user = User::find(data, user_id.clone()).await?;
+    let mut user = User::find(data, user_id.clone()).await?;
     user.check_enabled()?;
     user.check_expired()?;
+    user.check_login_policy(data, ip.clone()).await?;
 
     client.validate_mfa(&user)?;
+    RequestObject::verify_login(data, &client, RequestObjectLogin::from(&req_data)).await?;
+
+    let (missing_attrs, user_attrs) = UserAttrConfigEntity::collect_missing_required(
+        data,
+        &user.id,
+        &client.id,
+        req_data.user_attrs,
+    )
+    .await?;
+    if !missing_attrs.is_empty() {
+        return Ok(AuthStep::AwaitProfile(AuthStepAwaitProfile {
+            has_password_been_hashed: false,
+            header_csrf: Session::get_csrf_header(&session.csrf_token),
+            header_origin,
+            missing_attrs,
+        }));
+    }
 
     let scopes = client.sanitize_login_scopes(&req_data.scopes)?;
-    let code_lifetime = if user.has_webauthn_enabled() {
-        client.auth_code_lifetime + *WEBAUTHN_REQ_EXP as i32
-    } else {
-        client.auth_code_lifetime
-    };
+    ResourceServer::validate_login(data, req_data.resource.as_deref(), &client.id, &scopes).await?;
+    AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref())?;
+    RequestObject::consume(data, req_data.request.as_deref()).await?;
+    let code_lifetime = client.auth_code_lifetime + user.mfa_code_lifetime();
 
-    let code = AuthCode::new(
+    let mut code = AuthCode::new(
         user.id.clone(),
         client.id,
         Some(session.id.clone()),
@@ -279,19 +541,32 @@ pub async fn authorize_refresh(
         req_data.code_challenge_method,
         req_data.nonce,
         scopes,
+        req_data.resource,
+        req_data.authorization_details,
         code_lifetime,
     );
+    code.user_attrs = user_attrs;
     code.save(data).await?;
 
+    // a login with an existing session is a login as well for the inactivity tracking
+    user.last_login = Some(OffsetDateTime::now_utc().unix_timestamp());
+    user.save(data, None, None).await?;
+
     // build location header
-    let header_loc = if let Some(s) = req_data.state {
-        format!("{}?code={}&state={}", req_data.redirect_uri, code.id, s)
-    } else {
-        format!("{}?code={}", req_data.redirect_uri, code.id)
-    };
+    let header_loc = code
+        .response_location(
+            data,
+            &req_data.redirect_uri,
+            req_data.state,
+            req_data.response_mode.unwrap_or_default(),
+        )
+        .await?;
 
     // check if we need to validate the 2nd factor
-    if user.has_webauthn_enabled() && *SESSION_RENEW_MFA {
+    if *SESSION_RENEW_MFA && PushMfaRequest::is_available(data, &user).await? {
+        PushMfaRequest::await_login(data, &user, session, header_loc, header_origin, false, ip)
+            .await
+    } else if user.has_webauthn_enabled() && *SESSION_RENEW_MFA {
         let step = AuthStepAwaitWebauthn {
             has_password_been_hashed: false,
             code: get_rand(48),
@@ -315,6 +590,8 @@ pub async fn authorize_refresh(
         login_req.save(data).await?;
 
         Ok(AuthStep::AwaitWebauthn(step))
+    } else if user.has_email_mfa_enabled() && *SESSION_RENEW_MFA {
+        EmailMfaCode::await_login(data, &user, session, header_loc, header_origin, false).await
     } else {
         Ok(AuthStep::LoggedIn(AuthStepLoggedIn {
             has_password_been_hashed: false,
@@ -329,7 +606,8 @@ pub async fn authorize_refresh(
     }
 }
 
-/// Builds the access token for a user after all validation has been successful
+/// Builds the access token for a user after all validation has been successful.
+/// Returns the token together with its `jti`.
 // too many arguments is not an issue - params cannot be mistaken because of enum wrappers
 #[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub async fn build_access_token(
@@ -341,7 +619,11 @@ pub async fn build_access_token(
     scope: Option<TokenScopes>,
     scope_customs: Option<(Vec<&Scope>, &Option<HashMap<String, Vec<u8>>>)>,
     device_code_flow: DeviceCodeFlow,
-) -> Result<String, ErrorResponse> {
+    sid: Option<TokenSid>,
+    exchange: Option<TokenExchange>,
+    resource: Option<&ResourceServer>,
+    authorization_details: Option<Vec<AuthorizationDetail>>,
+) -> Result<(String, String), ErrorResponse> {
     let did = match device_code_flow {
         DeviceCodeFlow::Yes(did) => Some(did),
         DeviceCodeFlow::No => None,
@@ -349,22 +631,40 @@ pub async fn build_access_token(
     let mut custom_claims = JwtAccessClaims {
         typ: JwtTokenType::Bearer,
         azp: client.id.to_string(),
+        client_id: if *ACCESS_TOKEN_JWT_PROFILE {
+            Some(client.id.to_string())
+        } else {
+            None
+        },
         scope: scope
             .map(|s| s.0)
             .unwrap_or_else(|| client.default_scopes.clone().replace(',', " ")),
+        authorization_details,
         allowed_origins: None,
         did,
         email: None,
         preferred_username: None,
         roles: None,
         groups: None,
+        organizations: None,
+        flags: None,
         cnf: dpop_fingerprint.map(|jkt| JktClaim { jkt: jkt.0 }),
         custom: None,
+        sid: sid.map(|sid| sid.0),
+        act: None,
+    };
+    let audience = match (exchange, resource) {
+        (Some(exchange), _) => {
+            custom_claims.act = exchange.act;
+            exchange.audience
+        }
+        (None, Some(rs)) => rs.resource.clone(),
+        (None, None) => client.id.to_string(),
     };
 
     // add user specific claims if available
     let sub = if let Some(user) = user {
-        custom_claims.preferred_username = Some(user.email.clone());
+        custom_claims.preferred_username = Some(user.preferred_username().to_string());
         custom_claims.roles = Some(user.get_roles());
 
         if custom_claims.scope.contains("email") {
@@ -375,6 +675,15 @@ pub async fn build_access_token(
             custom_claims.groups = Some(user.get_groups());
         }
 
+        if custom_claims.scope.contains("organizations") {
+            custom_claims.organizations = Some(Organization::find_for_user(data, &user.id).await?);
+        }
+
+        if custom_claims.scope.contains("flags") {
+            custom_claims.flags =
+                Some(FeatureFlag::find_enabled_names(data, user, &client.id).await?);
+        }
+
         Some(&user.id)
     } else {
         None
@@ -405,13 +714,20 @@ pub async fn build_access_token(
         coarsetime::Duration::from_secs(lifetime as u64),
     )
     .with_issuer(data.issuer.clone())
-    .with_audience(client.id.to_string());
+    .with_audience(audience);
 
     if let Some(sub) = sub {
         claims = claims.with_subject(sub);
+    } else if *ACCESS_TOKEN_JWT_PROFILE {
+        // RFC 9068 requires a `sub` - without a user, the client is the subject
+        claims = claims.with_subject(&client.id);
     }
+    // always set, because single tokens can only be revoked by their `jti`
+    let jti = get_rand(24);
+    claims = claims.with_jwt_id(&jti);
 
-    sign_access_token(data, claims, client).await
+    let token = sign_access_token(data, claims, client).await?;
+    Ok((token, jti))
 }
 
 /// Builds the id token for a user after all validation has been successful
@@ -427,6 +743,7 @@ pub async fn build_id_token(
     scope: &str,
     scope_customs: Option<(Vec<&Scope>, &Option<HashMap<String, Vec<u8>>>)>,
     auth_code_flow: AuthCodeFlow,
+    sid: Option<TokenSid>,
 ) -> Result<String, ErrorResponse> {
     let now_ts = Utc::now().timestamp();
 
@@ -466,7 +783,7 @@ pub async fn build_id_token(
         amr: vec![amr],
         auth_time,
         at_hash: at_hash.0,
-        preferred_username: user.email.clone(),
+        preferred_username: user.preferred_username().to_string(),
         email: None,
         email_verified: None,
         given_name: None,
@@ -477,9 +794,12 @@ pub async fn build_id_token(
         phone: None,
         roles: user.get_roles(),
         groups: None,
+        organizations: None,
+        flags: None,
         cnf: dpop_fingerprint.map(|jkt| JktClaim { jkt: jkt.0 }),
         custom: None,
         webid,
+        sid: sid.map(|sid| sid.0),
     };
 
     let mut user_values = None;
@@ -533,6 +853,14 @@ pub async fn build_id_token(
         custom_claims.groups = Some(user.get_groups());
     }
 
+    if scope.contains("organizations") {
+        custom_claims.organizations = Some(Organization::find_for_user(data, &user.id).await?);
+    }
+
+    if scope.contains("flags") {
+        custom_claims.flags = Some(FeatureFlag::find_enabled_names(data, user, &client.id).await?);
+    }
+
     if let Some((cust, user_attrs)) = scope_customs {
         let user_attrs = user_attrs.as_ref().unwrap();
         let mut attr = HashMap::with_capacity(cust.len());
@@ -589,12 +917,22 @@ pub async fn build_refresh_token(
     scope: Option<TokenScopes>,
     is_mfa: bool,
     device_code_flow: DeviceCodeFlow,
+    sid: Option<TokenSid>,
+    rt_chain: RefreshTokenChain,
+    resource: Option<&ResourceServer>,
+    authorization_details: Option<Vec<AuthorizationDetail>>,
 ) -> Result<String, ErrorResponse> {
+    // DPoP bound tokens are bound to their key already, which is a lot stronger
+    let device_fp = rt_chain.device_fp.filter(|_| dpop_fingerprint.is_none());
+
     let custom_claims = JwtRefreshClaims {
         azp: client.id.clone(),
         typ: JwtTokenType::Refresh,
         uid: user.id.clone(),
         cnf: dpop_fingerprint.map(|jkt| JktClaim { jkt: jkt.0 }),
+        sid: sid.map(|sid| sid.0),
+        resource: resource.map(|rs| rs.resource.clone()),
+        authorization_details,
     };
 
     let claims = Claims::with_custom_claims(custom_claims, coarsetime::Duration::from_hours(48))
@@ -622,7 +960,23 @@ pub async fn build_refresh_token(
         )
         .await?;
     } else {
-        let exp = nbf.add(chrono::Duration::hours(*REFRESH_TOKEN_LIFETIME as i64));
+        let lifetime = chrono::Duration::hours(*REFRESH_TOKEN_LIFETIME as i64);
+        // With an idle timeout, the lifetime becomes the absolute one for the whole chain and
+        // each single token only lives for the idle timeout.
+        let (exp, chain_exp) = if let Some(idle) = *REFRESH_TOKEN_IDLE_TIMEOUT {
+            let chain_exp = rt_chain
+                .exp
+                .unwrap_or_else(|| nbf.add(lifetime).timestamp());
+            let exp = nbf
+                .add(chrono::Duration::minutes(idle as i64))
+                .timestamp()
+                .min(chain_exp);
+            let exp = DateTime::from_timestamp(exp, 0).unwrap_or(nbf);
+            (exp, Some(chain_exp))
+        } else {
+            (nbf.add(lifetime), None)
+        };
+
         RefreshToken::create(
             data,
             validation_string,
@@ -631,6 +985,9 @@ pub async fn build_refresh_token(
             exp,
             scope.map(|s| s.0),
             is_mfa,
+            client.id.clone(),
+            chain_exp,
+            device_fp,
         )
         .await?;
     }
@@ -678,15 +1035,117 @@ pub fn get_bearer_token_from_header(headers: &HeaderMap) -> Result<String, Error
     Ok(bearer.to_string())
 }
 
+/// Extracts the access token from either a `Bearer` or `DPoP` Authorization header.
+/// The returned `bool` is `true` for the `DPoP` scheme.
+pub fn get_access_token_from_header(headers: &HeaderMap) -> Result<(String, bool), ErrorResponse> {
+    let dpop_token = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix(TOKEN_DPOP))
+        .and_then(|v| v.strip_prefix(' '));
+
+    match dpop_token {
+        Some(token) if !token.is_empty() => Ok((token.to_string(), true)),
+        _ => get_bearer_token_from_header(headers).map(|token| (token, false)),
+    }
+}
+
+/// Returns the evaluated feature flags for the user and client of the bearer token
+pub async fn get_feature_flags(
+    data: &web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<FeatureFlagsEvaluatedResponse, ErrorResponse> {
+    let bearer = get_bearer_token_from_header(req.headers())?;
+
+    let claims = validate_token::<JwtCommonClaims>(data, &bearer).await?;
+    validate_access_token_profile(&bearer, &claims)?;
+    if claims.custom.typ != JwtTokenType::Bearer {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Token Type must be 'Bearer'".to_string(),
+        ));
+    }
+
+    // the flags are evaluated for the tokens client, which must still exist and be enabled
+    let client = Client::find_maybe_ephemeral(data, claims.custom.azp.clone())
+        .await
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::WWWAuthenticate("client-not-found".to_string()),
+                "The client has not been found".to_string(),
+            )
+        })?;
+    if !client.enabled {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::WWWAuthenticate("client-disabled".to_string()),
+            "The client has been disabled".to_string(),
+        ));
+    }
+
+    // tokens for a client without a user have the client itself as `sub` or none at all
+    let user = match claims.subject {
+        Some(uid) if uid != claims.custom.azp => {
+            let user = User::find_by_sub(data, uid).await.map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::WWWAuthenticate("user-not-found".to_string()),
+                    "The user has not been found".to_string(),
+                )
+            })?;
+            if !user.enabled || user.check_expired().is_err() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::WWWAuthenticate("user-disabled".to_string()),
+                    "The user has been disabled".to_string(),
+                ));
+            }
+            Some(user)
+        }
+        _ => None,
+    };
+
+    let flags = FeatureFlag::evaluate_all(data, user.as_ref(), &claims.custom.azp).await?;
+    Ok(FeatureFlagsEvaluatedResponse { flags })
+}
+
 /// Returns the 'userInfo' for the [/oidc/userinfo endpoint](crate::handlers::get_userinfo)<br>
 pub async fn get_userinfo(
     data: &web::Data<AppState>,
     req: HttpRequest,
 ) -> Result<Userinfo, ErrorResponse> {
+    userinfo_with_azp(data, req)
+        .await
+        .map(|(userinfo, _)| userinfo)
+}
+
+/// Returns the response for the [/oidc/userinfo endpoint](crate::handlers::get_userinfo).
+/// It will be encrypted as `application/jwt`, if the client has registered a
+/// `userinfo_encrypted_response_alg`.
+pub async fn get_userinfo_response(
+    data: &web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    let (userinfo, azp) = userinfo_with_azp(data, req).await?;
+
+    let client = Client::find_maybe_ephemeral(data, azp).await?;
+    if client.userinfo_encrypted_response_alg.is_some() {
+        let json = serde_json::to_vec(&userinfo)?;
+        if let Some(jwe) = client.encrypt_userinfo(data, &json).await? {
+            return Ok(HttpResponse::Ok().content_type("application/jwt").body(jwe));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(userinfo))
+}
+
+/// Returns the userinfo together with the `azp` of the access token.
+async fn userinfo_with_azp(
+    data: &web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<(Userinfo, String), ErrorResponse> {
     // get bearer token
-    let bearer = get_bearer_token_from_header(req.headers())?;
+    let (bearer, is_dpop) = get_access_token_from_header(req.headers())?;
 
     let claims = validate_token::<JwtCommonClaims>(data, &bearer).await?;
+    validate_access_token_profile(&bearer, &claims)?;
     if claims.custom.typ != JwtTokenType::Bearer {
         return Err(ErrorResponse::new(
             ErrorResponseType::BadRequest,
@@ -694,6 +1153,33 @@ pub async fn get_userinfo(
         ));
     }
 
+    // DPoP bound tokens must be presented with the DPoP scheme and a matching proof (RFC9449 7.1)
+    match (&claims.custom.cnf, is_dpop) {
+        (Some(cnf), true) => {
+            DPoPProof::validated_for_resource(
+                data,
+                &req,
+                &DPOP_USERINFO_ENDPOINT,
+                &bearer,
+                &cnf.jkt,
+            )
+            .await?;
+        }
+        (Some(_), false) => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::DPoP(None),
+                "DPoP bound access tokens must use the DPoP authorization scheme".to_string(),
+            ));
+        }
+        (None, true) => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::DPoP(None),
+                "The access token is not DPoP bound".to_string(),
+            ));
+        }
+        (None, false) => {}
+    }
+
     let scope = claims.custom.scope.unwrap_or_else(|| "openid".to_string());
     let uid = claims.subject.ok_or_else(|| {
         ErrorResponse::new(
@@ -701,7 +1187,7 @@ pub async fn get_userinfo(
             String::from("Token without 'sub' - could not extract the Principal"),
         )
     })?;
-    let user = User::find(data, uid).await.map_err(|_| {
+    let user = User::find_by_sub(data, uid).await.map_err(|_| {
         ErrorResponse::new(
             ErrorResponseType::WWWAuthenticate("user-not-found".to_string()),
             "The user has not been found".to_string(),
@@ -715,6 +1201,7 @@ pub async fn get_userinfo(
             "The user has been disabled".to_string(),
         ));
     }
+    UserActivity::record(data, user.id.clone(), UserActivityKind::Userinfo);
 
     if *USERINFO_STRICT {
         // if the token has been issued to a device, make sure it still exists and is valid
@@ -729,12 +1216,14 @@ pub async fn get_userinfo(
         }
 
         // make sure the original client still exists and is enabled
-        let client = Client::find(data, claims.custom.azp).await.map_err(|_| {
-            ErrorResponse::new(
-                ErrorResponseType::WWWAuthenticate("client-not-found".to_string()),
-                "The client has not been found".to_string(),
-            )
-        })?;
+        let client = Client::find(data, claims.custom.azp.clone())
+            .await
+            .map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::WWWAuthenticate("client-not-found".to_string()),
+                    "The client has not been found".to_string(),
+                )
+            })?;
         if !client.enabled {
             return Err(ErrorResponse::new(
                 ErrorResponseType::WWWAuthenticate("client-disabled".to_string()),
@@ -753,7 +1242,7 @@ pub async fn get_userinfo(
         sub: user.id.clone(),
         name: format!("{} {}", &user.given_name, &user.family_name),
         roles,
-        mfa_enabled: user.has_webauthn_enabled(),
+        mfa_enabled: user.has_mfa_enabled(),
 
         // scope: address
         address: None,
@@ -762,9 +1251,15 @@ pub async fn get_userinfo(
         email: None,
         email_verified: None,
 
+        // scope: flags
+        flags: None,
+
         // scope: groups
         groups,
 
+        // scope: organizations
+        organizations: None,
+
         // scope: profile
         preferred_username: None,
         given_name: None,
@@ -788,7 +1283,7 @@ pub async fn get_userinfo(
     let mut user_values_fetched = false;
 
     if scope.contains("profile") {
-        userinfo.preferred_username = Some(user.email.clone());
+        userinfo.preferred_username = Some(user.preferred_username().to_string());
         userinfo.given_name = Some(user.given_name.clone());
         userinfo.family_name = Some(user.family_name.clone());
         userinfo.locale = Some(user.language.to_string());
@@ -803,6 +1298,15 @@ pub async fn get_userinfo(
         }
     }
 
+    if scope.contains("organizations") {
+        userinfo.organizations = Some(Organization::find_for_user(data, &user.id).await?);
+    }
+
+    if scope.contains("flags") {
+        let client_id = claims.custom.azp.as_str();
+        userinfo.flags = Some(FeatureFlag::find_enabled_names(data, &user, client_id).await?);
+    }
+
     if scope.contains("address") {
         if !user_values_fetched {
             user_values = UserValues::find(data, &user.id).await?;
@@ -827,61 +1331,164 @@ pub async fn get_userinfo(
         }
     }
 
-    Ok(userinfo)
+    Ok((userinfo, claims.custom.azp))
 }
 
 /// Returns [TokenInfo](crate::models::response::TokenInfo) for the
-/// [/oidc/tokenInfo endpoint](crate::handlers::post_token_info)
-pub async fn get_token_info(
+/// [/oidc/introspect endpoint](crate::handlers::post_introspect)
+///
+/// `caller_client` is the already authenticated client making the request, or `None` for
+/// API keys. Refresh tokens are opaque to everyone apart from the client they have been issued
+/// to, which means they are only reported as active to this client or to an API key.
+pub async fn introspect(
     data: &web::Data<AppState>,
     token: &str,
+    caller_client: Option<&str>,
 ) -> Result<TokenInfo, ErrorResponse> {
-    let claims_res = validate_token::<JwtCommonClaims>(data, token).await;
-    if claims_res.is_err() {
-        return Ok(TokenInfo {
-            active: false,
-            scope: None,
-            client_id: None,
-            username: None,
-            exp: None,
-            cnf: None,
-        });
+    let claims = match validate_token::<JwtCommonClaims>(data, token).await {
+        Ok(claims) => claims,
+        Err(_) => return Ok(TokenInfo::default()),
+    };
+
+    if claims.custom.typ == JwtTokenType::Refresh {
+        return introspect_refresh_token(data, token, caller_client).await;
+    }
+    if validate_access_token_profile(token, &claims).is_err() {
+        return Ok(TokenInfo::default());
     }
 
-    let claims = claims_res.unwrap();
-    // scope does not exist for ID tokens, for all others unwrap is safe
-    let scope = claims.custom.scope;
-    let client_id = claims.custom.azp;
-    let username = claims.subject;
-    let exp = claims.expires_at.unwrap().as_secs();
-    let cnf = claims.custom.cnf;
+    Ok(TokenInfo {
+        active: true,
+        // scope does not exist for ID tokens
+        scope: claims.custom.scope,
+        authorization_details: claims.custom.authorization_details,
+        client_id: Some(claims.custom.azp),
+        username: claims.subject.clone(),
+        token_type: Some(claims.custom.typ.as_str().to_string()),
+        exp: claims.expires_at.map(|exp| exp.as_secs()),
+        iat: claims.issued_at.map(|iat| iat.as_secs()),
+        nbf: claims.invalid_before.map(|nbf| nbf.as_secs()),
+        sub: claims.subject,
+        aud: claims.audiences.map(sorted_audiences),
+        iss: claims.issuer,
+        jti: claims.jwt_id,
+        cnf: claims.custom.cnf,
+        sid: claims.custom.sid,
+    })
+}
+
+/// Refresh tokens are rotated and invalidated inside the database, while the JWT itself may
+/// still be valid. The database entry is the source of truth for its state and expiry.
+async fn introspect_refresh_token(
+    data: &web::Data<AppState>,
+    token: &str,
+    caller_client: Option<&str>,
+) -> Result<TokenInfo, ErrorResponse> {
+    let claims = match validate_token::<JwtRefreshClaims>(data, token).await {
+        Ok(claims) => claims,
+        Err(_) => return Ok(TokenInfo::default()),
+    };
+    if let Some(client_id) = caller_client {
+        if client_id != claims.custom.azp {
+            return Ok(TokenInfo::default());
+        }
+    }
+
+    let (_, validation_str) = token.split_at(token.len() - 49);
+    let (exp, scope) = if let Ok(rt) = RefreshToken::find(data, validation_str).await {
+        (rt.exp, rt.scope)
+    } else if let Ok(rt) = RefreshTokenDevice::find(data, validation_str).await {
+        (rt.exp, rt.scope)
+    } else {
+        return Ok(TokenInfo::default());
+    };
+    if exp < OffsetDateTime::now_utc().unix_timestamp() {
+        return Ok(TokenInfo::default());
+    }
 
     Ok(TokenInfo {
         active: true,
         scope,
-        client_id: Some(client_id),
-        username,
-        exp: Some(exp),
-        cnf,
+        authorization_details: None,
+        client_id: Some(claims.custom.azp),
+        username: Some(claims.custom.uid.clone()),
+        token_type: Some(JwtTokenType::Refresh.as_str().to_string()),
+        exp: Some(exp as u64),
+        iat: claims.issued_at.map(|iat| iat.as_secs()),
+        nbf: claims.invalid_before.map(|nbf| nbf.as_secs()),
+        sub: Some(claims.custom.uid),
+        aud: claims.audiences.map(sorted_audiences),
+        iss: claims.issuer,
+        jti: claims.jwt_id,
+        cnf: claims.custom.cnf,
+        sid: claims.custom.sid,
     })
 }
 
+fn sorted_audiences(aud: Audiences) -> Vec<String> {
+    let mut aud = aud.into_set().into_iter().collect::<Vec<_>>();
+    aud.sort();
+    aud
+}
+
+/// Checks if the session behind a `sid` claim is still alive.
+pub async fn get_sid_info(data: &web::Data<AppState>, sid: &str) -> Result<SidInfo, ErrorResponse> {
+    // an unknown sid is not an error for the caller, just like an invalid token for introspection
+    match Session::find_by_sid(data, sid).await {
+        Ok(session) if session.is_alive() => Ok(SidInfo {
+            active: true,
+            exp: Some(session.exp),
+        }),
+        _ => Ok(SidInfo {
+            active: false,
+            exp: None,
+        }),
+    }
+}
+
 /// Main entrance function for returning a whole new [TokenSet](crate::models::response::TokenSet)
 pub async fn get_token_set(
     req_data: TokenRequest,
     data: &web::Data<AppState>,
     req: HttpRequest,
 ) -> Result<(TokenSet, Vec<(HeaderName, HeaderValue)>), ErrorResponse> {
-    match req_data.grant_type.as_str() {
+    let client_id = req_data
+        .try_get_client_id_secret(&req)
+        .ok()
+        .map(|(client_id, _)| client_id);
+    let grant_type = req_data.grant_type.clone();
+
+    let res = match grant_type.as_str() {
         "authorization_code" => grant_type_code(data, req, req_data).await,
         "client_credentials" => grant_type_credentials(data, req, req_data).await,
         "password" => grant_type_password(data, req, req_data).await,
         "refresh_token" => grant_type_refresh(data, req, req_data).await,
+        GRANT_TYPE_TOKEN_EXCHANGE => grant_type_token_exchange(data, req, req_data).await,
         _ => Err(ErrorResponse::new(
             ErrorResponseType::BadRequest,
             String::from("Invalid 'grant_type'"),
         )),
+    };
+
+    if let Err(err) = &res {
+        record_token_error(data, client_id, &grant_type, err).await;
     }
+    res
+}
+
+/// Records a failed token request for the error telemetry. Only errors for existing clients are
+/// kept for the per-client overview.
+async fn record_token_error(
+    data: &web::Data<AppState>,
+    client_id: Option<String>,
+    grant_type: &str,
+    err: &ErrorResponse,
+) {
+    let client_id = match client_id {
+        Some(id) if Client::find(data, id.clone()).await.is_ok() => Some(id),
+        _ => None,
+    };
+    token_errors::record(client_id.as_deref(), grant_type, err);
 }
 
 /// Return a [TokenSet](crate::models::response::TokenSet) for the `authorization_code` flow
@@ -909,6 +1516,7 @@ async fn grant_type_code(
                 format!("Client '{}' not found", client_id),
             )
         })?;
+    client.validate_enabled()?;
     let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
     if client.confidential {
         let secret = client_secret.ok_or_else(|| {
@@ -919,6 +1527,7 @@ async fn grant_type_code(
             )
         })?;
         client.validate_secret(&secret, &req)?;
+        client.validate_source_ip(data, &req).await?;
     }
     client.validate_flow("authorization_code")?;
 
@@ -942,7 +1551,7 @@ async fn grant_type_code(
 
     // get the auth code from the cache
     let idx = req_data.code.as_ref().unwrap().to_owned();
-    let code = AuthCode::find(data, idx).await?.ok_or_else(|| {
+    let mut code = AuthCode::find(data, idx).await?.ok_or_else(|| {
         warn!(
             "'auth_code' could not be found inside the cache - Host: {}",
             get_client_ip(&req),
@@ -951,19 +1560,22 @@ async fn grant_type_code(
             ErrorResponseType::Unauthorized,
             "'auth_code' could not be found inside the cache".to_string(),
         )
+        .with_code(ErrorCode::GrantExpired)
     })?;
     // validate the auth code
     if code.client_id != client_id {
         let err = format!("Wrong 'code' for client_id '{}'", client_id);
         warn!(err);
-        return Err(ErrorResponse::new(ErrorResponseType::Unauthorized, err));
+        return Err(ErrorResponse::new(ErrorResponseType::Unauthorized, err)
+            .with_code(ErrorCode::GrantExpired));
     }
     if code.exp < OffsetDateTime::now_utc().unix_timestamp() {
         warn!("The Authorization Code has expired");
         return Err(ErrorResponse::new(
             ErrorResponseType::SessionExpired,
             String::from("The Authorization Code has expired"),
-        ));
+        )
+        .with_code(ErrorCode::GrantExpired));
     }
     if code.challenge.is_some() {
         if req_data.code_verifier.is_none() {
@@ -971,7 +1583,8 @@ async fn grant_type_code(
             return Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
                 String::from("'code_verifier' is missing"),
-            ));
+            )
+            .with_code(ErrorCode::PkceMismatch));
         }
 
         if code.challenge_method.as_ref().unwrap().eq("plain") {
@@ -980,10 +1593,12 @@ async fn grant_type_code(
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Unauthorized,
                     String::from("'code_verifier' does not match the challenge"),
-                ));
+                )
+                .with_code(ErrorCode::PkceMismatch));
             }
         } else {
-            let hash = digest::digest(&digest::SHA256, req_data.code_verifier.unwrap().as_bytes());
+            let verifier = req_data.code_verifier.as_deref().unwrap_or_default();
+            let hash = digest::digest(&digest::SHA256, verifier.as_bytes());
             let hash_base64 = base64_url_encode(hash.as_ref());
 
             if !code.challenge.as_ref().unwrap().eq(&hash_base64) {
@@ -991,10 +1606,29 @@ async fn grant_type_code(
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Unauthorized,
                     String::from("'code_verifier' does not match the challenge"),
-                ));
+                )
+                .with_code(ErrorCode::PkceMismatch));
             }
         }
     }
+    // A code must only ever be used once. The claim is atomic, even if the same code is
+    // redeemed on multiple cluster members at the same time.
+    if !UsedJti::try_consume(data, "auth_code", &code.id, code.exp).await? {
+        auth_code_reuse(data, &req, &code).await?;
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "'auth_code' has been used already".to_string(),
+        )
+        .with_code(ErrorCode::GrantExpired));
+    }
+    if AppAttestation::is_required(&client.id) {
+        if let Err(err) = AppAttestation::validate(data, &client.id, &code.id, &req_data).await {
+            // the same code must not be retried with another proof
+            warn!("Invalid app attestation for client '{}'", client.id);
+            code.delete(data).await?;
+            return Err(err);
+        }
+    }
     // We will not perform another `redirect_uri` check at this point, like stated in the RFC.
     // It is just unnecessary because of the way Rauthy handles the flow init during GET /authorize.
     //
@@ -1005,7 +1639,43 @@ async fn grant_type_code(
     //
     // An additional check at this point does not provide any security benefit but only uses resources.
 
+    // The `resource` of the token request must be the same, if one has been given during the
+    // authorization request already.
+    let resource = match (&code.resource, &req_data.resource) {
+        (Some(code_rs), Some(req_rs)) if code_rs != req_rs => {
+            warn!("'resource' does not match the authorization request");
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "invalid_target: 'resource' does not match the authorization request".to_string(),
+            )
+            .with_code(ErrorCode::InvalidTarget));
+        }
+        (Some(rs), _) | (None, Some(rs)) => {
+            ResourceServer::find_for_request(data, Some(rs.as_str()), &client.id).await?
+        }
+        (None, None) => None,
+    };
+    // the token request may only ask for a subset of the granted `authorization_details`
+    let granted = code
+        .authorization_details
+        .as_deref()
+        .map(AuthorizationDetail::parse_param)
+        .transpose()?;
+    let authorization_details =
+        TokenAuthorizationDetails::new(granted, req_data.authorization_details.as_deref())?;
+
+    // All factors have been validated at this point. Required attributes, which have been
+    // provided during the login, must exist before the token set is built.
+    if let Some(values) = code.user_attrs.take() {
+        UserAttrConfigEntity::save_missing_required(data, &code.user_id, &client.id, values)
+            .await?;
+    }
+
     let user = User::find(data, code.user_id.clone()).await?;
+    let sid = match &code.session_id {
+        Some(session_id) => Some(TokenSid(Session::link_sid(data, session_id).await?)),
+        None => None,
+    };
     let token_set = TokenSet::from_user(
         &user,
         data,
@@ -1015,6 +1685,10 @@ async fn grant_type_code(
         Some(TokenScopes(code.scopes.join(" "))),
         AuthCodeFlow::Yes,
         DeviceCodeFlow::No,
+        sid,
+        RefreshTokenChain::new(&req),
+        resource.as_ref(),
+        authorization_details,
     )
     .await?;
 
@@ -1035,7 +1709,38 @@ async fn grant_type_code(
         session.groups = user.groups;
         session.save(data).await?;
     }
-    code.delete(data).await?;
+
+    // Keep the code with its usage until it expires instead of deleting it, so everything
+    // issued for it can be revoked on a replay.
+    let usage = AuthCodeUsage {
+        ip: real_ip_from_req(&req),
+        access_token: Some((
+            token_set.access_token_jti.clone(),
+            OffsetDateTime::now_utc().unix_timestamp() + token_set.expires_in as i64,
+        )),
+        refresh_token: token_set.refresh_token.as_ref().map(|rt| {
+            let (_, validation_str) = rt.split_at(rt.len() - 49);
+            validation_str.to_string()
+        }),
+    };
+    code.used = Some(usage.clone());
+    code.save(data).await?;
+
+    // A replay may have happened while the tokens were being issued. In that case, it could
+    // not see this usage and it is up to us to revoke it.
+    if !UsedJti::try_consume(data, "auth_code_done", &code.id, code.exp).await? {
+        warn!(
+            "'auth_code' for client '{}' has been re-used during the token request",
+            client_id
+        );
+        revoke_auth_code_usage(data, usage).await?;
+        code.delete(data).await?;
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "'auth_code' has been used already".to_string(),
+        )
+        .with_code(ErrorCode::GrantExpired));
+    }
 
     // update timestamp if it is a dynamic client
     if client.is_dynamic() {
@@ -1045,6 +1750,62 @@ async fn grant_type_code(
     Ok((token_set, headers))
 }
 
+/// Handles the second usage of an authorization code, which most probably means that it has been
+/// stolen. We cannot tell which of both parties is the legitimate one, so everything issued for
+/// the code gets revoked, like suggested in RFC6749 4.1.2.
+async fn auth_code_reuse(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    code: &AuthCode,
+) -> Result<(), ErrorResponse> {
+    warn!(
+        "Re-used 'auth_code' for client '{}' - revoking all issued tokens",
+        code.client_id
+    );
+
+    // If the first redemption has not finished yet, it will find our marker and revoke its
+    // tokens itself. Otherwise, its usage is in the cache by now.
+    let usage = if UsedJti::try_consume(data, "auth_code_done", &code.id, code.exp).await? {
+        None
+    } else {
+        AuthCode::find(data, code.id.clone())
+            .await?
+            .and_then(|c| c.used)
+    };
+    if let Some(usage) = &usage {
+        revoke_auth_code_usage(data, usage.clone()).await?;
+    }
+    code.delete(data).await?;
+
+    data.tx_events
+        .send_async(Event::auth_code_reuse(
+            &code.client_id,
+            &code.user_id,
+            usage.as_ref().and_then(|u| u.ip.as_deref()),
+            real_ip_from_req(req),
+        ))
+        .await
+        .unwrap();
+
+    Ok(())
+}
+
+/// Revokes the access and refresh token, that have been issued for an authorization code.
+async fn revoke_auth_code_usage(
+    data: &web::Data<AppState>,
+    usage: AuthCodeUsage,
+) -> Result<(), ErrorResponse> {
+    if let Some((jti, exp)) = usage.access_token {
+        if exp > OffsetDateTime::now_utc().unix_timestamp() {
+            RevokedToken::create(data, jti, exp, "authorization code reuse".to_string()).await?;
+        }
+    }
+    if let Some(id) = usage.refresh_token {
+        RefreshToken::invalidate(data, &id).await?;
+    }
+    Ok(())
+}
+
 /// Return a [TokenSet](crate::models::response::TokenSet) for the `client_credentials` flow
 #[tracing::instrument(skip_all, fields(client_id = req_data.client_id, username = req_data.username))]
 async fn grant_type_credentials(
@@ -1052,37 +1813,240 @@ async fn grant_type_credentials(
     req: HttpRequest,
     req_data: TokenRequest,
 ) -> Result<(TokenSet, Vec<(HeaderName, HeaderValue)>), ErrorResponse> {
-    if req_data.client_secret.is_none() {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            String::from("'client_secret' is missing"),
-        ));
+    // workloads authenticate with their platform identity instead of a secret
+    let client = if req_data.client_assertion_type.is_some() {
+        client_from_workload_assertion(data, &req_data).await?
+    } else {
+        if req_data.client_secret.is_none() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("'client_secret' is missing"),
+            ));
+        }
+
+        let (client_id, client_secret) = req_data.try_get_client_id_secret(&req)?;
+        let client = Client::find(data, client_id).await?;
+        let secret = client_secret.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("'client_secret' is missing"),
+            )
+        })?;
+        if !client.confidential {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("'client_credentials' flow is allowed for confidential clients only"),
+            ));
+        }
+        client.validate_enabled()?;
+        client.validate_secret(&secret, &req)?;
+        client
+    };
+    client.validate_source_ip(data, &req).await?;
+    client.validate_flow("client_credentials")?;
+    let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
+
+    let mut headers = Vec::new();
+    let dpop_fingerprint =
+        if let Some(proof) = DPoPProof::opt_validated_from(data, &req, &header_origin).await? {
+            if let Some(nonce) = &proof.claims.nonce {
+                headers.push((
+                    HeaderName::from_str(HEADER_DPOP_NONCE).unwrap(),
+                    HeaderValue::from_str(nonce).unwrap(),
+                ));
+            }
+            Some(DpopFingerprint(proof.jwk_fingerprint()?))
+        } else {
+            None
+        };
+    // We do not push the origin header, because client credentials should never be used from
+    // any browser at all
+
+    // update timestamp if it is a dynamic client
+    if client.is_dynamic() {
+        ClientDyn::update_used(data, &client.id).await?;
     }
 
-    let (client_id, client_secret) = req_data.try_get_client_id_secret(&req)?;
-    let client = Client::find(data, client_id).await?;
-    if !client.confidential {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            String::from("'client_credentials' flow is allowed for confidential clients only"),
-        ));
+    let resource =
+        ResourceServer::find_for_request(data, req_data.resource.as_deref(), &client.id).await?;
+    let authorization_details =
+        AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref())?;
+    let ts = TokenSet::for_client_credentials(
+        data,
+        &client,
+        dpop_fingerprint,
+        resource.as_ref(),
+        authorization_details,
+    )
+    .await?;
+    Ok((ts, headers))
+}
+
+/// Return a [TokenSet](crate::models::response::TokenSet) for the `token-exchange` grant (RFC 8693)
+///
+/// Only confidential clients with the grant in their `flows_enabled` may exchange tokens, and
+/// only tokens that have been issued for them. Workloads may authenticate with a
+/// `client_assertion` instead, like for the `client_credentials` grant. Without an
+/// `actor_token`, the new token impersonates the subject. With one, the actor is added to the
+/// `act` delegation chain.
+/// Another `audience` than the client itself must be in its `token_exchange_audiences`.
+#[tracing::instrument(skip_all, fields(client_id = req_data.client_id))]
+async fn grant_type_token_exchange(
+    data: &web::Data<AppState>,
+    req: HttpRequest,
+    req_data: TokenRequest,
+) -> Result<(TokenSet, Vec<(HeaderName, HeaderValue)>), ErrorResponse> {
+    // mesh workloads exchange user tokens with their platform identity instead of a secret
+    let client = if req_data.client_assertion_type.is_some() {
+        client_from_workload_assertion(data, &req_data).await?
+    } else {
+        let (client_id, client_secret) = req_data.try_get_client_id_secret(&req)?;
+        let client = Client::find(data, client_id).await?;
+        client.validate_enabled()?;
+        if !client.confidential {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("'token-exchange' is allowed for confidential clients only"),
+            ));
+        }
+        let secret = client_secret.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("'client_secret' is missing"),
+            )
+        })?;
+        client.validate_secret(&secret, &req)?;
+        client
+    };
+    client.validate_source_ip(data, &req).await?;
+    client.validate_flow(GRANT_TYPE_TOKEN_EXCHANGE)?;
+
+    if let Some(typ) = &req_data.requested_token_type {
+        if typ != TOKEN_TYPE_ACCESS_TOKEN {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "'requested_token_type' must be '{}'",
+                    TOKEN_TYPE_ACCESS_TOKEN
+                ),
+            ));
+        }
     }
-    if !client.enabled {
+
+    let subject_token = req_data.subject_token.as_deref().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            String::from("'subject_token' is missing"),
+        )
+    })?;
+    let subject = validate_exchange_token(
+        data,
+        &client,
+        subject_token,
+        req_data.subject_token_type.as_deref(),
+        "subject_token",
+    )
+    .await?;
+
+    let act = match req_data.actor_token.as_deref() {
+        Some(actor_token) => {
+            let actor = validate_exchange_token(
+                data,
+                &client,
+                actor_token,
+                req_data.actor_token_type.as_deref(),
+                "actor_token",
+            )
+            .await?;
+            // the actor must be the client itself or a user, that has logged in to it
+            if actor.custom.azp != client.id {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Unauthorized,
+                    format!(
+                        "'actor_token' has not been issued to client '{}'",
+                        client.id
+                    ),
+                ));
+            }
+            Some(ActClaim {
+                sub: actor.subject.unwrap_or(actor.custom.azp),
+                act: subject.custom.act.clone().map(Box::new),
+            })
+        }
+        // an impersonation must not hide an existing delegation chain
+        None => subject.custom.act.clone(),
+    };
+    if act.as_ref().map(|act| act.depth()).unwrap_or(0) > TOKEN_EXCHANGE_MAX_ACTORS {
         return Err(ErrorResponse::new(
             ErrorResponseType::BadRequest,
-            String::from("client is disabled"),
+            format!(
+                "The delegation chain must not exceed {} actors",
+                TOKEN_EXCHANGE_MAX_ACTORS
+            ),
         ));
     }
-    let secret = client_secret.ok_or_else(|| {
-        ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            String::from("'client_secret' is missing"),
+
+    let audience = match req_data.audience {
+        Some(aud) if aud != client.id => {
+            let allowed = client
+                .get_token_exchange_audiences()
+                .map(|audiences| audiences.contains(&aud))
+                .unwrap_or(false);
+            if !allowed {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!(
+                        "Client '{}' may not exchange tokens for the 'audience' '{}'",
+                        client.id, aud
+                    ),
+                )
+                .with_code(ErrorCode::InvalidTarget));
+            }
+
+            let target = Client::find(data, aud).await.map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    String::from("The 'audience' is not a known client"),
+                )
+            })?;
+            target.validate_enabled()?;
+            target.id
+        }
+        _ => client.id.clone(),
+    };
+    let scope = narrow_exchange_scope(
+        subject.custom.scope.as_deref().unwrap_or_default(),
+        &client.get_scopes(),
+        req_data.scope.as_deref(),
+    )?;
+
+    // tokens for a client without a user have the client itself as `sub` or none at all
+    let user = match &subject.subject {
+        Some(sub) if sub != &subject.custom.azp => {
+            let user = User::find_by_sub(data, sub.clone()).await?;
+            user.check_enabled()?;
+            user.check_expired()?;
+            Some(user)
+        }
+        _ => None,
+    };
+
+    // the new token must never outlive the subject token
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let subject_exp = subject
+        .expires_at
+        .map(|exp| exp.as_secs() as i64)
+        .unwrap_or(now);
+    let lifetime = (client.access_token_lifetime as i64).min(subject_exp - now);
+    if lifetime <= 0 {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            String::from("The 'subject_token' has expired"),
         )
-    })?;
-    client.validate_secret(&secret, &req)?;
-    client.validate_flow("client_credentials")?;
-    let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
+        .with_code(ErrorCode::GrantExpired));
+    }
 
+    let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
     let mut headers = Vec::new();
     let dpop_fingerprint =
         if let Some(proof) = DPoPProof::opt_validated_from(data, &req, &header_origin).await? {
@@ -1096,25 +2060,176 @@ async fn grant_type_credentials(
         } else {
             None
         };
-    // We do not push the origin header, because client credentials should never be used from
-    // any browser at all
 
-    // update timestamp if it is a dynamic client
-    if client.is_dynamic() {
-        ClientDyn::update_used(data, &client.id).await?;
+    info!(
+        "Token exchange by client '{}' for audience '{}' with actor {:?}",
+        client.id,
+        audience,
+        act.as_ref().map(|act| act.sub.as_str()),
+    );
+
+    if client.is_dynamic() {
+        ClientDyn::update_used(data, &client.id).await?;
+    }
+    if let Some(user) = &user {
+        UserActivity::record(data, user.id.clone(), UserActivityKind::Refresh);
+    }
+
+    let ts = TokenSet::for_token_exchange(
+        user.as_ref(),
+        data,
+        &client,
+        dpop_fingerprint,
+        lifetime,
+        TokenScopes(scope),
+        TokenExchange { audience, act },
+    )
+    .await?;
+    Ok((ts, headers))
+}
+
+/// Validates a `subject_token` or `actor_token` for the `token-exchange` grant. Only access
+/// tokens from this instance, which have been issued for the requesting client, are accepted.
+async fn validate_exchange_token(
+    data: &web::Data<AppState>,
+    client: &Client,
+    token: &str,
+    token_type: Option<&str>,
+    param: &str,
+) -> Result<claims::JWTClaims<JwtCommonClaims>, ErrorResponse> {
+    if token_type != Some(TOKEN_TYPE_ACCESS_TOKEN) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("'{}_type' must be '{}'", param, TOKEN_TYPE_ACCESS_TOKEN),
+        ));
+    }
+
+    let claims = validate_token::<JwtCommonClaims>(data, token)
+        .await
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                format!("'{}' is invalid", param),
+            )
+            .with_code(ErrorCode::GrantExpired)
+        })?;
+    validate_access_token_profile(token, &claims)?;
+    if claims.custom.typ != JwtTokenType::Bearer {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("'{}' must be an access token", param),
+        ));
+    }
+    // we cannot validate the proof of possession for a token, that is not our own
+    if claims.custom.cnf.is_some() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("'{}' must not be DPoP bound", param),
+        ));
+    }
+
+    let issued_for_client = claims
+        .audiences
+        .clone()
+        .map(|aud| aud.into_set().contains(&client.id))
+        .unwrap_or(false);
+    if !issued_for_client {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            format!("'{}' has not been issued for client '{}'", param, client.id),
+        ));
+    }
+
+    Ok(claims)
+}
+
+/// The scopes of an exchanged token can only be narrowed down. They are the intersection of
+/// the requested ones, the scopes of the subject token and the scopes of the client. Without a
+/// requested `scope`, all scopes of the subject token, which the client may use, are kept.
+fn narrow_exchange_scope(
+    subject_scope: &str,
+    client_scopes: &[String],
+    requested: Option<&str>,
+) -> Result<String, ErrorResponse> {
+    let allowed = subject_scope
+        .split_whitespace()
+        .filter(|s| client_scopes.iter().any(|c| c == s))
+        .collect::<Vec<_>>();
+
+    let Some(requested) = requested.filter(|s| !s.trim().is_empty()) else {
+        return Ok(allowed.join(" "));
+    };
+
+    let mut scopes = Vec::new();
+    for scope in requested.split_whitespace() {
+        if allowed.contains(&scope) && !scopes.contains(&scope) {
+            scopes.push(scope);
+        } else {
+            debug!(
+                "Dropping 'scope' {} from the token exchange - not granted to subject or client",
+                scope
+            );
+        }
+    }
+    if scopes.is_empty() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            String::from("None of the requested 'scope's can be granted"),
+        ));
     }
+    Ok(scopes.join(" "))
+}
 
-    let ts = TokenSet::for_client_credentials(data, &client, dpop_fingerprint).await?;
-    Ok((ts, headers))
+/// Validates the workload identity from the `client_assertion` and returns the client it is
+/// mapped to via `SPIFFE_CLIENTS`, `WORKLOAD_AWS_CLIENTS` or `WORKLOAD_GCP_CLIENTS`.
+async fn client_from_workload_assertion(
+    data: &web::Data<AppState>,
+    req_data: &TokenRequest,
+) -> Result<Client, ErrorResponse> {
+    let assertion = req_data.client_assertion.as_deref().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            String::from("'client_assertion' is missing"),
+        )
+    })?;
+
+    let client_id = match req_data.client_assertion_type.as_deref() {
+        Some(CLIENT_ASSERTION_TYPE_SPIFFE) => SpiffeSvid::validate(data, assertion).await?,
+        Some(CLIENT_ASSERTION_TYPE_AWS_STS) => {
+            WorkloadIdentity::validate_aws(data, assertion).await?
+        }
+        Some(CLIENT_ASSERTION_TYPE_GCP_ID_TOKEN) => {
+            WorkloadIdentity::validate_gcp(data, assertion).await?
+        }
+        _ => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("Unsupported 'client_assertion_type'"),
+            ));
+        }
+    };
+    if let Some(id) = &req_data.client_id {
+        if id != &client_id {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                String::from("The 'client_id' does not match the workload identity"),
+            ));
+        }
+    }
+
+    let client = Client::find(data, client_id).await?;
+    client.validate_enabled()?;
+    Ok(client)
 }
 
 /// Return a [TokenSet](crate::models::response::TokenSet) for the `device_code` flow
 #[tracing::instrument(skip_all, fields(client_id = payload.client_id))]
 pub async fn grant_type_device_code(
     data: &web::Data<AppState>,
-    peer_ip: Option<String>,
+    req: &HttpRequest,
     payload: TokenRequest,
 ) -> HttpResponse {
+    let peer_ip = real_ip_from_req(req);
     let device_code = match &payload.device_code {
         None => {
             return HttpResponse::BadRequest().json(OAuth2ErrorResponse {
@@ -1140,6 +2255,13 @@ pub async fn grant_type_device_code(
             error_description: Some(Cow::from("Invalid `client_id`")),
         });
     }
+    // the code may have been issued before the client required app attestation
+    if let Err(err) = AppAttestation::validate_grant(&code.client_id, GRANT_TYPE_DEVICE_CODE) {
+        return HttpResponse::BadRequest().json(OAuth2ErrorResponse {
+            error: OAuth2ErrorTypeResponse::UnauthorizedClient,
+            error_description: Some(Cow::from(err.message)),
+        });
+    }
 
     // We need to check the device_code again, because the `find_by_device_code` uses
     // the `user_code` as cache index under the hood for smaller footprints and the
@@ -1158,6 +2280,36 @@ pub async fn grant_type_device_code(
         });
     }
 
+    let client = match Client::find(data, code.client_id.clone()).await {
+        Ok(client) => client,
+        Err(err) => {
+            // the client may have been deleted while the device was polling
+            return HttpResponse::BadRequest().json(OAuth2ErrorResponse {
+                error: OAuth2ErrorTypeResponse::UnauthorizedClient,
+                error_description: Some(Cow::from(err.message)),
+            });
+        }
+    };
+    if let Err(err) = client.validate_source_ip(data, req).await {
+        return HttpResponse::BadRequest().json(OAuth2ErrorResponse {
+            error: OAuth2ErrorTypeResponse::UnauthorizedClient,
+            error_description: Some(Cow::from(err.message)),
+        });
+    }
+
+    let resource =
+        match ResourceServer::find_for_request(data, payload.resource.as_deref(), &code.client_id)
+            .await
+        {
+            Ok(rs) => rs,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(OAuth2ErrorResponse {
+                    error: OAuth2ErrorTypeResponse::InvalidTarget,
+                    error_description: Some(Cow::from(err.message)),
+                });
+            }
+        };
+
     debug!("device auth code poll request is valid");
     let mut error = OAuth2ErrorTypeResponse::AuthorizationPending;
     let mut error_description = Cow::default();
@@ -1202,18 +2354,6 @@ pub async fn grant_type_device_code(
             }
         };
 
-        let client = match Client::find(data, code.client_id.clone()).await {
-            Ok(client) => client,
-            Err(err) => {
-                // at this point, this should never fail - only if the DB went down in the meantime
-                error!("{:?}", err);
-                return HttpResponse::InternalServerError().json(OAuth2ErrorResponse {
-                    error: OAuth2ErrorTypeResponse::InvalidRequest,
-                    error_description: Some(Cow::from(err.to_string())),
-                });
-            }
-        };
-
         let access_exp = now.add(chrono::Duration::seconds(
             client.access_token_lifetime as i64,
         ));
@@ -1264,6 +2404,10 @@ pub async fn grant_type_device_code(
             code.scopes.map(TokenScopes),
             AuthCodeFlow::No,
             DeviceCodeFlow::Yes(device.id),
+            None,
+            RefreshTokenChain::default(),
+            resource.as_ref(),
+            TokenAuthorizationDetails::default(),
         )
         .await
         {
@@ -1317,6 +2461,7 @@ async fn grant_type_password(
     let password = req_data.password.unwrap();
 
     let client = Client::find(data, client_id).await?;
+    client.validate_enabled()?;
     let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
     if client.confidential {
         let secret = client_secret.ok_or_else(|| {
@@ -1326,8 +2471,15 @@ async fn grant_type_password(
             )
         })?;
         client.validate_secret(&secret, &req)?;
+        client.validate_source_ip(data, &req).await?;
     }
     client.validate_flow("password")?;
+    let resource =
+        ResourceServer::find_for_request(data, req_data.resource.as_deref(), &client.id).await?;
+    let authorization_details = TokenAuthorizationDetails::new(
+        AuthorizationDetail::from_param(&client, req_data.authorization_details.as_deref())?,
+        None,
+    )?;
 
     let mut headers = Vec::new();
     let dpop_fingerprint =
@@ -1348,23 +2500,31 @@ async fn grant_type_password(
 
     // This Error must be the same if user does not exist AND passwords do not match to prevent
     // username enumeration
-    let mut user = User::find_by_email(data, String::from(email))
-        .await
-        .map_err(|_| {
-            warn!(
-                "False login from Host: '{}' with invalid username: '{}'",
-                get_client_ip(&req),
-                email
-            );
-            ErrorResponse::new(
-                ErrorResponseType::Unauthorized,
-                String::from("Invalid user credentials"),
-            )
-        })?;
+    let mut user_res = User::find_by_login(data, String::from(email)).await;
+    if user_res.is_err() {
+        // users, which have not been migrated yet, are imported on their first login
+        match user_store::import_on_miss(data, email, &password, real_ip_from_req(&req)).await {
+            Ok(Some(user)) => user_res = Ok(user),
+            Ok(None) => {}
+            Err(err) => error!("External user store lookup: {:?}", err),
+        }
+    }
+    let mut user = user_res.map_err(|_| {
+        warn!(
+            "False login from Host: '{}' with invalid username: '{}'",
+            get_client_ip(&req),
+            email
+        );
+        ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            String::from("Invalid user credentials"),
+        )
+        .with_code(ErrorCode::InvalidCredentials)
+    })?;
     user.check_enabled()?;
     user.check_expired()?;
 
-    match user.validate_password(data, password.clone()).await {
+    match user.validate_password_login(data, password.clone()).await {
         Ok(_) => {
             user.last_login = Some(OffsetDateTime::now_utc().unix_timestamp());
             user.last_failed_login = None;
@@ -1395,6 +2555,10 @@ async fn grant_type_password(
                 None,
                 AuthCodeFlow::No,
                 DeviceCodeFlow::No,
+                None,
+                RefreshTokenChain::new(&req),
+                resource.as_ref(),
+                authorization_details,
             )
             .await?;
             Ok((ts, headers))
@@ -1432,6 +2596,7 @@ async fn grant_type_refresh(
     }
     let (client_id, client_secret) = req_data.try_get_client_id_secret(&req)?;
     let client = Client::find_maybe_ephemeral(data, client_id).await?;
+    client.validate_enabled()?;
 
     let header_origin = client.validate_origin(&req, &data.listen_scheme, &data.public_url)?;
 
@@ -1443,6 +2608,7 @@ async fn grant_type_refresh(
             )
         })?;
         client.validate_secret(&secret, &req)?;
+        client.validate_source_ip(data, &req).await?;
     }
 
     client.validate_flow("refresh_token")?;
@@ -1450,7 +2616,15 @@ async fn grant_type_refresh(
     let refresh_token = req_data.refresh_token.unwrap();
 
     // validate common refresh token claims first and get the payload
-    let (ts, dpop_none) = validate_refresh_token(Some(client), &refresh_token, data, &req).await?;
+    let (ts, dpop_none) = validate_refresh_token(
+        Some(client),
+        &refresh_token,
+        data,
+        &req,
+        req_data.resource.as_deref(),
+        req_data.authorization_details.as_deref(),
+    )
+    .await?;
 
     let mut headers = Vec::new();
     if let Some(h) = header_origin {
@@ -1466,37 +2640,157 @@ async fn grant_type_refresh(
     Ok((ts, headers))
 }
 
+/// # Business logic for [POST /oidc/token/session](crate::handlers::post_token_session)
+///
+/// Issues fresh tokens for a first-party SPA in exchange for a valid session cookie, without
+/// ever handing out a refresh token to the browser. Only clients listed in
+/// `SESSION_TOKEN_CLIENTS` may use it, and the request must always come with a valid `Origin`.
+pub async fn session_token(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    mut session: Session,
+    req_data: SessionTokenRequest,
+) -> Result<(TokenSet, Session, Vec<(HeaderName, HeaderValue)>), ErrorResponse> {
+    if !SESSION_TOKEN_CLIENTS.contains(&req_data.client_id) {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Session based token refresh is not allowed for this client".to_string(),
+        ));
+    }
+
+    // The endpoint is a CSRF exception, because the SPA has no way to get the token cross-origin.
+    // Together with the `SameSite=Lax` session cookie, a mandatory and allowed `Origin` plus the
+    // JSON body, which always triggers a CORS preflight, prevent any cross-site requests instead.
+    if req.headers().get(header::ORIGIN).is_none() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The 'Origin' header is missing".to_string(),
+        ));
+    }
+
+    let client = Client::find(data, req_data.client_id).await?;
+    client.validate_enabled()?;
+    AppAttestation::validate_grant(&client.id, "session_token")?;
+    let header_origin = client.validate_origin(req, &data.listen_scheme, &data.public_url)?;
+
+    let user_id = session.user_id.clone().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "No user linked to this session".to_string(),
+        )
+    })?;
+    let user = User::find(data, user_id).await?;
+    user.check_enabled()?;
+    session.validate_user_expiry(&user)?;
+    client.validate_mfa(&user)?;
+    UserActivity::record(data, user.id.clone(), UserActivityKind::Refresh);
+
+    let scopes = req_data
+        .scope
+        .map(|s| s.split(' ').map(String::from).collect::<Vec<String>>());
+    let scopes = client.sanitize_login_scopes(&scopes)?;
+
+    // short-lived access tokens only and never any refresh token
+    let mut token_client = client.clone();
+    token_client.refresh_token = false;
+    token_client.access_token_lifetime = client.access_token_lifetime.min(*SESSION_TOKEN_LIFETIME);
+
+    let sid = TokenSid(Session::link_sid(data, &session.id).await?);
+    let token_set = TokenSet::from_user(
+        &user,
+        data,
+        &token_client,
+        None,
+        None,
+        Some(TokenScopes(scopes.join(" "))),
+        AuthCodeFlow::No,
+        DeviceCodeFlow::No,
+        Some(sid),
+        RefreshTokenChain::new(req),
+        None,
+        TokenAuthorizationDetails::default(),
+    )
+    .await?;
+
+    if *SESSION_TOKEN_ROTATE {
+        session.rotate(data).await?;
+    }
+
+    let mut headers = Vec::with_capacity(2);
+    if let Some(h) = header_origin {
+        headers.push(h);
+        headers.push((
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        ));
+    }
+
+    Ok((token_set, session, headers))
+}
+
+/// Returns the CORS headers for a preflight request to `/oidc/token/session`, if the `Origin`
+/// is allowed for any of the `SESSION_TOKEN_CLIENTS`.
+pub async fn session_token_preflight(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+) -> Vec<(HeaderName, HeaderValue)> {
+    for client_id in SESSION_TOKEN_CLIENTS.iter() {
+        let Ok(client) = Client::find(data, client_id.clone()).await else {
+            continue;
+        };
+        if let Ok(Some(origin)) = client.validate_origin(req, &data.listen_scheme, &data.public_url)
+        {
+            return vec![
+                origin,
+                (
+                    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                ),
+                (
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static("POST"),
+                ),
+                (
+                    header::ACCESS_CONTROL_ALLOW_HEADERS,
+                    HeaderValue::from_static("content-type"),
+                ),
+                (
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from_static("3600"),
+                ),
+            ];
+        }
+    }
+    Vec::new()
+}
+
 /**
 Handles the login delay.
 
-With every successful login, a new average login time is calculated for how
-long it took for a successful login. If a login failed though, the answer will be delayed by the
-current average for a successful login, to prevent things like username enumeration.
+With every successful login, its duration is recorded in a sliding window for its source, which
+is the account or, if unknown, the peer IP. If a login failed though, the answer will be delayed by
+the median over all sources for a successful login, to prevent things like username enumeration.
+Each source only gets a single vote, so that one client cannot skew the delay for others.
  */
 pub async fn handle_login_delay(
     data: &web::Data<AppState>,
     peer_ip: Option<String>,
+    account: Option<String>,
     start: Duration,
     cache_config: &redhac::CacheConfig,
     // the bool for Ok() is true is the password has been hashed
     // the bool for Err() means if we need to add a login delay (and none otherwise for better UX)
     res: Result<(HttpResponse, bool), (ErrorResponse, bool)>,
 ) -> Result<HttpResponse, ErrorResponse> {
-    let success_time = cache_get!(
-        i64,
-        CACHE_NAME_LOGIN_DELAY.to_string(),
-        IDX_LOGIN_TIME.to_string(),
-        cache_config,
-        false
-    )
-    .await?
-    .unwrap_or(2000);
+    let success_time = login_delay::success_millis(cache_config).await?;
 
     let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     let delta = end - start;
 
     match res {
         Ok((resp, has_password_been_hashed)) => {
+            let source = login_delay::source_key(account.as_deref(), peer_ip.as_deref());
+
             // cleanup possibly blacklisted IP
             if let Some(ip) = peer_ip {
                 data.tx_ip_blacklist
@@ -1507,20 +2801,11 @@ pub async fn handle_login_delay(
                 warn!("No IP in login delay handler - check your reverse proxy setup");
             }
 
-            // only calculate the new median login time base on the full duration incl password hash
+            // only record the login time based on the full duration incl password hash
             if has_password_been_hashed {
-                let new_time = (success_time + delta.as_millis() as i64) / 2;
-
-                cache_put(
-                    CACHE_NAME_LOGIN_DELAY.to_string(),
-                    IDX_LOGIN_TIME.to_string(),
-                    cache_config,
-                    &new_time,
-                )
-                .await?;
-
-                debug!("New login_success_time: {}", new_time);
+                login_delay::record_success(&source, delta.as_millis() as u64);
             }
+            login_latency::record(LatencyStage::Authorize, delta);
 
             Ok(resp)
         }
@@ -1529,6 +2814,17 @@ pub async fn handle_login_delay(
                 return Err(err);
             }
 
+            // Trusted networks like an office VPN share a single IP for many users. They are
+            // never delayed or blacklisted, but failed logins are still audited.
+            if is_trusted_login_ip(peer_ip.as_deref()) {
+                debug!("Failed login from trusted network - skipping the login delay");
+                data.tx_events
+                    .send_async(Event::invalid_login(1, peer_ip.unwrap_or_default()))
+                    .await
+                    .unwrap();
+                return Err(err);
+            }
+
             let mut failed_logins = 1;
 
             // check possibly blacklisted IP
@@ -1572,9 +2868,8 @@ pub async fn handle_login_delay(
             let sleep_time_median = {
                 let time_taken = end.sub(start).as_millis() as u64;
                 let mut sleep_time_median = 0;
-                let su64 = success_time as u64;
-                if time_taken < su64 {
-                    sleep_time_median = su64 - time_taken;
+                if time_taken < success_time {
+                    sleep_time_median = success_time - time_taken;
                 }
                 sleep_time_median
             };
@@ -1697,55 +2992,79 @@ pub async fn logout(
 ) -> Result<String, ErrorResponse> {
     let colors = ColorEntity::find_rauthy(data).await?;
 
-    if logout_request.id_token_hint.is_none() {
-        return Ok(LogoutHtml::build(&session.csrf_token, false, &colors, lang));
-    }
-
-    // check if the provided token hint is a valid
-    let token_raw = logout_request.id_token_hint.unwrap();
-    let claims = validate_token::<JwtIdClaims>(data, &token_raw).await?;
-
-    // check if it is an ID token
-    if JwtTokenType::Id != claims.custom.typ {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            String::from("The provided token is not an ID token"),
-        ));
-    }
+    let (hint_valid, location) =
+        validate_logout_request(data, &logout_request, session.user_id.as_deref()).await?;
+    // without a valid `id_token_hint`, the user must always confirm the logout
+    Ok(LogoutHtml::build(
+        &session.csrf_token,
+        hint_valid,
+        location.as_deref().unwrap_or_default(),
+        &colors,
+        lang,
+    ))
+}
 
-    // from here on, the token_hint contains a valid ID token -> skip the logout confirmation
-    if logout_request.post_logout_redirect_uri.is_some() {
-        // unwrap is safe since the token is valid already
-        let client_id = claims.custom.azp;
-        let client = Client::find(data, client_id).await?;
-        if client.post_logout_redirect_uris.is_none() {
+/// Validates an RP-Initiated Logout request.
+///
+/// Returns if the `id_token_hint` is valid for the given user, which allows skipping the logout
+/// confirmation, and the final `post_logout_redirect_uri` with the `state` appended. A redirect
+/// is only allowed if the client could be identified by the `id_token_hint` or `client_id` and
+/// the URI is registered for it.
+pub async fn validate_logout_request(
+    data: &web::Data<AppState>,
+    logout_request: &LogoutRequest,
+    user_id: Option<&str>,
+) -> Result<(bool, Option<String>), ErrorResponse> {
+    let mut hint_valid = false;
+    let mut client_id = logout_request.client_id.clone();
+
+    if let Some(token_raw) = &logout_request.id_token_hint {
+        let claims = validate_token_with_tolerance::<JwtIdClaims>(
+            data,
+            token_raw,
+            ID_TOKEN_HINT_TOLERANCE_SECS,
+        )
+        .await?;
+        if JwtTokenType::Id != claims.custom.typ {
             return Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
-                String::from("Given 'post_logout_redirect_uri' is not allowed"),
+                String::from("The provided token is not an ID token"),
             ));
         }
-
-        let target = logout_request.post_logout_redirect_uri.unwrap();
-        let uri_vec = client.get_post_logout_uris();
-        let valid_redirect = uri_vec.as_ref().unwrap().iter().filter(|uri| {
-            if uri.ends_with('*') && target.starts_with(uri.split_once('*').unwrap().0) {
-                return true;
-            }
-            if target.eq(*uri) {
-                return true;
-            }
-            false
-        });
-        if valid_redirect.count() == 0 {
+        if client_id.is_some() && client_id.as_deref() != Some(claims.custom.azp.as_str()) {
             return Err(ErrorResponse::new(
                 ErrorResponseType::BadRequest,
-                String::from("Given 'post_logout_redirect_uri' is not allowed"),
+                String::from("The 'client_id' does not match the 'id_token_hint'"),
             ));
         }
-        // redirect uri is valid at this point
+
+        // a hint for another user than the current one is treated as if there was none
+        hint_valid = match (user_id, claims.subject) {
+            (Some(user_id), Some(sub)) if sub == user_id => true,
+            // the hint may have been issued before the user has been merged into the current one
+            (Some(user_id), Some(sub)) => {
+                UserMerge::find_new_id(data, &sub).await?.as_deref() == Some(user_id)
+            }
+            _ => false,
+        };
+        client_id = Some(claims.custom.azp);
     }
 
-    Ok(LogoutHtml::build(&session.csrf_token, true, &colors, lang))
+    let Some(target) = &logout_request.post_logout_redirect_uri else {
+        return Ok((hint_valid, None));
+    };
+    let Some(client_id) = client_id else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            String::from(
+                "A 'post_logout_redirect_uri' needs either an 'id_token_hint' or a 'client_id'",
+            ),
+        ));
+    };
+    let client = Client::find(data, client_id).await?;
+    let location = client.post_logout_location(target, logout_request.state.as_deref())?;
+
+    Ok((hint_valid, Some(location)))
 }
 
 // TODO move into entity
@@ -1860,6 +3179,7 @@ pub async fn rotate_jwks(data: &web::Data<AppState>) -> Result<(), ErrorResponse
         &data.caches.ha_cache_config,
     )
     .await?;
+    Invalidation::Jwks.publish(data).await;
 
     info!("Finished JWKS rotation");
 
@@ -1879,7 +3199,16 @@ async fn sign_access_token(
 ) -> Result<String, ErrorResponse> {
     let key_pair_type = JwkKeyPairAlg::from_str(&client.access_token_alg)?;
     let kp = JwkKeyPair::find_latest(data, &client.access_token_alg, key_pair_type).await?;
-    sign_jwt!(kp, claims)
+    JwkLifecycle::count_usage(&kp.kid);
+    if *ACCESS_TOKEN_JWT_PROFILE {
+        let options = HeaderOptions {
+            signature_type: Some(JWT_TYP_ACCESS_TOKEN.to_string()),
+            ..Default::default()
+        };
+        sign_jwt_with_options!(kp, claims, &options)
+    } else {
+        sign_jwt!(kp, claims)
+    }
 }
 
 /// Signs an id token
@@ -1890,6 +3219,7 @@ async fn sign_id_token(
 ) -> Result<String, ErrorResponse> {
     let key_pair_type = JwkKeyPairAlg::from_str(&client.id_token_alg)?;
     let kp = JwkKeyPair::find_latest(data, &client.id_token_alg, key_pair_type).await?;
+    JwkLifecycle::count_usage(&kp.kid);
     sign_jwt!(kp, claims)
 }
 
@@ -1901,6 +3231,7 @@ async fn sign_refresh_token(
     let alg = String::from("EdDSA");
     let key_pair_type = JwkKeyPairAlg::from_str(&alg)?;
     let kp = JwkKeyPair::find_latest(data, &alg, key_pair_type).await?;
+    JwkLifecycle::count_usage(&kp.kid);
     sign_jwt!(kp, claims)
 }
 
@@ -1920,7 +3251,10 @@ pub async fn validate_auth_req_param(
     let header = client.validate_origin(req, &data.listen_scheme, &data.public_url)?;
 
     // allowed redirect uris
-    client.validate_redirect_uri(redirect_uri)?;
+    if let Err(err) = client.validate_redirect_uri(redirect_uri) {
+        token_errors::record(Some(&client.id), "authorization_code", &err);
+        return Err(err);
+    }
 
     // code challenge + method
     if client.challenge.is_some() {
@@ -1953,10 +3287,15 @@ pub async fn validate_refresh_token(
     refresh_token: &str,
     data: &web::Data<AppState>,
     req: &HttpRequest,
+    // the optional `resource` from the token request, which must match the bound one
+    requested_resource: Option<&str>,
+    // the optional subset of the granted `authorization_details` from the token request
+    requested_details: Option<&str>,
 ) -> Result<(TokenSet, Option<String>), ErrorResponse> {
     let options = VerificationOptions {
         // allowed_audiences: Some(HashSet::from_strings(&[&])), // TODO change after making client non-opt
         allowed_issuers: Some(HashSet::from_strings(&[&data.issuer])),
+        time_tolerance: Some(coarsetime::Duration::from_secs(*CLOCK_SKEW_LEEWAY)),
         ..Default::default()
     };
 
@@ -1978,6 +3317,8 @@ pub async fn validate_refresh_token(
 
     // get uid
     let uid = claims.custom.uid;
+    // keep the binding to the original session for the new tokens
+    let sid = claims.custom.sid.map(TokenSid);
 
     // get azp / client
     let client = if let Some(c) = client_opt {
@@ -1993,6 +3334,15 @@ pub async fn validate_refresh_token(
     }
     let header_origin = client.validate_origin(req, &data.listen_scheme, &data.public_url)?;
 
+    // a refresh token can only ever be used for the resource it has been issued for
+    let resource = ResourceServer::find_for_refresh(
+        data,
+        claims.custom.resource.as_deref(),
+        requested_resource,
+        &client.id,
+    )
+    .await?;
+
     // validate DPoP proof
     let (dpop_fingerprint, dpop_nonce) = if let Some(cnf) = claims.custom.cnf {
         // if the refresh token contains the 'cnf' header, we must validate the DPoP as well
@@ -2023,6 +3373,15 @@ pub async fn validate_refresh_token(
 
     // check expires_at from the db entry
     if rt.exp < OffsetDateTime::now_utc().unix_timestamp() {
+        // A token, which simply has not been used within the idle timeout, is no misuse.
+        if rt.is_idle_expired() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                String::from("Refresh Token has expired because of inactivity"),
+            )
+            .with_code(ErrorCode::GrantExpired));
+        }
+
         // if an already used refresh token was provided again, invalidate all existing ones for the
         // user as well to prevent possible security issues
         RefreshToken::invalidate_all_for_user(data, &rt.user_id).await?;
@@ -2032,19 +3391,40 @@ pub async fn validate_refresh_token(
                 "Refresh Token has expired already. All other refresh tokens\
                 for this user have been invalidated now because of misuse.",
             ),
-        ));
+        )
+        .with_code(ErrorCode::GrantExpired));
+    }
+
+    if let Some(device_fp) = &rt.device_fp {
+        if device_fp != &RefreshTokenChain::device_fingerprint(req) {
+            // the token has most probably been copied -> make sure it cannot be used anymore
+            warn!(
+                "Refresh token for user {} used from another device - invalidating it",
+                rt.user_id
+            );
+            rt.exp = OffsetDateTime::now_utc().unix_timestamp();
+            rt.save(data).await?;
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                String::from("The refresh token is bound to another device"),
+            ));
+        }
     }
+    let rt_chain = RefreshTokenChain {
+        exp: rt.chain_exp,
+        device_fp: rt.device_fp.clone(),
+    };
+
+    let authorization_details =
+        TokenAuthorizationDetails::new(claims.custom.authorization_details, requested_details)?;
 
-    let mut user = User::find(data, uid).await?;
+    let user = User::find(data, uid).await?;
     user.check_enabled()?;
     user.check_expired()?;
 
     // at this point, everything has been validated -> we can issue a new TokenSet safely
     debug!("Refresh Token - all good!");
-
-    // set last login
-    user.last_login = Some(OffsetDateTime::now_utc().unix_timestamp());
-    user.save(data, None, None).await?;
+    UserActivity::record(data, user.id.clone(), UserActivityKind::Refresh);
 
     // invalidate current refresh token
     let now = OffsetDateTime::now_utc().unix_timestamp();
@@ -2066,6 +3446,10 @@ pub async fn validate_refresh_token(
             // TODO should we even ever set mfa for refresh tokens?
             AuthCodeFlow::No,
             DeviceCodeFlow::No,
+            sid,
+            rt_chain,
+            resource.as_ref(),
+            authorization_details,
         )
         .await
     } else {
@@ -2079,6 +3463,10 @@ pub async fn validate_refresh_token(
             // TODO should we even ever set mfa for refresh tokens?
             AuthCodeFlow::No,
             DeviceCodeFlow::No,
+            sid,
+            rt_chain,
+            resource.as_ref(),
+            authorization_details,
         )
         .await
     }?;
@@ -2089,10 +3477,24 @@ pub async fn validate_refresh_token(
 pub async fn validate_token<T: serde::Serialize + for<'de> ::serde::Deserialize<'de>>(
     data: &web::Data<AppState>,
     token: &str,
+) -> Result<claims::JWTClaims<T>, ErrorResponse> {
+    validate_token_with_tolerance(data, token, *CLOCK_SKEW_LEEWAY).await
+}
+
+/// The time tolerance for an `id_token_hint`, which basically ignores its `exp`. RPs will often
+/// only initiate a logout after their ID token has expired, which OpenID Connect RP-Initiated
+/// Logout explicitly allows.
+const ID_TOKEN_HINT_TOLERANCE_SECS: u64 = 365 * 24 * 60 * 60;
+
+async fn validate_token_with_tolerance<T: serde::Serialize + for<'de> ::serde::Deserialize<'de>>(
+    data: &web::Data<AppState>,
+    token: &str,
+    tolerance_secs: u64,
 ) -> Result<claims::JWTClaims<T>, ErrorResponse> {
     let options = jwt_simple::prelude::VerificationOptions {
         // allowed_audiences: Some(HashSet::from_strings(&[&])), // TODO
         allowed_issuers: Some(HashSet::from_strings(&[&data.issuer])),
+        time_tolerance: Some(coarsetime::Duration::from_secs(tolerance_secs)),
         ..Default::default()
     };
 
@@ -2101,10 +3503,134 @@ pub async fn validate_token<T: serde::Serialize + for<'de> ::serde::Deserialize<
 
     // retrieve jwk for kid
     let kp = JwkKeyPair::find(data, kid).await?;
-    validate_jwt!(T, kp, token, options)
+    let claims = validate_jwt!(T, kp, token, options)?;
+
+    if let Some(jti) = &claims.jwt_id {
+        RevokedToken::check(data, jti).await?;
+    }
 
     // TODO check roles if we add more users / roles
+    Ok(claims)
+}
+
+/// Validates the "JWT Profile for OAuth 2.0 Access Tokens" (RFC 9068) for an already validated
+/// access token. This is a no-op for all other token types or without `ACCESS_TOKEN_JWT_PROFILE`.
+pub fn validate_access_token_profile(
+    token: &str,
+    claims: &claims::JWTClaims<JwtCommonClaims>,
+) -> Result<(), ErrorResponse> {
+    if !*ACCESS_TOKEN_JWT_PROFILE {
+        return Ok(());
+    }
+    check_access_token_profile(token, claims)
+}
+
+#[inline]
+fn check_access_token_profile(
+    token: &str,
+    claims: &claims::JWTClaims<JwtCommonClaims>,
+) -> Result<(), ErrorResponse> {
+    if !matches!(claims.custom.typ, JwtTokenType::Bearer | JwtTokenType::DPoP) {
+        return Ok(());
+    }
+
+    let typ_ok = Token::decode_metadata(token)
+        .ok()
+        .and_then(|meta| meta.signature_type().map(|typ| typ.to_lowercase()))
+        .map(|typ| typ == JWT_TYP_ACCESS_TOKEN || typ == "application/at+jwt")
+        .unwrap_or(false);
+    if !typ_ok {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            format!(
+                "Access token header 'typ' must be '{}'",
+                JWT_TYP_ACCESS_TOKEN
+            ),
+        ));
+    }
+
+    if claims.custom.client_id.is_none()
+        || claims.jwt_id.is_none()
+        || claims.audiences.is_none()
+        || claims.subject.is_none()
+    {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "Access token is missing claims required by RFC 9068".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use rauthy_common::utils::base64_url_no_pad_encode;
+
+    #[test]
+    fn test_check_access_token_profile() {
+        let token = |typ: &str| {
+            let header = format!(r#"{{"alg":"EdDSA","typ":"{}"}}"#, typ);
+            format!("{}.e30.sig", base64_url_no_pad_encode(header.as_bytes()))
+        };
+        let claims = |typ: &str, without: &str| {
+            let mut value = serde_json::json!({
+                "typ": typ,
+                "azp": "client",
+                "client_id": "client",
+                "jti": "SomeJti",
+                "aud": "client",
+                "sub": "client",
+            });
+            value.as_object_mut().unwrap().remove(without);
+            serde_json::from_value::<claims::JWTClaims<JwtCommonClaims>>(value).unwrap()
+        };
+
+        let at = token(JWT_TYP_ACCESS_TOKEN);
+        assert!(check_access_token_profile(&at, &claims("Bearer", "")).is_ok());
+        assert!(check_access_token_profile(&at, &claims("DPoP", "")).is_ok());
+        let at_media_type = token("application/at+jwt");
+        assert!(check_access_token_profile(&at_media_type, &claims("Bearer", "")).is_ok());
+        assert!(check_access_token_profile(&token("JWT"), &claims("Bearer", "")).is_err());
+        assert!(check_access_token_profile(&at, &claims("Bearer", "client_id")).is_err());
+        assert!(check_access_token_profile(&at, &claims("Bearer", "jti")).is_err());
+        assert!(check_access_token_profile(&at, &claims("Bearer", "sub")).is_err());
+
+        // only access tokens follow the profile
+        assert!(check_access_token_profile(&token("JWT"), &claims("Id", "")).is_ok());
+    }
+
+    #[test]
+    fn test_narrow_exchange_scope() {
+        let subject = "openid email profile";
+        let client = ["openid", "email", "profile", "groups"].map(String::from);
+
+        assert_eq!(
+            narrow_exchange_scope(subject, &client, None).unwrap(),
+            subject
+        );
+        assert_eq!(
+            narrow_exchange_scope(subject, &client, Some(" ")).unwrap(),
+            subject
+        );
+        assert_eq!(
+            narrow_exchange_scope(subject, &client, Some("email openid email")).unwrap(),
+            "email openid"
+        );
+        assert_eq!(
+            narrow_exchange_scope(subject, &client, Some("openid groups")).unwrap(),
+            "openid"
+        );
+        assert!(narrow_exchange_scope(subject, &client, Some("groups")).is_err());
+        assert!(narrow_exchange_scope("", &client, Some("openid")).is_err());
+
+        // scopes, which the client may not use anymore, are dropped
+        let client = ["openid", "email"].map(String::from);
+        assert_eq!(
+            narrow_exchange_scope(subject, &client, None).unwrap(),
+            "openid email"
+        );
+        assert!(narrow_exchange_scope(subject, &client, Some("profile")).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use rauthy_common::constants::{BREAK_GLASS_HASH, BREAK_GLASS_SESSION_LIFETIME};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::password_hasher::ComparePasswords;
+use rauthy_common::rate_limit;
+use rauthy_common::utils::real_ip_from_req;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::break_glass::{BreakGlassCode, BreakGlassCredential};
+use rauthy_models::entity::sessions::{Session, SessionState};
+use rauthy_models::entity::users::User;
+use rauthy_models::events::event::Event;
+use rauthy_models::request::BreakGlassRequest;
+use rauthy_models::response::BreakGlassResponse;
+use tracing::warn;
+
+/// # Business logic for [POST /break_glass](crate::handlers::post_break_glass)
+///
+/// Validates the offline break glass credential and returns a one-time URL, which creates a
+/// short-lived admin session when it is opened in the browser. Each credential can only be
+/// used a single time.
+pub async fn start(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    req_data: BreakGlassRequest,
+) -> Result<BreakGlassResponse, ErrorResponse> {
+    let Some(hash) = &*BREAK_GLASS_HASH else {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "Break glass access is not configured".to_string(),
+        ));
+    };
+    let ip = real_ip_from_req(req);
+
+    // only a single try per second across the whole cluster
+    rate_limit::check("break_glass", 1, &data.caches.ha_cache_config).await?;
+
+    if BreakGlassCredential::is_used(data, hash).await? {
+        warn!(
+            "Break glass attempt from {:?} with an already used credential",
+            ip
+        );
+        send_event(
+            data,
+            "rejected - the credential has been used already",
+            None,
+            ip,
+        )
+        .await;
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "This break glass credential has been used already - provision a new one".to_string(),
+        ));
+    }
+
+    let is_match = ComparePasswords::is_match(req_data.secret, hash.clone()).await?;
+    let user = if is_match {
+        User::find_by_email(data, req_data.email.clone()).await.ok()
+    } else {
+        None
+    };
+    let user = match user {
+        Some(user) if user.is_admin() => user,
+        _ => {
+            warn!(
+                "Invalid break glass attempt for '{}' from {:?}",
+                req_data.email, ip
+            );
+            let text = format!("rejected - invalid credentials for {}", req_data.email);
+            send_event(data, &text, None, ip).await;
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "Invalid credentials".to_string(),
+            )
+            .with_code(ErrorCode::InvalidCredentials));
+        }
+    };
+
+    BreakGlassCredential::set_used(data, hash).await?;
+    let code = BreakGlassCode::create(data, user.id).await?;
+
+    warn!(
+        "Break glass access has been issued for '{}' from {:?}",
+        user.email, ip
+    );
+    let text = format!("access code issued for {}", user.email);
+    send_event(data, &text, Some(code.user_id), ip).await;
+
+    Ok(BreakGlassResponse {
+        url: format!("{}/break_glass/{}", data.issuer, code.code),
+        exp: Utc::now().timestamp() + 60,
+    })
+}
+
+/// # Business logic for [GET /break_glass/{code}](crate::handlers::get_break_glass)
+///
+/// Exchanges a break glass code for an admin session, which cannot be extended and expires
+/// after `BREAK_GLASS_SESSION_LIFETIME`.
+pub async fn finish(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    code: String,
+) -> Result<HttpResponse, ErrorResponse> {
+    let code = BreakGlassCode::consume(data, code).await?;
+    let user = User::find(data, code.user_id).await?;
+    user.check_enabled()?;
+    user.check_expired()?;
+
+    let ip = real_ip_from_req(req);
+    let mut session = Session::try_new(&user, *BREAK_GLASS_SESSION_LIFETIME, ip.clone())?;
+    session.state = SessionState::Auth;
+    // the offline credential replaces the second factor, which is most probably lost
+    session.is_mfa = true;
+    session.save(data).await?;
+
+    warn!(
+        "Break glass admin session created for '{}' from {:?}",
+        user.email, ip
+    );
+    let text = format!(
+        "admin session created for {} - expires at {}",
+        user.email, session.exp
+    );
+    send_event(data, &text, Some(user.id), ip).await;
+
+    Ok(HttpResponse::Found()
+        .cookie(session.client_cookie())
+        .insert_header((LOCATION, "/auth/v1/admin"))
+        .finish())
+}
+
+async fn send_event(
+    data: &web::Data<AppState>,
+    text: &str,
+    user_id: Option<String>,
+    ip: Option<String>,
+) {
+    let mut event = Event::break_glass(text.to_string(), ip);
+    event.user_id = user_id;
+    data.tx_events.send_async(event).await.unwrap();
+}
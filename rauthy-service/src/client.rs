@@ -2,6 +2,7 @@ use actix_web::web;
 use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::jwe;
 use rauthy_models::request::UpdateClientRequest;
 use rauthy_models::response::ClientSecretResponse;
 
@@ -51,8 +52,9 @@ pub async fn update_client(
     client.auth_code_lifetime = client_req.auth_code_lifetime;
     client.access_token_lifetime = client_req.access_token_lifetime;
 
-    client.scopes = Client::sanitize_scopes(data, client_req.scopes).await?;
-    client.default_scopes = Client::sanitize_scopes(data, client_req.default_scopes).await?;
+    client.scopes = Client::sanitize_scopes(data, &client.id, client_req.scopes).await?;
+    client.default_scopes =
+        Client::sanitize_scopes(data, &client.id, client_req.default_scopes).await?;
 
     client.challenge = client_req.challenges.map(|c| c.join(","));
     client.force_mfa = client_req.force_mfa;
@@ -60,6 +62,51 @@ pub async fn update_client(
     client.contacts = client_req.contacts.map(|c| c.join(","));
     client.client_uri = client_req.client_uri;
 
+    // source networks are only checked for authenticated token requests
+    client.allowed_cidrs = if client.confidential {
+        client_req
+            .allowed_cidrs
+            .filter(|c| !c.is_empty())
+            .map(|c| c.join(","))
+    } else {
+        None
+    };
+
+    client.jwks_uri = client_req.jwks_uri.filter(|uri| !uri.is_empty());
+    client.require_signed_request_object = client_req.require_signed_request_object;
+    if client.require_signed_request_object && client.jwks_uri.is_none() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Signed request objects can only be required with a 'jwks_uri'".to_string(),
+        ));
+    }
+    (
+        client.id_token_encrypted_response_alg,
+        client.id_token_encrypted_response_enc,
+    ) = jwe::encrypted_response_values(
+        "id_token",
+        client_req.id_token_encrypted_response_alg,
+        client_req.id_token_encrypted_response_enc,
+        client.jwks_uri.as_deref(),
+    )?;
+    (
+        client.userinfo_encrypted_response_alg,
+        client.userinfo_encrypted_response_enc,
+    ) = jwe::encrypted_response_values(
+        "userinfo",
+        client_req.userinfo_encrypted_response_alg,
+        client_req.userinfo_encrypted_response_enc,
+        client.jwks_uri.as_deref(),
+    )?;
+    client.authorization_details_types = client_req
+        .authorization_details_types
+        .filter(|t| !t.is_empty())
+        .map(|t| t.join(","));
+    client.token_exchange_audiences = client_req
+        .token_exchange_audiences
+        .filter(|aud| !aud.is_empty())
+        .map(|aud| aud.join(","));
+
     client.save(data, None).await?;
     Ok(client)
 }
@@ -0,0 +1,119 @@
+use actix_web::web;
+use chrono::Utc;
+use rauthy_common::constants::ADMIN_FORCE_MFA;
+use rauthy_common::error_response::ErrorResponse;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::jwk::Jwk;
+use rauthy_models::entity::password::PasswordPolicy;
+use rauthy_models::entity::users::User;
+use rauthy_models::events::event::EventLevel;
+use rauthy_models::events::notifier::EventNotifier;
+use rauthy_models::events::EVENT_PERSIST_LEVEL;
+use rauthy_models::response::{
+    ComplianceReport, ComplianceReportAdmin, ComplianceReportClient, ComplianceReportEventSink,
+    ComplianceReportKey, PasswordPolicyResponse,
+};
+
+/// Builds a point-in-time compliance report of the current configuration.
+pub async fn build_report(data: &web::Data<AppState>) -> Result<ComplianceReport, ErrorResponse> {
+    let now = Utc::now().timestamp();
+
+    let admins = User::find_all(data)
+        .await?
+        .into_iter()
+        .filter(|u| u.is_admin())
+        .map(|u| ComplianceReportAdmin {
+            mfa: u.has_mfa_enabled(),
+            id: u.id,
+            email: u.email,
+            enabled: u.enabled,
+            last_login: u.last_login,
+        })
+        .collect();
+
+    let clients = Client::find_all(data)
+        .await?
+        .into_iter()
+        .filter_map(|c| {
+            let findings = client_findings(&c);
+            if findings.is_empty() {
+                None
+            } else {
+                Some(ComplianceReportClient {
+                    id: c.id,
+                    name: c.name,
+                    enabled: c.enabled,
+                    confidential: c.confidential,
+                    findings,
+                })
+            }
+        })
+        .collect();
+
+    let password_policy = PasswordPolicyResponse::from(PasswordPolicy::find(data).await?);
+
+    let signing_keys = Jwk::find_history(&data.db)
+        .await?
+        .into_iter()
+        .filter(|k| k.active)
+        .map(|k| ComplianceReportKey {
+            age_days: (now - k.created_at) / 86400,
+            kid: k.kid,
+            alg: k.alg,
+            created_at: k.created_at,
+        })
+        .collect();
+
+    let event_sinks = EventNotifier::configured_sinks()
+        .into_iter()
+        .map(|(sink, level)| ComplianceReportEventSink {
+            sink: sink.to_string(),
+            level,
+        })
+        .collect();
+
+    Ok(ComplianceReport {
+        generated_at: now,
+        issuer: data.issuer.clone(),
+        admin_force_mfa: *ADMIN_FORCE_MFA,
+        admins,
+        clients,
+        password_policy,
+        signing_keys,
+        event_sinks,
+        event_persist_level: EventLevel::from(*EVENT_PERSIST_LEVEL.get().unwrap()),
+    })
+}
+
+/// Checks a client for settings which are considered weak.
+fn client_findings(client: &Client) -> Vec<String> {
+    let mut findings = Vec::new();
+
+    let flows = client.get_flows();
+    if flows.iter().any(|f| f == "authorization_code") {
+        match client.get_challenges() {
+            None => findings.push("PKCE is not enforced".to_string()),
+            Some(challenges) if challenges.iter().any(|c| c == "plain") => {
+                findings.push("PKCE method 'plain' is allowed".to_string())
+            }
+            Some(_) => {}
+        }
+    }
+    if flows.iter().any(|f| f == "password") {
+        findings.push("The resource owner password flow is enabled".to_string());
+    }
+
+    for uri in client.get_redirect_uris() {
+        if uri.contains('*') {
+            findings.push(format!("Wildcard redirect URI: {}", uri));
+        } else if uri.starts_with("http://")
+            && !uri.starts_with("http://localhost")
+            && !uri.starts_with("http://127.0.0.1")
+        {
+            findings.push(format!("Insecure redirect URI: {}", uri));
+        }
+    }
+
+    findings
+}
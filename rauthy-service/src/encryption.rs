@@ -5,8 +5,43 @@ use rauthy_models::app_state::AppState;
 use rauthy_models::entity::api_keys::ApiKeyEntity;
 use rauthy_models::entity::auth_providers::AuthProvider;
 use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::email_aliases::EmailAlias;
+use rauthy_models::entity::jobs::Job;
+use rauthy_models::entity::user_attr::{UserAttrConfigEntity, UserAttrValueEntity};
+use rauthy_models::entity::users::User;
+use rauthy_models::entity::users_values::UserValues;
+use rauthy_models::entity::web_push::VapidKey;
+use std::collections::HashSet;
 use tracing::{error, info};
 
+/// Encrypts all personal data, which is still stored in cleartext, depending on
+/// `PII_ENCRYPTION`. E-Mails are converted in both directions to match `PII_ENCRYPTION_EMAIL`
+/// and the `ENC_KEY_ACTIVE`. Job payloads are always encrypted.
+pub async fn encrypt_existing_pii(
+    data: &actix_web::web::Data<AppState>,
+) -> Result<(), ErrorResponse> {
+    let sensitive = UserAttrConfigEntity::find_all(data)
+        .await?
+        .into_iter()
+        .filter(|attr| attr.sensitive)
+        .map(|attr| attr.name)
+        .collect::<HashSet<String>>();
+
+    let modified = UserValues::encrypt_existing(data).await?
+        + UserAttrValueEntity::encrypt_existing(data, &sensitive).await?
+        + User::convert_emails(data).await?
+        + EmailAlias::convert_emails(data).await?
+        + Job::encrypt_existing(data).await?;
+    if modified > 0 {
+        info!(
+            "Converted {} stored values to the PII encryption config",
+            modified
+        );
+    }
+
+    Ok(())
+}
+
 /// Migrates encrypted data in the backend to a new key.
 /// JWKS's are just rotated and a new set will be created.
 pub async fn migrate_encryption_alg(
@@ -106,6 +141,18 @@ pub async fn migrate_encryption_alg(
         new_kid
     );
 
+    // migrate encrypted personal data
+    info!("Starting PII migration to key id: {}", new_kid);
+    modified += UserValues::migrate_pii_key(data, new_kid).await?;
+    modified += UserAttrValueEntity::migrate_pii_key(data, new_kid).await?;
+    info!("Finished PII migration to key id: {}", new_kid);
+
+    // migrate the VAPID key for push notifications
+    modified += VapidKey::migrate_enc_key(data, new_kid).await?;
+
+    // migrate queued job payloads
+    modified += Job::migrate_enc_key(data, new_kid).await?;
+
     info!(
         "Finished secrets migration to key id: {} after {} ms. Modified {} encryption's",
         new_kid,
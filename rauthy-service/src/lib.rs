@@ -2,8 +2,13 @@
 
 #![forbid(unsafe_code)]
 
+pub mod account;
 pub mod auth;
+pub mod break_glass;
 pub mod client;
+pub mod compliance;
 pub mod encryption;
+pub mod mfa_recovery;
 pub mod password_reset;
+pub mod sso_handoff;
 pub mod token_set;
@@ -0,0 +1,262 @@
+use crate::password_reset::new_reset_link;
+use actix_web::{web, HttpRequest};
+use rauthy_common::constants::{MFA_RECOVERY_ADMIN_APPROVAL, MFA_RECOVERY_ENABLE};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::real_ip_from_req;
+use rauthy_models::app_state::AppState;
+use rauthy_models::email::{send_mfa_recovery, send_pwd_reset};
+use rauthy_models::entity::magic_links::MagicLinkUsage;
+use rauthy_models::entity::mfa_recovery::{MfaRecovery, MfaRecoveryStatus};
+use rauthy_models::entity::refresh_tokens::RefreshToken;
+use rauthy_models::entity::sessions::Session;
+use rauthy_models::entity::users::User;
+use rauthy_models::entity::webauthn::PasskeyEntity;
+use rauthy_models::events::event::Event;
+use rauthy_models::response::MfaRecoveryResponse;
+use tracing::{info, warn};
+
+/// # Business logic for [POST /users/mfa_recovery](crate::handlers::post_mfa_recovery)
+///
+/// Starts a new recovery and sends the confirmation code via E-Mail. To not leak any
+/// information about existing users, this will silently do nothing for unknown or disabled
+/// users, and for users without any MFA factor, who can use the default password reset.
+pub async fn request(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    email: String,
+) -> Result<(), ErrorResponse> {
+    check_enabled()?;
+    let ip = real_ip_from_req(req);
+
+    let Ok(user) = User::find_by_email(data, email).await else {
+        return Ok(());
+    };
+    if !user.enabled || !user.has_webauthn_enabled() {
+        return Ok(());
+    }
+
+    if let Some(rec) = MfaRecovery::find_open_by_user(data, &user.id).await? {
+        if rec.blocks_new_request() {
+            warn!(
+                "MFA recovery request for '{}' with an already running one from {:?}",
+                user.email, ip
+            );
+            return Ok(());
+        }
+    }
+
+    let rec = MfaRecovery::create(data, user.id.clone()).await?;
+    send_mfa_recovery(data, &user, &rec.id, None).await;
+
+    send_event(data, &user.id, format!("requested for {}", user.email), ip).await;
+    Ok(())
+}
+
+/// # Business logic for [POST /users/mfa_recovery/confirm](crate::handlers::post_mfa_recovery_confirm)
+///
+/// Confirms the E-Mail ownership and starts the waiting period. The user gets notified about
+/// the earliest time the recovery can be finished.
+pub async fn confirm(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    code: String,
+) -> Result<MfaRecoveryResponse, ErrorResponse> {
+    check_enabled()?;
+    let ip = real_ip_from_req(req);
+
+    let mut rec = MfaRecovery::find(data, &code).await?;
+    rec.confirm()?;
+    rec.save(data).await?;
+
+    let user = User::find(data, rec.user_id.clone()).await?;
+    send_mfa_recovery(data, &user, &rec.id, rec.not_before).await;
+
+    send_event(
+        data,
+        &user.id,
+        format!(
+            "confirmed for {} - waiting until {}",
+            user.email,
+            rec.not_before.unwrap_or_default()
+        ),
+        ip,
+    )
+    .await;
+    Ok(MfaRecoveryResponse::new(rec, *MFA_RECOVERY_ADMIN_APPROVAL))
+}
+
+/// # Business logic for [POST /users/mfa_recovery/cancel](crate::handlers::post_mfa_recovery_cancel)
+pub async fn cancel(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    code: String,
+) -> Result<(), ErrorResponse> {
+    let mut rec = MfaRecovery::find(data, &code).await?;
+    if !rec.status.is_open() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid or expired recovery code".to_string(),
+        ));
+    }
+    rec.status = MfaRecoveryStatus::Cancelled;
+    rec.save(data).await?;
+
+    let user = User::find(data, rec.user_id).await?;
+    send_event(
+        data,
+        &user.id,
+        format!("cancelled with the code for {}", user.email),
+        real_ip_from_req(req),
+    )
+    .await;
+    Ok(())
+}
+
+/// # Business logic for [POST /users/mfa_recovery/finish](crate::handlers::post_mfa_recovery_finish)
+///
+/// Removes all passkeys and the password, revokes all sessions and refresh tokens and sends
+/// out a new magic link. This magic link is the only way to get into the account again. It can
+/// only be used to register a new passkey, so the account never ends up without MFA.
+pub async fn finish(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    code: String,
+) -> Result<(), ErrorResponse> {
+    check_enabled()?;
+    let ip = real_ip_from_req(req);
+
+    let mut rec = MfaRecovery::find(data, &code).await?;
+    rec.validate_finish(*MFA_RECOVERY_ADMIN_APPROVAL)?;
+
+    let mut user = User::find(data, rec.user_id.clone()).await?;
+    user.check_enabled()?;
+
+    let pks = PasskeyEntity::find_for_user(data, &user.id).await?;
+    let mut txn = data.db.begin().await?;
+    for pk in &pks {
+        PasskeyEntity::delete_by_id_name(data, &user.id, &pk.name, Some(&mut txn)).await?;
+    }
+    user.webauthn_user_id = None;
+    user.password = None;
+    user.password_expires = None;
+    user.save(data, None, Some(&mut txn)).await?;
+    txn.commit().await?;
+
+    // revoked in any case, no matter if `SESSION_REVOKE_ON_CREDENTIAL_CHANGE` is set
+    Session::invalidate_for_user(data, &user.id).await?;
+    RefreshToken::invalidate_for_user(data, &user.id).await?;
+
+    rec.status = MfaRecoveryStatus::Completed;
+    rec.save(data).await?;
+
+    let ml = new_reset_link(data, &user, MagicLinkUsage::PasskeyReset(None)).await?;
+    send_pwd_reset(data, &ml, &user).await;
+
+    info!(
+        "MFA recovery finished for '{}' - removed {} passkeys",
+        user.email,
+        pks.len()
+    );
+    send_event(
+        data,
+        &user.id,
+        format!(
+            "finished for {} - {} passkeys and the password removed, re-enrollment link sent",
+            user.email,
+            pks.len()
+        ),
+        ip,
+    )
+    .await;
+    Ok(())
+}
+
+/// # Business logic for [GET /users/{id}/mfa_recovery](crate::handlers::get_user_mfa_recovery)
+pub async fn status(
+    data: &web::Data<AppState>,
+    user_id: &str,
+) -> Result<MfaRecoveryResponse, ErrorResponse> {
+    MfaRecovery::find_open_by_user(data, user_id)
+        .await?
+        .map(|rec| MfaRecoveryResponse::new(rec, *MFA_RECOVERY_ADMIN_APPROVAL))
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "No open MFA recovery for this user".to_string(),
+            )
+        })
+}
+
+/// # Business logic for [POST /users/{id}/mfa_recovery/approve](crate::handlers::post_user_mfa_recovery_approve)
+pub async fn approve(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    user_id: &str,
+    approved_by: String,
+) -> Result<MfaRecoveryResponse, ErrorResponse> {
+    let mut rec = MfaRecovery::find_open_by_user(data, user_id)
+        .await?
+        .filter(|rec| rec.status == MfaRecoveryStatus::Waiting)
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "No confirmed MFA recovery for this user".to_string(),
+            )
+        })?;
+    rec.approved_by = Some(approved_by);
+    rec.save(data).await?;
+
+    let user = User::find(data, rec.user_id.clone()).await?;
+    send_event(
+        data,
+        &user.id,
+        format!(
+            "approved for {} by {}",
+            user.email,
+            rec.approved_by.as_deref().unwrap_or_default()
+        ),
+        real_ip_from_req(req),
+    )
+    .await;
+    Ok(MfaRecoveryResponse::new(rec, *MFA_RECOVERY_ADMIN_APPROVAL))
+}
+
+/// # Business logic for [DELETE /users/{id}/mfa_recovery](crate::handlers::delete_user_mfa_recovery)
+pub async fn cancel_for_user(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    user_id: &str,
+    by: &str,
+) -> Result<(), ErrorResponse> {
+    let user = User::find(data, user_id.to_string()).await?;
+    let count = MfaRecovery::cancel_for_user(data, &user.id).await?;
+    if count > 0 {
+        send_event(
+            data,
+            &user.id,
+            format!("cancelled for {} by {}", user.email, by),
+            real_ip_from_req(req),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+#[inline]
+fn check_enabled() -> Result<(), ErrorResponse> {
+    if *MFA_RECOVERY_ENABLE {
+        Ok(())
+    } else {
+        Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The MFA recovery is not enabled".to_string(),
+        ))
+    }
+}
+
+async fn send_event(data: &web::Data<AppState>, user_id: &str, text: String, ip: Option<String>) {
+    data.tx_events
+        .send_async(Event::mfa_recovery(text, ip).for_user(user_id.to_string()))
+        .await
+        .unwrap();
+}
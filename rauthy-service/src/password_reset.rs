@@ -1,21 +1,28 @@
 use actix_web::cookie::SameSite;
 use actix_web::{cookie, web, HttpRequest, HttpResponse};
-use rauthy_common::constants::{PWD_CSRF_HEADER, PWD_RESET_COOKIE};
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::constants::{
+    PWD_CSRF_HEADER, PWD_RESET_COOKIE, SESSION_REVOKE_ON_CREDENTIAL_CHANGE,
+};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
+use rauthy_common::password_hasher::HashPassword;
 use rauthy_common::utils::{get_rand, real_ip_from_req};
 use rauthy_models::app_state::AppState;
+use rauthy_models::email::send_pwd_reset;
 use rauthy_models::entity::colors::ColorEntity;
 use rauthy_models::entity::magic_links::{MagicLink, MagicLinkUsage};
 use rauthy_models::entity::password::PasswordPolicy;
 use rauthy_models::entity::sessions::Session;
-use rauthy_models::entity::users::User;
+use rauthy_models::entity::users::{CredentialChange, User};
+use rauthy_models::entity::users_values::UserValues;
 use rauthy_models::entity::webauthn;
 use rauthy_models::entity::webauthn::WebauthnServiceReq;
 use rauthy_models::events::event::Event;
 use rauthy_models::language::Language;
 use rauthy_models::request::{
-    PasswordResetRequest, WebauthnRegFinishRequest, WebauthnRegStartRequest,
+    PasswordResetChannel, PasswordResetRequest, WebauthnRegFinishRequest, WebauthnRegStartRequest,
 };
+use rauthy_models::response::AdminPasswordResetResponse;
+use rauthy_models::sms;
 use rauthy_models::templates::PwdResetHtml;
 use time::OffsetDateTime;
 use tracing::{debug, error};
@@ -85,8 +92,9 @@ pub async fn handle_put_user_passkey_start<'a>(
     ml.validate(&user.id, &req, true)?;
 
     // if we register a new passkey, we need to make sure that the magic link is for a new user
+    // or the re-enrollment for a passkey only account
     match MagicLinkUsage::try_from(&ml.usage)? {
-        MagicLinkUsage::NewUser(_) => {}
+        MagicLinkUsage::NewUser(_) | MagicLinkUsage::PasskeyReset(_) => {}
         _ => {
             return Err(ErrorResponse::new(
                 ErrorResponseType::Forbidden,
@@ -112,10 +120,6 @@ pub async fn handle_put_user_passkey_finish<'a>(
     let mut ml = MagicLink::find(data, ml_id).await?;
     ml.validate(&user_id, &req, true)?;
 
-    // finish webauthn request -> always force UV for passkey only accounts
-    debug!("ml is valid - finishing webauthn request");
-    webauthn::reg_finish(data, user_id.clone(), req_data).await?;
-
     // validate csrf token
     match req.headers().get(PWD_CSRF_HEADER) {
         None => {
@@ -134,14 +138,59 @@ pub async fn handle_put_user_passkey_finish<'a>(
         }
     }
 
+    // A re-enrollment replaces all existing passkeys. They are only deleted together with the
+    // successful registration of the new one, so a failed try never leaves the user without any
+    // credentials. The `webauthn_user_id` is kept, which means UV will still be forced. If the
+    // registration fails, the magic link is still valid and can be used for another try.
+    let is_passkey_reset = matches!(
+        MagicLinkUsage::try_from(&ml.usage)?,
+        MagicLinkUsage::PasskeyReset(_)
+    );
+
+    // finish webauthn request -> always force UV for passkey only accounts
+    debug!("ml is valid - finishing webauthn request");
+    webauthn::reg_finish(data, user_id.clone(), req_data, is_passkey_reset).await?;
+
     debug!("invalidating magic link pwd");
     // all good
     ml.invalidate(data).await?;
     // we are re-fetching the user on purpose here to not need to modify the general webauthn fn
     let mut user = User::find(data, user_id).await?;
+    if !user.email_verified {
+        user.sync_email_domain_memberships(data, None).await?;
+    }
     user.email_verified = true;
     user.save(data, None, None).await?;
 
+    if is_passkey_reset {
+        let ip = real_ip_from_req(&req);
+        data.tx_events
+            .send_async(
+                Event::user_password_reset(
+                    format!(
+                        "Passkey re-enrolled via Password Reset Form: {}",
+                        user.email
+                    ),
+                    ip.clone(),
+                )
+                .for_user(user.id.clone()),
+            )
+            .await
+            .unwrap();
+
+        if *SESSION_REVOKE_ON_CREDENTIAL_CHANGE {
+            user.revoke_sessions_on_credential_change(
+                data,
+                CredentialChange::PasskeyReset,
+                None,
+                ip,
+            )
+            .await?;
+        } else {
+            Session::invalidate_for_user(data, &user.id).await?;
+        }
+    }
+
     // delete the cookie
     let cookie = cookie::Cookie::build(PWD_RESET_COOKIE, "")
         .secure(true)
@@ -192,11 +241,22 @@ pub async fn handle_put_user_password_reset<'a>(
     let mut ml = MagicLink::find(data, &req_data.magic_link_id).await?;
     ml.validate(&user.id, &req, true)?;
 
+    // passkey only accounts never get a password from a reset link, they re-enroll a passkey
+    if let MagicLinkUsage::PasskeyReset(_) = MagicLinkUsage::try_from(&ml.usage)? {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "A password cannot be set for a passkey only account here".to_string(),
+        ));
+    }
+
     // validate password
     user.apply_password_rules(data, &req_data.password).await?;
 
     // all good
     ml.invalidate(data).await?;
+    if !user.email_verified {
+        user.sync_email_domain_memberships(data, None).await?;
+    }
     user.email_verified = true;
     user.save(data, None, None).await?;
 
@@ -208,15 +268,23 @@ pub async fn handle_put_user_password_reset<'a>(
         Some(ip) => ip,
     };
     data.tx_events
-        .send_async(Event::user_password_reset(
-            format!("Reset via Password Reset Form: {}", user.email),
-            Some(ip),
-        ))
+        .send_async(
+            Event::user_password_reset(
+                format!("Reset via Password Reset Form: {}", user.email),
+                Some(ip.clone()),
+            )
+            .for_user(user.id.clone()),
+        )
         .await
         .unwrap();
 
     // delete all existing user sessions to have a clean flow
-    Session::invalidate_for_user(data, &user.id).await?;
+    if *SESSION_REVOKE_ON_CREDENTIAL_CHANGE {
+        user.revoke_sessions_on_credential_change(data, CredentialChange::Password, None, Some(ip))
+            .await?;
+    } else {
+        Session::invalidate_for_user(data, &user.id).await?;
+    }
 
     // check if we got a custom `redirect_uri` during registration
     let redirect_uri = match MagicLinkUsage::try_from(&ml.usage)? {
@@ -235,3 +303,161 @@ pub async fn handle_put_user_password_reset<'a>(
         .finish();
     Ok((cookie, redirect_uri))
 }
+
+/// Lifetime of a one-time password issued by an admin
+const OTP_LIFETIME_SECS: i64 = 600;
+
+/// Starts a password reset for a user on behalf of an admin via the given delivery channel.
+pub async fn handle_admin_password_reset(
+    data: &web::Data<AppState>,
+    req: HttpRequest,
+    user_id: String,
+    channel: PasswordResetChannel,
+) -> Result<AdminPasswordResetResponse, ErrorResponse> {
+    let mut user = User::find(data, user_id).await?;
+    let ip = real_ip_from_req(&req);
+
+    match channel {
+        PasswordResetChannel::Email => {
+            let ml = new_reset_link(data, &user, MagicLinkUsage::for_reset(&user, None)).await?;
+            send_pwd_reset(data, &ml, &user).await;
+
+            data.tx_events
+                .send_async(Event::admin_reset_email(user.email, ip).for_user(user.id))
+                .await
+                .unwrap();
+
+            Ok(AdminPasswordResetResponse {
+                channel,
+                otp: None,
+                exp: ml.exp,
+            })
+        }
+
+        PasswordResetChannel::Otp => {
+            let otp = get_rand(16);
+
+            // The OTP replaces the current password and expires quickly. The pending magic link
+            // makes sure that it can only be redeemed once for a new password reset link.
+            let ml = new_link(
+                data,
+                &user,
+                MagicLinkUsage::PasswordOtp,
+                OTP_LIFETIME_SECS / 60,
+            )
+            .await?;
+            let exp = ml.exp;
+            user.password = Some(HashPassword::hash_password(otp.clone()).await?);
+            user.password_expires = Some(exp);
+            user.save(data, None, None).await?;
+
+            user.revoke_sessions_on_credential_change(
+                data,
+                CredentialChange::Password,
+                None,
+                ip.clone(),
+            )
+            .await?;
+
+            data.tx_events
+                .send_async(Event::admin_reset_otp(user.email, ip).for_user(user.id))
+                .await
+                .unwrap();
+
+            Ok(AdminPasswordResetResponse {
+                channel,
+                otp: Some(otp),
+                exp,
+            })
+        }
+
+        PasswordResetChannel::Sms => {
+            if !sms::is_sms_configured() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "SMS delivery is not configured".to_string(),
+                ));
+            }
+
+            let phone = UserValues::find(data, &user.id)
+                .await?
+                .and_then(|v| v.phone)
+                .ok_or_else(|| {
+                    ErrorResponse::new(
+                        ErrorResponseType::BadRequest,
+                        "The user has no phone number".to_string(),
+                    )
+                })?;
+
+            let ml = new_reset_link(data, &user, MagicLinkUsage::for_reset(&user, None)).await?;
+            sms::send_pwd_reset_sms(data, &ml, &phone).await?;
+
+            data.tx_events
+                .send_async(Event::admin_reset_sms(user.email, ip).for_user(user.id))
+                .await
+                .unwrap();
+
+            Ok(AdminPasswordResetResponse {
+                channel,
+                otp: None,
+                exp: ml.exp,
+            })
+        }
+    }
+}
+
+/// Creates a new password reset magic link and invalidates a possibly existing one.
+pub(crate) async fn new_reset_link(
+    data: &web::Data<AppState>,
+    user: &User,
+    usage: MagicLinkUsage,
+) -> Result<MagicLink, ErrorResponse> {
+    new_link(data, user, usage, data.ml_lt_pwd_reset as i64).await
+}
+
+async fn new_link(
+    data: &web::Data<AppState>,
+    user: &User,
+    usage: MagicLinkUsage,
+    lifetime_minutes: i64,
+) -> Result<MagicLink, ErrorResponse> {
+    if let Ok(mut ml) = MagicLink::find_by_user(data, user.id.clone()).await {
+        if ml.exp > OffsetDateTime::now_utc().unix_timestamp() {
+            ml.invalidate(data).await?;
+        }
+    }
+    MagicLink::invalidate_password_otp(data, &user.id).await?;
+
+    MagicLink::create(data, user.id.clone(), lifetime_minutes, usage).await
+}
+
+/// Redeems a one-time password, which has been issued by an admin. It can only be used once and
+/// only to set a new password: the user gets a fresh password reset link instead of a session.
+///
+/// Returns the location of the new reset link.
+pub async fn redeem_admin_otp(
+    data: &web::Data<AppState>,
+    user: &mut User,
+    otp_link: MagicLink,
+    otp: String,
+) -> Result<String, ErrorResponse> {
+    let err = ErrorResponse::new(
+        ErrorResponseType::Unauthorized,
+        String::from("Invalid user credentials"),
+    )
+    .with_code(ErrorCode::InvalidCredentials);
+
+    if !user.match_passwords(otp).await? || !otp_link.try_use(data).await? {
+        return Err(err);
+    }
+
+    user.password = None;
+    user.password_expires = None;
+    user.save(data, None, None).await?;
+
+    let ml = new_reset_link(data, user, MagicLinkUsage::PasswordReset(None)).await?;
+    Ok(format!(
+        "{}/users/{}/reset/{}?type={}",
+        data.issuer, ml.user_id, ml.id, ml.usage,
+    ))
+}
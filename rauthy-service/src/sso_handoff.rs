@@ -0,0 +1,162 @@
+use actix_web::http::header::{CACHE_CONTROL, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use rauthy_common::constants::{HEADER_HTML, SESSION_LIFETIME, SSO_HANDOFF_TOKEN_LIFETIME};
+use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use rauthy_common::utils::real_ip_from_req;
+use rauthy_models::app_state::AppState;
+use rauthy_models::entity::sessions::{Session, SessionState};
+use rauthy_models::entity::sso_handoff::{SsoHandoffPending, SsoHandoffToken};
+use rauthy_models::entity::users::User;
+use rauthy_models::request::{SsoHandoffConfirmRequest, SsoHandoffParams, SsoHandoffRequest};
+use rauthy_models::response::SsoHandoffResponse;
+use rauthy_models::templates::SsoHandoffHtml;
+use tracing::info;
+
+const DEFAULT_REDIRECT: &str = "/auth/v1/account";
+
+/// # Business logic for [POST /oidc/sso_handoff](crate::handlers::post_sso_handoff)
+///
+/// Issues a login hint token for the user of the given session, which can be used once at the
+/// peer instance to get a session there as well.
+pub async fn start(
+    data: &web::Data<AppState>,
+    mut session: Session,
+    req_data: SsoHandoffRequest,
+) -> Result<SsoHandoffResponse, ErrorResponse> {
+    if !SsoHandoffToken::is_enabled() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The SSO handoff is not enabled".to_string(),
+        ));
+    }
+
+    let user_id = session.user_id.clone().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Unauthorized,
+            "No user linked to this session".to_string(),
+        )
+    })?;
+    let user = User::find(data, user_id).await?;
+    user.check_enabled()?;
+    session.validate_user_expiry(&user)?;
+
+    let login_hint_token =
+        SsoHandoffToken::issue(data, &user, session.is_mfa, &req_data.audience).await?;
+
+    // the JWT only contains URL safe characters and needs no encoding
+    let peer = req_data.audience.trim_end_matches('/');
+    let url = format!(
+        "{}/oidc/sso_handoff?login_hint_token={}",
+        peer, login_hint_token
+    );
+
+    info!("SSO handoff token issued for '{}' to {}", user.email, peer);
+
+    Ok(SsoHandoffResponse {
+        login_hint_token,
+        url,
+        exp: Utc::now().timestamp() + *SSO_HANDOFF_TOKEN_LIFETIME as i64,
+    })
+}
+
+/// # Business logic for [GET /oidc/sso_handoff](crate::handlers::get_sso_handoff)
+///
+/// Validates and consumes a login hint token from a peer instance. The session will only be
+/// created after the user has confirmed the account in the same browser.
+pub async fn finish(
+    data: &web::Data<AppState>,
+    params: SsoHandoffParams,
+) -> Result<HttpResponse, ErrorResponse> {
+    // validate the redirect first to not consume the token for nothing
+    let location = match params.redirect_uri {
+        None => DEFAULT_REDIRECT.to_string(),
+        Some(uri) if is_local_path(&uri) => uri,
+        Some(_) => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The 'redirect_uri' must be a path at this instance".to_string(),
+            ));
+        }
+    };
+
+    let handoff = SsoHandoffToken::validate(data, &params.login_hint_token).await?;
+    handoff.user.check_enabled()?;
+    handoff.user.check_expired()?;
+
+    let pending = SsoHandoffPending::create(data, &handoff, location).await?;
+    let body = SsoHandoffHtml::build(&pending.id, &pending.email, &pending.peer);
+
+    Ok(HttpResponse::Ok()
+        .cookie(pending.cookie())
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .insert_header(HEADER_HTML)
+        .body(body))
+}
+
+/// # Business logic for [POST /oidc/sso_handoff/confirm](crate::handlers::post_sso_handoff_confirm)
+///
+/// Creates the session for a confirmed SSO handoff and redirects to the `redirect_uri`.
+pub async fn confirm(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+    req_data: SsoHandoffConfirmRequest,
+) -> Result<HttpResponse, ErrorResponse> {
+    if !SsoHandoffToken::is_enabled() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "The SSO handoff is not enabled".to_string(),
+        ));
+    }
+
+    let pending = SsoHandoffPending::consume(data, req, &req_data.id).await?;
+
+    // the user may have changed in the meantime
+    let mut user = User::find(data, pending.user_id).await?;
+    user.check_enabled()?;
+    user.check_expired()?;
+
+    let ip = real_ip_from_req(req);
+    user.check_login_policy(data, ip.clone()).await?;
+    let mut session = Session::try_new(&user, *SESSION_LIFETIME, ip.clone())?;
+    session.state = SessionState::Auth;
+    session.is_mfa = pending.mfa;
+    session.save(data).await?;
+
+    user.last_login = Some(Utc::now().timestamp());
+    user.save(data, None, None).await?;
+
+    info!(
+        "Session created for '{}' from {:?} via SSO handoff from {}",
+        user.email, ip, pending.peer
+    );
+
+    Ok(HttpResponse::Found()
+        .cookie(session.client_cookie())
+        .cookie(SsoHandoffPending::cookie_remove())
+        .insert_header((LOCATION, pending.redirect_uri))
+        .finish())
+}
+
+/// Only relative paths below `/auth/v1/` are allowed, which prevents open redirects.
+fn is_local_path(uri: &str) -> bool {
+    uri.starts_with("/auth/v1/") && !uri.contains("..") && !uri.contains('\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_local_path() {
+        assert!(is_local_path("/auth/v1/account"));
+        assert!(is_local_path(
+            "/auth/v1/oidc/authorize?client_id=app&redirect_uri=https://app.example.com"
+        ));
+
+        assert!(!is_local_path("https://evil.example.com/auth/v1/account"));
+        assert!(!is_local_path("//evil.example.com/auth/v1/account"));
+        assert!(!is_local_path("/auth/v1/../../admin"));
+        assert!(!is_local_path("/other"));
+    }
+}
@@ -1,16 +1,20 @@
 use crate::auth;
-use actix_web::web;
-use rauthy_common::error_response::{ErrorResponse, ErrorResponseType};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{web, HttpRequest};
+use rauthy_common::constants::{REFRESH_TOKEN_BIND_DEVICE, TOKEN_TYPE_ACCESS_TOKEN};
+use rauthy_common::error_response::{ErrorCode, ErrorResponse, ErrorResponseType};
 use rauthy_common::utils::base64_url_no_pad_encode;
 use rauthy_models::app_state::AppState;
 use rauthy_models::entity::clients::Client;
+use rauthy_models::entity::resource_servers::ResourceServer;
 use rauthy_models::entity::scopes::Scope;
 use rauthy_models::entity::user_attr::UserAttrValueEntity;
+use rauthy_models::entity::user_client_grants::UserClientGrant;
 use rauthy_models::entity::users::User;
-use rauthy_models::JwtTokenType;
+use rauthy_models::{ActClaim, AuthorizationDetail, JwtTokenType};
 use ring::digest;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use time::OffsetDateTime;
 use utoipa::ToSchema;
 
@@ -75,6 +79,72 @@ pub struct TokenNonce(pub String);
 /// Contains the scopes as a single String separated by `\s`
 pub struct TokenScopes(pub String);
 
+/// The `sid` claim, which binds the tokens to the session they have been issued for
+#[derive(Clone)]
+pub struct TokenSid(pub String);
+
+/// The target and the delegation chain for an access token from a token exchange (RFC 8693)
+pub struct TokenExchange {
+    /// The `client_id` of the target service, which becomes the `aud`
+    pub audience: String,
+    pub act: Option<ActClaim>,
+}
+
+/// The RFC 9396 `authorization_details`, which have been requested in addition to the ones
+/// derived from parameterized scopes. The access token only gets the `access` ones, while the
+/// refresh token keeps all `granted` ones, so a later refresh can ask for another subset.
+#[derive(Debug, Default)]
+pub struct TokenAuthorizationDetails {
+    pub access: Option<Vec<AuthorizationDetail>>,
+    pub granted: Option<Vec<AuthorizationDetail>>,
+}
+
+impl TokenAuthorizationDetails {
+    /// Narrows the `granted` details down to the `requested` ones from a token request.
+    pub fn new(
+        granted: Option<Vec<AuthorizationDetail>>,
+        requested: Option<&str>,
+    ) -> Result<Self, ErrorResponse> {
+        let access = AuthorizationDetail::narrow(granted.clone(), requested)?;
+        Ok(Self { access, granted })
+    }
+}
+
+/// Carries the state of a refresh token rotation chain over to the next refresh token.
+#[derive(Debug, Clone, Default)]
+pub struct RefreshTokenChain {
+    /// The absolute expiry of the chain, `None` for a new one
+    pub exp: Option<i64>,
+    /// The device fingerprint, if `REFRESH_TOKEN_BIND_DEVICE` is set
+    pub device_fp: Option<String>,
+}
+
+impl RefreshTokenChain {
+    /// Starts a new chain for the device of the given request.
+    pub fn new(req: &HttpRequest) -> Self {
+        Self {
+            exp: None,
+            device_fp: if *REFRESH_TOKEN_BIND_DEVICE {
+                Some(Self::device_fingerprint(req))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// The device fingerprint is derived from the `User-Agent`. It is only a weak binding and
+    /// used for clients without DPoP, which bind their tokens to a private key instead.
+    pub fn device_fingerprint(req: &HttpRequest) -> String {
+        let ua = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let hash = digest::digest(&digest::SHA256, ua.as_bytes());
+        base64_url_no_pad_encode(hash.as_ref())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TokenSet {
     pub access_token: String,
@@ -84,6 +154,15 @@ pub struct TokenSet {
     pub expires_in: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub refresh_token: Option<String>,
+    /// Only set for the `token-exchange` grant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+    /// RFC 9396 details for granted parameterized scopes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorization_details: Option<Vec<AuthorizationDetail>>,
+    /// The `jti` of the access token
+    #[serde(skip)]
+    pub access_token_jti: String,
 }
 
 impl TokenSet {
@@ -91,30 +170,95 @@ impl TokenSet {
         data: &web::Data<AppState>,
         client: &Client,
         dpop_fingerprint: Option<DpopFingerprint>,
+        resource: Option<&ResourceServer>,
+        authorization_details: Option<Vec<AuthorizationDetail>>,
     ) -> Result<Self, ErrorResponse> {
+        let scope = match resource {
+            Some(rs) => rs.filter_scopes(&client.default_scopes.replace(',', " "))?,
+            None => client.default_scopes.replace(',', " "),
+        };
+        let authorization_details = AuthorizationDetail::merge(
+            Scope::authorization_details(client, &scope),
+            authorization_details,
+        );
         let token_type = if dpop_fingerprint.is_some() {
             JwtTokenType::DPoP
         } else {
             JwtTokenType::Bearer
         };
-        let access_token = auth::build_access_token(
+        let (access_token, access_token_jti) = auth::build_access_token(
             None,
             data,
             client,
             dpop_fingerprint,
             client.access_token_lifetime as i64,
-            None,
+            Some(TokenScopes(scope)),
             None,
             DeviceCodeFlow::No,
+            None,
+            None,
+            resource,
+            authorization_details.clone(),
         )
         .await?;
 
         Ok(Self {
             access_token,
+            access_token_jti,
             token_type,
             id_token: None,
             expires_in: client.access_token_lifetime,
             refresh_token: None,
+            issued_token_type: None,
+            authorization_details,
+        })
+    }
+
+    /// Issues only an access token for the `token-exchange` grant. The `user` is `None`, if the
+    /// subject token has been issued to a client without a user.
+    pub async fn for_token_exchange(
+        user: Option<&User>,
+        data: &web::Data<AppState>,
+        client: &Client,
+        dpop_fingerprint: Option<DpopFingerprint>,
+        lifetime: i64,
+        scopes: TokenScopes,
+        exchange: TokenExchange,
+    ) -> Result<Self, ErrorResponse> {
+        if let Some(user) = user {
+            user.check_login_policy(data, None).await?;
+        }
+        let authorization_details = Scope::authorization_details(client, &scopes.0);
+        let token_type = if dpop_fingerprint.is_some() {
+            JwtTokenType::DPoP
+        } else {
+            JwtTokenType::Bearer
+        };
+        let (access_token, access_token_jti) = auth::build_access_token(
+            user,
+            data,
+            client,
+            dpop_fingerprint,
+            lifetime,
+            Some(scopes),
+            None,
+            DeviceCodeFlow::No,
+            None,
+            Some(exchange),
+            None,
+            authorization_details.clone(),
+        )
+        .await?;
+
+        Ok(Self {
+            access_token,
+            access_token_jti,
+            token_type,
+            id_token: None,
+            expires_in: lifetime as i32,
+            refresh_token: None,
+            issued_token_type: Some(TOKEN_TYPE_ACCESS_TOKEN.to_string()),
+            authorization_details,
         })
     }
 
@@ -129,16 +273,31 @@ impl TokenSet {
         scopes: Option<TokenScopes>,
         auth_code_flow: AuthCodeFlow,
         device_code_flow: DeviceCodeFlow,
+        sid: Option<TokenSid>,
+        rt_chain: RefreshTokenChain,
+        resource: Option<&ResourceServer>,
+        authorization_details: TokenAuthorizationDetails,
     ) -> Result<Self, ErrorResponse> {
+        // All grants for a user end up here, which makes this the one place to enforce the
+        // group login policies for each new token.
+        user.check_login_policy(data, None).await?;
+
         let scopes = scopes.map(|s| s.0);
         let scope = if let Some(s) = &scopes {
             s.clone()
         } else {
             client.default_scopes.clone().replace(',', " ")
         };
+        // The access token for a resource server only gets its allowed scopes, while the
+        // ID and refresh token keep all of them. The refresh token stays bound to the resource.
+        let access_scope = match resource {
+            Some(rs) => rs.filter_scopes(&scope)?,
+            None => scope.clone(),
+        };
 
         // check for any non-custom scopes and prepare data
         let cust = Scope::extract_custom(&scope);
+        let cust_access = Scope::extract_custom(&access_scope);
 
         let scps;
         let attrs;
@@ -148,14 +307,20 @@ impl TokenSet {
             let mut customs_access = Vec::with_capacity(cust.len());
             let mut customs_id = Vec::with_capacity(cust.len());
 
+            // parameterized scopes use the mappings of their pattern
+            let is_requested = |requested: &HashSet<&str>, s: &Scope| {
+                requested.contains(s.name.as_str())
+                    || (Scope::is_pattern(&s.name)
+                        && requested
+                            .iter()
+                            .any(|r| Scope::match_pattern(&s.name, r).is_some()))
+            };
             for s in scps.as_ref().unwrap() {
-                if cust.contains(s.name.as_str()) {
-                    if s.attr_include_access.is_some() {
-                        customs_access.push(s);
-                    }
-                    if s.attr_include_id.is_some() {
-                        customs_id.push(s);
-                    }
+                if s.attr_include_access.is_some() && is_requested(&cust_access, s) {
+                    customs_access.push(s);
+                }
+                if s.attr_include_id.is_some() && is_requested(&cust, s) {
+                    customs_id.push(s);
                 }
             }
 
@@ -196,7 +361,8 @@ impl TokenSet {
                 return Err(ErrorResponse::new(
                     ErrorResponseType::Forbidden,
                     "User has expired".to_string(),
-                ));
+                )
+                .with_code(ErrorCode::UserExpired));
             }
 
             let client_lt = client.access_token_lifetime.unsigned_abs() as i64;
@@ -214,15 +380,22 @@ impl TokenSet {
         } else {
             JwtTokenType::Bearer
         };
-        let access_token = auth::build_access_token(
+        let TokenAuthorizationDetails { access, granted } = authorization_details;
+        let authorization_details =
+            AuthorizationDetail::merge(Scope::authorization_details(client, &access_scope), access);
+        let (access_token, access_token_jti) = auth::build_access_token(
             Some(user),
             data,
             client,
             dpop_fingerprint.clone(),
             lifetime,
-            Some(TokenScopes(scope.clone())),
+            Some(TokenScopes(access_scope.clone())),
             customs_access,
             device_code_flow.clone(),
+            sid.clone(),
+            None,
+            resource,
+            authorization_details.clone(),
         )
         .await?;
 
@@ -241,8 +414,10 @@ impl TokenSet {
             &scope,
             customs_id,
             auth_code_flow,
+            sid.clone(),
         )
         .await?;
+        let id_token = client.encrypt_id_token(data, id_token).await?;
         let refresh_token = if client.refresh_token {
             Some(
                 auth::build_refresh_token(
@@ -254,6 +429,10 @@ impl TokenSet {
                     scopes.map(TokenScopes),
                     user.has_webauthn_enabled(),
                     device_code_flow,
+                    sid,
+                    rt_chain,
+                    resource,
+                    granted,
                 )
                 .await?,
             )
@@ -261,12 +440,20 @@ impl TokenSet {
             None
         };
 
+        // ephemeral clients do not exist in the database
+        if !client.is_ephemeral() {
+            UserClientGrant::record(data, user.id.clone(), client.id.clone(), scope);
+        }
+
         Ok(Self {
             access_token,
+            access_token_jti,
             token_type,
             id_token: Some(id_token),
             expires_in: client.access_token_lifetime,
             refresh_token,
+            issued_token_type: None,
+            authorization_details,
         })
     }
 }